@@ -72,6 +72,8 @@ fn run() -> Result<(), Error> {
         sw_ver_str: "1",
         serial_no: "aabbccdd",
         device_name: "OnOff Light",
+        product_appearance: Default::default(),
+        device_type: Some(DEV_TYPE_ON_OFF_LIGHT.dtype),
     };
 
     let (ipv4_addr, ipv6_addr, interface) = initialize_network()?;
@@ -116,7 +118,10 @@ fn run() -> Result<(), Error> {
     info!("Matter initialized");
 
     #[cfg(all(feature = "std", not(target_os = "espidf")))]
-    let mut psm = rs_matter::persist::Psm::new(&matter, std::env::temp_dir().join("rs-matter"))?;
+    let mut psm = rs_matter::persist::Psm::new(
+        &matter,
+        rs_matter::persist::FileStore::new(std::env::temp_dir().join("rs-matter"))?,
+    )?;
 
     let handler = HandlerCompat(handler(&matter));
 
@@ -176,7 +181,8 @@ const NODE: Node<'static> = Node {
         root_endpoint::endpoint(0),
         Endpoint {
             id: 1,
-            device_type: DEV_TYPE_ON_OFF_LIGHT,
+            device_types: &[DEV_TYPE_ON_OFF_LIGHT],
+            tags: &[],
             clusters: &[descriptor::CLUSTER, cluster_on_off::CLUSTER],
         },
     ],
@@ -185,11 +191,11 @@ const NODE: Node<'static> = Node {
 fn handler<'a>(matter: &'a Matter<'a>) -> impl Metadata + NonBlockingHandler + 'a {
     (
         NODE,
-        root_endpoint::handler(0, matter)
+        root_endpoint::handler(0, &NODE, matter)
             .chain(
                 1,
                 descriptor::ID,
-                descriptor::DescriptorCluster::new(*matter.borrow()),
+                descriptor::DescriptorCluster::new(&NODE, *matter.borrow()),
             )
             .chain(
                 1,
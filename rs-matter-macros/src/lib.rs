@@ -106,6 +106,73 @@ fn parse_tag_val(field: &syn::Field) -> Option<u8> {
     None
 }
 
+fn is_unknown_variant(variant: &syn::Variant) -> bool {
+    variant.attrs.iter().any(|attr| {
+        if let Ok(List(MetaList { path, nested, .. })) = attr.parse_meta() {
+            path.is_ident("tlvargs")
+                && nested
+                    .iter()
+                    .any(|a| matches!(a, Meta(syn::Meta::Path(p)) if p.is_ident("unknown")))
+        } else {
+            false
+        }
+    })
+}
+
+/// The variants of a C-like enum, along with their discriminants
+struct CEnumVariants<'a> {
+    names: Vec<&'a syn::Ident>,
+    values: Vec<u32>,
+    unknown: Option<&'a syn::Ident>,
+}
+
+/// Returns the variants, if all of them are unit variants, except for an optional
+/// catch-all variant marked with #[tlvargs(unknown)] which holds the raw discriminant
+fn parse_c_enum(data_enum: &syn::DataEnum) -> Option<CEnumVariants> {
+    let mut variants = CEnumVariants {
+        names: Vec::new(),
+        values: Vec::new(),
+        unknown: None,
+    };
+    let mut next = 0;
+
+    for v in data_enum.variants.iter() {
+        if is_unknown_variant(v) {
+            if !matches!(&v.fields, syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1) {
+                panic!(
+                    "The unknown variant must hold the discriminant {:?}",
+                    v.ident
+                );
+            }
+            variants.unknown = Some(&v.ident);
+            continue;
+        }
+
+        if !matches!(v.fields, syn::Fields::Unit) {
+            return None;
+        }
+
+        let value = if let Some((_, expr)) = &v.discriminant {
+            if let syn::Expr::Lit(syn::ExprLit {
+                lit: Int(litint), ..
+            }) = expr
+            {
+                litint.base10_parse::<u32>().unwrap()
+            } else {
+                panic!("Only integer discriminants are supported {:?}", v.ident);
+            }
+        } else {
+            next
+        };
+
+        variants.names.push(&v.ident);
+        variants.values.push(value);
+        next = value + 1;
+    }
+
+    Some(variants)
+}
+
 fn get_crate_name() -> String {
     let found_crate = proc_macro_crate::crate_name("rs-matter").unwrap_or_else(|err| {
         eprintln!("Warning: defaulting to `crate` {err}");
@@ -228,6 +295,38 @@ fn gen_totlv_for_enum(
     expanded.into()
 }
 
+/// Generate a ToTlv implementation for a C-like enum
+fn gen_totlv_for_c_enum(
+    variants: &CEnumVariants,
+    enum_name: &proc_macro2::Ident,
+    generics: &syn::Generics,
+) -> TokenStream {
+    let variant_names = &variants.names;
+    let values = &variants.values;
+    let unknown = variants.unknown.map(|unknown| {
+        quote! {
+            Self::#unknown(value) => tw.u32(tag_type, *value),
+        }
+    });
+
+    let krate = Ident::new(&get_crate_name(), Span::call_site());
+
+    let expanded = quote! {
+        impl #generics #krate::tlv::ToTLV for #enum_name #generics {
+            fn to_tlv(&self, tw: &mut #krate::tlv::TLVWriter, tag_type: #krate::tlv::TagType) -> Result<(), #krate::error::Error> {
+                match self {
+                    #(
+                        Self::#variant_names => tw.u32(tag_type, #values),
+                    )*
+                    #unknown
+                }
+            }
+        }
+    };
+
+    expanded.into()
+}
+
 /// Derive ToTLV Macro
 ///
 /// This macro works for structures. It will create an implementation
@@ -243,6 +342,11 @@ fn gen_totlv_for_enum(
 ///        to be encoded as a structure or list. Possible values: list
 ///        (Default: struct)
 ///
+/// Members of type Option<T> are optional: a None member is not encoded
+/// at all, and a missing tag decodes to None. A member which can be
+/// present with a TLV Null value should use Nullable<T> instead, or
+/// Option<Nullable<T>> if it is also optional.
+///
 /// Additionally, structure members can use the tagval attribute to
 /// define a specific tag to be used
 /// For example:
@@ -250,6 +354,14 @@ fn gen_totlv_for_enum(
 ///  name: u8,
 /// In the above case, the 'name' attribute will be encoded/decoded with
 /// the tag 22
+///
+/// C-like enums are encoded/decoded as an unsigned integer holding the
+/// discriminant of the variant. An enum can opt into accepting unknown
+/// discriminants with a catch-all variant
+/// For example:
+///  #[tlvargs(unknown)]
+///  Unknown(u32),
+/// Discriminants without a matching variant are otherwise rejected
 
 #[proc_macro_derive(ToTLV, attributes(tlvargs, tagval))]
 pub fn derive_totlv(item: TokenStream) -> TokenStream {
@@ -266,6 +378,9 @@ pub fn derive_totlv(item: TokenStream) -> TokenStream {
     {
         gen_totlv_for_struct(fields, name, &tlvargs, &generics)
     } else if let syn::Data::Enum(data_enum) = ast.data {
+        if let Some(variants) = parse_c_enum(&data_enum) {
+            return gen_totlv_for_c_enum(&variants, name, &generics);
+        }
         gen_totlv_for_enum(&data_enum, name, &tlvargs, &generics)
     } else {
         panic!(
@@ -303,12 +418,7 @@ fn gen_fromtlv_for_struct(
             tag_start += 1;
         }
         idents.push(&field.ident);
-
-        if let Type::Path(path) = type_name {
-            types.push(&path.path.segments[0].ident);
-        } else {
-            panic!("Don't know what to do {:?}", type_name);
-        }
+        types.push(type_name);
     }
 
     let krate = Ident::new(&get_crate_name(), Span::call_site());
@@ -326,9 +436,9 @@ fn gen_fromtlv_for_struct(
                        let #idents = if Some(true) == item.as_ref().map(|x| x.check_ctx_tag(#tags)) {
                            let backup = item;
                            item = t_iter.next();
-                           #types::from_tlv(&backup.unwrap())
+                           <#types as #krate::tlv::FromTLV<#lifetime>>::from_tlv(&backup.unwrap())
                        } else {
-                           #types::tlv_not_found()
+                           <#types as #krate::tlv::FromTLV<#lifetime>>::tlv_not_found()
                        }?;
                    )*
                    Ok(Self {
@@ -344,9 +454,9 @@ fn gen_fromtlv_for_struct(
                fn from_tlv(t: &#krate::tlv::TLVElement<#lifetime>) -> Result<Self, #krate::error::Error> {
                    #(
                        let #idents = if let Ok(s) = t.find_tag(#tags as u32) {
-                           #types::from_tlv(&s)
+                           <#types as #krate::tlv::FromTLV<#lifetime>>::from_tlv(&s)
                        } else {
-                           #types::tlv_not_found()
+                           <#types as #krate::tlv::FromTLV<#lifetime>>::tlv_not_found()
                        }?;
                    )*
 
@@ -416,6 +526,45 @@ fn gen_fromtlv_for_enum(
     expanded.into()
 }
 
+/// Generate a FromTlv implementation for a C-like enum
+fn gen_fromtlv_for_c_enum(
+    variants: &CEnumVariants,
+    enum_name: &proc_macro2::Ident,
+    tlvargs: TlvArgs,
+    generics: &syn::Generics,
+) -> TokenStream {
+    let lifetime = tlvargs.lifetime;
+    let variant_names = &variants.names;
+    let values = &variants.values;
+
+    let krate = Ident::new(&get_crate_name(), Span::call_site());
+
+    let fallback = if let Some(unknown) = variants.unknown {
+        quote! {
+            value => Ok(Self::#unknown(value)),
+        }
+    } else {
+        quote! {
+            _ => Err(#krate::error::Error::new(#krate::error::ErrorCode::Invalid)),
+        }
+    };
+
+    let expanded = quote! {
+        impl #generics #krate::tlv::FromTLV <#lifetime> for #enum_name #generics {
+            fn from_tlv(t: &#krate::tlv::TLVElement<#lifetime>) -> Result<Self, #krate::error::Error> {
+                match t.u32()? {
+                    #(
+                        #values => Ok(Self::#variant_names),
+                    )*
+                    #fallback
+                }
+            }
+        }
+    };
+
+    expanded.into()
+}
+
 /// Derive FromTLV Macro
 ///
 /// This macro works for structures. It will create an implementation
@@ -436,6 +585,11 @@ fn gen_fromtlv_for_enum(
 /// unordered: By default, the decoder expects that the tags are in
 ///        sequentially increasing order. Set this if that is not the case.
 ///
+/// Members of type Option<T> are optional: a None member is not encoded
+/// at all, and a missing tag decodes to None. A member which can be
+/// present with a TLV Null value should use Nullable<T> instead, or
+/// Option<Nullable<T>> if it is also optional.
+///
 /// Additionally, structure members can use the tagval attribute to
 /// define a specific tag to be used
 /// For example:
@@ -443,6 +597,14 @@ fn gen_fromtlv_for_enum(
 ///  name: u8,
 /// In the above case, the 'name' attribute will be encoded/decoded with
 /// the tag 22
+///
+/// C-like enums are encoded/decoded as an unsigned integer holding the
+/// discriminant of the variant. An enum can opt into accepting unknown
+/// discriminants with a catch-all variant
+/// For example:
+///  #[tlvargs(unknown)]
+///  Unknown(u32),
+/// Discriminants without a matching variant are otherwise rejected
 
 #[proc_macro_derive(FromTLV, attributes(tlvargs, tagval))]
 pub fn derive_fromtlv(item: TokenStream) -> TokenStream {
@@ -460,6 +622,9 @@ pub fn derive_fromtlv(item: TokenStream) -> TokenStream {
     {
         gen_fromtlv_for_struct(fields, name, tlvargs, &generics)
     } else if let syn::Data::Enum(data_enum) = ast.data {
+        if let Some(variants) = parse_c_enum(&data_enum) {
+            return gen_fromtlv_for_c_enum(&variants, name, tlvargs, &generics);
+        }
         gen_fromtlv_for_enum(&data_enum, name, tlvargs, &generics)
     } else {
         panic!(
@@ -23,7 +23,7 @@ use crate::{
     fabric,
     interaction_model::messages::GenericPath,
     tlv::{self, FromTLV, TLVElement, TLVList, TLVWriter, TagType, ToTLV},
-    transport::session::{Session, SessionMode, MAX_CAT_IDS_PER_NOC},
+    transport::session::{NocCatIds, Session, SessionMode, MAX_CAT_IDS_PER_NOC},
     utils::writebuf::WriteBuf,
 };
 use log::error;
@@ -32,7 +32,7 @@ use num_derive::FromPrimitive;
 // Matter Minimum Requirements
 pub const SUBJECTS_PER_ENTRY: usize = 4;
 pub const TARGETS_PER_ENTRY: usize = 3;
-pub const ENTRIES_PER_FABRIC: usize = 3;
+pub const ENTRIES_PER_FABRIC: usize = 4;
 
 // TODO: Check if this and the SessionMode can be combined into some generic data structure
 #[derive(FromPrimitive, Copy, Clone, PartialEq, Debug)]
@@ -71,12 +71,13 @@ impl ToTLV for AuthMode {
 const MAX_ACCESSOR_SUBJECTS: usize = 1 + MAX_CAT_IDS_PER_NOC;
 /// The CAT Prefix used in Subjects
 pub const NOC_CAT_SUBJECT_PREFIX: u64 = 0xFFFF_FFFD_0000_0000;
+const NOC_CAT_PREFIX_MASK: u64 = 0xFFFF_FFFF_0000_0000;
 const NOC_CAT_ID_MASK: u64 = 0xFFFF_0000;
 const NOC_CAT_VERSION_MASK: u64 = 0xFFFF;
 
 /// Is this identifier a NOC CAT
 fn is_noc_cat(id: u64) -> bool {
-    (id & NOC_CAT_SUBJECT_PREFIX) == NOC_CAT_SUBJECT_PREFIX
+    (id & NOC_CAT_PREFIX_MASK) == NOC_CAT_SUBJECT_PREFIX
 }
 
 /// Get the 16-bit NOC CAT id from the identifier
@@ -106,6 +107,11 @@ impl AccessorSubjects {
     }
 
     pub fn add_catid(&mut self, subject: u32) -> Result<(), Error> {
+        // Version 0 is not a valid CAT version
+        if get_noc_cat_version(subject as u64) == 0 {
+            Err(ErrorCode::Invalid)?;
+        }
+
         for (i, val) in self.0.iter().enumerate() {
             if *val == 0 {
                 self.0[i] = NOC_CAT_SUBJECT_PREFIX | (subject as u64);
@@ -174,8 +180,8 @@ impl<'a> Accessor<'a> {
                 let mut subject =
                     AccessorSubjects::new(session.get_peer_node_id().unwrap_or_default());
                 for i in c.cat_ids {
-                    if i != 0 {
-                        let _ = subject.add_catid(i);
+                    if i != 0 && subject.add_catid(i).is_err() {
+                        error!("Ignoring invalid CAT {:x} of the peer", i);
                     }
                 }
                 Accessor::new(c.fab_idx, subject, AuthMode::Case, acl_mgr)
@@ -183,6 +189,12 @@ impl<'a> Accessor<'a> {
             SessionMode::Pase => {
                 Accessor::new(0, AccessorSubjects::new(1), AuthMode::Pase, acl_mgr)
             }
+            SessionMode::Group(g) => Accessor::new(
+                g.fab_idx,
+                AccessorSubjects::new(g.group_id as u64),
+                AuthMode::Group,
+                acl_mgr,
+            ),
 
             SessionMode::PlainText => {
                 Accessor::new(0, AccessorSubjects::new(1), AuthMode::Invalid, acl_mgr)
@@ -205,6 +217,72 @@ impl<'a> Accessor<'a> {
     }
 }
 
+/// Builds the accessor of a fabricated CASE session, without a CASE handshake
+///
+/// If a privilege is provided, an ACL entry granting it to the node of the session
+/// on the fabric of the session is installed when building the accessor.
+#[cfg(test)]
+pub(crate) struct AccessorBuilder {
+    fab_idx: u8,
+    node_id: u64,
+    cat_ids: NocCatIds,
+    privilege: Option<Privilege>,
+}
+
+#[cfg(test)]
+impl AccessorBuilder {
+    pub fn new(fab_idx: u8, node_id: u64) -> Self {
+        Self {
+            fab_idx,
+            node_id,
+            cat_ids: Default::default(),
+            privilege: None,
+        }
+    }
+
+    pub fn cat_ids(mut self, cat_ids: &NocCatIds) -> Self {
+        self.cat_ids = *cat_ids;
+        self
+    }
+
+    pub fn privilege(mut self, privilege: Privilege) -> Self {
+        self.privilege = Some(privilege);
+        self
+    }
+
+    /// The session the accessor is derived from
+    pub fn session(&self) -> Session {
+        use crate::{
+            transport::{
+                network::Address,
+                session::{CaseDetails, CloneData},
+            },
+            utils::{epoch::dummy_epoch, rand::dummy_rand},
+        };
+
+        let clone_data = CloneData::new(
+            1,
+            self.node_id,
+            0,
+            0,
+            Address::default(),
+            SessionMode::Case(CaseDetails::new(self.fab_idx, &self.cat_ids)),
+        );
+
+        Session::clone(&clone_data, dummy_epoch, dummy_rand)
+    }
+
+    pub fn build<'a>(&self, acl_mgr: &'a RefCell<AclMgr>) -> Result<Accessor<'a>, Error> {
+        if let Some(privilege) = self.privilege {
+            let mut entry = AclEntry::new(self.fab_idx, privilege, AuthMode::Case);
+            entry.add_subject(self.node_id)?;
+            acl_mgr.borrow_mut().add(entry)?;
+        }
+
+        Ok(Accessor::for_session(&self.session(), acl_mgr))
+    }
+}
+
 #[derive(Debug)]
 pub struct AccessDesc {
     /// The object to be acted upon
@@ -392,6 +470,10 @@ const MAX_ACL_ENTRIES: usize = ENTRIES_PER_FABRIC * fabric::MAX_SUPPORTED_FABRIC
 
 type AclEntries = heapless::Vec<Option<AclEntry>, MAX_ACL_ENTRIES>;
 
+/// A copy of the entries of the ACL Mgr, which can be restored later
+#[derive(Clone, Debug, PartialEq)]
+pub struct AclBackup(AclEntries);
+
 pub struct AclMgr {
     entries: AclEntries,
     changed: bool,
@@ -421,24 +503,21 @@ impl AclMgr {
             .filter(|a| a.fab_idx == entry.fab_idx)
             .count();
         if cnt >= ENTRIES_PER_FABRIC {
-            Err(ErrorCode::NoSpace)?;
+            Err(ErrorCode::ResourceExhausted)?;
         }
 
         let slot = self.entries.iter().position(|a| a.is_none());
 
-        if slot.is_some() || self.entries.len() < MAX_ACL_ENTRIES {
-            if let Some(index) = slot {
-                self.entries[index] = Some(entry);
-            } else {
-                self.entries
-                    .push(Some(entry))
-                    .map_err(|_| ErrorCode::NoSpace)
-                    .unwrap();
-            }
-
-            self.changed = true;
+        if let Some(index) = slot {
+            self.entries[index] = Some(entry);
+        } else {
+            self.entries
+                .push(Some(entry))
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
         }
 
+        self.changed = true;
+
         Ok(())
     }
 
@@ -452,13 +531,16 @@ impl AclMgr {
         Ok(())
     }
 
-    pub fn delete(&mut self, index: u8, fab_idx: u8) -> Result<(), Error> {
-        let old = self.for_index_in_fabric(index, fab_idx)?;
-        *old = None;
+    /// Delete the entry and return it
+    pub fn delete(&mut self, index: u8, fab_idx: u8) -> Result<AclEntry, Error> {
+        let old = self
+            .for_index_in_fabric(index, fab_idx)?
+            .take()
+            .ok_or(ErrorCode::NotFound)?;
 
         self.changed = true;
 
-        Ok(())
+        Ok(old)
     }
 
     pub fn delete_for_fabric(&mut self, fab_idx: u8) -> Result<(), Error> {
@@ -476,6 +558,41 @@ impl AclMgr {
         Ok(())
     }
 
+    /// A copy of the entries, to be restored with `restore`
+    pub fn backup(&self) -> AclBackup {
+        AclBackup(self.entries.clone())
+    }
+
+    /// Restore the entries of the backup
+    ///
+    /// Entries of fabrics for which `has_fabric` returns false are not restored, as their
+    /// fabric was removed after the backup was taken.
+    pub fn restore<F>(&mut self, backup: AclBackup, has_fabric: F)
+    where
+        F: Fn(u8) -> bool,
+    {
+        let mut entries = backup.0;
+        for entry in &mut entries {
+            if entry
+                .as_ref()
+                .and_then(|e| e.fab_idx)
+                .map(|fab_idx| !has_fabric(fab_idx))
+                .unwrap_or(false)
+            {
+                *entry = None;
+            }
+        }
+
+        if entries != self.entries {
+            self.entries = entries;
+            self.changed = true;
+        }
+    }
+
+    pub fn acls(&self) -> impl Iterator<Item = &AclEntry> {
+        self.entries.iter().flatten()
+    }
+
     pub fn for_each_acl<T>(&self, mut f: T) -> Result<(), Error>
     where
         T: FnMut(&AclEntry) -> Result<(), Error>,
@@ -583,9 +700,14 @@ mod tests {
         acl::{gen_noc_cat, AccessorSubjects},
         data_model::objects::{Access, Privilege},
         interaction_model::messages::GenericPath,
+        transport::{
+            network::Address,
+            session::{CaseDetails, CloneData, Session, SessionMode},
+        },
+        utils::{epoch::dummy_epoch, rand::dummy_rand},
     };
 
-    use super::{AccessReq, Accessor, AclEntry, AclMgr, AuthMode, Target};
+    use super::{AccessReq, Accessor, AccessorBuilder, AclEntry, AclMgr, AuthMode, Target};
 
     #[test]
     fn test_basic_empty_subject_target() {
@@ -707,6 +829,64 @@ mod tests {
         assert_eq!(req.allow(), true);
     }
 
+    #[test]
+    fn test_cat_for_session() {
+        let am = RefCell::new(AclMgr::new());
+        am.borrow_mut().erase_all().unwrap();
+
+        // The NOC of the peer has the CAT 0xABCD_0003, and an invalid one with version 0
+        let clone_data = CloneData::new(
+            1,
+            112233,
+            0,
+            0,
+            Address::default(),
+            SessionMode::Case(CaseDetails::new(
+                2,
+                &[gen_noc_cat(0xABCD, 3), gen_noc_cat(0xCAFE, 0), 0],
+            )),
+        );
+        let session = Session::clone(&clone_data, dummy_epoch, dummy_rand);
+
+        let accessor = Accessor::for_session(&session, &am);
+        let path = GenericPath::new(Some(1), Some(1234), None);
+        let mut req = AccessReq::new(&accessor, path, Access::READ);
+        req.set_target_perms(Access::RWVA);
+
+        // Deny for CAT version higher than the version of the session's CAT
+        let mut new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
+        new.add_subject_catid(gen_noc_cat(0xABCD, 4)).unwrap();
+        am.borrow_mut().add(new).unwrap();
+        assert_eq!(req.allow(), false);
+
+        // Deny for the invalid CAT of the session
+        let mut new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
+        new.add_subject_catid(gen_noc_cat(0xCAFE, 0)).unwrap();
+        am.borrow_mut().add(new).unwrap();
+        assert_eq!(req.allow(), false);
+
+        // Allow for CAT version floor lower than the version of the session's CAT
+        let mut new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
+        new.add_subject_catid(gen_noc_cat(0xABCD, 2)).unwrap();
+        am.borrow_mut().add(new).unwrap();
+        assert_eq!(req.allow(), true);
+
+        am.borrow_mut().erase_all().unwrap();
+
+        // Deny for a subject outside of the CAT range, even though its lower bits match
+        let mut new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
+        new.add_subject(0xFFFF_FFFF_0000_0000 | gen_noc_cat(0xABCD, 2) as u64)
+            .unwrap();
+        am.borrow_mut().add(new).unwrap();
+        assert_eq!(req.allow(), false);
+
+        // Allow for exact node match
+        let mut new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
+        new.add_subject(112233).unwrap();
+        am.borrow_mut().add(new).unwrap();
+        assert_eq!(req.allow(), true);
+    }
+
     #[test]
     fn test_target() {
         let am = RefCell::new(AclMgr::new());
@@ -808,6 +988,35 @@ mod tests {
         assert_eq!(req.allow(), true);
     }
 
+    #[test]
+    fn test_builder_view_only() {
+        let am = RefCell::new(AclMgr::new());
+        am.borrow_mut().erase_all().unwrap();
+        let accessor = AccessorBuilder::new(2, 112233)
+            .privilege(Privilege::VIEW)
+            .build(&am)
+            .unwrap();
+        let path = GenericPath::new(Some(1), Some(1234), Some(1));
+
+        assert_eq!(accessor.fab_idx, 2);
+
+        // Read on an RWVM with view access - allow
+        let mut req = AccessReq::new(&accessor, path.clone(), Access::READ);
+        req.set_target_perms(Access::RWVM);
+        assert_eq!(req.allow(), true);
+
+        // Write on an RWVM without manage access - deny
+        let mut req = AccessReq::new(&accessor, path.clone(), Access::WRITE);
+        req.set_target_perms(Access::RWVM);
+        assert_eq!(req.allow(), false);
+
+        // The view-only grant is only for fabric 2
+        let accessor = AccessorBuilder::new(1, 112233).build(&am).unwrap();
+        let mut req = AccessReq::new(&accessor, path, Access::READ);
+        req.set_target_perms(Access::RWVM);
+        assert_eq!(req.allow(), false);
+    }
+
     #[test]
     fn test_delete_for_fabric() {
         let am = RefCell::new(AclMgr::new());
@@ -0,0 +1,1083 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The Bulk Data Exchange (BDX) protocol, in its synchronous mode
+//!
+//! The transfer is always driven by the initiator: the sender for a `SendInit`,
+//! and the receiver for a `ReceiveInit`.
+
+use core::cell::RefCell;
+
+use byteorder::{ByteOrder, LittleEndian};
+use log::{error, info};
+use num_derive::FromPrimitive;
+
+use crate::{
+    error::{Error, ErrorCode},
+    secure_channel::{
+        common::{OpCode as SCOpCode, PROTO_ID_SECURE_CHANNEL},
+        status_report::{create_status_report, GeneralCode},
+    },
+    transport::{exchange::Exchange, packet::Packet},
+    utils::writebuf::WriteBuf,
+};
+
+pub const PROTO_ID_BDX: u16 = 0x02;
+
+pub const BDX_VERSION: u8 = 0;
+
+/// The largest block which fits in a packet
+pub const MAX_BLOCK_SIZE: u16 = 1024;
+
+/// The longest file designator which can be served
+pub const MAX_FILE_DESIGNATOR_LEN: usize = 256;
+
+const TC_VERSION_MASK: u8 = 0x0f;
+const TC_SENDER_DRIVE: u8 = 0x10;
+const TC_RECEIVER_DRIVE: u8 = 0x20;
+
+const RC_DEF_LEN: u8 = 0x01;
+const RC_START_OFFSET: u8 = 0x02;
+const RC_WIDE_RANGE: u8 = 0x10;
+
+#[derive(FromPrimitive, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OpCode {
+    SendInit = 0x01,
+    SendAccept = 0x02,
+    ReceiveInit = 0x04,
+    ReceiveAccept = 0x05,
+    BlockQuery = 0x10,
+    Block = 0x11,
+    BlockEof = 0x12,
+    BlockAck = 0x13,
+    BlockAckEof = 0x14,
+    BlockQueryWithSkip = 0x15,
+}
+
+/// The protocol codes of the status reports aborting a transfer
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StatusCode {
+    LengthTooLarge = 0x12,
+    LengthTooShort = 0x13,
+    LengthMismatch = 0x14,
+    LengthRequired = 0x15,
+    BadMessageContents = 0x16,
+    BadBlockCounter = 0x17,
+    UnexpectedMessage = 0x18,
+    ResponderBusy = 0x19,
+    TransferFailedUnknownError = 0x1f,
+    TransferMethodNotSupported = 0x50,
+    FileDesignatorUnknown = 0x51,
+    StartOffsetNotSupported = 0x52,
+    VersionNotSupported = 0x53,
+    Unknown = 0x5f,
+}
+
+/// Which side of the transfer sends the messages which the other side answers
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Drive {
+    Sender,
+    Receiver,
+}
+
+impl Drive {
+    const fn flag(&self) -> u8 {
+        match self {
+            Self::Sender => TC_SENDER_DRIVE,
+            Self::Receiver => TC_RECEIVER_DRIVE,
+        }
+    }
+}
+
+/// The proposal of a `SendInit` or a `ReceiveInit`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferInit<'a> {
+    pub drive: Drive,
+    pub max_block_size: u16,
+    pub start_offset: Option<u64>,
+    pub max_length: Option<u64>,
+    pub file_designator: &'a [u8],
+}
+
+impl<'a> TransferInit<'a> {
+    /// Accept the proposal with a block size no larger than the one provided
+    pub fn accept(&self, max_block_size: u16) -> TransferAccept {
+        TransferAccept {
+            drive: self.drive,
+            max_block_size: self.max_block_size.min(max_block_size),
+            length: None,
+        }
+    }
+}
+
+/// The answer of a `SendAccept` or a `ReceiveAccept`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferAccept {
+    pub drive: Drive,
+    pub max_block_size: u16,
+    /// The length of the data; only carried by a `ReceiveAccept`
+    pub length: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message<'a> {
+    SendInit(TransferInit<'a>),
+    SendAccept(TransferAccept),
+    ReceiveInit(TransferInit<'a>),
+    ReceiveAccept(TransferAccept),
+    BlockQuery(u32),
+    Block(u32, &'a [u8]),
+    BlockEof(u32, &'a [u8]),
+    BlockAck(u32),
+    BlockAckEof(u32),
+}
+
+impl<'a> Message<'a> {
+    pub fn opcode(&self) -> OpCode {
+        match self {
+            Self::SendInit(_) => OpCode::SendInit,
+            Self::SendAccept(_) => OpCode::SendAccept,
+            Self::ReceiveInit(_) => OpCode::ReceiveInit,
+            Self::ReceiveAccept(_) => OpCode::ReceiveAccept,
+            Self::BlockQuery(_) => OpCode::BlockQuery,
+            Self::Block(_, _) => OpCode::Block,
+            Self::BlockEof(_, _) => OpCode::BlockEof,
+            Self::BlockAck(_) => OpCode::BlockAck,
+            Self::BlockAckEof(_) => OpCode::BlockAckEof,
+        }
+    }
+
+    pub fn encode(&self, wb: &mut WriteBuf) -> Result<(), Error> {
+        match self {
+            Self::SendInit(init) | Self::ReceiveInit(init) => {
+                let wide = init.start_offset.unwrap_or(0) > u32::MAX as u64
+                    || init.max_length.unwrap_or(0) > u32::MAX as u64;
+
+                let mut range_control = if wide { RC_WIDE_RANGE } else { 0 };
+                if init.start_offset.is_some() {
+                    range_control |= RC_START_OFFSET;
+                }
+                if init.max_length.is_some() {
+                    range_control |= RC_DEF_LEN;
+                }
+
+                wb.le_u8(BDX_VERSION | init.drive.flag())?;
+                wb.le_u8(range_control)?;
+                wb.le_u16(init.max_block_size)?;
+                for value in [init.start_offset, init.max_length].into_iter().flatten() {
+                    wb.le_uint(if wide { 8 } else { 4 }, value)?;
+                }
+                wb.le_u16(init.file_designator.len() as _)?;
+                wb.append(init.file_designator)
+            }
+            Self::SendAccept(accept) => {
+                wb.le_u8(BDX_VERSION | accept.drive.flag())?;
+                wb.le_u16(accept.max_block_size)
+            }
+            Self::ReceiveAccept(accept) => {
+                let wide = accept.length.unwrap_or(0) > u32::MAX as u64;
+
+                let mut range_control = if wide { RC_WIDE_RANGE } else { 0 };
+                if accept.length.is_some() {
+                    range_control |= RC_DEF_LEN;
+                }
+
+                wb.le_u8(BDX_VERSION | accept.drive.flag())?;
+                wb.le_u8(range_control)?;
+                wb.le_u16(accept.max_block_size)?;
+                if let Some(length) = accept.length {
+                    wb.le_uint(if wide { 8 } else { 4 }, length)?;
+                }
+
+                Ok(())
+            }
+            Self::Block(counter, data) | Self::BlockEof(counter, data) => {
+                wb.le_u32(*counter)?;
+                wb.append(data)
+            }
+            Self::BlockQuery(counter) | Self::BlockAck(counter) | Self::BlockAckEof(counter) => {
+                wb.le_u32(*counter)
+            }
+        }
+    }
+
+    pub fn decode(opcode: u8, payload: &'a [u8]) -> Result<Self, Error> {
+        let mut reader = Reader(payload);
+
+        let opcode = num::FromPrimitive::from_u8(opcode).ok_or(ErrorCode::InvalidOpcode)?;
+
+        let msg = match opcode {
+            OpCode::SendInit | OpCode::ReceiveInit => {
+                let drive = Self::decode_drive(reader.u8()?)?;
+                let range_control = reader.u8()?;
+                let max_block_size = reader.u16()?;
+
+                let wide = range_control & RC_WIDE_RANGE != 0;
+                let start_offset = if range_control & RC_START_OFFSET != 0 {
+                    Some(reader.uint(wide)?)
+                } else {
+                    None
+                };
+                let max_length = if range_control & RC_DEF_LEN != 0 {
+                    Some(reader.uint(wide)?)
+                } else {
+                    None
+                };
+
+                let len = reader.u16()?;
+                let file_designator = reader.take(len as _)?;
+
+                // Any metadata which follows is ignored
+                let init = TransferInit {
+                    drive,
+                    max_block_size,
+                    start_offset,
+                    max_length,
+                    file_designator,
+                };
+
+                if opcode == OpCode::SendInit {
+                    Self::SendInit(init)
+                } else {
+                    Self::ReceiveInit(init)
+                }
+            }
+            OpCode::SendAccept => Self::SendAccept(TransferAccept {
+                drive: Self::decode_drive(reader.u8()?)?,
+                max_block_size: reader.u16()?,
+                length: None,
+            }),
+            OpCode::ReceiveAccept => {
+                let drive = Self::decode_drive(reader.u8()?)?;
+                let range_control = reader.u8()?;
+                let max_block_size = reader.u16()?;
+                let length = if range_control & RC_DEF_LEN != 0 {
+                    Some(reader.uint(range_control & RC_WIDE_RANGE != 0)?)
+                } else {
+                    None
+                };
+
+                Self::ReceiveAccept(TransferAccept {
+                    drive,
+                    max_block_size,
+                    length,
+                })
+            }
+            OpCode::BlockQuery => Self::BlockQuery(reader.u32()?),
+            OpCode::Block => Self::Block(reader.u32()?, reader.0),
+            OpCode::BlockEof => Self::BlockEof(reader.u32()?, reader.0),
+            OpCode::BlockAck => Self::BlockAck(reader.u32()?),
+            OpCode::BlockAckEof => Self::BlockAckEof(reader.u32()?),
+            // Only the synchronous mode is supported
+            OpCode::BlockQueryWithSkip => Err(ErrorCode::InvalidOpcode)?,
+        };
+
+        Ok(msg)
+    }
+
+    /// Only a single, synchronous drive mode can be proposed
+    fn decode_drive(transfer_control: u8) -> Result<Drive, Error> {
+        if transfer_control & TC_VERSION_MASK != BDX_VERSION {
+            Err(ErrorCode::Invalid)?;
+        }
+
+        match transfer_control & !TC_VERSION_MASK {
+            TC_SENDER_DRIVE => Ok(Drive::Sender),
+            TC_RECEIVER_DRIVE => Ok(Drive::Receiver),
+            _ => Err(ErrorCode::Invalid.into()),
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            Err(ErrorCode::TruncatedPacket)?;
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(LittleEndian::read_u16(self.take(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(LittleEndian::read_u32(self.take(4)?))
+    }
+
+    fn uint(&mut self, wide: bool) -> Result<u64, Error> {
+        if wide {
+            Ok(LittleEndian::read_u64(self.take(8)?))
+        } else {
+            self.u32().map(Into::into)
+        }
+    }
+}
+
+/// The data sent over BDX
+pub trait BdxSource {
+    /// Read the data at the provided offset, returning the number of bytes read,
+    /// which is less than the length of the buffer only at the end of the data
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Error>;
+}
+
+impl BdxSource for &[u8] {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let data = self.get(offset as usize..).unwrap_or(&[]);
+        let len = data.len().min(buf.len());
+
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+}
+
+/// The destination of the data received over BDX
+pub trait BdxSink {
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), Error>;
+}
+
+/// The files served to the peers which initiate a transfer
+pub trait BdxFiles {
+    /// Read the file at the provided offset, as per [BdxSource::read]; the read of a
+    /// file which is not served fails with `NotFound`
+    fn read(&self, file_designator: &[u8], offset: u64, buf: &mut [u8]) -> Result<usize, Error>;
+}
+
+/// The file of a transfer, read out of the served files
+struct File<'a> {
+    files: &'a dyn BdxFiles,
+    designator: RefCell<heapless::Vec<u8, MAX_FILE_DESIGNATOR_LEN>>,
+}
+
+impl<'a> BdxSource for File<'a> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        self.files.read(&self.designator.borrow(), offset, buf)
+    }
+}
+
+/// One side of the block exchange, once the transfer is accepted
+pub trait Transfer {
+    /// The first message of the block exchange, if this side drives it
+    fn start<'b>(&mut self, buf: &'b mut [u8]) -> Result<Option<Message<'b>>, StatusCode>;
+
+    /// The message answering the one of the peer, if any
+    fn handle<'b>(
+        &mut self,
+        msg: &Message,
+        buf: &'b mut [u8],
+    ) -> Result<Option<Message<'b>>, StatusCode>;
+
+    fn is_done(&self) -> bool;
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Transfer,
+    AwaitEofAck,
+    Done,
+}
+
+pub struct BdxSender<'a> {
+    source: &'a dyn BdxSource,
+    drive: Drive,
+    block_size: u16,
+    offset: u64,
+    counter: u32,
+    state: State,
+}
+
+impl<'a> BdxSender<'a> {
+    pub fn new(source: &'a dyn BdxSource, accept: &TransferAccept, start_offset: u64) -> Self {
+        Self {
+            source,
+            drive: accept.drive,
+            block_size: accept.max_block_size.min(MAX_BLOCK_SIZE),
+            offset: start_offset,
+            counter: 0,
+            state: State::Transfer,
+        }
+    }
+
+    fn next_block<'b>(&mut self, buf: &'b mut [u8]) -> Result<Message<'b>, StatusCode> {
+        let buf = &mut buf[..self.block_size as usize];
+
+        let len = self.source.read(self.offset, buf).map_err(|e| {
+            error!("Reading the block at {} failed: {:?}", self.offset, e);
+            StatusCode::TransferFailedUnknownError
+        })?;
+
+        let counter = self.counter;
+
+        self.offset += len as u64;
+        self.counter = counter.wrapping_add(1);
+
+        if len < buf.len() {
+            self.state = State::AwaitEofAck;
+            Ok(Message::BlockEof(counter, &buf[..len]))
+        } else {
+            Ok(Message::Block(counter, buf))
+        }
+    }
+
+    /// The counter of the last block sent
+    fn last_counter(&self) -> u32 {
+        self.counter.wrapping_sub(1)
+    }
+}
+
+impl<'a> Transfer for BdxSender<'a> {
+    fn start<'b>(&mut self, buf: &'b mut [u8]) -> Result<Option<Message<'b>>, StatusCode> {
+        if self.drive == Drive::Sender {
+            self.next_block(buf).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn handle<'b>(
+        &mut self,
+        msg: &Message,
+        buf: &'b mut [u8],
+    ) -> Result<Option<Message<'b>>, StatusCode> {
+        match (self.state, self.drive, msg) {
+            (State::Transfer, Drive::Sender, Message::BlockAck(counter)) => {
+                if *counter != self.last_counter() {
+                    Err(StatusCode::BadBlockCounter)?;
+                }
+
+                self.next_block(buf).map(Some)
+            }
+            (State::Transfer, Drive::Receiver, Message::BlockQuery(counter)) => {
+                if *counter != self.counter {
+                    Err(StatusCode::BadBlockCounter)?;
+                }
+
+                self.next_block(buf).map(Some)
+            }
+            (State::AwaitEofAck, _, Message::BlockAckEof(counter)) => {
+                if *counter != self.last_counter() {
+                    Err(StatusCode::BadBlockCounter)?;
+                }
+
+                self.state = State::Done;
+
+                Ok(None)
+            }
+            _ => Err(StatusCode::UnexpectedMessage),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+}
+
+pub struct BdxReceiver<'a> {
+    sink: &'a mut dyn BdxSink,
+    drive: Drive,
+    block_size: u16,
+    offset: u64,
+    counter: u32,
+    state: State,
+}
+
+impl<'a> BdxReceiver<'a> {
+    pub fn new(sink: &'a mut dyn BdxSink, accept: &TransferAccept, start_offset: u64) -> Self {
+        Self {
+            sink,
+            drive: accept.drive,
+            block_size: accept.max_block_size.min(MAX_BLOCK_SIZE),
+            offset: start_offset,
+            counter: 0,
+            state: State::Transfer,
+        }
+    }
+
+    /// The number of bytes received so far
+    pub fn received(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<'a> Transfer for BdxReceiver<'a> {
+    fn start<'b>(&mut self, _buf: &'b mut [u8]) -> Result<Option<Message<'b>>, StatusCode> {
+        if self.drive == Drive::Receiver {
+            Ok(Some(Message::BlockQuery(self.counter)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn handle<'b>(
+        &mut self,
+        msg: &Message,
+        _buf: &'b mut [u8],
+    ) -> Result<Option<Message<'b>>, StatusCode> {
+        let (counter, data, eof) = match (self.state, msg) {
+            (State::Transfer, Message::Block(counter, data)) => (*counter, data, false),
+            (State::Transfer, Message::BlockEof(counter, data)) => (*counter, data, true),
+            _ => Err(StatusCode::UnexpectedMessage)?,
+        };
+
+        if counter != self.counter {
+            Err(StatusCode::BadBlockCounter)?;
+        }
+
+        if data.len() > self.block_size as usize {
+            Err(StatusCode::LengthTooLarge)?;
+        }
+
+        self.sink.write(self.offset, data).map_err(|e| {
+            error!("Writing the block at {} failed: {:?}", self.offset, e);
+            StatusCode::TransferFailedUnknownError
+        })?;
+
+        self.offset += data.len() as u64;
+        self.counter = counter.wrapping_add(1);
+
+        if eof {
+            self.state = State::Done;
+            Ok(Some(Message::BlockAckEof(counter)))
+        } else if self.drive == Drive::Sender {
+            Ok(Some(Message::BlockAck(counter)))
+        } else {
+            Ok(Some(Message::BlockQuery(self.counter)))
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+}
+
+/// Send the data to the peer, driving the transfer
+pub async fn send(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &mut Packet<'_>,
+    file_designator: &[u8],
+    source: &dyn BdxSource,
+    max_block_size: u16,
+) -> Result<(), Error> {
+    let init = TransferInit {
+        drive: Drive::Sender,
+        max_block_size: max_block_size.min(MAX_BLOCK_SIZE),
+        start_offset: None,
+        max_length: None,
+        file_designator,
+    };
+
+    let accept = match initiate(exchange, tx, rx, &Message::SendInit(init)).await? {
+        Message::SendAccept(accept) if accept.drive == Drive::Sender => accept,
+        _ => return abort(exchange, tx, StatusCode::UnexpectedMessage).await,
+    };
+
+    run(exchange, tx, rx, &mut BdxSender::new(source, &accept, 0)).await
+}
+
+/// Receive the data from the peer, driving the transfer; returns the length of the data
+pub async fn receive(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &mut Packet<'_>,
+    file_designator: &[u8],
+    sink: &mut dyn BdxSink,
+    max_block_size: u16,
+) -> Result<u64, Error> {
+    let init = TransferInit {
+        drive: Drive::Receiver,
+        max_block_size: max_block_size.min(MAX_BLOCK_SIZE),
+        start_offset: None,
+        max_length: None,
+        file_designator,
+    };
+
+    let accept = match initiate(exchange, tx, rx, &Message::ReceiveInit(init)).await? {
+        Message::ReceiveAccept(accept) if accept.drive == Drive::Receiver => accept,
+        _ => return abort(exchange, tx, StatusCode::UnexpectedMessage).await,
+    };
+
+    let mut receiver = BdxReceiver::new(sink, &accept, 0);
+
+    run(exchange, tx, rx, &mut receiver).await?;
+
+    Ok(receiver.received())
+}
+
+/// Serve the data requested by the `ReceiveInit` in `rx`, with the source opened for its
+/// file designator
+pub async fn serve<'s, F>(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &mut Packet<'_>,
+    max_block_size: u16,
+    open: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&[u8]) -> Option<&'s dyn BdxSource>,
+{
+    let (source, accept, start_offset) = match decode(rx) {
+        Ok(Message::ReceiveInit(init)) => {
+            info!("Serving BDX transfer of {:?}", init.file_designator);
+
+            match open(init.file_designator) {
+                Some(source) => (
+                    source,
+                    init.accept(max_block_size),
+                    init.start_offset.unwrap_or(0),
+                ),
+                None => return abort(exchange, tx, StatusCode::FileDesignatorUnknown).await,
+            }
+        }
+        Ok(_) => return abort(exchange, tx, StatusCode::UnexpectedMessage).await,
+        Err(_) => return abort(exchange, tx, StatusCode::BadMessageContents).await,
+    };
+
+    prepare(tx, &Message::ReceiveAccept(accept.clone()))?;
+    exchange.exchange(tx, rx).await?;
+
+    let mut sender = BdxSender::new(source, &accept, start_offset);
+
+    step(exchange, tx, rx, &mut sender).await
+}
+
+/// Accept the data offered by the `SendInit` in `rx`, with the sink opened for its
+/// file designator; returns the length of the data
+pub async fn accept<'s, F>(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &mut Packet<'_>,
+    max_block_size: u16,
+    open: F,
+) -> Result<u64, Error>
+where
+    F: FnOnce(&[u8]) -> Option<&'s mut dyn BdxSink>,
+{
+    let (sink, accept, start_offset) = match decode(rx) {
+        Ok(Message::SendInit(init)) => {
+            info!("Accepting BDX transfer of {:?}", init.file_designator);
+
+            match open(init.file_designator) {
+                Some(sink) => (
+                    sink,
+                    init.accept(max_block_size),
+                    init.start_offset.unwrap_or(0),
+                ),
+                None => return abort(exchange, tx, StatusCode::FileDesignatorUnknown).await,
+            }
+        }
+        Ok(_) => return abort(exchange, tx, StatusCode::UnexpectedMessage).await,
+        Err(_) => return abort(exchange, tx, StatusCode::BadMessageContents).await,
+    };
+
+    prepare(tx, &Message::SendAccept(accept.clone()))?;
+    exchange.exchange(tx, rx).await?;
+
+    let mut receiver = BdxReceiver::new(sink, &accept, start_offset);
+
+    step(exchange, tx, rx, &mut receiver).await?;
+
+    Ok(receiver.received() - start_offset)
+}
+
+/// Respond to the transfer which the peer initiated with the message in `rx`, by
+/// serving the requested file out of the provided files, if any
+///
+/// Only the transfers to the peer are served, so a `SendInit` is rejected.
+pub async fn respond(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &mut Packet<'_>,
+    files: Option<&dyn BdxFiles>,
+) -> Result<(), Error> {
+    if rx.get_proto_raw_opcode() == OpCode::SendInit as u8 {
+        return abort(exchange, tx, StatusCode::TransferMethodNotSupported).await;
+    }
+
+    let Some(files) = files else {
+        return abort(exchange, tx, StatusCode::FileDesignatorUnknown).await;
+    };
+
+    let file = File {
+        files,
+        designator: RefCell::new(heapless::Vec::new()),
+    };
+
+    serve(exchange, tx, rx, MAX_BLOCK_SIZE, |file_designator| {
+        // The file designator is kept, as the packet holding it is reused by the transfer
+        *file.designator.borrow_mut() = heapless::Vec::from_slice(file_designator).ok()?;
+
+        match files.read(file_designator, 0, &mut []) {
+            Ok(_) => Some(&file as &dyn BdxSource),
+            Err(e) => {
+                if e.code() != ErrorCode::NotFound {
+                    error!("Opening {:?} failed: {:?}", file_designator, e);
+                }
+
+                None
+            }
+        }
+    })
+    .await
+}
+
+async fn initiate<'r>(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &'r mut Packet<'_>,
+    init: &Message<'_>,
+) -> Result<Message<'r>, Error> {
+    prepare(tx, init)?;
+    exchange.exchange(tx, rx).await?;
+
+    decode(rx)
+}
+
+/// Drive the block exchange
+async fn run<T: Transfer>(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &mut Packet<'_>,
+    transfer: &mut T,
+) -> Result<(), Error> {
+    let mut buf = [0; MAX_BLOCK_SIZE as usize];
+
+    match transfer.start(&mut buf) {
+        Ok(Some(msg)) => prepare(tx, &msg)?,
+        Ok(None) => Err(ErrorCode::InvalidState)?,
+        Err(status) => return abort(exchange, tx, status).await,
+    }
+
+    exchange.exchange(tx, rx).await?;
+
+    step(exchange, tx, rx, transfer).await
+}
+
+/// Answer the messages of the peer, from the one in `rx`, until the transfer completes
+async fn step<T: Transfer>(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    rx: &mut Packet<'_>,
+    transfer: &mut T,
+) -> Result<(), Error> {
+    let mut buf = [0; MAX_BLOCK_SIZE as usize];
+
+    loop {
+        let reply = match decode(rx) {
+            Ok(msg) => transfer.handle(&msg, &mut buf),
+            Err(e) if e.code() == ErrorCode::TransferAborted => Err(e)?,
+            Err(_) => Err(StatusCode::BadMessageContents),
+        };
+
+        match reply {
+            Ok(Some(msg)) => prepare(tx, &msg)?,
+            // The peer sent the last message of the transfer
+            Ok(None) if transfer.is_done() => return exchange.acknowledge().await,
+            Ok(None) => Err(ErrorCode::InvalidState)?,
+            Err(status) => return abort(exchange, tx, status).await,
+        }
+
+        if transfer.is_done() {
+            return exchange.send_complete(tx).await;
+        }
+
+        exchange.exchange(tx, rx).await?;
+    }
+}
+
+fn prepare(tx: &mut Packet<'_>, msg: &Message<'_>) -> Result<(), Error> {
+    tx.reset();
+    tx.set_proto_id(PROTO_ID_BDX);
+    tx.set_proto_opcode(msg.opcode() as _);
+
+    msg.encode(tx.get_writebuf()?)
+}
+
+fn decode<'r>(rx: &'r Packet<'_>) -> Result<Message<'r>, Error> {
+    if rx.get_proto_id() == PROTO_ID_SECURE_CHANNEL
+        && rx.get_proto_raw_opcode() == SCOpCode::StatusReport as u8
+    {
+        error!("BDX transfer aborted by the peer");
+        Err(ErrorCode::TransferAborted)?;
+    }
+
+    if rx.get_proto_id() != PROTO_ID_BDX {
+        Err(ErrorCode::InvalidOpcode)?;
+    }
+
+    Message::decode(rx.get_proto_raw_opcode(), rx.as_slice())
+}
+
+async fn abort<T>(
+    exchange: &mut Exchange<'_>,
+    tx: &mut Packet<'_>,
+    status: StatusCode,
+) -> Result<T, Error> {
+    error!("Aborting BDX transfer: {:?}", status);
+
+    create_status_report(
+        tx,
+        GeneralCode::Failure,
+        PROTO_ID_BDX as _,
+        status as _,
+        None,
+    )?;
+    exchange.send_complete(tx).await?;
+
+    Err(ErrorCode::TransferAborted.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::writebuf::WriteBuf;
+
+    use super::{
+        BdxReceiver, BdxSender, BdxSink, BdxSource, Drive, Message, StatusCode, Transfer,
+        TransferAccept, TransferInit,
+    };
+
+    struct Sink {
+        data: heapless::Vec<u8, 4096>,
+    }
+
+    impl BdxSink for Sink {
+        fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), crate::error::Error> {
+            assert_eq!(offset as usize, self.data.len());
+            self.data.extend_from_slice(data).unwrap();
+
+            Ok(())
+        }
+    }
+
+    /// Encode the message and decode it back, as it travels over the wire
+    fn wire<'b>(msg: &Message, buf: &'b mut [u8]) -> Message<'b> {
+        let len = encode(msg, buf);
+
+        Message::decode(msg.opcode() as _, &buf[..len]).unwrap()
+    }
+
+    fn encode(msg: &Message, buf: &mut [u8]) -> usize {
+        let mut wb = WriteBuf::new(buf);
+        msg.encode(&mut wb).unwrap();
+
+        wb.as_slice().len()
+    }
+
+    /// Exchange the messages of the two sides until the transfer completes
+    ///
+    /// The lost messages are retransmitted by MRP, below the transfer, so they are not
+    /// seen here; the transfers over an exchange are driven by the integration tests.
+    fn transfer(
+        initiator: &mut dyn Transfer,
+        responder: &mut dyn Transfer,
+    ) -> Result<(), StatusCode> {
+        let mut buf = [0; 1100];
+        let mut tx = [0; 1100];
+        let mut rx = [0; 1100];
+
+        let mut next = initiator
+            .start(&mut buf)?
+            .map(|msg| (msg.opcode() as u8, encode(&msg, &mut tx)));
+        let mut sides = [initiator, responder];
+        let mut count = 0;
+
+        while let Some((opcode, len)) = next {
+            rx[..len].copy_from_slice(&tx[..len]);
+
+            let msg = Message::decode(opcode, &rx[..len]).unwrap();
+
+            next = sides[(count + 1) % 2]
+                .handle(&msg, &mut buf)?
+                .map(|msg| (msg.opcode() as u8, encode(&msg, &mut tx)));
+            count += 1;
+        }
+
+        assert!(sides[0].is_done() && sides[1].is_done());
+
+        Ok(())
+    }
+
+    fn data() -> [u8; 2500] {
+        let mut data = [0; 2500];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i % 251) as u8;
+        }
+
+        data
+    }
+
+    #[test]
+    fn sender_drive() {
+        let data = data();
+        let source: &[u8] = &data;
+        let mut sink = Sink {
+            data: heapless::Vec::new(),
+        };
+
+        let accept = TransferAccept {
+            drive: Drive::Sender,
+            max_block_size: 512,
+            length: None,
+        };
+
+        let mut sender = BdxSender::new(&source, &accept, 0);
+        let mut receiver = BdxReceiver::new(&mut sink, &accept, 0);
+
+        transfer(&mut sender, &mut receiver).unwrap();
+        assert_eq!(receiver.received(), data.len() as u64);
+        assert_eq!(sink.data.as_slice(), &data);
+    }
+
+    #[test]
+    fn receiver_drive() {
+        let data = data();
+        let source: &[u8] = &data;
+        let mut sink = Sink {
+            data: heapless::Vec::new(),
+        };
+
+        let accept = TransferAccept {
+            drive: Drive::Receiver,
+            max_block_size: 1000,
+            length: None,
+        };
+
+        let mut sender = BdxSender::new(&source, &accept, 0);
+        let mut receiver = BdxReceiver::new(&mut sink, &accept, 0);
+
+        transfer(&mut receiver, &mut sender).unwrap();
+        assert_eq!(sink.data.as_slice(), &data);
+    }
+
+    #[test]
+    /// A block which is an exact multiple of the block size still ends with a `BlockEof`
+    fn exact_blocks() {
+        let data = [0x55; 1024];
+        let source: &[u8] = &data;
+        let mut sink = Sink {
+            data: heapless::Vec::new(),
+        };
+
+        let accept = TransferAccept {
+            drive: Drive::Sender,
+            max_block_size: 256,
+            length: None,
+        };
+
+        let mut sender = BdxSender::new(&source, &accept, 0);
+        let mut receiver = BdxReceiver::new(&mut sink, &accept, 0);
+
+        transfer(&mut sender, &mut receiver).unwrap();
+        assert_eq!(sink.data.as_slice(), &data);
+    }
+
+    #[test]
+    fn bad_block_counter() {
+        let data = data();
+        let source: &[u8] = &data;
+        let mut sink = Sink {
+            data: heapless::Vec::new(),
+        };
+
+        let accept = TransferAccept {
+            drive: Drive::Sender,
+            max_block_size: 512,
+            length: None,
+        };
+
+        let mut buf = [0; 512];
+
+        let mut receiver = BdxReceiver::new(&mut sink, &accept, 0);
+        assert_eq!(
+            receiver.handle(&Message::Block(0, &data[..512]), &mut buf),
+            Ok(Some(Message::BlockAck(0)))
+        );
+        // A retransmission which reaches the state machine is a protocol violation
+        assert_eq!(
+            receiver.handle(&Message::Block(0, &data[..512]), &mut buf),
+            Err(StatusCode::BadBlockCounter)
+        );
+        assert_eq!(
+            receiver.handle(&Message::Block(1, &data[..513]), &mut buf),
+            Err(StatusCode::LengthTooLarge)
+        );
+
+        let mut sender = BdxSender::new(&source, &accept, 0);
+        assert!(matches!(
+            sender.start(&mut buf),
+            Ok(Some(Message::Block(0, _)))
+        ));
+        assert_eq!(
+            sender.handle(&Message::BlockAck(1), &mut buf),
+            Err(StatusCode::BadBlockCounter)
+        );
+        assert_eq!(
+            sender.handle(&Message::BlockQuery(1), &mut buf),
+            Err(StatusCode::UnexpectedMessage)
+        );
+    }
+
+    #[test]
+    fn init_accept() {
+        let init = TransferInit {
+            drive: Drive::Receiver,
+            max_block_size: 1024,
+            start_offset: Some(0x1_0000_0000),
+            max_length: None,
+            file_designator: b"image.ota",
+        };
+
+        let mut buf = [0; 100];
+
+        let msg = Message::ReceiveInit(init.clone());
+        assert_eq!(wire(&msg, &mut buf), msg);
+
+        let msg = Message::SendInit(TransferInit {
+            drive: Drive::Sender,
+            start_offset: None,
+            max_length: Some(2500),
+            ..init.clone()
+        });
+        assert_eq!(wire(&msg, &mut buf), msg);
+
+        let accept = init.accept(512);
+        assert_eq!(accept.max_block_size, 512);
+
+        let msg = Message::ReceiveAccept(TransferAccept {
+            length: Some(2500),
+            ..accept.clone()
+        });
+        assert_eq!(wire(&msg, &mut buf), msg);
+
+        let msg = Message::SendAccept(TransferAccept {
+            drive: Drive::Sender,
+            ..accept
+        });
+        assert_eq!(wire(&msg, &mut buf), msg);
+    }
+}
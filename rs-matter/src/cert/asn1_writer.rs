@@ -17,7 +17,7 @@
 
 use time::OffsetDateTime;
 
-use super::{CertConsumer, MAX_DEPTH};
+use super::{CertConsumer, MAX_DEPTH, NO_EXPIRY_TIME};
 use crate::{
     error::{Error, ErrorCode},
     utils::epoch::MATTER_EPOCH_SECS,
@@ -300,4 +300,8 @@ impl<'a> CertConsumer for ASN1Writer<'a> {
             self.write_str(0x17, time_str.as_bytes())
         }
     }
+
+    fn no_expiry(&mut self, _tag: &str) -> Result<(), Error> {
+        self.write_str(0x18, NO_EXPIRY_TIME)
+    }
 }
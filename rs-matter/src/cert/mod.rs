@@ -28,6 +28,7 @@ use num_derive::FromPrimitive;
 
 pub use self::asn1_writer::ASN1Writer;
 use self::printer::CertPrinter;
+pub use self::x509::{matter_to_x509, x509_to_matter};
 
 pub const MAX_CERT_TLV_LEN: usize = 1024; // TODO
 
@@ -143,31 +144,33 @@ fn encode_key_usage(key_usage: u16, w: &mut dyn CertConsumer) -> Result<(), Erro
     Ok(())
 }
 
+const OID_SERVER_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+const OID_CLIENT_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
+const OID_CODE_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+const OID_EMAIL_PROT: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x04];
+const OID_TIMESTAMP: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x08];
+const OID_OCSP_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+
+// Indexed by the Matter key purpose ID, which starts at 1
+const EXT_KEY_USAGE_ENCODING: [(&str, &[u8]); 7] = [
+    ("", &[0; 8]),
+    ("ServerAuth", &OID_SERVER_AUTH),
+    ("ClientAuth", &OID_CLIENT_AUTH),
+    ("CodeSign", &OID_CODE_SIGN),
+    ("EmailProtection", &OID_EMAIL_PROT),
+    ("Timestamp", &OID_TIMESTAMP),
+    ("OCSPSign", &OID_OCSP_SIGN),
+];
+
 fn encode_extended_key_usage(
     list: impl Iterator<Item = u8>,
     w: &mut dyn CertConsumer,
 ) -> Result<(), Error> {
-    const OID_SERVER_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
-    const OID_CLIENT_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
-    const OID_CODE_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
-    const OID_EMAIL_PROT: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x04];
-    const OID_TIMESTAMP: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x08];
-    const OID_OCSP_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
-    let encoding = [
-        ("", &[0; 8]),
-        ("ServerAuth", &OID_SERVER_AUTH),
-        ("ClientAuth", &OID_CLIENT_AUTH),
-        ("CodeSign", &OID_CODE_SIGN),
-        ("EmailProtection", &OID_EMAIL_PROT),
-        ("Timestamp", &OID_TIMESTAMP),
-        ("OCSPSign", &OID_OCSP_SIGN),
-    ];
-
     w.start_seq("")?;
     for t in list {
         let t = t as usize;
-        if t > 0 && t <= encoding.len() {
-            w.oid(encoding[t].0, encoding[t].1)?;
+        if t > 0 && t < EXT_KEY_USAGE_ENCODING.len() {
+            w.oid(EXT_KEY_USAGE_ENCODING[t].0, EXT_KEY_USAGE_ENCODING[t].1)?;
         } else {
             error!("Skipping encoding key usage out of bounds");
         }
@@ -215,6 +218,12 @@ fn encode_extension_end(w: &mut dyn CertConsumer) -> Result<(), Error> {
     w.end_seq()
 }
 
+const OID_BASIC_CONSTRAINTS: [u8; 3] = [0x55, 0x1D, 0x13];
+const OID_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x0F];
+const OID_EXT_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x25];
+const OID_SUBJ_KEY_IDENTIFIER: [u8; 3] = [0x55, 0x1D, 0x0E];
+const OID_AUTH_KEY_ID: [u8; 3] = [0x55, 0x1D, 0x23];
+
 #[derive(FromTLV, ToTLV, Default, Debug)]
 #[tlvargs(lifetime = "'a", start = 1, datatype = "list")]
 struct Extensions<'a> {
@@ -228,12 +237,6 @@ struct Extensions<'a> {
 
 impl<'a> Extensions<'a> {
     fn encode(&self, w: &mut dyn CertConsumer) -> Result<(), Error> {
-        const OID_BASIC_CONSTRAINTS: [u8; 3] = [0x55, 0x1D, 0x13];
-        const OID_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x0F];
-        const OID_EXT_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x25];
-        const OID_SUBJ_KEY_IDENTIFIER: [u8; 3] = [0x55, 0x1D, 0x0E];
-        const OID_AUTH_KEY_ID: [u8; 3] = [0x55, 0x1D, 0x23];
-
         w.start_ctx("X509v3 extensions:", 3)?;
         w.start_seq("")?;
         if let Some(t) = &self.basic_const {
@@ -296,6 +299,9 @@ enum DnTags {
     RootCaId = 20,
     FabricId = 21,
     NocCat = 22,
+    // The Matter-TLV encoding has no tags for the DNs of the attestation certificates
+    VendorId = 23,
+    ProductId = 24,
 }
 
 #[derive(Debug)]
@@ -389,94 +395,111 @@ impl<'a> ToTLV for DistNames<'a> {
     }
 }
 
+const OID_COMMON_NAME: [u8; 3] = [0x55_u8, 0x04, 0x03];
+const OID_SURNAME: [u8; 3] = [0x55_u8, 0x04, 0x04];
+const OID_SERIAL_NUMBER: [u8; 3] = [0x55_u8, 0x04, 0x05];
+const OID_COUNTRY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x06];
+const OID_LOCALITY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x07];
+const OID_STATE_NAME: [u8; 3] = [0x55_u8, 0x04, 0x08];
+const OID_ORGANIZATION_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0A];
+const OID_ORGANIZATIONAL_UNIT_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0B];
+const OID_TITLE: [u8; 3] = [0x55_u8, 0x04, 0x0C];
+const OID_NAME: [u8; 3] = [0x55_u8, 0x04, 0x29];
+const OID_GIVEN_NAME: [u8; 3] = [0x55_u8, 0x04, 0x2A];
+const OID_INITIALS: [u8; 3] = [0x55_u8, 0x04, 0x2B];
+const OID_GENERATION_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2C];
+const OID_DN_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2E];
+const OID_PSEUDONYM: [u8; 3] = [0x55_u8, 0x04, 0x41];
+const OID_DOMAIN_COMPONENT: [u8; 10] = [
+    0x09_u8, 0x92, 0x26, 0x89, 0x93, 0xF2, 0x2C, 0x64, 0x01, 0x19,
+];
+const OID_MATTER_NODE_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x01,
+];
+const OID_MATTER_FW_SIGNING_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x02,
+];
+const OID_MATTER_ICAC_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x03,
+];
+const OID_MATTER_RCAC_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x04,
+];
+const OID_MATTER_FABRIC_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x05,
+];
+const OID_MATTER_CASE_AUTH_TAG: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x06,
+];
+const OID_MATTER_VENDOR_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x02, 0x01,
+];
+const OID_MATTER_PRODUCT_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x02, 0x02,
+];
+
+// Indexed by the DN tag minus 1
+const DN_ENCODING: [(&str, &[u8], Option<IntToStringLen>); 24] = [
+    ("Common Name:", &OID_COMMON_NAME, None),
+    ("Surname:", &OID_SURNAME, None),
+    ("Serial Number", &OID_SERIAL_NUMBER, None),
+    ("Country Name", &OID_COUNTRY_NAME, None),
+    ("Locality name", &OID_LOCALITY_NAME, None),
+    ("State Name", &OID_STATE_NAME, None),
+    ("Org Name", &OID_ORGANIZATION_NAME, None),
+    ("OU Name", &OID_ORGANIZATIONAL_UNIT_NAME, None),
+    ("Title", &OID_TITLE, None),
+    ("Name", &OID_NAME, None),
+    ("Given Name", &OID_GIVEN_NAME, None),
+    ("Initials", &OID_INITIALS, None),
+    ("Gen Qualifier", &OID_GENERATION_QUALIFIER, None),
+    ("DN Qualifier", &OID_DN_QUALIFIER, None),
+    ("Pseudonym", &OID_PSEUDONYM, None),
+    ("Domain Component", &OID_DOMAIN_COMPONENT, None),
+    (
+        "Chip Node Id:",
+        &OID_MATTER_NODE_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip Firmware Signing Id:",
+        &OID_MATTER_FW_SIGNING_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip ICA Id:",
+        &OID_MATTER_ICAC_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip Root CA Id:",
+        &OID_MATTER_RCAC_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip Fabric Id:",
+        &OID_MATTER_FABRIC_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip NOC CAT Id:",
+        &OID_MATTER_CASE_AUTH_TAG,
+        Some(IntToStringLen::Len8),
+    ),
+    (
+        "Chip Vendor Id:",
+        &OID_MATTER_VENDOR_ID,
+        Some(IntToStringLen::Len4),
+    ),
+    (
+        "Chip Product Id:",
+        &OID_MATTER_PRODUCT_ID,
+        Some(IntToStringLen::Len4),
+    ),
+];
+
 impl<'a> DistNames<'a> {
     fn encode(&self, tag: &str, w: &mut dyn CertConsumer) -> Result<(), Error> {
-        const OID_COMMON_NAME: [u8; 3] = [0x55_u8, 0x04, 0x03];
-        const OID_SURNAME: [u8; 3] = [0x55_u8, 0x04, 0x04];
-        const OID_SERIAL_NUMBER: [u8; 3] = [0x55_u8, 0x04, 0x05];
-        const OID_COUNTRY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x06];
-        const OID_LOCALITY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x07];
-        const OID_STATE_NAME: [u8; 3] = [0x55_u8, 0x04, 0x08];
-        const OID_ORGANIZATION_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0A];
-        const OID_ORGANIZATIONAL_UNIT_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0B];
-        const OID_TITLE: [u8; 3] = [0x55_u8, 0x04, 0x0C];
-        const OID_NAME: [u8; 3] = [0x55_u8, 0x04, 0x29];
-        const OID_GIVEN_NAME: [u8; 3] = [0x55_u8, 0x04, 0x2A];
-        const OID_INITIALS: [u8; 3] = [0x55_u8, 0x04, 0x2B];
-        const OID_GENERATION_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2C];
-        const OID_DN_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2E];
-        const OID_PSEUDONYM: [u8; 3] = [0x55_u8, 0x04, 0x41];
-        const OID_DOMAIN_COMPONENT: [u8; 10] = [
-            0x09_u8, 0x92, 0x26, 0x89, 0x93, 0xF2, 0x2C, 0x64, 0x01, 0x19,
-        ];
-        const OID_MATTER_NODE_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x01,
-        ];
-        const OID_MATTER_FW_SIGNING_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x02,
-        ];
-        const OID_MATTER_ICAC_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x03,
-        ];
-        const OID_MATTER_RCAC_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x04,
-        ];
-        const OID_MATTER_FABRIC_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x05,
-        ];
-        const OID_MATTER_CASE_AUTH_TAG: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x06,
-        ];
-
-        const DN_ENCODING: [(&str, &[u8], Option<IntToStringLen>); 22] = [
-            ("Common Name:", &OID_COMMON_NAME, None),
-            ("Surname:", &OID_SURNAME, None),
-            ("Serial Number", &OID_SERIAL_NUMBER, None),
-            ("Country Name", &OID_COUNTRY_NAME, None),
-            ("Locality name", &OID_LOCALITY_NAME, None),
-            ("State Name", &OID_STATE_NAME, None),
-            ("Org Name", &OID_ORGANIZATION_NAME, None),
-            ("OU Name", &OID_ORGANIZATIONAL_UNIT_NAME, None),
-            ("Title", &OID_TITLE, None),
-            ("Name", &OID_NAME, None),
-            ("Given Name", &OID_GIVEN_NAME, None),
-            ("Initials", &OID_INITIALS, None),
-            ("Gen Qualifier", &OID_GENERATION_QUALIFIER, None),
-            ("DN Qualifier", &OID_DN_QUALIFIER, None),
-            ("Pseudonym", &OID_PSEUDONYM, None),
-            ("Domain Component", &OID_DOMAIN_COMPONENT, None),
-            (
-                "Chip Node Id:",
-                &OID_MATTER_NODE_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip Firmware Signing Id:",
-                &OID_MATTER_FW_SIGNING_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip ICA Id:",
-                &OID_MATTER_ICAC_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip Root CA Id:",
-                &OID_MATTER_RCAC_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip Fabric Id:",
-                &OID_MATTER_FABRIC_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip NOC CAT Id:",
-                &OID_MATTER_CASE_AUTH_TAG,
-                Some(IntToStringLen::Len8),
-            ),
-        ];
-
         w.start_seq(tag)?;
         for (id, value) in &self.dn {
             let tag: Option<DnTags> = num::FromPrimitive::from_u8(*id);
@@ -504,6 +527,7 @@ impl<'a> DistNames<'a> {
 enum IntToStringLen {
     Len16,
     Len8,
+    Len4,
 }
 
 fn encode_dn_value(
@@ -529,6 +553,11 @@ fn encode_dn_value(
                 write!(&mut string, "{:08X}", v).unwrap();
                 w.utf8str("", &string)?
             }
+            Some(IntToStringLen::Len4) => {
+                let mut string = heapless::String::<32>::new();
+                write!(&mut string, "{:04X}", v).unwrap();
+                w.utf8str("", &string)?
+            }
             _ => {
                 error!("Invalid encoding");
                 Err(ErrorCode::Invalid)?
@@ -585,6 +614,16 @@ impl<'a> Cert<'a> {
             .ok_or_else(|| Error::from(ErrorCode::NoFabricId))
     }
 
+    /// The vendor ID of an attestation certificate
+    pub fn get_vendor_id(&self) -> Option<u16> {
+        self.subject.u64(DnTags::VendorId).map(|vid| vid as u16)
+    }
+
+    /// The product ID of a DAC, or of a PAI which is specific to a product
+    pub fn get_product_id(&self) -> Option<u16> {
+        self.subject.u64(DnTags::ProductId).map(|pid| pid as u16)
+    }
+
     pub fn get_pubkey(&self) -> &[u8] {
         self.pubkey.0
     }
@@ -651,7 +690,12 @@ impl<'a> Cert<'a> {
 
         w.start_seq("Validity:")?;
         w.utctime("Not Before:", self.not_before)?;
-        w.utctime("Not After:", self.not_after)?;
+        if self.not_after == 0 {
+            // No well-defined expiration date
+            w.no_expiry("Not After:")?;
+        } else {
+            w.utctime("Not After:", self.not_after)?;
+        }
         w.end_seq()?;
 
         self.subject.encode("Subject:", w)?;
@@ -745,16 +789,22 @@ pub trait CertConsumer {
     fn end_ctx(&mut self) -> Result<(), Error>;
     fn oid(&mut self, tag: &str, oid: &[u8]) -> Result<(), Error>;
     fn utctime(&mut self, tag: &str, epoch: u32) -> Result<(), Error>;
+    fn no_expiry(&mut self, tag: &str) -> Result<(), Error>;
 }
 
 const MAX_DEPTH: usize = 10;
+
+/// The X.509 GeneralizedTime of a certificate with no well-defined expiration date,
+/// which is a not-after of 0 in Matter-TLV
+const NO_EXPIRY_TIME: &[u8] = b"99991231235959Z";
 const MAX_ASN1_CERT_SIZE: usize = 1000;
 
 mod asn1_writer;
 mod printer;
+mod x509;
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use log::info;
 
     use crate::cert::Cert;
@@ -859,7 +909,7 @@ mod tests {
         }
     }
 
-    mod test_vectors {
+    pub(crate) mod test_vectors {
         // Group 1
         pub const NOC1_SUCCESS: [u8; 247] = [
             0x15, 0x30, 0x1, 0x1, 0x1, 0x24, 0x2, 0x1, 0x37, 0x3, 0x24, 0x13, 0x1, 0x24, 0x15, 0x1,
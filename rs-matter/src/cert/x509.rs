@@ -0,0 +1,662 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! Conversion between Matter-TLV certificates and DER-encoded X.509 certificates
+//!
+//! See section 6.5 Operational Certificate Encoding in the Matter specification. Only the
+//! subset of X.509 that has a Matter-TLV representation is supported: ECDSA with SHA256
+//! over prime256v1 keys, the standard and the Matter-specific DN attributes (including the
+//! vendor and product IDs of the attestation certificates) encoded as UTF8 or Printable
+//! strings, and the Basic Constraints, Key Usage, Extended Key Usage, Subject Key ID and
+//! Authority Key ID extensions.
+
+use time::{Date, Month};
+
+use crate::{
+    crypto,
+    error::{Error, ErrorCode},
+    tlv::{OctetStr, TLVArray},
+    utils::epoch::MATTER_EPOCH_SECS,
+};
+use log::error;
+
+use super::{
+    reverse_byte, ASN1Writer, BasicConstraints, Cert, CertConsumer, DistNameValue, DistNames,
+    EcCurveIdValue, Extensions, IntToStringLen, PubKeyAlgoValue, SignAlgoValue, DN_ENCODING,
+    EXT_KEY_USAGE_ENCODING, MAX_ASN1_CERT_SIZE, MAX_CERT_TLV_LEN, NO_EXPIRY_TIME, OID_AUTH_KEY_ID,
+    OID_BASIC_CONSTRAINTS, OID_ECDSA_WITH_SHA256, OID_EC_TYPE_PRIME256V1, OID_EXT_KEY_USAGE,
+    OID_KEY_USAGE, OID_PUB_KEY_ECPUBKEY, OID_SUBJ_KEY_IDENTIFIER,
+};
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const UTF8_STRING: u8 = 0x0C;
+const PRINTABLE_STRING: u8 = 0x13;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const VERSION: u8 = 0xA0;
+const EXTENSIONS: u8 = 0xA3;
+const KEY_IDENTIFIER: u8 = 0x80;
+
+const X509_V3: u8 = 2;
+
+// A sequence of two integers, each of which may need a leading zero
+const MAX_DER_SIGNATURE_LEN: usize = 2 + 2 * (2 + crypto::BIGNUM_LEN_BYTES + 1);
+
+/// Convert a Matter-TLV certificate into a DER-encoded X.509 certificate
+pub fn matter_to_x509(cert: &[u8]) -> Result<heapless::Vec<u8, MAX_ASN1_CERT_SIZE>, Error> {
+    let cert = Cert::new(cert)?;
+
+    let signature = cert.get_signature();
+    if signature.len() != crypto::EC_SIGNATURE_LEN_BYTES {
+        Err(ErrorCode::Invalid)?;
+    }
+
+    // The X.509 signature is the DER encoding of the (r, s) pair
+    let (r, s) = signature.split_at(crypto::BIGNUM_LEN_BYTES);
+    let mut der_signature = [0; MAX_DER_SIGNATURE_LEN];
+    let mut sw = ASN1Writer::new(&mut der_signature);
+    let mut int_buf = [0; crypto::BIGNUM_LEN_BYTES + 1];
+    sw.start_seq("")?;
+    sw.integer("", encode_integer(r, &mut int_buf))?;
+    sw.integer("", encode_integer(s, &mut int_buf))?;
+    sw.end_seq()?;
+
+    let mut buf = [0; MAX_ASN1_CERT_SIZE];
+    let mut w = ASN1Writer::new(&mut buf);
+    w.start_seq("")?;
+    cert.encode(&mut w)?;
+    w.start_seq("")?;
+    w.oid("", &OID_ECDSA_WITH_SHA256)?;
+    w.end_seq()?;
+    w.bitstr("", false, sw.as_slice())?;
+    w.end_seq()?;
+
+    heapless::Vec::from_slice(w.as_slice()).map_err(|_| ErrorCode::NoSpace.into())
+}
+
+/// Convert a DER-encoded X.509 certificate into a Matter-TLV certificate
+///
+/// Certificates which use anything outside of what the Matter-TLV encoding can represent
+/// are rejected, as their signature would not verify after the conversion.
+pub fn x509_to_matter(der: &[u8]) -> Result<heapless::Vec<u8, MAX_CERT_TLV_LEN>, Error> {
+    let mut outer = DerReader::new(der);
+    let mut x509 = outer.read_seq(SEQUENCE)?;
+    outer.finish()?;
+
+    let mut tbs = x509.read_seq(SEQUENCE)?;
+    read_sign_algo(&mut x509)?;
+    let mut signature = [0; crypto::EC_SIGNATURE_LEN_BYTES];
+    decode_signature(x509.read(BIT_STRING)?, &mut signature)?;
+    x509.finish()?;
+
+    let mut version = tbs.read_seq(VERSION)?;
+    if version.read(INTEGER)? != [X509_V3] {
+        error!("Only X.509 v3 certificates are supported");
+        Err(ErrorCode::Invalid)?;
+    }
+    version.finish()?;
+
+    let serial_no = tbs.read(INTEGER)?;
+    read_sign_algo(&mut tbs)?;
+    let issuer = decode_dist_names(tbs.read(SEQUENCE)?)?;
+
+    let mut validity = tbs.read_seq(SEQUENCE)?;
+    let not_before = decode_time(&mut validity)?;
+    let not_after = decode_time(&mut validity)?;
+    validity.finish()?;
+
+    let subject = decode_dist_names(tbs.read(SEQUENCE)?)?;
+
+    let mut pubkey_info = tbs.read_seq(SEQUENCE)?;
+    let mut pubkey_algo = pubkey_info.read_seq(SEQUENCE)?;
+    read_oid(&mut pubkey_algo, &OID_PUB_KEY_ECPUBKEY)?;
+    read_oid(&mut pubkey_algo, &OID_EC_TYPE_PRIME256V1)?;
+    pubkey_algo.finish()?;
+    let pubkey = decode_bits(pubkey_info.read(BIT_STRING)?)?;
+    pubkey_info.finish()?;
+
+    let mut ext_key_usage = heapless::Vec::new();
+    let extensions = match tbs.read_optional(EXTENSIONS)? {
+        Some(extensions) => decode_extensions(extensions, &mut ext_key_usage)?,
+        None => Extensions::default(),
+    };
+    tbs.finish()?;
+
+    let cert = Cert {
+        serial_no: OctetStr(serial_no),
+        sign_algo: SignAlgoValue::ECDSAWithSHA256 as _,
+        issuer,
+        not_before,
+        not_after,
+        subject,
+        pubkey_algo: PubKeyAlgoValue::EcPubKey as _,
+        ec_curve_id: EcCurveIdValue::Prime256V1 as _,
+        pubkey: OctetStr(pubkey),
+        extensions,
+        signature: OctetStr(&signature),
+    };
+
+    let mut buf = [0; MAX_CERT_TLV_LEN];
+    let len = cert.as_tlv(&mut buf)?;
+
+    heapless::Vec::from_slice(&buf[..len]).map_err(|_| ErrorCode::NoSpace.into())
+}
+
+/// A reader over the DER-encoded elements of a constructed ASN.1 value
+struct DerReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Read the next element, which has to be of the given tag, returning its contents
+    fn read(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        let (&actual, data) = self.data.split_first().ok_or(ErrorCode::Invalid)?;
+        if actual != tag {
+            error!("Expected ASN.1 tag {:02x}, found {:02x}", tag, actual);
+            Err(ErrorCode::Invalid)?;
+        }
+
+        let (&len, mut data) = data.split_first().ok_or(ErrorCode::Invalid)?;
+        let len = if len < 0x80 {
+            len as usize
+        } else {
+            // Certificates never need more than 2 bytes of length
+            let bytes_of_len = (len & 0x7F) as usize;
+            if bytes_of_len == 0 || bytes_of_len > 2 || data.len() < bytes_of_len {
+                Err(ErrorCode::Invalid)?;
+            }
+
+            let (len, rest) = data.split_at(bytes_of_len);
+            data = rest;
+            len.iter().fold(0, |len, byte| (len << 8) | *byte as usize)
+        };
+
+        if data.len() < len {
+            Err(ErrorCode::Invalid)?;
+        }
+
+        let (value, rest) = data.split_at(len);
+        self.data = rest;
+
+        Ok(value)
+    }
+
+    fn read_seq(&mut self, tag: u8) -> Result<DerReader<'a>, Error> {
+        Ok(DerReader::new(self.read(tag)?))
+    }
+
+    fn read_optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>, Error> {
+        if self.data.first() == Some(&tag) {
+            Ok(Some(self.read(tag)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Check that all the elements were read
+    fn finish(&self) -> Result<(), Error> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            error!("Unexpected trailing ASN.1 data: {:x?}", self.data);
+            Err(ErrorCode::Invalid.into())
+        }
+    }
+}
+
+fn read_oid(reader: &mut DerReader, oid: &[u8]) -> Result<(), Error> {
+    let actual = reader.read(OID)?;
+    if actual != oid {
+        error!("Unsupported OID {:x?}", actual);
+        Err(ErrorCode::Invalid)?;
+    }
+
+    Ok(())
+}
+
+fn read_sign_algo(reader: &mut DerReader) -> Result<(), Error> {
+    let mut sign_algo = reader.read_seq(SEQUENCE)?;
+    read_oid(&mut sign_algo, &OID_ECDSA_WITH_SHA256)?;
+    sign_algo.finish()
+}
+
+/// The contents of a bit string, which has to be a whole number of bytes
+fn decode_bits(bits: &[u8]) -> Result<&[u8], Error> {
+    match bits.split_first() {
+        Some((0, bits)) => Ok(bits),
+        _ => Err(ErrorCode::Invalid.into()),
+    }
+}
+
+/// Encode a fixed-length unsigned integer as the contents of a DER integer, i.e. with
+/// its leading zeros stripped, and with a leading zero if its top bit is set
+fn encode_integer<'a>(int: &[u8], buf: &'a mut [u8]) -> &'a [u8] {
+    let start = int
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(int.len() - 1);
+    let int = &int[start..];
+
+    let pad = (int[0] & 0x80 != 0) as usize;
+    buf[0] = 0;
+    buf[pad..pad + int.len()].copy_from_slice(int);
+
+    &buf[..pad + int.len()]
+}
+
+/// The inverse of `encode_integer`, left-padding the integer to the length of `out`
+fn decode_integer(int: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    let start = int.iter().position(|byte| *byte != 0).unwrap_or(int.len());
+    let int = &int[start..];
+
+    if int.len() > out.len() {
+        Err(ErrorCode::Invalid)?;
+    }
+
+    let pad = out.len() - int.len();
+    out[..pad].fill(0);
+    out[pad..].copy_from_slice(int);
+
+    Ok(())
+}
+
+fn decode_signature(
+    bits: &[u8],
+    signature: &mut [u8; crypto::EC_SIGNATURE_LEN_BYTES],
+) -> Result<(), Error> {
+    let mut outer = DerReader::new(decode_bits(bits)?);
+    let mut pair = outer.read_seq(SEQUENCE)?;
+    outer.finish()?;
+
+    let (r, s) = signature.split_at_mut(crypto::BIGNUM_LEN_BYTES);
+    decode_integer(pair.read(INTEGER)?, r)?;
+    decode_integer(pair.read(INTEGER)?, s)?;
+
+    pair.finish()
+}
+
+fn decode_dist_names(names: &[u8]) -> Result<DistNames, Error> {
+    let mut names = DerReader::new(names);
+    let mut dist_names = DistNames::default();
+
+    while !names.is_empty() {
+        let mut rdn = names.read_seq(SET)?;
+        let mut attr = rdn.read_seq(SEQUENCE)?;
+        rdn.finish()?;
+
+        let oid = attr.read(OID)?;
+        let index = DN_ENCODING
+            .iter()
+            .position(|(_, dn_oid, _)| *dn_oid == oid)
+            .ok_or_else(|| {
+                error!("Unsupported DN {:x?}", oid);
+                ErrorCode::Invalid
+            })?;
+
+        let value = if let Some(value) = attr.read_optional(UTF8_STRING)? {
+            DistNameValue::Utf8Str(value)
+        } else {
+            DistNameValue::PrintableStr(attr.read(PRINTABLE_STRING)?)
+        };
+        attr.finish()?;
+
+        // The Matter-specific DNs are integers, encoded as upper-case hex strings
+        let value = match (DN_ENCODING[index].2, value) {
+            (None, value) => value,
+            (Some(int_len), DistNameValue::Utf8Str(value)) => {
+                let expected_len = match int_len {
+                    IntToStringLen::Len16 => 16,
+                    IntToStringLen::Len8 => 8,
+                    IntToStringLen::Len4 => 4,
+                };
+
+                if value.len() != expected_len
+                    || !value
+                        .iter()
+                        .all(|c| c.is_ascii_digit() || (b'A'..=b'F').contains(c))
+                {
+                    Err(ErrorCode::Invalid)?;
+                }
+
+                let value = u64::from_str_radix(core::str::from_utf8(value)?, 16)
+                    .map_err(|_| ErrorCode::Invalid)?;
+
+                DistNameValue::Uint(value)
+            }
+            (Some(_), _) => Err(ErrorCode::Invalid)?,
+        };
+
+        dist_names
+            .dn
+            .push((index as u8 + 1, value))
+            .map_err(|_| ErrorCode::BufferTooSmall)?;
+    }
+
+    Ok(dist_names)
+}
+
+/// Decode an UTCTime or a GeneralizedTime into seconds since the Matter epoch
+///
+/// The time of no well-defined expiration date, as used by attestation certificates,
+/// decodes into 0.
+fn decode_time(validity: &mut DerReader) -> Result<u32, Error> {
+    let (year, time) = if let Some(time) = validity.read_optional(UTC_TIME)? {
+        if time.len() != 13 {
+            Err(ErrorCode::Invalid)?;
+        }
+
+        // Two-digit years below 50 are in the 21st century
+        let year = decode_digits(&time[..2])?;
+        (
+            if year < 50 { 2000 + year } else { 1900 + year },
+            &time[2..],
+        )
+    } else {
+        let time = validity.read(GENERALIZED_TIME)?;
+        if time == NO_EXPIRY_TIME {
+            return Ok(0);
+        }
+
+        if time.len() != 15 {
+            Err(ErrorCode::Invalid)?;
+        }
+
+        (decode_digits(&time[..4])?, &time[4..])
+    };
+
+    if time[10] != b'Z' {
+        Err(ErrorCode::Invalid)?;
+    }
+
+    let month =
+        Month::try_from(decode_digits(&time[0..2])? as u8).map_err(|_| ErrorCode::Invalid)?;
+    let day = decode_digits(&time[2..4])? as u8;
+    let hour = decode_digits(&time[4..6])? as u8;
+    let minute = decode_digits(&time[6..8])? as u8;
+    let second = decode_digits(&time[8..10])? as u8;
+
+    let secs = Date::from_calendar_date(year as _, month, day)
+        .and_then(|date| date.with_hms(hour, minute, second))
+        .map_err(|_| ErrorCode::Invalid)?
+        .assume_utc()
+        .unix_timestamp();
+
+    u32::try_from(secs - MATTER_EPOCH_SECS as i64).map_err(|_| ErrorCode::Invalid.into())
+}
+
+fn decode_digits(digits: &[u8]) -> Result<u16, Error> {
+    digits.iter().try_fold(0, |value, digit| {
+        if digit.is_ascii_digit() {
+            Ok(value * 10 + (digit - b'0') as u16)
+        } else {
+            Err(ErrorCode::Invalid.into())
+        }
+    })
+}
+
+fn decode_extensions<'a>(
+    extensions: &'a [u8],
+    ext_key_usage: &'a mut heapless::Vec<u8, { EXT_KEY_USAGE_ENCODING.len() }>,
+) -> Result<Extensions<'a>, Error> {
+    let mut outer = DerReader::new(extensions);
+    let mut list = outer.read_seq(SEQUENCE)?;
+    outer.finish()?;
+
+    let mut extensions = Extensions::default();
+    let mut has_ext_key_usage = false;
+
+    while !list.is_empty() {
+        let mut extension = list.read_seq(SEQUENCE)?;
+        let oid = extension.read(OID)?;
+        // Whether the extension is critical is implied by its type
+        extension.read_optional(BOOLEAN)?;
+        let mut value = DerReader::new(extension.read(OCTET_STRING)?);
+        extension.finish()?;
+
+        if oid == OID_BASIC_CONSTRAINTS {
+            let mut constraints = value.read_seq(SEQUENCE)?;
+            // DER omits the default value of false
+            let is_ca = match constraints.read_optional(BOOLEAN)? {
+                Some(&[0xFF]) => true,
+                Some(_) => Err(ErrorCode::Invalid)?,
+                None => false,
+            };
+            let path = match constraints.read_optional(INTEGER)? {
+                Some(&[path]) => Some(path),
+                Some(_) => Err(ErrorCode::Invalid)?,
+                None => None,
+            };
+            constraints.finish()?;
+
+            extensions.basic_const = Some(BasicConstraints { is_ca, path });
+        } else if oid == OID_KEY_USAGE {
+            // The bits are in the reverse order of the Matter key usage flags
+            let bits = value.read(BIT_STRING)?;
+            let bits = bits
+                .get(1..)
+                .filter(|bits| !bits.is_empty() && bits.len() <= 2);
+            let bits = bits.ok_or(ErrorCode::Invalid)?;
+
+            extensions.key_usage = Some(bits.iter().rev().fold(0, |key_usage, byte| {
+                (key_usage << 8) | reverse_byte(*byte) as u16
+            }));
+        } else if oid == OID_EXT_KEY_USAGE {
+            let mut purposes = value.read_seq(SEQUENCE)?;
+            while !purposes.is_empty() {
+                let oid = purposes.read(OID)?;
+                let purpose = EXT_KEY_USAGE_ENCODING
+                    .iter()
+                    .skip(1)
+                    .position(|(_, purpose_oid)| *purpose_oid == oid)
+                    .ok_or(ErrorCode::Invalid)?;
+
+                ext_key_usage
+                    .push(purpose as u8 + 1)
+                    .map_err(|_| ErrorCode::Invalid)?;
+            }
+
+            has_ext_key_usage = true;
+        } else if oid == OID_SUBJ_KEY_IDENTIFIER {
+            extensions.subj_key_id = Some(OctetStr(value.read(OCTET_STRING)?));
+        } else if oid == OID_AUTH_KEY_ID {
+            let mut auth_key_id = value.read_seq(SEQUENCE)?;
+            extensions.auth_key_id = Some(OctetStr(auth_key_id.read(KEY_IDENTIFIER)?));
+            auth_key_id.finish()?;
+        } else {
+            error!("Unsupported extension {:x?}", oid);
+            Err(ErrorCode::Invalid)?;
+        }
+
+        value.finish()?;
+    }
+
+    if has_ext_key_usage {
+        extensions.ext_key_usage = Some(TLVArray::new(ext_key_usage));
+    }
+
+    Ok(extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cert::tests::test_vectors, cert::Cert, crypto::KeyPair};
+
+    use super::{matter_to_x509, x509_to_matter, DerReader, SEQUENCE};
+
+    const TEST_CERTS: [&[u8]; 3] = [
+        &test_vectors::NOC1_SUCCESS,
+        &test_vectors::ICAC1_SUCCESS,
+        &test_vectors::RCA1_SUCCESS,
+    ];
+
+    // credentials/examples/ExampleDACs.cpp FFF1-8000-0002-Cert
+    const DAC_CERT: [u8; 492] = [
+        0x30, 0x82, 0x01, 0xe8, 0x30, 0x82, 0x01, 0x8e, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x08,
+        0x52, 0x72, 0x4d, 0x21, 0xe2, 0xc1, 0x74, 0xaf, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48,
+        0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x3d, 0x31, 0x25, 0x30, 0x23, 0x06, 0x03, 0x55, 0x04,
+        0x03, 0x0c, 0x1c, 0x4d, 0x61, 0x74, 0x74, 0x65, 0x72, 0x20, 0x44, 0x65, 0x76, 0x20, 0x50,
+        0x41, 0x49, 0x20, 0x30, 0x78, 0x46, 0x46, 0x46, 0x31, 0x20, 0x6e, 0x6f, 0x20, 0x50, 0x49,
+        0x44, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c,
+        0x02, 0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x30, 0x20, 0x17, 0x0d, 0x32, 0x32, 0x30,
+        0x32, 0x30, 0x35, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x39, 0x39, 0x39,
+        0x39, 0x31, 0x32, 0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30, 0x53, 0x31,
+        0x25, 0x30, 0x23, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x1c, 0x4d, 0x61, 0x74, 0x74, 0x65,
+        0x72, 0x20, 0x44, 0x65, 0x76, 0x20, 0x44, 0x41, 0x43, 0x20, 0x30, 0x78, 0x46, 0x46, 0x46,
+        0x31, 0x2f, 0x30, 0x78, 0x38, 0x30, 0x30, 0x32, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b,
+        0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31,
+        0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02,
+        0x02, 0x0c, 0x04, 0x38, 0x30, 0x30, 0x32, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86,
+        0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07,
+        0x03, 0x42, 0x00, 0x04, 0xda, 0x93, 0xf1, 0x67, 0x36, 0x25, 0x67, 0x50, 0xd9, 0x03, 0xb0,
+        0x34, 0xba, 0x45, 0x88, 0xab, 0xaf, 0x58, 0x95, 0x4f, 0x77, 0xaa, 0x9f, 0xd9, 0x98, 0x9d,
+        0xfd, 0x40, 0x0d, 0x7a, 0xb3, 0xfd, 0xc9, 0x75, 0x3b, 0x3b, 0x92, 0x1b, 0x29, 0x4c, 0x95,
+        0x0f, 0xd9, 0xd2, 0x80, 0xd1, 0x4c, 0x43, 0x86, 0x2f, 0x16, 0xdc, 0x85, 0x4b, 0x00, 0xed,
+        0x39, 0xe7, 0x50, 0xba, 0xbf, 0x1d, 0xc4, 0xca, 0xa3, 0x60, 0x30, 0x5e, 0x30, 0x0c, 0x06,
+        0x03, 0x55, 0x1d, 0x13, 0x01, 0x01, 0xff, 0x04, 0x02, 0x30, 0x00, 0x30, 0x0e, 0x06, 0x03,
+        0x55, 0x1d, 0x0f, 0x01, 0x01, 0xff, 0x04, 0x04, 0x03, 0x02, 0x07, 0x80, 0x30, 0x1d, 0x06,
+        0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0xef, 0x06, 0x56, 0x11, 0x9c, 0x1c, 0x91,
+        0xa7, 0x9a, 0x94, 0xe6, 0xdc, 0xf3, 0x79, 0x79, 0xdb, 0xd0, 0x7f, 0xf8, 0xa3, 0x30, 0x1f,
+        0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0x63, 0x54, 0x0e, 0x47,
+        0xf6, 0x4b, 0x1c, 0x38, 0xd1, 0x38, 0x84, 0xa4, 0x62, 0xd1, 0x6c, 0x19, 0x5d, 0x8f, 0xfb,
+        0x3c, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03, 0x48,
+        0x00, 0x30, 0x45, 0x02, 0x20, 0x46, 0x86, 0x81, 0x07, 0x33, 0xbf, 0x0d, 0xc8, 0xff, 0x4c,
+        0xb5, 0x14, 0x5a, 0x6b, 0xfa, 0x1a, 0xec, 0xff, 0xa8, 0xb6, 0xda, 0xb6, 0xc3, 0x51, 0xaa,
+        0xee, 0xcd, 0xaf, 0xb8, 0xbe, 0x95, 0x7d, 0x02, 0x21, 0x00, 0xe8, 0xc2, 0x8d, 0x6b, 0xfc,
+        0xc8, 0x7a, 0x7d, 0x54, 0x2e, 0xad, 0x6e, 0xda, 0xca, 0x14, 0x8d, 0x5f, 0xa5, 0x06, 0x1e,
+        0x51, 0x7c, 0xbe, 0x4f, 0x24, 0xa7, 0x20, 0xe1, 0xc0, 0x59, 0xde, 0x1a,
+    ];
+
+    // credentials/examples/ExamplePAI.cpp FFF1
+    const PAI_CERT: [u8; 463] = [
+        0x30, 0x82, 0x01, 0xcb, 0x30, 0x82, 0x01, 0x71, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x08,
+        0x56, 0xad, 0x82, 0x22, 0xad, 0x94, 0x5b, 0x64, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48,
+        0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x30, 0x31, 0x18, 0x30, 0x16, 0x06, 0x03, 0x55, 0x04,
+        0x03, 0x0c, 0x0f, 0x4d, 0x61, 0x74, 0x74, 0x65, 0x72, 0x20, 0x54, 0x65, 0x73, 0x74, 0x20,
+        0x50, 0x41, 0x41, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82,
+        0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x30, 0x20, 0x17, 0x0d, 0x32,
+        0x32, 0x30, 0x32, 0x30, 0x35, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x39,
+        0x39, 0x39, 0x39, 0x31, 0x32, 0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30,
+        0x3d, 0x31, 0x25, 0x30, 0x23, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x1c, 0x4d, 0x61, 0x74,
+        0x74, 0x65, 0x72, 0x20, 0x44, 0x65, 0x76, 0x20, 0x50, 0x41, 0x49, 0x20, 0x30, 0x78, 0x46,
+        0x46, 0x46, 0x31, 0x20, 0x6e, 0x6f, 0x20, 0x50, 0x49, 0x44, 0x31, 0x14, 0x30, 0x12, 0x06,
+        0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04, 0x46, 0x46,
+        0x46, 0x31, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+        0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0x41,
+        0x9a, 0x93, 0x15, 0xc2, 0x17, 0x3e, 0x0c, 0x8c, 0x87, 0x6d, 0x03, 0xcc, 0xfc, 0x94, 0x48,
+        0x52, 0x64, 0x7f, 0x7f, 0xec, 0x5e, 0x50, 0x82, 0xf4, 0x05, 0x99, 0x28, 0xec, 0xa8, 0x94,
+        0xc5, 0x94, 0x15, 0x13, 0x09, 0xac, 0x63, 0x1e, 0x4c, 0xb0, 0x33, 0x92, 0xaf, 0x68, 0x4b,
+        0x0b, 0xaf, 0xb7, 0xe6, 0x5b, 0x3b, 0x81, 0x62, 0xc2, 0xf5, 0x2b, 0xf9, 0x31, 0xb8, 0xe7,
+        0x7a, 0xaa, 0x82, 0xa3, 0x66, 0x30, 0x64, 0x30, 0x12, 0x06, 0x03, 0x55, 0x1d, 0x13, 0x01,
+        0x01, 0xff, 0x04, 0x08, 0x30, 0x06, 0x01, 0x01, 0xff, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x06,
+        0x03, 0x55, 0x1d, 0x0f, 0x01, 0x01, 0xff, 0x04, 0x04, 0x03, 0x02, 0x01, 0x06, 0x30, 0x1d,
+        0x06, 0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0x63, 0x54, 0x0e, 0x47, 0xf6, 0x4b,
+        0x1c, 0x38, 0xd1, 0x38, 0x84, 0xa4, 0x62, 0xd1, 0x6c, 0x19, 0x5d, 0x8f, 0xfb, 0x3c, 0x30,
+        0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0x6a, 0xfd, 0x22,
+        0x77, 0x1f, 0x51, 0x1f, 0xec, 0xbf, 0x16, 0x41, 0x97, 0x67, 0x10, 0xdc, 0xdc, 0x31, 0xa1,
+        0x71, 0x7e, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03,
+        0x48, 0x00, 0x30, 0x45, 0x02, 0x21, 0x00, 0xb2, 0xef, 0x27, 0xf4, 0x9a, 0xe9, 0xb5, 0x0f,
+        0xb9, 0x1e, 0xea, 0xc9, 0x4c, 0x4d, 0x0b, 0xdb, 0xb8, 0xd7, 0x92, 0x9c, 0x6c, 0xb8, 0x8f,
+        0xac, 0xe5, 0x29, 0x36, 0x8d, 0x12, 0x05, 0x4c, 0x0c, 0x02, 0x20, 0x65, 0x5d, 0xc9, 0x2b,
+        0x86, 0xbd, 0x90, 0x98, 0x82, 0xa6, 0xc6, 0x21, 0x77, 0xb8, 0x25, 0xd7, 0xd0, 0x5e, 0xdb,
+        0xe7, 0xc2, 0x2f, 0x9f, 0xea, 0x71, 0x22, 0x0e, 0x7e, 0xa7, 0x03, 0xf8, 0x91,
+    ];
+
+    #[test]
+    fn x509_round_trip() {
+        for cert in TEST_CERTS {
+            let x509 = matter_to_x509(cert).unwrap();
+            let matter = x509_to_matter(&x509).unwrap();
+            assert_eq!(matter.as_slice(), cert);
+
+            assert_eq!(matter_to_x509(&matter).unwrap(), x509);
+        }
+    }
+
+    #[test]
+    /// The attestation certificates carry the vendor and product IDs, and do not expire
+    fn x509_attestation_round_trip() {
+        let dac = x509_to_matter(&DAC_CERT).unwrap();
+        let pai = x509_to_matter(&PAI_CERT).unwrap();
+        assert_eq!(matter_to_x509(&dac).unwrap().as_slice(), DAC_CERT);
+        assert_eq!(matter_to_x509(&pai).unwrap().as_slice(), PAI_CERT);
+
+        let dac = Cert::new(&dac).unwrap();
+        let pai = Cert::new(&pai).unwrap();
+        assert_eq!(dac.get_vendor_id(), Some(0xFFF1));
+        assert_eq!(dac.get_product_id(), Some(0x8002));
+        assert_eq!(pai.get_vendor_id(), Some(0xFFF1));
+        assert_eq!(pai.get_product_id(), None);
+
+        // The DAC is signed by the PAI over its re-encoded X.509 TBS data
+        dac.verify_chain_start().add_cert(&pai).unwrap();
+    }
+
+    #[test]
+    /// The X.509 certificate carries the same TBS data that the Matter-TLV signature is over
+    fn x509_signature() {
+        let x509 = matter_to_x509(&test_vectors::RCA1_SUCCESS).unwrap();
+        let contents = DerReader::new(&x509).read(SEQUENCE).unwrap();
+
+        let rca = Cert::new(&test_vectors::RCA1_SUCCESS).unwrap();
+        let mut asn1 = [0; 1000];
+        let len = rca.as_asn1(&mut asn1).unwrap();
+        let tbs = &contents[..len];
+        assert_eq!(tbs, &asn1[..len]);
+
+        // The root is self-signed
+        KeyPair::new_from_public(rca.get_pubkey())
+            .unwrap()
+            .verify_msg(tbs, rca.get_signature())
+            .unwrap();
+    }
+
+    #[test]
+    fn malformed_x509() {
+        let x509 = matter_to_x509(&test_vectors::NOC1_SUCCESS).unwrap();
+
+        // Truncated
+        assert!(x509_to_matter(&x509[..x509.len() - 1]).is_err());
+
+        // Trailing data
+        let mut trailing = x509.clone();
+        trailing.push(0).unwrap();
+        assert!(x509_to_matter(&trailing).is_err());
+
+        // Not ECDSA with SHA256
+        let mut algo = x509.clone();
+        let at = algo
+            .windows(8)
+            .position(|w| w == [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02])
+            .unwrap();
+        algo[at + 7] = 0x03;
+        assert!(x509_to_matter(&algo).is_err());
+    }
+}
@@ -146,7 +146,7 @@ pub fn decode_vec<const N: usize>(base38_str: &str) -> Result<heapless::Vec<u8,
 /// # Arguments
 /// * `base38_str` - base38-encoded string to decode
 ///
-/// Fails if the string contains invalid characters or if the supplied buffer is too small to fit the decoded data
+/// Yields an error for a chunk of invalid characters, or of an invalid length
 pub fn decode(base38_str: &str) -> impl Iterator<Item = Result<u8, Error>> + '_ {
     let stru = base38_str.as_bytes();
 
@@ -159,7 +159,6 @@ pub fn decode(base38_str: &str) -> impl Iterator<Item = Result<u8, Error>> + '_
             let offset = stru.len() / 5 * 5;
             decode_base38(&stru[offset..])
         })
-        .take_while(Result::is_ok)
 }
 
 fn decode_base38(chars: &[u8]) -> impl Iterator<Item = Result<u8, Error>> {
@@ -171,10 +170,14 @@ fn decode_base38(chars: &[u8]) -> impl Iterator<Item = Result<u8, Error>> {
         4 => 2,
         2 => 1,
         0 => 0,
-        _ => -1,
+        _ => {
+            // A single error for a chunk of an invalid length
+            cerr = Some(ErrorCode::InvalidData);
+            1
+        }
     };
 
-    if repeat >= 0 {
+    if cerr.is_none() {
         for c in chars.iter().rev() {
             match decode_char(*c) {
                 Ok(v) => value = value * RADIX + v as u32,
@@ -184,8 +187,11 @@ fn decode_base38(chars: &[u8]) -> impl Iterator<Item = Result<u8, Error>> {
                 }
             }
         }
-    } else {
-        cerr = Some(ErrorCode::InvalidData)
+
+        // The chunk has to decode into `repeat` bytes exactly
+        if repeat > 0 && value >> (8 * repeat) != 0 {
+            cerr = Some(ErrorCode::InvalidData);
+        }
     }
 
     (0..repeat)
@@ -200,7 +206,7 @@ fn decode_base38(chars: &[u8]) -> impl Iterator<Item = Result<u8, Error>> {
                 Ok(byte)
             }
         })
-        .take_while(Result::is_ok)
+        .take(if cerr.is_some() { 1 } else { repeat })
 }
 
 fn decode_char(c: u8) -> Result<u8, Error> {
@@ -239,4 +245,14 @@ mod tests {
             DECODED
         );
     }
+
+    #[test]
+    fn cannot_base38_decode_invalid() {
+        // A character outside of the alphabet
+        assert!(decode_vec::<16>("-MOA5:ZU02").is_err());
+        // A trailing chunk of an invalid length
+        assert!(decode_vec::<16>("-MOA57ZU").is_err());
+        // A chunk which does not fit in three bytes
+        assert!(decode_vec::<16>(".....").is_err());
+    }
 }
@@ -15,24 +15,46 @@
  *    limitations under the License.
  */
 
-use core::{borrow::Borrow, cell::RefCell};
+use core::{
+    borrow::Borrow,
+    cell::{Cell, RefCell},
+    time::Duration,
+};
 
 use crate::{
     acl::AclMgr,
+    bdx::BdxFiles,
     data_model::{
         cluster_basic_information::BasicInfoConfig,
-        sdm::{dev_att::DevAttDataFetcher, failsafe::FailSafe},
+        cluster_binding::BindingMgr,
+        cluster_ota_requestor::OtaProviderMgr,
+        core::DEFAULT_CMD_TIMEOUT,
+        events::{EventLogger, EventMgr, EventPriority},
+        objects::{AttrId, ClusterId, EndptId},
+        sdm::{
+            dev_att::DevAttDataFetcher, failsafe::FailSafe, general_diagnostics::DiagMgr,
+            icd_management::IcdClientMgr,
+        },
+        subscriptions::SubscriptionMgr,
     },
     error::*,
-    fabric::FabricMgr,
-    mdns::Mdns,
+    fabric::{FabricMgr, FabricScoped},
+    groups::GroupMgr,
+    mdns::{CommissioningMode, Mdns},
     pairing::{print_pairing_code_and_qr, DiscoveryCapabilities},
-    secure_channel::{pake::PaseMgr, spake2p::VerifierData},
+    secure_channel::{case::ResumptionMgr, pake::PaseMgr, spake2p::VerifierData},
+    tlv::ToTLV,
     transport::{
+        counter::CounterMgr,
         exchange::{ExchangeCtx, MAX_EXCHANGES},
-        session::SessionMgr,
+        session::{SessionMgr, MAX_SESSION_IDLE_TIME},
+    },
+    utils::{
+        clock::{Clock, SystemClock},
+        epoch::Epoch,
+        rand::Rand,
+        select::Notification,
     },
-    utils::{epoch::Epoch, rand::Rand, select::Notification},
 };
 
 /* The Matter Port */
@@ -49,17 +71,30 @@ pub struct CommissioningData {
 /// The primary Matter Object
 pub struct Matter<'a> {
     fabric_mgr: RefCell<FabricMgr>,
-    pub acl_mgr: RefCell<AclMgr>, // Public for tests
+    pub acl_mgr: RefCell<AclMgr>,     // Public for tests
+    pub group_mgr: RefCell<GroupMgr>, // Public for tests
     pase_mgr: RefCell<PaseMgr>,
+    resumption_mgr: RefCell<ResumptionMgr>,
     failsafe: RefCell<FailSafe>,
+    pub diag_mgr: RefCell<DiagMgr>,                 // Public for tests
+    pub subscription_mgr: RefCell<SubscriptionMgr>, // Public for tests
+    pub event_mgr: RefCell<EventMgr>,               // Public for tests
+    pub binding_mgr: RefCell<BindingMgr>,           // Public for tests
+    pub ota_provider_mgr: RefCell<OtaProviderMgr>,  // Public for tests
+    pub icd_client_mgr: RefCell<IcdClientMgr>,      // Public for tests
+    pub group_data_ctr: RefCell<CounterMgr>,        // Public for tests
+    pub group_ctrl_ctr: RefCell<CounterMgr>,        // Public for tests
     persist_notification: Notification,
     pub(crate) send_notification: Notification,
+    pub(crate) report_notification: Notification,
     mdns: &'a dyn Mdns,
     pub(crate) epoch: Epoch,
     pub(crate) rand: Rand,
     dev_det: &'a BasicInfoConfig<'a>,
     dev_att: &'a dyn DevAttDataFetcher,
     pub(crate) port: u16,
+    cmd_timeout: Cell<Duration>,
+    bdx_files: Option<&'a dyn BdxFiles>,
     pub(crate) exchanges: RefCell<heapless::Vec<ExchangeCtx, MAX_EXCHANGES>>,
     pub session_mgr: RefCell<SessionMgr>, // Public for tests
 }
@@ -85,6 +120,11 @@ impl<'a> Matter<'a> {
     /// * dev_att: An object that implements the trait [DevAttDataFetcher]. Any Matter device
     /// requires a set of device attestation certificates and keys. It is the responsibility of
     /// this object to return the device attestation details when queried upon.
+    /// * rand: The source of the randomness of the stack. The keys of the sessions, the
+    /// random values of the handshakes and the operational keys of the fabrics are all
+    /// drawn from it, so it has to be a CSPRNG, such as `sys_rand`. Deterministic
+    /// generators are for tests only, and with a `Rand` that never produces a valid key
+    /// (e.g. `dummy_rand`) the handshakes fail with `ErrorCode::Crypto`.
     #[inline(always)]
     pub fn new(
         dev_det: &'a BasicInfoConfig<'a>,
@@ -93,20 +133,48 @@ impl<'a> Matter<'a> {
         epoch: Epoch,
         rand: Rand,
         port: u16,
+    ) -> Self {
+        Self::new_with_clock(dev_det, dev_att, mdns, epoch, rand, &SystemClock, port)
+    }
+
+    /// Same as `new`, but with the intervals of the subscriptions measured against
+    /// the provided clock, rather than against the [SystemClock]
+    #[inline(always)]
+    pub fn new_with_clock(
+        dev_det: &'a BasicInfoConfig<'a>,
+        dev_att: &'a dyn DevAttDataFetcher,
+        mdns: &'a dyn Mdns,
+        epoch: Epoch,
+        rand: Rand,
+        clock: &'static dyn Clock,
+        port: u16,
     ) -> Self {
         Self {
             fabric_mgr: RefCell::new(FabricMgr::new()),
             acl_mgr: RefCell::new(AclMgr::new()),
+            group_mgr: RefCell::new(GroupMgr::new()),
             pase_mgr: RefCell::new(PaseMgr::new(epoch, rand)),
-            failsafe: RefCell::new(FailSafe::new()),
+            resumption_mgr: RefCell::new(ResumptionMgr::new()),
+            failsafe: RefCell::new(FailSafe::new(epoch)),
+            diag_mgr: RefCell::new(DiagMgr::new(epoch)),
+            subscription_mgr: RefCell::new(SubscriptionMgr::new(clock)),
+            event_mgr: RefCell::new(EventMgr::new(epoch)),
+            binding_mgr: RefCell::new(BindingMgr::new()),
+            ota_provider_mgr: RefCell::new(OtaProviderMgr::new()),
+            icd_client_mgr: RefCell::new(IcdClientMgr::new()),
+            group_data_ctr: RefCell::new(CounterMgr::new(rand)),
+            group_ctrl_ctr: RefCell::new(CounterMgr::new(rand)),
             persist_notification: Notification::new(),
             send_notification: Notification::new(),
+            report_notification: Notification::new(),
             mdns,
             epoch,
             rand,
             dev_det,
             dev_att,
             port,
+            cmd_timeout: Cell::new(DEFAULT_CMD_TIMEOUT),
+            bdx_files: None,
             exchanges: RefCell::new(heapless::Vec::new()),
             session_mgr: RefCell::new(SessionMgr::new(epoch, rand)),
         }
@@ -124,6 +192,26 @@ impl<'a> Matter<'a> {
         self.port
     }
 
+    /// How long an asynchronous command handler may take, before the command is
+    /// failed with a `Busy` status
+    pub fn cmd_timeout(&self) -> Duration {
+        self.cmd_timeout.get()
+    }
+
+    pub fn set_cmd_timeout(&self, cmd_timeout: Duration) {
+        self.cmd_timeout.set(cmd_timeout);
+    }
+
+    /// The files served to the peers which initiate a BDX transfer, e.g. the images
+    /// of an OTA provider
+    pub fn bdx_files(&self) -> Option<&'a dyn BdxFiles> {
+        self.bdx_files
+    }
+
+    pub fn set_bdx_files(&mut self, files: &'a dyn BdxFiles) {
+        self.bdx_files = Some(files);
+    }
+
     pub fn load_fabrics(&self, data: &[u8]) -> Result<(), Error> {
         self.fabric_mgr.borrow_mut().load(data, self.mdns)
     }
@@ -132,6 +220,34 @@ impl<'a> Matter<'a> {
         self.acl_mgr.borrow_mut().load(data)
     }
 
+    pub fn load_diag(&self, data: &[u8]) -> Result<(), Error> {
+        self.diag_mgr.borrow_mut().load(data)
+    }
+
+    pub fn load_bindings(&self, data: &[u8]) -> Result<(), Error> {
+        self.binding_mgr.borrow_mut().load(data)
+    }
+
+    pub fn load_ota_providers(&self, data: &[u8]) -> Result<(), Error> {
+        self.ota_provider_mgr.borrow_mut().load(data)
+    }
+
+    pub fn load_icd_clients(&self, data: &[u8]) -> Result<(), Error> {
+        self.icd_client_mgr.borrow_mut().load(data)
+    }
+
+    pub fn load_groups(&self, data: &[u8]) -> Result<(), Error> {
+        self.group_mgr.borrow_mut().load(data)
+    }
+
+    pub fn load_group_data_ctr(&self, data: &[u8]) -> Result<(), Error> {
+        self.group_data_ctr.borrow_mut().load(data)
+    }
+
+    pub fn load_group_ctrl_ctr(&self, data: &[u8]) -> Result<(), Error> {
+        self.group_ctrl_ctr.borrow_mut().load(data)
+    }
+
     pub fn store_fabrics<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
         self.fabric_mgr.borrow_mut().store(buf)
     }
@@ -140,8 +256,44 @@ impl<'a> Matter<'a> {
         self.acl_mgr.borrow_mut().store(buf)
     }
 
+    pub fn store_diag<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        self.diag_mgr.borrow_mut().store(buf)
+    }
+
+    pub fn store_bindings<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        self.binding_mgr.borrow_mut().store(buf)
+    }
+
+    pub fn store_ota_providers<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        self.ota_provider_mgr.borrow_mut().store(buf)
+    }
+
+    pub fn store_icd_clients<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        self.icd_client_mgr.borrow_mut().store(buf)
+    }
+
+    pub fn store_groups<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        self.group_mgr.borrow_mut().store(buf)
+    }
+
+    pub fn store_group_data_ctr<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        self.group_data_ctr.borrow_mut().store(buf)
+    }
+
+    pub fn store_group_ctrl_ctr<'b>(&self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        self.group_ctrl_ctr.borrow_mut().store(buf)
+    }
+
     pub fn is_changed(&self) -> bool {
-        self.acl_mgr.borrow().is_changed() || self.fabric_mgr.borrow().is_changed()
+        self.acl_mgr.borrow().is_changed()
+            || self.fabric_mgr.borrow().is_changed()
+            || self.diag_mgr.borrow().is_changed()
+            || self.binding_mgr.borrow().is_changed()
+            || self.ota_provider_mgr.borrow().is_changed()
+            || self.icd_client_mgr.borrow().is_changed()
+            || self.group_mgr.borrow().is_changed()
+            || self.group_data_ctr.borrow().is_changed()
+            || self.group_ctrl_ctr.borrow().is_changed()
     }
 
     pub fn start_comissioning(
@@ -149,6 +301,10 @@ impl<'a> Matter<'a> {
         dev_comm: CommissioningData,
         buf: &mut [u8],
     ) -> Result<bool, Error> {
+        self.pase_mgr
+            .borrow_mut()
+            .set_basic_commissioning(dev_comm.verifier.clone(), dev_comm.discriminator);
+
         if !self.pase_mgr.borrow().is_pase_session_enabled() && self.fabric_mgr.borrow().is_empty()
         {
             print_pairing_code_and_qr(
@@ -161,6 +317,7 @@ impl<'a> Matter<'a> {
             self.pase_mgr.borrow_mut().enable_pase_session(
                 dev_comm.verifier,
                 dev_comm.discriminator,
+                CommissioningMode::Basic,
                 self.mdns,
             )?;
 
@@ -170,6 +327,63 @@ impl<'a> Matter<'a> {
         }
     }
 
+    /// Remove the sessions which were not used for longer than `MAX_SESSION_IDLE_TIME`
+    pub fn expire_idle_sessions(&self) {
+        self.session_mgr
+            .borrow_mut()
+            .remove_idle(MAX_SESSION_IDLE_TIME);
+    }
+
+    /// Revert the changes done under the fail-safe, if it has expired
+    pub fn expire_failsafe(&self) -> Result<(), Error> {
+        let rollback = self.failsafe.borrow_mut().expired();
+
+        if let Some(rollback) = rollback {
+            rollback.apply(
+                &mut self.fabric_mgr.borrow_mut(),
+                FabricScoped {
+                    acl_mgr: &mut self.acl_mgr.borrow_mut(),
+                    group_mgr: &mut self.group_mgr.borrow_mut(),
+                    subscription_mgr: &mut self.subscription_mgr.borrow_mut(),
+                    session_mgr: &mut self.session_mgr.borrow_mut(),
+                    binding_mgr: &mut self.binding_mgr.borrow_mut(),
+                    ota_provider_mgr: &mut self.ota_provider_mgr.borrow_mut(),
+                    icd_client_mgr: &mut self.icd_client_mgr.borrow_mut(),
+                    resumption_mgr: &mut self.resumption_mgr.borrow_mut(),
+                },
+                None,
+                self.mdns,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Close the commissioning window opened by an administrator, if it has timed out
+    pub fn expire_commissioning_window(&self) -> Result<(), Error> {
+        self.pase_mgr.borrow_mut().expire_window(self.mdns)
+    }
+
+    /// The earliest time (as per the epoch) at which an MRP retransmission or acknowledgement
+    /// is due, or at which the fail-safe or the commissioning window expire
+    pub(crate) fn next_deadline(&self) -> Option<Duration> {
+        let mrp = self
+            .exchanges
+            .borrow()
+            .iter()
+            .filter_map(|ctx| ctx.mrp.deadline())
+            .min();
+
+        [
+            mrp,
+            self.failsafe.borrow().expires_at(),
+            self.pase_mgr.borrow().window_expires_at(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
     pub fn notify_changed(&self) {
         if self.is_changed() {
             self.persist_notification.signal(());
@@ -179,6 +393,92 @@ impl<'a> Matter<'a> {
     pub async fn wait_changed(&self) {
         self.persist_notification.wait().await
     }
+
+    /// Notify the subscribers of the attributes of the provided cluster that these changed.
+    ///
+    /// To be called by the application whenever it changes the value of an attribute,
+    /// so that the change gets reported, as soon as the min intervals of the subscriptions allow.
+    pub fn notify_attribute_changed(&self, endpoint: EndptId, cluster: ClusterId) {
+        if self
+            .subscription_mgr
+            .borrow_mut()
+            .notify_change(endpoint, cluster)
+        {
+            self.report_notification.signal(());
+        }
+    }
+
+    /// Same as `notify_attribute_changed`, but only the provided attribute gets reported
+    pub fn mark_dirty(&self, endpoint: EndptId, cluster: ClusterId, attr: AttrId) {
+        if self
+            .subscription_mgr
+            .borrow_mut()
+            .mark_dirty(endpoint, cluster, attr)
+        {
+            self.report_notification.signal(());
+        }
+    }
+
+    /// Same as `mark_dirty`, but with the new value of the attribute, so that an attribute
+    /// which changes back to its last reported value before the next report is not reported
+    pub fn mark_value(
+        &self,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        attr: AttrId,
+        value: &dyn ToTLV,
+    ) {
+        if self
+            .subscription_mgr
+            .borrow_mut()
+            .mark_value(endpoint, cluster, attr, value)
+        {
+            self.report_notification.signal(());
+        }
+    }
+
+    /// Log an event and notify the subscriptions requesting it.
+    ///
+    /// An urgent event gets reported right away, rather than with the next report
+    /// allowed by the min interval of the subscription.
+    pub fn log_event(
+        &self,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        event_id: u32,
+        priority: EventPriority,
+        urgent: bool,
+        payload: &dyn ToTLV,
+    ) -> Result<u64, Error> {
+        let number = self
+            .event_mgr
+            .borrow_mut()
+            .log(endpoint, cluster, event_id, priority, urgent, payload)?;
+
+        let event_mgr = self.event_mgr.borrow();
+
+        if let Some(event) = event_mgr.next(number, |event| event.number == number) {
+            if self.subscription_mgr.borrow_mut().notify_event(event) {
+                self.report_notification.signal(());
+            }
+        }
+
+        Ok(number)
+    }
+}
+
+impl<'a> EventLogger for Matter<'a> {
+    fn log_event(
+        &self,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        event_id: u32,
+        priority: EventPriority,
+        urgent: bool,
+        payload: &dyn ToTLV,
+    ) -> Result<u64, Error> {
+        Matter::log_event(self, endpoint, cluster, event_id, priority, urgent, payload)
+    }
 }
 
 impl<'a> Borrow<RefCell<FabricMgr>> for Matter<'a> {
@@ -193,18 +493,66 @@ impl<'a> Borrow<RefCell<AclMgr>> for Matter<'a> {
     }
 }
 
+impl<'a> Borrow<RefCell<GroupMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<GroupMgr> {
+        &self.group_mgr
+    }
+}
+
 impl<'a> Borrow<RefCell<PaseMgr>> for Matter<'a> {
     fn borrow(&self) -> &RefCell<PaseMgr> {
         &self.pase_mgr
     }
 }
 
+impl<'a> Borrow<RefCell<ResumptionMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<ResumptionMgr> {
+        &self.resumption_mgr
+    }
+}
+
 impl<'a> Borrow<RefCell<FailSafe>> for Matter<'a> {
     fn borrow(&self) -> &RefCell<FailSafe> {
         &self.failsafe
     }
 }
 
+impl<'a> Borrow<RefCell<DiagMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<DiagMgr> {
+        &self.diag_mgr
+    }
+}
+
+impl<'a> Borrow<RefCell<SubscriptionMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<SubscriptionMgr> {
+        &self.subscription_mgr
+    }
+}
+
+impl<'a> Borrow<RefCell<BindingMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<BindingMgr> {
+        &self.binding_mgr
+    }
+}
+
+impl<'a> Borrow<RefCell<OtaProviderMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<OtaProviderMgr> {
+        &self.ota_provider_mgr
+    }
+}
+
+impl<'a> Borrow<RefCell<IcdClientMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<IcdClientMgr> {
+        &self.icd_client_mgr
+    }
+}
+
+impl<'a> Borrow<RefCell<EventMgr>> for Matter<'a> {
+    fn borrow(&self) -> &RefCell<EventMgr> {
+        &self.event_mgr
+    }
+}
+
 impl<'a> Borrow<BasicInfoConfig<'a>> for Matter<'a> {
     fn borrow(&self) -> &BasicInfoConfig<'a> {
         self.dev_det
@@ -217,6 +565,12 @@ impl<'a> Borrow<dyn DevAttDataFetcher + 'a> for Matter<'a> {
     }
 }
 
+impl<'a> Borrow<dyn EventLogger + 'a> for Matter<'a> {
+    fn borrow(&self) -> &(dyn EventLogger + 'a) {
+        self
+    }
+}
+
 impl<'a> Borrow<dyn Mdns + 'a> for Matter<'a> {
     fn borrow(&self) -> &(dyn Mdns + 'a) {
         self.mdns
@@ -116,6 +116,30 @@ pub fn pbkdf2_hmac(pass: &[u8], iter: usize, salt: &[u8], key: &mut [u8]) -> Res
     Ok(())
 }
 
+/// The packet encryption (AES-128-CCM) of this backend
+pub struct EspMbedtlsCrypto;
+
+impl super::Crypto for EspMbedtlsCrypto {
+    fn encrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+        data_len: usize,
+    ) -> Result<usize, Error> {
+        encrypt_in_place(key, nonce, ad, data, data_len)
+    }
+
+    fn decrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, Error> {
+        decrypt_in_place(key, nonce, ad, data)
+    }
+}
+
 pub fn encrypt_in_place(
     _key: &[u8],
     _nonce: &[u8],
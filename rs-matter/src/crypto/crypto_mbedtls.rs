@@ -36,8 +36,9 @@ use crate::{
     // TODO: We should move ASN1Writer out of Cert,
     // so Crypto doesn't have to depend on Cert
     cert::{ASN1Writer, CertConsumer},
+    crypto::MAX_RAND_SCALAR_ATTEMPTS,
     error::{Error, ErrorCode},
-    utils::rand::Rand,
+    utils::rand::{Rand, RandSource},
 };
 
 pub struct HmacSha256 {
@@ -67,11 +68,36 @@ pub struct KeyPair {
     key: Pk,
 }
 
+/// A random scalar between 1 and the order of the group minus 1, drawn from `rand`
+/// so that tests seeding their generator get reproducible keys
+///
+/// Fails with `ErrorCode::Crypto` if `rand` does not come up with one in
+/// `MAX_RAND_SCALAR_ATTEMPTS` draws.
+pub(crate) fn rand_scalar(group: &EcGroup, rand: Rand) -> Result<Mpi, Error> {
+    let order = group.order()?;
+    let zero = Mpi::new(0)?;
+    let mut bytes = [0; 32];
+
+    for _ in 0..MAX_RAND_SCALAR_ATTEMPTS {
+        rand.fill_bytes(&mut bytes);
+
+        let scalar = Mpi::from_binary(&bytes)?;
+        if scalar > zero && scalar < order {
+            return Ok(scalar);
+        }
+    }
+
+    error!("The Rand does not produce valid scalars");
+    Err(ErrorCode::Crypto.into())
+}
+
 impl KeyPair {
-    pub fn new(_rand: Rand) -> Result<Self, Error> {
-        let mut ctr_drbg = CtrDrbg::new(Arc::new(OsEntropy::new()), None)?;
+    pub fn new(rand: Rand) -> Result<Self, Error> {
+        let group = EcGroup::new(EcGroupId::SecP256R1)?;
+        let priv_key = rand_scalar(&group, rand)?;
+
         Ok(Self {
-            key: Pk::generate_ec(&mut ctr_drbg, EcGroupId::SecP256R1)?,
+            key: Pk::private_from_ec_components(group, priv_key)?,
         })
     }
 
@@ -291,6 +317,30 @@ pub fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8], key: &mut [u8]) -> Resu
     Hkdf::hkdf(Type::Sha256, salt, ikm, info, key).map_err(|_e| ErrorCode::TLSStack.into())
 }
 
+/// The packet encryption (AES-128-CCM) of this backend
+pub struct MbedtlsCrypto;
+
+impl super::Crypto for MbedtlsCrypto {
+    fn encrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+        data_len: usize,
+    ) -> Result<usize, Error> {
+        encrypt_in_place(key, nonce, ad, data, data_len)
+    }
+
+    fn decrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, Error> {
+        decrypt_in_place(key, nonce, ad, data)
+    }
+}
+
 pub fn encrypt_in_place(
     key: &[u8],
     nonce: &[u8],
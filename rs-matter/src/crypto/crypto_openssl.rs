@@ -17,14 +17,15 @@
 
 use core::fmt::{self, Debug};
 
+use crate::crypto::MAX_RAND_SCALAR_ATTEMPTS;
 use crate::error::{Error, ErrorCode};
-use crate::utils::rand::Rand;
+use crate::utils::rand::{Rand, RandSource};
 
 use alloc::vec;
 use foreign_types::ForeignTypeRef;
 use log::error;
 use openssl::asn1::Asn1Type;
-use openssl::bn::{BigNum, BigNumContext};
+use openssl::bn::{BigNum, BigNumContext, BigNumRef};
 use openssl::cipher::CipherRef;
 use openssl::cipher_ctx::{CipherCtx, CipherCtxRef};
 use openssl::derive::Deriver;
@@ -80,10 +81,39 @@ pub struct KeyPair {
     key: KeyType,
 }
 
+/// A random scalar between 1 and `order - 1`, drawn from `rand` so that tests
+/// seeding their generator get reproducible keys
+///
+/// Fails with `ErrorCode::Crypto` if `rand` does not come up with one in
+/// `MAX_RAND_SCALAR_ATTEMPTS` draws.
+pub(crate) fn rand_scalar(order: &BigNumRef, rand: Rand) -> Result<BigNum, Error> {
+    let mut bytes = [0; 32];
+
+    for _ in 0..MAX_RAND_SCALAR_ATTEMPTS {
+        rand.fill_bytes(&mut bytes);
+
+        let scalar = BigNum::from_slice(&bytes)?;
+        if scalar.num_bits() > 0 && *scalar < *order {
+            return Ok(scalar);
+        }
+    }
+
+    error!("The Rand does not produce valid scalars");
+    Err(ErrorCode::Crypto.into())
+}
+
 impl KeyPair {
-    pub fn new(_rand: Rand) -> Result<Self, Error> {
+    pub fn new(rand: Rand) -> Result<Self, Error> {
         let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
-        let key = EcKey::generate(&group)?;
+        let mut ctx = BigNumContext::new()?;
+        let mut order = BigNum::new()?;
+        group.order(&mut order, &mut ctx)?;
+
+        let priv_key = rand_scalar(&order, rand)?;
+        let mut pub_key = EcPoint::new(&group)?;
+        pub_key.mul_generator(&group, &priv_key, &ctx)?;
+
+        let key = EcKey::from_private_components(&group, &priv_key, &pub_key)?;
         Ok(Self {
             key: KeyType::Private(key),
         })
@@ -259,6 +289,30 @@ pub fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8], key: &mut [u8]) -> Resu
     Ok(())
 }
 
+/// The packet encryption (AES-128-CCM) of this backend
+pub struct OpensslCrypto;
+
+impl super::Crypto for OpensslCrypto {
+    fn encrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+        data_len: usize,
+    ) -> Result<usize, Error> {
+        encrypt_in_place(key, nonce, ad, data, data_len)
+    }
+
+    fn decrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, Error> {
+        decrypt_in_place(key, nonce, ad, data)
+    }
+}
+
 pub fn encrypt_in_place(
     key: &[u8],
     nonce: &[u8],
@@ -41,9 +41,9 @@ use x509_cert::{
 };
 
 use crate::{
+    crypto::MAX_RAND_SCALAR_ATTEMPTS,
     error::{Error, ErrorCode},
-    secure_channel::crypto_rustcrypto::RandRngCore,
-    utils::rand::Rand,
+    utils::rand::{Rand, RandSource},
 };
 
 type HmacSha256I = hmac::Hmac<sha2::Sha256>;
@@ -112,10 +112,29 @@ pub struct KeyPair {
     key: KeyType,
 }
 
+/// A random non-zero scalar below the order of the curve, drawn from `rand` so
+/// that tests seeding their generator get reproducible keys
+///
+/// Fails with `ErrorCode::Crypto` if `rand` does not come up with one in
+/// `MAX_RAND_SCALAR_ATTEMPTS` draws.
+pub(crate) fn rand_scalar(rand: Rand) -> Result<SecretKey, Error> {
+    let mut bytes = [0; 32];
+
+    for _ in 0..MAX_RAND_SCALAR_ATTEMPTS {
+        rand.fill_bytes(&mut bytes);
+
+        if let Ok(scalar) = SecretKey::from_slice(&bytes) {
+            return Ok(scalar);
+        }
+    }
+
+    error!("The Rand does not produce valid scalars");
+    Err(ErrorCode::Crypto.into())
+}
+
 impl KeyPair {
     pub fn new(rand: Rand) -> Result<Self, Error> {
-        let mut rng = RandRngCore(rand);
-        let secret_key = SecretKey::random(&mut rng);
+        let secret_key = rand_scalar(rand)?;
 
         Ok(Self {
             key: KeyType::Private(secret_key),
@@ -301,6 +320,30 @@ pub fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8], key: &mut [u8]) -> Resu
 }
 
 // TODO: add tests and check against mbedtls and openssl
+/// The packet encryption (AES-128-CCM) of this backend
+pub struct RustCrypto;
+
+impl super::Crypto for RustCrypto {
+    fn encrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+        data_len: usize,
+    ) -> Result<usize, Error> {
+        encrypt_in_place(key, nonce, ad, data, data_len)
+    }
+
+    fn decrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, Error> {
+        decrypt_in_place(key, nonce, ad, data)
+    }
+}
+
 pub fn encrypt_in_place(
     key: &[u8],
     nonce: &[u8],
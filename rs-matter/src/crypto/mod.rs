@@ -37,6 +37,38 @@ pub const ECDH_SHARED_SECRET_LEN_BYTES: usize = 32;
 
 pub const EC_SIGNATURE_LEN_BYTES: usize = 64;
 
+/// How many scalars the backends draw from a `Rand` for a key, before giving up on it
+///
+/// A CSPRNG practically never needs a second attempt, so running out of them means
+/// that the `Rand` is broken (e.g. always zero), rather than unlucky.
+pub(crate) const MAX_RAND_SCALAR_ATTEMPTS: usize = 8;
+
+/// The packet encryption of a crypto backend: AES-128-CCM, with the tag appended
+/// to the cipher text
+///
+/// The backend is selected with a cargo feature (`crypto-mbedtls`, `crypto-rustcrypto` or
+/// `openssl`), and is the [`Backend`] of the secure sessions.
+pub trait Crypto {
+    /// Encrypt the first `data_len` bytes of `data` in place, and write the tag after
+    /// them, returning the length of the cipher text and the tag
+    fn encrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+        data_len: usize,
+    ) -> Result<usize, Error>;
+
+    /// Decrypt `data`, i.e. the cipher text followed by the tag, in place, returning
+    /// the length of the plain text
+    fn decrypt_in_place(
+        key: &[u8],
+        nonce: &[u8],
+        ad: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, Error>;
+}
+
 #[cfg(all(feature = "mbedtls", target_os = "espidf"))]
 mod crypto_esp_mbedtls;
 #[cfg(all(feature = "mbedtls", target_os = "espidf"))]
@@ -62,6 +94,17 @@ pub mod crypto_dummy;
 #[cfg(not(any(feature = "openssl", feature = "mbedtls", feature = "rustcrypto")))]
 pub use self::crypto_dummy::*;
 
+#[cfg(all(feature = "mbedtls", target_os = "espidf"))]
+pub type Backend = EspMbedtlsCrypto;
+#[cfg(all(feature = "mbedtls", not(target_os = "espidf")))]
+pub type Backend = MbedtlsCrypto;
+#[cfg(feature = "openssl")]
+pub type Backend = OpensslCrypto;
+#[cfg(feature = "rustcrypto")]
+pub type Backend = RustCrypto;
+#[cfg(not(any(feature = "openssl", feature = "mbedtls", feature = "rustcrypto")))]
+pub type Backend = DummyCrypto;
+
 impl<'a> FromTLV<'a> for KeyPair {
     fn from_tlv(t: &crate::tlv::TLVElement<'a>) -> Result<Self, Error>
     where
@@ -98,10 +141,27 @@ impl ToTLV for KeyPair {
 
 #[cfg(test)]
 mod tests {
-    use crate::error::ErrorCode;
+    use crate::{error::ErrorCode, utils::rand::dummy_rand};
 
     use super::KeyPair;
 
+    /// A broken `Rand`, all of whose scalars are above the order of the curve
+    fn ones_rand(buf: &mut [u8]) {
+        buf.fill(0xff);
+    }
+
+    #[test]
+    /// A `Rand` which never produces a valid scalar fails the key generation, rather than
+    /// spinning forever
+    fn test_new_with_broken_rand() {
+        for rand in [dummy_rand, ones_rand] {
+            assert_eq!(
+                KeyPair::new(rand).map(|_| ()).map_err(|e| e.code()),
+                Err(ErrorCode::Crypto)
+            );
+        }
+    }
+
     #[test]
     fn test_verify_msg_success() {
         let key = KeyPair::new_from_public(&test_vectors::PUB_KEY1).unwrap();
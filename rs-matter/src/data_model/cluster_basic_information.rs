@@ -18,7 +18,15 @@
 use core::convert::TryInto;
 
 use super::objects::*;
-use crate::{attribute_enum, error::Error, utils::rand::Rand};
+use crate::{
+    attribute_enum,
+    data_model::subscriptions::SUBSCRIPTIONS_PER_FABRIC,
+    error::Error,
+    interaction_model::core::MAX_PATHS_PER_INVOKE,
+    tlv::{TLVWriter, TagType, ToTLV},
+    transport::session::CASE_SESSIONS_PER_FABRIC,
+    utils::rand::Rand,
+};
 use strum::FromRepr;
 
 pub const ID: u32 = 0x0028;
@@ -33,6 +41,9 @@ pub enum Attributes {
     SwVer(AttrType<u32>) = 9,
     SwVerString(AttrUtfType) = 0xa,
     SerialNo(AttrUtfType) = 0x0f,
+    CapabilityMinima(()) = 0x13,
+    ProductAppearance(()) = 0x14,
+    MaxPathsPerInvoke(AttrType<u16>) = 0x16,
 }
 
 attribute_enum!(Attributes);
@@ -45,8 +56,90 @@ pub enum AttributesDiscriminants {
     SwVer = 9,
     SwVerString = 0xa,
     SerialNo = 0x0f,
+    CapabilityMinima = 0x13,
+    ProductAppearance = 0x14,
+    MaxPathsPerInvoke = 0x16,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ProductFinish {
+    #[default]
+    Other = 0,
+    Matte = 1,
+    Satin = 2,
+    Polished = 3,
+    Rugged = 4,
+    Fabric = 5,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ProductColor {
+    Black = 0,
+    Navy = 1,
+    Green = 2,
+    Teal = 3,
+    Maroon = 4,
+    Purple = 5,
+    Olive = 6,
+    Gray = 7,
+    Blue = 8,
+    Lime = 9,
+    Aqua = 10,
+    Red = 11,
+    Fuchsia = 12,
+    Yellow = 13,
+    White = 14,
+    Nickel = 15,
+    Chrome = 16,
+    Brass = 17,
+    Copper = 18,
+    Silver = 19,
+    Gold = 20,
+}
+
+/// The appearance of the product, as reported by the ProductAppearance attribute
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProductAppearance {
+    pub finish: ProductFinish,
+    /// The primary color of the product, if known
+    pub primary_color: Option<ProductColor>,
+}
+
+impl ProductAppearance {
+    pub const fn new(finish: ProductFinish, primary_color: Option<ProductColor>) -> Self {
+        Self {
+            finish,
+            primary_color,
+        }
+    }
+}
+
+impl ToTLV for ProductAppearance {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag: TagType) -> Result<(), Error> {
+        tw.start_struct(tag)?;
+        tw.u8(TagType::Context(0), self.finish as _)?;
+        match self.primary_color {
+            Some(color) => tw.u8(TagType::Context(1), color as _)?,
+            None => tw.null(TagType::Context(1))?,
+        }
+        tw.end_container()
+    }
+}
+
+/// The minimum capabilities that the node guarantees to every fabric
+#[derive(ToTLV)]
+struct CapabilityMinima {
+    case_sessions_per_fabric: u16,
+    subscriptions_per_fabric: u16,
+}
+
+const CAPABILITY_MINIMA: CapabilityMinima = CapabilityMinima {
+    case_sessions_per_fabric: CASE_SESSIONS_PER_FABRIC as _,
+    subscriptions_per_fabric: SUBSCRIPTIONS_PER_FABRIC as _,
+};
+
 #[derive(Default)]
 pub struct BasicInfoConfig<'a> {
     pub vid: u16,
@@ -57,10 +150,14 @@ pub struct BasicInfoConfig<'a> {
     pub serial_no: &'a str,
     /// Device name; up to 32 characters
     pub device_name: &'a str,
+    pub product_appearance: ProductAppearance,
+    /// The primary device type, advertised during commissioning, if any
+    pub device_type: Option<u16>,
 }
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID as _,
+    revision: 1,
     feature_map: 0,
     attributes: &[
         FEATURE_MAP,
@@ -100,8 +197,28 @@ pub const CLUSTER: Cluster<'static> = Cluster {
             Access::RV,
             Quality::FIXED,
         ),
+        Attribute::new(
+            AttributesDiscriminants::CapabilityMinima as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::ProductAppearance as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MaxPathsPerInvoke as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
     ],
     commands: &[],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
 };
 
 pub struct BasicInfoCluster<'a> {
@@ -130,6 +247,11 @@ impl<'a> BasicInfoCluster<'a> {
                     Attributes::SwVer(codec) => codec.encode(writer, self.cfg.sw_ver),
                     Attributes::SwVerString(codec) => codec.encode(writer, self.cfg.sw_ver_str),
                     Attributes::SerialNo(codec) => codec.encode(writer, self.cfg.serial_no),
+                    Attributes::CapabilityMinima(_) => writer.set(CAPABILITY_MINIMA),
+                    Attributes::ProductAppearance(_) => writer.set(self.cfg.product_appearance),
+                    Attributes::MaxPathsPerInvoke(codec) => {
+                        codec.encode(writer, MAX_PATHS_PER_INVOKE as _)
+                    }
                 }
             }
         } else {
@@ -151,3 +273,62 @@ impl<'a> ChangeNotifier<()> for BasicInfoCluster<'a> {
         self.data_ver.consume_change(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributesDiscriminants, BasicInfoCluster, BasicInfoConfig, ID};
+    use crate::{
+        data_model::{
+            objects::{AttrDataEncoder, AttrDetails, Node},
+            subscriptions::SUBSCRIPTIONS_PER_FABRIC,
+        },
+        tlv::{get_root_node_struct, TLVWriter},
+        transport::session::CASE_SESSIONS_PER_FABRIC,
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    #[test]
+    fn capability_minima_read() {
+        let cfg = BasicInfoConfig::default();
+        let cluster = BasicInfoCluster::new(&cfg, dummy_rand);
+
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 0,
+            cluster_id: ID,
+            attr_id: AttributesDiscriminants::CapabilityMinima as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .unwrap();
+
+        let minima = get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap();
+
+        assert_eq!(
+            minima.find_tag(0).unwrap().u16().unwrap(),
+            CASE_SESSIONS_PER_FABRIC as u16
+        );
+        assert_eq!(
+            minima.find_tag(1).unwrap().u16().unwrap(),
+            SUBSCRIPTIONS_PER_FABRIC as u16
+        );
+    }
+}
@@ -0,0 +1,466 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::RefCell;
+use core::convert::TryInto;
+
+use crate::data_model::objects::*;
+use crate::fabric::MAX_SUPPORTED_FABRICS;
+use crate::interaction_model::messages::ib::{attr_list_write, ListOperation};
+use crate::tlv::{self, FromTLV, TLVElement, TLVList, TLVWriter, TagType, ToTLV};
+use crate::utils::rand::Rand;
+use crate::utils::writebuf::WriteBuf;
+use crate::{attribute_enum, error::*};
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x001E;
+
+pub const BINDINGS_PER_FABRIC: usize = 4;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    Binding(()) = 0x0,
+}
+
+attribute_enum!(Attributes);
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: 0,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::Binding as u16,
+            Access::RWVM.union(Access::FAB_SCOPED),
+            Quality::NONE,
+        ),
+    ],
+    commands: &[],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+/// A binding target, which is either a group, or an endpoint of a node
+#[derive(FromTLV, ToTLV, Debug, Clone, PartialEq)]
+#[tlvargs(start = 1)]
+pub struct Target {
+    pub node: Option<u64>,
+    pub group: Option<u16>,
+    pub endpoint: Option<EndptId>,
+    pub cluster: Option<ClusterId>,
+    #[tagval(0xFE)]
+    pub fab_idx: Option<u8>,
+}
+
+impl Target {
+    fn is_valid(&self) -> bool {
+        match self.group {
+            Some(_) => self.node.is_none() && self.endpoint.is_none(),
+            None => self.node.is_some() && self.endpoint.is_some(),
+        }
+    }
+}
+
+const MAX_BINDINGS: usize = BINDINGS_PER_FABRIC * MAX_SUPPORTED_FABRICS;
+
+/// The bindings of all fabrics, which are persisted
+pub struct BindingMgr {
+    bindings: heapless::Vec<Target, MAX_BINDINGS>,
+    changed: bool,
+}
+
+impl BindingMgr {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            bindings: heapless::Vec::new(),
+            changed: false,
+        }
+    }
+
+    /// Add a binding of the provided fabric, whatever the fabric index in the target
+    pub fn add(&mut self, mut target: Target, fab_idx: u8) -> Result<(), Error> {
+        if !target.is_valid() {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        if self
+            .bindings
+            .iter()
+            .filter(|target| target.fab_idx == Some(fab_idx))
+            .count()
+            >= BINDINGS_PER_FABRIC
+        {
+            Err(ErrorCode::ResourceExhausted)?;
+        }
+
+        target.fab_idx = Some(fab_idx);
+        self.bindings
+            .push(target)
+            .map_err(|_| ErrorCode::ResourceExhausted)?;
+
+        self.changed = true;
+
+        Ok(())
+    }
+
+    pub fn remove_for_fabric(&mut self, fab_idx: u8) {
+        let len = self.bindings.len();
+
+        self.bindings
+            .retain(|target| target.fab_idx != Some(fab_idx));
+
+        self.changed |= self.bindings.len() != len;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Target> {
+        self.bindings.iter()
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        let root = TLVList::new(data).iter().next().ok_or(ErrorCode::Invalid)?;
+
+        tlv::from_tlv(&mut self.bindings, &root)?;
+        self.changed = false;
+
+        Ok(())
+    }
+
+    pub fn store<'a>(&mut self, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        if self.changed {
+            let mut wb = WriteBuf::new(buf);
+            let mut tw = TLVWriter::new(&mut wb);
+            self.bindings
+                .as_slice()
+                .to_tlv(&mut tw, TagType::Anonymous)?;
+
+            self.changed = false;
+
+            let len = tw.get_tail();
+
+            Ok(Some(&buf[..len]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+impl Default for BindingMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct BindingCluster<'a> {
+    data_ver: Dataver,
+    binding_mgr: &'a RefCell<BindingMgr>,
+}
+
+impl<'a> BindingCluster<'a> {
+    pub fn new(binding_mgr: &'a RefCell<BindingMgr>, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            binding_mgr,
+        }
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::Binding(_) => {
+                        writer.set_list(self.binding_mgr.borrow().iter().filter(|target| {
+                            attr.is_visible_to(target.fab_idx.unwrap_or_default())
+                        }))
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        match attr.attr_id.try_into()? {
+            Attributes::Binding(_) => {
+                attr_list_write(attr, data.with_dataver(self.data_ver.get())?, |op, data| {
+                    self.write_binding_attr(&op, data, attr.fab_idx)
+                })?
+            }
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    fn write_binding_attr(
+        &self,
+        op: &ListOperation,
+        data: &TLVElement,
+        fab_idx: u8,
+    ) -> Result<(), Error> {
+        match op {
+            ListOperation::AddItem => {
+                // The fabric index of the entry is always the one of the accessing fabric
+                self.binding_mgr
+                    .borrow_mut()
+                    .add(Target::from_tlv(data)?, fab_idx)
+            }
+            ListOperation::DeleteList => {
+                self.binding_mgr.borrow_mut().remove_for_fabric(fab_idx);
+                Ok(())
+            }
+            ListOperation::EditItem(_) | ListOperation::DeleteItem(_) => {
+                Err(ErrorCode::InvalidAction.into())
+            }
+        }
+    }
+}
+
+impl<'a> Handler for BindingCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        BindingCluster::read(self, attr, encoder)
+    }
+
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        BindingCluster::write(self, attr, data)
+    }
+}
+
+impl<'a> NonBlockingHandler for BindingCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for BindingCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use crate::{
+        data_model::objects::{AttrDataEncoder, AttrDetails, Node},
+        error::{Error, ErrorCode},
+        interaction_model::messages::ib::ListOperation,
+        tlv::{get_root_node_struct, FromTLV, TLVWriter, TagType, ToTLV},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{AttributesDiscriminants, BindingCluster, BindingMgr, Target, ID};
+
+    fn try_add(cluster: &BindingCluster, target: Target, fab_idx: u8) -> Result<(), Error> {
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+        target.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+
+        let data = get_root_node_struct(writebuf.as_slice()).unwrap();
+        cluster.write_binding_attr(&ListOperation::AddItem, &data, fab_idx)
+    }
+
+    fn add(cluster: &BindingCluster, target: Target, fab_idx: u8) {
+        try_add(cluster, target, fab_idx).unwrap();
+    }
+
+    fn read(cluster: &BindingCluster, fab_idx: u8) -> heapless::Vec<Target, 8> {
+        read_filtered(cluster, fab_idx, true)
+    }
+
+    fn read_filtered(
+        cluster: &BindingCluster,
+        fab_idx: u8,
+        fab_filter: bool,
+    ) -> heapless::Vec<Target, 8> {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 1,
+            cluster_id: ID,
+            attr_id: AttributesDiscriminants::Binding as _,
+            list_index: None,
+            fab_idx,
+            fab_filter,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 400];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .unwrap();
+
+        get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap()
+            .enter()
+            .unwrap()
+            .map(|entry| Target::from_tlv(&entry).unwrap())
+            .collect()
+    }
+
+    fn node_target(node: u64, endpoint: u16, fab_idx: u8) -> Target {
+        Target {
+            node: Some(node),
+            group: None,
+            endpoint: Some(endpoint),
+            cluster: Some(0x0006),
+            fab_idx: Some(fab_idx),
+        }
+    }
+
+    #[test]
+    /// Each fabric only reads the bindings that it has written
+    fn fabric_filtered_read() {
+        let binding_mgr = RefCell::new(BindingMgr::new());
+        let cluster = BindingCluster::new(&binding_mgr, dummy_rand);
+
+        // The fabric index in the written data is ignored, and replaced with the accessing fabric
+        add(&cluster, node_target(0x10, 1, 2), 1);
+        add(
+            &cluster,
+            Target {
+                node: None,
+                group: Some(0x0101),
+                endpoint: None,
+                cluster: None,
+                fab_idx: None,
+            },
+            2,
+        );
+        add(&cluster, node_target(0x20, 2, 1), 1);
+
+        assert_eq!(
+            read(&cluster, 1),
+            [node_target(0x10, 1, 1), node_target(0x20, 2, 1)]
+        );
+        assert_eq!(
+            read(&cluster, 2),
+            [Target {
+                node: None,
+                group: Some(0x0101),
+                endpoint: None,
+                cluster: None,
+                fab_idx: Some(2),
+            }]
+        );
+
+        // Fabric 2 replacing its list leaves the bindings of fabric 1 untouched
+        let data = get_root_node_struct(&[0x15, 0x18]).unwrap();
+        cluster
+            .write_binding_attr(&ListOperation::DeleteList, &data, 2)
+            .unwrap();
+
+        assert_eq!(read(&cluster, 1).len(), 2);
+        assert!(read(&cluster, 2).is_empty());
+    }
+
+    #[test]
+    /// A read which is not fabric-filtered reports the bindings of all fabrics, each
+    /// tagged with the index of its fabric
+    fn fabric_unfiltered_read() {
+        let binding_mgr = RefCell::new(BindingMgr::new());
+        let cluster = BindingCluster::new(&binding_mgr, dummy_rand);
+
+        add(&cluster, node_target(0x10, 1, 1), 1);
+        add(&cluster, node_target(0x20, 2, 2), 2);
+        add(&cluster, node_target(0x30, 3, 1), 1);
+
+        assert_eq!(
+            read_filtered(&cluster, 1, false),
+            [
+                node_target(0x10, 1, 1),
+                node_target(0x20, 2, 2),
+                node_target(0x30, 3, 1)
+            ]
+        );
+        assert_eq!(read_filtered(&cluster, 2, true), [node_target(0x20, 2, 2)]);
+    }
+
+    #[test]
+    /// A written binding reads back, and survives a reboot
+    fn write_read_round_trip() {
+        let binding_mgr = RefCell::new(BindingMgr::new());
+        let cluster = BindingCluster::new(&binding_mgr, dummy_rand);
+
+        add(&cluster, node_target(0x10, 1, 1), 1);
+        assert_eq!(read(&cluster, 1), [node_target(0x10, 1, 1)]);
+        assert!(binding_mgr.borrow().is_changed());
+
+        let mut buf = [0; 200];
+        let data = binding_mgr.borrow_mut().store(&mut buf).unwrap().unwrap();
+        assert!(!binding_mgr.borrow().is_changed());
+
+        let reloaded_mgr = RefCell::new(BindingMgr::new());
+        reloaded_mgr.borrow_mut().load(data).unwrap();
+        let reloaded = BindingCluster::new(&reloaded_mgr, dummy_rand);
+
+        assert_eq!(read(&reloaded, 1), [node_target(0x10, 1, 1)]);
+    }
+
+    #[test]
+    /// A binding targets either a group, or an endpoint of a node
+    fn node_group_exclusion() {
+        let binding_mgr = RefCell::new(BindingMgr::new());
+        let cluster = BindingCluster::new(&binding_mgr, dummy_rand);
+
+        let both = Target {
+            group: Some(0x0101),
+            ..node_target(0x10, 1, 1)
+        };
+        let neither = Target {
+            node: None,
+            endpoint: None,
+            ..node_target(0x10, 1, 1)
+        };
+        let no_endpoint = Target {
+            endpoint: None,
+            ..node_target(0x10, 1, 1)
+        };
+
+        for target in [both, neither, no_endpoint] {
+            assert_eq!(
+                try_add(&cluster, target, 1).map_err(|e| e.code()),
+                Err(ErrorCode::ConstraintError)
+            );
+        }
+
+        assert!(read(&cluster, 1).is_empty());
+        assert!(!binding_mgr.borrow().is_changed());
+    }
+}
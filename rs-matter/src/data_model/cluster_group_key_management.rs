@@ -0,0 +1,640 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::RefCell;
+use core::convert::TryInto;
+
+use crate::crypto::SYMM_KEY_LEN_BYTES;
+use crate::data_model::objects::*;
+use crate::fabric::FabricMgr;
+use crate::groups::{
+    EpochKey, GroupKeySet, GroupMgr, MAX_EPOCH_KEYS, MAX_GROUPS_PER_FABRIC,
+    MAX_GROUP_KEY_SETS_PER_FABRIC,
+};
+use crate::interaction_model::messages::ib::{attr_list_write, ListOperation};
+use crate::tlv::{FromTLV, Nullable, OctetStr, TLVElement, ToTLV, UtfStr};
+use crate::transport::exchange::Exchange;
+use crate::utils::rand::Rand;
+use crate::{attribute_enum, cmd_enter, command_enum, error::*};
+use log::{error, info};
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x003F;
+
+/// The key set ID of the IPK, which is managed through the Node Operational Credentials cluster
+const IPK_KEY_SET_ID: u16 = 0;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    GroupKeyMap(()) = 0,
+    GroupTable(()) = 1,
+    MaxGroupsPerFabric(AttrType<u16>) = 2,
+    MaxGroupKeysPerFabric(AttrType<u16>) = 3,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    KeySetWrite = 0x00,
+    KeySetRead = 0x01,
+    KeySetRemove = 0x03,
+    KeySetReadAllIndices = 0x04,
+}
+
+command_enum!(Commands);
+
+#[repr(u16)]
+pub enum RespCommands {
+    KeySetReadResp = 0x02,
+    KeySetReadAllIndicesResp = 0x05,
+}
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: 0,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::GroupKeyMap as u16,
+            Access::RWVM.union(Access::FAB_SCOPED),
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::GroupTable as u16,
+            Access::RV.union(Access::FAB_SCOPED),
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MaxGroupsPerFabric as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MaxGroupKeysPerFabric as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+    ],
+    commands: &[
+        Commands::KeySetWrite as _,
+        Commands::KeySetRead as _,
+        Commands::KeySetRemove as _,
+        Commands::KeySetReadAllIndices as _,
+    ],
+    generated_commands: &[
+        RespCommands::KeySetReadResp as _,
+        RespCommands::KeySetReadAllIndicesResp as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[
+        Commands::KeySetRead as _,
+        Commands::KeySetReadAllIndices as _,
+    ],
+    manage_commands: &[],
+    admin_commands: &[
+        Commands::KeySetWrite as _,
+        Commands::KeySetRead as _,
+        Commands::KeySetRemove as _,
+        Commands::KeySetReadAllIndices as _,
+    ],
+};
+
+#[derive(FromTLV, ToTLV, Debug, Clone)]
+#[tlvargs(lifetime = "'a")]
+pub struct GroupKeySetStruct<'a> {
+    pub key_set_id: u16,
+    pub policy: u8,
+    pub epoch_key0: Nullable<OctetStr<'a>>,
+    pub epoch_start_time0: Nullable<u64>,
+    pub epoch_key1: Nullable<OctetStr<'a>>,
+    pub epoch_start_time1: Nullable<u64>,
+    pub epoch_key2: Nullable<OctetStr<'a>>,
+    pub epoch_start_time2: Nullable<u64>,
+}
+
+impl<'a> GroupKeySetStruct<'a> {
+    fn epoch_keys(&self) -> [(&Nullable<OctetStr<'a>>, &Nullable<u64>); MAX_EPOCH_KEYS] {
+        [
+            (&self.epoch_key0, &self.epoch_start_time0),
+            (&self.epoch_key1, &self.epoch_start_time1),
+            (&self.epoch_key2, &self.epoch_start_time2),
+        ]
+    }
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct KeySetWriteReq<'a> {
+    key_set: GroupKeySetStruct<'a>,
+}
+
+#[derive(FromTLV)]
+struct KeySetReq {
+    key_set_id: u16,
+}
+
+#[derive(ToTLV)]
+struct KeySetReadResp<'a> {
+    key_set: GroupKeySetStruct<'a>,
+}
+
+#[derive(ToTLV)]
+struct KeySetReadAllIndicesResp<'a> {
+    key_set_ids: &'a [u16],
+}
+
+#[derive(FromTLV, ToTLV, Debug)]
+#[tlvargs(start = 1)]
+struct GroupKeyMapStruct {
+    group_id: u16,
+    key_set_id: u16,
+    #[tagval(0xFE)]
+    fab_idx: Option<u8>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(start = 1)]
+struct GroupInfoMapStruct<'a> {
+    group_id: u16,
+    endpoints: &'a [EndptId],
+    group_name: Option<UtfStr<'a>>,
+    #[tagval(0xFE)]
+    fab_idx: Option<u8>,
+}
+
+pub struct GroupKeyManagementCluster<'a> {
+    data_ver: Dataver,
+    fabric_mgr: &'a RefCell<FabricMgr>,
+    group_mgr: &'a RefCell<GroupMgr>,
+}
+
+impl<'a> GroupKeyManagementCluster<'a> {
+    pub fn new(
+        fabric_mgr: &'a RefCell<FabricMgr>,
+        group_mgr: &'a RefCell<GroupMgr>,
+        rand: Rand,
+    ) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            fabric_mgr,
+            group_mgr,
+        }
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::GroupKeyMap(_) => writer.set_list(
+                        self.group_mgr
+                            .borrow()
+                            .key_map()
+                            .filter(|entry| attr.is_visible_to(entry.fab_idx))
+                            .map(|entry| GroupKeyMapStruct {
+                                group_id: entry.group_id,
+                                key_set_id: entry.key_set_id,
+                                fab_idx: Some(entry.fab_idx),
+                            }),
+                    ),
+                    Attributes::GroupTable(_) => writer.set_list(
+                        self.group_mgr
+                            .borrow()
+                            .groups()
+                            .filter(|group| attr.is_visible_to(group.fab_idx))
+                            .map(|group| GroupInfoMapStruct {
+                                group_id: group.group_id,
+                                endpoints: &group.endpoints,
+                                group_name: (!group.name.is_empty())
+                                    .then(|| UtfStr::new(group.name.as_bytes())),
+                                fab_idx: Some(group.fab_idx),
+                            }),
+                    ),
+                    Attributes::MaxGroupsPerFabric(codec) => {
+                        codec.encode(writer, MAX_GROUPS_PER_FABRIC as _)
+                    }
+                    // The IPK counts as one of the key sets of the fabric
+                    Attributes::MaxGroupKeysPerFabric(codec) => {
+                        codec.encode(writer, (MAX_GROUP_KEY_SETS_PER_FABRIC + 1) as _)
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        match attr.attr_id.try_into()? {
+            Attributes::GroupKeyMap(_) => {
+                attr_list_write(attr, data.with_dataver(self.data_ver.get())?, |op, data| {
+                    self.write_key_map_attr(&op, data, attr.fab_idx)
+                })?
+            }
+            _ => {
+                error!("Attribute not yet supported: this shouldn't happen");
+                Err(ErrorCode::AttributeNotFound)?
+            }
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    pub fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        let fab_idx = exchange
+            .with_session(|sess| Ok(sess.get_local_fabric_idx()))?
+            .ok_or(ErrorCode::UnsupportedAccess)?;
+
+        match cmd.cmd_id.try_into()? {
+            Commands::KeySetWrite => {
+                let compressed_id = self
+                    .fabric_mgr
+                    .borrow()
+                    .get_fabric(fab_idx as _)?
+                    .ok_or(ErrorCode::NoFabricId)?
+                    .compressed_id();
+
+                self.handle_command_keysetwrite(fab_idx, &compressed_id, data)?
+            }
+            Commands::KeySetRead => self.handle_command_keysetread(fab_idx, data, encoder)?,
+            Commands::KeySetRemove => self.handle_command_keysetremove(fab_idx, data)?,
+            Commands::KeySetReadAllIndices => {
+                self.handle_command_keysetreadallindices(fab_idx, encoder)?
+            }
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    fn write_key_map_attr(
+        &self,
+        op: &ListOperation,
+        data: &TLVElement,
+        fab_idx: u8,
+    ) -> Result<(), Error> {
+        info!("Performing Group Key Map operation {:?}", op);
+        match op {
+            ListOperation::AddItem => {
+                let entry = GroupKeyMapStruct::from_tlv(data)?;
+
+                let mut group_mgr = self.group_mgr.borrow_mut();
+
+                // A group can only be mapped once per fabric, and never to the IPK
+                if entry.key_set_id == IPK_KEY_SET_ID
+                    || group_mgr
+                        .key_map()
+                        .any(|e| e.fab_idx == fab_idx && e.group_id == entry.group_id)
+                {
+                    Err(ErrorCode::ConstraintError)?;
+                }
+
+                // Overwrite the fabric index with our accessing fabric index
+                group_mgr.map_group_key(fab_idx, entry.group_id, entry.key_set_id)
+            }
+            ListOperation::DeleteList => {
+                self.group_mgr.borrow_mut().clear_key_map(fab_idx);
+                Ok(())
+            }
+            ListOperation::EditItem(_) | ListOperation::DeleteItem(_) => {
+                Err(ErrorCode::InvalidAction.into())
+            }
+        }
+    }
+
+    fn handle_command_keysetwrite(
+        &self,
+        fab_idx: u8,
+        compressed_id: &[u8],
+        data: &TLVElement,
+    ) -> Result<(), Error> {
+        cmd_enter!("KeySetWrite");
+
+        let req = KeySetWriteReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        let key_set = req.key_set;
+
+        if key_set.key_set_id == IPK_KEY_SET_ID || key_set.policy > 1 {
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
+        let mut group_mgr = self.group_mgr.borrow_mut();
+
+        if group_mgr.key_set(fab_idx, key_set.key_set_id).is_some() {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        let mut epoch_keys = heapless::Vec::new();
+        let mut in_order = true;
+
+        for (key, start_time) in key_set.epoch_keys() {
+            match (key, start_time) {
+                (Nullable::NotNull(key), Nullable::NotNull(start_time)) if in_order => {
+                    if key.0.len() != SYMM_KEY_LEN_BYTES {
+                        Err(ErrorCode::ConstraintError)?;
+                    }
+
+                    epoch_keys
+                        .push(EpochKey::new(*start_time, key.0, compressed_id)?)
+                        .map_err(|_| ErrorCode::NoSpace)?;
+                }
+                (Nullable::Null, Nullable::Null) => in_order = false,
+                // The first epoch key is mandatory, and the following ones need to come in order
+                _ => Err(ErrorCode::InvalidCommand)?,
+            }
+        }
+
+        if epoch_keys.is_empty() {
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
+        group_mgr.set_key_set(GroupKeySet {
+            fab_idx,
+            key_set_id: key_set.key_set_id,
+            policy: key_set.policy,
+            epoch_keys,
+        })
+    }
+
+    fn handle_command_keysetread(
+        &self,
+        fab_idx: u8,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("KeySetRead");
+
+        let req = KeySetReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let group_mgr = self.group_mgr.borrow();
+        let key_set = group_mgr
+            .key_set(fab_idx, req.key_set_id)
+            .ok_or(ErrorCode::NotFound)?;
+
+        let start_time = |index: usize| {
+            key_set
+                .epoch_keys
+                .get(index)
+                .map(|key| Nullable::NotNull(key.start_time))
+                .unwrap_or(Nullable::Null)
+        };
+
+        // The epoch keys themselves are never to be disclosed
+        let cmd_data = KeySetReadResp {
+            key_set: GroupKeySetStruct {
+                key_set_id: key_set.key_set_id,
+                policy: key_set.policy,
+                epoch_key0: Nullable::Null,
+                epoch_start_time0: start_time(0),
+                epoch_key1: Nullable::Null,
+                epoch_start_time1: start_time(1),
+                epoch_key2: Nullable::Null,
+                epoch_start_time2: start_time(2),
+            },
+        };
+
+        encoder
+            .with_command(RespCommands::KeySetReadResp as _)?
+            .set(cmd_data)?;
+
+        Ok(())
+    }
+
+    fn handle_command_keysetremove(&self, fab_idx: u8, data: &TLVElement) -> Result<(), Error> {
+        cmd_enter!("KeySetRemove");
+
+        let req = KeySetReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        if req.key_set_id == IPK_KEY_SET_ID {
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
+        self.group_mgr
+            .borrow_mut()
+            .remove_key_set(fab_idx, req.key_set_id)
+    }
+
+    fn handle_command_keysetreadallindices(
+        &self,
+        fab_idx: u8,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("KeySetReadAllIndices");
+
+        let mut key_set_ids = heapless::Vec::<u16, { MAX_GROUP_KEY_SETS_PER_FABRIC + 1 }>::new();
+
+        // The IPK is always there
+        key_set_ids
+            .push(IPK_KEY_SET_ID)
+            .map_err(|_| ErrorCode::NoSpace)?;
+
+        for key_set in self.group_mgr.borrow().key_sets(fab_idx) {
+            key_set_ids
+                .push(key_set.key_set_id)
+                .map_err(|_| ErrorCode::NoSpace)?;
+        }
+
+        encoder
+            .with_command(RespCommands::KeySetReadAllIndicesResp as _)?
+            .set(KeySetReadAllIndicesResp {
+                key_set_ids: &key_set_ids,
+            })?;
+
+        Ok(())
+    }
+}
+
+impl<'a> Handler for GroupKeyManagementCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        GroupKeyManagementCluster::read(self, attr, encoder)
+    }
+
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        GroupKeyManagementCluster::write(self, attr, data)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        GroupKeyManagementCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for GroupKeyManagementCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for GroupKeyManagementCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use crate::{
+        data_model::objects::{CmdDataEncoder, CmdDataTracker, CmdDetails, Node},
+        fabric::FabricMgr,
+        groups::GroupMgr,
+        tlv::{get_root_node_struct, FromTLV, Nullable, OctetStr, TLVWriter, TagType, ToTLV},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{Commands, GroupKeyManagementCluster, GroupKeySetStruct, ID};
+
+    const COMPRESSED_ID: [u8; 8] = [0x87, 0xe1, 0xb0, 0x04, 0xe2, 0x35, 0xa1, 0x30];
+
+    const EPOCH_KEY: [u8; 16] = [
+        0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xab, 0xac, 0xad, 0xae,
+        0xaf,
+    ];
+
+    fn key_set_write(
+        cluster: &GroupKeyManagementCluster,
+        key_set_id: u16,
+        epoch_key: &[u8],
+    ) -> bool {
+        let mut buf: [u8; 100] = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        GroupKeySetStruct {
+            key_set_id,
+            policy: 0,
+            epoch_key0: Nullable::NotNull(OctetStr::new(epoch_key)),
+            epoch_start_time0: Nullable::NotNull(1000),
+            epoch_key1: Nullable::Null,
+            epoch_start_time1: Nullable::Null,
+            epoch_key2: Nullable::Null,
+            epoch_start_time2: Nullable::Null,
+        }
+        .to_tlv(&mut tw, TagType::Context(0))
+        .unwrap();
+        tw.end_container().unwrap();
+
+        let data = get_root_node_struct(writebuf.as_slice()).unwrap();
+
+        cluster
+            .handle_command_keysetwrite(1, &COMPRESSED_ID, &data)
+            .is_ok()
+    }
+
+    #[test]
+    /// The epoch keys are never disclosed by KeySetRead
+    fn key_set_write_read() {
+        let fabric_mgr = RefCell::new(FabricMgr::new());
+        let group_mgr = RefCell::new(GroupMgr::new());
+        let cluster = GroupKeyManagementCluster::new(&fabric_mgr, &group_mgr, dummy_rand);
+
+        assert!(key_set_write(&cluster, 42, &EPOCH_KEY));
+        assert_eq!(
+            group_mgr.borrow().key_set(1, 42).unwrap().epoch_keys[0].session_id(),
+            0x6ee8
+        );
+
+        let mut req_buf: [u8; 20] = [0; 20];
+        let mut req_writebuf = WriteBuf::new(&mut req_buf);
+        let mut tw = TLVWriter::new(&mut req_writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.u16(TagType::Context(0), 42).unwrap();
+        tw.end_container().unwrap();
+
+        let req = get_root_node_struct(req_writebuf.as_slice()).unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let node = Node {
+            id: 0,
+            endpoints: &[],
+        };
+        let cmd = CmdDetails {
+            node: &node,
+            endpoint_id: 0,
+            cluster_id: ID,
+            cmd_id: Commands::KeySetRead as _,
+            wildcard: false,
+            command_ref: None,
+        };
+        let mut tracker = CmdDataTracker::new();
+
+        cluster
+            .handle_command_keysetread(1, &req, CmdDataEncoder::new(&cmd, &mut tracker, &mut tw))
+            .unwrap();
+
+        let resp = get_root_node_struct(writebuf.as_slice()).unwrap();
+        let key_set = GroupKeySetStruct::from_tlv(
+            &resp
+                .find_tag(0)
+                .unwrap()
+                .find_tag(1)
+                .unwrap()
+                .find_tag(0)
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(key_set.key_set_id, 42);
+        assert_eq!(key_set.policy, 0);
+        assert_eq!(key_set.epoch_key0, Nullable::Null);
+        assert_eq!(key_set.epoch_start_time0, Nullable::NotNull(1000));
+        assert_eq!(key_set.epoch_key1, Nullable::Null);
+        assert_eq!(key_set.epoch_start_time1, Nullable::Null);
+
+        // Removing the key set a second time fails, as it is gone
+        assert!(cluster.handle_command_keysetremove(1, &req).is_ok());
+        assert!(cluster.handle_command_keysetremove(1, &req).is_err());
+    }
+
+    #[test]
+    /// Epoch keys of the wrong length and duplicate key set IDs are rejected
+    fn key_set_write_invalid() {
+        let fabric_mgr = RefCell::new(FabricMgr::new());
+        let group_mgr = RefCell::new(GroupMgr::new());
+        let cluster = GroupKeyManagementCluster::new(&fabric_mgr, &group_mgr, dummy_rand);
+
+        assert!(!key_set_write(&cluster, 42, &EPOCH_KEY[..8]));
+        assert!(group_mgr.borrow().key_set(1, 42).is_none());
+
+        assert!(key_set_write(&cluster, 42, &EPOCH_KEY));
+        assert!(!key_set_write(&cluster, 42, &EPOCH_KEY));
+
+        // The IPK can't be written through this cluster
+        assert!(!key_set_write(&cluster, 0, &EPOCH_KEY));
+    }
+}
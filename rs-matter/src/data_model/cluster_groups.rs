@@ -0,0 +1,635 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::RefCell;
+use core::convert::TryInto;
+
+use super::cluster_identify::IdentifyCluster;
+use super::objects::*;
+use crate::groups::{GroupMgr, MAX_GROUPS_PER_FABRIC, MAX_GROUP_NAME_LEN};
+use crate::interaction_model::core::IMStatusCode;
+use crate::tlv::{FromTLV, Nullable, TLVArray, TLVElement, ToTLV, UtfStr};
+use crate::transport::exchange::Exchange;
+use crate::utils::rand::Rand;
+use crate::{attribute_enum, cmd_enter, command_enum, error::*};
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0004;
+
+/// The GroupNames feature
+pub const FEATURE_GROUP_NAMES: u32 = 0x01;
+
+/// The value of the NameSupport attribute, when group names are supported
+const NAME_SUPPORT: u8 = 0x80;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    NameSupport(AttrType<u8>) = 0,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    AddGroup = 0x00,
+    ViewGroup = 0x01,
+    GetGroupMembership = 0x02,
+    RemoveGroup = 0x03,
+    RemoveAllGroups = 0x04,
+    AddGroupIfIdentifying = 0x05,
+}
+
+command_enum!(Commands);
+
+#[repr(u16)]
+pub enum RespCommands {
+    AddGroupResp = 0x00,
+    ViewGroupResp = 0x01,
+    GetGroupMembershipResp = 0x02,
+    RemoveGroupResp = 0x03,
+}
+
+/// The metadata of the cluster, with group names supported.
+///
+/// Use `Cluster { feature_map: 0, ..CLUSTER }` for an endpoint whose handler
+/// does not support group names.
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 4,
+    feature_map: FEATURE_GROUP_NAMES,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::NameSupport as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+    ],
+    commands: &[
+        Commands::AddGroup as _,
+        Commands::ViewGroup as _,
+        Commands::GetGroupMembership as _,
+        Commands::RemoveGroup as _,
+        Commands::RemoveAllGroups as _,
+        Commands::AddGroupIfIdentifying as _,
+    ],
+    generated_commands: &[
+        RespCommands::AddGroupResp as _,
+        RespCommands::ViewGroupResp as _,
+        RespCommands::GetGroupMembershipResp as _,
+        RespCommands::RemoveGroupResp as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[Commands::ViewGroup as _, Commands::GetGroupMembership as _],
+    manage_commands: &[
+        Commands::AddGroup as _,
+        Commands::RemoveGroup as _,
+        Commands::RemoveAllGroups as _,
+        Commands::AddGroupIfIdentifying as _,
+    ],
+    admin_commands: &[],
+};
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddGroupReq<'a> {
+    group_id: u16,
+    group_name: UtfStr<'a>,
+}
+
+#[derive(FromTLV)]
+struct GroupReq {
+    group_id: u16,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct GetGroupMembershipReq<'a> {
+    group_list: TLVArray<'a, u16>,
+}
+
+#[derive(ToTLV)]
+struct GroupResp {
+    status: u8,
+    group_id: u16,
+}
+
+#[derive(ToTLV)]
+struct ViewGroupResp<'a> {
+    status: u8,
+    group_id: u16,
+    group_name: UtfStr<'a>,
+}
+
+#[derive(ToTLV)]
+struct GetGroupMembershipResp<'a> {
+    capacity: Nullable<u8>,
+    group_list: &'a [u16],
+}
+
+pub struct GroupsCluster<'a> {
+    data_ver: Dataver,
+    name_support: bool,
+    group_mgr: &'a RefCell<GroupMgr>,
+    identify: Option<&'a IdentifyCluster<'a>>,
+}
+
+impl<'a> GroupsCluster<'a> {
+    /// Create the handler of the cluster.
+    ///
+    /// `name_support` should match the GroupNames feature of the cluster metadata of the endpoint.
+    pub fn new(group_mgr: &'a RefCell<GroupMgr>, name_support: bool, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            name_support,
+            group_mgr,
+            identify: None,
+        }
+    }
+
+    /// Have AddGroupIfIdentifying look at the Identify cluster of the endpoint.
+    ///
+    /// Without it, the command is rejected as unsupported.
+    pub fn with_identify(self, identify: &'a IdentifyCluster<'a>) -> Self {
+        Self {
+            identify: Some(identify),
+            ..self
+        }
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::NameSupport(codec) => {
+                        codec.encode(writer, if self.name_support { NAME_SUPPORT } else { 0 })
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        let fab_idx = exchange
+            .with_session(|sess| Ok(sess.get_local_fabric_idx()))?
+            .ok_or(ErrorCode::UnsupportedAccess)?;
+        let endpoint = cmd.endpoint_id;
+
+        match cmd.cmd_id.try_into()? {
+            Commands::AddGroup => self.handle_command_addgroup(fab_idx, endpoint, data, encoder)?,
+            Commands::ViewGroup => {
+                self.handle_command_viewgroup(fab_idx, endpoint, data, encoder)?
+            }
+            Commands::GetGroupMembership => {
+                self.handle_command_getgroupmembership(fab_idx, endpoint, data, encoder)?
+            }
+            Commands::RemoveGroup => {
+                self.handle_command_removegroup(fab_idx, endpoint, data, encoder)?
+            }
+            Commands::RemoveAllGroups => {
+                cmd_enter!("RemoveAllGroups");
+                self.group_mgr
+                    .borrow_mut()
+                    .remove_endpoint(fab_idx, endpoint);
+            }
+            Commands::AddGroupIfIdentifying => {
+                self.handle_command_addgroupifidentifying(fab_idx, endpoint, data)?
+            }
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    fn add_group(&self, fab_idx: u8, endpoint: EndptId, req: &AddGroupReq) -> Result<(), Error> {
+        let name = if self.name_support {
+            match req.group_name.as_str() {
+                Ok(name) if name.len() <= MAX_GROUP_NAME_LEN => name,
+                _ => Err(ErrorCode::ConstraintError)?,
+            }
+        } else {
+            ""
+        };
+
+        if req.group_id == 0 {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        let mut group_mgr = self.group_mgr.borrow_mut();
+
+        // A group can only be joined once it has a key set for its messages
+        if !group_mgr
+            .key_map()
+            .any(|e| e.fab_idx == fab_idx && e.group_id == req.group_id)
+        {
+            Err(ErrorCode::UnsupportedAccess)?;
+        }
+
+        group_mgr.add_group_endpoint(fab_idx, req.group_id, endpoint, name)
+    }
+
+    fn handle_command_addgroup(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("AddGroup");
+
+        let req = AddGroupReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        let status = match self.add_group(fab_idx, endpoint, &req) {
+            Ok(()) => IMStatusCode::Success,
+            Err(e) => e.into(),
+        };
+
+        encoder
+            .with_command(RespCommands::AddGroupResp as _)?
+            .set(GroupResp {
+                status: status as _,
+                group_id: req.group_id,
+            })?;
+
+        Ok(())
+    }
+
+    /// There is no response command, the outcome of adding the group is the status of the command
+    fn handle_command_addgroupifidentifying(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+    ) -> Result<(), Error> {
+        cmd_enter!("AddGroupIfIdentifying");
+
+        let identify = self.identify.ok_or(ErrorCode::CommandNotFound)?;
+
+        let req = AddGroupReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        if req.group_id == 0 {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        // Nothing to do unless the endpoint is identifying
+        if identify.is_identifying() {
+            self.add_group(fab_idx, endpoint, &req)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_command_viewgroup(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("ViewGroup");
+
+        let req = GroupReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let group_mgr = self.group_mgr.borrow();
+        let group = group_mgr
+            .group(fab_idx, req.group_id)
+            .filter(|g| g.endpoints.contains(&endpoint));
+
+        let status = if req.group_id == 0 {
+            IMStatusCode::ConstraintError
+        } else if group.is_none() {
+            IMStatusCode::NotFound
+        } else {
+            IMStatusCode::Success
+        };
+
+        encoder
+            .with_command(RespCommands::ViewGroupResp as _)?
+            .set(ViewGroupResp {
+                status: status as _,
+                group_id: req.group_id,
+                group_name: UtfStr::new(group.map(|g| g.name.as_bytes()).unwrap_or(&[])),
+            })?;
+
+        Ok(())
+    }
+
+    fn handle_command_getgroupmembership(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("GetGroupMembership");
+
+        let req = GetGroupMembershipReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let group_mgr = self.group_mgr.borrow();
+
+        let mut fabric_groups = 0;
+        let mut group_list = heapless::Vec::<u16, MAX_GROUPS_PER_FABRIC>::new();
+
+        for group in group_mgr.groups().filter(|g| g.fab_idx == fab_idx) {
+            fabric_groups += 1;
+
+            // An empty list in the request stands for all groups
+            if group.endpoints.contains(&endpoint)
+                && (req.group_list.iter().next().is_none()
+                    || req.group_list.iter().any(|id| id == group.group_id))
+            {
+                group_list
+                    .push(group.group_id)
+                    .map_err(|_| ErrorCode::NoSpace)?;
+            }
+        }
+
+        encoder
+            .with_command(RespCommands::GetGroupMembershipResp as _)?
+            .set(GetGroupMembershipResp {
+                capacity: Nullable::NotNull((MAX_GROUPS_PER_FABRIC - fabric_groups) as _),
+                group_list: &group_list,
+            })?;
+
+        Ok(())
+    }
+
+    fn handle_command_removegroup(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("RemoveGroup");
+
+        let req = GroupReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let status = if req.group_id == 0 {
+            IMStatusCode::ConstraintError
+        } else {
+            match self
+                .group_mgr
+                .borrow_mut()
+                .remove_group_endpoint(fab_idx, req.group_id, endpoint)
+            {
+                Ok(()) => IMStatusCode::Success,
+                Err(e) => e.into(),
+            }
+        };
+
+        info!("Removing group {}: {:?}", req.group_id, status);
+
+        encoder
+            .with_command(RespCommands::RemoveGroupResp as _)?
+            .set(GroupResp {
+                status: status as _,
+                group_id: req.group_id,
+            })?;
+
+        Ok(())
+    }
+}
+
+impl<'a> Handler for GroupsCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        GroupsCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        GroupsCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for GroupsCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for GroupsCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use crate::{
+        data_model::{
+            cluster_identify::{EffectId, IdentifyCluster, IdentifyHandler, IdentifyType},
+            objects::{CmdDataEncoder, CmdDataTracker, CmdDetails, Node},
+        },
+        error::{Error, ErrorCode},
+        groups::GroupMgr,
+        interaction_model::core::IMStatusCode,
+        tlv::{get_root_node_struct, TLVWriter, TagType},
+        utils::{epoch::dummy_epoch, rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{Commands, GroupsCluster, ID};
+
+    struct NoIndicator;
+
+    impl IdentifyHandler for NoIndicator {
+        fn on_identify_start(&self) {}
+
+        fn on_identify_stop(&self) {}
+
+        fn on_effect(&self, _effect: EffectId, _variant: u8) {}
+    }
+
+    /// Invoke AddGroupIfIdentifying on endpoint 1 of fabric 1, which has no response
+    fn add_group_if_identifying(cluster: &GroupsCluster, group_id: u16) -> Result<(), Error> {
+        let mut req_buf: [u8; 50] = [0; 50];
+        let mut req_writebuf = WriteBuf::new(&mut req_buf);
+        let mut tw = TLVWriter::new(&mut req_writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.u16(TagType::Context(0), group_id).unwrap();
+        tw.utf16(TagType::Context(1), b"Kitchen").unwrap();
+        tw.end_container().unwrap();
+
+        let req = get_root_node_struct(req_writebuf.as_slice()).unwrap();
+
+        cluster.handle_command_addgroupifidentifying(1, 1, &req)
+    }
+
+    /// Invoke the command on endpoint 1 of fabric 1, returning the status and
+    /// the group name of its response
+    fn invoke(
+        cluster: &GroupsCluster,
+        cmd_id: Commands,
+        group_id: u16,
+    ) -> (u8, heapless::Vec<u8, 16>) {
+        let mut req_buf: [u8; 50] = [0; 50];
+        let mut req_writebuf = WriteBuf::new(&mut req_buf);
+        let mut tw = TLVWriter::new(&mut req_writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.u16(TagType::Context(0), group_id).unwrap();
+        if matches!(cmd_id, Commands::AddGroup) {
+            tw.utf16(TagType::Context(1), b"Kitchen").unwrap();
+        }
+        tw.end_container().unwrap();
+
+        let req = get_root_node_struct(req_writebuf.as_slice()).unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let node = Node {
+            id: 0,
+            endpoints: &[],
+        };
+        let cmd = CmdDetails {
+            node: &node,
+            endpoint_id: 1,
+            cluster_id: ID,
+            cmd_id: cmd_id as _,
+            wildcard: false,
+            command_ref: None,
+        };
+        let mut tracker = CmdDataTracker::new();
+        let encoder = CmdDataEncoder::new(&cmd, &mut tracker, &mut tw);
+
+        match cmd_id {
+            Commands::AddGroup => cluster.handle_command_addgroup(1, 1, &req, encoder),
+            Commands::ViewGroup => cluster.handle_command_viewgroup(1, 1, &req, encoder),
+            Commands::RemoveGroup => cluster.handle_command_removegroup(1, 1, &req, encoder),
+            _ => unreachable!(),
+        }
+        .unwrap();
+
+        let resp = get_root_node_struct(writebuf.as_slice()).unwrap();
+        let data = resp.find_tag(0).unwrap().find_tag(1).unwrap();
+
+        assert_eq!(data.find_tag(1).unwrap().u16().unwrap(), group_id);
+
+        let name = data
+            .find_tag(2)
+            .map(|name| heapless::Vec::from_slice(name.slice().unwrap()).unwrap())
+            .unwrap_or_default();
+
+        (data.find_tag(0).unwrap().u8().unwrap(), name)
+    }
+
+    #[test]
+    fn add_view_group() {
+        let group_mgr = RefCell::new(GroupMgr::new());
+        group_mgr.borrow_mut().map_group_key(1, 0x0101, 42).unwrap();
+
+        let cluster = GroupsCluster::new(&group_mgr, true, dummy_rand);
+
+        let (status, _) = invoke(&cluster, Commands::AddGroup, 0x0101);
+        assert_eq!(status, IMStatusCode::Success as u8);
+        assert!(group_mgr.borrow().is_member(1, 0x0101, 1));
+
+        let (status, name) = invoke(&cluster, Commands::ViewGroup, 0x0101);
+        assert_eq!(status, IMStatusCode::Success as u8);
+        assert_eq!(name.as_slice(), b"Kitchen");
+
+        let (status, _) = invoke(&cluster, Commands::RemoveGroup, 0x0101);
+        assert_eq!(status, IMStatusCode::Success as u8);
+
+        let (status, _) = invoke(&cluster, Commands::ViewGroup, 0x0101);
+        assert_eq!(status, IMStatusCode::NotFound as u8);
+    }
+
+    #[test]
+    /// Group ID 0 is reserved, and groups without a key set can't be joined
+    fn add_group_invalid() {
+        let group_mgr = RefCell::new(GroupMgr::new());
+        group_mgr.borrow_mut().map_group_key(1, 0, 42).unwrap();
+
+        let cluster = GroupsCluster::new(&group_mgr, true, dummy_rand);
+
+        let (status, _) = invoke(&cluster, Commands::AddGroup, 0);
+        assert_eq!(status, IMStatusCode::ConstraintError as u8);
+
+        let (status, _) = invoke(&cluster, Commands::AddGroup, 0x0102);
+        assert_eq!(status, IMStatusCode::UnsupportedAccess as u8);
+
+        assert!(group_mgr.borrow().groups().next().is_none());
+    }
+
+    #[test]
+    /// The group is only added while the endpoint is identifying
+    fn add_group_if_identifying() {
+        let group_mgr = RefCell::new(GroupMgr::new());
+        group_mgr.borrow_mut().map_group_key(1, 0x0101, 42).unwrap();
+
+        let identify =
+            IdentifyCluster::new(IdentifyType::None, &NoIndicator, dummy_epoch, dummy_rand);
+
+        let cluster = GroupsCluster::new(&group_mgr, true, dummy_rand).with_identify(&identify);
+
+        add_group_if_identifying(&cluster, 0x0101).unwrap();
+        assert!(!group_mgr.borrow().is_member(1, 0x0101, 1));
+
+        identify.identify(10);
+        add_group_if_identifying(&cluster, 0x0101).unwrap();
+        assert!(group_mgr.borrow().is_member(1, 0x0101, 1));
+
+        // The same checks as with AddGroup apply
+        assert_eq!(
+            add_group_if_identifying(&cluster, 0x0102)
+                .unwrap_err()
+                .code(),
+            ErrorCode::UnsupportedAccess
+        );
+        assert_eq!(
+            add_group_if_identifying(&cluster, 0).unwrap_err().code(),
+            ErrorCode::ConstraintError
+        );
+    }
+
+    #[test]
+    /// Without the Identify cluster of the endpoint, the command is not supported
+    fn add_group_if_identifying_unsupported() {
+        let group_mgr = RefCell::new(GroupMgr::new());
+        group_mgr.borrow_mut().map_group_key(1, 0x0101, 42).unwrap();
+
+        let cluster = GroupsCluster::new(&group_mgr, true, dummy_rand);
+
+        assert_eq!(
+            add_group_if_identifying(&cluster, 0x0101)
+                .unwrap_err()
+                .code(),
+            ErrorCode::CommandNotFound
+        );
+        assert!(group_mgr.borrow().groups().next().is_none());
+    }
+}
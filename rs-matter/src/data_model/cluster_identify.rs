@@ -0,0 +1,490 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::{cell::Cell, convert::TryInto, time::Duration};
+
+use super::objects::*;
+use crate::{
+    attribute_enum, cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    tlv::{FromTLV, TLVElement},
+    transport::exchange::Exchange,
+    utils::{epoch::Epoch, rand::Rand},
+};
+use embassy_time::Timer;
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0003;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    IdentifyTime(AttrType<u16>) = 0x0,
+    IdentifyType(AttrType<u8>) = 0x1,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    Identify = 0x00,
+    TriggerEffect = 0x40,
+}
+
+command_enum!(Commands);
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 4,
+    feature_map: 0,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::IdentifyTime as u16,
+            Access::RWVO,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::IdentifyType as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+    ],
+    commands: &[Commands::Identify as _, Commands::TriggerEffect as _],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[Commands::Identify as _, Commands::TriggerEffect as _],
+    admin_commands: &[],
+};
+
+/// The way the device identifies itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IdentifyType {
+    None = 0,
+    LightOutput = 1,
+    VisibleIndicator = 2,
+    AudibleBeep = 3,
+    Display = 4,
+    Actuator = 5,
+}
+
+/// The effects of the TriggerEffect command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+#[repr(u8)]
+pub enum EffectId {
+    Blink = 0x00,
+    Breathe = 0x01,
+    Okay = 0x02,
+    ChannelChange = 0x0b,
+    /// Complete the current effect sequence before terminating
+    FinishEffect = 0xfe,
+    /// Terminate the current effect as soon as possible
+    StopEffect = 0xff,
+}
+
+#[derive(FromTLV)]
+struct IdentifyReq {
+    identify_time: u16,
+}
+
+#[derive(FromTLV)]
+struct TriggerEffectReq {
+    effect_id: u8,
+    effect_variant: u8,
+}
+
+/// The Identify Handler Trait
+///
+/// Objects that implement this trait drive the indicator of the device (blink an LED,
+/// beep, ...) on behalf of the cluster.
+pub trait IdentifyHandler {
+    /// Called when the device starts identifying, i.e. `IdentifyTime` becomes non-zero
+    fn on_identify_start(&self);
+
+    /// Called when the device stops identifying, because `IdentifyTime` has counted
+    /// down to zero or was set to zero
+    fn on_identify_stop(&self);
+
+    /// Called for the TriggerEffect command. For `EffectId::FinishEffect` the current
+    /// effect should be allowed to complete its sequence, rather than be cut short.
+    fn on_effect(&self, effect: EffectId, variant: u8);
+}
+
+pub struct IdentifyCluster<'a> {
+    data_ver: Dataver,
+    identify_type: IdentifyType,
+    handler: &'a dyn IdentifyHandler,
+    epoch: Epoch,
+    /// The time at which the device stops identifying, if it is identifying
+    end: Cell<Option<Duration>>,
+    /// The effect which is running, if any, and whether it terminates once
+    /// its current sequence completes
+    effect: Cell<Option<(EffectId, bool)>>,
+}
+
+impl<'a> IdentifyCluster<'a> {
+    pub fn new(
+        identify_type: IdentifyType,
+        handler: &'a dyn IdentifyHandler,
+        epoch: Epoch,
+        rand: Rand,
+    ) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            identify_type,
+            handler,
+            epoch,
+            end: Cell::new(None),
+            effect: Cell::new(None),
+        }
+    }
+
+    /// The remaining identify time, in seconds
+    pub fn identify_time(&self) -> u16 {
+        self.end
+            .get()
+            .map(|end| {
+                let remaining = end.saturating_sub((self.epoch)());
+                let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+
+                secs.min(u16::MAX as _) as u16
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn is_identifying(&self) -> bool {
+        self.identify_time() > 0
+    }
+
+    /// Start identifying for `secs` seconds, or stop identifying if `secs` is 0
+    pub fn identify(&self, secs: u16) {
+        let identifying = self.end.get().is_some();
+
+        if secs > 0 {
+            self.end
+                .set(Some((self.epoch)() + Duration::from_secs(secs as _)));
+
+            if !identifying {
+                self.handler.on_identify_start();
+            }
+        } else {
+            self.end.set(None);
+
+            if identifying {
+                self.handler.on_identify_stop();
+            }
+        }
+
+        self.data_ver.changed();
+    }
+
+    /// The effect which is running, if any
+    pub fn effect(&self) -> Option<EffectId> {
+        self.effect.get().map(|(effect, _)| effect)
+    }
+
+    /// Whether the running effect terminates once its current sequence completes
+    pub fn is_finishing(&self) -> bool {
+        matches!(self.effect.get(), Some((_, true)))
+    }
+
+    /// To be called by the integrator whenever the running effect completed its sequence.
+    ///
+    /// The effect repeats its sequence, unless it was asked to finish, in which case it terminates.
+    pub fn effect_completed(&self) {
+        if self.is_finishing() {
+            self.effect.set(None);
+        }
+    }
+
+    /// Stop identifying, if the identify time has counted down to zero
+    pub fn tick(&self) {
+        if let Some(end) = self.end.get() {
+            if (self.epoch)() >= end {
+                self.end.set(None);
+                self.handler.on_identify_stop();
+                self.data_ver.changed();
+            }
+        }
+    }
+
+    /// Count the identify time down, for as long as the cluster is in use
+    pub async fn run(&self) -> Result<(), Error> {
+        loop {
+            Timer::after(embassy_time::Duration::from_secs(1)).await;
+
+            self.tick();
+        }
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::IdentifyTime(codec) => codec.encode(writer, self.identify_time()),
+                    Attributes::IdentifyType(codec) => {
+                        codec.encode(writer, self.identify_type as _)
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        let data = data.with_dataver(self.data_ver.get())?;
+
+        match attr.attr_id.try_into()? {
+            Attributes::IdentifyTime(codec) => self.identify(codec.decode(data)?),
+            _ => Err(ErrorCode::UnsupportedAccess)?,
+        }
+
+        Ok(())
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        _encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::Identify => {
+                cmd_enter!("Identify");
+                let req = IdentifyReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+                self.identify(req.identify_time);
+            }
+            Commands::TriggerEffect => {
+                cmd_enter!("TriggerEffect");
+                let req = TriggerEffectReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+                self.trigger_effect(req.effect_id, req.effect_variant)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn trigger_effect(&self, effect_id: u8, variant: u8) -> Result<(), Error> {
+        let effect = EffectId::from_repr(effect_id).ok_or(ErrorCode::ConstraintError)?;
+
+        match effect {
+            EffectId::FinishEffect => {
+                if let Some((running, _)) = self.effect.get() {
+                    self.effect.set(Some((running, true)));
+                }
+            }
+            EffectId::StopEffect => self.effect.set(None),
+            _ => self.effect.set(Some((effect, false))),
+        }
+
+        self.handler.on_effect(effect, variant);
+
+        Ok(())
+    }
+}
+
+impl<'a> Handler for IdentifyCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        IdentifyCluster::read(self, attr, encoder)
+    }
+
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        IdentifyCluster::write(self, attr, data)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        IdentifyCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for IdentifyCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for IdentifyCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, sync::atomic::Ordering};
+
+    use crate::{test_support::mock_epoch, utils::rand::dummy_rand};
+
+    use super::{EffectId, IdentifyCluster, IdentifyHandler, IdentifyType};
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Start,
+        Stop,
+        Effect(EffectId),
+    }
+
+    #[derive(Default)]
+    struct MockIndicator {
+        calls: RefCell<heapless::Vec<Call, 8>>,
+    }
+
+    impl IdentifyHandler for MockIndicator {
+        fn on_identify_start(&self) {
+            self.calls.borrow_mut().push(Call::Start).unwrap();
+        }
+
+        fn on_identify_stop(&self) {
+            self.calls.borrow_mut().push(Call::Stop).unwrap();
+        }
+
+        fn on_effect(&self, effect: EffectId, _variant: u8) {
+            self.calls.borrow_mut().push(Call::Effect(effect)).unwrap();
+        }
+    }
+
+    #[test]
+    /// Identifying for 3 seconds counts the identify time down to 0
+    fn identify_counts_down() {
+        mock_epoch!(MOCK_NOW_SECS, mock_epoch, secs);
+
+        MOCK_NOW_SECS.store(1000, Ordering::SeqCst);
+
+        let indicator = MockIndicator::default();
+        let cluster = IdentifyCluster::new(
+            IdentifyType::LightOutput,
+            &indicator,
+            mock_epoch,
+            dummy_rand,
+        );
+
+        cluster.identify(3);
+        assert_eq!(cluster.identify_time(), 3);
+        assert_eq!(indicator.calls.borrow().as_slice(), &[Call::Start]);
+
+        MOCK_NOW_SECS.store(1001, Ordering::SeqCst);
+        cluster.tick();
+        assert_eq!(cluster.identify_time(), 2);
+        assert!(cluster.is_identifying());
+
+        MOCK_NOW_SECS.store(1003, Ordering::SeqCst);
+        cluster.tick();
+        assert_eq!(cluster.identify_time(), 0);
+        assert!(!cluster.is_identifying());
+        assert_eq!(
+            indicator.calls.borrow().as_slice(),
+            &[Call::Start, Call::Stop]
+        );
+
+        // Already stopped
+        MOCK_NOW_SECS.store(1004, Ordering::SeqCst);
+        cluster.tick();
+        assert_eq!(indicator.calls.borrow().len(), 2);
+    }
+
+    #[test]
+    /// Extending the identify time does not restart identifying, and setting it to 0
+    /// stops it right away
+    fn identify_extend_and_cancel() {
+        mock_epoch!(MOCK_NOW_SECS, mock_epoch, secs);
+
+        MOCK_NOW_SECS.store(2000, Ordering::SeqCst);
+
+        let indicator = MockIndicator::default();
+        let cluster = IdentifyCluster::new(
+            IdentifyType::VisibleIndicator,
+            &indicator,
+            mock_epoch,
+            dummy_rand,
+        );
+
+        cluster.identify(3);
+        cluster.identify(10);
+        assert_eq!(cluster.identify_time(), 10);
+
+        cluster.identify(0);
+        assert_eq!(cluster.identify_time(), 0);
+        assert_eq!(
+            indicator.calls.borrow().as_slice(),
+            &[Call::Start, Call::Stop]
+        );
+    }
+
+    #[test]
+    /// Effects go to the handler, and unknown effects are rejected
+    fn trigger_effect() {
+        mock_epoch!(MOCK_NOW_SECS, mock_epoch, secs);
+
+        let indicator = MockIndicator::default();
+        let cluster = IdentifyCluster::new(
+            IdentifyType::LightOutput,
+            &indicator,
+            mock_epoch,
+            dummy_rand,
+        );
+
+        cluster.trigger_effect(EffectId::Breathe as _, 0).unwrap();
+        assert_eq!(cluster.effect(), Some(EffectId::Breathe));
+
+        // Unless asked to finish, the effect repeats its sequence
+        cluster.effect_completed();
+        assert_eq!(cluster.effect(), Some(EffectId::Breathe));
+
+        // Finishing lets the current sequence complete, rather than stopping the effect
+        cluster
+            .trigger_effect(EffectId::FinishEffect as _, 0)
+            .unwrap();
+        assert_eq!(cluster.effect(), Some(EffectId::Breathe));
+        assert!(cluster.is_finishing());
+
+        cluster.effect_completed();
+        assert_eq!(cluster.effect(), None);
+        assert!(!cluster.is_finishing());
+
+        // Stopping terminates the effect right away
+        cluster.trigger_effect(EffectId::Blink as _, 0).unwrap();
+        cluster
+            .trigger_effect(EffectId::StopEffect as _, 0)
+            .unwrap();
+        assert_eq!(cluster.effect(), None);
+
+        assert!(cluster.trigger_effect(0x10, 0).is_err());
+
+        assert_eq!(
+            indicator.calls.borrow().as_slice(),
+            &[
+                Call::Effect(EffectId::Breathe),
+                Call::Effect(EffectId::FinishEffect),
+                Call::Effect(EffectId::Blink),
+                Call::Effect(EffectId::StopEffect),
+            ]
+        );
+    }
+}
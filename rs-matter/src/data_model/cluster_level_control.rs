@@ -0,0 +1,631 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::{cell::Cell, convert::TryInto, time::Duration};
+
+use super::{cluster_on_off::OnOffCluster, objects::*};
+use crate::{
+    attribute_enum, cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    tlv::{FromTLV, Nullable, TLVElement},
+    transport::exchange::Exchange,
+    utils::{epoch::Epoch, rand::Rand},
+};
+use embassy_time::Timer;
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0008;
+
+/// The OnOff feature: the cluster is coupled with the OnOff cluster of its endpoint
+pub const FEATURE_ON_OFF: u32 = 0x01;
+
+/// The interval at which running transitions step the current level
+const TICK_MS: u64 = 100;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    CurrentLevel(AttrType<u8>) = 0x00,
+    RemainingTime(AttrType<u16>) = 0x01,
+    MinLevel(AttrType<u8>) = 0x02,
+    MaxLevel(AttrType<u8>) = 0x03,
+    OnLevel(AttrType<Nullable<u8>>) = 0x11,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    MoveToLevel = 0x00,
+    Move = 0x01,
+    Step = 0x02,
+    Stop = 0x03,
+    MoveToLevelWithOnOff = 0x04,
+    MoveWithOnOff = 0x05,
+    StepWithOnOff = 0x06,
+    StopWithOnOff = 0x07,
+}
+
+command_enum!(Commands);
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 5,
+    feature_map: FEATURE_ON_OFF,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::CurrentLevel as u16,
+            Access::RV,
+            Quality::SN,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::RemainingTime as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MinLevel as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MaxLevel as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::OnLevel as u16,
+            Access::RWVO,
+            Quality::NONE,
+        ),
+    ],
+    commands: &[
+        Commands::MoveToLevel as _,
+        Commands::Move as _,
+        Commands::Step as _,
+        Commands::Stop as _,
+        Commands::MoveToLevelWithOnOff as _,
+        Commands::MoveWithOnOff as _,
+        Commands::StepWithOnOff as _,
+        Commands::StopWithOnOff as _,
+    ],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+/// The direction of the Move and Step commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+#[repr(u8)]
+pub enum MoveMode {
+    Up = 0,
+    Down = 1,
+}
+
+#[derive(FromTLV)]
+struct MoveToLevelReq {
+    level: u8,
+    /// In tenths of a second
+    transition_time: Nullable<u16>,
+}
+
+#[derive(FromTLV)]
+struct MoveReq {
+    move_mode: u8,
+    /// In units per second
+    rate: Nullable<u8>,
+}
+
+#[derive(FromTLV)]
+struct StepReq {
+    step_mode: u8,
+    step_size: u8,
+    /// In tenths of a second
+    transition_time: Nullable<u16>,
+}
+
+/// The Level Handler Trait
+///
+/// Objects that implement this trait actuate the level of the device (dim a light,
+/// ...) on behalf of the cluster. Running transitions call it on every step.
+pub trait LevelHandler {
+    fn set_level(&self, level: u8);
+}
+
+#[derive(Clone, Copy)]
+struct Transition {
+    from: u8,
+    to: u8,
+    start: Duration,
+    duration: Duration,
+    with_on_off: bool,
+}
+
+impl Transition {
+    fn level_at(&self, now: Duration) -> u8 {
+        let elapsed = now.saturating_sub(self.start);
+
+        if elapsed >= self.duration {
+            self.to
+        } else {
+            let delta = self.to as i64 - self.from as i64;
+            let progress = elapsed.as_millis() as i64;
+
+            (self.from as i64 + delta * progress / self.duration.as_millis() as i64) as u8
+        }
+    }
+
+    fn remaining(&self, now: Duration) -> Duration {
+        (self.start + self.duration).saturating_sub(now)
+    }
+}
+
+pub struct LevelControlCluster<'a> {
+    data_ver: Dataver,
+    handler: &'a dyn LevelHandler,
+    on_off: Option<&'a OnOffCluster>,
+    min_level: u8,
+    max_level: u8,
+    epoch: Epoch,
+    current: Cell<u8>,
+    on_level: Cell<Option<u8>>,
+    transition: Cell<Option<Transition>>,
+}
+
+impl<'a> LevelControlCluster<'a> {
+    /// Create the cluster, with its level between `min_level` and `max_level`.
+    /// The `with-on/off` commands switch `on_off`, if given.
+    pub fn new(
+        handler: &'a dyn LevelHandler,
+        on_off: Option<&'a OnOffCluster>,
+        min_level: u8,
+        max_level: u8,
+        epoch: Epoch,
+        rand: Rand,
+    ) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            handler,
+            on_off,
+            min_level,
+            max_level,
+            epoch,
+            current: Cell::new(min_level),
+            on_level: Cell::new(None),
+            transition: Cell::new(None),
+        }
+    }
+
+    pub fn current_level(&self) -> u8 {
+        self.current.get()
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.get().is_some()
+    }
+
+    /// Move to `level` within `transition_time` tenths of a second, or right away
+    pub fn move_to_level(&self, level: u8, transition_time: Option<u16>, with_on_off: bool) {
+        let level = level.clamp(self.min_level, self.max_level);
+        let duration = Duration::from_millis(transition_time.unwrap_or(0) as u64 * 100);
+
+        self.start(level, duration, with_on_off);
+    }
+
+    /// Move up or down to the end of the range, at `rate` units per second, or right away
+    pub fn move_level(
+        &self,
+        mode: MoveMode,
+        rate: Option<u8>,
+        with_on_off: bool,
+    ) -> Result<(), Error> {
+        let level = match mode {
+            MoveMode::Up => self.max_level,
+            MoveMode::Down => self.min_level,
+        };
+
+        let duration = match rate {
+            Some(0) => Err(ErrorCode::InvalidCommand)?,
+            Some(rate) => {
+                let delta = (level as i16 - self.current.get() as i16).unsigned_abs();
+
+                Duration::from_millis(delta as u64 * 1000 / rate as u64)
+            }
+            None => Duration::ZERO,
+        };
+
+        self.start(level, duration, with_on_off);
+
+        Ok(())
+    }
+
+    /// Move up or down by `step_size`, within `transition_time` tenths of a second
+    pub fn step(
+        &self,
+        mode: MoveMode,
+        step_size: u8,
+        transition_time: Option<u16>,
+        with_on_off: bool,
+    ) {
+        let level = match mode {
+            MoveMode::Up => self.current.get().saturating_add(step_size),
+            MoveMode::Down => self.current.get().saturating_sub(step_size),
+        };
+
+        self.move_to_level(level, transition_time, with_on_off);
+    }
+
+    /// Halt the running transition, if any, at the level it has reached
+    pub fn stop(&self) {
+        self.tick();
+        self.transition.set(None);
+    }
+
+    /// Step the running transition to the level it should have by now
+    pub fn tick(&self) {
+        let Some(transition) = self.transition.get() else {
+            return;
+        };
+
+        let now = (self.epoch)();
+
+        self.set_current(transition.level_at(now));
+
+        if transition.remaining(now).is_zero() {
+            self.transition.set(None);
+
+            // Dimming all the way down with the with-on/off commands switches off
+            if transition.with_on_off && transition.to == self.min_level {
+                if let Some(on_off) = self.on_off {
+                    on_off.set(false);
+                }
+            }
+        }
+    }
+
+    /// Step the running transitions, for as long as the cluster is in use
+    pub async fn run(&self) -> Result<(), Error> {
+        loop {
+            Timer::after(embassy_time::Duration::from_millis(TICK_MS)).await;
+
+            self.tick();
+        }
+    }
+
+    fn start(&self, level: u8, duration: Duration, with_on_off: bool) {
+        // Moving above the minimum with the with-on/off commands switches on
+        if with_on_off && level > self.min_level {
+            if let Some(on_off) = self.on_off {
+                on_off.set(true);
+            }
+        }
+
+        self.transition.set(Some(Transition {
+            from: self.current.get(),
+            to: level,
+            start: (self.epoch)(),
+            duration,
+            with_on_off,
+        }));
+
+        self.tick();
+    }
+
+    fn set_current(&self, level: u8) {
+        if self.current.get() != level {
+            self.current.set(level);
+            self.handler.set_level(level);
+            self.data_ver.changed();
+        }
+    }
+
+    fn remaining_time(&self) -> u16 {
+        self.transition
+            .get()
+            .map(|transition| {
+                let remaining = transition.remaining((self.epoch)()).as_millis() / 100;
+
+                remaining.min(u16::MAX as _) as u16
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::CurrentLevel(codec) => codec.encode(writer, self.current.get()),
+                    Attributes::RemainingTime(codec) => codec.encode(writer, self.remaining_time()),
+                    Attributes::MinLevel(codec) => codec.encode(writer, self.min_level),
+                    Attributes::MaxLevel(codec) => codec.encode(writer, self.max_level),
+                    Attributes::OnLevel(codec) => codec.encode(
+                        writer,
+                        match self.on_level.get() {
+                            Some(level) => Nullable::NotNull(level),
+                            None => Nullable::Null,
+                        },
+                    ),
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        let data = data.with_dataver(self.data_ver.get())?;
+
+        match attr.attr_id.try_into()? {
+            Attributes::OnLevel(codec) => {
+                let level = codec.decode(data)?.unwrap_notnull();
+                if level
+                    .map(|l| l < self.min_level || l > self.max_level)
+                    .unwrap_or(false)
+                {
+                    Err(ErrorCode::ConstraintError)?;
+                }
+
+                self.on_level.set(level);
+            }
+            _ => Err(ErrorCode::UnsupportedAccess)?,
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        _encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::MoveToLevel => {
+                cmd_enter!("MoveToLevel");
+                self.handle_command_movetolevel(data, false)?;
+            }
+            Commands::Move => {
+                cmd_enter!("Move");
+                self.handle_command_move(data, false)?;
+            }
+            Commands::Step => {
+                cmd_enter!("Step");
+                self.handle_command_step(data, false)?;
+            }
+            Commands::Stop => {
+                cmd_enter!("Stop");
+                self.stop();
+            }
+            Commands::MoveToLevelWithOnOff => {
+                cmd_enter!("MoveToLevelWithOnOff");
+                self.handle_command_movetolevel(data, true)?;
+            }
+            Commands::MoveWithOnOff => {
+                cmd_enter!("MoveWithOnOff");
+                self.handle_command_move(data, true)?;
+            }
+            Commands::StepWithOnOff => {
+                cmd_enter!("StepWithOnOff");
+                self.handle_command_step(data, true)?;
+            }
+            Commands::StopWithOnOff => {
+                cmd_enter!("StopWithOnOff");
+                self.stop();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_command_movetolevel(
+        &self,
+        data: &TLVElement,
+        with_on_off: bool,
+    ) -> Result<(), Error> {
+        let req = MoveToLevelReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        self.move_to_level(req.level, req.transition_time.unwrap_notnull(), with_on_off);
+
+        Ok(())
+    }
+
+    fn handle_command_move(&self, data: &TLVElement, with_on_off: bool) -> Result<(), Error> {
+        let req = MoveReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        let mode = MoveMode::from_repr(req.move_mode).ok_or(ErrorCode::InvalidCommand)?;
+
+        self.move_level(mode, req.rate.unwrap_notnull(), with_on_off)
+    }
+
+    fn handle_command_step(&self, data: &TLVElement, with_on_off: bool) -> Result<(), Error> {
+        let req = StepReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        let mode = MoveMode::from_repr(req.step_mode).ok_or(ErrorCode::InvalidCommand)?;
+
+        self.step(
+            mode,
+            req.step_size,
+            req.transition_time.unwrap_notnull(),
+            with_on_off,
+        );
+
+        Ok(())
+    }
+}
+
+impl<'a> Handler for LevelControlCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        LevelControlCluster::read(self, attr, encoder)
+    }
+
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        LevelControlCluster::write(self, attr, data)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        LevelControlCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for LevelControlCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for LevelControlCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::Cell, sync::atomic::Ordering};
+
+    use crate::{
+        data_model::cluster_on_off::OnOffCluster, test_support::mock_epoch, utils::rand::dummy_rand,
+    };
+
+    use super::{LevelControlCluster, LevelHandler, MoveMode};
+
+    #[derive(Default)]
+    struct MockDimmer {
+        level: Cell<u8>,
+        steps: Cell<usize>,
+    }
+
+    impl LevelHandler for MockDimmer {
+        fn set_level(&self, level: u8) {
+            self.level.set(level);
+            self.steps.set(self.steps.get() + 1);
+        }
+    }
+
+    #[test]
+    /// Levels outside of the range of the cluster are clamped
+    fn move_to_level_clamps() {
+        mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+        let dimmer = MockDimmer::default();
+        let cluster = LevelControlCluster::new(&dimmer, None, 10, 200, mock_epoch, dummy_rand);
+
+        cluster.move_to_level(250, None, false);
+        assert_eq!(cluster.current_level(), 200);
+        assert_eq!(dimmer.level.get(), 200);
+
+        cluster.move_to_level(0, None, false);
+        assert_eq!(cluster.current_level(), 10);
+
+        cluster.step(MoveMode::Up, 255, None, false);
+        assert_eq!(cluster.current_level(), 200);
+        assert!(!cluster.is_transitioning());
+    }
+
+    #[test]
+    /// A transition steps the level over time, and reaches its target once its time
+    /// has elapsed
+    fn transition_reaches_target() {
+        mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+        MOCK_NOW_MS.store(10_000, Ordering::SeqCst);
+
+        let dimmer = MockDimmer::default();
+        let cluster = LevelControlCluster::new(&dimmer, None, 0, 254, mock_epoch, dummy_rand);
+
+        // 2 seconds from 0 to 200
+        cluster.move_to_level(200, Some(20), false);
+        assert_eq!(cluster.current_level(), 0);
+        assert_eq!(cluster.remaining_time(), 20);
+
+        MOCK_NOW_MS.store(11_000, Ordering::SeqCst);
+        cluster.tick();
+        assert_eq!(cluster.current_level(), 100);
+        assert_eq!(cluster.remaining_time(), 10);
+
+        MOCK_NOW_MS.store(12_500, Ordering::SeqCst);
+        cluster.tick();
+        assert_eq!(cluster.current_level(), 200);
+        assert_eq!(dimmer.level.get(), 200);
+        assert!(!cluster.is_transitioning());
+        assert_eq!(dimmer.steps.get(), 2);
+    }
+
+    #[test]
+    /// Stop halts a running Move at the level it has reached
+    fn stop_halts_transition() {
+        mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+        MOCK_NOW_MS.store(20_000, Ordering::SeqCst);
+
+        let dimmer = MockDimmer::default();
+        let cluster = LevelControlCluster::new(&dimmer, None, 0, 254, mock_epoch, dummy_rand);
+
+        // 10 units per second up
+        cluster.move_level(MoveMode::Up, Some(10), false).unwrap();
+        assert!(cluster.move_level(MoveMode::Up, Some(0), false).is_err());
+
+        MOCK_NOW_MS.store(23_000, Ordering::SeqCst);
+        cluster.stop();
+        assert_eq!(cluster.current_level(), 30);
+        assert!(!cluster.is_transitioning());
+
+        MOCK_NOW_MS.store(30_000, Ordering::SeqCst);
+        cluster.tick();
+        assert_eq!(cluster.current_level(), 30);
+    }
+
+    #[test]
+    /// The with-on/off commands switch on when moving above the minimum, and off
+    /// when reaching it
+    fn with_on_off() {
+        mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+        MOCK_NOW_MS.store(40_000, Ordering::SeqCst);
+
+        let dimmer = MockDimmer::default();
+        let on_off = OnOffCluster::new(dummy_rand);
+        let cluster =
+            LevelControlCluster::new(&dimmer, Some(&on_off), 1, 254, mock_epoch, dummy_rand);
+
+        // Without on/off, the OnOff cluster is left alone
+        cluster.move_to_level(50, None, false);
+        assert!(!on_off.get());
+
+        cluster.move_to_level(100, Some(10), true);
+        assert!(on_off.get());
+
+        cluster.move_to_level(1, Some(10), true);
+        assert!(on_off.get());
+
+        MOCK_NOW_MS.store(41_000, Ordering::SeqCst);
+        cluster.tick();
+        assert_eq!(cluster.current_level(), 1);
+        assert!(!on_off.get());
+    }
+}
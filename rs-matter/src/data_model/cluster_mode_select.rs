@@ -0,0 +1,367 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::{cell::Cell, convert::TryInto};
+
+use super::objects::*;
+use crate::{
+    attribute_enum, cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    tlv::{FromTLV, Nullable, TLVElement, TLVWriter, TagType, ToTLV},
+    transport::exchange::Exchange,
+    utils::rand::Rand,
+};
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0050;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    Description(AttrUtfType) = 0x00,
+    StandardNamespace(AttrType<Nullable<u16>>) = 0x01,
+    SupportedModes(()) = 0x02,
+    CurrentMode(AttrType<u8>) = 0x03,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    ChangeToMode = 0x00,
+}
+
+command_enum!(Commands);
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: 0,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::Description as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::StandardNamespace as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::SupportedModes as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::CurrentMode as u16,
+            Access::RV,
+            Quality::SN,
+        ),
+    ],
+    commands: &[Commands::ChangeToMode as _],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+/// The cluster-specific status codes of the commands
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StatusCode {
+    UnsupportedMode = 0x01,
+}
+
+/// A semantic tag of a mode, in the standard namespace of the cluster unless a
+/// manufacturer code is given
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ModeTag {
+    pub mfg_code: u16,
+    pub value: u16,
+}
+
+impl ToTLV for ModeTag {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag_type: TagType) -> Result<(), Error> {
+        tw.start_struct(tag_type)?;
+        tw.u16(TagType::Context(0), self.mfg_code)?;
+        tw.u16(TagType::Context(1), self.value)?;
+        tw.end_container()
+    }
+}
+
+/// A mode the device supports, as listed in the SupportedModes attribute
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ModeOption<'a> {
+    pub label: &'a str,
+    pub mode: u8,
+    pub semantic_tags: &'a [ModeTag],
+}
+
+impl<'a> ToTLV for ModeOption<'a> {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag_type: TagType) -> Result<(), Error> {
+        tw.start_struct(tag_type)?;
+        tw.utf8(TagType::Context(0), self.label.as_bytes())?;
+        tw.u8(TagType::Context(1), self.mode)?;
+        tw.start_array(TagType::Context(2))?;
+        for semantic_tag in self.semantic_tags {
+            semantic_tag.to_tlv(tw, TagType::Anonymous)?;
+        }
+        tw.end_container()?;
+        tw.end_container()
+    }
+}
+
+#[derive(FromTLV, ToTLV)]
+pub struct ChangeToModeReq {
+    pub new_mode: u8,
+}
+
+/// The Mode Handler Trait
+///
+/// Objects that implement this trait actuate the mode of the device on behalf of
+/// the cluster, which only ever asks for one of its supported modes.
+pub trait ModeHandler {
+    fn set_mode(&self, mode: u8);
+}
+
+pub struct ModeSelectCluster<'a> {
+    data_ver: Dataver,
+    handler: &'a dyn ModeHandler,
+    description: &'a str,
+    standard_namespace: Option<u16>,
+    supported_modes: &'a [ModeOption<'a>],
+    current: Cell<u8>,
+}
+
+impl<'a> ModeSelectCluster<'a> {
+    /// Create the cluster, in the first of `supported_modes`
+    ///
+    /// There has to be at least one supported mode, which the cluster can start in.
+    pub fn new(
+        handler: &'a dyn ModeHandler,
+        description: &'a str,
+        standard_namespace: Option<u16>,
+        supported_modes: &'a [ModeOption<'a>],
+        rand: Rand,
+    ) -> Result<Self, Error> {
+        let first = supported_modes.first().ok_or(ErrorCode::InvalidData)?;
+
+        Ok(Self {
+            data_ver: Dataver::new(rand),
+            handler,
+            description,
+            standard_namespace,
+            supported_modes,
+            current: Cell::new(first.mode),
+        })
+    }
+
+    pub fn current_mode(&self) -> u8 {
+        self.current.get()
+    }
+
+    /// Change to `mode`, which has to be one of the supported modes
+    ///
+    /// Changing to the current mode does nothing.
+    pub fn change_to_mode(&self, mode: u8) -> Result<(), Error> {
+        if !self
+            .supported_modes
+            .iter()
+            .any(|option| option.mode == mode)
+        {
+            Err(ErrorCode::ClusterStatus(StatusCode::UnsupportedMode as _))?;
+        }
+
+        if self.current.get() != mode {
+            self.current.set(mode);
+            self.handler.set_mode(mode);
+            self.data_ver.changed();
+        }
+
+        Ok(())
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::Description(codec) => codec.encode(writer, self.description),
+                    Attributes::StandardNamespace(codec) => codec.encode(
+                        writer,
+                        self.standard_namespace
+                            .map_or(Nullable::Null, Nullable::NotNull),
+                    ),
+                    Attributes::SupportedModes(_) => writer.set_list(self.supported_modes),
+                    Attributes::CurrentMode(codec) => codec.encode(writer, self.current.get()),
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        _encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::ChangeToMode => {
+                cmd_enter!("ChangeToMode");
+                let req = ChangeToModeReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+                self.change_to_mode(req.new_mode)
+            }
+        }
+    }
+}
+
+impl<'a> Handler for ModeSelectCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        ModeSelectCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        ModeSelectCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for ModeSelectCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for ModeSelectCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use crate::{error::ErrorCode, utils::rand::dummy_rand};
+
+    use super::{ModeHandler, ModeOption, ModeSelectCluster, ModeTag, StatusCode};
+
+    const MODES: &[ModeOption<'static>] = &[
+        ModeOption {
+            label: "Normal",
+            mode: 0,
+            semantic_tags: &[ModeTag {
+                mfg_code: 0,
+                value: 0,
+            }],
+        },
+        ModeOption {
+            label: "Eco",
+            mode: 4,
+            semantic_tags: &[
+                ModeTag {
+                    mfg_code: 0,
+                    value: 1,
+                },
+                ModeTag {
+                    mfg_code: 0xfff1,
+                    value: 0x8000,
+                },
+            ],
+        },
+    ];
+
+    #[derive(Default)]
+    struct MockActuator {
+        mode: Cell<Option<u8>>,
+        changes: Cell<usize>,
+    }
+
+    impl ModeHandler for MockActuator {
+        fn set_mode(&self, mode: u8) {
+            self.mode.set(Some(mode));
+            self.changes.set(self.changes.get() + 1);
+        }
+    }
+
+    #[test]
+    fn change_to_supported_mode() {
+        let actuator = MockActuator::default();
+        let cluster = ModeSelectCluster::new(&actuator, "Fan", None, MODES, dummy_rand).unwrap();
+        assert_eq!(cluster.current_mode(), 0);
+
+        let dataver = cluster.data_ver.get();
+
+        cluster.change_to_mode(4).unwrap();
+        assert_eq!(cluster.current_mode(), 4);
+        assert_eq!(actuator.mode.get(), Some(4));
+        assert_ne!(cluster.data_ver.get(), dataver);
+    }
+
+    #[test]
+    /// Modes which are not listed are rejected, and the device is left alone
+    fn change_to_unknown_mode() {
+        let actuator = MockActuator::default();
+        let cluster = ModeSelectCluster::new(&actuator, "Fan", None, MODES, dummy_rand).unwrap();
+
+        assert_eq!(
+            cluster.change_to_mode(1).map_err(|e| e.code()),
+            Err(ErrorCode::ClusterStatus(StatusCode::UnsupportedMode as _))
+        );
+        assert_eq!(cluster.current_mode(), 0);
+        assert_eq!(actuator.mode.get(), None);
+    }
+
+    #[test]
+    /// Changing to the current mode succeeds without actuating the device
+    fn change_to_current_mode() {
+        let actuator = MockActuator::default();
+        let cluster = ModeSelectCluster::new(&actuator, "Fan", None, MODES, dummy_rand).unwrap();
+
+        cluster.change_to_mode(4).unwrap();
+        let dataver = cluster.data_ver.get();
+
+        cluster.change_to_mode(4).unwrap();
+        assert_eq!(cluster.current_mode(), 4);
+        assert_eq!(actuator.changes.get(), 1);
+        assert_eq!(cluster.data_ver.get(), dataver);
+    }
+
+    #[test]
+    /// Without a supported mode, there is no mode to start in
+    fn no_supported_modes() {
+        let actuator = MockActuator::default();
+
+        assert_eq!(
+            ModeSelectCluster::new(&actuator, "Fan", None, &[], dummy_rand)
+                .err()
+                .map(|e| e.code()),
+            Some(ErrorCode::InvalidData)
+        );
+    }
+}
@@ -15,22 +15,37 @@
  *    limitations under the License.
  */
 
-use core::{cell::Cell, convert::TryInto};
+use core::{cell::Cell, convert::TryInto, time::Duration};
 
 use super::objects::*;
 use crate::{
-    attribute_enum, cmd_enter, command_enum, error::Error, tlv::TLVElement,
-    transport::exchange::Exchange, utils::rand::Rand,
+    attribute_enum, cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    tlv::{FromTLV, TLVElement},
+    transport::exchange::Exchange,
+    utils::{epoch::Epoch, rand::Rand},
 };
+use embassy_time::Timer;
 use log::info;
 use strum::{EnumDiscriminants, FromRepr};
 
 pub const ID: u32 = 0x0006;
 
+/// The Lighting feature
+pub const FEATURE_LIGHTING: u32 = 0x01;
+
+/// The AcceptOnlyWhenOn bit of the OnOffControl field of the OnWithTimedOff command
+const ACCEPT_ONLY_WHEN_ON: u8 = 0x01;
+
+/// The unit of the OnTime and OffWaitTime attributes
+const TENTH_SEC: Duration = Duration::from_millis(100);
+
 #[derive(FromRepr, EnumDiscriminants)]
 #[repr(u16)]
 pub enum Attributes {
     OnOff(AttrType<bool>) = 0x0,
+    OnTime(AttrType<u16>) = 0x4001,
+    OffWaitTime(AttrType<u16>) = 0x4002,
 }
 
 attribute_enum!(Attributes);
@@ -41,12 +56,14 @@ pub enum Commands {
     Off = 0x0,
     On = 0x01,
     Toggle = 0x02,
+    OnWithTimedOff = 0x42,
 }
 
 command_enum!(Commands);
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID as _,
+    revision: 4,
     feature_map: 0,
     attributes: &[
         FEATURE_MAP,
@@ -62,11 +79,73 @@ pub const CLUSTER: Cluster<'static> = Cluster {
         CommandsDiscriminants::On as _,
         CommandsDiscriminants::Toggle as _,
     ],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
 };
 
+/// The metadata of the cluster, with the Lighting feature, for handlers created
+/// with `OnOffCluster::new_lighting`
+pub const LIGHTING_CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 4,
+    feature_map: FEATURE_LIGHTING,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::OnOff as u16,
+            Access::RV,
+            Quality::SN,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::OnTime as u16,
+            Access::RWVO,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::OffWaitTime as u16,
+            Access::RWVO,
+            Quality::NONE,
+        ),
+    ],
+    commands: &[
+        CommandsDiscriminants::Off as _,
+        CommandsDiscriminants::On as _,
+        CommandsDiscriminants::Toggle as _,
+        CommandsDiscriminants::OnWithTimedOff as _,
+    ],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+#[derive(FromTLV)]
+struct OnWithTimedOffReq {
+    on_off_control: u8,
+    on_time: u16,
+    off_wait_time: u16,
+}
+
+/// The timed-off state of the Lighting feature, in tenths of a second
+struct Lighting {
+    epoch: Epoch,
+    /// The time the light stays on, before turning off
+    on_time: Cell<u16>,
+    /// The time, once off, during which OnWithTimedOff does not turn the light on
+    off_wait_time: Cell<u16>,
+    /// The time up to which the countdowns are accounted for
+    last_tick: Cell<Duration>,
+}
+
 pub struct OnOffCluster {
     data_ver: Dataver,
     on: Cell<bool>,
+    lighting: Option<Lighting>,
 }
 
 impl OnOffCluster {
@@ -74,9 +153,37 @@ impl OnOffCluster {
         Self {
             data_ver: Dataver::new(rand),
             on: Cell::new(false),
+            lighting: None,
         }
     }
 
+    /// Create the cluster with the Lighting feature, whose timed-off countdowns
+    /// follow `epoch`
+    pub fn new_lighting(epoch: Epoch, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            on: Cell::new(false),
+            lighting: Some(Lighting {
+                epoch,
+                on_time: Cell::new(0),
+                off_wait_time: Cell::new(0),
+                last_tick: Cell::new(epoch()),
+            }),
+        }
+    }
+
+    pub fn cluster(&self) -> &'static Cluster<'static> {
+        if self.lighting.is_some() {
+            &LIGHTING_CLUSTER
+        } else {
+            &CLUSTER
+        }
+    }
+
+    pub fn get(&self) -> bool {
+        self.on.get()
+    }
+
     pub fn set(&self, on: bool) {
         if self.on.get() != on {
             self.on.set(on);
@@ -84,13 +191,130 @@ impl OnOffCluster {
         }
     }
 
+    /// The remaining time the light stays on, in tenths of a second
+    pub fn on_time(&self) -> u16 {
+        self.tick();
+
+        self.lighting.as_ref().map(|l| l.on_time.get()).unwrap_or(0)
+    }
+
+    /// The remaining time during which the light is guarded off, in tenths of a second
+    pub fn off_wait_time(&self) -> u16 {
+        self.tick();
+
+        self.lighting
+            .as_ref()
+            .map(|l| l.off_wait_time.get())
+            .unwrap_or(0)
+    }
+
+    /// Turn on, and cancel a running timed-off
+    pub fn on(&self) {
+        self.tick();
+
+        if let Some(lighting) = &self.lighting {
+            lighting.on_time.set(0);
+            lighting.off_wait_time.set(0);
+        }
+
+        self.set(true);
+    }
+
+    /// Turn off; the off-wait time, if any, starts counting down
+    pub fn off(&self) {
+        self.tick();
+
+        if let Some(lighting) = &self.lighting {
+            lighting.on_time.set(0);
+        }
+
+        self.set(false);
+    }
+
+    /// Turn on for `on_time` tenths of a second, then turn off and guard the light
+    /// off for `off_wait_time` tenths of a second
+    pub fn on_with_timed_off(&self, on_off_control: u8, on_time: u16, off_wait_time: u16) {
+        self.tick();
+
+        let Some(lighting) = &self.lighting else {
+            return;
+        };
+
+        if on_off_control & ACCEPT_ONLY_WHEN_ON != 0 && !self.on.get() {
+            return;
+        }
+
+        if lighting.off_wait_time.get() > 0 && !self.on.get() {
+            // Guarded off: only shorten the guard
+            lighting
+                .off_wait_time
+                .set(lighting.off_wait_time.get().min(off_wait_time));
+        } else {
+            lighting.on_time.set(lighting.on_time.get().max(on_time));
+            lighting.off_wait_time.set(off_wait_time);
+
+            self.set(true);
+        }
+    }
+
+    /// Count the timed-off state down up to now, turning off once `OnTime` reaches zero
+    pub fn tick(&self) {
+        let Some(lighting) = &self.lighting else {
+            return;
+        };
+
+        let elapsed = (lighting.epoch)().saturating_sub(lighting.last_tick.get());
+        let tenths = (elapsed.as_millis() / TENTH_SEC.as_millis()) as u32;
+
+        lighting
+            .last_tick
+            .set(lighting.last_tick.get() + TENTH_SEC * tenths);
+
+        let tenths = tenths.min(u16::MAX as _) as u16;
+
+        if self.on.get() {
+            if lighting.on_time.get() > 0 {
+                lighting
+                    .on_time
+                    .set(lighting.on_time.get().saturating_sub(tenths));
+
+                if lighting.on_time.get() == 0 {
+                    lighting.off_wait_time.set(0);
+                    self.set(false);
+                }
+            }
+        } else {
+            lighting
+                .off_wait_time
+                .set(lighting.off_wait_time.get().saturating_sub(tenths));
+        }
+    }
+
+    /// Count the timed-off state down, for as long as the cluster is in use
+    pub async fn run(&self) -> Result<(), Error> {
+        loop {
+            Timer::after(embassy_time::Duration::from_millis(
+                TENTH_SEC.as_millis() as _
+            ))
+            .await;
+
+            self.tick();
+        }
+    }
+
     pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
         if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
             if attr.is_system() {
-                CLUSTER.read(attr.attr_id, writer)
+                self.cluster().read(attr.attr_id, writer)
             } else {
                 match attr.attr_id.try_into()? {
                     Attributes::OnOff(codec) => codec.encode(writer, self.on.get()),
+                    Attributes::OnTime(codec) => {
+                        codec.encode(writer, self.lighting()?.on_time.get())
+                    }
+                    Attributes::OffWaitTime(codec) => {
+                        codec.encode(writer, self.lighting()?.off_wait_time.get())
+                    }
                 }
             }
         } else {
@@ -101,8 +325,14 @@ impl OnOffCluster {
     pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
         let data = data.with_dataver(self.data_ver.get())?;
 
+        self.tick();
+
         match attr.attr_id.try_into()? {
             Attributes::OnOff(codec) => self.set(codec.decode(data)?),
+            Attributes::OnTime(codec) => self.lighting()?.on_time.set(codec.decode(data)?),
+            Attributes::OffWaitTime(codec) => {
+                self.lighting()?.off_wait_time.set(codec.decode(data)?)
+            }
         }
 
         self.data_ver.changed();
@@ -114,21 +344,31 @@ impl OnOffCluster {
         &self,
         _exchange: &Exchange,
         cmd: &CmdDetails,
-        _data: &TLVElement,
+        data: &TLVElement,
         _encoder: CmdDataEncoder,
     ) -> Result<(), Error> {
         match cmd.cmd_id.try_into()? {
             Commands::Off => {
                 cmd_enter!("Off");
-                self.set(false);
+                self.off();
             }
             Commands::On => {
                 cmd_enter!("On");
-                self.set(true);
+                self.on();
             }
             Commands::Toggle => {
                 cmd_enter!("Toggle");
-                self.set(!self.on.get());
+                if self.on.get() {
+                    self.off();
+                } else {
+                    self.on();
+                }
+            }
+            Commands::OnWithTimedOff => {
+                cmd_enter!("OnWithTimedOff");
+                let req = OnWithTimedOffReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+                self.on_with_timed_off(req.on_off_control, req.on_time, req.off_wait_time);
             }
         }
 
@@ -136,6 +376,12 @@ impl OnOffCluster {
 
         Ok(())
     }
+
+    fn lighting(&self) -> Result<&Lighting, Error> {
+        self.lighting
+            .as_ref()
+            .ok_or_else(|| ErrorCode::AttributeNotFound.into())
+    }
 }
 
 impl Handler for OnOffCluster {
@@ -166,3 +412,82 @@ impl ChangeNotifier<()> for OnOffCluster {
         self.data_ver.consume_change(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+
+    use crate::{test_support::mock_epoch, utils::rand::dummy_rand};
+
+    use super::{OnOffCluster, ACCEPT_ONLY_WHEN_ON};
+
+    #[test]
+    /// OnWithTimedOff turns the light on, counts OnTime down, and turns the light off
+    fn on_with_timed_off() {
+        mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+        MOCK_NOW_MS.store(10_000, Ordering::SeqCst);
+
+        let cluster = OnOffCluster::new_lighting(mock_epoch, dummy_rand);
+
+        cluster.on_with_timed_off(0, 30, 20);
+        assert!(cluster.get());
+        assert_eq!(cluster.on_time(), 30);
+
+        MOCK_NOW_MS.store(11_050, Ordering::SeqCst);
+        assert_eq!(cluster.on_time(), 20);
+        assert!(cluster.get());
+
+        MOCK_NOW_MS.store(13_000, Ordering::SeqCst);
+        cluster.tick();
+        assert!(!cluster.get());
+        assert_eq!(cluster.on_time(), 0);
+        assert_eq!(cluster.off_wait_time(), 0);
+    }
+
+    #[test]
+    /// On cancels a running timed-off, so the light stays on
+    fn on_cancels_timed_off() {
+        mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+        MOCK_NOW_MS.store(20_000, Ordering::SeqCst);
+
+        let cluster = OnOffCluster::new_lighting(mock_epoch, dummy_rand);
+
+        cluster.on_with_timed_off(0, 30, 0);
+        MOCK_NOW_MS.store(21_000, Ordering::SeqCst);
+        cluster.on();
+        assert_eq!(cluster.on_time(), 0);
+
+        MOCK_NOW_MS.store(30_000, Ordering::SeqCst);
+        cluster.tick();
+        assert!(cluster.get());
+    }
+
+    #[test]
+    /// Once turned off, the off-wait time guards the light against OnWithTimedOff
+    fn off_wait_time_guard() {
+        mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+        MOCK_NOW_MS.store(40_000, Ordering::SeqCst);
+
+        let cluster = OnOffCluster::new_lighting(mock_epoch, dummy_rand);
+
+        // Only accepted when on
+        cluster.on_with_timed_off(ACCEPT_ONLY_WHEN_ON, 30, 50);
+        assert!(!cluster.get());
+
+        cluster.on_with_timed_off(0, 30, 50);
+        cluster.off();
+        assert_eq!(cluster.off_wait_time(), 50);
+
+        MOCK_NOW_MS.store(42_000, Ordering::SeqCst);
+        cluster.on_with_timed_off(0, 30, 50);
+        assert!(!cluster.get());
+        assert_eq!(cluster.off_wait_time(), 30);
+
+        MOCK_NOW_MS.store(45_000, Ordering::SeqCst);
+        cluster.on_with_timed_off(0, 30, 50);
+        assert!(cluster.get());
+    }
+}
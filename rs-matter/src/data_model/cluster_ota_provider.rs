@@ -0,0 +1,538 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::{cell::RefCell, convert::TryInto, fmt::Write};
+
+use super::objects::*;
+use crate::{
+    bdx::BdxFiles,
+    cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    tlv::{FromTLV, OctetStr, TLVArray, TLVElement, TLVWriter, TagType, ToTLV, UtfStr},
+    transport::exchange::Exchange,
+    utils::rand::Rand,
+};
+use log::info;
+use strum::FromRepr;
+
+pub const ID: u32 = 0x0029;
+
+/// The maximum length of the BDX URI of an image
+pub const MAX_IMAGE_URI_LEN: usize = 256;
+/// The maximum length of the version string of an image
+pub const MAX_VERSION_STR_LEN: usize = 64;
+pub const UPDATE_TOKEN_LEN: usize = 8;
+/// The number of updates which can be pending at the same time; the oldest one is
+/// forgotten when a new one is offered
+pub const MAX_PENDING_UPDATES: usize = 4;
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    QueryImage = 0x00,
+    ApplyUpdateRequest = 0x02,
+    NotifyUpdateApplied = 0x04,
+}
+
+command_enum!(Commands);
+
+#[repr(u16)]
+pub enum RespCommands {
+    QueryImageResponse = 0x01,
+    ApplyUpdateResponse = 0x03,
+}
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: 0,
+    attributes: &[FEATURE_MAP, ATTRIBUTE_LIST],
+    commands: &[
+        Commands::QueryImage as _,
+        Commands::ApplyUpdateRequest as _,
+        Commands::NotifyUpdateApplied as _,
+    ],
+    generated_commands: &[
+        RespCommands::QueryImageResponse as _,
+        RespCommands::ApplyUpdateResponse as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[Commands::QueryImage as _, Commands::ApplyUpdateRequest as _],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromTLV, ToTLV)]
+pub enum QueryImageStatus {
+    UpdateAvailable = 0,
+    Busy = 1,
+    /// The requestor is up to date
+    NotAvailable = 2,
+    DownloadProtocolNotSupported = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromTLV, ToTLV)]
+pub enum ApplyUpdateAction {
+    Proceed = 0,
+    AwaitNextAction = 1,
+    Discontinue = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromTLV, ToTLV)]
+#[repr(u8)]
+pub enum DownloadProtocol {
+    BdxSynchronous = 0,
+    BdxAsynchronous = 1,
+    Https = 2,
+    VendorSpecific = 3,
+    #[tlvargs(unknown)]
+    Unknown(u32),
+}
+
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a")]
+pub struct QueryImageReq<'a> {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub software_version: u32,
+    pub protocols_supported: TLVArray<'a, DownloadProtocol>,
+    pub hardware_version: Option<u16>,
+    pub location: Option<UtfStr<'a>>,
+    pub requestor_can_consent: Option<bool>,
+    pub metadata_for_provider: Option<OctetStr<'a>>,
+}
+
+#[derive(Debug, PartialEq, FromTLV, ToTLV)]
+pub struct QueryImageResp {
+    pub status: QueryImageStatus,
+    pub delayed_action_time: Option<u32>,
+    pub image_uri: Option<heapless::String<MAX_IMAGE_URI_LEN>>,
+    pub software_version: Option<u32>,
+    pub software_version_str: Option<heapless::String<MAX_VERSION_STR_LEN>>,
+    pub update_token: Option<heapless::Vec<u8, UPDATE_TOKEN_LEN>>,
+    pub user_consent_needed: Option<bool>,
+}
+
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a")]
+pub struct ApplyUpdateReq<'a> {
+    pub update_token: OctetStr<'a>,
+    pub new_version: u32,
+}
+
+#[derive(Debug, PartialEq, FromTLV, ToTLV)]
+pub struct ApplyUpdateResp {
+    pub action: ApplyUpdateAction,
+    /// In seconds
+    pub delayed_action_time: u32,
+}
+
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a")]
+pub struct NotifyUpdateAppliedReq<'a> {
+    pub update_token: OctetStr<'a>,
+    pub software_version: u32,
+}
+
+/// A software image which the provider can offer
+pub struct ImageInfo<'a> {
+    pub version: u32,
+    pub version_str: &'a str,
+    /// The file designator with which the image is requested over BDX
+    pub file_designator: &'a str,
+}
+
+/// The Image Store Trait
+///
+/// Objects that implement this trait hold the software images offered by the provider.
+pub trait ImageStore {
+    /// The newest image for the product, if any
+    fn latest(&self, vendor_id: u16, product_id: u16) -> Option<ImageInfo<'_>>;
+
+    /// Read the bytes of the image at the provided offset, returning the number of
+    /// bytes read, which is 0 at the end of the image; the read of an unknown image
+    /// fails with `NotFound`
+    fn read(&self, file_designator: &str, offset: u64, buf: &mut [u8]) -> Result<usize, Error>;
+}
+
+struct PendingUpdate {
+    token: [u8; UPDATE_TOKEN_LEN],
+    version: u32,
+}
+
+pub struct OtaProviderCluster<'a> {
+    data_ver: Dataver,
+    store: &'a dyn ImageStore,
+    pending: RefCell<heapless::Vec<PendingUpdate, MAX_PENDING_UPDATES>>,
+    rand: Rand,
+}
+
+impl<'a> OtaProviderCluster<'a> {
+    pub fn new(store: &'a dyn ImageStore, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            store,
+            pending: RefCell::new(heapless::Vec::new()),
+            rand,
+        }
+    }
+
+    /// Offer the newest image to a requestor; the image is served by the provider with
+    /// the provided node id
+    pub fn query_image(
+        &self,
+        provider_node_id: u64,
+        req: &QueryImageReq,
+    ) -> Result<QueryImageResp, Error> {
+        let mut resp = QueryImageResp {
+            status: QueryImageStatus::NotAvailable,
+            delayed_action_time: None,
+            image_uri: None,
+            software_version: None,
+            software_version_str: None,
+            update_token: None,
+            user_consent_needed: None,
+        };
+
+        let Some(image) = self.store.latest(req.vendor_id, req.product_id) else {
+            return Ok(resp);
+        };
+
+        if image.version <= req.software_version {
+            return Ok(resp);
+        }
+
+        if !req
+            .protocols_supported
+            .iter()
+            .any(|protocol| protocol == DownloadProtocol::BdxSynchronous)
+        {
+            resp.status = QueryImageStatus::DownloadProtocolNotSupported;
+            return Ok(resp);
+        }
+
+        let mut uri = heapless::String::new();
+        write!(
+            uri,
+            "bdx://{:016X}/{}",
+            provider_node_id, image.file_designator
+        )
+        .map_err(|_| ErrorCode::NoSpace)?;
+
+        let mut version_str = heapless::String::new();
+        version_str
+            .push_str(image.version_str)
+            .map_err(|_| ErrorCode::NoSpace)?;
+
+        let mut token = [0; UPDATE_TOKEN_LEN];
+        (self.rand)(&mut token);
+
+        let mut pending = self.pending.borrow_mut();
+        if pending.is_full() {
+            pending.remove(0);
+        }
+        pending
+            .push(PendingUpdate {
+                token,
+                version: image.version,
+            })
+            .map_err(|_| ErrorCode::NoSpace)?;
+
+        resp.status = QueryImageStatus::UpdateAvailable;
+        resp.image_uri = Some(uri);
+        resp.software_version = Some(image.version);
+        resp.software_version_str = Some(version_str);
+        resp.update_token = Some(heapless::Vec::from_slice(&token).unwrap());
+
+        Ok(resp)
+    }
+
+    /// Allow the requestor to apply a downloaded image, if it was offered with the token
+    pub fn apply_update(&self, token: &[u8], new_version: u32) -> ApplyUpdateResp {
+        let known = self
+            .pending
+            .borrow()
+            .iter()
+            .any(|update| update.token == token && update.version == new_version);
+
+        ApplyUpdateResp {
+            action: if known {
+                ApplyUpdateAction::Proceed
+            } else {
+                ApplyUpdateAction::Discontinue
+            },
+            delayed_action_time: 0,
+        }
+    }
+
+    /// Forget an update, once the requestor reports it as applied
+    pub fn notify_update_applied(&self, token: &[u8], software_version: u32) -> Result<(), Error> {
+        let mut pending = self.pending.borrow_mut();
+
+        let index = pending
+            .iter()
+            .position(|update| update.token == token)
+            .ok_or(ErrorCode::NotFound)?;
+
+        info!(
+            "Update to version {} applied (offered version {})",
+            software_version, pending[index].version
+        );
+
+        pending.remove(index);
+
+        Ok(())
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                Err(ErrorCode::AttributeNotFound.into())
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::QueryImage => self.handle_command_queryimage(exchange, data, encoder),
+            Commands::ApplyUpdateRequest => self.handle_command_applyupdaterequest(data, encoder),
+            Commands::NotifyUpdateApplied => self.handle_command_notifyupdateapplied(data),
+        }
+    }
+
+    fn handle_command_queryimage(
+        &self,
+        exchange: &Exchange,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("QueryImage");
+
+        let req = QueryImageReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        // The image is served by our node on the fabric of the requestor
+        let provider_node_id = exchange.with_session(|sess| {
+            sess.get_local_fabric_idx()
+                .map(|_| sess.get_local_node_id())
+                .ok_or(ErrorCode::UnsupportedAccess.into())
+        })?;
+
+        let resp = self.query_image(provider_node_id, &req)?;
+
+        encoder
+            .with_command(RespCommands::QueryImageResponse as _)?
+            .set(resp)
+    }
+
+    fn handle_command_applyupdaterequest(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("ApplyUpdateRequest");
+
+        let req = ApplyUpdateReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let resp = self.apply_update(req.update_token.0, req.new_version);
+
+        encoder
+            .with_command(RespCommands::ApplyUpdateResponse as _)?
+            .set(resp)
+    }
+
+    fn handle_command_notifyupdateapplied(&self, data: &TLVElement) -> Result<(), Error> {
+        cmd_enter!("NotifyUpdateApplied");
+
+        let req = NotifyUpdateAppliedReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        self.notify_update_applied(req.update_token.0, req.software_version)
+    }
+}
+
+impl<'a> Handler for OtaProviderCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        OtaProviderCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        OtaProviderCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for OtaProviderCluster<'a> {}
+
+/// The images offered by the provider are downloaded by the requestors over BDX
+impl<'a> BdxFiles for OtaProviderCluster<'a> {
+    fn read(&self, file_designator: &[u8], offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let file_designator =
+            core::str::from_utf8(file_designator).map_err(|_| ErrorCode::NotFound)?;
+
+        self.store.read(file_designator, offset, buf)
+    }
+}
+
+impl<'a> ChangeNotifier<()> for OtaProviderCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{Error, ErrorCode},
+        test_support::seq_rand,
+        tlv::{TLVArray, TLVWriter, TagType, ToTLV},
+        utils::writebuf::WriteBuf,
+    };
+
+    use super::{
+        ApplyUpdateAction, DownloadProtocol, ImageInfo, ImageStore, OtaProviderCluster,
+        QueryImageReq, QueryImageStatus,
+    };
+
+    struct TestStore;
+
+    impl ImageStore for TestStore {
+        fn latest(&self, vendor_id: u16, product_id: u16) -> Option<ImageInfo<'_>> {
+            (vendor_id == 0xfff1 && product_id == 0x8000).then_some(ImageInfo {
+                version: 2,
+                version_str: "2.0",
+                file_designator: "fw-2.bin",
+            })
+        }
+
+        fn read(
+            &self,
+            _file_designator: &str,
+            _offset: u64,
+            _buf: &mut [u8],
+        ) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    fn query<'a>(software_version: u32, protocols: &'a [DownloadProtocol]) -> QueryImageReq<'a> {
+        QueryImageReq {
+            vendor_id: 0xfff1,
+            product_id: 0x8000,
+            software_version,
+            protocols_supported: TLVArray::new(protocols),
+            hardware_version: None,
+            location: None,
+            requestor_can_consent: None,
+            metadata_for_provider: None,
+        }
+    }
+
+    #[test]
+    fn up_to_date() {
+        let cluster = OtaProviderCluster::new(&TestStore, seq_rand);
+
+        let resp = cluster
+            .query_image(1, &query(2, &[DownloadProtocol::BdxSynchronous]))
+            .unwrap();
+        assert_eq!(resp.status, QueryImageStatus::NotAvailable);
+        assert_eq!(resp.image_uri, None);
+        assert_eq!(resp.update_token, None);
+
+        // Only the status is encoded
+        let mut buf = [0; 20];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+        resp.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+        assert_eq!(writebuf.as_slice(), [0x15, 0x24, 0, 2, 0x18]);
+
+        // A requestor which only downloads over HTTPS cannot be served
+        let resp = cluster
+            .query_image(1, &query(1, &[DownloadProtocol::Https]))
+            .unwrap();
+        assert_eq!(resp.status, QueryImageStatus::DownloadProtocolNotSupported);
+        assert_eq!(resp.image_uri, None);
+    }
+
+    #[test]
+    fn pending_image() {
+        let cluster = OtaProviderCluster::new(&TestStore, seq_rand);
+
+        let resp = cluster
+            .query_image(
+                0x1122,
+                &query(
+                    1,
+                    &[
+                        DownloadProtocol::Unknown(7),
+                        DownloadProtocol::BdxSynchronous,
+                    ],
+                ),
+            )
+            .unwrap();
+        assert_eq!(resp.status, QueryImageStatus::UpdateAvailable);
+        assert_eq!(
+            resp.image_uri.as_deref(),
+            Some("bdx://0000000000001122/fw-2.bin")
+        );
+        assert_eq!(resp.software_version, Some(2));
+        assert_eq!(resp.software_version_str.as_deref(), Some("2.0"));
+
+        let token = resp.update_token.unwrap();
+        assert_eq!(token.len(), 8);
+
+        // Only the offered version can be applied with the token
+        assert_eq!(
+            cluster.apply_update(&token, 2).action,
+            ApplyUpdateAction::Proceed
+        );
+        assert_eq!(
+            cluster.apply_update(&token, 3).action,
+            ApplyUpdateAction::Discontinue
+        );
+        assert_eq!(
+            cluster.apply_update(&[0; 8], 2).action,
+            ApplyUpdateAction::Discontinue
+        );
+
+        // Once applied, the token is forgotten
+        cluster.notify_update_applied(&token, 2).unwrap();
+        assert_eq!(
+            cluster.apply_update(&token, 2).action,
+            ApplyUpdateAction::Discontinue
+        );
+        assert_eq!(
+            cluster
+                .notify_update_applied(&token, 2)
+                .map_err(|e| e.code()),
+            Err(ErrorCode::NotFound)
+        );
+    }
+}
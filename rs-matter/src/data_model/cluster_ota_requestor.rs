@@ -0,0 +1,1072 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::{Cell, RefCell};
+use core::convert::TryInto;
+
+use super::cluster_basic_information::BasicInfoConfig;
+use super::events::{EventMgr, EventPriority};
+use super::objects::*;
+use crate::{
+    acl::Accessor,
+    attribute_enum, cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    fabric::MAX_SUPPORTED_FABRICS,
+    interaction_model::messages::{
+        ib::{attr_list_write, ListOperation},
+        GenericPath,
+    },
+    tlv::{self, FromTLV, Nullable, OctetStr, TLVElement, TLVList, TLVWriter, TagType, ToTLV},
+    transport::exchange::Exchange,
+    utils::{rand::Rand, writebuf::WriteBuf},
+};
+use log::{info, warn};
+
+#[cfg(feature = "nightly")]
+pub use asynch::*;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x002A;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    DefaultOtaProviders(()) = 0x00,
+    UpdatePossible(AttrType<bool>) = 0x01,
+    UpdateState(AttrType<u8>) = 0x02,
+    UpdateStateProgress(AttrType<Nullable<u8>>) = 0x03,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    AnnounceOtaProvider = 0x00,
+}
+
+command_enum!(Commands);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Events {
+    StateTransition = 0x00,
+    VersionApplied = 0x01,
+    DownloadError = 0x02,
+}
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: 0,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::DefaultOtaProviders as u16,
+            Access::RWVA.union(Access::FAB_SCOPED),
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::UpdatePossible as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::UpdateState as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::UpdateStateProgress as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+    ],
+    commands: &[Commands::AnnounceOtaProvider as _],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    // Announcements of non-administrators are ignored by the cluster itself, rather
+    // than rejected by the Interaction Model
+    admin_commands: &[],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromTLV, ToTLV)]
+pub enum AnnouncementReason {
+    SimpleAnnouncement = 0,
+    UpdateAvailable = 1,
+    UrgentUpdateAvailable = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum UpdateState {
+    Unknown = 0,
+    Idle = 1,
+    Querying = 2,
+    DelayedOnQuery = 3,
+    Downloading = 4,
+    Applying = 5,
+    DelayedOnApply = 6,
+    RollingBack = 7,
+    DelayedOnUserConsent = 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ChangeReason {
+    Unknown = 0,
+    Success = 1,
+    Failure = 2,
+    TimeOut = 3,
+    DelayByProvider = 4,
+}
+
+/// An OTA provider, i.e. the endpoint of a node of a fabric with the provider cluster
+#[derive(FromTLV, ToTLV, Debug, Clone, PartialEq)]
+#[tlvargs(start = 1)]
+pub struct ProviderLocation {
+    pub provider_node_id: u64,
+    pub endpoint: EndptId,
+    #[tagval(0xFE)]
+    pub fab_idx: Option<u8>,
+}
+
+/// The default OTA providers of all fabrics, which are persisted
+pub struct OtaProviderMgr {
+    providers: heapless::Vec<ProviderLocation, MAX_SUPPORTED_FABRICS>,
+    changed: bool,
+}
+
+impl OtaProviderMgr {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            providers: heapless::Vec::new(),
+            changed: false,
+        }
+    }
+
+    /// Add the default provider of the provided fabric, whatever the fabric index in
+    /// the location; there is at most one default provider per fabric
+    pub fn add(&mut self, mut provider: ProviderLocation, fab_idx: u8) -> Result<(), Error> {
+        if self
+            .providers
+            .iter()
+            .any(|provider| provider.fab_idx == Some(fab_idx))
+        {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        provider.fab_idx = Some(fab_idx);
+        self.providers
+            .push(provider)
+            .map_err(|_| ErrorCode::ResourceExhausted)?;
+
+        self.changed = true;
+
+        Ok(())
+    }
+
+    pub fn remove_for_fabric(&mut self, fab_idx: u8) {
+        let len = self.providers.len();
+
+        self.providers
+            .retain(|provider| provider.fab_idx != Some(fab_idx));
+
+        self.changed |= self.providers.len() != len;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProviderLocation> {
+        self.providers.iter()
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        let root = TLVList::new(data).iter().next().ok_or(ErrorCode::Invalid)?;
+
+        tlv::from_tlv(&mut self.providers, &root)?;
+        self.changed = false;
+
+        Ok(())
+    }
+
+    pub fn store<'a>(&mut self, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        if self.changed {
+            let mut wb = WriteBuf::new(buf);
+            let mut tw = TLVWriter::new(&mut wb);
+            self.providers
+                .as_slice()
+                .to_tlv(&mut tw, TagType::Anonymous)?;
+
+            self.changed = false;
+
+            let len = tw.get_tail();
+
+            Ok(Some(&buf[..len]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+impl Default for OtaProviderMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a")]
+pub struct AnnounceOtaProviderReq<'a> {
+    pub provider_node_id: u64,
+    pub vendor_id: u16,
+    pub announcement_reason: AnnouncementReason,
+    pub metadata_for_node: Option<OctetStr<'a>>,
+    pub endpoint: EndptId,
+}
+
+#[derive(ToTLV)]
+struct StateTransitionEvent {
+    previous_state: u8,
+    new_state: u8,
+    reason: u8,
+    target_software_version: Nullable<u32>,
+}
+
+#[derive(ToTLV)]
+struct VersionAppliedEvent {
+    software_version: u32,
+    product_id: u16,
+}
+
+pub struct OtaRequestorCluster<'a> {
+    data_ver: Dataver,
+    endpoint_id: EndptId,
+    basic_info: &'a BasicInfoConfig<'a>,
+    event_mgr: &'a RefCell<EventMgr>,
+    provider_mgr: &'a RefCell<OtaProviderMgr>,
+    /// The provider of the last announcement, which is queried rather than the defaults
+    announced: RefCell<Option<(ProviderLocation, AnnouncementReason)>>,
+    state: Cell<UpdateState>,
+    progress: Cell<Option<u8>>,
+}
+
+impl<'a> OtaRequestorCluster<'a> {
+    pub fn new(
+        endpoint_id: EndptId,
+        basic_info: &'a BasicInfoConfig<'a>,
+        event_mgr: &'a RefCell<EventMgr>,
+        provider_mgr: &'a RefCell<OtaProviderMgr>,
+        rand: Rand,
+    ) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            endpoint_id,
+            basic_info,
+            event_mgr,
+            provider_mgr,
+            announced: RefCell::new(None),
+            state: Cell::new(UpdateState::Idle),
+            progress: Cell::new(None),
+        }
+    }
+
+    pub fn update_state(&self) -> UpdateState {
+        self.state.get()
+    }
+
+    /// The reason of the last announcement of a provider, if any
+    pub fn announcement(&self) -> Option<AnnouncementReason> {
+        self.announced.borrow().as_ref().map(|(_, reason)| *reason)
+    }
+
+    /// Report that the device runs a new version, once the image is applied
+    pub fn version_applied(&self) -> Result<(), Error> {
+        self.event_mgr.borrow_mut().log(
+            self.endpoint_id,
+            ID,
+            Events::VersionApplied as _,
+            EventPriority::Critical,
+            false,
+            &VersionAppliedEvent {
+                software_version: self.basic_info.sw_ver,
+                product_id: self.basic_info.pid,
+            },
+        )?;
+
+        self.set_state(UpdateState::Idle, ChangeReason::Success, None)
+            .map(|_| ())
+    }
+
+    fn set_state(
+        &self,
+        new_state: UpdateState,
+        reason: ChangeReason,
+        target_software_version: Option<u32>,
+    ) -> Result<UpdateState, Error> {
+        let previous_state = self.state.replace(new_state);
+
+        self.progress.set(match new_state {
+            UpdateState::Downloading => Some(0),
+            // The progress is not tracked while downloading, as the length of the image
+            // is not known upfront
+            UpdateState::Applying | UpdateState::DelayedOnApply => Some(100),
+            _ => None,
+        });
+
+        self.data_ver.changed();
+
+        self.event_mgr.borrow_mut().log(
+            self.endpoint_id,
+            ID,
+            Events::StateTransition as _,
+            EventPriority::Info,
+            false,
+            &StateTransitionEvent {
+                previous_state: previous_state as _,
+                new_state: new_state as _,
+                reason: reason as _,
+                target_software_version: target_software_version
+                    .map(Nullable::NotNull)
+                    .unwrap_or(Nullable::Null),
+            },
+        )?;
+
+        Ok(new_state)
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::DefaultOtaProviders(_) => {
+                        writer.set_list(self.provider_mgr.borrow().iter().filter(|provider| {
+                            attr.is_visible_to(provider.fab_idx.unwrap_or_default())
+                        }))
+                    }
+                    Attributes::UpdatePossible(codec) => codec.encode(writer, true),
+                    Attributes::UpdateState(codec) => codec.encode(writer, self.state.get() as _),
+                    Attributes::UpdateStateProgress(codec) => codec.encode(
+                        writer,
+                        self.progress
+                            .get()
+                            .map(Nullable::NotNull)
+                            .unwrap_or(Nullable::Null),
+                    ),
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        match attr.attr_id.try_into()? {
+            Attributes::DefaultOtaProviders(_) => {
+                attr_list_write(attr, data.with_dataver(self.data_ver.get())?, |op, data| {
+                    self.write_providers_attr(&op, data, attr.fab_idx)
+                })?
+            }
+            _ => Err(ErrorCode::AttributeNotFound)?,
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    fn write_providers_attr(
+        &self,
+        op: &ListOperation,
+        data: &TLVElement,
+        fab_idx: u8,
+    ) -> Result<(), Error> {
+        match op {
+            ListOperation::AddItem => self
+                .provider_mgr
+                .borrow_mut()
+                .add(ProviderLocation::from_tlv(data)?, fab_idx),
+            ListOperation::DeleteList => {
+                self.provider_mgr.borrow_mut().remove_for_fabric(fab_idx);
+                Ok(())
+            }
+            ListOperation::EditItem(_) | ListOperation::DeleteItem(_) => {
+                Err(ErrorCode::InvalidAction.into())
+            }
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        _encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::AnnounceOtaProvider => {
+                self.handle_command_announceotaprovider(exchange, cmd, data)
+            }
+        }
+    }
+
+    fn handle_command_announceotaprovider(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+    ) -> Result<(), Error> {
+        cmd_enter!("AnnounceOtaProvider");
+
+        let req = AnnounceOtaProviderReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let accessor = exchange.accessor()?;
+
+        self.announce(&req, &accessor, cmd.endpoint_id);
+
+        Ok(())
+    }
+
+    /// Record the announced provider, unless the announcement comes from an accessor
+    /// without administrative privileges
+    fn announce(&self, req: &AnnounceOtaProviderReq, accessor: &Accessor, endpoint_id: EndptId) {
+        let admin = Cluster::check_cmd_access(
+            accessor,
+            GenericPath::new(
+                Some(endpoint_id),
+                Some(ID),
+                Some(Commands::AnnounceOtaProvider as _),
+            ),
+            Access::WRITE.union(Access::NEED_ADMIN),
+        )
+        .is_ok();
+
+        if !admin {
+            warn!("Ignoring the OTA provider announced by a non-administrator");
+            return;
+        }
+
+        info!(
+            "OTA provider {:x} announced: {:?}",
+            req.provider_node_id, req.announcement_reason
+        );
+
+        *self.announced.borrow_mut() = Some((
+            ProviderLocation {
+                provider_node_id: req.provider_node_id,
+                endpoint: req.endpoint,
+                fab_idx: Some(accessor.fab_idx),
+            },
+            req.announcement_reason,
+        ));
+    }
+}
+
+impl<'a> Handler for OtaRequestorCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        OtaRequestorCluster::read(self, attr, encoder)
+    }
+
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        OtaRequestorCluster::write(self, attr, data)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        OtaRequestorCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for OtaRequestorCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for OtaRequestorCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(feature = "nightly")]
+mod asynch {
+    use crate::{
+        bdx::BdxSink,
+        data_model::{
+            cluster_ota_provider::{
+                ApplyUpdateAction, ApplyUpdateReq, ApplyUpdateResp, DownloadProtocol,
+                QueryImageReq, QueryImageResp, QueryImageStatus,
+            },
+            events::EventPriority,
+        },
+        error::{Error, ErrorCode},
+        tlv::{Nullable, OctetStr, TLVArray, ToTLV},
+    };
+    use log::{error, info, warn};
+
+    use super::{ChangeReason, Events, OtaRequestorCluster, ProviderLocation, UpdateState, ID};
+
+    #[derive(ToTLV)]
+    struct DownloadErrorEvent {
+        software_version: u32,
+        bytes_downloaded: u64,
+        progress_percent: Nullable<u8>,
+        platform_code: Nullable<i64>,
+    }
+
+    /// The Provider Client Trait
+    ///
+    /// Objects that implement this trait reach the OTA providers on behalf of the requestor:
+    /// they invoke the commands of the provider cluster, and download the images over BDX.
+    pub trait ProviderClient {
+        async fn query_image(
+            &self,
+            provider: &ProviderLocation,
+            req: &QueryImageReq<'_>,
+        ) -> Result<QueryImageResp, Error>;
+
+        /// Download the image with the provided file designator into the sink
+        async fn download(
+            &self,
+            provider: &ProviderLocation,
+            file_designator: &str,
+            sink: &mut dyn BdxSink,
+        ) -> Result<(), Error>;
+
+        async fn apply_update(
+            &self,
+            provider: &ProviderLocation,
+            req: &ApplyUpdateReq<'_>,
+        ) -> Result<ApplyUpdateResp, Error>;
+    }
+
+    /// Counts the bytes written to the sink of the image, for the DownloadError events
+    struct CountingSink<'a> {
+        sink: &'a mut dyn BdxSink,
+        len: u64,
+    }
+
+    impl<'a> BdxSink for CountingSink<'a> {
+        fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), Error> {
+            self.sink.write(offset, data)?;
+            self.len = self.len.max(offset + data.len() as u64);
+
+            Ok(())
+        }
+    }
+
+    impl<'a> OtaRequestorCluster<'a> {
+        /// Query the provider of the last announcement, or else the first default provider,
+        /// for a newer image, download it into the sink and ask the provider for the permission
+        /// to apply it
+        ///
+        /// Returns the update state reached: `Applying` once the downloaded image can be
+        /// applied, which is then up to the application, or `Idle` when there is no update.
+        pub async fn check_for_update<C>(
+            &self,
+            client: &C,
+            sink: &mut dyn BdxSink,
+        ) -> Result<UpdateState, Error>
+        where
+            C: ProviderClient,
+        {
+            let provider = self
+                .announced
+                .borrow_mut()
+                .take()
+                .map(|(provider, _)| provider)
+                .or_else(|| self.provider_mgr.borrow().iter().next().cloned())
+                .ok_or(ErrorCode::NotFound)?;
+
+            self.set_state(UpdateState::Querying, ChangeReason::Success, None)?;
+
+            let protocols = [DownloadProtocol::BdxSynchronous];
+            let query = QueryImageReq {
+                vendor_id: self.basic_info.vid,
+                product_id: self.basic_info.pid,
+                software_version: self.basic_info.sw_ver,
+                protocols_supported: TLVArray::new(&protocols),
+                hardware_version: Some(self.basic_info.hw_ver),
+                location: None,
+                requestor_can_consent: None,
+                metadata_for_provider: None,
+            };
+
+            let resp = match client.query_image(&provider, &query).await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    error!("Querying the OTA provider failed: {:?}", e);
+                    self.set_state(UpdateState::Idle, ChangeReason::Failure, None)?;
+                    return Err(e);
+                }
+            };
+
+            let (version, token, file_designator) = match resp.status {
+                QueryImageStatus::UpdateAvailable => {
+                    match (
+                        resp.software_version,
+                        resp.update_token.as_ref(),
+                        resp.image_uri.as_deref().and_then(Self::file_designator),
+                    ) {
+                        (Some(version), Some(token), Some(file_designator)) => {
+                            (version, token, file_designator)
+                        }
+                        _ => {
+                            warn!("Ignoring an incomplete image offer of the OTA provider");
+                            return self.set_state(UpdateState::Idle, ChangeReason::Failure, None);
+                        }
+                    }
+                }
+                QueryImageStatus::Busy => {
+                    return self.set_state(
+                        UpdateState::DelayedOnQuery,
+                        ChangeReason::DelayByProvider,
+                        None,
+                    );
+                }
+                QueryImageStatus::NotAvailable => {
+                    return self.set_state(UpdateState::Idle, ChangeReason::Success, None);
+                }
+                QueryImageStatus::DownloadProtocolNotSupported => {
+                    return self.set_state(UpdateState::Idle, ChangeReason::Failure, None);
+                }
+            };
+
+            self.set_state(
+                UpdateState::Downloading,
+                ChangeReason::Success,
+                Some(version),
+            )?;
+
+            let mut counting = CountingSink { sink, len: 0 };
+            if let Err(e) = client
+                .download(&provider, file_designator, &mut counting)
+                .await
+            {
+                error!("Downloading the image failed: {:?}", e);
+
+                self.event_mgr.borrow_mut().log(
+                    self.endpoint_id,
+                    ID,
+                    Events::DownloadError as _,
+                    EventPriority::Info,
+                    false,
+                    &DownloadErrorEvent {
+                        software_version: version,
+                        bytes_downloaded: counting.len,
+                        progress_percent: Nullable::Null,
+                        platform_code: Nullable::Null,
+                    },
+                )?;
+
+                self.set_state(UpdateState::Idle, ChangeReason::Failure, Some(version))?;
+                return Err(e);
+            }
+
+            info!("Downloaded {} bytes of version {}", counting.len, version);
+
+            let apply = ApplyUpdateReq {
+                update_token: OctetStr::new(token),
+                new_version: version,
+            };
+
+            match client.apply_update(&provider, &apply).await {
+                Ok(resp) => match resp.action {
+                    ApplyUpdateAction::Proceed => {
+                        self.set_state(UpdateState::Applying, ChangeReason::Success, Some(version))
+                    }
+                    ApplyUpdateAction::AwaitNextAction => self.set_state(
+                        UpdateState::DelayedOnApply,
+                        ChangeReason::DelayByProvider,
+                        Some(version),
+                    ),
+                    ApplyUpdateAction::Discontinue => {
+                        self.set_state(UpdateState::Idle, ChangeReason::Success, None)
+                    }
+                },
+                Err(e) => {
+                    error!("Requesting to apply the image failed: {:?}", e);
+                    self.set_state(UpdateState::Idle, ChangeReason::Failure, Some(version))?;
+                    Err(e)
+                }
+            }
+        }
+
+        /// The file designator of a BDX image URI, i.e. `bdx://<node id>/<file designator>`
+        fn file_designator(uri: &str) -> Option<&str> {
+            let (node_id, file_designator) = uri.strip_prefix("bdx://")?.split_once('/')?;
+
+            (node_id.len() == 16 && u64::from_str_radix(node_id, 16).is_ok())
+                .then_some(file_designator)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, time::Duration};
+
+    use crate::{
+        acl::{Accessor, AccessorSubjects, AclEntry, AclMgr, AuthMode},
+        data_model::{events::EventMgr, objects::Privilege},
+        error::Error,
+        interaction_model::messages::ib::ListOperation,
+        test_support::BASIC_INFO,
+        tlv::{get_root_node_struct, TLVWriter, TagType, ToTLV},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    #[cfg(feature = "nightly")]
+    use crate::{
+        bdx::BdxSink,
+        data_model::cluster_ota_provider::{
+            ApplyUpdateAction, ApplyUpdateReq, ApplyUpdateResp, QueryImageReq, QueryImageResp,
+            QueryImageStatus,
+        },
+        error::ErrorCode,
+    };
+
+    #[cfg(feature = "nightly")]
+    use super::{Events, ProviderClient, UpdateState, ID};
+
+    use super::{
+        AnnounceOtaProviderReq, AnnouncementReason, OtaProviderMgr, OtaRequestorCluster,
+        ProviderLocation,
+    };
+
+    #[cfg(feature = "nightly")]
+    const IMAGE: &[u8] = b"the bytes of the new firmware image";
+
+    fn dummy_epoch() -> Duration {
+        Duration::from_millis(1000)
+    }
+
+    #[cfg(feature = "nightly")]
+    struct MockProvider {
+        fail_download: bool,
+    }
+
+    #[cfg(feature = "nightly")]
+    impl ProviderClient for MockProvider {
+        async fn query_image(
+            &self,
+            provider: &ProviderLocation,
+            req: &QueryImageReq<'_>,
+        ) -> Result<QueryImageResp, Error> {
+            assert_eq!(provider.provider_node_id, 0x1122);
+            assert_eq!(req.software_version, BASIC_INFO.sw_ver);
+
+            Ok(QueryImageResp {
+                status: QueryImageStatus::UpdateAvailable,
+                delayed_action_time: None,
+                image_uri: Some("bdx://0000000000001122/fw-2.bin".into()),
+                software_version: Some(2),
+                software_version_str: Some("2.0".into()),
+                update_token: Some(heapless::Vec::from_slice(&[7; 8]).unwrap()),
+                user_consent_needed: None,
+            })
+        }
+
+        async fn download(
+            &self,
+            _provider: &ProviderLocation,
+            file_designator: &str,
+            sink: &mut dyn BdxSink,
+        ) -> Result<(), Error> {
+            assert_eq!(file_designator, "fw-2.bin");
+
+            for (index, block) in IMAGE.chunks(16).enumerate() {
+                if self.fail_download && index == 1 {
+                    Err(ErrorCode::TransferAborted)?;
+                }
+
+                sink.write(index as u64 * 16, block)?;
+            }
+
+            Ok(())
+        }
+
+        async fn apply_update(
+            &self,
+            _provider: &ProviderLocation,
+            req: &ApplyUpdateReq<'_>,
+        ) -> Result<ApplyUpdateResp, Error> {
+            assert_eq!(req.update_token.0, &[7; 8]);
+            assert_eq!(req.new_version, 2);
+
+            Ok(ApplyUpdateResp {
+                action: ApplyUpdateAction::Proceed,
+                delayed_action_time: 0,
+            })
+        }
+    }
+
+    #[cfg(feature = "nightly")]
+    struct Image {
+        data: heapless::Vec<u8, 64>,
+    }
+
+    #[cfg(feature = "nightly")]
+    impl BdxSink for Image {
+        fn write(&mut self, _offset: u64, data: &[u8]) -> Result<(), Error> {
+            self.data
+                .extend_from_slice(data)
+                .map_err(|_| ErrorCode::NoSpace.into())
+        }
+    }
+
+    fn add_provider(cluster: &OtaRequestorCluster, fab_idx: u8) -> Result<(), Error> {
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+        ProviderLocation {
+            provider_node_id: 0x1122,
+            endpoint: 0,
+            fab_idx: None,
+        }
+        .to_tlv(&mut tw, TagType::Anonymous)
+        .unwrap();
+
+        let data = get_root_node_struct(writebuf.as_slice()).unwrap();
+        cluster.write_providers_attr(&ListOperation::AddItem, &data, fab_idx)
+    }
+
+    /// The event IDs of the logged events, along with the new state of the transitions
+    #[cfg(feature = "nightly")]
+    fn logged_events(event_mgr: &RefCell<EventMgr>) -> heapless::Vec<(u32, Option<u8>), 8> {
+        let mut events = heapless::Vec::new();
+        for event in event_mgr.borrow().iter() {
+            assert_eq!(event.cluster, ID);
+
+            let mut buf = [0; 100];
+            let mut writebuf = WriteBuf::new(&mut buf);
+            let mut tw = TLVWriter::new(&mut writebuf);
+            event.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+
+            let new_state = (event.event_id == Events::StateTransition as u32).then(|| {
+                get_root_node_struct(writebuf.as_slice())
+                    .unwrap()
+                    .find_tag(1)
+                    .unwrap()
+                    .find_tag(7)
+                    .unwrap()
+                    .find_tag(1)
+                    .unwrap()
+                    .u8()
+                    .unwrap()
+            });
+            events.push((event.event_id, new_state)).unwrap();
+        }
+
+        events
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn query_download_apply() {
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let provider_mgr = RefCell::new(OtaProviderMgr::new());
+        let cluster =
+            OtaRequestorCluster::new(0, &BASIC_INFO, &event_mgr, &provider_mgr, dummy_rand);
+
+        let mut image = Image {
+            data: heapless::Vec::new(),
+        };
+
+        let provider = MockProvider {
+            fail_download: false,
+        };
+
+        // Nothing to query without a provider
+        assert_eq!(
+            embassy_futures::block_on(cluster.check_for_update(&provider, &mut image))
+                .map_err(|e| e.code()),
+            Err(ErrorCode::NotFound)
+        );
+
+        add_provider(&cluster, 1).unwrap();
+        // Only one default provider per fabric
+        assert_eq!(
+            add_provider(&cluster, 1).map_err(|e| e.code()),
+            Err(ErrorCode::ConstraintError)
+        );
+
+        assert_eq!(
+            embassy_futures::block_on(cluster.check_for_update(&provider, &mut image)).unwrap(),
+            UpdateState::Applying
+        );
+        assert_eq!(image.data.as_slice(), IMAGE);
+
+        let transition = Events::StateTransition as u32;
+        assert_eq!(
+            logged_events(&event_mgr).as_slice(),
+            &[
+                (transition, Some(UpdateState::Querying as u8)),
+                (transition, Some(UpdateState::Downloading as u8)),
+                (transition, Some(UpdateState::Applying as u8)),
+            ]
+        );
+
+        // Once the device restarts with the new image
+        cluster.version_applied().unwrap();
+        assert_eq!(cluster.update_state(), UpdateState::Idle);
+        assert_eq!(
+            logged_events(&event_mgr)[3..],
+            [
+                (Events::VersionApplied as u32, None),
+                (transition, Some(UpdateState::Idle as u8)),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn download_error() {
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let provider_mgr = RefCell::new(OtaProviderMgr::new());
+        let cluster =
+            OtaRequestorCluster::new(0, &BASIC_INFO, &event_mgr, &provider_mgr, dummy_rand);
+        add_provider(&cluster, 1).unwrap();
+
+        let mut image = Image {
+            data: heapless::Vec::new(),
+        };
+
+        let provider = MockProvider {
+            fail_download: true,
+        };
+
+        assert_eq!(
+            embassy_futures::block_on(cluster.check_for_update(&provider, &mut image))
+                .map_err(|e| e.code()),
+            Err(ErrorCode::TransferAborted)
+        );
+        assert_eq!(cluster.update_state(), UpdateState::Idle);
+
+        let transition = Events::StateTransition as u32;
+        assert_eq!(
+            logged_events(&event_mgr).as_slice(),
+            &[
+                (transition, Some(UpdateState::Querying as u8)),
+                (transition, Some(UpdateState::Downloading as u8)),
+                (Events::DownloadError as u32, None),
+                (transition, Some(UpdateState::Idle as u8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn announce_from_admin_only() {
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let provider_mgr = RefCell::new(OtaProviderMgr::new());
+        let cluster =
+            OtaRequestorCluster::new(0, &BASIC_INFO, &event_mgr, &provider_mgr, dummy_rand);
+
+        let acl_mgr = RefCell::new(AclMgr::new());
+        acl_mgr.borrow_mut().erase_all().unwrap();
+
+        let mut admin = AclEntry::new(1, Privilege::ADMIN, AuthMode::Case);
+        admin.add_subject(100).unwrap();
+        acl_mgr.borrow_mut().add(admin).unwrap();
+
+        let mut operator = AclEntry::new(2, Privilege::OPERATE, AuthMode::Case);
+        operator.add_subject(200).unwrap();
+        acl_mgr.borrow_mut().add(operator).unwrap();
+
+        let req = AnnounceOtaProviderReq {
+            provider_node_id: 0x1122,
+            vendor_id: 0xfff1,
+            announcement_reason: AnnouncementReason::UpdateAvailable,
+            metadata_for_node: None,
+            endpoint: 0,
+        };
+
+        let accessor = Accessor::new(2, AccessorSubjects::new(200), AuthMode::Case, &acl_mgr);
+        cluster.announce(&req, &accessor, 0);
+        assert_eq!(cluster.announcement(), None);
+
+        let accessor = Accessor::new(1, AccessorSubjects::new(100), AuthMode::Case, &acl_mgr);
+        cluster.announce(&req, &accessor, 0);
+        assert_eq!(
+            cluster.announcement(),
+            Some(AnnouncementReason::UpdateAvailable)
+        );
+
+        // The announced provider is queried, even though there is no default one
+        #[cfg(feature = "nightly")]
+        {
+            let mut image = Image {
+                data: heapless::Vec::new(),
+            };
+            let provider = MockProvider {
+                fail_download: false,
+            };
+
+            assert_eq!(
+                embassy_futures::block_on(cluster.check_for_update(&provider, &mut image)).unwrap(),
+                UpdateState::Applying
+            );
+            assert_eq!(cluster.announcement(), None);
+        }
+    }
+
+    #[test]
+    fn persisted_providers() {
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let provider_mgr = RefCell::new(OtaProviderMgr::new());
+        let cluster =
+            OtaRequestorCluster::new(0, &BASIC_INFO, &event_mgr, &provider_mgr, dummy_rand);
+
+        add_provider(&cluster, 1).unwrap();
+        add_provider(&cluster, 2).unwrap();
+        assert!(provider_mgr.borrow().is_changed());
+
+        let mut buf = [0; 100];
+        let data = provider_mgr.borrow_mut().store(&mut buf).unwrap().unwrap();
+        assert!(!provider_mgr.borrow().is_changed());
+
+        let reloaded_mgr = RefCell::new(OtaProviderMgr::new());
+        reloaded_mgr.borrow_mut().load(data).unwrap();
+        assert!(!reloaded_mgr.borrow().is_changed());
+        assert!(reloaded_mgr
+            .borrow()
+            .iter()
+            .eq(provider_mgr.borrow().iter()));
+
+        // The default provider goes away with its fabric
+        reloaded_mgr.borrow_mut().remove_for_fabric(1);
+        assert!(reloaded_mgr.borrow().is_changed());
+        assert_eq!(
+            reloaded_mgr
+                .borrow()
+                .iter()
+                .map(|provider| provider.fab_idx)
+                .collect::<heapless::Vec<_, 2>>(),
+            [Some(2)]
+        );
+    }
+}
@@ -0,0 +1,539 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::{Cell, RefCell};
+use core::convert::TryInto;
+
+use super::events::{EventLogger, EventPriority};
+use super::objects::*;
+use crate::{
+    attribute_enum,
+    error::{Error, ErrorCode},
+    tlv::{Nullable, ToTLV},
+    utils::rand::Rand,
+};
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x002F;
+
+/// The Wired feature, for sources connected to the mains
+pub const FEATURE_WIRED: u32 = 0x01;
+/// The Battery feature, for sources which are batteries
+pub const FEATURE_BATTERY: u32 = 0x02;
+
+/// The maximum number of faults a battery reports at once
+pub const MAX_BAT_FAULTS: usize = 3;
+
+/// Below this charge, in percents, the charge level of the battery is reported as a warning
+pub const BAT_WARNING_PERCENT: u8 = 25;
+/// Below this charge, in percents, the charge level of the battery is reported as critical
+pub const BAT_CRITICAL_PERCENT: u8 = 10;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    Status(AttrType<u8>) = 0x00,
+    Order(AttrType<u8>) = 0x01,
+    Description(AttrUtfType) = 0x02,
+    WiredCurrentType(AttrType<u8>) = 0x05,
+    BatPercentRemaining(AttrType<Nullable<u8>>) = 0x0C,
+    BatChargeLevel(AttrType<u8>) = 0x0E,
+    BatReplacementNeeded(AttrType<bool>) = 0x0F,
+    BatReplaceability(AttrType<u8>) = 0x10,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Events {
+    WiredFaultChange = 0x00,
+    BatFaultChange = 0x01,
+}
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: FEATURE_WIRED,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::Status as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::Order as u16,
+            Access::RV,
+            Quality::PERSISTENT,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::Description as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::WiredCurrentType as u16,
+            Access::RV,
+            Quality::FIXED,
+        )
+        .with_feature(FEATURE_WIRED),
+        Attribute::new(
+            AttributesDiscriminants::BatPercentRemaining as u16,
+            Access::RV,
+            Quality::NONE,
+        )
+        .with_feature(FEATURE_BATTERY),
+        Attribute::new(
+            AttributesDiscriminants::BatChargeLevel as u16,
+            Access::RV,
+            Quality::NONE,
+        )
+        .with_feature(FEATURE_BATTERY),
+        Attribute::new(
+            AttributesDiscriminants::BatReplacementNeeded as u16,
+            Access::RV,
+            Quality::NONE,
+        )
+        .with_feature(FEATURE_BATTERY),
+        Attribute::new(
+            AttributesDiscriminants::BatReplaceability as u16,
+            Access::RV,
+            Quality::FIXED,
+        )
+        .with_feature(FEATURE_BATTERY),
+    ],
+    commands: &[],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+/// The PowerSource cluster of a battery
+pub const CLUSTER_BATTERY: Cluster<'static> = Cluster {
+    feature_map: FEATURE_BATTERY,
+    ..CLUSTER
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PowerSourceStatus {
+    Unspecified = 0,
+    Active = 1,
+    Standby = 2,
+    Unavailable = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum WiredCurrentType {
+    AC = 0,
+    DC = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BatChargeLevel {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+}
+
+impl BatChargeLevel {
+    pub fn from_percent(percent: u8) -> Self {
+        if percent < BAT_CRITICAL_PERCENT {
+            Self::Critical
+        } else if percent < BAT_WARNING_PERCENT {
+            Self::Warning
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BatReplaceability {
+    Unspecified = 0,
+    NotReplaceable = 1,
+    UserReplaceable = 2,
+    FactoryReplaceable = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToTLV)]
+pub enum BatFault {
+    Unspecified = 0,
+    OverTemp = 1,
+    UnderTemp = 2,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct BatFaultChangeEvent<'a> {
+    current: &'a [BatFault],
+    previous: &'a [BatFault],
+}
+
+/// The Battery Driver Trait
+///
+/// Objects that implement this trait report the state of the battery of the device.
+pub trait BatteryDriver {
+    /// The remaining charge, in percents, or `None` if it is not known
+    fn percent_remaining(&self) -> Option<u8>;
+
+    fn replacement_needed(&self) -> bool;
+
+    /// Whether - and by whom - the battery can be replaced
+    fn replaceability(&self) -> BatReplaceability;
+
+    /// The faults the battery currently has, if any
+    fn faults(&self) -> heapless::Vec<BatFault, MAX_BAT_FAULTS>;
+}
+
+/// What the power source is
+pub enum PowerSource<'a> {
+    Wired(WiredCurrentType),
+    Battery(&'a dyn BatteryDriver),
+}
+
+pub struct PowerSourceCluster<'a> {
+    data_ver: Dataver,
+    endpoint_id: EndptId,
+    source: PowerSource<'a>,
+    description: &'a str,
+    order: u8,
+    events: &'a dyn EventLogger,
+    status: Cell<PowerSourceStatus>,
+    /// The battery faults as of the last check, so as to report their changes
+    bat_faults: RefCell<heapless::Vec<BatFault, MAX_BAT_FAULTS>>,
+}
+
+impl<'a> PowerSourceCluster<'a> {
+    pub fn new(
+        endpoint_id: EndptId,
+        source: PowerSource<'a>,
+        description: &'a str,
+        order: u8,
+        events: &'a dyn EventLogger,
+        rand: Rand,
+    ) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            endpoint_id,
+            source,
+            description,
+            order,
+            events,
+            status: Cell::new(PowerSourceStatus::Active),
+            bat_faults: RefCell::new(heapless::Vec::new()),
+        }
+    }
+
+    /// The cluster matching the kind of the power source
+    pub fn cluster(&self) -> &'static Cluster<'static> {
+        match self.source {
+            PowerSource::Wired(_) => &CLUSTER,
+            PowerSource::Battery(_) => &CLUSTER_BATTERY,
+        }
+    }
+
+    pub fn set_status(&self, status: PowerSourceStatus) {
+        if self.status.get() != status {
+            self.status.set(status);
+            self.data_ver.changed();
+        }
+    }
+
+    /// Let the cluster know that the state of the battery might have changed
+    ///
+    /// A change of the faults of the battery is logged as a `BatFaultChange` event,
+    /// which the subscriptions to it get reported when logged with the `Matter` instance.
+    pub fn battery_changed(&self) -> Result<(), Error> {
+        let PowerSource::Battery(battery) = self.source else {
+            return Ok(());
+        };
+
+        self.data_ver.changed();
+
+        let current = battery.faults();
+        let mut previous = self.bat_faults.borrow_mut();
+
+        if *previous != current {
+            self.events.log_event(
+                self.endpoint_id,
+                ID,
+                Events::BatFaultChange as _,
+                EventPriority::Info,
+                false,
+                &BatFaultChangeEvent {
+                    current: &current,
+                    previous: &previous,
+                },
+            )?;
+
+            *previous = current;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                self.cluster().read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::Status(codec) => codec.encode(writer, self.status.get() as _),
+                    Attributes::Order(codec) => codec.encode(writer, self.order),
+                    Attributes::Description(codec) => codec.encode(writer, self.description),
+                    Attributes::WiredCurrentType(codec) => {
+                        codec.encode(writer, self.current_type()? as _)
+                    }
+                    Attributes::BatPercentRemaining(codec) => codec.encode(
+                        writer,
+                        self.battery()?
+                            .percent_remaining()
+                            // Reported in half percents
+                            .map(|percent| Nullable::NotNull(percent.min(100) * 2))
+                            .unwrap_or(Nullable::Null),
+                    ),
+                    Attributes::BatChargeLevel(codec) => {
+                        codec.encode(writer, self.charge_level()? as _)
+                    }
+                    Attributes::BatReplacementNeeded(codec) => {
+                        codec.encode(writer, self.battery()?.replacement_needed())
+                    }
+                    Attributes::BatReplaceability(codec) => {
+                        codec.encode(writer, self.battery()?.replaceability() as _)
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Battery attributes are absent from a source which is not a battery
+    fn battery(&self) -> Result<&'a dyn BatteryDriver, Error> {
+        match self.source {
+            PowerSource::Battery(battery) => Ok(battery),
+            PowerSource::Wired(_) => Err(ErrorCode::AttributeNotFound.into()),
+        }
+    }
+
+    fn current_type(&self) -> Result<WiredCurrentType, Error> {
+        match self.source {
+            PowerSource::Wired(current_type) => Ok(current_type),
+            PowerSource::Battery(_) => Err(ErrorCode::AttributeNotFound.into()),
+        }
+    }
+
+    /// A battery with an unknown charge is assumed to be fine
+    fn charge_level(&self) -> Result<BatChargeLevel, Error> {
+        Ok(self
+            .battery()?
+            .percent_remaining()
+            .map(BatChargeLevel::from_percent)
+            .unwrap_or(BatChargeLevel::Ok))
+    }
+}
+
+impl<'a> Handler for PowerSourceCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        PowerSourceCluster::read(self, attr, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for PowerSourceCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for PowerSourceCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::{Cell, RefCell};
+
+    use crate::{
+        data_model::{
+            events::EventMgr,
+            objects::{AttrDataEncoder, AttrDetails, Node},
+        },
+        error::ErrorCode,
+        tlv::{get_root_node_struct, TLVWriter},
+        utils::{epoch::dummy_epoch, rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{
+        AttributesDiscriminants, BatChargeLevel, BatFault, BatReplaceability, BatteryDriver,
+        Events, PowerSource, PowerSourceCluster, PowerSourceStatus, WiredCurrentType, ID,
+        MAX_BAT_FAULTS,
+    };
+
+    struct MockBattery {
+        percent: Cell<u8>,
+        fault: Cell<Option<BatFault>>,
+    }
+
+    impl BatteryDriver for MockBattery {
+        fn percent_remaining(&self) -> Option<u8> {
+            Some(self.percent.get())
+        }
+
+        fn replacement_needed(&self) -> bool {
+            false
+        }
+
+        fn replaceability(&self) -> BatReplaceability {
+            BatReplaceability::UserReplaceable
+        }
+
+        fn faults(&self) -> heapless::Vec<BatFault, MAX_BAT_FAULTS> {
+            self.fault.get().into_iter().collect()
+        }
+    }
+
+    fn read(
+        cluster: &PowerSourceCluster,
+        attr_id: AttributesDiscriminants,
+    ) -> Result<u8, ErrorCode> {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 1,
+            cluster_id: ID,
+            attr_id: attr_id as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .map_err(|e| e.code())?;
+
+        Ok(get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap()
+            .u8()
+            .unwrap())
+    }
+
+    fn bat_fault_events(event_mgr: &RefCell<EventMgr>) -> usize {
+        event_mgr
+            .borrow()
+            .iter()
+            .filter(|event| event.event_id == Events::BatFaultChange as u32)
+            .count()
+    }
+
+    #[test]
+    fn battery_low_and_faulty() {
+        let battery = MockBattery {
+            percent: Cell::new(20),
+            fault: Cell::new(None),
+        };
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let cluster = PowerSourceCluster::new(
+            1,
+            PowerSource::Battery(&battery),
+            "Battery",
+            0,
+            &event_mgr,
+            dummy_rand,
+        );
+
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::BatPercentRemaining),
+            Ok(40)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::BatChargeLevel),
+            Ok(BatChargeLevel::Warning as u8)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::BatReplaceability),
+            Ok(BatReplaceability::UserReplaceable as u8)
+        );
+
+        // No change, no event
+        cluster.battery_changed().unwrap();
+        assert_eq!(bat_fault_events(&event_mgr), 0);
+
+        battery.fault.set(Some(BatFault::OverTemp));
+        cluster.battery_changed().unwrap();
+        assert_eq!(bat_fault_events(&event_mgr), 1);
+
+        // The same fault again is no change either
+        cluster.battery_changed().unwrap();
+        assert_eq!(bat_fault_events(&event_mgr), 1);
+
+        battery.fault.set(None);
+        cluster.battery_changed().unwrap();
+        assert_eq!(bat_fault_events(&event_mgr), 2);
+    }
+
+    #[test]
+    fn mains_without_battery() {
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let cluster = PowerSourceCluster::new(
+            1,
+            PowerSource::Wired(WiredCurrentType::AC),
+            "Mains",
+            0,
+            &event_mgr,
+            dummy_rand,
+        );
+
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::Status),
+            Ok(PowerSourceStatus::Active as u8)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::WiredCurrentType),
+            Ok(WiredCurrentType::AC as u8)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::BatChargeLevel),
+            Err(ErrorCode::AttributeNotFound)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::BatReplacementNeeded),
+            Err(ErrorCode::AttributeNotFound)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::BatReplaceability),
+            Err(ErrorCode::AttributeNotFound)
+        );
+    }
+}
@@ -0,0 +1,1047 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+use core::cell::{Cell, RefCell};
+use core::convert::TryInto;
+
+use super::{
+    cluster_level_control::{self, LevelControlCluster},
+    cluster_on_off::{self, OnOffCluster},
+    objects::*,
+};
+use crate::{
+    attribute_enum, cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    fabric::MAX_SUPPORTED_FABRICS,
+    groups::GroupMgr,
+    interaction_model::core::IMStatusCode,
+    tlv::{FromTLV, Nullable, TLVArray, TLVElement, ToTLV, UtfStr},
+    transport::exchange::Exchange,
+    utils::rand::Rand,
+};
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0005;
+
+/// The SceneNames feature
+pub const FEATURE_SCENE_NAMES: u32 = 0x01;
+
+/// The number of scenes each fabric can have in the scene table
+pub const MAX_SCENES_PER_FABRIC: usize = 8;
+
+pub const MAX_SCENE_NAME_LEN: usize = 16;
+
+/// The number of clusters a scene can hold the attribute values of
+pub const MAX_EXTENSION_FIELD_SETS: usize = 4;
+
+/// The number of attribute values a scene can hold for each cluster
+pub const MAX_ATTRIBUTE_VALUES: usize = 4;
+
+const MAX_SCENES: usize = MAX_SCENES_PER_FABRIC * MAX_SUPPORTED_FABRICS;
+
+/// The value of the NameSupport attribute, when scene names are supported
+const NAME_SUPPORT: u8 = 0x80;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    SceneCount(AttrType<u8>) = 0x00,
+    CurrentScene(AttrType<u8>) = 0x01,
+    CurrentGroup(AttrType<u16>) = 0x02,
+    SceneValid(AttrType<bool>) = 0x03,
+    NameSupport(AttrType<u8>) = 0x04,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    AddScene = 0x00,
+    ViewScene = 0x01,
+    RemoveScene = 0x02,
+    RemoveAllScenes = 0x03,
+    StoreScene = 0x04,
+    RecallScene = 0x05,
+    GetSceneMembership = 0x06,
+}
+
+command_enum!(Commands);
+
+#[repr(u16)]
+pub enum RespCommands {
+    AddSceneResp = 0x00,
+    ViewSceneResp = 0x01,
+    RemoveSceneResp = 0x02,
+    RemoveAllScenesResp = 0x03,
+    StoreSceneResp = 0x04,
+    GetSceneMembershipResp = 0x06,
+}
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 4,
+    feature_map: FEATURE_SCENE_NAMES,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::SceneCount as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::CurrentScene as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::CurrentGroup as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::SceneValid as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::NameSupport as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+    ],
+    commands: &[
+        Commands::AddScene as _,
+        Commands::ViewScene as _,
+        Commands::RemoveScene as _,
+        Commands::RemoveAllScenes as _,
+        Commands::StoreScene as _,
+        Commands::RecallScene as _,
+        Commands::GetSceneMembership as _,
+    ],
+    generated_commands: &[
+        RespCommands::AddSceneResp as _,
+        RespCommands::ViewSceneResp as _,
+        RespCommands::RemoveSceneResp as _,
+        RespCommands::RemoveAllScenesResp as _,
+        RespCommands::StoreSceneResp as _,
+        RespCommands::GetSceneMembershipResp as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[
+        Commands::AddScene as _,
+        Commands::ViewScene as _,
+        Commands::RemoveScene as _,
+        Commands::RemoveAllScenes as _,
+        Commands::StoreScene as _,
+        Commands::GetSceneMembership as _,
+    ],
+    manage_commands: &[
+        Commands::AddScene as _,
+        Commands::RemoveScene as _,
+        Commands::RemoveAllScenes as _,
+        Commands::StoreScene as _,
+    ],
+    admin_commands: &[],
+};
+
+/// The value of an attribute, as held by a scene
+#[derive(FromTLV, ToTLV, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributeValuePair {
+    pub attribute_id: u32,
+    pub attribute_value: u32,
+}
+
+/// The attribute values a scene holds for one cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionFieldSet {
+    pub cluster_id: u32,
+    pub attribute_values: heapless::Vec<AttributeValuePair, MAX_ATTRIBUTE_VALUES>,
+}
+
+impl ExtensionFieldSet {
+    pub fn new(cluster_id: u32) -> Self {
+        Self {
+            cluster_id,
+            attribute_values: heapless::Vec::new(),
+        }
+    }
+
+    /// The value the set holds for attribute `attribute_id`, if any
+    pub fn value(&self, attribute_id: u32) -> Option<u32> {
+        self.attribute_values
+            .iter()
+            .find(|pair| pair.attribute_id == attribute_id)
+            .map(|pair| pair.attribute_value)
+    }
+}
+
+pub type ExtensionFieldSets = heapless::Vec<ExtensionFieldSet, MAX_EXTENSION_FIELD_SETS>;
+
+/// The Scene Handler Trait
+///
+/// Objects that implement this trait snapshot and apply the values of the scene-able
+/// attributes of the clusters of the endpoint, on behalf of the cluster.
+pub trait SceneHandler {
+    /// Add the current values of the scene-able attributes to `field_sets`
+    fn store(&self, field_sets: &mut ExtensionFieldSets) -> Result<(), Error>;
+
+    /// Apply the values of `field_sets`, within `transition_time` tenths of a second
+    fn recall(&self, field_sets: &[ExtensionFieldSet], transition_time: u16) -> Result<(), Error>;
+}
+
+/// A scene handler for the OnOff attribute of the OnOff cluster, and the
+/// CurrentLevel attribute of the Level Control cluster, of the endpoint
+pub struct OnOffLevelScenes<'a> {
+    on_off: Option<&'a OnOffCluster>,
+    level_control: Option<&'a LevelControlCluster<'a>>,
+}
+
+impl<'a> OnOffLevelScenes<'a> {
+    pub const fn new(
+        on_off: Option<&'a OnOffCluster>,
+        level_control: Option<&'a LevelControlCluster<'a>>,
+    ) -> Self {
+        Self {
+            on_off,
+            level_control,
+        }
+    }
+}
+
+impl<'a> SceneHandler for OnOffLevelScenes<'a> {
+    fn store(&self, field_sets: &mut ExtensionFieldSets) -> Result<(), Error> {
+        if let Some(on_off) = self.on_off {
+            let mut set = ExtensionFieldSet::new(cluster_on_off::ID);
+            set.attribute_values
+                .push(AttributeValuePair {
+                    attribute_id: cluster_on_off::AttributesDiscriminants::OnOff as _,
+                    attribute_value: on_off.get() as _,
+                })
+                .map_err(|_| ErrorCode::NoSpace)?;
+
+            field_sets.push(set).map_err(|_| ErrorCode::NoSpace)?;
+        }
+
+        if let Some(level_control) = self.level_control {
+            let mut set = ExtensionFieldSet::new(cluster_level_control::ID);
+            set.attribute_values
+                .push(AttributeValuePair {
+                    attribute_id: cluster_level_control::AttributesDiscriminants::CurrentLevel as _,
+                    attribute_value: level_control.current_level() as _,
+                })
+                .map_err(|_| ErrorCode::NoSpace)?;
+
+            field_sets.push(set).map_err(|_| ErrorCode::NoSpace)?;
+        }
+
+        Ok(())
+    }
+
+    fn recall(&self, field_sets: &[ExtensionFieldSet], transition_time: u16) -> Result<(), Error> {
+        for set in field_sets {
+            match set.cluster_id {
+                cluster_on_off::ID => {
+                    let value = set.value(cluster_on_off::AttributesDiscriminants::OnOff as _);
+
+                    if let (Some(on_off), Some(value)) = (self.on_off, value) {
+                        if value != 0 {
+                            on_off.on();
+                        } else {
+                            on_off.off();
+                        }
+                    }
+                }
+                cluster_level_control::ID => {
+                    let value = set
+                        .value(cluster_level_control::AttributesDiscriminants::CurrentLevel as _);
+
+                    if let (Some(level_control), Some(value)) = (self.level_control, value) {
+                        level_control.move_to_level(
+                            value.min(u8::MAX as _) as _,
+                            Some(transition_time),
+                            false,
+                        );
+                    }
+                }
+                // The values of other clusters are kept, but not applied
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(FromTLV, ToTLV, Clone)]
+#[tlvargs(lifetime = "'a")]
+struct ExtensionFieldSetReq<'a> {
+    cluster_id: u32,
+    attribute_value_list: TLVArray<'a, AttributeValuePair>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddSceneReq<'a> {
+    group_id: u16,
+    scene_id: u8,
+    /// In seconds
+    transition_time: u16,
+    scene_name: UtfStr<'a>,
+    extension_field_sets: TLVArray<'a, ExtensionFieldSetReq<'a>>,
+}
+
+#[derive(FromTLV)]
+struct SceneReq {
+    group_id: u16,
+    scene_id: u8,
+}
+
+#[derive(FromTLV)]
+struct GroupReq {
+    group_id: u16,
+}
+
+#[derive(FromTLV)]
+struct RecallSceneReq {
+    group_id: u16,
+    scene_id: u8,
+    /// In tenths of a second, overriding the transition time of the scene
+    transition_time: Option<Nullable<u16>>,
+}
+
+#[derive(ToTLV)]
+struct SceneResp {
+    status: u8,
+    group_id: u16,
+    scene_id: u8,
+}
+
+#[derive(ToTLV)]
+struct ExtensionFieldSetResp<'a> {
+    cluster_id: u32,
+    attribute_value_list: &'a [AttributeValuePair],
+}
+
+#[derive(ToTLV)]
+struct ViewSceneResp<'a> {
+    status: u8,
+    group_id: u16,
+    scene_id: u8,
+    transition_time: Option<u16>,
+    scene_name: Option<UtfStr<'a>>,
+    extension_field_sets: Option<&'a [ExtensionFieldSetResp<'a>]>,
+}
+
+#[derive(ToTLV)]
+struct RemoveAllScenesResp {
+    status: u8,
+    group_id: u16,
+}
+
+#[derive(ToTLV)]
+struct GetSceneMembershipResp<'a> {
+    status: u8,
+    capacity: Nullable<u8>,
+    group_id: u16,
+    scene_list: Option<&'a [u8]>,
+}
+
+#[derive(Debug, Clone)]
+struct Scene {
+    fab_idx: u8,
+    group_id: u16,
+    scene_id: u8,
+    name: heapless::String<MAX_SCENE_NAME_LEN>,
+    /// In seconds
+    transition_time: u16,
+    field_sets: ExtensionFieldSets,
+}
+
+pub struct ScenesCluster<'a> {
+    data_ver: Dataver,
+    group_mgr: &'a RefCell<GroupMgr>,
+    handler: &'a dyn SceneHandler,
+    scenes: RefCell<heapless::Vec<Scene, MAX_SCENES>>,
+    /// The scene last stored or recalled, as `(group_id, scene_id)`
+    current: Cell<Option<(u16, u8)>>,
+}
+
+impl<'a> ScenesCluster<'a> {
+    pub fn new(
+        group_mgr: &'a RefCell<GroupMgr>,
+        handler: &'a dyn SceneHandler,
+        rand: Rand,
+    ) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            group_mgr,
+            handler,
+            scenes: RefCell::new(heapless::Vec::new()),
+            current: Cell::new(None),
+        }
+    }
+
+    /// Remove the scenes of a fabric, e.g. as the fabric is removed
+    pub fn remove_fabric(&self, fab_idx: u8) {
+        self.remove_scenes(|scene| scene.fab_idx == fab_idx);
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                let current = self.current.get();
+
+                match attr.attr_id.try_into()? {
+                    Attributes::SceneCount(codec) => {
+                        codec.encode(writer, self.scenes.borrow().len() as _)
+                    }
+                    Attributes::CurrentScene(codec) => {
+                        codec.encode(writer, current.map(|(_, scene)| scene).unwrap_or(0))
+                    }
+                    Attributes::CurrentGroup(codec) => {
+                        codec.encode(writer, current.map(|(group, _)| group).unwrap_or(0))
+                    }
+                    Attributes::SceneValid(codec) => codec.encode(writer, current.is_some()),
+                    Attributes::NameSupport(codec) => codec.encode(writer, NAME_SUPPORT),
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        let fab_idx = exchange
+            .with_session(|sess| Ok(sess.get_local_fabric_idx()))?
+            .ok_or(ErrorCode::UnsupportedAccess)?;
+        let endpoint = cmd.endpoint_id;
+
+        match cmd.cmd_id.try_into()? {
+            Commands::AddScene => self.handle_command_addscene(fab_idx, endpoint, data, encoder)?,
+            Commands::ViewScene => {
+                self.handle_command_viewscene(fab_idx, endpoint, data, encoder)?
+            }
+            Commands::RemoveScene => {
+                self.handle_command_removescene(fab_idx, endpoint, data, encoder)?
+            }
+            Commands::RemoveAllScenes => {
+                self.handle_command_removeallscenes(fab_idx, endpoint, data, encoder)?
+            }
+            Commands::StoreScene => {
+                self.handle_command_storescene(fab_idx, endpoint, data, encoder)?
+            }
+            Commands::RecallScene => self.handle_command_recallscene(fab_idx, endpoint, data)?,
+            Commands::GetSceneMembership => {
+                self.handle_command_getscenemembership(fab_idx, endpoint, data, encoder)?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a scene, or replace the scene with the same group and scene IDs
+    fn add_scene(&self, fab_idx: u8, endpoint: EndptId, req: &AddSceneReq) -> IMStatusCode {
+        if !self.is_valid_group(fab_idx, endpoint, req.group_id) {
+            return IMStatusCode::InvalidCommand;
+        }
+
+        let name = match req.scene_name.as_str() {
+            Ok(name) if name.len() <= MAX_SCENE_NAME_LEN => name,
+            _ => return IMStatusCode::ConstraintError,
+        };
+
+        let mut field_sets = ExtensionFieldSets::new();
+        for req_set in req.extension_field_sets.iter() {
+            let mut set = ExtensionFieldSet::new(req_set.cluster_id);
+            for pair in req_set.attribute_value_list.iter() {
+                if set.attribute_values.push(pair).is_err() {
+                    return IMStatusCode::ResourceExhausted;
+                }
+            }
+
+            if field_sets.push(set).is_err() {
+                return IMStatusCode::ResourceExhausted;
+            }
+        }
+
+        let scene = Scene {
+            fab_idx,
+            group_id: req.group_id,
+            scene_id: req.scene_id,
+            name: name.into(),
+            transition_time: req.transition_time,
+            field_sets,
+        };
+
+        match self.put_scene(scene) {
+            Ok(()) => IMStatusCode::Success,
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Store the current values of the scene-able attributes as a scene, keeping
+    /// the name and transition time of the scene it replaces, if any
+    fn store_scene(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        group_id: u16,
+        scene_id: u8,
+    ) -> IMStatusCode {
+        if !self.is_valid_group(fab_idx, endpoint, group_id) {
+            return IMStatusCode::InvalidCommand;
+        }
+
+        let mut field_sets = ExtensionFieldSets::new();
+        if let Err(e) = self.handler.store(&mut field_sets) {
+            return e.into();
+        }
+
+        let (name, transition_time) = self
+            .scenes
+            .borrow()
+            .iter()
+            .find(|scene| scene.matches(fab_idx, group_id, scene_id))
+            .map(|scene| (scene.name.clone(), scene.transition_time))
+            .unwrap_or_default();
+
+        let scene = Scene {
+            fab_idx,
+            group_id,
+            scene_id,
+            name,
+            transition_time,
+            field_sets,
+        };
+
+        match self.put_scene(scene) {
+            Ok(()) => {
+                self.set_current(Some((group_id, scene_id)));
+                IMStatusCode::Success
+            }
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Apply the values of a scene, within `transition_time` tenths of a second,
+    /// or within the transition time of the scene
+    fn recall_scene(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        group_id: u16,
+        scene_id: u8,
+        transition_time: Option<u16>,
+    ) -> Result<(), Error> {
+        if !self.is_valid_group(fab_idx, endpoint, group_id) {
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
+        let scenes = self.scenes.borrow();
+        let scene = scenes
+            .iter()
+            .find(|scene| scene.matches(fab_idx, group_id, scene_id))
+            .ok_or(ErrorCode::NotFound)?;
+
+        let transition_time =
+            transition_time.unwrap_or_else(|| scene.transition_time.saturating_mul(10));
+
+        info!(
+            "Recalling scene {} of group {}, within {} tenths of a second",
+            scene_id, group_id, transition_time
+        );
+
+        self.handler.recall(&scene.field_sets, transition_time)?;
+        self.set_current(Some((group_id, scene_id)));
+
+        Ok(())
+    }
+
+    fn put_scene(&self, scene: Scene) -> Result<(), Error> {
+        let mut scenes = self.scenes.borrow_mut();
+
+        if let Some(existing) = scenes
+            .iter_mut()
+            .find(|s| s.matches(scene.fab_idx, scene.group_id, scene.scene_id))
+        {
+            *existing = scene;
+        } else {
+            if scenes.iter().filter(|s| s.fab_idx == scene.fab_idx).count() >= MAX_SCENES_PER_FABRIC
+            {
+                Err(ErrorCode::ResourceExhausted)?;
+            }
+
+            scenes
+                .push(scene)
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+
+            // Only the scene count is reported, so replacing a scene changes no attribute
+            self.data_ver.changed();
+        }
+
+        Ok(())
+    }
+
+    fn remove_scenes<F>(&self, mut f: F) -> usize
+    where
+        F: FnMut(&Scene) -> bool,
+    {
+        let mut scenes = self.scenes.borrow_mut();
+        let len = scenes.len();
+
+        scenes.retain(|scene| !f(scene));
+
+        if let Some((group_id, scene_id)) = self.current.get() {
+            if !scenes
+                .iter()
+                .any(|scene| scene.group_id == group_id && scene.scene_id == scene_id)
+            {
+                self.set_current(None);
+            }
+        }
+
+        let removed = len - scenes.len();
+        if removed > 0 {
+            self.data_ver.changed();
+        }
+
+        removed
+    }
+
+    fn set_current(&self, current: Option<(u16, u8)>) {
+        if self.current.replace(current) != current {
+            self.data_ver.changed();
+        }
+    }
+
+    /// Scenes belong either to no group (group ID 0), or to a group of the endpoint
+    fn is_valid_group(&self, fab_idx: u8, endpoint: EndptId, group_id: u16) -> bool {
+        group_id == 0
+            || self
+                .group_mgr
+                .borrow()
+                .is_member(fab_idx, group_id, endpoint)
+    }
+
+    fn handle_command_addscene(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("AddScene");
+
+        let req = AddSceneReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        let status = self.add_scene(fab_idx, endpoint, &req);
+
+        encoder
+            .with_command(RespCommands::AddSceneResp as _)?
+            .set(SceneResp {
+                status: status as _,
+                group_id: req.group_id,
+                scene_id: req.scene_id,
+            })
+    }
+
+    fn handle_command_viewscene(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("ViewScene");
+
+        let req = SceneReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let scenes = self.scenes.borrow();
+        let scene = scenes
+            .iter()
+            .find(|scene| scene.matches(fab_idx, req.group_id, req.scene_id));
+
+        let status = if !self.is_valid_group(fab_idx, endpoint, req.group_id) {
+            IMStatusCode::InvalidCommand
+        } else if scene.is_none() {
+            IMStatusCode::NotFound
+        } else {
+            IMStatusCode::Success
+        };
+
+        let field_sets = scene
+            .map(|scene| {
+                scene
+                    .field_sets
+                    .iter()
+                    .map(|set| ExtensionFieldSetResp {
+                        cluster_id: set.cluster_id,
+                        attribute_value_list: &set.attribute_values,
+                    })
+                    .collect::<heapless::Vec<_, MAX_EXTENSION_FIELD_SETS>>()
+            })
+            .unwrap_or_default();
+
+        let scene = scene.filter(|_| status == IMStatusCode::Success);
+
+        encoder
+            .with_command(RespCommands::ViewSceneResp as _)?
+            .set(ViewSceneResp {
+                status: status as _,
+                group_id: req.group_id,
+                scene_id: req.scene_id,
+                transition_time: scene.map(|scene| scene.transition_time),
+                scene_name: scene.map(|scene| UtfStr::new(scene.name.as_bytes())),
+                extension_field_sets: scene.map(|_| &field_sets[..]),
+            })
+    }
+
+    fn handle_command_removescene(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("RemoveScene");
+
+        let req = SceneReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let status = if !self.is_valid_group(fab_idx, endpoint, req.group_id) {
+            IMStatusCode::InvalidCommand
+        } else if self.remove_scenes(|scene| scene.matches(fab_idx, req.group_id, req.scene_id))
+            == 0
+        {
+            IMStatusCode::NotFound
+        } else {
+            IMStatusCode::Success
+        };
+
+        encoder
+            .with_command(RespCommands::RemoveSceneResp as _)?
+            .set(SceneResp {
+                status: status as _,
+                group_id: req.group_id,
+                scene_id: req.scene_id,
+            })
+    }
+
+    fn handle_command_removeallscenes(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("RemoveAllScenes");
+
+        let req = GroupReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let status = if !self.is_valid_group(fab_idx, endpoint, req.group_id) {
+            IMStatusCode::InvalidCommand
+        } else {
+            self.remove_scenes(|scene| scene.fab_idx == fab_idx && scene.group_id == req.group_id);
+            IMStatusCode::Success
+        };
+
+        encoder
+            .with_command(RespCommands::RemoveAllScenesResp as _)?
+            .set(RemoveAllScenesResp {
+                status: status as _,
+                group_id: req.group_id,
+            })
+    }
+
+    fn handle_command_storescene(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("StoreScene");
+
+        let req = SceneReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        let status = self.store_scene(fab_idx, endpoint, req.group_id, req.scene_id);
+
+        encoder
+            .with_command(RespCommands::StoreSceneResp as _)?
+            .set(SceneResp {
+                status: status as _,
+                group_id: req.group_id,
+                scene_id: req.scene_id,
+            })
+    }
+
+    fn handle_command_recallscene(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+    ) -> Result<(), Error> {
+        cmd_enter!("RecallScene");
+
+        let req = RecallSceneReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        self.recall_scene(
+            fab_idx,
+            endpoint,
+            req.group_id,
+            req.scene_id,
+            req.transition_time.and_then(Nullable::unwrap_notnull),
+        )
+    }
+
+    fn handle_command_getscenemembership(
+        &self,
+        fab_idx: u8,
+        endpoint: EndptId,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("GetSceneMembership");
+
+        let req = GroupReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let valid = self.is_valid_group(fab_idx, endpoint, req.group_id);
+
+        let scenes = self.scenes.borrow();
+        let fabric_scenes = scenes.iter().filter(|s| s.fab_idx == fab_idx).count();
+
+        let scene_list = scenes
+            .iter()
+            .filter(|s| s.fab_idx == fab_idx && s.group_id == req.group_id)
+            .map(|s| s.scene_id)
+            .collect::<heapless::Vec<_, MAX_SCENES_PER_FABRIC>>();
+
+        encoder
+            .with_command(RespCommands::GetSceneMembershipResp as _)?
+            .set(GetSceneMembershipResp {
+                status: if valid {
+                    IMStatusCode::Success
+                } else {
+                    IMStatusCode::InvalidCommand
+                } as _,
+                capacity: Nullable::NotNull((MAX_SCENES_PER_FABRIC - fabric_scenes) as _),
+                group_id: req.group_id,
+                scene_list: valid.then_some(&scene_list[..]),
+            })
+    }
+}
+
+impl Scene {
+    fn matches(&self, fab_idx: u8, group_id: u16, scene_id: u8) -> bool {
+        self.fab_idx == fab_idx && self.group_id == group_id && self.scene_id == scene_id
+    }
+}
+
+impl<'a> Handler for ScenesCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        ScenesCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        ScenesCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for ScenesCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for ScenesCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        cell::{Cell, RefCell},
+        sync::atomic::Ordering,
+    };
+
+    use crate::{
+        data_model::{
+            cluster_level_control::{LevelControlCluster, LevelHandler},
+            cluster_on_off::OnOffCluster,
+        },
+        error::ErrorCode,
+        groups::GroupMgr,
+        interaction_model::core::IMStatusCode,
+        test_support::mock_epoch,
+        utils::rand::dummy_rand,
+    };
+
+    use super::{OnOffLevelScenes, ScenesCluster, MAX_SCENES_PER_FABRIC};
+
+    mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+    #[derive(Default)]
+    struct MockDimmer {
+        level: Cell<u8>,
+    }
+
+    impl LevelHandler for MockDimmer {
+        fn set_level(&self, level: u8) {
+            self.level.set(level);
+        }
+    }
+
+    #[test]
+    fn store_recall() {
+        MOCK_NOW_MS.store(10_000, Ordering::SeqCst);
+
+        let group_mgr = RefCell::new(GroupMgr::new());
+        let dimmer = MockDimmer::default();
+        let on_off = OnOffCluster::new(dummy_rand);
+        let level_control =
+            LevelControlCluster::new(&dimmer, Some(&on_off), 1, 254, mock_epoch, dummy_rand);
+
+        let handler = OnOffLevelScenes::new(Some(&on_off), Some(&level_control));
+        let cluster = ScenesCluster::new(&group_mgr, &handler, dummy_rand);
+
+        on_off.on();
+        level_control.move_to_level(200, None, false);
+        assert_eq!(cluster.store_scene(1, 1, 0, 1), IMStatusCode::Success);
+
+        on_off.off();
+        level_control.move_to_level(20, None, false);
+        assert_eq!(cluster.store_scene(1, 1, 0, 2), IMStatusCode::Success);
+
+        // Recall the first scene right away
+        cluster.recall_scene(1, 1, 0, 1, Some(0)).unwrap();
+        assert!(on_off.get());
+        assert_eq!(level_control.current_level(), 200);
+        assert_eq!(dimmer.level.get(), 200);
+        assert_eq!(cluster.current.get(), Some((0, 1)));
+
+        // Recall the second scene over one second
+        cluster.recall_scene(1, 1, 0, 2, Some(10)).unwrap();
+        assert!(!on_off.get());
+        assert!(level_control.is_transitioning());
+
+        MOCK_NOW_MS.store(10_500, Ordering::SeqCst);
+        level_control.tick();
+        assert_eq!(level_control.current_level(), 110);
+
+        MOCK_NOW_MS.store(11_000, Ordering::SeqCst);
+        level_control.tick();
+        assert_eq!(level_control.current_level(), 20);
+        assert!(!level_control.is_transitioning());
+        assert_eq!(cluster.current.get(), Some((0, 2)));
+
+        // Scenes are fabric-scoped
+        assert_eq!(
+            cluster.recall_scene(2, 1, 0, 1, None).map_err(|e| e.code()),
+            Err(ErrorCode::NotFound)
+        );
+    }
+
+    #[test]
+    fn recall_missing_scene() {
+        let group_mgr = RefCell::new(GroupMgr::new());
+        let on_off = OnOffCluster::new(dummy_rand);
+        let handler = OnOffLevelScenes::new(Some(&on_off), None);
+        let cluster = ScenesCluster::new(&group_mgr, &handler, dummy_rand);
+
+        let err = cluster.recall_scene(1, 1, 0, 7, None).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert_eq!(err.to_im_status().status, IMStatusCode::NotFound);
+        assert!(!on_off.get());
+
+        // Scenes of groups the endpoint is not a member of are invalid
+        assert_eq!(
+            cluster
+                .recall_scene(1, 1, 0x10, 7, None)
+                .map_err(|e| e.code()),
+            Err(ErrorCode::InvalidCommand)
+        );
+        assert_eq!(
+            cluster.store_scene(1, 1, 0x10, 7),
+            IMStatusCode::InvalidCommand
+        );
+    }
+
+    #[test]
+    fn table_is_bounded() {
+        let group_mgr = RefCell::new(GroupMgr::new());
+        let on_off = OnOffCluster::new(dummy_rand);
+        let handler = OnOffLevelScenes::new(Some(&on_off), None);
+        let cluster = ScenesCluster::new(&group_mgr, &handler, dummy_rand);
+
+        for scene_id in 0..MAX_SCENES_PER_FABRIC as u8 {
+            assert_eq!(
+                cluster.store_scene(1, 1, 0, scene_id),
+                IMStatusCode::Success
+            );
+        }
+
+        assert_eq!(
+            cluster.store_scene(1, 1, 0, 100),
+            IMStatusCode::ResourceExhausted
+        );
+
+        // Storing an existing scene again replaces it
+        assert_eq!(cluster.store_scene(1, 1, 0, 0), IMStatusCode::Success);
+        // Other fabrics have their own share of the table
+        assert_eq!(cluster.store_scene(2, 1, 0, 100), IMStatusCode::Success);
+
+        assert_eq!(
+            cluster.remove_scenes(|scene| scene.fab_idx == 1),
+            MAX_SCENES_PER_FABRIC
+        );
+        assert_eq!(cluster.scenes.borrow().len(), 1);
+    }
+
+    #[test]
+    fn data_version() {
+        let group_mgr = RefCell::new(GroupMgr::new());
+        let on_off = OnOffCluster::new(dummy_rand);
+        let handler = OnOffLevelScenes::new(Some(&on_off), None);
+        let cluster = ScenesCluster::new(&group_mgr, &handler, dummy_rand);
+
+        let ver = cluster.data_ver.get();
+        assert_eq!(cluster.store_scene(1, 1, 0, 1), IMStatusCode::Success);
+        assert_ne!(cluster.data_ver.get(), ver);
+
+        // Neither replacing the current scene, nor recalling it again, nor removing
+        // no scene changes any attribute
+        let ver = cluster.data_ver.get();
+        assert_eq!(cluster.store_scene(1, 1, 0, 1), IMStatusCode::Success);
+        cluster.recall_scene(1, 1, 0, 1, Some(0)).unwrap();
+        assert_eq!(cluster.remove_scenes(|scene| scene.scene_id == 9), 0);
+        assert_eq!(cluster.data_ver.get(), ver);
+
+        assert_eq!(cluster.remove_scenes(|scene| scene.scene_id == 1), 1);
+        assert_ne!(cluster.data_ver.get(), ver);
+    }
+}
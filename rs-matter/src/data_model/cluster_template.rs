@@ -30,9 +30,15 @@ const CLUSTER_NETWORK_COMMISSIONING_ID: u32 = 0x0031;
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: CLUSTER_NETWORK_COMMISSIONING_ID as _,
+    revision: 1,
     feature_map: 0,
     attributes: &[FEATURE_MAP, ATTRIBUTE_LIST],
     commands: &[],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
 };
 
 pub struct TemplateCluster {
@@ -0,0 +1,506 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::Cell;
+use core::convert::TryInto;
+
+use super::objects::*;
+use crate::{
+    attribute_enum, cmd_enter, command_enum,
+    error::{Error, ErrorCode},
+    tlv::{FromTLV, Nullable, TLVElement},
+    transport::exchange::Exchange,
+    utils::rand::Rand,
+};
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0201;
+
+/// The Heating feature
+pub const FEATURE_HEATING: u32 = 0x01;
+/// The Cooling feature
+pub const FEATURE_COOLING: u32 = 0x02;
+/// The AutoMode feature, where the heating and cooling setpoints are kept apart by a deadband
+pub const FEATURE_AUTO_MODE: u32 = 0x20;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    LocalTemperature(AttrType<Nullable<i16>>) = 0x00,
+    OccupiedCoolingSetpoint(AttrType<i16>) = 0x11,
+    OccupiedHeatingSetpoint(AttrType<i16>) = 0x12,
+    MinHeatSetpointLimit(AttrType<i16>) = 0x15,
+    MaxHeatSetpointLimit(AttrType<i16>) = 0x16,
+    MinCoolSetpointLimit(AttrType<i16>) = 0x17,
+    MaxCoolSetpointLimit(AttrType<i16>) = 0x18,
+    MinSetpointDeadBand(AttrType<i8>) = 0x19,
+    ControlSequenceOfOperation(AttrType<u8>) = 0x1B,
+    SystemMode(AttrType<u8>) = 0x1C,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    SetpointRaiseLower = 0x00,
+}
+
+command_enum!(Commands);
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 6,
+    feature_map: FEATURE_HEATING | FEATURE_COOLING | FEATURE_AUTO_MODE,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::LocalTemperature as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::OccupiedCoolingSetpoint as u16,
+            Access::RWVO,
+            Quality::SN,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::OccupiedHeatingSetpoint as u16,
+            Access::RWVO,
+            Quality::SN,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MinHeatSetpointLimit as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MaxHeatSetpointLimit as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MinCoolSetpointLimit as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MaxCoolSetpointLimit as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::MinSetpointDeadBand as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::ControlSequenceOfOperation as u16,
+            Access::RWVM,
+            Quality::PERSISTENT,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::SystemMode as u16,
+            Access::RWVM,
+            Quality::SN,
+        ),
+    ],
+    commands: &[Commands::SetpointRaiseLower as _],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+#[repr(u8)]
+pub enum SystemMode {
+    Off = 0,
+    Auto = 1,
+    Cool = 3,
+    Heat = 4,
+    EmergencyHeat = 5,
+    Precooling = 6,
+    FanOnly = 7,
+    Dry = 8,
+    Sleep = 9,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+#[repr(u8)]
+pub enum ControlSequence {
+    CoolingOnly = 0,
+    CoolingWithReheat = 1,
+    HeatingOnly = 2,
+    HeatingWithReheat = 3,
+    CoolingAndHeating = 4,
+    CoolingAndHeatingWithReheat = 5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+#[repr(u8)]
+pub enum SetpointRaiseLowerMode {
+    Heat = 0,
+    Cool = 1,
+    Both = 2,
+}
+
+/// The limits of the setpoints of a thermostat, in hundredths of a degree Celsius
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetpointLimits {
+    pub min_heat: i16,
+    pub max_heat: i16,
+    pub min_cool: i16,
+    pub max_cool: i16,
+    /// The least the heating setpoint is below the cooling one, in tenths of a degree
+    pub min_deadband: i8,
+}
+
+impl SetpointLimits {
+    /// The limits the Matter specification defaults to
+    pub const DEFAULT: Self = Self {
+        min_heat: 700,
+        max_heat: 3000,
+        min_cool: 1600,
+        max_cool: 3200,
+        min_deadband: 25,
+    };
+
+    fn deadband(&self) -> i16 {
+        self.min_deadband as i16 * 10
+    }
+}
+
+#[derive(FromTLV)]
+struct SetpointRaiseLowerReq {
+    mode: u8,
+    /// In tenths of a degree
+    amount: i8,
+}
+
+pub struct ThermostatCluster {
+    data_ver: Dataver,
+    limits: SetpointLimits,
+    local_temperature: Cell<Option<i16>>,
+    heating_setpoint: Cell<i16>,
+    cooling_setpoint: Cell<i16>,
+    control_sequence: Cell<ControlSequence>,
+    system_mode: Cell<SystemMode>,
+}
+
+impl ThermostatCluster {
+    pub fn new(limits: SetpointLimits, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            limits,
+            local_temperature: Cell::new(None),
+            heating_setpoint: Cell::new(2000),
+            cooling_setpoint: Cell::new(2600),
+            control_sequence: Cell::new(ControlSequence::CoolingAndHeating),
+            system_mode: Cell::new(SystemMode::Auto),
+        }
+    }
+
+    pub fn heating_setpoint(&self) -> i16 {
+        self.heating_setpoint.get()
+    }
+
+    pub fn cooling_setpoint(&self) -> i16 {
+        self.cooling_setpoint.get()
+    }
+
+    pub fn system_mode(&self) -> SystemMode {
+        self.system_mode.get()
+    }
+
+    /// Report the temperature the sensor of the thermostat measures, if any
+    pub fn set_local_temperature(&self, temperature: Option<i16>) {
+        if self.local_temperature.get() != temperature {
+            self.local_temperature.set(temperature);
+            self.data_ver.changed();
+        }
+    }
+
+    /// Set the heating setpoint, which must be within its limits and below the
+    /// cooling setpoint by at least the deadband
+    pub fn set_heating_setpoint(&self, setpoint: i16) -> Result<(), Error> {
+        if setpoint < self.limits.min_heat
+            || setpoint > self.limits.max_heat
+            || setpoint > self.cooling_setpoint.get() - self.limits.deadband()
+        {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        self.heating_setpoint.set(setpoint);
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    /// Set the cooling setpoint, which must be within its limits and above the
+    /// heating setpoint by at least the deadband
+    pub fn set_cooling_setpoint(&self, setpoint: i16) -> Result<(), Error> {
+        if setpoint < self.limits.min_cool
+            || setpoint > self.limits.max_cool
+            || setpoint < self.heating_setpoint.get() + self.limits.deadband()
+        {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        self.cooling_setpoint.set(setpoint);
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    /// Move the setpoints by the amount, in tenths of a degree
+    ///
+    /// Rather than failing, the setpoints stop at their limits. The other setpoint
+    /// is pushed along when needed so as to keep the deadband, as far as its own
+    /// limits let it.
+    pub fn setpoint_raise_lower(&self, mode: SetpointRaiseLowerMode, amount: i8) {
+        let limits = &self.limits;
+        let amount = amount as i16 * 10;
+        let deadband = limits.deadband();
+
+        let mut heating = self.heating_setpoint.get();
+        let mut cooling = self.cooling_setpoint.get();
+
+        if matches!(
+            mode,
+            SetpointRaiseLowerMode::Heat | SetpointRaiseLowerMode::Both
+        ) {
+            heating = heating
+                .saturating_add(amount)
+                .clamp(limits.min_heat, limits.max_heat);
+        }
+
+        if matches!(
+            mode,
+            SetpointRaiseLowerMode::Cool | SetpointRaiseLowerMode::Both
+        ) {
+            cooling = cooling
+                .saturating_add(amount)
+                .clamp(limits.min_cool, limits.max_cool);
+        }
+
+        if cooling - heating < deadband {
+            if mode == SetpointRaiseLowerMode::Cool {
+                heating = (cooling - deadband).max(limits.min_heat);
+                cooling = heating + deadband;
+            } else {
+                cooling = (heating + deadband).min(limits.max_cool);
+                heating = cooling - deadband;
+            }
+        }
+
+        self.heating_setpoint.set(heating);
+        self.cooling_setpoint.set(cooling);
+        self.data_ver.changed();
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::LocalTemperature(codec) => codec.encode(
+                        writer,
+                        self.local_temperature
+                            .get()
+                            .map(Nullable::NotNull)
+                            .unwrap_or(Nullable::Null),
+                    ),
+                    Attributes::OccupiedCoolingSetpoint(codec) => {
+                        codec.encode(writer, self.cooling_setpoint.get())
+                    }
+                    Attributes::OccupiedHeatingSetpoint(codec) => {
+                        codec.encode(writer, self.heating_setpoint.get())
+                    }
+                    Attributes::MinHeatSetpointLimit(codec) => {
+                        codec.encode(writer, self.limits.min_heat)
+                    }
+                    Attributes::MaxHeatSetpointLimit(codec) => {
+                        codec.encode(writer, self.limits.max_heat)
+                    }
+                    Attributes::MinCoolSetpointLimit(codec) => {
+                        codec.encode(writer, self.limits.min_cool)
+                    }
+                    Attributes::MaxCoolSetpointLimit(codec) => {
+                        codec.encode(writer, self.limits.max_cool)
+                    }
+                    Attributes::MinSetpointDeadBand(codec) => {
+                        codec.encode(writer, self.limits.min_deadband)
+                    }
+                    Attributes::ControlSequenceOfOperation(codec) => {
+                        codec.encode(writer, self.control_sequence.get() as _)
+                    }
+                    Attributes::SystemMode(codec) => {
+                        codec.encode(writer, self.system_mode.get() as _)
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        let data = data.with_dataver(self.data_ver.get())?;
+
+        match attr.attr_id.try_into()? {
+            Attributes::OccupiedCoolingSetpoint(codec) => {
+                self.set_cooling_setpoint(codec.decode(data)?)
+            }
+            Attributes::OccupiedHeatingSetpoint(codec) => {
+                self.set_heating_setpoint(codec.decode(data)?)
+            }
+            Attributes::ControlSequenceOfOperation(codec) => {
+                let sequence = ControlSequence::from_repr(codec.decode(data)?)
+                    .ok_or(ErrorCode::ConstraintError)?;
+
+                self.control_sequence.set(sequence);
+                self.data_ver.changed();
+
+                Ok(())
+            }
+            Attributes::SystemMode(codec) => {
+                let mode =
+                    SystemMode::from_repr(codec.decode(data)?).ok_or(ErrorCode::ConstraintError)?;
+
+                self.system_mode.set(mode);
+                self.data_ver.changed();
+
+                Ok(())
+            }
+            _ => Err(ErrorCode::UnsupportedAccess.into()),
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        _encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::SetpointRaiseLower => self.handle_command_setpointraiselower(data),
+        }
+    }
+
+    fn handle_command_setpointraiselower(&self, data: &TLVElement) -> Result<(), Error> {
+        cmd_enter!("SetpointRaiseLower");
+
+        let req = SetpointRaiseLowerReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        let mode = SetpointRaiseLowerMode::from_repr(req.mode).ok_or(ErrorCode::InvalidCommand)?;
+
+        self.setpoint_raise_lower(mode, req.amount);
+
+        Ok(())
+    }
+}
+
+impl Handler for ThermostatCluster {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        ThermostatCluster::read(self, attr, encoder)
+    }
+
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        ThermostatCluster::write(self, attr, data)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        ThermostatCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl NonBlockingHandler for ThermostatCluster {}
+
+impl ChangeNotifier<()> for ThermostatCluster {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::ErrorCode, utils::rand::dummy_rand};
+
+    use super::{SetpointLimits, SetpointRaiseLowerMode, ThermostatCluster};
+
+    #[test]
+    /// Setpoints closer than the deadband are rejected, without changing either
+    fn deadband_enforced() {
+        let cluster = ThermostatCluster::new(SetpointLimits::DEFAULT, dummy_rand);
+        assert_eq!(cluster.heating_setpoint(), 2000);
+        assert_eq!(cluster.cooling_setpoint(), 2600);
+
+        // 2.5 degrees of deadband
+        assert_eq!(
+            cluster.set_heating_setpoint(2400).map_err(|e| e.code()),
+            Err(ErrorCode::ConstraintError)
+        );
+        assert_eq!(
+            cluster.set_cooling_setpoint(2200).map_err(|e| e.code()),
+            Err(ErrorCode::ConstraintError)
+        );
+        assert_eq!(cluster.heating_setpoint(), 2000);
+        assert_eq!(cluster.cooling_setpoint(), 2600);
+
+        assert!(cluster.set_heating_setpoint(2350).is_ok());
+        assert!(cluster.set_cooling_setpoint(2350).is_err());
+
+        // Out of the limits
+        assert!(cluster.set_heating_setpoint(600).is_err());
+        assert!(cluster.set_cooling_setpoint(3300).is_err());
+    }
+
+    #[test]
+    /// Raising the heating setpoint stops at its limit, pushing the cooling setpoint along
+    fn raise_clamps_at_max_heat() {
+        let cluster = ThermostatCluster::new(
+            SetpointLimits {
+                max_cool: 3500,
+                ..SetpointLimits::DEFAULT
+            },
+            dummy_rand,
+        );
+
+        // 12.7 degrees up from 20
+        cluster.setpoint_raise_lower(SetpointRaiseLowerMode::Heat, 127);
+        assert_eq!(cluster.heating_setpoint(), 3000);
+        assert_eq!(cluster.cooling_setpoint(), 3250);
+
+        cluster.setpoint_raise_lower(SetpointRaiseLowerMode::Heat, 10);
+        assert_eq!(cluster.heating_setpoint(), 3000);
+
+        cluster.setpoint_raise_lower(SetpointRaiseLowerMode::Both, -128);
+        assert_eq!(cluster.heating_setpoint(), 1720);
+        assert_eq!(cluster.cooling_setpoint(), 1970);
+    }
+}
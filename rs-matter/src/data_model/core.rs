@@ -15,24 +15,74 @@
  *    limitations under the License.
  */
 
-use portable_atomic::{AtomicU32, Ordering};
+use core::{cell::RefCell, time::Duration};
+
+use log::{error, warn};
 
 use super::objects::*;
+use super::subscriptions::{DirtySet, Subscription, SubscriptionInfo, SubscriptionMgr};
 use crate::{
     alloc,
     error::*,
-    interaction_model::core::Interaction,
+    interaction_model::{
+        core::{Interaction, OpCode, SubscribeDriver, WriteChunks},
+        messages::{
+            ib::{AttrStatus, CmdStatus},
+            msg::SubscribeReq,
+            GenericPath,
+        },
+    },
+    tlv::TLVElement,
     transport::{exchange::Exchange, packet::Packet},
+    utils::timer::{EmbassyTimer, Timer},
 };
 
-// TODO: For now...
-static SUBS_ID: AtomicU32 = AtomicU32::new(1);
+/// How long an asynchronous command handler may take, before the command is failed
+/// with a `Busy` status
+pub const DEFAULT_CMD_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub struct DataModel<T>(T);
+/// The data model of the node, with its command timeout awaited with the timer `M`
+pub struct DataModel<'a, T, M = EmbassyTimer> {
+    handler: T,
+    subscriptions: &'a RefCell<SubscriptionMgr>,
+    cmd_timeout: Duration,
+    timer: M,
+}
 
-impl<T> DataModel<T> {
-    pub fn new(handler: T) -> Self {
-        Self(handler)
+impl<'a, T> DataModel<'a, T> {
+    pub fn new(handler: T, subscriptions: &'a RefCell<SubscriptionMgr>) -> Self {
+        Self {
+            handler,
+            subscriptions,
+            cmd_timeout: DEFAULT_CMD_TIMEOUT,
+            timer: EmbassyTimer,
+        }
+    }
+}
+
+impl<'a, T, M> DataModel<'a, T, M> {
+    pub fn with_cmd_timeout(mut self, cmd_timeout: Duration) -> Self {
+        self.cmd_timeout = cmd_timeout;
+        self
+    }
+
+    /// Await the command timeout with the provided timer rather than with the time driver
+    /// of `embassy-time`
+    pub fn with_timer<M2>(self, timer: M2) -> DataModel<'a, T, M2> {
+        DataModel {
+            handler: self.handler,
+            subscriptions: self.subscriptions,
+            cmd_timeout: self.cmd_timeout,
+            timer,
+        }
+    }
+
+    /// All subscriptions currently active across all fabrics.
+    ///
+    /// The returned iterator is a snapshot, so this is safe to call while
+    /// interactions are in flight.
+    pub fn active_subscriptions(&self) -> impl Iterator<Item = SubscriptionInfo> {
+        self.subscriptions.borrow().infos().into_iter()
     }
 
     pub async fn handle<'r, 'p>(
@@ -44,98 +94,346 @@ impl<T> DataModel<T> {
     ) -> Result<(), Error>
     where
         T: DataModelHandler,
+        M: Timer,
     {
+        if exchange.id().session_id.is_group
+            && rx.get_proto_raw_opcode() != OpCode::InvokeRequest as u8
+        {
+            // Only invokes are allowed over groupcast
+            warn!(
+                "Dropping group message with opcode {}",
+                rx.get_proto_raw_opcode()
+            );
+            return Ok(());
+        }
+
         let timeout = Interaction::timeout(exchange, rx, tx).await?;
 
-        let mut interaction = alloc!(Interaction::new(
-            exchange,
-            rx,
-            tx,
-            rx_status,
-            || SUBS_ID.fetch_add(1, Ordering::SeqCst),
-            timeout,
-        )?);
+        #[cfg(feature = "nightly")]
+        let metadata = self.handler.lock().await;
+
+        #[cfg(not(feature = "nightly"))]
+        let metadata = self.handler.lock();
+
+        // A chunked write keeps the exchange open until its last chunk arrives, with the
+        // timed interaction (if any) applying to all of its chunks
+        let mut chunks = WriteChunks::default();
+
+        loop {
+            let more_chunks = {
+                let mut interaction = alloc!(Interaction::new(
+                    exchange,
+                    rx,
+                    tx,
+                    rx_status,
+                    |exchange, req| {
+                        let (fab_idx, peer_node_id, sess_id) = exchange.with_session(|sess| {
+                            Ok((
+                                sess.get_local_fabric_idx().unwrap_or_default(),
+                                sess.get_peer_node_id().unwrap_or_default(),
+                                sess.get_local_sess_id(),
+                            ))
+                        })?;
+
+                        let subscription = self.subscriptions.borrow_mut().add(
+                            fab_idx,
+                            peer_node_id,
+                            sess_id,
+                            req,
+                        )?;
 
-        #[cfg(feature = "alloc")]
-        let interaction = &mut *interaction;
+                        // The reporting may be idle, awaiting nothing, so wake it up
+                        // to schedule the max interval of the new subscription
+                        exchange.notify_subscriptions();
 
-        #[cfg(not(feature = "alloc"))]
-        let interaction = &mut interaction;
+                        Ok(subscription)
+                    },
+                    timeout,
+                )?);
+
+                #[cfg(feature = "alloc")]
+                let interaction = &mut *interaction;
+
+                #[cfg(not(feature = "alloc"))]
+                let interaction = &mut interaction;
+
+                if interaction.start().await? {
+                    self.process(&metadata.node(), interaction, &mut chunks)
+                        .await?
+                } else {
+                    false
+                }
+            };
+
+            if !more_chunks {
+                break;
+            }
+
+            exchange.exchange(tx, rx).await?;
+
+            let opcode: OpCode = rx.get_proto_opcode()?;
+            if opcode != OpCode::WriteRequest {
+                error!("Expected the next chunk of a write, got: {:?}", opcode);
+                Err(ErrorCode::InvalidOpcode)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Push a report of the subscription to the subscriber, on the provided exchange,
+    /// which we initiated on the session of the subscription.
+    ///
+    /// Returns `false` if the peer rejected the report, in which case the subscription is dead.
+    pub async fn report<'r, 'p>(
+        &self,
+        exchange: &'r mut Exchange<'_>,
+        subscription: &Subscription,
+        tx: &'r mut Packet<'p>,
+        rx_status: &'r mut Packet<'p>,
+    ) -> Result<bool, Error>
+    where
+        T: DataModelHandler,
+    {
+        let mut req = subscription.req()?;
+
+        // Only the attributes which changed since the last report are reported,
+        // so the data version filters of the original request no longer apply
+        req.dataver_filters = None;
+
+        if subscription.dirty.is_empty() && !subscription.events_pending {
+            // Nothing changed and the max interval elapsed, so this is a keep-alive report,
+            // which carries nothing but the subscription ID
+            req.attr_requests = None;
+            req.event_requests = None;
+        }
 
         #[cfg(feature = "nightly")]
-        let metadata = self.0.lock().await;
+        let metadata = self.handler.lock().await;
 
         #[cfg(not(feature = "nightly"))]
-        let metadata = self.0.lock();
-
-        if interaction.start().await? {
-            match interaction {
-                Interaction::Read {
-                    req,
-                    ref mut driver,
-                } => {
-                    let accessor = driver.accessor()?;
-
-                    'outer: for item in metadata.node().read(req, None, &accessor) {
-                        while !AttrDataEncoder::handle_read(&item, &self.0, &mut driver.writer()?)
-                            .await?
-                        {
-                            if !driver.send_chunk(req).await? {
-                                break 'outer;
-                            }
+        let metadata = self.handler.lock();
+
+        let mut driver = SubscribeDriver::new_report(exchange, subscription, &req, tx, rx_status)?;
+
+        self.subscribe(
+            &metadata.node(),
+            &req,
+            Some(&subscription.dirty),
+            &mut driver,
+        )
+        .await
+    }
+
+    /// Returns `true` if this is a write which is to be continued with more chunks
+    async fn process(
+        &self,
+        node: &Node<'_>,
+        interaction: &mut Interaction<'_, '_, '_>,
+        chunks: &mut WriteChunks,
+    ) -> Result<bool, Error>
+    where
+        T: DataModelHandler,
+        M: Timer,
+    {
+        match interaction {
+            Interaction::Read {
+                req,
+                ref mut driver,
+            } => {
+                let accessor = driver.accessor()?;
+                let resume = ResumeRead::new();
+
+                'outer: for item in node.read(req, None, &accessor) {
+                    if self.prune_read(&item) {
+                        continue;
+                    }
+
+                    while !AttrDataEncoder::handle_read(
+                        &item,
+                        &self.handler,
+                        &mut driver.writer()?,
+                        &resume,
+                    )
+                    .await?
+                    {
+                        if !driver.send_chunk(req).await? {
+                            break 'outer;
                         }
                     }
+                }
 
-                    driver.complete(req).await?;
+                driver.complete(req).await?;
+            }
+            Interaction::Write {
+                req,
+                ref mut driver,
+            } => {
+                let accessor = driver.accessor()?;
+                let mut failed = false;
+
+                driver.resume(chunks)?;
+
+                for item in node.write(req, driver.is_timed(), &accessor) {
+                    if !AttrDataEncoder::handle_write(&item, &self.handler, &mut driver.writer()?)
+                        .await?
+                    {
+                        failed = true;
+                    } else if let Ok((attr, _)) = &item {
+                        let dirty = self.subscriptions.borrow_mut().mark_dirty(
+                            attr.endpoint_id,
+                            attr.cluster_id,
+                            attr.attr_id,
+                        );
+
+                        if dirty {
+                            driver.notify_subscriptions();
+                        }
+                    }
                 }
-                Interaction::Write {
-                    req,
-                    ref mut driver,
-                } => {
-                    let accessor = driver.accessor()?;
-
-                    for item in metadata.node().write(req, &accessor) {
-                        AttrDataEncoder::handle_write(&item, &self.0, &mut driver.writer()?)
-                            .await?;
+
+                return driver.complete(req, failed, chunks).await;
+            }
+            Interaction::Invoke {
+                req,
+                ref mut driver,
+            } => {
+                let accessor = driver.accessor()?;
+                let group = driver.group()?;
+
+                for item in node.invoke(req, &accessor) {
+                    if let Some(group) = &group {
+                        if !Self::is_groupcastable(node, &item, |ep| {
+                            driver.is_group_member(group, ep)
+                        }) {
+                            continue;
+                        }
                     }
 
-                    driver.complete(req).await?;
+                    let (mut tw, exchange) = driver.writer_exchange()?;
+
+                    CmdDataEncoder::handle(
+                        &item,
+                        &self.handler,
+                        &mut tw,
+                        exchange,
+                        self.cmd_timeout,
+                        &self.timer,
+                    )
+                    .await?;
                 }
-                Interaction::Invoke {
-                    req,
-                    ref mut driver,
-                } => {
-                    let accessor = driver.accessor()?;
 
-                    for item in metadata.node().invoke(req, &accessor) {
-                        let (mut tw, exchange) = driver.writer_exchange()?;
+                driver.complete(req).await?;
+            }
+            Interaction::Subscribe {
+                req,
+                ref mut driver,
+            } => {
+                let established = self.subscribe(node, req, None, driver).await;
 
-                        CmdDataEncoder::handle(&item, &self.0, &mut tw, exchange).await?;
-                    }
+                if !matches!(established, Ok(true)) {
+                    // Either the peer rejected the priming reports or we failed
+                    // midway; in both cases the subscription is dead
+                    self.subscriptions
+                        .borrow_mut()
+                        .remove(driver.subscription_id());
+                }
+
+                established?;
+            }
+        }
+
+        Ok(false)
+    }
 
-                    driver.complete(req).await?;
+    /// Report the subscribed attributes or - with a dirty set - only these of them
+    /// which changed since the last report
+    async fn subscribe(
+        &self,
+        node: &Node<'_>,
+        req: &SubscribeReq<'_>,
+        dirty: Option<&DirtySet>,
+        driver: &mut SubscribeDriver<'_, '_, '_>,
+    ) -> Result<bool, Error>
+    where
+        T: DataModelHandler,
+    {
+        let accessor = driver.accessor()?;
+        let resume = ResumeRead::new();
+
+        'outer: for item in node.subscribing_read(req, None, &accessor) {
+            if self.prune_read(&item) {
+                continue;
+            }
+
+            if let Some(dirty) = dirty {
+                // The errors of the subscribed paths were already reported when priming
+                let changed = match &item {
+                    Ok(attr) => dirty.is_dirty(attr.endpoint_id, attr.cluster_id, attr.attr_id),
+                    Err(_) => false,
+                };
+
+                if !changed {
+                    continue;
                 }
-                Interaction::Subscribe {
-                    req,
-                    ref mut driver,
-                } => {
-                    let accessor = driver.accessor()?;
-
-                    'outer: for item in metadata.node().subscribing_read(req, None, &accessor) {
-                        while !AttrDataEncoder::handle_read(&item, &self.0, &mut driver.writer()?)
-                            .await?
-                        {
-                            if !driver.send_chunk(req).await? {
-                                break 'outer;
-                            }
-                        }
-                    }
+            }
 
-                    driver.complete(req).await?;
+            while !AttrDataEncoder::handle_read(
+                &item,
+                &self.handler,
+                &mut driver.writer()?,
+                &resume,
+            )
+            .await?
+            {
+                if !driver.send_chunk(req).await? {
+                    break 'outer;
                 }
             }
         }
 
-        Ok(())
+        driver.complete(req).await
+    }
+
+    /// Whether the command of a group invoke is to be executed.
+    ///
+    /// Commands which failed, which target an endpoint outside of the group or which
+    /// respond with data are silently dropped, as nothing is sent back for a group invoke.
+    fn is_groupcastable<F>(
+        node: &Node<'_>,
+        item: &Result<(CmdDetails<'_>, TLVElement<'_>), CmdStatus>,
+        is_member: F,
+    ) -> bool
+    where
+        F: FnOnce(EndptId) -> bool,
+    {
+        let Ok((cmd, _)) = item else {
+            return false;
+        };
+
+        let responds = node
+            .endpoints
+            .iter()
+            .find(|ep| ep.id == cmd.endpoint_id)
+            .and_then(|ep| ep.clusters.iter().find(|cl| cl.id == cmd.cluster_id))
+            .map(|cl| cl.is_response_command(cmd.cmd_id))
+            .unwrap_or(true);
+
+        !responds && is_member(cmd.endpoint_id)
+    }
+
+    /// Whether the handler asked for this attribute of a wildcard read to be left out
+    fn prune_read(&self, item: &Result<AttrDetails<'_>, AttrStatus>) -> bool
+    where
+        T: DataModelHandler,
+    {
+        match item {
+            Ok(attr) if attr.wildcard => self.handler.prune_read(&GenericPath::new(
+                Some(attr.endpoint_id),
+                Some(attr.cluster_id),
+                Some(attr.attr_id as _),
+            )),
+            _ => false,
+        }
     }
 }
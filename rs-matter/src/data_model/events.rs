@@ -0,0 +1,409 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::RefCell;
+
+use log::info;
+
+use crate::{
+    acl::Accessor,
+    data_model::objects::{Access, Cluster, ClusterId, EndptId},
+    error::{Error, ErrorCode},
+    interaction_model::messages::{
+        ib::{EventDataTag, EventFilter, EventPath, EventRespTag},
+        GenericPath,
+    },
+    tlv::{TLVArray, TLVWriter, TagType, ToTLV},
+    utils::{epoch::Epoch, writebuf::WriteBuf},
+};
+
+/// The number of events we keep around for controllers to read, across all priorities
+pub const MAX_EVENTS: usize = 16;
+
+/// The default number of events kept for each priority, indexed by `EventPriority`
+pub const DEFAULT_EVENT_CAPACITIES: [usize; 3] = [4, 8, 4];
+
+/// The maximum size of the TLV-encoded payload of a single event
+///
+/// This is large enough for the AccessControl events, which carry a whole entry of
+/// the ACL or extension list
+pub const MAX_EVENT_PAYLOAD_SIZE: usize = 160;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum EventPriority {
+    Debug = 0,
+    Info = 1,
+    Critical = 2,
+}
+
+/// A logged event, along with its already-encoded payload
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub number: u64,
+    pub endpoint: EndptId,
+    pub cluster: ClusterId,
+    pub event_id: u32,
+    pub priority: EventPriority,
+    /// Whether the event is to be reported to the subscribers right away, rather
+    /// than with the next report allowed by their min interval
+    pub urgent: bool,
+    /// Milliseconds since the UNIX epoch
+    pub epoch_ts: u64,
+    /// The payload, encoded with the context tag of the Data field of the EventDataIB
+    payload: heapless::Vec<u8, MAX_EVENT_PAYLOAD_SIZE>,
+}
+
+impl Event {
+    pub fn path(&self) -> EventPath {
+        EventPath::new(&GenericPath::new(
+            Some(self.endpoint),
+            Some(self.cluster),
+            Some(self.event_id),
+        ))
+    }
+
+    /// Whether the event is requested by the provided (possibly wildcard) path
+    pub fn matches(&self, path: &EventPath) -> bool {
+        path.endpoint.map(|ep| ep == self.endpoint).unwrap_or(true)
+            && path.cluster.map(|cl| cl == self.cluster).unwrap_or(true)
+            && path.event.map(|ev| ev == self.event_id).unwrap_or(true)
+    }
+
+    /// Whether the event passes all of the provided filters
+    pub fn is_filtered_in(&self, filters: Option<&TLVArray<EventFilter>>) -> bool {
+        filters
+            .map(|filters| {
+                filters.iter().all(|filter| {
+                    filter
+                        .event_min
+                        .map(|event_min| self.number >= event_min)
+                        .unwrap_or(true)
+                })
+            })
+            .unwrap_or(true)
+    }
+
+    /// Events require the same privileges as reading an attribute of their cluster
+    pub fn is_accessible(&self, accessor: &Accessor) -> bool {
+        Cluster::check_attr_access(
+            accessor,
+            GenericPath::new(Some(self.endpoint), Some(self.cluster), Some(self.event_id)),
+            false,
+            Access::RV,
+        )
+        .is_ok()
+    }
+}
+
+impl ToTLV for Event {
+    /// Encode the event as an EventReportIB
+    fn to_tlv(&self, tw: &mut TLVWriter, tag_type: TagType) -> Result<(), Error> {
+        tw.start_struct(tag_type)?;
+        tw.start_struct(TagType::Context(EventRespTag::Data as u8))?;
+
+        self.path()
+            .to_tlv(tw, TagType::Context(EventDataTag::Path as u8))?;
+        tw.u64(
+            TagType::Context(EventDataTag::EventNumber as u8),
+            self.number,
+        )?;
+        tw.u8(
+            TagType::Context(EventDataTag::Priority as u8),
+            self.priority as u8,
+        )?;
+        tw.u64(TagType::Context(EventDataTag::EpochTs as u8), self.epoch_ts)?;
+        tw.get_buf().append(&self.payload)?;
+
+        tw.end_container()?;
+        tw.end_container()
+    }
+}
+
+/// The logged events, with a bounded queue per priority
+///
+/// Once the queue of a priority is full, its oldest event is evicted, so a flood of
+/// events of one priority never pushes out those of another. Event numbers keep
+/// increasing across evictions.
+pub struct EventMgr {
+    epoch: Epoch,
+    next_number: u64,
+    capacities: [usize; 3],
+    /// Sorted by event number
+    events: heapless::Vec<Event, MAX_EVENTS>,
+}
+
+impl EventMgr {
+    #[inline(always)]
+    pub const fn new(epoch: Epoch) -> Self {
+        Self {
+            epoch,
+            next_number: 0,
+            capacities: DEFAULT_EVENT_CAPACITIES,
+            events: heapless::Vec::new(),
+        }
+    }
+
+    /// Set the number of events kept for each priority, indexed by `EventPriority`.
+    ///
+    /// The capacities cannot add up to more than `MAX_EVENTS`. Events beyond the new
+    /// capacity of their priority are evicted, oldest first.
+    pub fn set_capacities(&mut self, capacities: [usize; 3]) -> Result<(), Error> {
+        if capacities.iter().sum::<usize>() > MAX_EVENTS {
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        self.capacities = capacities;
+
+        for priority in [
+            EventPriority::Debug,
+            EventPriority::Info,
+            EventPriority::Critical,
+        ] {
+            while self.count(priority) > self.capacity(priority) {
+                self.evict_oldest(priority);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn capacity(&self, priority: EventPriority) -> usize {
+        self.capacities[priority as usize]
+    }
+
+    /// Log a new event and return its event number.
+    ///
+    /// Note that the subscriptions are only notified of the events logged with
+    /// `Matter::log_event`, i.e. with the `EventLogger` of the `Matter` instance.
+    ///
+    /// Once the queue of the event's priority is full, its oldest event is evicted.
+    /// An event of a priority with no capacity is numbered, but not kept.
+    pub fn log(
+        &mut self,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        event_id: u32,
+        priority: EventPriority,
+        urgent: bool,
+        payload: &dyn ToTLV,
+    ) -> Result<u64, Error> {
+        let mut buf = [0; MAX_EVENT_PAYLOAD_SIZE];
+        let mut wb = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut wb);
+
+        payload.to_tlv(&mut tw, TagType::Context(EventDataTag::Data as u8))?;
+
+        let event = Event {
+            number: self.next_number,
+            endpoint,
+            cluster,
+            event_id,
+            priority,
+            urgent,
+            epoch_ts: (self.epoch)().as_millis() as u64,
+            payload: heapless::Vec::from_slice(wb.as_slice()).map_err(|_| ErrorCode::NoSpace)?,
+        };
+
+        self.next_number += 1;
+
+        info!(
+            "Logged event {} for endpoint {}, cluster {:#x}, event {}",
+            event.number, event.endpoint, event.cluster, event.event_id
+        );
+
+        let number = event.number;
+
+        if self.capacity(priority) > 0 {
+            if self.count(priority) >= self.capacity(priority) {
+                self.evict_oldest(priority);
+            }
+
+            self.events
+                .push(event)
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+        }
+
+        Ok(number)
+    }
+
+    /// The event with the lowest event number which is at least `from` and
+    /// which satisfies the provided predicate
+    ///
+    /// Asking for an event number which is already evicted yields the oldest
+    /// matching event still around.
+    pub fn next<F>(&self, from: u64, f: F) -> Option<&Event>
+    where
+        F: Fn(&Event) -> bool,
+    {
+        self.events
+            .iter()
+            .find(|event| event.number >= from && f(event))
+    }
+
+    /// The lowest event number still available for `priority`, if any
+    pub fn min_number(&self, priority: EventPriority) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|event| event.priority == priority)
+            .map(|event| event.number)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+
+    fn count(&self, priority: EventPriority) -> usize {
+        self.events
+            .iter()
+            .filter(|event| event.priority == priority)
+            .count()
+    }
+
+    fn evict_oldest(&mut self, priority: EventPriority) {
+        if let Some(index) = self
+            .events
+            .iter()
+            .position(|event| event.priority == priority)
+        {
+            self.events.remove(index);
+        }
+    }
+}
+
+/// Where the clusters log their events
+///
+/// The `Matter` instance notifies the subscriptions requesting the events it logs,
+/// while a bare `EventMgr` only keeps them around to be read.
+pub trait EventLogger {
+    /// Log an event and return its event number
+    fn log_event(
+        &self,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        event_id: u32,
+        priority: EventPriority,
+        urgent: bool,
+        payload: &dyn ToTLV,
+    ) -> Result<u64, Error>;
+}
+
+impl EventLogger for RefCell<EventMgr> {
+    fn log_event(
+        &self,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        event_id: u32,
+        priority: EventPriority,
+        urgent: bool,
+        payload: &dyn ToTLV,
+    ) -> Result<u64, Error> {
+        self.borrow_mut()
+            .log(endpoint, cluster, event_id, priority, urgent, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::{EventMgr, EventPriority, DEFAULT_EVENT_CAPACITIES, MAX_EVENTS};
+
+    fn dummy_epoch() -> Duration {
+        Duration::from_millis(1000)
+    }
+
+    #[test]
+    fn test_event_numbers_and_eviction() {
+        let mut mgr = EventMgr::new(dummy_epoch);
+        let info_capacity = DEFAULT_EVENT_CAPACITIES[EventPriority::Info as usize] as u64;
+
+        assert_eq!(
+            mgr.log(1, 6, 0, EventPriority::Critical, false, &1u8)
+                .unwrap(),
+            0
+        );
+        for i in 1..=info_capacity {
+            assert_eq!(
+                mgr.log(1, 6, 0, EventPriority::Info, false, &1u8).unwrap(),
+                i
+            );
+        }
+
+        // The Info queue is full, so its oldest event has to go, but not the Critical one
+        assert_eq!(
+            mgr.log(1, 6, 0, EventPriority::Info, false, &1u8).unwrap(),
+            info_capacity + 1
+        );
+        assert_eq!(mgr.iter().count(), info_capacity as usize + 1);
+        assert_eq!(mgr.next(0, |_| true).map(|e| e.number), Some(0));
+        assert_eq!(mgr.next(1, |_| true).map(|e| e.number), Some(2));
+        assert_eq!(mgr.iter().next().unwrap().epoch_ts, 1000);
+    }
+
+    #[test]
+    /// Filling the Debug queue evicts the oldest Debug events only
+    fn test_debug_overflow_keeps_critical() {
+        let mut mgr = EventMgr::new(dummy_epoch);
+        mgr.set_capacities([4, 4, 2]).unwrap();
+
+        mgr.log(1, 6, 0, EventPriority::Critical, false, &1u8)
+            .unwrap();
+        mgr.log(1, 6, 0, EventPriority::Info, false, &1u8).unwrap();
+        for _ in 0..10 {
+            mgr.log(1, 6, 0, EventPriority::Debug, false, &1u8).unwrap();
+        }
+
+        // Event numbers keep increasing across the evictions
+        assert_eq!(
+            mgr.log(1, 6, 0, EventPriority::Debug, false, &1u8).unwrap(),
+            12
+        );
+
+        assert_eq!(mgr.min_number(EventPriority::Critical), Some(0));
+        assert_eq!(mgr.min_number(EventPriority::Info), Some(1));
+        assert_eq!(mgr.min_number(EventPriority::Debug), Some(9));
+        assert_eq!(mgr.iter().count(), 6);
+
+        // An evicted event number yields the oldest Debug event still around
+        assert_eq!(
+            mgr.next(3, |e| e.priority == EventPriority::Debug)
+                .map(|e| e.number),
+            Some(9)
+        );
+    }
+
+    #[test]
+    fn test_capacities() {
+        let mut mgr = EventMgr::new(dummy_epoch);
+
+        assert!(mgr.set_capacities([MAX_EVENTS, 1, 0]).is_err());
+
+        for _ in 0..3 {
+            mgr.log(1, 6, 0, EventPriority::Info, false, &1u8).unwrap();
+        }
+
+        // Shrinking evicts the oldest events, and no capacity keeps no events
+        mgr.set_capacities([0, 1, 1]).unwrap();
+        assert_eq!(mgr.min_number(EventPriority::Info), Some(2));
+        assert_eq!(
+            mgr.log(1, 6, 0, EventPriority::Debug, false, &1u8).unwrap(),
+            3
+        );
+        assert_eq!(mgr.min_number(EventPriority::Debug), None);
+    }
+}
@@ -17,12 +17,25 @@
 
 pub mod core;
 pub mod device_types;
+pub mod events;
 pub mod objects;
 
 pub mod cluster_basic_information;
+pub mod cluster_binding;
+pub mod cluster_group_key_management;
+pub mod cluster_groups;
+pub mod cluster_identify;
+pub mod cluster_level_control;
 // TODO pub mod cluster_media_playback;
+pub mod cluster_mode_select;
 pub mod cluster_on_off;
+pub mod cluster_ota_provider;
+pub mod cluster_ota_requestor;
+pub mod cluster_power_source;
+pub mod cluster_scenes;
 pub mod cluster_template;
+pub mod cluster_thermostat;
 pub mod root_endpoint;
 pub mod sdm;
+pub mod subscriptions;
 pub mod system_model;
@@ -17,6 +17,8 @@
 #![allow(clippy::bad_bit_mask)]
 
 use crate::data_model::objects::GlobalElements;
+use crate::error::{Error, ErrorCode};
+use crate::tlv::{ElementType, TLVElement};
 
 use super::{AttrId, Privilege};
 use bitflags::bitflags;
@@ -43,6 +45,7 @@ bitflags! {
         const RWVA = Self::READ.bits | Self::WRITE.bits | Self::NEED_VIEW.bits | Self::NEED_ADMIN.bits;
         const RWFA = Self::READ.bits | Self::WRITE.bits | Self::FAB_SCOPED.bits | Self::NEED_ADMIN.bits;
         const RWVM = Self::READ.bits | Self::WRITE.bits | Self::NEED_VIEW.bits | Self::NEED_MANAGE.bits;
+        const RWVO = Self::READ.bits | Self::WRITE.bits | Self::NEED_VIEW.bits | Self::NEED_OPERATE.bits;
     }
 }
 
@@ -82,11 +85,60 @@ bitflags! {
     }
 }
 
+/// A constraint on the values which can be written to an attribute
+///
+/// Null values, as well as values of a type the constraint doesn't apply to, are left
+/// for the handler of the attribute to deal with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constraint {
+    /// An integer within the inclusive range
+    Range(i64, i64),
+    /// A string or an octet string of at most this many bytes
+    MaxLength(usize),
+    /// An integer, which is one of the allowed values of an enum
+    Enum(&'static [u64]),
+}
+
+impl Constraint {
+    pub fn validate(&self, data: &TLVElement) -> Result<(), Error> {
+        let valid = match self {
+            Self::Range(min, max) => {
+                let value = match data.get_element_type() {
+                    ElementType::S8(_)
+                    | ElementType::S16(_)
+                    | ElementType::S32(_)
+                    | ElementType::S64(_) => i128::from(data.i64()?),
+                    ElementType::U8(_)
+                    | ElementType::U16(_)
+                    | ElementType::U32(_)
+                    | ElementType::U64(_) => i128::from(data.u64()?),
+                    _ => return Ok(()),
+                };
+
+                (i128::from(*min)..=i128::from(*max)).contains(&value)
+            }
+            Self::MaxLength(max) => data.slice().map(|s| s.len() <= *max).unwrap_or(true),
+            Self::Enum(values) => data.u64().map(|v| values.contains(&v)).unwrap_or(true),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(ErrorCode::ConstraintError.into())
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Attribute {
     pub id: AttrId,
     pub quality: Quality,
     pub access: Access,
+    /// The constraint that written values are validated against, before reaching the handler
+    pub constraint: Option<Constraint>,
+    /// The feature bits the attribute is conditional upon, or 0 for an attribute
+    /// which is always present
+    pub feature: u32,
 }
 
 impl Attribute {
@@ -95,15 +147,35 @@ impl Attribute {
             id,
             access,
             quality,
+            constraint: None,
+            feature: 0,
+        }
+    }
+
+    pub const fn with_constraint(self, constraint: Constraint) -> Self {
+        Self {
+            constraint: Some(constraint),
+            ..self
         }
     }
 
+    /// Make the attribute present only when the cluster has any of the `feature` bits
+    /// in its feature map
+    pub const fn with_feature(self, feature: u32) -> Self {
+        Self { feature, ..self }
+    }
+
+    /// Whether the attribute is present in a cluster with the provided feature map
+    pub fn is_supported(&self, feature_map: u32) -> bool {
+        self.feature == 0 || self.feature & feature_map != 0
+    }
+
     pub fn is_system(&self) -> bool {
         Self::is_system_attr(self.id)
     }
 
     pub fn is_system_attr(attr_id: AttrId) -> bool {
-        attr_id >= (GlobalElements::ServerGenCmd as AttrId)
+        attr_id >= (GlobalElements::GeneratedCommandList as AttrId)
     }
 }
 
@@ -116,8 +188,11 @@ impl core::fmt::Display for Attribute {
 #[cfg(test)]
 #[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use super::Access;
-    use crate::data_model::objects::Privilege;
+    use super::{Access, Constraint};
+    use crate::data_model::objects::{
+        AttrDetails, Attribute, Cluster, Endpoint, Node, Privilege, Quality,
+    };
+    use crate::tlv::{ElementType, TLVElement, TagType};
 
     #[test]
     fn test_read() {
@@ -183,4 +258,88 @@ mod tests {
         assert_eq!(c.is_ok(Access::WRITE, Privilege::MANAGE), true);
         assert_eq!(c.is_ok(Access::WRITE, Privilege::ADMIN), true);
     }
+
+    #[test]
+    fn test_constraint() {
+        let value = |v| TLVElement::new(TagType::Anonymous, ElementType::U8(v));
+
+        let c = Constraint::Range(0, 100);
+        assert!(c.validate(&value(100)).is_ok());
+        assert!(c.validate(&value(200)).is_err());
+        assert!(c
+            .validate(&TLVElement::new(TagType::Anonymous, ElementType::S8(-1)))
+            .is_err());
+        // Null is left for the handler to check
+        assert!(c
+            .validate(&TLVElement::new(TagType::Anonymous, ElementType::Null))
+            .is_ok());
+
+        let c = Constraint::MaxLength(4);
+        assert!(c
+            .validate(&TLVElement::new(
+                TagType::Anonymous,
+                ElementType::Utf8l(b"1234")
+            ))
+            .is_ok());
+        assert!(c
+            .validate(&TLVElement::new(
+                TagType::Anonymous,
+                ElementType::Utf8l(b"12345")
+            ))
+            .is_err());
+
+        let c = Constraint::Enum(&[0, 2]);
+        assert!(c.validate(&value(2)).is_ok());
+        assert!(c.validate(&value(1)).is_err());
+    }
+
+    #[test]
+    /// A write of a value outside of the range of the attribute is rejected
+    fn test_write_constraint() {
+        const CLUSTER: Cluster<'static> = Cluster {
+            id: 0x1234,
+            revision: 1,
+            feature_map: 0,
+            attributes: &[
+                Attribute::new(0, Access::RWVA, Quality::NONE)
+                    .with_constraint(Constraint::Range(0, 100)),
+                Attribute::new(1, Access::RWVA, Quality::NONE),
+            ],
+            commands: &[],
+            generated_commands: &[],
+            timed_commands: &[],
+            response_commands: &[],
+            manage_commands: &[],
+            admin_commands: &[],
+        };
+
+        let node = Node {
+            id: 0,
+            endpoints: &[Endpoint {
+                id: 1,
+                device_types: &[crate::data_model::device_types::DEV_TYPE_ON_OFF_LIGHT],
+                tags: &[],
+                clusters: &[CLUSTER],
+            }],
+        };
+
+        let attr = |attr_id| AttrDetails {
+            node: &node,
+            endpoint_id: 1,
+            cluster_id: 0x1234,
+            attr_id,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let data = TLVElement::new(TagType::Anonymous, ElementType::U8(200));
+        assert!(attr(0).check_constraint(&data).is_err());
+        assert!(attr(1).check_constraint(&data).is_ok());
+
+        let data = TLVElement::new(TagType::Anonymous, ElementType::U8(50));
+        assert!(attr(0).check_constraint(&data).is_ok());
+    }
 }
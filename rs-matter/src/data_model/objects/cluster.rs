@@ -26,12 +26,12 @@ use crate::{
     interaction_model::{
         core::IMStatusCode,
         messages::{
-            ib::{AttrPath, AttrStatus, CmdPath, CmdStatus},
+            ib::{AttrPath, AttrStatus, CmdPath, CmdStatus, Status},
             GenericPath,
         },
     },
     // TODO: This layer shouldn't really depend on the TLV layer, should create an abstraction layer
-    tlv::{Nullable, TLVWriter, TagType},
+    tlv::{Nullable, TLVElement, TLVWriter, TagType},
 };
 use core::{
     convert::TryInto,
@@ -41,12 +41,12 @@ use core::{
 #[derive(Clone, Copy, Debug, Eq, PartialEq, FromRepr)]
 #[repr(u16)]
 pub enum GlobalElements {
-    _ClusterRevision = 0xFFFD,
+    ClusterRevision = 0xFFFD,
     FeatureMap = 0xFFFC,
     AttributeList = 0xFFFB,
     _EventList = 0xFFFA,
-    _ClientGenCmd = 0xFFF9,
-    ServerGenCmd = 0xFFF8,
+    AcceptedCommandList = 0xFFF9,
+    GeneratedCommandList = 0xFFF8,
     FabricIndex = 0xFE,
 }
 
@@ -61,6 +61,35 @@ pub const ATTRIBUTE_LIST: Attribute = Attribute::new(
     Quality::NONE,
 );
 
+pub const CLUSTER_REVISION: Attribute = Attribute::new(
+    GlobalElements::ClusterRevision as _,
+    Access::RV,
+    Quality::FIXED,
+);
+
+pub const ACCEPTED_COMMAND_LIST: Attribute = Attribute::new(
+    GlobalElements::AcceptedCommandList as _,
+    Access::RV,
+    Quality::FIXED,
+);
+
+pub const GENERATED_COMMAND_LIST: Attribute = Attribute::new(
+    GlobalElements::GeneratedCommandList as _,
+    Access::RV,
+    Quality::FIXED,
+);
+
+/// The global attributes of every cluster, whose values `Cluster::read` derives from
+/// the cluster metadata. Those which a cluster does not declare are reported after its
+/// declared attributes.
+const GLOBAL_ATTRIBUTES: &[Attribute] = &[
+    GENERATED_COMMAND_LIST,
+    ACCEPTED_COMMAND_LIST,
+    ATTRIBUTE_LIST,
+    FEATURE_MAP,
+    CLUSTER_REVISION,
+];
+
 // TODO: What if we instead of creating this, we just pass the AttrData/AttrPath to the read/write
 // methods?
 /// The Attribute Details structure records the details about the attribute under consideration.
@@ -98,16 +127,49 @@ impl<'a> AttrDetails<'a> {
         }
     }
 
+    /// Whether an entry of a fabric-scoped list, belonging to the provided fabric,
+    /// should be reported to the accessing fabric
+    pub fn is_visible_to(&self, fab_idx: u8) -> bool {
+        !self.fab_filter || self.fab_idx == fab_idx
+    }
+
+    /// The metadata of the attribute
+    pub fn attribute(&self) -> Option<&'a Attribute> {
+        self.node
+            .check_endpoint(self.endpoint_id)
+            .ok()?
+            .check_cluster(self.cluster_id)
+            .ok()?
+            .attribute(self.attr_id)
+    }
+
+    /// Validate the data to be written against the constraint of the attribute, if any
+    pub fn check_constraint(&self, data: &TLVElement) -> Result<(), Error> {
+        match self.attribute().and_then(|attr| attr.constraint.as_ref()) {
+            Some(constraint) => constraint.validate(data),
+            None => Ok(()),
+        }
+    }
+
     pub fn status(&self, status: IMStatusCode) -> Result<Option<AttrStatus>, Error> {
-        if self.should_report(status) {
+        self.im_status(Status::new(status, 0))
+    }
+
+    /// The status with which the provided error is reported for this attribute
+    pub fn error_status(&self, error: &Error) -> Result<Option<AttrStatus>, Error> {
+        self.im_status(error.to_im_status())
+    }
+
+    fn im_status(&self, status: Status) -> Result<Option<AttrStatus>, Error> {
+        if self.should_report(status.status) {
             Ok(Some(AttrStatus::new(
                 &GenericPath {
                     endpoint: Some(self.endpoint_id),
                     cluster: Some(self.cluster_id),
                     leaf: Some(self.attr_id as _),
                 },
-                status,
-                0,
+                status.status,
+                status.cluster_status,
             )))
         } else {
             Ok(None)
@@ -137,6 +199,8 @@ pub struct CmdDetails<'a> {
     pub cluster_id: ClusterId,
     pub cmd_id: CmdId,
     pub wildcard: bool,
+    /// The reference of the command in a batched invoke, to be echoed in the response
+    pub command_ref: Option<u16>,
 }
 
 impl<'a> CmdDetails<'a> {
@@ -157,16 +221,28 @@ impl<'a> CmdDetails<'a> {
     }
 
     pub fn status(&self, status: IMStatusCode) -> Option<CmdStatus> {
-        if self.should_report(status) {
-            Some(CmdStatus::new(
-                CmdPath::new(
-                    Some(self.endpoint_id),
-                    Some(self.cluster_id),
-                    Some(self.cmd_id),
-                ),
-                status,
-                0,
-            ))
+        self.im_status(Status::new(status, 0))
+    }
+
+    /// The status with which the provided error is reported for this command
+    pub fn error_status(&self, error: &Error) -> Option<CmdStatus> {
+        self.im_status(error.to_im_status())
+    }
+
+    fn im_status(&self, status: Status) -> Option<CmdStatus> {
+        if self.should_report(status.status) {
+            Some(
+                CmdStatus::new(
+                    CmdPath::new(
+                        Some(self.endpoint_id),
+                        Some(self.cluster_id),
+                        Some(self.cmd_id),
+                    ),
+                    status.status,
+                    status.cluster_status,
+                )
+                .set_command_ref(self.command_ref),
+            )
         } else {
             None
         }
@@ -190,32 +266,77 @@ impl<'a> CmdDetails<'a> {
 #[derive(Debug, Clone)]
 pub struct Cluster<'a> {
     pub id: ClusterId,
+    pub revision: u16,
     pub feature_map: u32,
+    /// The attributes of the cluster. Attributes conditional upon features which are
+    /// not in the feature map are not reported.
     pub attributes: &'a [Attribute],
     pub commands: &'a [CmdId],
+    /// The response commands the cluster generates, as reported by GeneratedCommandList
+    pub generated_commands: &'a [CmdId],
+    /// The subset of the commands which may only be invoked as part of a timed interaction
+    pub timed_commands: &'a [CmdId],
+    /// The subset of the commands which respond with data, and which are thus not allowed
+    /// to be invoked over groupcast
+    pub response_commands: &'a [CmdId],
+    /// The subset of the commands which require the Manage privilege, rather than Operate
+    pub manage_commands: &'a [CmdId],
+    /// The subset of the commands which require the Administer privilege
+    pub admin_commands: &'a [CmdId],
 }
 
 impl<'a> Cluster<'a> {
+    #[allow(clippy::too_many_arguments)]
     pub const fn new(
         id: ClusterId,
+        revision: u16,
         feature_map: u32,
         attributes: &'a [Attribute],
         commands: &'a [CmdId],
+        generated_commands: &'a [CmdId],
+        timed_commands: &'a [CmdId],
+        response_commands: &'a [CmdId],
+        manage_commands: &'a [CmdId],
+        admin_commands: &'a [CmdId],
     ) -> Self {
         Self {
             id,
+            revision,
             feature_map,
             attributes,
             commands,
+            generated_commands,
+            timed_commands,
+            response_commands,
+            manage_commands,
+            admin_commands,
         }
     }
 
+    /// The attributes the cluster reports: its declared attributes which are present
+    /// with its features, followed by the global attributes it does not declare
+    pub fn supported_attributes(&self) -> impl Iterator<Item = &'_ Attribute> + '_ {
+        self.attributes
+            .iter()
+            .filter(move |attribute| attribute.is_supported(self.feature_map))
+            .chain(GLOBAL_ATTRIBUTES.iter().filter(move |global| {
+                !self
+                    .attributes
+                    .iter()
+                    .any(|attribute| attribute.id == global.id)
+            }))
+    }
+
+    pub fn attribute(&self, attr: AttrId) -> Option<&'_ Attribute> {
+        self.supported_attributes()
+            .find(|attribute| attribute.id == attr)
+    }
+
     pub fn match_attributes(
         &self,
         attr: Option<AttrId>,
     ) -> impl Iterator<Item = &'_ Attribute> + '_ {
-        self.attributes
-            .iter()
+        self.supported_attributes()
             .filter(move |attribute| attr.map(|attr| attr == attribute.id).unwrap_or(true))
     }
 
@@ -234,9 +355,7 @@ impl<'a> Cluster<'a> {
         write: bool,
     ) -> Result<(), IMStatusCode> {
         let attribute = self
-            .attributes
-            .iter()
-            .find(|attribute| attribute.id == attr)
+            .attribute(attr)
             .ok_or(IMStatusCode::UnsupportedAttribute)?;
 
         Self::check_attr_access(
@@ -247,20 +366,47 @@ impl<'a> Cluster<'a> {
         )
     }
 
+    pub fn is_timed_command(&self, cmd: CmdId) -> bool {
+        self.timed_commands.contains(&cmd)
+    }
+
+    pub fn is_response_command(&self, cmd: CmdId) -> bool {
+        self.response_commands.contains(&cmd)
+    }
+
+    /// The access of the command, i.e. the privilege required for invoking it
+    pub fn command_access(&self, cmd: CmdId) -> Access {
+        let privilege = if self.admin_commands.contains(&cmd) {
+            Access::NEED_ADMIN
+        } else if self.manage_commands.contains(&cmd) {
+            Access::NEED_MANAGE
+        } else {
+            Access::NEED_OPERATE
+        };
+
+        Access::WRITE.union(privilege)
+    }
+
     pub fn check_command(
         &self,
         accessor: &Accessor,
         ep: EndptId,
         cmd: CmdId,
+        timed: bool,
     ) -> Result<(), IMStatusCode> {
         self.commands
             .iter()
             .find(|id| **id == cmd)
             .ok_or(IMStatusCode::UnsupportedCommand)?;
 
+        if !timed && self.is_timed_command(cmd) {
+            Err(IMStatusCode::TimedRequestMisMatch)?;
+        }
+
         Self::check_cmd_access(
             accessor,
             GenericPath::new(Some(ep), Some(self.id), Some(cmd)),
+            self.command_access(cmd),
         )
     }
 
@@ -295,15 +441,11 @@ impl<'a> Cluster<'a> {
     pub(crate) fn check_cmd_access(
         accessor: &Accessor,
         path: GenericPath,
+        target_perms: Access,
     ) -> Result<(), IMStatusCode> {
         let mut access_req = AccessReq::new(accessor, path, Access::WRITE);
 
-        access_req.set_target_perms(
-            Access::WRITE
-                .union(Access::NEED_OPERATE)
-                .union(Access::NEED_MANAGE)
-                .union(Access::NEED_ADMIN),
-        ); // TODO
+        access_req.set_target_perms(target_perms);
         if access_req.allow() {
             Ok(())
         } else {
@@ -318,6 +460,19 @@ impl<'a> Cluster<'a> {
                 writer.complete()
             }
             GlobalElements::FeatureMap => writer.set(self.feature_map),
+            GlobalElements::ClusterRevision => writer.set(self.revision),
+            GlobalElements::AcceptedCommandList => {
+                Self::encode_command_ids(self.commands, AttrDataWriter::TAG, &mut writer)?;
+                writer.complete()
+            }
+            GlobalElements::GeneratedCommandList => {
+                Self::encode_command_ids(
+                    self.generated_commands,
+                    AttrDataWriter::TAG,
+                    &mut writer,
+                )?;
+                writer.complete()
+            }
             other => {
                 error!("This attribute is not yet handled {:?}", other);
                 Err(ErrorCode::AttributeNotFound.into())
@@ -327,12 +482,21 @@ impl<'a> Cluster<'a> {
 
     fn encode_attribute_ids(&self, tag: TagType, tw: &mut TLVWriter) -> Result<(), Error> {
         tw.start_array(tag)?;
-        for a in self.attributes {
+        for a in self.supported_attributes() {
             tw.u16(TagType::Anonymous, a.id)?;
         }
 
         tw.end_container()
     }
+
+    fn encode_command_ids(cmds: &[CmdId], tag: TagType, tw: &mut TLVWriter) -> Result<(), Error> {
+        tw.start_array(tag)?;
+        for cmd in cmds {
+            tw.u32(TagType::Anonymous, *cmd)?;
+        }
+
+        tw.end_container()
+    }
 }
 
 impl<'a> core::fmt::Display for Cluster<'a> {
@@ -347,3 +511,121 @@ impl<'a> core::fmt::Display for Cluster<'a> {
         write!(f, " ], ")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_model::objects::{
+            Access, AttrDataEncoder, AttrDetails, Attribute, Cluster, GlobalElements, Node,
+            Quality, ATTRIBUTE_LIST, FEATURE_MAP,
+        },
+        tlv::{get_root_node_struct, TLVWriter},
+        utils::writebuf::WriteBuf,
+    };
+
+    const FEATURE_OPTIONAL: u32 = 0x02;
+
+    const CLUSTER: Cluster<'static> = Cluster {
+        id: 0x1234,
+        revision: 3,
+        feature_map: 0,
+        attributes: &[
+            FEATURE_MAP,
+            ATTRIBUTE_LIST,
+            Attribute::new(0, Access::RV, Quality::NONE),
+            Attribute::new(1, Access::RV, Quality::NONE).with_feature(FEATURE_OPTIONAL),
+        ],
+        commands: &[0x00, 0x02],
+        generated_commands: &[0x01],
+        timed_commands: &[],
+        response_commands: &[0x00],
+        manage_commands: &[],
+        admin_commands: &[],
+    };
+
+    fn read(cluster: &Cluster, attr_id: GlobalElements) -> heapless::Vec<u32, 16> {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 0,
+            cluster_id: cluster.id,
+            attr_id: attr_id as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let writer = AttrDataEncoder::new(&attr, &mut tw)
+            .with_dataver(0)
+            .unwrap()
+            .unwrap();
+        cluster.read(attr.attr_id, writer).unwrap();
+
+        let data = get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap();
+
+        match data.enter() {
+            Some(iter) => iter.map(|e| e.u32().unwrap()).collect(),
+            None => [data.u32().unwrap()].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    /// Attributes conditional upon a feature are reported only with the feature,
+    /// and the global attributes which are not declared are reported nevertheless
+    fn attribute_list_features() {
+        const GLOBALS: [u32; 5] = [
+            GlobalElements::FeatureMap as _,
+            GlobalElements::AttributeList as _,
+            GlobalElements::GeneratedCommandList as _,
+            GlobalElements::AcceptedCommandList as _,
+            GlobalElements::ClusterRevision as _,
+        ];
+
+        let list = read(&CLUSTER, GlobalElements::AttributeList);
+        assert_eq!(
+            &list[..],
+            &[GLOBALS[0], GLOBALS[1], 0, GLOBALS[2], GLOBALS[3], GLOBALS[4]]
+        );
+        assert!(CLUSTER.attribute(1).is_none());
+        assert_eq!(CLUSTER.match_attributes(None).count(), 6);
+
+        let cluster = Cluster {
+            feature_map: FEATURE_OPTIONAL,
+            ..CLUSTER
+        };
+
+        let list = read(&cluster, GlobalElements::AttributeList);
+        assert_eq!(
+            &list[..],
+            &[GLOBALS[0], GLOBALS[1], 0, 1, GLOBALS[2], GLOBALS[3], GLOBALS[4]]
+        );
+        assert!(cluster.attribute(1).is_some());
+        assert_eq!(cluster.match_attributes(None).count(), 7);
+    }
+
+    #[test]
+    fn global_attributes() {
+        assert_eq!(&read(&CLUSTER, GlobalElements::ClusterRevision)[..], &[3]);
+        assert_eq!(
+            &read(&CLUSTER, GlobalElements::AcceptedCommandList)[..],
+            &[0x00, 0x02]
+        );
+        assert_eq!(
+            &read(&CLUSTER, GlobalElements::GeneratedCommandList)[..],
+            &[0x01]
+        );
+    }
+}
@@ -15,15 +15,17 @@
  *    limitations under the License.
  */
 
+use core::cell::Cell;
 use core::fmt::{Debug, Formatter};
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 
 use crate::interaction_model::core::IMStatusCode;
 use crate::interaction_model::messages::ib::{
     AttrPath, AttrResp, AttrStatus, CmdDataTag, CmdPath, CmdStatus, InvResp, InvRespTag,
 };
-use crate::tlv::UtfStr;
+use crate::tlv::{Nullable, UtfStr};
 use crate::transport::exchange::Exchange;
 use crate::{
     error::{Error, ErrorCode},
@@ -32,6 +34,10 @@ use crate::{
 };
 use log::error;
 
+use crate::utils::timer::Timer;
+#[cfg(feature = "nightly")]
+use embassy_futures::select::{select, Either};
+
 use super::{AttrDetails, CmdDetails, DataModelHandler};
 
 // TODO: Should this return an IMStatusCode Error? But if yes, the higher layer
@@ -117,21 +123,50 @@ impl<'a> FromTLV<'a> for EncodeValue<'a> {
     }
 }
 
+/// The position a read is to be resumed at in the next report chunk, when the chunk ran out
+/// of space in the middle of a list attribute encoded with `AttrDataWriter::set_list`
+#[derive(Debug, Default)]
+pub struct ResumeRead {
+    list_index: Cell<Option<usize>>,
+}
+
+impl ResumeRead {
+    pub const fn new() -> Self {
+        Self {
+            list_index: Cell::new(None),
+        }
+    }
+
+    /// The index of the first list element which is still to be reported, if a list
+    /// attribute is being continued
+    pub fn list_index(&self) -> Option<usize> {
+        self.list_index.get()
+    }
+
+    fn reset(&self) {
+        self.list_index.set(None);
+    }
+}
+
 pub struct AttrDataEncoder<'a, 'b, 'c> {
     dataver_filter: Option<u32>,
     path: AttrPath,
     tw: &'a mut TLVWriter<'b, 'c>,
+    resume: Option<&'a ResumeRead>,
 }
 
 impl<'a, 'b, 'c> AttrDataEncoder<'a, 'b, 'c> {
+    /// Returns `false` if the chunk ran out of space, in which case the read of the
+    /// attribute is to be repeated in the next chunk, with the same `resume` state
     pub async fn handle_read<T: DataModelHandler>(
         item: &Result<AttrDetails<'_>, AttrStatus>,
         handler: &T,
         tw: &mut TLVWriter<'_, '_>,
+        resume: &ResumeRead,
     ) -> Result<bool, Error> {
         let status = match item {
             Ok(attr) => {
-                let encoder = AttrDataEncoder::new(attr, tw);
+                let encoder = AttrDataEncoder::new(attr, tw).with_resume(resume);
 
                 let result = {
                     #[cfg(not(feature = "nightly"))]
@@ -145,15 +180,15 @@ impl<'a, 'b, 'c> AttrDataEncoder<'a, 'b, 'c> {
                     }
                 };
 
+                if matches!(&result, Err(e) if e.code() == ErrorCode::NoSpace) {
+                    return Ok(false);
+                }
+
+                resume.reset();
+
                 match result {
                     Ok(()) => None,
-                    Err(e) => {
-                        if e.code() == ErrorCode::NoSpace {
-                            return Ok(false);
-                        } else {
-                            attr.status(e.into())?
-                        }
-                    }
+                    Err(e) => attr.error_status(&e)?,
                 }
             }
             Err(status) => Some(status.clone()),
@@ -166,38 +201,47 @@ impl<'a, 'b, 'c> AttrDataEncoder<'a, 'b, 'c> {
         Ok(true)
     }
 
+    /// Returns `false` if the write could not be applied
     pub async fn handle_write<T: DataModelHandler>(
         item: &Result<(AttrDetails<'_>, TLVElement<'_>), AttrStatus>,
         handler: &T,
         tw: &mut TLVWriter<'_, '_>,
-    ) -> Result<(), Error> {
-        let status = match item {
+    ) -> Result<bool, Error> {
+        let (status, written) = match item {
             Ok((attr, data)) => {
-                let result = {
+                let result = if let Err(error) = attr.check_constraint(data) {
+                    Err(error)
+                } else {
                     #[cfg(not(feature = "nightly"))]
                     {
-                        handler.write(attr, AttrData::new(attr.dataver, data))
+                        handler.write(attr, Self::write_data(attr, data))
                     }
 
                     #[cfg(feature = "nightly")]
                     {
-                        handler.write(attr, AttrData::new(attr.dataver, data)).await
+                        handler.write(attr, Self::write_data(attr, data)).await
                     }
                 };
 
                 match result {
-                    Ok(()) => attr.status(IMStatusCode::Success)?,
-                    Err(error) => attr.status(error.into())?,
+                    Ok(()) => (attr.status(IMStatusCode::Success)?, true),
+                    Err(error) => (attr.error_status(&error)?, false),
                 }
             }
-            Err(status) => Some(status.clone()),
+            Err(status) => (Some(status.clone()), false),
         };
 
         if let Some(status) = status {
             status.to_tlv(tw, TagType::Anonymous)?;
         }
 
-        Ok(())
+        Ok(written)
+    }
+
+    /// The data of a write, along with the operation the write performs, should the
+    /// attribute be a list
+    fn write_data<'d>(attr: &AttrDetails, data: &'d TLVElement<'d>) -> AttrData<'d> {
+        AttrData::new(attr.dataver, data).with_list_op(ListOp::new(attr.list_index, data))
     }
 
     pub fn new(attr: &AttrDetails, tw: &'a mut TLVWriter<'b, 'c>) -> Self {
@@ -205,22 +249,26 @@ impl<'a, 'b, 'c> AttrDataEncoder<'a, 'b, 'c> {
             dataver_filter: attr.dataver,
             path: attr.path(),
             tw,
+            resume: None,
         }
     }
 
+    /// Allow list attributes encoded with `AttrDataWriter::set_list` to be split across
+    /// report chunks, with their progress tracked in `resume`
+    pub fn with_resume(mut self, resume: &'a ResumeRead) -> Self {
+        self.resume = Some(resume);
+        self
+    }
+
     pub fn with_dataver(self, dataver: u32) -> Result<Option<AttrDataWriter<'a, 'b, 'c>>, Error> {
         if self
             .dataver_filter
             .map(|dataver_filter| dataver_filter != dataver)
             .unwrap_or(true)
         {
-            let mut writer = AttrDataWriter::new(self.tw);
+            let mut writer = AttrDataWriter::new(self.tw, dataver, self.path, self.resume);
 
-            writer.start_struct(TagType::Anonymous)?;
-            writer.start_struct(TagType::Context(AttrRespTag::Data as _))?;
-            writer.u32(TagType::Context(AttrDataTag::DataVer as _), dataver)?;
-            self.path
-                .to_tlv(&mut writer, TagType::Context(AttrDataTag::Path as _))?;
+            writer.start_report()?;
 
             Ok(Some(writer))
         } else {
@@ -233,26 +281,168 @@ pub struct AttrDataWriter<'a, 'b, 'c> {
     tw: &'a mut TLVWriter<'b, 'c>,
     anchor: usize,
     completed: bool,
+    dataver: u32,
+    path: AttrPath,
+    resume: Option<&'a ResumeRead>,
 }
 
 impl<'a, 'b, 'c> AttrDataWriter<'a, 'b, 'c> {
     pub const TAG: TagType = TagType::Context(AttrDataTag::Data as _);
 
-    fn new(tw: &'a mut TLVWriter<'b, 'c>) -> Self {
+    /// The space needed to close a list and the report it is in
+    const LIST_END_LEN: usize = 3;
+
+    fn new(
+        tw: &'a mut TLVWriter<'b, 'c>,
+        dataver: u32,
+        path: AttrPath,
+        resume: Option<&'a ResumeRead>,
+    ) -> Self {
         let anchor = tw.get_tail();
 
         Self {
             tw,
             anchor,
             completed: false,
+            dataver,
+            path,
+            resume,
         }
     }
 
+    fn start_report(&mut self) -> Result<(), Error> {
+        Self::start(self.tw, self.dataver, &self.path)
+    }
+
+    /// Open the report of an attribute, up to its data
+    fn start(tw: &mut TLVWriter, dataver: u32, path: &AttrPath) -> Result<(), Error> {
+        tw.start_struct(TagType::Anonymous)?;
+        tw.start_struct(TagType::Context(AttrRespTag::Data as _))?;
+        tw.u32(TagType::Context(AttrDataTag::DataVer as _), dataver)?;
+        path.to_tlv(tw, TagType::Context(AttrDataTag::Path as _))
+    }
+
     pub fn set<T: ToTLV>(self, value: T) -> Result<(), Error> {
         value.to_tlv(self.tw, Self::TAG)?;
         self.complete()
     }
 
+    /// Encode a list attribute element by element, so that a list which does not fit in
+    /// the current report chunk is continued in the next ones, if the encoder was created
+    /// `with_resume`.
+    ///
+    /// The chunk which runs out of space carries the list with as many elements as fit.
+    /// The next chunks append the remaining elements, in reports of their own with a null
+    /// list index, starting exactly with the first element which did not fit.
+    pub fn set_list<I>(mut self, elements: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: ToTLV,
+    {
+        match self.resume.and_then(ResumeRead::list_index) {
+            Some(from) => self.append_list(elements, from),
+            None => {
+                let partial = self.start_list(elements)?;
+                let resume = self.resume;
+
+                self.complete()?;
+
+                if let (Some(resume), Some(index)) = (resume, partial) {
+                    resume.list_index.set(Some(index));
+                    Err(ErrorCode::NoSpace.into())
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Encode the list with the elements which fit in the chunk, keeping the space to
+    /// close it. Returns the index of the first element which did not fit, if any.
+    fn start_list<I>(&mut self, elements: I) -> Result<Option<usize>, Error>
+    where
+        I: IntoIterator,
+        I::Item: ToTLV,
+    {
+        self.tw.start_array(Self::TAG)?;
+
+        let resumable = self.resume.is_some();
+        if resumable {
+            self.tw.get_buf().shrink(Self::LIST_END_LEN)?;
+        }
+
+        let mut partial = None;
+        let mut result = Ok(());
+
+        for (index, element) in elements.into_iter().enumerate() {
+            let anchor = self.tw.get_tail();
+
+            if let Err(e) = element.to_tlv(self.tw, TagType::Anonymous) {
+                if resumable && index > 0 && e.code() == ErrorCode::NoSpace {
+                    self.tw.rewind_to(anchor);
+                    partial = Some(index);
+                } else {
+                    result = Err(e);
+                }
+
+                break;
+            }
+        }
+
+        if resumable {
+            self.tw.get_buf().expand(Self::LIST_END_LEN)?;
+        }
+
+        result?;
+
+        self.tw.end_container()?;
+
+        Ok(partial)
+    }
+
+    /// Append the elements of the list from index `from` on, each in a report of its own
+    fn append_list<I>(mut self, elements: I, from: usize) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: ToTLV,
+    {
+        // The report opened for the whole list is not needed
+        self.reset();
+        self.completed = true;
+
+        let path = AttrPath {
+            list_index: Some(Nullable::Null),
+            ..self.path.clone()
+        };
+
+        for (index, element) in elements.into_iter().enumerate().skip(from) {
+            let anchor = self.tw.get_tail();
+
+            let result = Self::start(self.tw, self.dataver, &path)
+                .and_then(|_| element.to_tlv(self.tw, Self::TAG))
+                .and_then(|_| self.tw.end_container())
+                .and_then(|_| self.tw.end_container());
+
+            if let Err(e) = result {
+                self.tw.rewind_to(anchor);
+
+                if e.code() == ErrorCode::NoSpace {
+                    if let Some(resume) = self.resume {
+                        resume.list_index.set(Some(index));
+                    }
+                }
+
+                return Err(e);
+            }
+        }
+
+        if let Some(resume) = self.resume {
+            resume.reset();
+        }
+
+        Ok(())
+    }
+
     pub fn complete(mut self) -> Result<(), Error> {
         self.tw.end_container()?;
         self.tw.end_container()?;
@@ -289,14 +479,57 @@ impl<'a, 'b, 'c> DerefMut for AttrDataWriter<'a, 'b, 'c> {
     }
 }
 
+/// The operation a write to a list attribute performs, as told by the list index of
+/// the written path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListOp {
+    /// Replace the whole list with the written list; the list index is absent
+    Replace,
+    /// Append the written element to the list; the list index is null
+    Append,
+    /// Replace the element at the index with the written element
+    EditAt(usize),
+    /// Delete the element at the index; the written data is null
+    DeleteAt(usize),
+}
+
+impl ListOp {
+    pub fn new(list_index: Option<Nullable<u16>>, data: &TLVElement) -> Self {
+        match list_index {
+            Some(Nullable::NotNull(index)) if data.null().is_ok() => Self::DeleteAt(index as _),
+            Some(Nullable::NotNull(index)) => Self::EditAt(index as _),
+            Some(Nullable::Null) => Self::Append,
+            // Without a list index, an element rather than a list is still
+            // taken as being appended
+            None if data.confirm_array().is_err() => Self::Append,
+            None => Self::Replace,
+        }
+    }
+}
+
 pub struct AttrData<'a> {
     for_dataver: Option<u32>,
     data: &'a TLVElement<'a>,
+    list_op: ListOp,
 }
 
 impl<'a> AttrData<'a> {
     pub fn new(for_dataver: Option<u32>, data: &'a TLVElement<'a>) -> Self {
-        Self { for_dataver, data }
+        Self {
+            for_dataver,
+            data,
+            list_op: ListOp::Replace,
+        }
+    }
+
+    pub fn with_list_op(mut self, list_op: ListOp) -> Self {
+        self.list_op = list_op;
+        self
+    }
+
+    /// The operation of the write, for a list attribute
+    pub fn list_op(&self) -> ListOp {
+        self.list_op
     }
 
     pub fn with_dataver(self, dataver: u32) -> Result<&'a TLVElement<'a>, Error> {
@@ -332,30 +565,57 @@ impl CmdDataTracker {
 pub struct CmdDataEncoder<'a, 'b, 'c> {
     tracker: &'a mut CmdDataTracker,
     path: CmdPath,
+    command_ref: Option<u16>,
     tw: &'a mut TLVWriter<'b, 'c>,
 }
 
 impl<'a, 'b, 'c> CmdDataEncoder<'a, 'b, 'c> {
-    pub async fn handle<T: DataModelHandler>(
+    /// Invoke the command, failing it with `Busy` if the handler does not complete
+    /// within `timeout` (as awaited with the provided timer), so that a stalled handler
+    /// does not wedge the exchange.
+    ///
+    /// Only asynchronous handlers are timed out, as a synchronous one cannot be interrupted.
+    pub async fn handle<T: DataModelHandler, M: Timer>(
         item: &Result<(CmdDetails<'_>, TLVElement<'_>), CmdStatus>,
         handler: &T,
         tw: &mut TLVWriter<'_, '_>,
         exchange: &Exchange<'_>,
+        timeout: Duration,
+        timer: &M,
     ) -> Result<(), Error> {
         let status = match item {
             Ok((cmd, data)) => {
+                #[cfg(feature = "nightly")]
+                let anchor = tw.get_tail();
+
                 let mut tracker = CmdDataTracker::new();
                 let encoder = CmdDataEncoder::new(cmd, &mut tracker, tw);
 
                 let result = {
                     #[cfg(not(feature = "nightly"))]
                     {
+                        let _ = (timeout, timer);
+
                         handler.invoke(exchange, cmd, data, encoder)
                     }
 
                     #[cfg(feature = "nightly")]
                     {
-                        handler.invoke(exchange, cmd, data, encoder).await
+                        let result = select(
+                            handler.invoke(exchange, cmd, data, encoder),
+                            timer.after(timeout),
+                        )
+                        .await;
+
+                        match result {
+                            Either::First(result) => result,
+                            Either::Second(_) => {
+                                // Drop whatever the handler encoded before it stalled
+                                tw.rewind_to(anchor);
+
+                                Err(ErrorCode::Busy.into())
+                            }
+                        }
                     }
                 };
 
@@ -363,7 +623,7 @@ impl<'a, 'b, 'c> CmdDataEncoder<'a, 'b, 'c> {
                     Ok(()) => cmd.success(&tracker),
                     Err(error) => {
                         error!("Error invoking command: {}", error);
-                        cmd.status(error.into())
+                        cmd.error_status(&error)
                     }
                 }
             }
@@ -388,12 +648,13 @@ impl<'a, 'b, 'c> CmdDataEncoder<'a, 'b, 'c> {
         Self {
             tracker,
             path: cmd.path(),
+            command_ref: cmd.command_ref,
             tw,
         }
     }
 
     pub fn with_command(mut self, cmd: u16) -> Result<CmdDataWriter<'a, 'b, 'c>, Error> {
-        let mut writer = CmdDataWriter::new(self.tracker, self.tw);
+        let mut writer = CmdDataWriter::new(self.tracker, self.command_ref, self.tw);
 
         writer.start_struct(TagType::Anonymous)?;
         writer.start_struct(TagType::Context(InvRespTag::Cmd as _))?;
@@ -404,10 +665,22 @@ impl<'a, 'b, 'c> CmdDataEncoder<'a, 'b, 'c> {
 
         Ok(writer)
     }
+
+    /// Complete the command with a failure status, carrying a cluster-specific status code
+    pub fn cluster_status(self, cluster_status: u16) -> Result<(), Error> {
+        let status = CmdStatus::new(self.path, IMStatusCode::Failure, cluster_status)
+            .set_command_ref(self.command_ref);
+
+        InvResp::Status(status).to_tlv(self.tw, TagType::Anonymous)?;
+        self.tracker.complete();
+
+        Ok(())
+    }
 }
 
 pub struct CmdDataWriter<'a, 'b, 'c> {
     tracker: &'a mut CmdDataTracker,
+    command_ref: Option<u16>,
     tw: &'a mut TLVWriter<'b, 'c>,
     anchor: usize,
     completed: bool,
@@ -416,11 +689,16 @@ pub struct CmdDataWriter<'a, 'b, 'c> {
 impl<'a, 'b, 'c> CmdDataWriter<'a, 'b, 'c> {
     pub const TAG: TagType = TagType::Context(CmdDataTag::Data as _);
 
-    fn new(tracker: &'a mut CmdDataTracker, tw: &'a mut TLVWriter<'b, 'c>) -> Self {
+    fn new(
+        tracker: &'a mut CmdDataTracker,
+        command_ref: Option<u16>,
+        tw: &'a mut TLVWriter<'b, 'c>,
+    ) -> Self {
         let anchor = tw.get_tail();
 
         Self {
             tracker,
+            command_ref,
             tw,
             anchor,
             completed: false,
@@ -433,6 +711,11 @@ impl<'a, 'b, 'c> CmdDataWriter<'a, 'b, 'c> {
     }
 
     pub fn complete(mut self) -> Result<(), Error> {
+        if let Some(command_ref) = self.command_ref {
+            self.tw
+                .u16(TagType::Context(CmdDataTag::Ref as _), command_ref)?;
+        }
+
         self.tw.end_container()?;
         self.tw.end_container()?;
 
@@ -543,3 +826,129 @@ macro_rules! command_enum {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_model::objects::{AttrDetails, Node},
+        error::ErrorCode,
+        interaction_model::messages::ib::AttrResp,
+        tlv::{get_root_node, FromTLV, Nullable, TLVWriter, TagType, ToTLV},
+        utils::writebuf::WriteBuf,
+    };
+
+    use super::{AttrDataEncoder, ListOp, ResumeRead};
+
+    /// Small enough for a list of 100 elements to take three chunks: one with the first
+    /// 85 elements of the list, and two with the rest of them appended, 10 per chunk
+    const CHUNK_LEN: usize = 280;
+
+    /// Encode into a new chunk as much of the list as fits, continuing from where the
+    /// previous chunk stopped, and collect the elements reported in the chunk.
+    /// Returns `true` once the list is complete.
+    fn read_chunk(
+        list: &[u16],
+        resume: &ResumeRead,
+        elements: &mut heapless::Vec<u16, 100>,
+    ) -> bool {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 1,
+            cluster_id: 6,
+            attr_id: 0,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; CHUNK_LEN];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        // The attribute reports of the chunk, with the space to close them kept
+        tw.start_array(TagType::Anonymous).unwrap();
+        tw.get_buf().shrink(1).unwrap();
+
+        let result = AttrDataEncoder::new(&attr, &mut tw)
+            .with_resume(resume)
+            .with_dataver(1)
+            .unwrap()
+            .unwrap()
+            .set_list(list);
+
+        tw.get_buf().expand(1).unwrap();
+        tw.end_container().unwrap();
+
+        for report in get_root_node(writebuf.as_slice()).unwrap().enter().unwrap() {
+            let data = AttrResp::from_tlv(&report).unwrap().unwrap_data();
+            let value = data.data.unwrap_tlv().unwrap();
+
+            if data.path.list_index == Some(Nullable::Null) {
+                elements.push(value.u16().unwrap()).unwrap();
+            } else {
+                assert_eq!(data.path.list_index, None);
+
+                for element in value.enter().unwrap() {
+                    elements.push(element.u16().unwrap()).unwrap();
+                }
+            }
+        }
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                assert_eq!(e.code(), ErrorCode::NoSpace);
+                false
+            }
+        }
+    }
+
+    #[test]
+    /// A list which does not fit in a chunk is continued in the next chunks exactly
+    /// where it stopped, without repeating or skipping any of its elements
+    fn list_spans_chunks() {
+        let source: heapless::Vec<u16, 100> = (1000..1100).collect();
+        let resume = ResumeRead::new();
+
+        let mut elements = heapless::Vec::new();
+        let mut chunks = 1;
+
+        while !read_chunk(&source, &resume, &mut elements) {
+            assert!(resume.list_index().is_some());
+            chunks += 1;
+        }
+
+        assert_eq!(chunks, 3);
+        assert_eq!(elements, source);
+        assert_eq!(resume.list_index(), None);
+    }
+
+    fn list_op(list_index: Option<Nullable<u16>>, data: &dyn ToTLV) -> ListOp {
+        let mut buf = [0; 16];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        data.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+
+        ListOp::new(list_index, &get_root_node(writebuf.as_slice()).unwrap())
+    }
+
+    #[test]
+    fn list_ops() {
+        assert_eq!(list_op(None, &[1_u16, 2]), ListOp::Replace);
+        assert_eq!(list_op(Some(Nullable::Null), &3_u16), ListOp::Append);
+        assert_eq!(
+            list_op(Some(Nullable::NotNull(2)), &3_u16),
+            ListOp::EditAt(2)
+        );
+        assert_eq!(
+            list_op(Some(Nullable::NotNull(2)), &Nullable::<u16>::Null),
+            ListOp::DeleteAt(2)
+        );
+    }
+}
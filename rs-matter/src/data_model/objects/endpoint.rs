@@ -19,12 +19,16 @@ use crate::{acl::Accessor, interaction_model::core::IMStatusCode};
 
 use core::fmt;
 
-use super::{AttrId, Attribute, Cluster, ClusterId, CmdId, DeviceType, EndptId};
+use super::{AttrId, Attribute, Cluster, ClusterId, CmdId, DeviceType, EndptId, SemanticTag};
 
 #[derive(Debug, Clone)]
 pub struct Endpoint<'a> {
     pub id: EndptId,
-    pub device_type: DeviceType,
+    /// The device types of the endpoint; more than one for a composed device
+    pub device_types: &'a [DeviceType],
+    /// The semantic tags of the endpoint, which are only reported by an endpoint with
+    /// the TagList feature of its Descriptor cluster
+    pub tags: &'a [SemanticTag<'a>],
     pub clusters: &'a [Cluster<'a>],
 }
 
@@ -66,22 +70,36 @@ impl<'a> Endpoint<'a> {
         accessor: &Accessor,
         cl: ClusterId,
         cmd: CmdId,
+        timed: bool,
     ) -> Result<(), IMStatusCode> {
         self.check_cluster(cl)
-            .and_then(|cluster| cluster.check_command(accessor, self.id, cmd))
+            .and_then(|cluster| cluster.check_command(accessor, self.id, cmd, timed))
     }
 
+    /// All the clusters for a wildcard, or the one cluster with the concrete ID
     pub fn match_clusters(&self, cl: Option<ClusterId>) -> impl Iterator<Item = &'_ Cluster> + '_ {
-        self.clusters
-            .iter()
-            .filter(move |cluster| cl.map(|id| id == cluster.id).unwrap_or(true))
+        let (all, concrete) = match cl {
+            Some(cl) => (None, self.check_cluster(cl).ok()),
+            None => (Some(self.clusters.iter()), None),
+        };
+
+        all.into_iter().flatten().chain(concrete)
     }
 
+    /// Same as `Node::check_endpoint`, a binary search for the clusters declared
+    /// in the order of their IDs, and a scan otherwise
     pub fn check_cluster(&self, cl: ClusterId) -> Result<&Cluster, IMStatusCode> {
-        self.clusters
-            .iter()
-            .find(|cluster| cluster.id == cl)
-            .ok_or(IMStatusCode::UnsupportedCluster)
+        match self
+            .clusters
+            .binary_search_by_key(&cl, |cluster| cluster.id)
+        {
+            Ok(index) => Ok(&self.clusters[index]),
+            Err(_) => self
+                .clusters
+                .iter()
+                .find(|cluster| cluster.id == cl)
+                .ok_or(IMStatusCode::UnsupportedCluster),
+        }
     }
 }
 
@@ -17,6 +17,7 @@
 
 use crate::{
     error::{Error, ErrorCode},
+    interaction_model::messages::GenericPath,
     tlv::TLVElement,
     transport::exchange::Exchange,
 };
@@ -56,6 +57,15 @@ pub trait Handler {
     ) -> Result<(), Error> {
         Err(ErrorCode::CommandNotFound.into())
     }
+
+    /// Consulted for every attribute a wildcard read expands to, before the attribute
+    /// is actually read. Returning `true` skips the attribute altogether, which allows
+    /// handlers to cheaply leave out e.g. clusters they know to be empty.
+    ///
+    /// Attributes requested with a concrete path are never pruned.
+    fn prune_read(&self, _path: &GenericPath) -> bool {
+        false
+    }
 }
 
 impl<T> Handler for &T
@@ -79,6 +89,10 @@ where
     ) -> Result<(), Error> {
         (**self).invoke(exchange, cmd, data, encoder)
     }
+
+    fn prune_read(&self, path: &GenericPath) -> bool {
+        (**self).prune_read(path)
+    }
 }
 
 impl<T> Handler for &mut T
@@ -102,6 +116,10 @@ where
     ) -> Result<(), Error> {
         (**self).invoke(exchange, cmd, data, encoder)
     }
+
+    fn prune_read(&self, path: &GenericPath) -> bool {
+        (**self).prune_read(path)
+    }
 }
 
 pub trait NonBlockingHandler: Handler {}
@@ -131,6 +149,10 @@ where
     ) -> Result<(), Error> {
         self.1.invoke(exchange, cmd, data, encoder)
     }
+
+    fn prune_read(&self, path: &GenericPath) -> bool {
+        self.1.prune_read(path)
+    }
 }
 
 impl<M, H> NonBlockingHandler for (M, H) where H: NonBlockingHandler {}
@@ -224,6 +246,16 @@ where
             self.next.invoke(exchange, cmd, data, encoder)
         }
     }
+
+    fn prune_read(&self, path: &GenericPath) -> bool {
+        if path.endpoint == Some(self.handler_endpoint)
+            && path.cluster == Some(self.handler_cluster)
+        {
+            self.handler.prune_read(path)
+        } else {
+            self.next.prune_read(path)
+        }
+    }
 }
 
 impl<H, T> NonBlockingHandler for ChainedHandler<H, T>
@@ -272,6 +304,10 @@ where
     ) -> Result<(), Error> {
         self.0.invoke(exchange, cmd, data, encoder)
     }
+
+    fn prune_read(&self, path: &GenericPath) -> bool {
+        self.0.prune_read(path)
+    }
 }
 
 impl<T> NonBlockingHandler for HandlerCompat<T> where T: NonBlockingHandler {}
@@ -299,12 +335,20 @@ mod asynch {
     use crate::{
         data_model::objects::{AttrData, AttrDataEncoder, AttrDetails, CmdDataEncoder, CmdDetails},
         error::{Error, ErrorCode},
+        interaction_model::messages::GenericPath,
         tlv::TLVElement,
         transport::exchange::Exchange,
     };
 
     use super::{ChainedHandler, EmptyHandler, Handler, HandlerCompat, NonBlockingHandler};
 
+    /// Wrap any `Handler` implementation - including a blocking one - in this struct
+    /// to use it where an `AsyncHandler` is expected.
+    ///
+    /// Each call runs the synchronous handler to completion, so unlike with
+    /// `HandlerCompat` the handler does not have to be a `NonBlockingHandler`.
+    pub struct SyncAsAsync<H>(pub H);
+
     pub trait AsyncHandler {
         async fn read<'a>(
             &'a self,
@@ -329,6 +373,11 @@ mod asynch {
         ) -> Result<(), Error> {
             Err(ErrorCode::CommandNotFound.into())
         }
+
+        /// See [`Handler::prune_read`]
+        fn prune_read(&self, _path: &GenericPath) -> bool {
+            false
+        }
     }
 
     impl<T> AsyncHandler for &mut T
@@ -360,6 +409,10 @@ mod asynch {
         ) -> Result<(), Error> {
             (**self).invoke(exchange, cmd, data, encoder).await
         }
+
+        fn prune_read(&self, path: &GenericPath) -> bool {
+            (**self).prune_read(path)
+        }
     }
 
     impl<T> AsyncHandler for &T
@@ -391,6 +444,10 @@ mod asynch {
         ) -> Result<(), Error> {
             (**self).invoke(exchange, cmd, data, encoder).await
         }
+
+        fn prune_read(&self, path: &GenericPath) -> bool {
+            (**self).prune_read(path)
+        }
     }
 
     impl<M, H> AsyncHandler for (M, H)
@@ -422,6 +479,10 @@ mod asynch {
         ) -> Result<(), Error> {
             self.1.invoke(exchange, cmd, data, encoder).await
         }
+
+        fn prune_read(&self, path: &GenericPath) -> bool {
+            self.1.prune_read(path)
+        }
     }
 
     impl<T> AsyncHandler for HandlerCompat<T>
@@ -453,6 +514,45 @@ mod asynch {
         ) -> Result<(), Error> {
             Handler::invoke(&self.0, exchange, cmd, data, encoder)
         }
+
+        fn prune_read(&self, path: &GenericPath) -> bool {
+            Handler::prune_read(&self.0, path)
+        }
+    }
+
+    impl<H> AsyncHandler for SyncAsAsync<H>
+    where
+        H: Handler,
+    {
+        async fn read<'a>(
+            &'a self,
+            attr: &'a AttrDetails<'_>,
+            encoder: AttrDataEncoder<'a, '_, '_>,
+        ) -> Result<(), Error> {
+            Handler::read(&self.0, attr, encoder)
+        }
+
+        async fn write<'a>(
+            &'a self,
+            attr: &'a AttrDetails<'_>,
+            data: AttrData<'a>,
+        ) -> Result<(), Error> {
+            Handler::write(&self.0, attr, data)
+        }
+
+        async fn invoke<'a>(
+            &'a self,
+            exchange: &'a Exchange<'_>,
+            cmd: &'a CmdDetails<'_>,
+            data: &'a TLVElement<'_>,
+            encoder: CmdDataEncoder<'a, '_, '_>,
+        ) -> Result<(), Error> {
+            Handler::invoke(&self.0, exchange, cmd, data, encoder)
+        }
+
+        fn prune_read(&self, path: &GenericPath) -> bool {
+            Handler::prune_read(&self.0, path)
+        }
     }
 
     impl AsyncHandler for EmptyHandler {
@@ -509,5 +609,15 @@ mod asynch {
                 self.next.invoke(exchange, cmd, data, encoder).await
             }
         }
+
+        fn prune_read(&self, path: &GenericPath) -> bool {
+            if path.endpoint == Some(self.handler_endpoint)
+                && path.cluster == Some(self.handler_cluster)
+            {
+                self.handler.prune_read(path)
+            } else {
+                self.next.prune_read(path)
+            }
+        }
     }
 }
@@ -122,7 +122,7 @@ where
 
 #[cfg(feature = "nightly")]
 pub mod asynch {
-    use crate::data_model::objects::{HandlerCompat, Node};
+    use crate::data_model::objects::{HandlerCompat, Node, SyncAsAsync};
 
     use super::{Metadata, MetadataGuard};
 
@@ -192,4 +192,17 @@ pub mod asynch {
             self.0.lock()
         }
     }
+
+    impl<T> AsyncMetadata for SyncAsAsync<T>
+    where
+        T: Metadata,
+    {
+        type MetadataGuard<'a> = T::MetadataGuard<'a>
+        where
+            Self: 'a;
+
+        async fn lock(&self) -> Self::MetadataGuard<'_> {
+            self.0.lock()
+        }
+    }
 }
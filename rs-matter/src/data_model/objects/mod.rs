@@ -54,3 +54,29 @@ pub struct DeviceType {
     pub dtype: u16,
     pub drev: u16,
 }
+
+/// A semantic tag, further describing an endpoint (e.g. its position among identical endpoints)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SemanticTag<'a> {
+    /// The vendor of a manufacturer-specific namespace, or None for a standard namespace
+    pub mfg_code: Option<u16>,
+    pub namespace_id: u8,
+    pub tag: u8,
+    pub label: Option<&'a str>,
+}
+
+impl<'a> ToTLV for SemanticTag<'a> {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag_type: TagType) -> Result<(), Error> {
+        tw.start_struct(tag_type)?;
+        match self.mfg_code {
+            Some(mfg_code) => tw.u16(TagType::Context(0), mfg_code)?,
+            None => tw.null(TagType::Context(0))?,
+        }
+        tw.u8(TagType::Context(1), self.namespace_id)?;
+        tw.u8(TagType::Context(2), self.tag)?;
+        if let Some(label) = self.label {
+            tw.utf8(TagType::Context(3), label.as_bytes())?;
+        }
+        tw.end_container()
+    }
+}
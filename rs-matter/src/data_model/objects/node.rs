@@ -35,7 +35,9 @@ use core::{
     iter::{once, Once},
 };
 
-use super::{AttrDetails, AttrId, Attribute, Cluster, ClusterId, CmdDetails, CmdId, EndptId};
+use super::{
+    Access, AttrDetails, AttrId, Attribute, Cluster, ClusterId, CmdDetails, CmdId, EndptId,
+};
 
 pub enum WildcardIter<T, E> {
     None,
@@ -105,6 +107,26 @@ impl<'a> Node<'a> {
         )
     }
 
+    /// Whether the read of a fabric-scoped attribute only reports the entries of the
+    /// accessing fabric. Reporting the entries of all fabrics, as requested with
+    /// `FabricFiltered=false`, takes the Administer privilege.
+    fn is_fabric_filtered(
+        fabric_filtered: bool,
+        accessor: &Accessor,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        attr: AttrId,
+    ) -> bool {
+        fabric_filtered
+            || Cluster::check_attr_access(
+                accessor,
+                GenericPath::new(Some(endpoint), Some(cluster), Some(attr as _)),
+                false,
+                Access::READ | Access::NEED_ADMIN,
+            )
+            .is_err()
+    }
+
     fn read_attr_requests<'s, 'm, P>(
         &'s self,
         attr_requests: P,
@@ -152,7 +174,13 @@ impl<'a> Node<'a> {
                             attr_id: attr.id,
                             list_index: path.list_index,
                             fab_idx: accessor.fab_idx,
-                            fab_filter: fabric_filtered,
+                            fab_filter: Self::is_fabric_filtered(
+                                fabric_filtered,
+                                accessor,
+                                ep.id,
+                                cl.id,
+                                attr.id,
+                            ),
                             dataver,
                             wildcard: true,
                         })
@@ -182,7 +210,13 @@ impl<'a> Node<'a> {
                             attr_id: attr,
                             list_index: path.list_index,
                             fab_idx: accessor.fab_idx,
-                            fab_filter: fabric_filtered,
+                            fab_filter: Self::is_fabric_filtered(
+                                fabric_filtered,
+                                accessor,
+                                ep,
+                                cl,
+                                attr,
+                            ),
                             dataver,
                             wildcard: false,
                         })
@@ -195,9 +229,12 @@ impl<'a> Node<'a> {
         }))
     }
 
+    /// The attributes to write, with these which may only be written as part of a
+    /// timed interaction rejected unless the write is `timed`
     pub fn write<'m>(
         &'m self,
         req: &'m WriteReq,
+        timed: bool,
         accessor: &'m Accessor<'m>,
     ) -> impl Iterator<Item = Result<(AttrDetails, TLVElement<'m>), AttrStatus>> + 'm {
         alloc!(req.write_requests.iter().flat_map(move |attr_data| {
@@ -221,13 +258,14 @@ impl<'a> Node<'a> {
                         attr_data.path.attr,
                     )
                     .filter(move |(ep, cl, attr)| {
-                        Cluster::check_attr_access(
-                            accessor,
-                            GenericPath::new(Some(ep.id), Some(cl.id), Some(attr.id as _)),
-                            true,
-                            attr.access,
-                        )
-                        .is_ok()
+                        (timed || !attr.access.contains(Access::TIMED_ONLY))
+                            && Cluster::check_attr_access(
+                                accessor,
+                                GenericPath::new(Some(ep.id), Some(cl.id), Some(attr.id as _)),
+                                true,
+                                attr.access,
+                            )
+                            .is_ok()
                     })
                     .map(move |(ep, cl, attr)| {
                         Ok((
@@ -252,23 +290,32 @@ impl<'a> Node<'a> {
                 let cl = attr_data.path.cluster.unwrap();
                 let attr = attr_data.path.attr.unwrap();
 
-                let result = match self.check_attribute(accessor, ep, cl, attr, true) {
-                    Ok(()) => Ok((
-                        AttrDetails {
-                            node: self,
-                            endpoint_id: ep,
-                            cluster_id: cl,
-                            attr_id: attr,
-                            list_index: attr_data.path.list_index,
-                            fab_idx: accessor.fab_idx,
-                            fab_filter: false,
-                            dataver: attr_data.data_ver,
-                            wildcard: false,
-                        },
-                        attr_data.data.unwrap_tlv().unwrap(),
-                    )),
-                    Err(err) => Err(AttrStatus::new(&attr_data.path.to_gp(), err, 0)),
-                };
+                let result =
+                    match self
+                        .check_attribute(accessor, ep, cl, attr, true)
+                        .and_then(|()| {
+                            if !timed && self.is_timed_write(ep, cl, attr) {
+                                Err(IMStatusCode::NeedsTimedInteraction)
+                            } else {
+                                Ok(())
+                            }
+                        }) {
+                        Ok(()) => Ok((
+                            AttrDetails {
+                                node: self,
+                                endpoint_id: ep,
+                                cluster_id: cl,
+                                attr_id: attr,
+                                list_index: attr_data.path.list_index,
+                                fab_idx: accessor.fab_idx,
+                                fab_filter: false,
+                                dataver: attr_data.data_ver,
+                                wildcard: false,
+                            },
+                            attr_data.data.unwrap_tlv().unwrap(),
+                        )),
+                        Err(err) => Err(AttrStatus::new(&attr_data.path.to_gp(), err, 0)),
+                    };
 
                 WildcardIter::Single(once(result))
             }
@@ -280,12 +327,30 @@ impl<'a> Node<'a> {
         req: &'m InvReq,
         accessor: &'m Accessor<'m>,
     ) -> impl Iterator<Item = Result<(CmdDetails, TLVElement<'m>), CmdStatus>> + 'm {
+        // The validity of the timed interaction itself (if any) is checked by the
+        // interaction model before we get here
+        let timed = req.timed_request.unwrap_or(false);
+
         alloc!(req
             .inv_requests
             .iter()
             .flat_map(|inv_requests| inv_requests.iter())
             .flat_map(move |cmd_data| {
-                if cmd_data.path.path.is_wildcard() {
+                if cmd_data.path.path.cluster.is_none() {
+                    WildcardIter::Single(once(Err(CmdStatus::new(
+                        cmd_data.path,
+                        IMStatusCode::UnsupportedCluster,
+                        0,
+                    )
+                    .set_command_ref(cmd_data.command_ref))))
+                } else if cmd_data.path.path.leaf.is_none() {
+                    WildcardIter::Single(once(Err(CmdStatus::new(
+                        cmd_data.path,
+                        IMStatusCode::UnsupportedCommand,
+                        0,
+                    )
+                    .set_command_ref(cmd_data.command_ref))))
+                } else if cmd_data.path.path.is_wildcard() {
                     let iter = self
                         .match_commands(
                             cmd_data.path.path.endpoint,
@@ -293,11 +358,13 @@ impl<'a> Node<'a> {
                             cmd_data.path.path.leaf.map(|leaf| leaf as _),
                         )
                         .filter(move |(ep, cl, cmd)| {
-                            Cluster::check_cmd_access(
-                                accessor,
-                                GenericPath::new(Some(ep.id), Some(cl.id), Some(*cmd)),
-                            )
-                            .is_ok()
+                            (timed || !cl.is_timed_command(*cmd))
+                                && Cluster::check_cmd_access(
+                                    accessor,
+                                    GenericPath::new(Some(ep.id), Some(cl.id), Some(*cmd)),
+                                    cl.command_access(*cmd),
+                                )
+                                .is_ok()
                         })
                         .map(move |(ep, cl, cmd)| {
                             Ok((
@@ -307,6 +374,7 @@ impl<'a> Node<'a> {
                                     cluster_id: cl.id,
                                     cmd_id: cmd,
                                     wildcard: true,
+                                    command_ref: cmd_data.command_ref,
                                 },
                                 cmd_data.data.clone().unwrap_tlv().unwrap(),
                             ))
@@ -318,7 +386,7 @@ impl<'a> Node<'a> {
                     let cl = cmd_data.path.path.cluster.unwrap();
                     let cmd = cmd_data.path.path.leaf.unwrap();
 
-                    let result = match self.check_command(accessor, ep, cl, cmd) {
+                    let result = match self.check_command(accessor, ep, cl, cmd, timed) {
                         Ok(()) => Ok((
                             CmdDetails {
                                 node: self,
@@ -326,10 +394,12 @@ impl<'a> Node<'a> {
                                 cluster_id: cmd_data.path.path.cluster.unwrap(),
                                 cmd_id: cmd_data.path.path.leaf.unwrap(),
                                 wildcard: false,
+                                command_ref: cmd_data.command_ref,
                             },
                             cmd_data.data.unwrap_tlv().unwrap(),
                         )),
-                        Err(err) => Err(CmdStatus::new(cmd_data.path, err, 0)),
+                        Err(err) => Err(CmdStatus::new(cmd_data.path, err, 0)
+                            .set_command_ref(cmd_data.command_ref)),
                     };
 
                     WildcardIter::Single(once(result))
@@ -385,28 +455,55 @@ impl<'a> Node<'a> {
             .and_then(|endpoint| endpoint.check_attribute(accessor, cl, attr, write))
     }
 
+    /// Whether the attribute may only be written as part of a timed interaction
+    fn is_timed_write(&self, ep: EndptId, cl: ClusterId, attr: AttrId) -> bool {
+        self.check_endpoint(ep)
+            .and_then(|endpoint| endpoint.check_cluster(cl))
+            .ok()
+            .and_then(|cluster| cluster.attribute(attr))
+            .map(|attribute| attribute.access.contains(Access::TIMED_ONLY))
+            .unwrap_or(false)
+    }
+
     pub fn check_command(
         &self,
         accessor: &Accessor,
         ep: EndptId,
         cl: ClusterId,
         cmd: CmdId,
+        timed: bool,
     ) -> Result<(), IMStatusCode> {
         self.check_endpoint(ep)
-            .and_then(|endpoint| endpoint.check_command(accessor, cl, cmd))
+            .and_then(|endpoint| endpoint.check_command(accessor, cl, cmd, timed))
     }
 
+    /// All the endpoints for a wildcard, or the one endpoint with the concrete ID
+    ///
+    /// A concrete endpoint is looked up (see `check_endpoint`), rather than matched against
+    /// every endpoint of the node, so that paths with a concrete endpoint stay cheap on large nodes.
     pub fn match_endpoints(&self, ep: Option<EndptId>) -> impl Iterator<Item = &'_ Endpoint> + '_ {
-        self.endpoints
-            .iter()
-            .filter(move |endpoint| ep.map(|id| id == endpoint.id).unwrap_or(true))
+        let (all, concrete) = match ep {
+            Some(ep) => (None, self.check_endpoint(ep).ok()),
+            None => (Some(self.endpoints.iter()), None),
+        };
+
+        all.into_iter().flatten().chain(concrete)
     }
 
+    /// The endpoints are usually declared in the order of their IDs, so they are looked up
+    /// with a binary search, falling back to a scan for the nodes declaring them out of order
     pub fn check_endpoint(&self, ep: EndptId) -> Result<&Endpoint, IMStatusCode> {
-        self.endpoints
-            .iter()
-            .find(|endpoint| endpoint.id == ep)
-            .ok_or(IMStatusCode::UnsupportedEndpoint)
+        match self
+            .endpoints
+            .binary_search_by_key(&ep, |endpoint| endpoint.id)
+        {
+            Ok(index) => Ok(&self.endpoints[index]),
+            Err(_) => self
+                .endpoints
+                .iter()
+                .find(|endpoint| endpoint.id == ep)
+                .ok_or(IMStatusCode::UnsupportedEndpoint),
+        }
     }
 }
 
@@ -3,40 +3,58 @@ use core::{borrow::Borrow, cell::RefCell};
 use crate::{
     acl::AclMgr,
     fabric::FabricMgr,
+    groups::GroupMgr,
     handler_chain_type,
     mdns::Mdns,
-    secure_channel::pake::PaseMgr,
+    secure_channel::{case::ResumptionMgr, pake::PaseMgr},
     utils::{epoch::Epoch, rand::Rand},
 };
 
 use super::{
     cluster_basic_information::{self, BasicInfoCluster, BasicInfoConfig},
-    objects::{Cluster, EmptyHandler, Endpoint, EndptId},
+    cluster_binding::BindingMgr,
+    cluster_group_key_management::{self, GroupKeyManagementCluster},
+    cluster_ota_requestor::OtaProviderMgr,
+    events::EventLogger,
+    objects::{Cluster, EmptyHandler, Endpoint, EndptId, Node},
     sdm::{
         admin_commissioning::{self, AdminCommCluster},
         dev_att::DevAttDataFetcher,
         failsafe::FailSafe,
         general_commissioning::{self, GenCommCluster},
+        general_diagnostics::{self, DiagMgr, GenDiagCluster},
+        icd_management::IcdClientMgr,
         noc::{self, NocCluster},
-        nw_commissioning::{self, NwCommCluster},
+        nw_commissioning::{self, NetworkDriver, NwCommCluster, WirelessNwCommCluster},
     },
+    subscriptions::SubscriptionMgr,
     system_model::{
         access_control::{self, AccessControlCluster},
         descriptor::{self, DescriptorCluster},
     },
 };
 
-pub type RootEndpointHandler<'a> = handler_chain_type!(
+/// The handler of a root endpoint, with the handler `N` of its Network Commissioning cluster
+pub type GenericRootEndpointHandler<'a, N> = handler_chain_type!(
     DescriptorCluster<'static>,
     BasicInfoCluster<'a>,
     GenCommCluster<'a>,
-    NwCommCluster,
+    N,
     AdminCommCluster<'a>,
     NocCluster<'a>,
-    AccessControlCluster<'a>
+    AccessControlCluster<'a>,
+    GroupKeyManagementCluster<'a>,
+    GenDiagCluster<'a>
 );
 
-pub const CLUSTERS: [Cluster<'static>; 7] = [
+/// The handler of the root endpoint of an Ethernet device
+pub type RootEndpointHandler<'a> = GenericRootEndpointHandler<'a, NwCommCluster>;
+
+/// The handler of the root endpoint of a Wi-Fi or a Thread device
+pub type WirelessRootEndpointHandler<'a> =
+    GenericRootEndpointHandler<'a, WirelessNwCommCluster<'a>>;
+
+pub const CLUSTERS: [Cluster<'static>; 9] = [
     descriptor::CLUSTER,
     cluster_basic_information::CLUSTER,
     general_commissioning::CLUSTER,
@@ -44,31 +62,151 @@ pub const CLUSTERS: [Cluster<'static>; 7] = [
     admin_commissioning::CLUSTER,
     noc::CLUSTER,
     access_control::CLUSTER,
+    cluster_group_key_management::CLUSTER,
+    general_diagnostics::CLUSTER,
 ];
 
+/// The clusters of the root endpoint of a Wi-Fi device
+pub const WIFI_CLUSTERS: [Cluster<'static>; 9] =
+    with_nw_commissioning(nw_commissioning::WIFI_CLUSTER);
+
+/// The clusters of the root endpoint of a Thread device
+pub const THREAD_CLUSTERS: [Cluster<'static>; 9] =
+    with_nw_commissioning(nw_commissioning::THREAD_CLUSTER);
+
+const fn with_nw_commissioning(cluster: Cluster<'static>) -> [Cluster<'static>; 9] {
+    let mut clusters = CLUSTERS;
+    clusters[3] = cluster;
+
+    clusters
+}
+
 pub const fn endpoint(id: EndptId) -> Endpoint<'static> {
     Endpoint {
         id,
-        device_type: super::device_types::DEV_TYPE_ROOT_NODE,
+        device_types: &[super::device_types::DEV_TYPE_ROOT_NODE],
+        tags: &[],
         clusters: &CLUSTERS,
     }
 }
 
-pub fn handler<'a, T>(endpoint_id: u16, matter: &'a T) -> RootEndpointHandler<'a>
+/// The root endpoint of a Wi-Fi device, to be used with `wireless_handler`
+pub const fn wifi_endpoint(id: EndptId) -> Endpoint<'static> {
+    Endpoint {
+        clusters: &WIFI_CLUSTERS,
+        ..endpoint(id)
+    }
+}
+
+/// The root endpoint of a Thread device, to be used with `wireless_handler`
+pub const fn thread_endpoint(id: EndptId) -> Endpoint<'static> {
+    Endpoint {
+        clusters: &THREAD_CLUSTERS,
+        ..endpoint(id)
+    }
+}
+
+pub fn handler<'a, T>(endpoint_id: u16, node: &Node, matter: &'a T) -> RootEndpointHandler<'a>
+where
+    T: Borrow<BasicInfoConfig<'a>>
+        + Borrow<dyn DevAttDataFetcher + 'a>
+        + Borrow<RefCell<PaseMgr>>
+        + Borrow<RefCell<FabricMgr>>
+        + Borrow<RefCell<AclMgr>>
+        + Borrow<dyn EventLogger + 'a>
+        + Borrow<RefCell<GroupMgr>>
+        + Borrow<RefCell<SubscriptionMgr>>
+        + Borrow<RefCell<BindingMgr>>
+        + Borrow<RefCell<OtaProviderMgr>>
+        + Borrow<RefCell<IcdClientMgr>>
+        + Borrow<RefCell<ResumptionMgr>>
+        + Borrow<RefCell<FailSafe>>
+        + Borrow<RefCell<DiagMgr>>
+        + Borrow<dyn Mdns + 'a>
+        + Borrow<Epoch>
+        + Borrow<Rand>
+        + 'a,
+{
+    handler_with(
+        endpoint_id,
+        node,
+        matter,
+        NwCommCluster::new(*matter.borrow()),
+    )
+}
+
+/// The handler of the root endpoint of a Wi-Fi or a Thread device, whose radio is operated
+/// by `driver`
+pub fn wireless_handler<'a, T>(
+    endpoint_id: u16,
+    node: &Node,
+    matter: &'a T,
+    driver: NetworkDriver<'a>,
+) -> WirelessRootEndpointHandler<'a>
+where
+    T: Borrow<BasicInfoConfig<'a>>
+        + Borrow<dyn DevAttDataFetcher + 'a>
+        + Borrow<RefCell<PaseMgr>>
+        + Borrow<RefCell<FabricMgr>>
+        + Borrow<RefCell<AclMgr>>
+        + Borrow<dyn EventLogger + 'a>
+        + Borrow<RefCell<GroupMgr>>
+        + Borrow<RefCell<SubscriptionMgr>>
+        + Borrow<RefCell<BindingMgr>>
+        + Borrow<RefCell<OtaProviderMgr>>
+        + Borrow<RefCell<IcdClientMgr>>
+        + Borrow<RefCell<ResumptionMgr>>
+        + Borrow<RefCell<FailSafe>>
+        + Borrow<RefCell<DiagMgr>>
+        + Borrow<dyn Mdns + 'a>
+        + Borrow<Epoch>
+        + Borrow<Rand>
+        + 'a,
+{
+    let nw_comm = WirelessNwCommCluster::new(driver, matter.borrow(), *matter.borrow());
+
+    handler_with(endpoint_id, node, matter, nw_comm)
+}
+
+/// The handler of a root endpoint with the provided handler of its Network Commissioning cluster
+pub fn handler_with<'a, T, N>(
+    endpoint_id: u16,
+    node: &Node,
+    matter: &'a T,
+    nw_comm: N,
+) -> GenericRootEndpointHandler<'a, N>
 where
     T: Borrow<BasicInfoConfig<'a>>
         + Borrow<dyn DevAttDataFetcher + 'a>
         + Borrow<RefCell<PaseMgr>>
         + Borrow<RefCell<FabricMgr>>
         + Borrow<RefCell<AclMgr>>
+        + Borrow<dyn EventLogger + 'a>
+        + Borrow<RefCell<GroupMgr>>
+        + Borrow<RefCell<SubscriptionMgr>>
+        + Borrow<RefCell<BindingMgr>>
+        + Borrow<RefCell<OtaProviderMgr>>
+        + Borrow<RefCell<IcdClientMgr>>
+        + Borrow<RefCell<ResumptionMgr>>
         + Borrow<RefCell<FailSafe>>
+        + Borrow<RefCell<DiagMgr>>
         + Borrow<dyn Mdns + 'a>
         + Borrow<Epoch>
         + Borrow<Rand>
         + 'a,
 {
-    wrap(
+    wrap_with(
         endpoint_id,
+        node,
+        nw_comm,
+        matter.borrow(),
+        matter.borrow(),
+        matter.borrow(),
+        matter.borrow(),
+        matter.borrow(),
+        matter.borrow(),
+        matter.borrow(),
+        matter.borrow(),
         matter.borrow(),
         matter.borrow(),
         matter.borrow(),
@@ -84,42 +222,138 @@ where
 #[allow(clippy::too_many_arguments)]
 pub fn wrap<'a>(
     endpoint_id: u16,
+    node: &Node,
     basic_info: &'a BasicInfoConfig<'a>,
     dev_att: &'a dyn DevAttDataFetcher,
     pase: &'a RefCell<PaseMgr>,
     fabric: &'a RefCell<FabricMgr>,
     acl: &'a RefCell<AclMgr>,
+    event: &'a dyn EventLogger,
+    group: &'a RefCell<GroupMgr>,
+    subscription: &'a RefCell<SubscriptionMgr>,
+    binding: &'a RefCell<BindingMgr>,
+    ota_provider: &'a RefCell<OtaProviderMgr>,
+    icd_client: &'a RefCell<IcdClientMgr>,
+    resumption: &'a RefCell<ResumptionMgr>,
     failsafe: &'a RefCell<FailSafe>,
+    diag: &'a RefCell<DiagMgr>,
     mdns: &'a dyn Mdns,
     epoch: Epoch,
     rand: Rand,
 ) -> RootEndpointHandler<'a> {
+    wrap_with(
+        endpoint_id,
+        node,
+        NwCommCluster::new(rand),
+        basic_info,
+        dev_att,
+        pase,
+        fabric,
+        acl,
+        event,
+        group,
+        subscription,
+        binding,
+        ota_provider,
+        icd_client,
+        resumption,
+        failsafe,
+        diag,
+        mdns,
+        epoch,
+        rand,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn wrap_with<'a, N>(
+    endpoint_id: u16,
+    node: &Node,
+    nw_comm: N,
+    basic_info: &'a BasicInfoConfig<'a>,
+    dev_att: &'a dyn DevAttDataFetcher,
+    pase: &'a RefCell<PaseMgr>,
+    fabric: &'a RefCell<FabricMgr>,
+    acl: &'a RefCell<AclMgr>,
+    event: &'a dyn EventLogger,
+    group: &'a RefCell<GroupMgr>,
+    subscription: &'a RefCell<SubscriptionMgr>,
+    binding: &'a RefCell<BindingMgr>,
+    ota_provider: &'a RefCell<OtaProviderMgr>,
+    icd_client: &'a RefCell<IcdClientMgr>,
+    resumption: &'a RefCell<ResumptionMgr>,
+    failsafe: &'a RefCell<FailSafe>,
+    diag: &'a RefCell<DiagMgr>,
+    mdns: &'a dyn Mdns,
+    epoch: Epoch,
+    rand: Rand,
+) -> GenericRootEndpointHandler<'a, N> {
     EmptyHandler
+        .chain(
+            endpoint_id,
+            general_diagnostics::ID,
+            GenDiagCluster::new(diag, rand),
+        )
+        .chain(
+            endpoint_id,
+            cluster_group_key_management::ID,
+            GroupKeyManagementCluster::new(fabric, group, rand),
+        )
         .chain(
             endpoint_id,
             access_control::ID,
-            AccessControlCluster::new(acl, rand),
+            AccessControlCluster::new(acl, event, rand),
         )
         .chain(
             endpoint_id,
             noc::ID,
-            NocCluster::new(dev_att, fabric, acl, failsafe, mdns, epoch, rand),
+            NocCluster::new(
+                dev_att,
+                fabric,
+                acl,
+                group,
+                subscription,
+                binding,
+                ota_provider,
+                icd_client,
+                resumption,
+                failsafe,
+                mdns,
+                epoch,
+                rand,
+            ),
         )
         .chain(
             endpoint_id,
             admin_commissioning::ID,
-            AdminCommCluster::new(pase, mdns, rand),
+            AdminCommCluster::new(pase, fabric, mdns, rand),
         )
-        .chain(endpoint_id, nw_commissioning::ID, NwCommCluster::new(rand))
+        .chain(endpoint_id, nw_commissioning::ID, nw_comm)
         .chain(
             endpoint_id,
             general_commissioning::ID,
-            GenCommCluster::new(failsafe, rand),
+            GenCommCluster::new(
+                failsafe,
+                fabric,
+                acl,
+                group,
+                subscription,
+                binding,
+                ota_provider,
+                icd_client,
+                resumption,
+                mdns,
+                rand,
+            ),
         )
         .chain(
             endpoint_id,
             cluster_basic_information::ID,
             BasicInfoCluster::new(basic_info, rand),
         )
-        .chain(endpoint_id, descriptor::ID, DescriptorCluster::new(rand))
+        .chain(
+            endpoint_id,
+            descriptor::ID,
+            DescriptorCluster::new(node, rand),
+        )
 }
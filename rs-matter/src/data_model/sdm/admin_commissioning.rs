@@ -17,22 +17,36 @@
 
 use core::cell::RefCell;
 use core::convert::TryInto;
+use core::time::Duration;
 
 use crate::data_model::objects::*;
-use crate::mdns::Mdns;
-use crate::secure_channel::pake::PaseMgr;
-use crate::secure_channel::spake2p::VerifierData;
+use crate::fabric::FabricMgr;
+use crate::mdns::{CommissioningMode, Mdns};
+use crate::secure_channel::pake::{PaseMgr, WindowAdmin};
+use crate::secure_channel::spake2p::{
+    VerifierData, MAX_ITERATION_COUNT, MAX_SALT_SIZE_BYTES, MIN_ITERATION_COUNT,
+    MIN_SALT_SIZE_BYTES, VERIFIER_SIZE_BYTES,
+};
 use crate::tlv::{FromTLV, Nullable, OctetStr, TLVElement};
 use crate::transport::exchange::Exchange;
 use crate::utils::rand::Rand;
 use crate::{attribute_enum, cmd_enter};
 use crate::{command_enum, error::*};
-use log::info;
+use log::{error, info};
 use num_derive::FromPrimitive;
 use strum::{EnumDiscriminants, FromRepr};
 
 pub const ID: u32 = 0x003C;
 
+/// The Basic feature, which adds the OpenBasicCommissioningWindow command
+pub const FEATURE_BASIC: u32 = 0x01;
+
+/// The range of the timeouts that a commissioning window may be opened with
+const MIN_WINDOW_TIMEOUT_SECS: u16 = 180;
+const MAX_WINDOW_TIMEOUT_SECS: u16 = 900;
+
+const MAX_DISCRIMINATOR: u16 = 0xFFF;
+
 #[derive(FromPrimitive, Debug, Copy, Clone, PartialEq)]
 pub enum WindowStatus {
     WindowNotOpen = 0,
@@ -40,12 +54,20 @@ pub enum WindowStatus {
     BasicWindowOpen = 2,
 }
 
+/// The cluster-specific status codes of the commands
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StatusCode {
+    Busy = 2,
+    PAKEParameterError = 3,
+    WindowNotOpen = 4,
+}
+
 #[derive(Copy, Clone, Debug, FromRepr, EnumDiscriminants)]
 #[repr(u16)]
 pub enum Attributes {
     WindowStatus(AttrType<u8>) = 0,
     AdminFabricIndex(AttrType<Nullable<u8>>) = 1,
-    AdminVendorId(AttrType<Nullable<u8>>) = 2,
+    AdminVendorId(AttrType<Nullable<u16>>) = 2,
 }
 
 attribute_enum!(Attributes);
@@ -62,7 +84,8 @@ command_enum!(Commands);
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID as _,
-    feature_map: 0,
+    revision: 1,
+    feature_map: FEATURE_BASIC,
     attributes: &[
         FEATURE_MAP,
         ATTRIBUTE_LIST,
@@ -84,32 +107,57 @@ pub const CLUSTER: Cluster<'static> = Cluster {
     ],
     commands: &[
         Commands::OpenCommWindow as _,
-        // Commands::OpenBasicCommWindow as _,
-        // Commands::RevokeComm as _,
+        Commands::OpenBasicCommWindow as _,
+        Commands::RevokeComm as _,
+    ],
+    generated_commands: &[],
+    timed_commands: &[
+        Commands::OpenCommWindow as _,
+        Commands::OpenBasicCommWindow as _,
+        Commands::RevokeComm as _,
+    ],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[
+        Commands::OpenCommWindow as _,
+        Commands::OpenBasicCommWindow as _,
+        Commands::RevokeComm as _,
     ],
 };
 
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 pub struct OpenCommWindowReq<'a> {
-    _timeout: u16,
+    timeout: u16,
     verifier: OctetStr<'a>,
     discriminator: u16,
     iterations: u32,
     salt: OctetStr<'a>,
 }
 
+#[derive(FromTLV)]
+pub struct OpenBasicCommWindowReq {
+    timeout: u16,
+}
+
 pub struct AdminCommCluster<'a> {
     data_ver: Dataver,
     pase_mgr: &'a RefCell<PaseMgr>,
+    fabric_mgr: &'a RefCell<FabricMgr>,
     mdns: &'a dyn Mdns,
 }
 
 impl<'a> AdminCommCluster<'a> {
-    pub fn new(pase_mgr: &'a RefCell<PaseMgr>, mdns: &'a dyn Mdns, rand: Rand) -> Self {
+    pub fn new(
+        pase_mgr: &'a RefCell<PaseMgr>,
+        fabric_mgr: &'a RefCell<FabricMgr>,
+        mdns: &'a dyn Mdns,
+        rand: Rand,
+    ) -> Self {
         Self {
             data_ver: Dataver::new(rand),
             pase_mgr,
+            fabric_mgr,
             mdns,
         }
     }
@@ -119,12 +167,27 @@ impl<'a> AdminCommCluster<'a> {
             if attr.is_system() {
                 CLUSTER.read(attr.attr_id, writer)
             } else {
+                let pase_mgr = self.pase_mgr.borrow();
+                let admin = pase_mgr.window_admin();
+
                 match attr.attr_id.try_into()? {
-                    Attributes::WindowStatus(codec) => codec.encode(writer, 1),
-                    Attributes::AdminVendorId(codec) => codec.encode(writer, Nullable::NotNull(1)),
-                    Attributes::AdminFabricIndex(codec) => {
-                        codec.encode(writer, Nullable::NotNull(1))
+                    Attributes::WindowStatus(codec) => {
+                        let status = match pase_mgr.commissioning_mode() {
+                            None => WindowStatus::WindowNotOpen,
+                            Some(CommissioningMode::Enhanced) => WindowStatus::EnhancedWindowOpen,
+                            Some(CommissioningMode::Basic) => WindowStatus::BasicWindowOpen,
+                        };
+
+                        codec.encode(writer, status as _)
                     }
+                    Attributes::AdminFabricIndex(codec) => codec.encode(
+                        writer,
+                        admin.map_or(Nullable::Null, |admin| Nullable::NotNull(admin.fab_idx)),
+                    ),
+                    Attributes::AdminVendorId(codec) => codec.encode(
+                        writer,
+                        admin.map_or(Nullable::Null, |admin| Nullable::NotNull(admin.vendor_id)),
+                    ),
                 }
             }
         } else {
@@ -134,13 +197,19 @@ impl<'a> AdminCommCluster<'a> {
 
     pub fn invoke(
         &self,
+        exchange: &Exchange,
         cmd: &CmdDetails,
         data: &TLVElement,
-        _encoder: CmdDataEncoder,
+        encoder: CmdDataEncoder,
     ) -> Result<(), Error> {
         match cmd.cmd_id.try_into()? {
-            Commands::OpenCommWindow => self.handle_command_opencomm_win(data)?,
-            _ => Err(ErrorCode::CommandNotFound)?,
+            Commands::OpenCommWindow => {
+                self.handle_command_opencomm_win(self.window_admin(exchange)?, data, encoder)?
+            }
+            Commands::OpenBasicCommWindow => {
+                self.handle_command_openbasiccomm_win(self.window_admin(exchange)?, data, encoder)?
+            }
+            Commands::RevokeComm => self.handle_command_revokecomm(encoder)?,
         }
 
         self.data_ver.changed();
@@ -148,15 +217,106 @@ impl<'a> AdminCommCluster<'a> {
         Ok(())
     }
 
-    fn handle_command_opencomm_win(&self, data: &TLVElement) -> Result<(), Error> {
+    /// The administrator on behalf of which the command is invoked
+    fn window_admin(&self, exchange: &Exchange) -> Result<WindowAdmin, Error> {
+        let fab_idx = exchange
+            .with_session(|sess| Ok(sess.get_local_fabric_idx()))?
+            .ok_or(ErrorCode::UnsupportedAccess)?;
+
+        let vendor_id = self
+            .fabric_mgr
+            .borrow()
+            .get_fabric(fab_idx as _)?
+            .map(|fabric| fabric.get_vendor_id())
+            .ok_or(ErrorCode::UnsupportedAccess)?;
+
+        Ok(WindowAdmin { fab_idx, vendor_id })
+    }
+
+    fn handle_command_opencomm_win(
+        &self,
+        admin: WindowAdmin,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
         cmd_enter!("Open Commissioning Window");
         let req = OpenCommWindowReq::from_tlv(data)?;
+
+        if !Self::is_valid_timeout(req.timeout) || req.discriminator > MAX_DISCRIMINATOR {
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
+        if self.pase_mgr.borrow().is_pase_session_enabled() {
+            return encoder.cluster_status(StatusCode::Busy as _);
+        }
+
+        if req.verifier.0.len() != VERIFIER_SIZE_BYTES
+            || !(MIN_ITERATION_COUNT..=MAX_ITERATION_COUNT).contains(&req.iterations)
+            || !(MIN_SALT_SIZE_BYTES..=MAX_SALT_SIZE_BYTES).contains(&req.salt.0.len())
+        {
+            error!("Invalid PAKE parameters");
+            return encoder.cluster_status(StatusCode::PAKEParameterError as _);
+        }
+
         let verifier = VerifierData::new(req.verifier.0, req.iterations, req.salt.0);
-        self.pase_mgr
-            .borrow_mut()
-            .enable_pase_session(verifier, req.discriminator, self.mdns)?;
 
-        Ok(())
+        self.pase_mgr.borrow_mut().open_window(
+            verifier,
+            req.discriminator,
+            CommissioningMode::Enhanced,
+            Duration::from_secs(req.timeout as _),
+            admin,
+            self.mdns,
+        )
+    }
+
+    fn handle_command_openbasiccomm_win(
+        &self,
+        admin: WindowAdmin,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("Open Basic Commissioning Window");
+        let req = OpenBasicCommWindowReq::from_tlv(data)?;
+
+        if !Self::is_valid_timeout(req.timeout) {
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
+        if self.pase_mgr.borrow().is_pase_session_enabled() {
+            return encoder.cluster_status(StatusCode::Busy as _);
+        }
+
+        // The basic window is opened with the onboarding passcode of the device
+        let (verifier, discriminator) = self
+            .pase_mgr
+            .borrow()
+            .basic_commissioning()
+            .ok_or(ErrorCode::InvalidState)?;
+
+        self.pase_mgr.borrow_mut().open_window(
+            verifier,
+            discriminator,
+            CommissioningMode::Basic,
+            Duration::from_secs(req.timeout as _),
+            admin,
+            self.mdns,
+        )
+    }
+
+    fn handle_command_revokecomm(&self, encoder: CmdDataEncoder) -> Result<(), Error> {
+        cmd_enter!("Revoke Commissioning");
+
+        if !self.pase_mgr.borrow().is_pase_session_enabled() {
+            return encoder.cluster_status(StatusCode::WindowNotOpen as _);
+        }
+
+        // TODO: Also disarm the fail-safe, if it was armed by a PASE session of the window
+        self.pase_mgr.borrow_mut().disable_pase_session(self.mdns)
+    }
+
+    fn is_valid_timeout(timeout: u16) -> bool {
+        (MIN_WINDOW_TIMEOUT_SECS..=MAX_WINDOW_TIMEOUT_SECS).contains(&timeout)
     }
 }
 
@@ -167,12 +327,12 @@ impl<'a> Handler for AdminCommCluster<'a> {
 
     fn invoke(
         &self,
-        _exchange: &Exchange,
+        exchange: &Exchange,
         cmd: &CmdDetails,
         data: &TLVElement,
         encoder: CmdDataEncoder,
     ) -> Result<(), Error> {
-        AdminCommCluster::invoke(self, cmd, data, encoder)
+        AdminCommCluster::invoke(self, exchange, cmd, data, encoder)
     }
 }
 
@@ -183,3 +343,186 @@ impl<'a> ChangeNotifier<()> for AdminCommCluster<'a> {
         self.data_ver.consume_change(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::sync::atomic::Ordering;
+
+    use crate::{
+        data_model::objects::{
+            AttrDataEncoder, AttrDetails, CmdDataEncoder, CmdDataTracker, CmdDetails, Node,
+        },
+        fabric::FabricMgr,
+        mdns::DummyMdns,
+        secure_channel::pake::{PaseMgr, WindowAdmin},
+        test_support::mock_epoch,
+        tlv::{get_root_node_struct, TLVWriter, TagType},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{
+        AdminCommCluster, AttributesDiscriminants, Commands, StatusCode, WindowStatus, ID,
+    };
+
+    mock_epoch!(MOCK_NOW_SECS, mock_epoch, secs);
+
+    const ADMIN: WindowAdmin = WindowAdmin {
+        fab_idx: 1,
+        vendor_id: 0xFFF1,
+    };
+
+    /// Invoke the command, returning the cluster-specific status it failed with, if any
+    fn invoke(cluster: &AdminCommCluster, cmd_id: Commands, timeout: u16) -> Option<u16> {
+        let mut req_buf = [0; 200];
+        let mut req_writebuf = WriteBuf::new(&mut req_buf);
+        let mut tw = TLVWriter::new(&mut req_writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.u16(TagType::Context(0), timeout).unwrap();
+        if matches!(cmd_id, Commands::OpenCommWindow) {
+            tw.str8(TagType::Context(1), &[0x11; 97]).unwrap();
+            tw.u16(TagType::Context(2), 840).unwrap();
+            tw.u32(TagType::Context(3), 1000).unwrap();
+            tw.str8(TagType::Context(4), &[0x22; 16]).unwrap();
+        }
+        tw.end_container().unwrap();
+
+        let req = get_root_node_struct(req_writebuf.as_slice()).unwrap();
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let node = Node {
+            id: 0,
+            endpoints: &[],
+        };
+        let cmd = CmdDetails {
+            node: &node,
+            endpoint_id: 0,
+            cluster_id: ID,
+            cmd_id: cmd_id as _,
+            wildcard: false,
+            command_ref: None,
+        };
+        let mut tracker = CmdDataTracker::new();
+        let encoder = CmdDataEncoder::new(&cmd, &mut tracker, &mut tw);
+
+        match cmd_id {
+            Commands::OpenCommWindow => cluster.handle_command_opencomm_win(ADMIN, &req, encoder),
+            Commands::OpenBasicCommWindow => {
+                cluster.handle_command_openbasiccomm_win(ADMIN, &req, encoder)
+            }
+            Commands::RevokeComm => cluster.handle_command_revokecomm(encoder),
+        }
+        .unwrap();
+
+        if writebuf.as_slice().is_empty() {
+            None
+        } else {
+            Some(
+                get_root_node_struct(writebuf.as_slice())
+                    .unwrap()
+                    .find_tag(1)
+                    .unwrap()
+                    .find_tag(1)
+                    .unwrap()
+                    .find_tag(1)
+                    .unwrap()
+                    .u16()
+                    .unwrap(),
+            )
+        }
+    }
+
+    fn window_status(cluster: &AdminCommCluster) -> u8 {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 0,
+            cluster_id: ID,
+            attr_id: AttributesDiscriminants::WindowStatus as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .unwrap();
+
+        get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap()
+            .u8()
+            .unwrap()
+    }
+
+    #[test]
+    /// Only one commissioning window may be open at a time
+    fn busy_window() {
+        let pase_mgr = RefCell::new(PaseMgr::new(mock_epoch, dummy_rand));
+        let fabric_mgr = RefCell::new(FabricMgr::new());
+        let cluster = AdminCommCluster::new(&pase_mgr, &fabric_mgr, &DummyMdns, dummy_rand);
+
+        assert_eq!(
+            invoke(&cluster, Commands::RevokeComm, 0),
+            Some(StatusCode::WindowNotOpen as _)
+        );
+
+        assert_eq!(invoke(&cluster, Commands::OpenCommWindow, 180), None);
+        assert_eq!(
+            window_status(&cluster),
+            WindowStatus::EnhancedWindowOpen as u8
+        );
+        assert_eq!(pase_mgr.borrow().window_admin(), Some(ADMIN));
+
+        assert_eq!(
+            invoke(&cluster, Commands::OpenCommWindow, 180),
+            Some(StatusCode::Busy as _)
+        );
+        assert_eq!(
+            invoke(&cluster, Commands::OpenBasicCommWindow, 180),
+            Some(StatusCode::Busy as _)
+        );
+
+        assert_eq!(invoke(&cluster, Commands::RevokeComm, 0), None);
+        assert_eq!(window_status(&cluster), WindowStatus::WindowNotOpen as u8);
+    }
+
+    #[test]
+    /// The window closes on its own once its timeout elapses
+    fn window_timeout() {
+        MOCK_NOW_SECS.store(1000, Ordering::SeqCst);
+
+        let pase_mgr = RefCell::new(PaseMgr::new(mock_epoch, dummy_rand));
+        let fabric_mgr = RefCell::new(FabricMgr::new());
+        let cluster = AdminCommCluster::new(&pase_mgr, &fabric_mgr, &DummyMdns, dummy_rand);
+
+        assert_eq!(invoke(&cluster, Commands::OpenCommWindow, 300), None);
+
+        MOCK_NOW_SECS.store(1299, Ordering::SeqCst);
+        pase_mgr.borrow_mut().expire_window(&DummyMdns).unwrap();
+        assert_eq!(
+            window_status(&cluster),
+            WindowStatus::EnhancedWindowOpen as u8
+        );
+
+        MOCK_NOW_SECS.store(1300, Ordering::SeqCst);
+        pase_mgr.borrow_mut().expire_window(&DummyMdns).unwrap();
+        assert_eq!(window_status(&cluster), WindowStatus::WindowNotOpen as u8);
+        assert!(pase_mgr.borrow().window_admin().is_none());
+    }
+}
@@ -15,7 +15,17 @@
  *    limitations under the License.
  */
 
-use crate::error::Error;
+use crate::crypto::{self, KeyPair};
+use crate::error::{Error, ErrorCode};
+use crate::tlv::{TLVWriter, TagType};
+use crate::utils::epoch::Epoch;
+use crate::utils::writebuf::WriteBuf;
+use log::error;
+
+/// The length of the attestation nonce of an `AttestationRequest`
+pub const ATTESTATION_NONCE_LEN: usize = 32;
+
+const MAX_CERT_DECLARATION_LEN: usize = 600;
 
 /// Device Attestation Data Type
 pub enum DataType {
@@ -43,3 +53,210 @@ pub trait DevAttDataFetcher {
     /// The type of data that can be queried is defined in the [DataType] enum.
     fn get_devatt_data(&self, data_type: DataType, data: &mut [u8]) -> Result<usize, Error>;
 }
+
+/// The Device Attestation Manager
+///
+/// Produces the device attestation responses of the Operational Credentials cluster from the
+/// DAC, the PAI and the Certification Declaration that a [DevAttDataFetcher] provides. The
+/// DAC private key never leaves the manager, other than for signing.
+pub struct AttestationMgr<'a> {
+    dev_att: &'a dyn DevAttDataFetcher,
+    epoch: Epoch,
+}
+
+impl<'a> AttestationMgr<'a> {
+    pub fn new(dev_att: &'a dyn DevAttDataFetcher, epoch: Epoch) -> Self {
+        Self { dev_att, epoch }
+    }
+
+    /// Get the DAC or the PAI certificate
+    pub fn cert<'b>(&self, data_type: DataType, buf: &'b mut [u8]) -> Result<&'b [u8], Error> {
+        if !matches!(data_type, DataType::DAC | DataType::PAI) {
+            Err(ErrorCode::Invalid)?;
+        }
+
+        let len = self.dev_att.get_devatt_data(data_type, buf)?;
+
+        Ok(&buf[..len])
+    }
+
+    /// Write the attestation elements, and their signature over the attestation challenge
+    /// of the session, as the fields of an `AttestationResponse`
+    ///
+    /// The attestation elements carry the Certification Declaration, the nonce of the
+    /// request and the current time.
+    pub fn attestation(
+        &self,
+        att_nonce: &[u8],
+        att_challenge: &[u8],
+        buf: &mut [u8],
+        resp: &mut TLVWriter,
+    ) -> Result<(), Error> {
+        if att_nonce.len() != ATTESTATION_NONCE_LEN {
+            error!("Invalid attestation nonce length: {}", att_nonce.len());
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
+        let mut cert_dec = [0; MAX_CERT_DECLARATION_LEN];
+        let len = self
+            .dev_att
+            .get_devatt_data(DataType::CertDeclaration, &mut cert_dec)?;
+        let cert_dec = &cert_dec[..len];
+
+        let mut elements = WriteBuf::new(buf);
+        let mut writer = TLVWriter::new(&mut elements);
+        writer.start_struct(TagType::Anonymous)?;
+        writer.str16(TagType::Context(1), cert_dec)?;
+        writer.str8(TagType::Context(2), att_nonce)?;
+        writer.u32(TagType::Context(3), (self.epoch)().as_secs() as u32)?;
+        writer.end_container()?;
+
+        resp.str16(TagType::Context(0), elements.as_slice())?;
+
+        self.sign(&mut elements, att_challenge, resp)
+    }
+
+    /// Sign the elements, with the attestation challenge of the session appended to them,
+    /// using the DAC private key, and write the signature as field 1 of the response
+    ///
+    /// The challenge is appended to `elements` in place.
+    pub fn sign(
+        &self,
+        elements: &mut WriteBuf,
+        att_challenge: &[u8],
+        resp: &mut TLVWriter,
+    ) -> Result<(), Error> {
+        let dac_key = {
+            let mut pubkey = [0_u8; crypto::EC_POINT_LEN_BYTES];
+            let mut privkey = [0_u8; crypto::BIGNUM_LEN_BYTES];
+            self.dev_att
+                .get_devatt_data(DataType::DACPubKey, &mut pubkey)?;
+            self.dev_att
+                .get_devatt_data(DataType::DACPrivKey, &mut privkey)?;
+            KeyPair::new_from_components(&pubkey, &privkey)
+        }?;
+
+        elements.copy_from_slice(att_challenge)?;
+
+        let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+        dac_key.sign_msg(elements.as_slice(), &mut signature)?;
+
+        resp.str8(TagType::Context(1), &signature)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{
+        crypto::{self, KeyPair},
+        error::{Error, ErrorCode},
+        test_support::seq_rand,
+        tlv::{get_root_node_struct, TLVWriter, TagType},
+        utils::{epoch::dummy_epoch, writebuf::WriteBuf},
+    };
+
+    use super::{AttestationMgr, DataType, DevAttDataFetcher, ATTESTATION_NONCE_LEN};
+
+    const TEST_CERT_DECLARATION: &[u8] = &[0x30, 0x81, 0xE8, 0x06, 0x09];
+
+    pub(crate) struct TestDevAtt {
+        pubkey: [u8; crypto::EC_POINT_LEN_BYTES],
+        privkey: [u8; crypto::BIGNUM_LEN_BYTES],
+    }
+
+    impl TestDevAtt {
+        pub(crate) fn new(dac: &KeyPair) -> Self {
+            let mut dev_att = Self {
+                pubkey: [0; crypto::EC_POINT_LEN_BYTES],
+                privkey: [0; crypto::BIGNUM_LEN_BYTES],
+            };
+            dac.get_public_key(&mut dev_att.pubkey).unwrap();
+            dac.get_private_key(&mut dev_att.privkey).unwrap();
+
+            dev_att
+        }
+    }
+
+    impl DevAttDataFetcher for TestDevAtt {
+        fn get_devatt_data(&self, data_type: DataType, data: &mut [u8]) -> Result<usize, Error> {
+            let src: &[u8] = match data_type {
+                DataType::DACPubKey => &self.pubkey,
+                DataType::DACPrivKey => &self.privkey,
+                DataType::CertDeclaration => TEST_CERT_DECLARATION,
+                _ => &[],
+            };
+            data[..src.len()].copy_from_slice(src);
+
+            Ok(src.len())
+        }
+    }
+
+    #[test]
+    /// The attestation elements carry the CD and the nonce, and are signed with the DAC
+    /// together with the attestation challenge
+    fn attestation_signature() {
+        let dac = KeyPair::new(seq_rand).unwrap();
+        let dev_att = TestDevAtt::new(&dac);
+        let attestation = AttestationMgr::new(&dev_att, dummy_epoch);
+
+        let nonce = [0x11; ATTESTATION_NONCE_LEN];
+        let challenge = [0x22; crypto::SYMM_KEY_LEN_BYTES];
+
+        let mut buf = [0; 900];
+        let mut element_buf = [0; 900];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        attestation
+            .attestation(&nonce, &challenge, &mut element_buf, &mut tw)
+            .unwrap();
+        tw.end_container().unwrap();
+
+        let resp = get_root_node_struct(writebuf.as_slice()).unwrap();
+        let elements = resp.find_tag(0).unwrap().slice().unwrap();
+        let signature = resp.find_tag(1).unwrap().slice().unwrap();
+
+        let att_elements = get_root_node_struct(elements).unwrap();
+        assert_eq!(
+            att_elements.find_tag(1).unwrap().slice().unwrap(),
+            TEST_CERT_DECLARATION
+        );
+        assert_eq!(att_elements.find_tag(2).unwrap().slice().unwrap(), &nonce);
+
+        let mut msg = heapless::Vec::<u8, 900>::from_slice(elements).unwrap();
+        msg.extend_from_slice(&challenge).unwrap();
+
+        let mut pubkey = [0; crypto::EC_POINT_LEN_BYTES];
+        dac.get_public_key(&mut pubkey).unwrap();
+        let dac_pubkey = KeyPair::new_from_public(&pubkey).unwrap();
+
+        dac_pubkey.verify_msg(&msg, signature).unwrap();
+
+        // The signature doesn't verify without the challenge
+        assert!(dac_pubkey.verify_msg(elements, signature).is_err());
+    }
+
+    #[test]
+    fn invalid_attestation_nonce() {
+        let dac = KeyPair::new(seq_rand).unwrap();
+        let dev_att = TestDevAtt::new(&dac);
+        let attestation = AttestationMgr::new(&dev_att, dummy_epoch);
+
+        let challenge = [0x22; crypto::SYMM_KEY_LEN_BYTES];
+
+        for nonce in [&[0x11; 31][..], &[0x11; 33][..], &[]] {
+            let mut buf = [0; 900];
+            let mut element_buf = [0; 900];
+            let mut writebuf = WriteBuf::new(&mut buf);
+            let mut tw = TLVWriter::new(&mut writebuf);
+
+            assert_eq!(
+                attestation
+                    .attestation(nonce, &challenge, &mut element_buf, &mut tw)
+                    .map_err(|e| e.code()),
+                Err(ErrorCode::InvalidCommand)
+            );
+        }
+    }
+}
@@ -15,14 +15,22 @@
  *    limitations under the License.
  */
 
+use core::time::Duration;
+
 use crate::{
+    acl::{AclBackup, AclMgr},
     error::{Error, ErrorCode},
+    fabric::{FabricMgr, FabricScoped, OpCredentials},
+    mdns::Mdns,
     transport::session::SessionMode,
+    utils::epoch::Epoch,
 };
-use log::error;
+use log::{error, info};
+
+/// The maximum time for which the fail-safe can be kept armed by re-arming it, in seconds
+pub const MAX_CUMULATIVE_FAILSAFE_SECS: u16 = 900;
 
 #[derive(PartialEq)]
-#[allow(dead_code)]
 #[allow(clippy::enum_variant_names)]
 enum NocState {
     NocNotRecvd,
@@ -31,48 +39,154 @@ enum NocState {
     UpdateNocRecvd(u8),
 }
 
-#[derive(PartialEq)]
 pub struct ArmedCtx {
     session_mode: SessionMode,
-    timeout: u8,
+    /// When the fail-safe was first armed, as per the epoch
+    armed_at: Duration,
+    /// When the fail-safe expires, as per the epoch
+    expires_at: Duration,
     noc_state: NocState,
+    /// The credentials of the fabric before an UpdateNOC, to be restored on expiry
+    prev_creds: Option<OpCredentials>,
+    /// The ACL before the fail-safe was armed, to be restored on expiry
+    prev_acl: AclBackup,
+    /// Whether the network configuration was changed
+    networks_changed: bool,
 }
 
-#[derive(PartialEq)]
 pub enum State {
     Idle,
     Armed(ArmedCtx),
 }
 
+/// The changes done while the fail-safe was armed, which have to be reverted
+/// because it expired or was disarmed without `CommissioningComplete`
+pub struct Rollback {
+    noc_state: NocState,
+    prev_creds: Option<OpCredentials>,
+    prev_acl: AclBackup,
+}
+
+impl Rollback {
+    /// Revert the changes
+    ///
+    /// The sessions of a removed fabric are removed too, except for the one with the local
+    /// session ID `except_sess_id`, if any.
+    pub fn apply(
+        self,
+        fabric_mgr: &mut FabricMgr,
+        mut scoped: FabricScoped,
+        except_sess_id: Option<u16>,
+        mdns: &dyn Mdns,
+    ) -> Result<(), Error> {
+        info!("Fail-Safe rollback: restoring the ACL");
+
+        // The entries of a fabric added under the fail-safe are not in the backup, and
+        // those of the fabrics removed since are not restored
+        scoped.acl_mgr.restore(self.prev_acl, |fab_idx| {
+            fabric_mgr.get_fabric(fab_idx as _).ok().flatten().is_some()
+        });
+
+        match self.noc_state {
+            NocState::NocNotRecvd => (),
+            NocState::AddNocRecvd(fab_idx) => {
+                info!("Fail-Safe rollback: removing fabric {}", fab_idx);
+
+                fabric_mgr.remove(fab_idx, &mut scoped, except_sess_id, mdns)?;
+            }
+            NocState::UpdateNocRecvd(fab_idx) => {
+                info!(
+                    "Fail-Safe rollback: restoring the NOC of fabric {}",
+                    fab_idx
+                );
+
+                if let Some(prev_creds) = self.prev_creds {
+                    fabric_mgr.update_noc(fab_idx, prev_creds, mdns)?;
+                }
+            }
+        }
+
+        // Pending CSRs and trusted roots are only valid for the fail-safe they were received in
+        scoped.session_mgr.clear_noc_data();
+
+        // The network configuration is reverted by the Network Commissioning cluster,
+        // once it sees `FailSafe::network_rollbacks` change
+
+        Ok(())
+    }
+}
+
 pub struct FailSafe {
     state: State,
+    breadcrumb: u64,
+    epoch: Epoch,
+    /// The number of times the fail-safe was armed while idle
+    armings: u32,
+    /// The number of rollbacks of a network configuration change
+    network_rollbacks: u32,
 }
 
 impl FailSafe {
     #[inline(always)]
-    pub const fn new() -> Self {
-        Self { state: State::Idle }
+    pub const fn new(epoch: Epoch) -> Self {
+        Self {
+            state: State::Idle,
+            breadcrumb: 0,
+            epoch,
+            armings: 0,
+            network_rollbacks: 0,
+        }
     }
 
-    pub fn arm(&mut self, timeout: u8, session_mode: SessionMode) -> Result<(), Error> {
+    /// Arm, or re-arm the fail-safe for `timeout` seconds
+    ///
+    /// Re-arming extends the fail-safe from now on, but not beyond [`MAX_CUMULATIVE_FAILSAFE_SECS`]
+    /// after it was first armed. A `timeout` of 0 disarms the fail-safe, returning the changes that
+    /// have to be reverted.
+    ///
+    /// Arming an idle fail-safe takes a backup of the ACL in `acl_mgr`, to be restored on expiry.
+    pub fn arm(
+        &mut self,
+        timeout: u16,
+        session_mode: SessionMode,
+        acl_mgr: &AclMgr,
+    ) -> Result<Option<Rollback>, Error> {
+        let now = (self.epoch)();
+
         match &mut self.state {
             State::Idle => {
-                self.state = State::Armed(ArmedCtx {
-                    session_mode,
-                    timeout,
-                    noc_state: NocState::NocNotRecvd,
-                })
+                if timeout > 0 {
+                    self.armings = self.armings.wrapping_add(1);
+                    self.state = State::Armed(ArmedCtx {
+                        session_mode,
+                        armed_at: now,
+                        expires_at: now + Self::timeout_secs(timeout),
+                        noc_state: NocState::NocNotRecvd,
+                        prev_creds: None,
+                        prev_acl: acl_mgr.backup(),
+                        networks_changed: false,
+                    })
+                }
+
+                Ok(None)
             }
             State::Armed(c) => {
                 if c.session_mode != session_mode {
                     error!("Received Fail-Safe Arm with different session modes; current {:?}, incoming {:?}", c.session_mode, session_mode);
                     Err(ErrorCode::Invalid)?;
                 }
-                // re-arm
-                c.timeout = timeout;
+
+                if timeout == 0 {
+                    Ok(self.expire())
+                } else {
+                    // re-arm
+                    c.expires_at = (now + Self::timeout_secs(timeout))
+                        .min(c.armed_at + Self::timeout_secs(MAX_CUMULATIVE_FAILSAFE_SECS));
+
+                    Ok(None)
+                }
             }
         }
-        Ok(())
     }
 
     pub fn disarm(&mut self, session_mode: SessionMode) -> Result<(), Error> {
@@ -99,13 +213,47 @@ impl FailSafe {
                     }
                 }
                 self.state = State::Idle;
+                self.breadcrumb = 0;
             }
         }
         Ok(())
     }
 
+    /// Disarm the fail-safe if it has expired, returning the changes that have to be reverted
+    pub fn expired(&mut self) -> Option<Rollback> {
+        match &self.state {
+            State::Armed(c) if (self.epoch)() >= c.expires_at => {
+                info!("Fail-Safe expired");
+                self.expire()
+            }
+            _ => None,
+        }
+    }
+
+    /// When the fail-safe expires, as per the epoch, if it is armed
+    pub fn expires_at(&self) -> Option<Duration> {
+        match &self.state {
+            State::Armed(c) => Some(c.expires_at),
+            State::Idle => None,
+        }
+    }
+
+    /// Identifies the arming of the fail-safe, if it is armed, so that the changes done under it
+    /// can be told apart from those done under an earlier one
+    pub fn arming(&self) -> Option<u32> {
+        self.is_armed().then_some(self.armings)
+    }
+
     pub fn is_armed(&self) -> bool {
-        self.state != State::Idle
+        matches!(self.state, State::Armed(_))
+    }
+
+    pub fn breadcrumb(&self) -> u64 {
+        self.breadcrumb
+    }
+
+    pub fn set_breadcrumb(&mut self, breadcrumb: u64) {
+        self.breadcrumb = breadcrumb;
     }
 
     pub fn record_add_noc(&mut self, fabric_index: u8) -> Result<(), Error> {
@@ -122,6 +270,43 @@ impl FailSafe {
         }
     }
 
+    pub fn record_update_noc(
+        &mut self,
+        fabric_index: u8,
+        prev_creds: OpCredentials,
+    ) -> Result<(), Error> {
+        match &mut self.state {
+            State::Idle => Err(ErrorCode::Invalid.into()),
+            State::Armed(c) => {
+                if c.noc_state == NocState::NocNotRecvd {
+                    c.noc_state = NocState::UpdateNocRecvd(fabric_index);
+                    c.prev_creds = Some(prev_creds);
+                    Ok(())
+                } else {
+                    Err(ErrorCode::Invalid.into())
+                }
+            }
+        }
+    }
+
+    /// Record that the network configuration was changed under the fail-safe
+    pub fn record_network_change(&mut self) -> Result<(), Error> {
+        match &mut self.state {
+            State::Idle => Err(ErrorCode::FailSafeRequired.into()),
+            State::Armed(c) => {
+                c.networks_changed = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// The number of times the fail-safe expired after the network configuration was changed
+    ///
+    /// The Network Commissioning cluster reverts its networks when this changes.
+    pub fn network_rollbacks(&self) -> u32 {
+        self.network_rollbacks
+    }
+
     pub fn allow_noc_change(&self) -> Result<bool, Error> {
         let allow = match &self.state {
             State::Idle => false,
@@ -129,10 +314,242 @@ impl FailSafe {
         };
         Ok(allow)
     }
+
+    fn expire(&mut self) -> Option<Rollback> {
+        self.breadcrumb = 0;
+
+        match core::mem::replace(&mut self.state, State::Idle) {
+            State::Idle => None,
+            State::Armed(c) => {
+                if c.networks_changed {
+                    self.network_rollbacks = self.network_rollbacks.wrapping_add(1);
+                }
+
+                Some(Rollback {
+                    noc_state: c.noc_state,
+                    prev_creds: c.prev_creds,
+                    prev_acl: c.prev_acl,
+                })
+            }
+        }
+    }
+
+    fn timeout_secs(timeout: u16) -> Duration {
+        Duration::from_secs(timeout as _)
+    }
 }
 
-impl Default for FailSafe {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use core::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    };
+
+    use crate::{
+        acl::{AclEntry, AclMgr, AuthMode},
+        cert::tests::test_vectors::{ICAC1_SUCCESS, NOC1_SUCCESS, RCA1_SUCCESS},
+        crypto::KeyPair,
+        data_model::{
+            cluster_binding::BindingMgr, cluster_ota_requestor::OtaProviderMgr, objects::Privilege,
+            sdm::icd_management::IcdClientMgr, subscriptions::SubscriptionMgr,
+        },
+        fabric::{Fabric, FabricMgr, FabricScoped},
+        groups::GroupMgr,
+        mdns::DummyMdns,
+        secure_channel::case::ResumptionMgr,
+        test_support::{mock_epoch, seq_rand},
+        transport::session::{CaseDetails, SessionMgr, SessionMode},
+        utils::{clock::DummyClock, epoch::dummy_epoch, rand::dummy_rand},
+    };
+
+    use super::{FailSafe, Rollback};
+
+    mock_epoch!(MOCK_NOW_SECS, mock_epoch, secs);
+
+    fn add_fabric(fabric_mgr: &mut FabricMgr) -> u8 {
+        let fabric = Fabric::new(
+            KeyPair::new(seq_rand).unwrap(),
+            heapless::Vec::from_slice(&RCA1_SUCCESS).unwrap(),
+            Some(heapless::Vec::from_slice(&ICAC1_SUCCESS).unwrap()),
+            heapless::Vec::from_slice(&NOC1_SUCCESS).unwrap(),
+            &[0x11; 16],
+            0xFFF1,
+            "",
+        )
+        .unwrap();
+
+        fabric_mgr.add(fabric, &DummyMdns).unwrap()
+    }
+
+    fn case(fab_idx: u8) -> SessionMode {
+        SessionMode::Case(CaseDetails::new(fab_idx, &[0; 3]))
+    }
+
+    fn apply(rollback: Rollback, fabric_mgr: &mut FabricMgr, acl_mgr: &mut AclMgr) {
+        rollback
+            .apply(
+                fabric_mgr,
+                FabricScoped {
+                    acl_mgr,
+                    group_mgr: &mut GroupMgr::new(),
+                    subscription_mgr: &mut SubscriptionMgr::new(&DummyClock),
+                    session_mgr: &mut SessionMgr::new(dummy_epoch, dummy_rand),
+                    binding_mgr: &mut BindingMgr::new(),
+                    ota_provider_mgr: &mut OtaProviderMgr::new(),
+                    icd_client_mgr: &mut IcdClientMgr::new(),
+                    resumption_mgr: &mut ResumptionMgr::new(),
+                },
+                None,
+                &DummyMdns,
+            )
+            .unwrap();
+    }
+
+    fn acl_entries(acl_mgr: &AclMgr) -> heapless::Vec<AclEntry, 4> {
+        let mut entries = heapless::Vec::new();
+        acl_mgr
+            .for_each_acl(|entry| {
+                entries.push(entry.clone()).unwrap();
+                Ok(())
+            })
+            .unwrap();
+
+        entries
+    }
+
+    #[test]
+    /// A NOC added under the fail-safe is removed when the fail-safe expires
+    fn expiry_removes_noc() {
+        MOCK_NOW_SECS.store(1000, Ordering::SeqCst);
+
+        let mut fabric_mgr = FabricMgr::new();
+        let mut acl_mgr = AclMgr::new();
+        let mut failsafe = FailSafe::new(mock_epoch);
+
+        assert!(failsafe
+            .arm(60, SessionMode::Pase, &acl_mgr)
+            .unwrap()
+            .is_none());
+        let fab_idx = add_fabric(&mut fabric_mgr);
+        failsafe.record_add_noc(fab_idx).unwrap();
+
+        // Re-arming extends the window from now on
+        MOCK_NOW_SECS.store(1050, Ordering::SeqCst);
+        assert!(failsafe
+            .arm(60, SessionMode::Pase, &acl_mgr)
+            .unwrap()
+            .is_none());
+
+        MOCK_NOW_SECS.store(1100, Ordering::SeqCst);
+        assert!(failsafe.expired().is_none());
+        assert!(failsafe.is_armed());
+
+        MOCK_NOW_SECS.store(1110, Ordering::SeqCst);
+        let rollback = failsafe.expired().unwrap();
+        assert!(!failsafe.is_armed());
+
+        apply(rollback, &mut fabric_mgr, &mut acl_mgr);
+        assert!(fabric_mgr.get_fabric(fab_idx as _).unwrap().is_none());
+        assert!(fabric_mgr.is_empty());
+    }
+
+    #[test]
+    /// The ACL changes done under the fail-safe are reverted when the fail-safe expires
+    fn expiry_restores_acl() {
+        static NOW_SECS: AtomicU64 = AtomicU64::new(1000);
+
+        fn epoch() -> Duration {
+            Duration::from_secs(NOW_SECS.load(Ordering::SeqCst))
+        }
+
+        let mut fabric_mgr = FabricMgr::new();
+        let mut acl_mgr = AclMgr::new();
+        let mut failsafe = FailSafe::new(epoch);
+
+        let fab_idx = add_fabric(&mut fabric_mgr);
+
+        let mut admin = AclEntry::new(fab_idx, Privilege::ADMIN, AuthMode::Case);
+        admin.add_subject(0x1234).unwrap();
+        acl_mgr.add(admin.clone()).unwrap();
+
+        failsafe.arm(60, case(fab_idx), &acl_mgr).unwrap();
+
+        let mut view = AclEntry::new(fab_idx, Privilege::VIEW, AuthMode::Case);
+        view.add_subject(0x5678).unwrap();
+        acl_mgr.delete(0, fab_idx).unwrap();
+        acl_mgr.add(view.clone()).unwrap();
+        assert_eq!(acl_entries(&acl_mgr).as_slice(), &[view]);
+
+        NOW_SECS.store(1060, Ordering::SeqCst);
+        let rollback = failsafe.expired().unwrap();
+        apply(rollback, &mut fabric_mgr, &mut acl_mgr);
+
+        assert_eq!(acl_entries(&acl_mgr).as_slice(), &[admin]);
+        assert!(fabric_mgr.get_fabric(fab_idx as _).unwrap().is_some());
+    }
+
+    #[test]
+    /// Only the expiry of a fail-safe under which the networks were changed is
+    /// a network rollback
+    fn network_rollbacks() {
+        let mut failsafe = FailSafe::new(dummy_epoch);
+        let acl_mgr = AclMgr::new();
+
+        assert!(failsafe.record_network_change().is_err());
+
+        failsafe.arm(60, SessionMode::Pase, &acl_mgr).unwrap();
+        failsafe.arm(0, SessionMode::Pase, &acl_mgr).unwrap();
+        assert_eq!(failsafe.network_rollbacks(), 0);
+
+        failsafe.arm(60, SessionMode::Pase, &acl_mgr).unwrap();
+        failsafe.record_network_change().unwrap();
+        failsafe.arm(0, SessionMode::Pase, &acl_mgr).unwrap();
+        assert_eq!(failsafe.network_rollbacks(), 1);
+    }
+
+    #[test]
+    /// Arming with a timeout of 0 disarms the fail-safe, reverting its changes
+    fn arm_zero_disarms() {
+        let mut failsafe = FailSafe::new(dummy_epoch);
+
+        // Nothing to disarm
+        assert!(failsafe
+            .arm(0, SessionMode::Pase, &AclMgr::new())
+            .unwrap()
+            .is_none());
+        assert!(!failsafe.is_armed());
+
+        failsafe.arm(60, SessionMode::Pase, &AclMgr::new()).unwrap();
+        failsafe.set_breadcrumb(5);
+        failsafe.record_add_noc(1).unwrap();
+
+        // Only the session that armed the fail-safe can disarm it
+        assert!(failsafe.arm(0, case(1), &AclMgr::new()).is_err());
+        assert!(failsafe.is_armed());
+
+        assert!(failsafe
+            .arm(0, SessionMode::Pase, &AclMgr::new())
+            .unwrap()
+            .is_some());
+        assert!(!failsafe.is_armed());
+        assert_eq!(failsafe.breadcrumb(), 0);
+    }
+
+    #[test]
+    /// CommissioningComplete has to come from the fabric whose NOC was added
+    fn commissioning_complete_fabric() {
+        let mut failsafe = FailSafe::new(dummy_epoch);
+
+        failsafe.arm(60, SessionMode::Pase, &AclMgr::new()).unwrap();
+        failsafe.record_add_noc(2).unwrap();
+
+        assert!(failsafe.disarm(SessionMode::Pase).is_err());
+        assert!(failsafe.disarm(case(1)).is_err());
+        assert!(failsafe.is_armed());
+
+        failsafe.disarm(case(2)).unwrap();
+        assert!(!failsafe.is_armed());
+        assert!(failsafe.expired().is_none());
     }
 }
@@ -15,17 +15,26 @@
  *    limitations under the License.
  */
 
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::convert::TryInto;
 
+use crate::acl::AclMgr;
+use crate::data_model::cluster_binding::BindingMgr;
+use crate::data_model::cluster_ota_requestor::OtaProviderMgr;
 use crate::data_model::objects::*;
-use crate::data_model::sdm::failsafe::FailSafe;
+use crate::data_model::sdm::failsafe::{FailSafe, Rollback, MAX_CUMULATIVE_FAILSAFE_SECS};
+use crate::data_model::sdm::icd_management::IcdClientMgr;
+use crate::data_model::subscriptions::SubscriptionMgr;
+use crate::fabric::{FabricMgr, FabricScoped};
+use crate::groups::GroupMgr;
+use crate::mdns::Mdns;
+use crate::secure_channel::case::ResumptionMgr;
 use crate::tlv::{FromTLV, TLVElement, TLVWriter, TagType, ToTLV, UtfStr};
 use crate::transport::exchange::Exchange;
 use crate::utils::rand::Rand;
 use crate::{attribute_enum, cmd_enter};
 use crate::{command_enum, error::*};
-use log::info;
+use log::{error, info};
 use strum::{EnumDiscriminants, FromRepr};
 
 #[derive(Clone, Copy)]
@@ -83,6 +92,7 @@ pub enum RegLocationType {
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID as _,
+    revision: 1,
     feature_map: 0,
     attributes: &[
         FEATURE_MAP,
@@ -113,27 +123,85 @@ pub const CLUSTER: Cluster<'static> = Cluster {
         Commands::SetRegulatoryConfig as _,
         Commands::CommissioningComplete as _,
     ],
+    generated_commands: &[
+        RespCommands::ArmFailsafeResp as _,
+        RespCommands::SetRegulatoryConfigResp as _,
+        RespCommands::CommissioningCompleteResp as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[
+        Commands::ArmFailsafe as _,
+        Commands::SetRegulatoryConfig as _,
+        Commands::CommissioningComplete as _,
+    ],
+    manage_commands: &[],
+    admin_commands: &[
+        Commands::ArmFailsafe as _,
+        Commands::SetRegulatoryConfig as _,
+        Commands::CommissioningComplete as _,
+    ],
 };
 
 #[derive(FromTLV, ToTLV)]
 struct FailSafeParams {
-    expiry_len: u8,
-    bread_crumb: u8,
+    expiry_len: u16,
+    bread_crumb: u64,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct RegulatoryConfigParams<'a> {
+    reg_config: u8,
+    country_code: UtfStr<'a>,
+    bread_crumb: u64,
 }
 
 pub struct GenCommCluster<'a> {
     data_ver: Dataver,
     expiry_len: u16,
+    reg_config: Cell<u8>,
     failsafe: &'a RefCell<FailSafe>,
+    fabric_mgr: &'a RefCell<FabricMgr>,
+    acl_mgr: &'a RefCell<AclMgr>,
+    group_mgr: &'a RefCell<GroupMgr>,
+    subscription_mgr: &'a RefCell<SubscriptionMgr>,
+    binding_mgr: &'a RefCell<BindingMgr>,
+    ota_provider_mgr: &'a RefCell<OtaProviderMgr>,
+    icd_client_mgr: &'a RefCell<IcdClientMgr>,
+    resumption_mgr: &'a RefCell<ResumptionMgr>,
+    mdns: &'a dyn Mdns,
 }
 
 impl<'a> GenCommCluster<'a> {
-    pub fn new(failsafe: &'a RefCell<FailSafe>, rand: Rand) -> Self {
+    pub fn new(
+        failsafe: &'a RefCell<FailSafe>,
+        fabric_mgr: &'a RefCell<FabricMgr>,
+        acl_mgr: &'a RefCell<AclMgr>,
+        group_mgr: &'a RefCell<GroupMgr>,
+        subscription_mgr: &'a RefCell<SubscriptionMgr>,
+        binding_mgr: &'a RefCell<BindingMgr>,
+        ota_provider_mgr: &'a RefCell<OtaProviderMgr>,
+        icd_client_mgr: &'a RefCell<IcdClientMgr>,
+        resumption_mgr: &'a RefCell<ResumptionMgr>,
+        mdns: &'a dyn Mdns,
+        rand: Rand,
+    ) -> Self {
         Self {
             data_ver: Dataver::new(rand),
             failsafe,
+            fabric_mgr,
+            acl_mgr,
+            group_mgr,
+            subscription_mgr,
+            binding_mgr,
+            ota_provider_mgr,
+            icd_client_mgr,
+            resumption_mgr,
+            mdns,
             // TODO: Arch-Specific
             expiry_len: 120,
+            // TODO: Arch-Specific
+            reg_config: Cell::new(RegLocationType::IndoorOutdoor as _),
         }
     }
 
@@ -147,11 +215,10 @@ impl<'a> GenCommCluster<'a> {
                 CLUSTER.read(attr.attr_id, writer)
             } else {
                 match attr.attr_id.try_into()? {
-                    Attributes::BreadCrumb(codec) => codec.encode(writer, 0),
-                    // TODO: Arch-Specific
-                    Attributes::RegConfig(codec) => {
-                        codec.encode(writer, RegLocationType::IndoorOutdoor as _)
+                    Attributes::BreadCrumb(codec) => {
+                        codec.encode(writer, self.failsafe.borrow().breadcrumb())
                     }
+                    Attributes::RegConfig(codec) => codec.encode(writer, self.reg_config.get()),
                     // TODO: Arch-Specific
                     Attributes::LocationCapability(codec) => {
                         codec.encode(writer, RegLocationType::IndoorOutdoor as _)
@@ -159,6 +226,7 @@ impl<'a> GenCommCluster<'a> {
                     Attributes::BasicCommissioningInfo(_) => {
                         writer.start_struct(AttrDataWriter::TAG)?;
                         writer.u16(TagType::Context(0), self.expiry_len)?;
+                        writer.u16(TagType::Context(1), MAX_CUMULATIVE_FAILSAFE_SECS)?;
                         writer.end_container()?;
 
                         writer.complete()
@@ -170,6 +238,25 @@ impl<'a> GenCommCluster<'a> {
         }
     }
 
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        let data = data.with_dataver(self.data_ver.get())?;
+
+        match attr.attr_id.try_into()? {
+            Attributes::BreadCrumb(codec) => self
+                .failsafe
+                .borrow_mut()
+                .set_breadcrumb(codec.decode(data)?),
+            _ => {
+                error!("Attribute not supported: this shouldn't happen");
+                Err(ErrorCode::AttributeNotFound)?
+            }
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
     pub fn invoke(
         &self,
         exchange: &Exchange,
@@ -178,9 +265,11 @@ impl<'a> GenCommCluster<'a> {
         encoder: CmdDataEncoder,
     ) -> Result<(), Error> {
         match cmd.cmd_id.try_into()? {
-            Commands::ArmFailsafe => self.handle_command_armfailsafe(exchange, data, encoder)?,
+            Commands::ArmFailsafe => {
+                self.handle_command_armfailsafe(exchange, cmd.endpoint_id, data, encoder)?
+            }
             Commands::SetRegulatoryConfig => {
-                self.handle_command_setregulatoryconfig(exchange, data, encoder)?
+                self.handle_command_setregulatoryconfig(exchange, cmd.endpoint_id, data, encoder)?
             }
             Commands::CommissioningComplete => {
                 self.handle_command_commissioningcomplete(exchange, encoder)?;
@@ -195,25 +284,33 @@ impl<'a> GenCommCluster<'a> {
     fn handle_command_armfailsafe(
         &self,
         exchange: &Exchange,
+        endpoint_id: EndptId,
         data: &TLVElement,
         encoder: CmdDataEncoder,
     ) -> Result<(), Error> {
         cmd_enter!("ARM Fail Safe");
 
-        let p = FailSafeParams::from_tlv(data)?;
+        let p = FailSafeParams::from_tlv(data).map_err(Error::map_invalid_command)?;
 
-        let status = if self
-            .failsafe
-            .borrow_mut()
-            .arm(
-                p.expiry_len,
-                exchange.with_session(|sess| Ok(sess.get_session_mode().clone()))?,
-            )
-            .is_err()
-        {
-            CommissioningError::ErrBusyWithOtherAdmin as u8
-        } else {
-            CommissioningError::Ok as u8
+        let result = self.failsafe.borrow_mut().arm(
+            p.expiry_len,
+            exchange.with_session(|sess| Ok(sess.get_session_mode().clone()))?,
+            &self.acl_mgr.borrow(),
+        );
+
+        let status = match result {
+            Ok(rollback) => {
+                if let Some(rollback) = rollback {
+                    self.rollback(exchange, rollback)?;
+                }
+
+                if p.expiry_len > 0 {
+                    self.set_breadcrumb(exchange, endpoint_id, p.bread_crumb);
+                }
+
+                CommissioningError::Ok as u8
+            }
+            Err(_) => CommissioningError::ErrBusyWithOtherAdmin as u8,
         };
 
         let cmd_data = CommonResponse {
@@ -230,20 +327,27 @@ impl<'a> GenCommCluster<'a> {
 
     fn handle_command_setregulatoryconfig(
         &self,
-        _exchange: &Exchange,
+        exchange: &Exchange,
+        endpoint_id: EndptId,
         data: &TLVElement,
         encoder: CmdDataEncoder,
     ) -> Result<(), Error> {
         cmd_enter!("Set Regulatory Config");
-        let country_code = data
-            .find_tag(1)
-            .map_err(|_| ErrorCode::InvalidCommand)?
-            .slice()
-            .map_err(|_| ErrorCode::InvalidCommand)?;
-        info!("Received country code: {:?}", country_code);
+        let p = RegulatoryConfigParams::from_tlv(data).map_err(Error::map_invalid_command)?;
+        info!("Received country code: {:?}", p.country_code);
+
+        // TODO: Arch-Specific; the location capability allows any regulatory config
+        let status = if p.reg_config > RegLocationType::IndoorOutdoor as u8 {
+            CommissioningError::ErrValueOutsideRange as u8
+        } else {
+            self.reg_config.set(p.reg_config);
+            self.set_breadcrumb(exchange, endpoint_id, p.bread_crumb);
+
+            CommissioningError::Ok as u8
+        };
 
         let cmd_data = CommonResponse {
-            error_code: 0,
+            error_code: status,
             debug_txt: UtfStr::new(b""),
         };
 
@@ -262,22 +366,22 @@ impl<'a> GenCommCluster<'a> {
         cmd_enter!("Commissioning Complete");
         let mut status: u8 = CommissioningError::Ok as u8;
 
-        // Has to be a Case Session
-        if exchange
+        if !self.failsafe.borrow().is_armed() {
+            status = CommissioningError::ErrNotCommissioning as u8;
+        } else if exchange
             .with_session(|sess| Ok(sess.get_local_fabric_idx()))?
             .is_none()
         {
+            // Has to be a Case Session
             status = CommissioningError::ErrInvalidAuth as u8;
-        }
-
-        // AddNOC or UpdateNOC must have happened, and that too for the same fabric
-        // scope that is for this session
-        if self
+        } else if self
             .failsafe
             .borrow_mut()
             .disarm(exchange.with_session(|sess| Ok(sess.get_session_mode().clone()))?)
             .is_err()
         {
+            // AddNOC or UpdateNOC must have happened, and that too for the same fabric
+            // scope that is for this session
             status = CommissioningError::ErrInvalidAuth as u8;
         }
 
@@ -292,6 +396,44 @@ impl<'a> GenCommCluster<'a> {
 
         Ok(())
     }
+
+    /// Set the breadcrumb, which the subscriptions only get reported if it differs from
+    /// the value of their last report
+    fn set_breadcrumb(&self, exchange: &Exchange, endpoint_id: EndptId, breadcrumb: u64) {
+        self.failsafe.borrow_mut().set_breadcrumb(breadcrumb);
+
+        if self.subscription_mgr.borrow_mut().mark_value(
+            endpoint_id,
+            ID,
+            AttributesDiscriminants::BreadCrumb as _,
+            &breadcrumb,
+        ) {
+            exchange.notify_subscriptions();
+        }
+    }
+
+    fn rollback(&self, exchange: &Exchange, rollback: Rollback) -> Result<(), Error> {
+        // The session of the request is still needed for sending the response
+        let sess_id = exchange.id().session_id.id;
+
+        exchange.with_session_mgr_mut(|sess_mgr| {
+            rollback.apply(
+                &mut self.fabric_mgr.borrow_mut(),
+                FabricScoped {
+                    acl_mgr: &mut self.acl_mgr.borrow_mut(),
+                    group_mgr: &mut self.group_mgr.borrow_mut(),
+                    subscription_mgr: &mut self.subscription_mgr.borrow_mut(),
+                    session_mgr: sess_mgr,
+                    binding_mgr: &mut self.binding_mgr.borrow_mut(),
+                    ota_provider_mgr: &mut self.ota_provider_mgr.borrow_mut(),
+                    icd_client_mgr: &mut self.icd_client_mgr.borrow_mut(),
+                    resumption_mgr: &mut self.resumption_mgr.borrow_mut(),
+                },
+                Some(sess_id),
+                self.mdns,
+            )
+        })
+    }
 }
 
 impl<'a> Handler for GenCommCluster<'a> {
@@ -299,6 +441,10 @@ impl<'a> Handler for GenCommCluster<'a> {
         GenCommCluster::read(self, attr, encoder)
     }
 
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        GenCommCluster::write(self, attr, data)
+    }
+
     fn invoke(
         &self,
         exchange: &Exchange,
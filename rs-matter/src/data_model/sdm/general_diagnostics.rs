@@ -0,0 +1,499 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::RefCell;
+use core::convert::TryInto;
+use core::time::Duration;
+
+use crate::data_model::objects::*;
+use crate::tlv::{FromTLV, OctetStr, TLVElement, TLVList, TLVWriter, TagType, ToTLV};
+use crate::transport::exchange::Exchange;
+use crate::transport::network::{Ipv4Addr, Ipv6Addr};
+use crate::utils::epoch::Epoch;
+use crate::utils::rand::Rand;
+use crate::utils::writebuf::WriteBuf;
+use crate::{attribute_enum, cmd_enter, command_enum, error::*};
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0033;
+
+pub const MAX_NETWORK_INTERFACES: usize = 2;
+pub const MAX_INTERFACE_ADDRS: usize = 4;
+pub const MAX_INTERFACE_NAME_LEN: usize = 32;
+pub const TEST_EVENT_ENABLE_KEY_LEN: usize = 16;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    NetworkInterfaces(()) = 0x00,
+    RebootCount(AttrType<u16>) = 0x01,
+    UpTime(AttrType<u64>) = 0x02,
+    BootReason(AttrType<u8>) = 0x04,
+    TestEventTriggersEnabled(AttrType<bool>) = 0x08,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    TestEventTrigger = 0x00,
+}
+
+command_enum!(Commands);
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: 0,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::NetworkInterfaces as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::RebootCount as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::UpTime as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::BootReason as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::TestEventTriggersEnabled as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+    ],
+    commands: &[Commands::TestEventTrigger as _],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[Commands::TestEventTrigger as _],
+    admin_commands: &[],
+};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[repr(u8)]
+pub enum BootReason {
+    #[default]
+    Unspecified = 0,
+    PowerOnReboot = 1,
+    BrownOutReset = 2,
+    SoftwareWatchdogReset = 3,
+    HardwareWatchdogReset = 4,
+    SoftwareUpdateCompleted = 5,
+    SoftwareReset = 6,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[repr(u8)]
+pub enum InterfaceType {
+    #[default]
+    Unspecified = 0,
+    WiFi = 1,
+    Ethernet = 2,
+    Cellular = 3,
+    Thread = 4,
+}
+
+/// A network interface of the node, as reported by the NetworkInterfaces attribute
+#[derive(Clone, Debug, Default)]
+pub struct NetworkInterface {
+    pub name: heapless::String<MAX_INTERFACE_NAME_LEN>,
+    pub is_operational: bool,
+    /// The MAC address (or the extended address, for Thread) of the interface
+    pub hardware_address: heapless::Vec<u8, 8>,
+    pub ipv4_addrs: heapless::Vec<Ipv4Addr, MAX_INTERFACE_ADDRS>,
+    pub ipv6_addrs: heapless::Vec<Ipv6Addr, MAX_INTERFACE_ADDRS>,
+    pub iface_type: InterfaceType,
+}
+
+impl ToTLV for NetworkInterface {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag: TagType) -> Result<(), Error> {
+        tw.start_struct(tag)?;
+        tw.utf8(TagType::Context(0), self.name.as_bytes())?;
+        tw.bool(TagType::Context(1), self.is_operational)?;
+        // We don't know whether the off-premise services are reachable
+        tw.null(TagType::Context(2))?;
+        tw.null(TagType::Context(3))?;
+        tw.str8(TagType::Context(4), &self.hardware_address)?;
+
+        tw.start_array(TagType::Context(5))?;
+        for addr in &self.ipv4_addrs {
+            tw.str8(TagType::Anonymous, &addr.octets())?;
+        }
+        tw.end_container()?;
+
+        tw.start_array(TagType::Context(6))?;
+        for addr in &self.ipv6_addrs {
+            tw.str8(TagType::Anonymous, &addr.octets())?;
+        }
+        tw.end_container()?;
+
+        tw.u8(TagType::Context(7), self.iface_type as _)?;
+        tw.end_container()
+    }
+}
+
+/// A handler of the test event triggers, returning `false` if the trigger is not supported
+pub type TestEventTriggerHandler = fn(u64) -> bool;
+
+/// The diagnostic state of the node.
+///
+/// The reboot count is persisted, and every load of the persisted state counts as a reboot.
+pub struct DiagMgr {
+    epoch: Epoch,
+    boot_time: Duration,
+    boot_reason: BootReason,
+    reboot_count: u16,
+    interfaces: heapless::Vec<NetworkInterface, MAX_NETWORK_INTERFACES>,
+    test_event_enable_key: [u8; TEST_EVENT_ENABLE_KEY_LEN],
+    test_event_handler: Option<TestEventTriggerHandler>,
+    changed: bool,
+}
+
+impl DiagMgr {
+    #[inline(always)]
+    pub fn new(epoch: Epoch) -> Self {
+        Self {
+            epoch,
+            boot_time: epoch(),
+            boot_reason: BootReason::Unspecified,
+            reboot_count: 0,
+            interfaces: heapless::Vec::new(),
+            test_event_enable_key: [0; TEST_EVENT_ENABLE_KEY_LEN],
+            test_event_handler: None,
+            // So that the very first boot gets persisted as well
+            changed: true,
+        }
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        let root = TLVList::new(data).iter().next().ok_or(ErrorCode::Invalid)?;
+
+        // We are running, so this is one more boot than the persisted one
+        self.reboot_count = root.find_tag(0)?.u16()?.saturating_add(1);
+        self.changed = true;
+
+        Ok(())
+    }
+
+    pub fn store<'a>(&mut self, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        if self.changed {
+            let mut wb = WriteBuf::new(buf);
+            let mut tw = TLVWriter::new(&mut wb);
+            tw.start_struct(TagType::Anonymous)?;
+            tw.u16(TagType::Context(0), self.reboot_count)?;
+            tw.end_container()?;
+
+            self.changed = false;
+
+            let len = tw.get_tail();
+
+            Ok(Some(&buf[..len]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn reboot_count(&self) -> u16 {
+        self.reboot_count
+    }
+
+    /// The time since the node booted
+    pub fn up_time(&self) -> Duration {
+        (self.epoch)().saturating_sub(self.boot_time)
+    }
+
+    pub fn boot_reason(&self) -> BootReason {
+        self.boot_reason
+    }
+
+    pub fn set_boot_reason(&mut self, boot_reason: BootReason) {
+        self.boot_reason = boot_reason;
+    }
+
+    pub fn add_interface(&mut self, interface: NetworkInterface) -> Result<(), Error> {
+        self.interfaces
+            .push(interface)
+            .map_err(|_| ErrorCode::NoSpace.into())
+    }
+
+    pub fn interfaces(&self) -> impl Iterator<Item = &NetworkInterface> {
+        self.interfaces.iter()
+    }
+
+    /// Enable the test event triggers, which can then be invoked with the provided enable key
+    pub fn enable_test_event_triggers(
+        &mut self,
+        enable_key: [u8; TEST_EVENT_ENABLE_KEY_LEN],
+        handler: TestEventTriggerHandler,
+    ) {
+        self.test_event_enable_key = enable_key;
+        self.test_event_handler = Some(handler);
+    }
+
+    pub fn test_event_triggers_enabled(&self) -> bool {
+        self.test_event_handler.is_some() && self.test_event_enable_key.iter().any(|b| *b != 0)
+    }
+
+    fn test_event_trigger(&self, enable_key: &[u8], event_trigger: u64) -> Result<(), Error> {
+        if !self.test_event_triggers_enabled()
+            || enable_key != self.test_event_enable_key.as_slice()
+        {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        match self.test_event_handler {
+            Some(handler) if handler(event_trigger) => Ok(()),
+            _ => Err(ErrorCode::InvalidCommand.into()),
+        }
+    }
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct TestEventTriggerReq<'a> {
+    enable_key: OctetStr<'a>,
+    event_trigger: u64,
+}
+
+pub struct GenDiagCluster<'a> {
+    data_ver: Dataver,
+    diag: &'a RefCell<DiagMgr>,
+}
+
+impl<'a> GenDiagCluster<'a> {
+    pub fn new(diag: &'a RefCell<DiagMgr>, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            diag,
+        }
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                let diag = self.diag.borrow();
+
+                match attr.attr_id.try_into()? {
+                    Attributes::NetworkInterfaces(_) => writer.set_list(diag.interfaces()),
+                    Attributes::RebootCount(codec) => codec.encode(writer, diag.reboot_count()),
+                    Attributes::UpTime(codec) => codec.encode(writer, diag.up_time().as_secs()),
+                    Attributes::BootReason(codec) => codec.encode(writer, diag.boot_reason() as _),
+                    Attributes::TestEventTriggersEnabled(codec) => {
+                        codec.encode(writer, diag.test_event_triggers_enabled())
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        _encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::TestEventTrigger => self.handle_command_testeventtrigger(data)?,
+        }
+
+        Ok(())
+    }
+
+    fn handle_command_testeventtrigger(&self, data: &TLVElement) -> Result<(), Error> {
+        cmd_enter!("TestEventTrigger");
+
+        let req = TestEventTriggerReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        info!("Received event trigger: {:x}", req.event_trigger);
+
+        self.diag
+            .borrow()
+            .test_event_trigger(req.enable_key.0, req.event_trigger)
+    }
+}
+
+impl<'a> Handler for GenDiagCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        GenDiagCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        GenDiagCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for GenDiagCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for GenDiagCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use core::time::Duration;
+
+    use crate::{
+        data_model::objects::{AttrDataEncoder, AttrDetails, Node},
+        error::ErrorCode,
+        tlv::{get_root_node_struct, TLVWriter, TagType},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{AttributesDiscriminants, DiagMgr, GenDiagCluster, ID};
+
+    static NOW: AtomicU64 = AtomicU64::new(1000);
+
+    fn test_epoch() -> Duration {
+        Duration::from_secs(NOW.load(Ordering::SeqCst))
+    }
+
+    fn read_up_time(cluster: &GenDiagCluster) -> u64 {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 0,
+            cluster_id: ID,
+            attr_id: AttributesDiscriminants::UpTime as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .unwrap();
+
+        get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap()
+            .u64()
+            .unwrap()
+    }
+
+    fn test_event_trigger(cluster: &GenDiagCluster, enable_key: &[u8]) -> Result<(), ErrorCode> {
+        let mut buf = [0; 50];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.str8(TagType::Context(0), enable_key).unwrap();
+        tw.u64(TagType::Context(1), 0x0042).unwrap();
+        tw.end_container().unwrap();
+
+        let req = get_root_node_struct(writebuf.as_slice()).unwrap();
+
+        cluster
+            .handle_command_testeventtrigger(&req)
+            .map_err(|e| e.code())
+    }
+
+    #[test]
+    fn up_time_monotonic() {
+        let diag = RefCell::new(DiagMgr::new(test_epoch));
+        let cluster = GenDiagCluster::new(&diag, dummy_rand);
+
+        let start = read_up_time(&cluster);
+
+        NOW.fetch_add(5, Ordering::SeqCst);
+        let later = read_up_time(&cluster);
+        assert!(later >= start + 5);
+        assert!(read_up_time(&cluster) >= later);
+    }
+
+    #[test]
+    fn test_event_trigger_enable_key() {
+        let diag = RefCell::new(DiagMgr::new(test_epoch));
+        let cluster = GenDiagCluster::new(&diag, dummy_rand);
+
+        // The triggers are disabled by default, which the all-zeroes key doesn't change
+        assert_eq!(
+            test_event_trigger(&cluster, &[0; 16]),
+            Err(ErrorCode::ConstraintError)
+        );
+
+        diag.borrow_mut()
+            .enable_test_event_triggers([0xaa; 16], |trigger| trigger == 0x0042);
+
+        assert_eq!(
+            test_event_trigger(&cluster, &[0xbb; 16]),
+            Err(ErrorCode::ConstraintError)
+        );
+        assert_eq!(
+            test_event_trigger(&cluster, &[0xaa; 8]),
+            Err(ErrorCode::ConstraintError)
+        );
+        assert_eq!(test_event_trigger(&cluster, &[0xaa; 16]), Ok(()));
+    }
+
+    #[test]
+    fn reboot_count_persisted() {
+        let mut buf = [0; 16];
+
+        let mut diag = DiagMgr::new(test_epoch);
+        let data = diag.store(&mut buf).unwrap().unwrap();
+        assert!(diag.store(&mut [0; 16]).unwrap().is_none());
+
+        let mut rebooted = DiagMgr::new(test_epoch);
+        rebooted.load(data).unwrap();
+        assert_eq!(rebooted.reboot_count(), 1);
+        assert!(rebooted.is_changed());
+    }
+}
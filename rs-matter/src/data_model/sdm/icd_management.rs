@@ -0,0 +1,951 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+use core::cell::{Cell, RefCell};
+use core::convert::TryInto;
+use core::time::Duration;
+
+use crate::{
+    acl::Accessor,
+    attribute_enum, cmd_enter, command_enum,
+    crypto::{
+        self, HmacSha256, AEAD_MIC_LEN_BYTES, AEAD_NONCE_LEN_BYTES, SHA256_HASH_LEN_BYTES,
+        SYMM_KEY_LEN_BYTES,
+    },
+    data_model::objects::*,
+    error::{Error, ErrorCode},
+    fabric::MAX_SUPPORTED_FABRICS,
+    interaction_model::messages::GenericPath,
+    secure_channel::common::{OpCode, PROTO_ID_SECURE_CHANNEL},
+    tlv::{self, FromTLV, OctetStr, TLVElement, TLVList, TLVWriter, TagType, ToTLV},
+    transport::{exchange::Exchange, network::Address, packet::Packet},
+    utils::{epoch::Epoch, rand::Rand, writebuf::WriteBuf},
+};
+use log::{info, warn};
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0046;
+
+/// The Check-In Protocol Support feature
+pub const FEATURE_CHECK_IN_PROTOCOL_SUPPORT: u32 = 0x01;
+
+/// The number of check-in clients which can be registered by each fabric
+pub const CLIENTS_SUPPORTED_PER_FABRIC: usize = 2;
+
+/// The longest a device stays active in response to a single StayActiveRequest
+pub const MAX_STAY_ACTIVE_DURATION_MS: u32 = 30000;
+
+const MAX_CLIENTS: usize = CLIENTS_SUPPORTED_PER_FABRIC * MAX_SUPPORTED_FABRICS;
+
+/// The length of the counter in the check-in payload
+const COUNTER_LEN: usize = 4;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    IdleModeDuration(AttrType<u32>) = 0x00,
+    ActiveModeDuration(AttrType<u32>) = 0x01,
+    ActiveModeThreshold(AttrType<u16>) = 0x02,
+    RegisteredClients(()) = 0x03,
+    ICDCounter(AttrType<u32>) = 0x04,
+    ClientsSupportedPerFabric(AttrType<u16>) = 0x05,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    RegisterClient = 0x00,
+    UnregisterClient = 0x02,
+    StayActiveRequest = 0x03,
+}
+
+command_enum!(Commands);
+
+#[repr(u16)]
+pub enum RespCommands {
+    RegisterClientResponse = 0x01,
+    StayActiveResponse = 0x04,
+}
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: FEATURE_CHECK_IN_PROTOCOL_SUPPORT,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::IdleModeDuration as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::ActiveModeDuration as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::ActiveModeThreshold as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::RegisteredClients as u16,
+            Access::READ
+                .union(Access::NEED_ADMIN)
+                .union(Access::FAB_SCOPED),
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::ICDCounter as u16,
+            Access::READ.union(Access::NEED_ADMIN),
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::ClientsSupportedPerFabric as u16,
+            Access::RV,
+            Quality::FIXED,
+        ),
+    ],
+    commands: &[
+        Commands::RegisterClient as _,
+        Commands::UnregisterClient as _,
+        Commands::StayActiveRequest as _,
+    ],
+    generated_commands: &[
+        RespCommands::RegisterClientResponse as _,
+        RespCommands::StayActiveResponse as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[
+        Commands::RegisterClient as _,
+        Commands::StayActiveRequest as _,
+    ],
+    manage_commands: &[
+        Commands::RegisterClient as _,
+        Commands::UnregisterClient as _,
+        Commands::StayActiveRequest as _,
+    ],
+    admin_commands: &[],
+};
+
+/// The operating modes of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcdConfig {
+    /// How long the device sleeps in the idle mode, in seconds
+    pub idle_mode_duration: u32,
+    /// How long the device stays awake in the active mode, in milliseconds
+    pub active_mode_duration: u32,
+    /// How long the device stays active after network activity, in milliseconds
+    pub active_mode_threshold: u16,
+}
+
+impl IcdConfig {
+    pub const fn new() -> Self {
+        Self {
+            idle_mode_duration: 300,
+            active_mode_duration: 300,
+            active_mode_threshold: 300,
+        }
+    }
+}
+
+impl Default for IcdConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A client registered to receive check-in messages, as reported by the
+/// RegisteredClients attribute. The shared key is never reported.
+#[derive(FromTLV, ToTLV, Debug, Clone, PartialEq)]
+#[tlvargs(start = 1)]
+pub struct MonitoringRegistration {
+    pub check_in_node_id: u64,
+    pub monitored_subject: u64,
+    #[tagval(0xFE)]
+    pub fab_idx: u8,
+}
+
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a")]
+pub struct RegisterClientReq<'a> {
+    pub check_in_node_id: u64,
+    pub monitored_subject: u64,
+    pub key: OctetStr<'a>,
+    pub verification_key: Option<OctetStr<'a>>,
+}
+
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a")]
+pub struct UnregisterClientReq<'a> {
+    pub check_in_node_id: u64,
+    pub verification_key: Option<OctetStr<'a>>,
+}
+
+#[derive(FromTLV, ToTLV)]
+pub struct StayActiveReq {
+    pub stay_active_duration: u32,
+}
+
+#[derive(FromTLV, ToTLV, Debug)]
+pub struct RegisterClientResp {
+    pub icd_counter: u32,
+}
+
+#[derive(FromTLV, ToTLV, Debug)]
+pub struct StayActiveResp {
+    pub promised_active_duration: u32,
+}
+
+#[derive(FromTLV, ToTLV, Debug, Clone)]
+struct Client {
+    registration: MonitoringRegistration,
+    key: heapless::Vec<u8, SYMM_KEY_LEN_BYTES>,
+}
+
+/// The check-in clients of all fabrics, with their keys, which are persisted
+pub struct IcdClientMgr {
+    clients: heapless::Vec<Client, MAX_CLIENTS>,
+    changed: bool,
+}
+
+impl IcdClientMgr {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            clients: heapless::Vec::new(),
+            changed: false,
+        }
+    }
+
+    pub fn remove_for_fabric(&mut self, fab_idx: u8) {
+        let len = self.clients.len();
+
+        self.clients
+            .retain(|client| client.registration.fab_idx != fab_idx);
+
+        self.changed |= self.clients.len() != len;
+    }
+
+    /// The clients which are registered to receive check-in messages
+    pub fn iter(&self) -> impl Iterator<Item = &MonitoringRegistration> {
+        self.clients.iter().map(|client| &client.registration)
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        let root = TLVList::new(data).iter().next().ok_or(ErrorCode::Invalid)?;
+
+        tlv::from_tlv(&mut self.clients, &root)?;
+        self.changed = false;
+
+        Ok(())
+    }
+
+    pub fn store<'a>(&mut self, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        if self.changed {
+            let mut wb = WriteBuf::new(buf);
+            let mut tw = TLVWriter::new(&mut wb);
+            self.clients
+                .as_slice()
+                .to_tlv(&mut tw, TagType::Anonymous)?;
+
+            self.changed = false;
+
+            let len = tw.get_tail();
+
+            Ok(Some(&buf[..len]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+impl Default for IcdClientMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct IcdManagementCluster<'a> {
+    data_ver: Dataver,
+    config: IcdConfig,
+    epoch: Epoch,
+    client_mgr: &'a RefCell<IcdClientMgr>,
+    /// The counter carried by the check-in messages
+    icd_counter: Cell<u32>,
+    /// The counter of the unsecured messages the check-ins are sent with
+    msg_counter: Cell<u32>,
+    /// Until when the device was asked to stay active, as per the epoch
+    active_until: Cell<Option<Duration>>,
+}
+
+impl<'a> IcdManagementCluster<'a> {
+    pub fn new(
+        config: IcdConfig,
+        epoch: Epoch,
+        client_mgr: &'a RefCell<IcdClientMgr>,
+        rand: Rand,
+    ) -> Self {
+        let mut counters = [0; 8];
+        rand(&mut counters);
+
+        Self {
+            data_ver: Dataver::new(rand),
+            config,
+            epoch,
+            client_mgr,
+            icd_counter: Cell::new(u32::from_le_bytes(counters[..4].try_into().unwrap())),
+            msg_counter: Cell::new(u32::from_le_bytes(counters[4..].try_into().unwrap())),
+            active_until: Cell::new(None),
+        }
+    }
+
+    /// The clients which are registered to receive check-in messages
+    pub fn registered_clients(&self) -> heapless::Vec<MonitoringRegistration, MAX_CLIENTS> {
+        self.client_mgr.borrow().iter().cloned().collect()
+    }
+
+    pub fn icd_counter(&self) -> u32 {
+        self.icd_counter.get()
+    }
+
+    /// Whether the device should stay active, as a client asked it to with a StayActiveRequest
+    pub fn is_active(&self) -> bool {
+        self.active_until
+            .get()
+            .map(|active_until| active_until > (self.epoch)())
+            .unwrap_or(false)
+    }
+
+    /// Prepare the check-in message for the registered client `check_in_node_id`
+    /// of the fabric `fab_idx`. Every check-in carries the next value of the ICD counter.
+    pub fn check_in(
+        &self,
+        fab_idx: u8,
+        check_in_node_id: u64,
+        tx: &mut Packet,
+        peer: Address,
+    ) -> Result<(), Error> {
+        let key: [u8; SYMM_KEY_LEN_BYTES] = self
+            .client_mgr
+            .borrow()
+            .clients
+            .iter()
+            .find(|client| {
+                client.registration.fab_idx == fab_idx
+                    && client.registration.check_in_node_id == check_in_node_id
+            })
+            .ok_or(ErrorCode::NotFound)?
+            .key
+            .as_slice()
+            .try_into()
+            .map_err(|_| ErrorCode::Invalid)?;
+
+        let counter = self.icd_counter.get().wrapping_add(1);
+        self.icd_counter.set(counter);
+
+        let ctr = self.msg_counter.get().wrapping_add(1);
+        self.msg_counter.set(ctr);
+
+        tx.reset();
+        tx.set_proto_id(PROTO_ID_SECURE_CHANNEL);
+        tx.set_proto_opcode(OpCode::ICDCheckIn as _);
+        // Check-ins are not acknowledged, as the client may not be listening
+        tx.unset_reliable();
+        tx.proto.set_initiator();
+        tx.plain.ctr = ctr;
+
+        let mut payload = [0; AEAD_NONCE_LEN_BYTES + COUNTER_LEN + AEAD_MIC_LEN_BYTES];
+        let len = encode_check_in(&key, counter, &mut payload)?;
+        tx.get_writebuf()?.copy_from_slice(&payload[..len])?;
+
+        tx.proto_encode(peer, None, 0, true, None, None)
+    }
+
+    /// Send a check-in message to each of the registered clients, which `resolve`
+    /// finds the address of
+    #[cfg(any(feature = "std", feature = "embassy-net"))]
+    pub async fn send_check_ins<D, F>(
+        &self,
+        udp: &crate::transport::udp::UdpListener<'_, D>,
+        mut resolve: F,
+    ) -> Result<(), Error>
+    where
+        D: crate::transport::network::NetworkStackDriver,
+        F: FnMut(u8, u64) -> Option<crate::transport::network::SocketAddr>,
+    {
+        for registration in self.registered_clients() {
+            let Some(addr) = resolve(registration.fab_idx, registration.check_in_node_id) else {
+                warn!(
+                    "No address for ICD client {:x}, skipping its check-in",
+                    registration.check_in_node_id
+                );
+                continue;
+            };
+
+            let mut buf = [0; crate::transport::packet::MAX_TX_BUF_SIZE];
+            let mut tx = Packet::new_tx(&mut buf);
+
+            self.check_in(
+                registration.fab_idx,
+                registration.check_in_node_id,
+                &mut tx,
+                Address::Udp(addr),
+            )?;
+
+            info!("Sending ICD check-in to {}", addr);
+
+            udp.send(addr, tx.as_slice()).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::IdleModeDuration(codec) => {
+                        codec.encode(writer, self.config.idle_mode_duration)
+                    }
+                    Attributes::ActiveModeDuration(codec) => {
+                        codec.encode(writer, self.config.active_mode_duration)
+                    }
+                    Attributes::ActiveModeThreshold(codec) => {
+                        codec.encode(writer, self.config.active_mode_threshold)
+                    }
+                    Attributes::RegisteredClients(_) => writer.set_list(
+                        self.client_mgr
+                            .borrow()
+                            .iter()
+                            .filter(|registration| attr.is_visible_to(registration.fab_idx)),
+                    ),
+                    Attributes::ICDCounter(codec) => codec.encode(writer, self.icd_counter.get()),
+                    Attributes::ClientsSupportedPerFabric(codec) => {
+                        codec.encode(writer, CLIENTS_SUPPORTED_PER_FABRIC as _)
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::RegisterClient => {
+                self.handle_command_registerclient(exchange, cmd, data, encoder)?
+            }
+            Commands::UnregisterClient => {
+                self.handle_command_unregisterclient(exchange, cmd, data)?
+            }
+            Commands::StayActiveRequest => self.handle_command_stayactiverequest(data, encoder)?,
+        }
+
+        Ok(())
+    }
+
+    fn handle_command_registerclient(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("RegisterClient");
+
+        let req = RegisterClientReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let accessor = exchange.accessor()?;
+        let admin = Self::is_admin(&accessor, cmd.endpoint_id, Commands::RegisterClient);
+
+        let icd_counter = self.register(&req, accessor.fab_idx, admin)?;
+
+        encoder
+            .with_command(RespCommands::RegisterClientResponse as _)?
+            .set(RegisterClientResp { icd_counter })
+    }
+
+    fn handle_command_unregisterclient(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+    ) -> Result<(), Error> {
+        cmd_enter!("UnregisterClient");
+
+        let req = UnregisterClientReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let accessor = exchange.accessor()?;
+        let admin = Self::is_admin(&accessor, cmd.endpoint_id, Commands::UnregisterClient);
+
+        self.unregister(&req, accessor.fab_idx, admin)
+    }
+
+    fn handle_command_stayactiverequest(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("StayActiveRequest");
+
+        let req = StayActiveReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let promised_active_duration = self.stay_active(req.stay_active_duration);
+
+        encoder
+            .with_command(RespCommands::StayActiveResponse as _)?
+            .set(StayActiveResp {
+                promised_active_duration,
+            })
+    }
+
+    /// Administrators may re-register or unregister a client without its verification key
+    fn is_admin(accessor: &Accessor, endpoint_id: EndptId, cmd: Commands) -> bool {
+        Cluster::check_cmd_access(
+            accessor,
+            GenericPath::new(Some(endpoint_id), Some(ID), Some(cmd as _)),
+            Access::WRITE.union(Access::NEED_ADMIN),
+        )
+        .is_ok()
+    }
+
+    /// Register a check-in client, or update its registration. Returns the current
+    /// value of the ICD counter, which the client synchronizes with.
+    fn register(&self, req: &RegisterClientReq, fab_idx: u8, admin: bool) -> Result<u32, Error> {
+        if req.key.0.len() != SYMM_KEY_LEN_BYTES {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        let key = heapless::Vec::from_slice(req.key.0).map_err(|_| ErrorCode::ConstraintError)?;
+
+        let mut client_mgr = self.client_mgr.borrow_mut();
+        let clients = &mut client_mgr.clients;
+
+        let registration = MonitoringRegistration {
+            check_in_node_id: req.check_in_node_id,
+            monitored_subject: req.monitored_subject,
+            fab_idx,
+        };
+
+        if let Some(client) = clients.iter_mut().find(|client| {
+            client.registration.fab_idx == fab_idx
+                && client.registration.check_in_node_id == req.check_in_node_id
+        }) {
+            if !admin {
+                Self::verify(&client.key, req.verification_key.as_ref())?;
+            }
+
+            client.registration = registration;
+            client.key = key;
+        } else {
+            let registered = clients
+                .iter()
+                .filter(|client| client.registration.fab_idx == fab_idx)
+                .count();
+
+            if registered >= CLIENTS_SUPPORTED_PER_FABRIC {
+                Err(ErrorCode::ResourceExhausted)?;
+            }
+
+            clients
+                .push(Client { registration, key })
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+        }
+
+        client_mgr.changed = true;
+
+        info!(
+            "Registered ICD client {:x} on fabric {}",
+            req.check_in_node_id, fab_idx
+        );
+
+        self.data_ver.changed();
+
+        Ok(self.icd_counter.get())
+    }
+
+    fn unregister(&self, req: &UnregisterClientReq, fab_idx: u8, admin: bool) -> Result<(), Error> {
+        let mut client_mgr = self.client_mgr.borrow_mut();
+        let clients = &mut client_mgr.clients;
+
+        let index = clients
+            .iter()
+            .position(|client| {
+                client.registration.fab_idx == fab_idx
+                    && client.registration.check_in_node_id == req.check_in_node_id
+            })
+            .ok_or(ErrorCode::NotFound)?;
+
+        if !admin {
+            Self::verify(&clients[index].key, req.verification_key.as_ref())?;
+        }
+
+        clients.swap_remove(index);
+        client_mgr.changed = true;
+
+        info!(
+            "Unregistered ICD client {:x} on fabric {}",
+            req.check_in_node_id, fab_idx
+        );
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    /// A verification key which does not match the registered key is reported as a Failure
+    fn verify(key: &[u8], verification_key: Option<&OctetStr>) -> Result<(), Error> {
+        match verification_key {
+            Some(verification_key) if verification_key.0 == key => Ok(()),
+            _ => {
+                warn!("ICD client verification key mismatch");
+                Err(ErrorCode::Invalid.into())
+            }
+        }
+    }
+
+    /// Stay active for at least the active mode threshold, and for at most
+    /// `MAX_STAY_ACTIVE_DURATION_MS`. Returns the promised duration, in milliseconds.
+    fn stay_active(&self, stay_active_duration: u32) -> u32 {
+        let promised = stay_active_duration
+            .min(MAX_STAY_ACTIVE_DURATION_MS)
+            .max(self.config.active_mode_threshold as _);
+
+        let active_until = (self.epoch)() + Duration::from_millis(promised as _);
+        if self
+            .active_until
+            .get()
+            .map(|until| until < active_until)
+            .unwrap_or(true)
+        {
+            self.active_until.set(Some(active_until));
+        }
+
+        promised
+    }
+}
+
+/// Encode the check-in payload into `buf`, returning its length: a nonce derived from
+/// the check-in key and the counter, followed by the encrypted counter and its MIC
+fn encode_check_in(
+    key: &[u8; SYMM_KEY_LEN_BYTES],
+    counter: u32,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let counter = counter.to_le_bytes();
+
+    let mut digest = [0; SHA256_HASH_LEN_BYTES];
+    let mut mac = HmacSha256::new(key)?;
+    mac.update(&counter)?;
+    mac.finish(&mut digest)?;
+
+    if buf.len() < AEAD_NONCE_LEN_BYTES + COUNTER_LEN + AEAD_MIC_LEN_BYTES {
+        Err(ErrorCode::NoSpace)?;
+    }
+
+    let (nonce, data) = buf.split_at_mut(AEAD_NONCE_LEN_BYTES);
+    nonce.copy_from_slice(&digest[..AEAD_NONCE_LEN_BYTES]);
+    data[..COUNTER_LEN].copy_from_slice(&counter);
+
+    let len = crypto::encrypt_in_place(key, nonce, &[], data, COUNTER_LEN)?;
+
+    Ok(AEAD_NONCE_LEN_BYTES + len)
+}
+
+impl<'a> Handler for IcdManagementCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        IcdManagementCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        IcdManagementCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for IcdManagementCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for IcdManagementCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, time::Duration};
+
+    use crate::{
+        crypto::{self, HmacSha256, AEAD_NONCE_LEN_BYTES, SHA256_HASH_LEN_BYTES},
+        error::ErrorCode,
+        interaction_model::core::IMStatusCode,
+        secure_channel::common::{OpCode, PROTO_ID_SECURE_CHANNEL},
+        tlv::OctetStr,
+        transport::{
+            network::Address,
+            packet::{Packet, MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+        },
+        utils::rand::dummy_rand,
+    };
+
+    use super::{
+        IcdClientMgr, IcdConfig, IcdManagementCluster, RegisterClientReq, UnregisterClientReq,
+        CLIENTS_SUPPORTED_PER_FABRIC,
+    };
+
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+
+    const OTHER_KEY: [u8; 16] = [0xaa; 16];
+
+    fn dummy_epoch() -> Duration {
+        Duration::from_millis(1000)
+    }
+
+    fn req<'a>(
+        check_in_node_id: u64,
+        key: &'a [u8],
+        verification_key: Option<&'a [u8]>,
+    ) -> RegisterClientReq<'a> {
+        RegisterClientReq {
+            check_in_node_id,
+            monitored_subject: check_in_node_id,
+            key: OctetStr(key),
+            verification_key: verification_key.map(OctetStr),
+        }
+    }
+
+    #[test]
+    fn registration_cap() {
+        let client_mgr = RefCell::new(IcdClientMgr::new());
+        let cluster =
+            IcdManagementCluster::new(IcdConfig::new(), dummy_epoch, &client_mgr, dummy_rand);
+
+        for node_id in 0..CLIENTS_SUPPORTED_PER_FABRIC as u64 {
+            cluster
+                .register(&req(node_id, &KEY, None), 1, false)
+                .unwrap();
+        }
+
+        let err = cluster
+            .register(&req(100, &KEY, None), 1, false)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ResourceExhausted);
+        assert_eq!(err.to_im_status().status, IMStatusCode::ResourceExhausted);
+
+        // Re-registering an existing client does not count towards the cap
+        cluster
+            .register(&req(0, &OTHER_KEY, Some(&KEY)), 1, false)
+            .unwrap();
+
+        // The cap is per fabric
+        cluster.register(&req(100, &KEY, None), 2, false).unwrap();
+
+        assert_eq!(
+            cluster.registered_clients().len(),
+            CLIENTS_SUPPORTED_PER_FABRIC + 1
+        );
+
+        // Keys must be 16 bytes long
+        assert_eq!(
+            cluster
+                .register(&req(101, &KEY[..8], None), 2, false)
+                .map_err(|e| e.code()),
+            Err(ErrorCode::ConstraintError)
+        );
+    }
+
+    #[test]
+    fn verification_key() {
+        let client_mgr = RefCell::new(IcdClientMgr::new());
+        let cluster =
+            IcdManagementCluster::new(IcdConfig::new(), dummy_epoch, &client_mgr, dummy_rand);
+
+        cluster.register(&req(1, &KEY, None), 1, false).unwrap();
+
+        // Re-registering requires the key the client was registered with...
+        let err = cluster
+            .register(&req(1, &OTHER_KEY, Some(&OTHER_KEY)), 1, false)
+            .unwrap_err();
+        assert_eq!(err.to_im_status().status, IMStatusCode::Failure);
+
+        let err = cluster
+            .register(&req(1, &OTHER_KEY, None), 1, false)
+            .unwrap_err();
+        assert_eq!(err.to_im_status().status, IMStatusCode::Failure);
+
+        // ... unless done by an administrator
+        cluster
+            .register(&req(1, &OTHER_KEY, None), 1, true)
+            .unwrap();
+
+        let unregister = |verification_key: &'static [u8]| UnregisterClientReq {
+            check_in_node_id: 1,
+            verification_key: Some(OctetStr(verification_key)),
+        };
+
+        let err = cluster.unregister(&unregister(&KEY), 1, false).unwrap_err();
+        assert_eq!(err.to_im_status().status, IMStatusCode::Failure);
+
+        cluster
+            .unregister(&unregister(&OTHER_KEY), 1, false)
+            .unwrap();
+        assert!(cluster.registered_clients().is_empty());
+
+        assert_eq!(
+            cluster
+                .unregister(&unregister(&OTHER_KEY), 1, false)
+                .map_err(|e| e.code()),
+            Err(ErrorCode::NotFound)
+        );
+    }
+
+    #[test]
+    fn check_in_counter() {
+        let client_mgr = RefCell::new(IcdClientMgr::new());
+        let cluster =
+            IcdManagementCluster::new(IcdConfig::new(), dummy_epoch, &client_mgr, dummy_rand);
+
+        let start = cluster.register(&req(1, &KEY, None), 1, false).unwrap();
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+
+        assert_eq!(
+            cluster
+                .check_in(2, 1, &mut tx, Address::default())
+                .map_err(|e| e.code()),
+            Err(ErrorCode::NotFound)
+        );
+
+        for expected in 1..=2 {
+            cluster.check_in(1, 1, &mut tx, Address::default()).unwrap();
+
+            let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+            let len = tx.as_slice().len();
+            rx_buf[..len].copy_from_slice(tx.as_slice());
+
+            let mut rx = Packet::new_rx(&mut rx_buf[..len]);
+            rx.plain_hdr_decode().unwrap();
+            rx.proto_decode(0, None).unwrap();
+
+            assert!(!rx.plain.is_encrypted());
+            assert!(!rx.proto.is_reliable());
+            assert_eq!(rx.get_proto_id(), PROTO_ID_SECURE_CHANNEL);
+            assert_eq!(rx.get_proto_raw_opcode(), OpCode::ICDCheckIn as u8);
+
+            let payload = rx.as_mut_slice();
+            assert_eq!(payload.len(), 13 + 4 + 16);
+
+            let (nonce, data) = payload.split_at_mut(AEAD_NONCE_LEN_BYTES);
+            let len = crypto::decrypt_in_place(&KEY, nonce, &[], data).unwrap();
+            assert_eq!(len, 4);
+
+            let counter = u32::from_le_bytes(data[..4].try_into().unwrap());
+            assert_eq!(counter, start.wrapping_add(expected));
+            assert_eq!(cluster.icd_counter(), counter);
+
+            // The nonce is derived from the key and the counter
+            let mut digest = [0; SHA256_HASH_LEN_BYTES];
+            let mut mac = HmacSha256::new(&KEY).unwrap();
+            mac.update(&counter.to_le_bytes()).unwrap();
+            mac.finish(&mut digest).unwrap();
+            assert_eq!(nonce, &digest[..AEAD_NONCE_LEN_BYTES]);
+        }
+    }
+
+    #[test]
+    fn stay_active() {
+        let client_mgr = RefCell::new(IcdClientMgr::new());
+        let cluster =
+            IcdManagementCluster::new(IcdConfig::new(), dummy_epoch, &client_mgr, dummy_rand);
+
+        assert!(!cluster.is_active());
+        assert_eq!(cluster.stay_active(10), 300);
+        assert_eq!(cluster.stay_active(60000), 30000);
+        assert!(cluster.is_active());
+    }
+
+    #[test]
+    fn persisted_clients() {
+        let client_mgr = RefCell::new(IcdClientMgr::new());
+        let cluster =
+            IcdManagementCluster::new(IcdConfig::new(), dummy_epoch, &client_mgr, dummy_rand);
+
+        cluster.register(&req(1, &KEY, None), 1, false).unwrap();
+        cluster
+            .register(&req(2, &OTHER_KEY, None), 2, false)
+            .unwrap();
+        assert!(client_mgr.borrow().is_changed());
+
+        let mut buf = [0; 256];
+        let data = client_mgr.borrow_mut().store(&mut buf).unwrap().unwrap();
+
+        let reloaded_mgr = RefCell::new(IcdClientMgr::new());
+        reloaded_mgr.borrow_mut().load(data).unwrap();
+        assert!(!reloaded_mgr.borrow().is_changed());
+
+        let reloaded =
+            IcdManagementCluster::new(IcdConfig::new(), dummy_epoch, &reloaded_mgr, dummy_rand);
+        assert_eq!(reloaded.registered_clients(), cluster.registered_clients());
+
+        // The keys are persisted too, so the clients can still be verified and checked in
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+        reloaded
+            .check_in(1, 1, &mut tx, Address::default())
+            .unwrap();
+
+        reloaded
+            .unregister(
+                &UnregisterClientReq {
+                    check_in_node_id: 2,
+                    verification_key: Some(OctetStr(&OTHER_KEY)),
+                },
+                2,
+                false,
+            )
+            .unwrap();
+        assert!(reloaded_mgr.borrow().is_changed());
+
+        // Removing a fabric removes its clients
+        reloaded_mgr.borrow_mut().remove_for_fabric(1);
+        assert!(reloaded.registered_clients().is_empty());
+    }
+}
@@ -19,5 +19,9 @@ pub mod admin_commissioning;
 pub mod dev_att;
 pub mod failsafe;
 pub mod general_commissioning;
+pub mod general_diagnostics;
+pub mod icd_management;
 pub mod noc;
 pub mod nw_commissioning;
+pub mod software_diagnostics;
+pub mod time_sync;
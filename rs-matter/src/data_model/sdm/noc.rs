@@ -21,10 +21,14 @@ use core::convert::TryInto;
 use crate::acl::{AclEntry, AclMgr, AuthMode};
 use crate::cert::{Cert, MAX_CERT_TLV_LEN};
 use crate::crypto::{self, KeyPair};
+use crate::data_model::cluster_binding::BindingMgr;
+use crate::data_model::cluster_ota_requestor::OtaProviderMgr;
 use crate::data_model::objects::*;
-use crate::data_model::sdm::dev_att;
-use crate::fabric::{Fabric, FabricMgr, MAX_SUPPORTED_FABRICS};
+use crate::data_model::subscriptions::SubscriptionMgr;
+use crate::fabric::{Fabric, FabricMgr, FabricScoped, OpCredentials, MAX_SUPPORTED_FABRICS};
+use crate::groups::GroupMgr;
 use crate::mdns::Mdns;
+use crate::secure_channel::case::ResumptionMgr;
 use crate::tlv::{FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV, UtfStr};
 use crate::transport::exchange::Exchange;
 use crate::transport::session::SessionMode;
@@ -35,8 +39,9 @@ use crate::{attribute_enum, cmd_enter, command_enum, error::*};
 use log::{error, info};
 use strum::{EnumDiscriminants, FromRepr};
 
-use super::dev_att::{DataType, DevAttDataFetcher};
+use super::dev_att::{AttestationMgr, DataType, DevAttDataFetcher};
 use super::failsafe::FailSafe;
+use super::icd_management::IcdClientMgr;
 
 // Node Operational Credentials Cluster
 
@@ -90,6 +95,7 @@ pub enum Commands {
     CertChainReq = 0x02,
     CSRReq = 0x04,
     AddNOC = 0x06,
+    UpdateNOC = 0x07,
     UpdateFabricLabel = 0x09,
     RemoveFabric = 0x0a,
     AddTrustedRootCert = 0x0b,
@@ -120,6 +126,7 @@ attribute_enum!(Attributes);
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID as _,
+    revision: 1,
     feature_map: 0,
     attributes: &[
         FEATURE_MAP,
@@ -150,6 +157,34 @@ pub const CLUSTER: Cluster<'static> = Cluster {
         Commands::CertChainReq as _,
         Commands::CSRReq as _,
         Commands::AddNOC as _,
+        Commands::UpdateNOC as _,
+        Commands::UpdateFabricLabel as _,
+        Commands::RemoveFabric as _,
+        Commands::AddTrustedRootCert as _,
+    ],
+    generated_commands: &[
+        RespCommands::AttReqResp as _,
+        RespCommands::CertChainResp as _,
+        RespCommands::CSRResp as _,
+        RespCommands::NOCResp as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[
+        Commands::AttReq as _,
+        Commands::CertChainReq as _,
+        Commands::CSRReq as _,
+        Commands::AddNOC as _,
+        Commands::UpdateNOC as _,
+        Commands::UpdateFabricLabel as _,
+        Commands::RemoveFabric as _,
+    ],
+    manage_commands: &[],
+    admin_commands: &[
+        Commands::AttReq as _,
+        Commands::CertChainReq as _,
+        Commands::CSRReq as _,
+        Commands::AddNOC as _,
+        Commands::UpdateNOC as _,
         Commands::UpdateFabricLabel as _,
         Commands::RemoveFabric as _,
         Commands::AddTrustedRootCert as _,
@@ -159,13 +194,16 @@ pub const CLUSTER: Cluster<'static> = Cluster {
 pub struct NocData {
     pub key_pair: KeyPair,
     pub root_ca: heapless::Vec<u8, { MAX_CERT_TLV_LEN }>,
+    /// Whether the CSR was requested for an UpdateNOC, rather than for an AddNOC
+    pub for_update_noc: bool,
 }
 
 impl NocData {
-    pub fn new(key_pair: KeyPair) -> Self {
+    pub fn new(key_pair: KeyPair, for_update_noc: bool) -> Self {
         Self {
             key_pair,
             root_ca: heapless::Vec::new(),
+            for_update_noc,
         }
     }
 }
@@ -186,18 +224,32 @@ struct NocResp<'a> {
 #[tlvargs(lifetime = "'a")]
 struct AddNocReq<'a> {
     noc_value: OctetStr<'a>,
-    icac_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
     ipk_value: OctetStr<'a>,
     case_admin_subject: u64,
     vendor_id: u16,
 }
 
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct UpdateNocReq<'a> {
+    noc_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
+}
+
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 struct CommonReq<'a> {
     str: OctetStr<'a>,
 }
 
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct CsrReq<'a> {
+    nonce: OctetStr<'a>,
+    for_update_noc: Option<bool>,
+}
+
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 struct UpdateFabricLabelReq<'a> {
@@ -216,11 +268,16 @@ struct RemoveFabricReq {
 
 pub struct NocCluster<'a> {
     data_ver: Dataver,
-    epoch: Epoch,
     rand: Rand,
-    dev_att: &'a dyn DevAttDataFetcher,
+    attestation: AttestationMgr<'a>,
     fabric_mgr: &'a RefCell<FabricMgr>,
     acl_mgr: &'a RefCell<AclMgr>,
+    group_mgr: &'a RefCell<GroupMgr>,
+    subscription_mgr: &'a RefCell<SubscriptionMgr>,
+    binding_mgr: &'a RefCell<BindingMgr>,
+    ota_provider_mgr: &'a RefCell<OtaProviderMgr>,
+    icd_client_mgr: &'a RefCell<IcdClientMgr>,
+    resumption_mgr: &'a RefCell<ResumptionMgr>,
     failsafe: &'a RefCell<FailSafe>,
     mdns: &'a dyn Mdns,
 }
@@ -230,6 +287,12 @@ impl<'a> NocCluster<'a> {
         dev_att: &'a dyn DevAttDataFetcher,
         fabric_mgr: &'a RefCell<FabricMgr>,
         acl_mgr: &'a RefCell<AclMgr>,
+        group_mgr: &'a RefCell<GroupMgr>,
+        subscription_mgr: &'a RefCell<SubscriptionMgr>,
+        binding_mgr: &'a RefCell<BindingMgr>,
+        ota_provider_mgr: &'a RefCell<OtaProviderMgr>,
+        icd_client_mgr: &'a RefCell<IcdClientMgr>,
+        resumption_mgr: &'a RefCell<ResumptionMgr>,
         failsafe: &'a RefCell<FailSafe>,
         mdns: &'a dyn Mdns,
         epoch: Epoch,
@@ -237,11 +300,16 @@ impl<'a> NocCluster<'a> {
     ) -> Self {
         Self {
             data_ver: Dataver::new(rand),
-            epoch,
             rand,
-            dev_att,
+            attestation: AttestationMgr::new(dev_att, epoch),
             fabric_mgr,
             acl_mgr,
+            group_mgr,
+            subscription_mgr,
+            binding_mgr,
+            ota_provider_mgr,
+            icd_client_mgr,
+            resumption_mgr,
             failsafe,
             mdns,
         }
@@ -260,7 +328,7 @@ impl<'a> NocCluster<'a> {
                     Attributes::Fabrics(_) => {
                         writer.start_array(AttrDataWriter::TAG)?;
                         self.fabric_mgr.borrow().for_each(|entry, fab_idx| {
-                            if !attr.fab_filter || attr.fab_idx == fab_idx {
+                            if attr.is_visible_to(fab_idx) {
                                 let root_ca_cert = entry.get_root_ca()?;
 
                                 entry
@@ -297,6 +365,7 @@ impl<'a> NocCluster<'a> {
     ) -> Result<(), Error> {
         match cmd.cmd_id.try_into()? {
             Commands::AddNOC => self.handle_command_addnoc(exchange, data, encoder)?,
+            Commands::UpdateNOC => self.handle_command_updatenoc(exchange, data, encoder)?,
             Commands::CSRReq => self.handle_command_csrrequest(exchange, data, encoder)?,
             Commands::AddTrustedRootCert => {
                 self.handle_command_addtrustedrootcert(exchange, data)?
@@ -331,6 +400,11 @@ impl<'a> NocCluster<'a> {
             .with_session_mut(|sess| Ok(sess.take_noc_data()))?
             .ok_or(NocStatus::MissingCsr)?;
 
+        if noc_data.for_update_noc {
+            error!("AddNOC with a CSR requested for UpdateNOC");
+            Err(Error::from(ErrorCode::ConstraintError))?;
+        }
+
         if !self
             .failsafe
             .borrow_mut()
@@ -353,21 +427,8 @@ impl<'a> NocCluster<'a> {
 
         let r = AddNocReq::from_tlv(data).map_err(|_| NocStatus::InvalidNOC)?;
 
-        let noc_cert = Cert::new(r.noc_value.0).map_err(|_| NocStatus::InvalidNOC)?;
-        info!("Received NOC as: {}", noc_cert);
-
-        let noc = heapless::Vec::from_slice(r.noc_value.0).map_err(|_| NocStatus::InvalidNOC)?;
-
-        let icac = if !r.icac_value.0.is_empty() {
-            let icac_cert = Cert::new(r.icac_value.0).map_err(|_| NocStatus::InvalidNOC)?;
-            info!("Received ICAC as: {}", icac_cert);
-
-            let icac =
-                heapless::Vec::from_slice(r.icac_value.0).map_err(|_| NocStatus::InvalidNOC)?;
-            Some(icac)
-        } else {
-            None
-        };
+        let (noc, icac) = get_certs(r.noc_value.0, r.icac_value.map(|icac| icac.0))?;
+        validate_chain(&noc_data.root_ca, icac.as_deref(), &noc)?;
 
         let fabric = Fabric::new(
             noc_data.key_pair,
@@ -392,6 +453,75 @@ impl<'a> NocCluster<'a> {
         Ok(fab_idx)
     }
 
+    fn _handle_command_updatenoc(
+        &self,
+        exchange: &Exchange,
+        data: &TLVElement,
+    ) -> Result<u8, NocError> {
+        // UpdateNOC always updates the fabric of the accessing CASE session
+        let fab_idx = if let SessionMode::Case(c) =
+            exchange.with_session(|sess| Ok(sess.get_session_mode().clone()))?
+        {
+            c.fab_idx
+        } else {
+            error!("UpdateNOC in a non-CASE session");
+            Err(Error::from(ErrorCode::UnsupportedAccess))?
+        };
+
+        let noc_data = exchange
+            .with_session_mut(|sess| Ok(sess.take_noc_data()))?
+            .ok_or(NocStatus::MissingCsr)?;
+
+        if !noc_data.for_update_noc {
+            error!("UpdateNOC with a CSR requested for AddNOC");
+            Err(Error::from(ErrorCode::ConstraintError))?;
+        }
+
+        if !self
+            .failsafe
+            .borrow_mut()
+            .allow_noc_change()
+            .map_err(|_| NocStatus::InsufficientPrivlege)?
+        {
+            error!("UpdateNOC not allowed by Fail Safe");
+            Err(NocStatus::InsufficientPrivlege)?;
+        }
+
+        let r = UpdateNocReq::from_tlv(data).map_err(|_| NocStatus::InvalidNOC)?;
+
+        let (noc, icac) = get_certs(r.noc_value.0, r.icac_value.map(|icac| icac.0))?;
+
+        // The new NOC has to chain up to the existing root of the fabric
+        {
+            let fabric_mgr = self.fabric_mgr.borrow();
+            let fabric = fabric_mgr
+                .get_fabric(fab_idx as _)?
+                .ok_or(NocStatus::InvalidFabricIndex)?;
+
+            validate_chain(&fabric.root_ca, icac.as_deref(), &noc)?;
+        }
+
+        let prev_creds = self
+            .fabric_mgr
+            .borrow_mut()
+            .update_noc(
+                fab_idx,
+                OpCredentials {
+                    key_pair: noc_data.key_pair,
+                    icac,
+                    noc,
+                },
+                self.mdns,
+            )
+            .map_err(|_| NocStatus::InvalidNOC)?;
+
+        self.failsafe
+            .borrow_mut()
+            .record_update_noc(fab_idx, prev_creds)?;
+
+        Ok(fab_idx)
+    }
+
     fn create_nocresponse(
         encoder: CmdDataEncoder,
         status_code: NocStatus,
@@ -445,19 +575,38 @@ impl<'a> NocCluster<'a> {
 
     fn handle_command_rmfabric(
         &self,
-        _exchange: &Exchange,
+        exchange: &Exchange,
         data: &TLVElement,
         encoder: CmdDataEncoder,
     ) -> Result<(), Error> {
         cmd_enter!("Remove Fabric");
         let req = RemoveFabricReq::from_tlv(data).map_err(Error::map_invalid_data_type)?;
-        if self
-            .fabric_mgr
-            .borrow_mut()
-            .remove(req.fab_idx, self.mdns)
-            .is_ok()
-        {
-            let _ = self.acl_mgr.borrow_mut().delete_for_fabric(req.fab_idx);
+
+        // The session of the request is still needed for sending the response
+        let sess_id = exchange.id().session_id.id;
+        let removed = exchange.with_session_mgr_mut(|sess_mgr| {
+            Ok(self
+                .fabric_mgr
+                .borrow_mut()
+                .remove(
+                    req.fab_idx,
+                    &mut FabricScoped {
+                        acl_mgr: &mut self.acl_mgr.borrow_mut(),
+                        group_mgr: &mut self.group_mgr.borrow_mut(),
+                        subscription_mgr: &mut self.subscription_mgr.borrow_mut(),
+                        session_mgr: sess_mgr,
+                        binding_mgr: &mut self.binding_mgr.borrow_mut(),
+                        ota_provider_mgr: &mut self.ota_provider_mgr.borrow_mut(),
+                        icd_client_mgr: &mut self.icd_client_mgr.borrow_mut(),
+                        resumption_mgr: &mut self.resumption_mgr.borrow_mut(),
+                    },
+                    Some(sess_id),
+                    self.mdns,
+                )
+                .is_ok())
+        })?;
+
+        if removed {
             // TODO: transaction.terminate();
             Ok(())
         } else {
@@ -484,6 +633,25 @@ impl<'a> NocCluster<'a> {
         Ok(())
     }
 
+    fn handle_command_updatenoc(
+        &self,
+        exchange: &Exchange,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("UpdateNOC");
+
+        let (status, fab_idx) = match self._handle_command_updatenoc(exchange, data) {
+            Ok(fab_idx) => (NocStatus::Ok, fab_idx),
+            Err(NocError::Status(status)) => (status, 0),
+            Err(NocError::Error(error)) => Err(error)?,
+        };
+
+        Self::create_nocresponse(encoder, status, fab_idx, "")?;
+
+        Ok(())
+    }
+
     fn handle_command_attrequest(
         &self,
         exchange: &Exchange,
@@ -504,21 +672,9 @@ impl<'a> NocCluster<'a> {
         let mut writer = encoder.with_command(RespCommands::AttReqResp as _)?;
 
         let mut buf: [u8; RESP_MAX] = [0; RESP_MAX];
-        let mut attest_element = WriteBuf::new(&mut buf);
         writer.start_struct(CmdDataWriter::TAG)?;
-        add_attestation_element(
-            self.epoch,
-            self.dev_att,
-            req.str.0,
-            &mut attest_element,
-            &mut writer,
-        )?;
-        add_attestation_signature(
-            self.dev_att,
-            &mut attest_element,
-            &attest_challenge,
-            &mut writer,
-        )?;
+        self.attestation
+            .attestation(req.str.0, &attest_challenge, &mut buf, &mut writer)?;
         writer.end_container()?;
 
         writer.complete()?;
@@ -538,11 +694,8 @@ impl<'a> NocCluster<'a> {
         let cert_type = get_certchainrequest_params(data).map_err(Error::map_invalid_command)?;
 
         let mut buf: [u8; RESP_MAX] = [0; RESP_MAX];
-        let len = self.dev_att.get_devatt_data(cert_type, &mut buf)?;
-        let buf = &buf[0..len];
-
         let cmd_data = CertChainResp {
-            cert: OctetStr::new(buf),
+            cert: OctetStr::new(self.attestation.cert(cert_type, &mut buf)?),
         };
 
         encoder
@@ -560,13 +713,24 @@ impl<'a> NocCluster<'a> {
     ) -> Result<(), Error> {
         cmd_enter!("CSRRequest");
 
-        let req = CommonReq::from_tlv(data).map_err(Error::map_invalid_command)?;
-        info!("Received CSR Nonce:{:?}", req.str);
+        let req = CsrReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        info!("Received CSR Nonce:{:?}", req.nonce);
 
         if !self.failsafe.borrow().is_armed() {
             Err(ErrorCode::UnsupportedAccess)?;
         }
 
+        let for_update_noc = req.for_update_noc.unwrap_or(false);
+        if for_update_noc
+            && !matches!(
+                exchange.with_session(|sess| Ok(sess.get_session_mode().clone()))?,
+                SessionMode::Case(_)
+            )
+        {
+            error!("CSR for UpdateNOC requested in a non-CASE session");
+            Err(ErrorCode::InvalidCommand)?;
+        }
+
         let noc_keypair = KeyPair::new(self.rand)?;
         let mut attest_challenge = [0u8; crypto::SYMM_KEY_LEN_BYTES];
         exchange.with_session(|sess| {
@@ -579,18 +743,14 @@ impl<'a> NocCluster<'a> {
         let mut buf: [u8; RESP_MAX] = [0; RESP_MAX];
         let mut nocsr_element = WriteBuf::new(&mut buf);
         writer.start_struct(CmdDataWriter::TAG)?;
-        add_nocsrelement(&noc_keypair, req.str.0, &mut nocsr_element, &mut writer)?;
-        add_attestation_signature(
-            self.dev_att,
-            &mut nocsr_element,
-            &attest_challenge,
-            &mut writer,
-        )?;
+        add_nocsrelement(&noc_keypair, req.nonce.0, &mut nocsr_element, &mut writer)?;
+        self.attestation
+            .sign(&mut nocsr_element, &attest_challenge, &mut writer)?;
         writer.end_container()?;
 
         writer.complete()?;
 
-        let noc_data = NocData::new(noc_keypair);
+        let noc_data = NocData::new(noc_keypair, for_update_noc);
         // Store this in the session data instead of cluster data, so it gets cleared
         // if the session goes away for some reason
         exchange.with_session_mut(|sess| {
@@ -611,26 +771,24 @@ impl<'a> NocCluster<'a> {
             Err(ErrorCode::UnsupportedAccess)?;
         }
 
-        // This may happen on CASE or PASE. For PASE, the existence of NOC Data is necessary
-        match exchange.with_session(|sess| Ok(sess.get_session_mode().clone()))? {
-            SessionMode::Case(_) => error!("CASE: AddTrustedRootCert handling pending"), // For a CASE Session, we just return success for now,
-            SessionMode::Pase => {
-                exchange.with_session_mut(|sess| {
-                    let noc_data = sess.get_noc_data().ok_or(ErrorCode::NoSession)?;
+        let req = CommonReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        info!("Received Trusted Cert:{:x?}", req.str);
 
-                    let req = CommonReq::from_tlv(data).map_err(Error::map_invalid_command)?;
-                    info!("Received Trusted Cert:{:x?}", req.str);
+        // Only a self-signed root certificate is accepted
+        Cert::new(req.str.0)
+            .and_then(|root_ca| root_ca.verify_chain_start().finalise())
+            .map_err(|_| ErrorCode::InvalidCommand)?;
 
-                    noc_data.root_ca = heapless::Vec::from_slice(req.str.0)
-                        .map_err(|_| ErrorCode::BufferTooSmall)?;
+        // This may happen on CASE or PASE. In both cases, the root is kept with the NOC Data
+        // of the CSR, so that it gets cleared if the session goes away
+        exchange.with_session_mut(|sess| {
+            let noc_data = sess.get_noc_data().ok_or(ErrorCode::ConstraintError)?;
 
-                    Ok(())
-                })?;
+            noc_data.root_ca =
+                heapless::Vec::from_slice(req.str.0).map_err(|_| ErrorCode::BufferTooSmall)?;
 
-                // TODO
-            }
-            _ => (),
-        }
+            Ok(())
+        })?;
 
         Ok(())
     }
@@ -660,47 +818,6 @@ impl<'a> ChangeNotifier<()> for NocCluster<'a> {
     }
 }
 
-fn add_attestation_element(
-    epoch: Epoch,
-    dev_att: &dyn DevAttDataFetcher,
-    att_nonce: &[u8],
-    write_buf: &mut WriteBuf,
-    t: &mut TLVWriter,
-) -> Result<(), Error> {
-    let mut cert_dec: [u8; MAX_CERT_DECLARATION_LEN] = [0; MAX_CERT_DECLARATION_LEN];
-    let len = dev_att.get_devatt_data(dev_att::DataType::CertDeclaration, &mut cert_dec)?;
-    let cert_dec = &cert_dec[0..len];
-
-    let epoch = epoch().as_secs() as u32;
-    let mut writer = TLVWriter::new(write_buf);
-    writer.start_struct(TagType::Anonymous)?;
-    writer.str16(TagType::Context(1), cert_dec)?;
-    writer.str8(TagType::Context(2), att_nonce)?;
-    writer.u32(TagType::Context(3), epoch)?;
-    writer.end_container()?;
-
-    t.str16(TagType::Context(0), write_buf.as_slice())
-}
-
-fn add_attestation_signature(
-    dev_att: &dyn DevAttDataFetcher,
-    attest_element: &mut WriteBuf,
-    attest_challenge: &[u8],
-    resp: &mut TLVWriter,
-) -> Result<(), Error> {
-    let dac_key = {
-        let mut pubkey = [0_u8; crypto::EC_POINT_LEN_BYTES];
-        let mut privkey = [0_u8; crypto::BIGNUM_LEN_BYTES];
-        dev_att.get_devatt_data(dev_att::DataType::DACPubKey, &mut pubkey)?;
-        dev_att.get_devatt_data(dev_att::DataType::DACPrivKey, &mut privkey)?;
-        KeyPair::new_from_components(&pubkey, &privkey)
-    }?;
-    attest_element.copy_from_slice(attest_challenge)?;
-    let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
-    dac_key.sign_msg(attest_element.as_slice(), &mut signature)?;
-    resp.str8(TagType::Context(1), &signature)
-}
-
 fn add_nocsrelement(
     noc_keypair: &KeyPair,
     csr_nonce: &[u8],
@@ -725,8 +842,149 @@ fn get_certchainrequest_params(data: &TLVElement) -> Result<DataType, Error> {
     const CERT_TYPE_PAI: u8 = 2;
     info!("Received Cert Type:{:?}", cert_type);
     match cert_type {
-        CERT_TYPE_DAC => Ok(dev_att::DataType::DAC),
-        CERT_TYPE_PAI => Ok(dev_att::DataType::PAI),
+        CERT_TYPE_DAC => Ok(DataType::DAC),
+        CERT_TYPE_PAI => Ok(DataType::PAI),
         _ => Err(ErrorCode::Invalid.into()),
     }
 }
+
+fn get_certs(
+    noc_value: &[u8],
+    icac_value: Option<&[u8]>,
+) -> Result<
+    (
+        heapless::Vec<u8, { MAX_CERT_TLV_LEN }>,
+        Option<heapless::Vec<u8, { MAX_CERT_TLV_LEN }>>,
+    ),
+    NocError,
+> {
+    let noc_cert = Cert::new(noc_value).map_err(|_| NocStatus::InvalidNOC)?;
+    info!("Received NOC as: {}", noc_cert);
+
+    let noc = heapless::Vec::from_slice(noc_value).map_err(|_| NocStatus::InvalidNOC)?;
+
+    let icac = match icac_value {
+        Some(icac_value) if !icac_value.is_empty() => {
+            let icac_cert = Cert::new(icac_value).map_err(|_| NocStatus::InvalidNOC)?;
+            info!("Received ICAC as: {}", icac_cert);
+
+            let icac = heapless::Vec::from_slice(icac_value).map_err(|_| NocStatus::InvalidNOC)?;
+            Some(icac)
+        }
+        _ => None,
+    };
+
+    Ok((noc, icac))
+}
+
+/// Verify that the NOC chains up to the root certificate, through the ICAC if present
+fn validate_chain(root_ca: &[u8], icac: Option<&[u8]>, noc: &[u8]) -> Result<(), NocError> {
+    if root_ca.is_empty() {
+        error!("No trusted root certificate was added for the NOC");
+        Err(Error::from(ErrorCode::ConstraintError))?;
+    }
+
+    let root_ca = Cert::new(root_ca).map_err(|_| NocStatus::InvalidNOC)?;
+    let noc = Cert::new(noc).map_err(|_| NocStatus::InvalidNOC)?;
+
+    let result = if let Some(icac) = icac {
+        let icac = Cert::new(icac).map_err(|_| NocStatus::InvalidNOC)?;
+
+        noc.verify_chain_start()
+            .add_cert(&icac)
+            .and_then(|verifier| verifier.add_cert(&root_ca))
+            .and_then(|verifier| verifier.finalise())
+    } else {
+        noc.verify_chain_start()
+            .add_cert(&root_ca)
+            .and_then(|verifier| verifier.finalise())
+    };
+
+    result.map_err(|e| {
+        error!("NOC chain validation failed: {:?}", e);
+        NocStatus::InvalidNOC.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cert::tests::test_vectors::{ICAC1_SUCCESS, NOC1_SUCCESS, RCA1_SUCCESS},
+        crypto::{self, KeyPair},
+        data_model::sdm::dev_att::{tests::TestDevAtt, AttestationMgr},
+        error::ErrorCode,
+        test_support::seq_rand,
+        tlv::{get_root_node_struct, TLVWriter, TagType},
+        utils::{epoch::dummy_epoch, writebuf::WriteBuf},
+    };
+
+    use super::{add_nocsrelement, validate_chain, NocError, NocStatus, RESP_MAX};
+
+    #[test]
+    /// The NOCSR elements carry the CSR nonce, and are signed with the DAC together
+    /// with the attestation challenge
+    fn csr_signature() {
+        let dac = KeyPair::new(seq_rand).unwrap();
+        let dev_att = TestDevAtt::new(&dac);
+        let attestation = AttestationMgr::new(&dev_att, dummy_epoch);
+        let noc_keypair = KeyPair::new(seq_rand).unwrap();
+
+        let nonce = [0x11; 32];
+        let challenge = [0x22; crypto::SYMM_KEY_LEN_BYTES];
+
+        let mut buf = [0; RESP_MAX];
+        let mut element_buf = [0; RESP_MAX];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut nocsr_element = WriteBuf::new(&mut element_buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        add_nocsrelement(&noc_keypair, &nonce, &mut nocsr_element, &mut tw).unwrap();
+        attestation
+            .sign(&mut nocsr_element, &challenge, &mut tw)
+            .unwrap();
+        tw.end_container().unwrap();
+
+        let resp = get_root_node_struct(writebuf.as_slice()).unwrap();
+        let elements = resp.find_tag(0).unwrap().slice().unwrap();
+        let signature = resp.find_tag(1).unwrap().slice().unwrap();
+
+        let nocsr = get_root_node_struct(elements).unwrap();
+        assert!(!nocsr.find_tag(1).unwrap().slice().unwrap().is_empty());
+        assert_eq!(nocsr.find_tag(2).unwrap().slice().unwrap(), &nonce);
+
+        let mut msg = heapless::Vec::<u8, RESP_MAX>::from_slice(elements).unwrap();
+        msg.extend_from_slice(&challenge).unwrap();
+
+        let mut pubkey = [0; crypto::EC_POINT_LEN_BYTES];
+        dac.get_public_key(&mut pubkey).unwrap();
+        let dac_pubkey = KeyPair::new_from_public(&pubkey).unwrap();
+
+        dac_pubkey.verify_msg(&msg, signature).unwrap();
+
+        // A signature over a different challenge doesn't verify
+        let len = msg.len();
+        msg[len - 1] ^= 0xFF;
+        assert!(dac_pubkey.verify_msg(&msg, signature).is_err());
+    }
+
+    #[test]
+    fn noc_chain_validation() {
+        assert!(validate_chain(&RCA1_SUCCESS, Some(&ICAC1_SUCCESS), &NOC1_SUCCESS).is_ok());
+
+        // The NOC is not issued by the root directly
+        assert!(matches!(
+            validate_chain(&RCA1_SUCCESS, None, &NOC1_SUCCESS),
+            Err(NocError::Status(NocStatus::InvalidNOC))
+        ));
+    }
+
+    #[test]
+    /// A NOC is rejected if no trusted root certificate was added before it
+    fn noc_without_root() {
+        assert!(matches!(
+            validate_chain(&[], Some(&ICAC1_SUCCESS), &NOC1_SUCCESS),
+            Err(NocError::Error(e)) if e.code() == ErrorCode::ConstraintError
+        ));
+    }
+}
@@ -15,30 +15,104 @@
  *    limitations under the License.
  */
 
+use core::cell::{Cell, RefCell};
+use core::convert::TryInto;
+
 use crate::{
-    data_model::objects::{
-        AttrDataEncoder, AttrDetails, ChangeNotifier, Cluster, Dataver, Handler,
-        NonBlockingHandler, ATTRIBUTE_LIST, FEATURE_MAP,
-    },
+    attribute_enum, cmd_enter, command_enum,
+    data_model::objects::*,
+    data_model::sdm::failsafe::FailSafe,
     error::{Error, ErrorCode},
+    tlv::{FromTLV, Nullable, OctetStr, TLVElement, TLVWriter, TagType, ToTLV, UtfStr},
+    transport::exchange::Exchange,
     utils::rand::Rand,
 };
+use log::{error, info};
+use strum::{EnumDiscriminants, FromRepr};
 
 pub const ID: u32 = 0x0031;
 
+/// The maximum number of networks that a wireless interface can be configured with
+pub const MAX_NETWORKS: usize = 4;
+/// The maximum number of scan results that are reported for a ScanNetworks command
+pub const MAX_SCAN_RESULTS: usize = 8;
+
+const MAX_NETWORK_ID_LEN: usize = 32;
+const MAX_WIFI_CREDENTIALS_LEN: usize = 64;
+const MAX_THREAD_DATASET_LEN: usize = 254;
+
+/// The Extended PAN ID TLV of a Thread Operational Dataset
+const THREAD_TLV_EXT_PAN_ID: u8 = 2;
+
 enum FeatureMap {
-    _Wifi = 0x01,
-    _Thread = 0x02,
+    Wifi = 0x01,
+    Thread = 0x02,
     Ethernet = 0x04,
 }
 
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    MaxNetworks(AttrType<u8>) = 0x00,
+    Networks(()) = 0x01,
+    InterfaceEnabled(AttrType<bool>) = 0x04,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    ScanNetworks = 0x00,
+    AddOrUpdateWifiNetwork = 0x02,
+    AddOrUpdateThreadNetwork = 0x03,
+    RemoveNetwork = 0x04,
+    ConnectNetwork = 0x06,
+    ReorderNetwork = 0x08,
+}
+
+command_enum!(Commands);
+
+#[repr(u16)]
+pub enum RespCommands {
+    ScanNetworksResp = 0x01,
+    NetworkConfigResp = 0x05,
+    ConnectNetworkResp = 0x07,
+}
+
+/// The status of a network commissioning command, which is reported in its response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NetworkCommissioningStatus {
+    Success = 0,
+    OutOfRange = 1,
+    BoundsExceeded = 2,
+    NetworkIDNotFound = 3,
+    DuplicateNetworkID = 4,
+    NetworkNotFound = 5,
+    RegulatoryError = 6,
+    AuthFailure = 7,
+    UnsupportedSecurity = 8,
+    OtherConnectionFailure = 9,
+    IPV6Failed = 10,
+    IPBindFailed = 11,
+    UnknownError = 12,
+}
+
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID as _,
+    revision: 1,
     feature_map: FeatureMap::Ethernet as _,
     attributes: &[FEATURE_MAP, ATTRIBUTE_LIST],
     commands: &[],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
 };
 
+/// The handler of the Network Commissioning cluster of an Ethernet interface
 pub struct NwCommCluster {
     data_ver: Dataver,
 }
@@ -72,3 +146,1031 @@ impl ChangeNotifier<()> for NwCommCluster {
         self.data_ver.consume_change(())
     }
 }
+
+const WIRELESS_ATTRIBUTES: &[Attribute] = &[
+    FEATURE_MAP,
+    ATTRIBUTE_LIST,
+    Attribute::new(
+        AttributesDiscriminants::MaxNetworks as u16,
+        Access::READ.union(Access::NEED_ADMIN),
+        Quality::FIXED,
+    ),
+    Attribute::new(
+        AttributesDiscriminants::Networks as u16,
+        Access::READ.union(Access::NEED_ADMIN),
+        Quality::NONE,
+    ),
+    Attribute::new(
+        AttributesDiscriminants::InterfaceEnabled as u16,
+        Access::RWVA,
+        Quality::PERSISTENT,
+    ),
+];
+
+pub const WIFI_CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: FeatureMap::Wifi as _,
+    attributes: WIRELESS_ATTRIBUTES,
+    commands: &[
+        Commands::ScanNetworks as _,
+        Commands::AddOrUpdateWifiNetwork as _,
+        Commands::RemoveNetwork as _,
+        Commands::ConnectNetwork as _,
+        Commands::ReorderNetwork as _,
+    ],
+    generated_commands: &[
+        RespCommands::ScanNetworksResp as _,
+        RespCommands::NetworkConfigResp as _,
+        RespCommands::ConnectNetworkResp as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[
+        Commands::ScanNetworks as _,
+        Commands::AddOrUpdateWifiNetwork as _,
+        Commands::RemoveNetwork as _,
+        Commands::ConnectNetwork as _,
+        Commands::ReorderNetwork as _,
+    ],
+    manage_commands: &[],
+    admin_commands: &[
+        Commands::ScanNetworks as _,
+        Commands::AddOrUpdateWifiNetwork as _,
+        Commands::RemoveNetwork as _,
+        Commands::ConnectNetwork as _,
+        Commands::ReorderNetwork as _,
+    ],
+};
+
+pub const THREAD_CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: FeatureMap::Thread as _,
+    attributes: WIRELESS_ATTRIBUTES,
+    commands: &[
+        Commands::ScanNetworks as _,
+        Commands::AddOrUpdateThreadNetwork as _,
+        Commands::RemoveNetwork as _,
+        Commands::ConnectNetwork as _,
+        Commands::ReorderNetwork as _,
+    ],
+    generated_commands: &[
+        RespCommands::ScanNetworksResp as _,
+        RespCommands::NetworkConfigResp as _,
+        RespCommands::ConnectNetworkResp as _,
+    ],
+    timed_commands: &[],
+    response_commands: &[
+        Commands::ScanNetworks as _,
+        Commands::AddOrUpdateThreadNetwork as _,
+        Commands::RemoveNetwork as _,
+        Commands::ConnectNetwork as _,
+        Commands::ReorderNetwork as _,
+    ],
+    manage_commands: &[],
+    admin_commands: &[
+        Commands::ScanNetworks as _,
+        Commands::AddOrUpdateThreadNetwork as _,
+        Commands::RemoveNetwork as _,
+        Commands::ConnectNetwork as _,
+        Commands::ReorderNetwork as _,
+    ],
+};
+
+/// A Wi-Fi network found by a scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct WifiScanResult {
+    /// The security types of the network, as a bitmap
+    pub security: u8,
+    pub ssid: heapless::Vec<u8, MAX_NETWORK_ID_LEN>,
+    pub bssid: [u8; 6],
+    pub channel: u16,
+    pub band: u8,
+    pub rssi: i8,
+}
+
+impl ToTLV for WifiScanResult {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag_type: TagType) -> Result<(), Error> {
+        tw.start_struct(tag_type)?;
+        tw.u8(TagType::Context(0), self.security)?;
+        tw.str8(TagType::Context(1), &self.ssid)?;
+        tw.str8(TagType::Context(2), &self.bssid)?;
+        tw.u16(TagType::Context(3), self.channel)?;
+        tw.u8(TagType::Context(4), self.band)?;
+        tw.i8(TagType::Context(5), self.rssi)?;
+        tw.end_container()
+    }
+}
+
+/// A Thread network found by a scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadScanResult {
+    pub pan_id: u16,
+    pub ext_pan_id: u64,
+    pub network_name: heapless::String<16>,
+    pub channel: u16,
+    pub version: u8,
+    pub ext_address: [u8; 8],
+    pub rssi: i8,
+    pub lqi: u8,
+}
+
+impl ToTLV for ThreadScanResult {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag_type: TagType) -> Result<(), Error> {
+        tw.start_struct(tag_type)?;
+        tw.u16(TagType::Context(0), self.pan_id)?;
+        tw.u64(TagType::Context(1), self.ext_pan_id)?;
+        tw.utf8(TagType::Context(2), self.network_name.as_bytes())?;
+        tw.u16(TagType::Context(3), self.channel)?;
+        tw.u8(TagType::Context(4), self.version)?;
+        tw.str8(TagType::Context(5), &self.ext_address)?;
+        tw.i8(TagType::Context(6), self.rssi)?;
+        tw.u8(TagType::Context(7), self.lqi)?;
+        tw.end_container()
+    }
+}
+
+/// The Wi-Fi Driver Trait
+///
+/// Objects that implement this trait perform the radio operations of a Wi-Fi interface
+/// on behalf of the Network Commissioning cluster.
+pub trait WifiDriver {
+    /// Scan for networks, optionally only for the ones with the provided SSID
+    fn scan(
+        &self,
+        ssid: Option<&[u8]>,
+        results: &mut heapless::Vec<WifiScanResult, MAX_SCAN_RESULTS>,
+    ) -> Result<(), NetworkCommissioningStatus>;
+
+    /// Connect to the network with the provided SSID and credentials
+    fn connect(&self, ssid: &[u8], credentials: &[u8]) -> Result<(), NetworkCommissioningStatus>;
+}
+
+/// The Thread Driver Trait
+///
+/// Objects that implement this trait perform the radio operations of a Thread interface
+/// on behalf of the Network Commissioning cluster.
+pub trait ThreadDriver {
+    /// Scan for networks
+    fn scan(
+        &self,
+        results: &mut heapless::Vec<ThreadScanResult, MAX_SCAN_RESULTS>,
+    ) -> Result<(), NetworkCommissioningStatus>;
+
+    /// Connect to the network described by the provided Operational Dataset
+    fn connect(&self, dataset: &[u8]) -> Result<(), NetworkCommissioningStatus>;
+}
+
+/// The driver of the wireless interface of a Network Commissioning cluster
+#[derive(Clone, Copy)]
+pub enum NetworkDriver<'a> {
+    Wifi(&'a dyn WifiDriver),
+    Thread(&'a dyn ThreadDriver),
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct ScanNetworksReq<'a> {
+    ssid: Option<Nullable<OctetStr<'a>>>,
+    bread_crumb: Option<u64>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddWifiNetworkReq<'a> {
+    ssid: OctetStr<'a>,
+    credentials: OctetStr<'a>,
+    bread_crumb: Option<u64>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddThreadNetworkReq<'a> {
+    dataset: OctetStr<'a>,
+    bread_crumb: Option<u64>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct NetworkReq<'a> {
+    network_id: OctetStr<'a>,
+    bread_crumb: Option<u64>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct ReorderNetworkReq<'a> {
+    network_id: OctetStr<'a>,
+    network_index: u8,
+    bread_crumb: Option<u64>,
+}
+
+#[derive(ToTLV)]
+struct NetworkConfigResp<'a> {
+    status: u8,
+    debug_txt: Option<UtfStr<'a>>,
+    network_index: Option<u8>,
+}
+
+#[derive(ToTLV)]
+struct ConnectNetworkResp<'a> {
+    status: u8,
+    debug_txt: Option<UtfStr<'a>>,
+    error_value: Nullable<i32>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct NetworkInfo<'a> {
+    network_id: OctetStr<'a>,
+    connected: bool,
+}
+
+#[derive(Clone)]
+struct Network {
+    id: heapless::Vec<u8, MAX_NETWORK_ID_LEN>,
+    /// The credentials of a Wi-Fi network, or the Operational Dataset of a Thread network
+    credentials: heapless::Vec<u8, MAX_THREAD_DATASET_LEN>,
+}
+
+/// The networks as they were before they were first changed under an arming of the fail-safe
+struct NetworksBackup {
+    networks: heapless::Vec<Network, MAX_NETWORKS>,
+    connected: Option<heapless::Vec<u8, MAX_NETWORK_ID_LEN>>,
+    /// The arming of the fail-safe
+    arming: u32,
+    /// `FailSafe::network_rollbacks` when the backup was taken
+    rollbacks: u32,
+}
+
+/// The handler of the Network Commissioning cluster of a Wi-Fi or a Thread interface
+///
+/// The networks changed under the fail-safe are reverted once it expires, the next time
+/// the cluster is accessed.
+pub struct WirelessNwCommCluster<'a> {
+    data_ver: Dataver,
+    driver: NetworkDriver<'a>,
+    failsafe: &'a RefCell<FailSafe>,
+    networks: RefCell<heapless::Vec<Network, MAX_NETWORKS>>,
+    connected: RefCell<Option<heapless::Vec<u8, MAX_NETWORK_ID_LEN>>>,
+    enabled: Cell<bool>,
+    backup: RefCell<Option<NetworksBackup>>,
+}
+
+impl<'a> WirelessNwCommCluster<'a> {
+    pub fn new(driver: NetworkDriver<'a>, failsafe: &'a RefCell<FailSafe>, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            driver,
+            failsafe,
+            networks: RefCell::new(heapless::Vec::new()),
+            connected: RefCell::new(None),
+            enabled: Cell::new(true),
+            backup: RefCell::new(None),
+        }
+    }
+
+    /// The cluster metadata matching the driver of the interface
+    pub fn cluster(&self) -> &'static Cluster<'static> {
+        match self.driver {
+            NetworkDriver::Wifi(_) => &WIFI_CLUSTER,
+            NetworkDriver::Thread(_) => &THREAD_CLUSTER,
+        }
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        self.sync_failsafe();
+
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                self.cluster().read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::MaxNetworks(codec) => codec.encode(writer, MAX_NETWORKS as _),
+                    Attributes::Networks(_) => {
+                        let connected = self.connected.borrow();
+
+                        writer.set_list(self.networks.borrow().iter().map(|network| NetworkInfo {
+                            network_id: OctetStr::new(&network.id),
+                            connected: connected.as_ref() == Some(&network.id),
+                        }))
+                    }
+                    Attributes::InterfaceEnabled(codec) => codec.encode(writer, self.enabled.get()),
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        let data = data.with_dataver(self.data_ver.get())?;
+
+        match attr.attr_id.try_into()? {
+            Attributes::InterfaceEnabled(codec) => self.enabled.set(codec.decode(data)?),
+            _ => {
+                error!("Attribute not supported: this shouldn't happen");
+                Err(ErrorCode::AttributeNotFound)?
+            }
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        self.sync_failsafe();
+
+        match cmd.cmd_id.try_into()? {
+            Commands::ScanNetworks => self.handle_command_scannetworks(data, encoder)?,
+            Commands::AddOrUpdateWifiNetwork => {
+                self.handle_command_addorupdatewifinetwork(data, encoder)?
+            }
+            Commands::AddOrUpdateThreadNetwork => {
+                self.handle_command_addorupdatethreadnetwork(data, encoder)?
+            }
+            Commands::RemoveNetwork => self.handle_command_removenetwork(data, encoder)?,
+            Commands::ConnectNetwork => self.handle_command_connectnetwork(data, encoder)?,
+            Commands::ReorderNetwork => self.handle_command_reordernetwork(data, encoder)?,
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    fn handle_command_scannetworks(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("ScanNetworks");
+
+        let req = ScanNetworksReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let mut writer = encoder.with_command(RespCommands::ScanNetworksResp as _)?;
+        writer.start_struct(CmdDataWriter::TAG)?;
+
+        match self.driver {
+            NetworkDriver::Wifi(driver) => {
+                let ssid = req
+                    .ssid
+                    .and_then(Nullable::unwrap_notnull)
+                    .map(|ssid| ssid.0);
+
+                let mut results = heapless::Vec::new();
+                let status = Self::status(driver.scan(ssid, &mut results));
+
+                writer.u8(TagType::Context(0), status as _)?;
+                writer.start_array(TagType::Context(2))?;
+                for result in &results {
+                    result.to_tlv(&mut writer, TagType::Anonymous)?;
+                }
+                writer.end_container()?;
+            }
+            NetworkDriver::Thread(driver) => {
+                let mut results = heapless::Vec::new();
+                let status = Self::status(driver.scan(&mut results));
+
+                writer.u8(TagType::Context(0), status as _)?;
+                writer.start_array(TagType::Context(3))?;
+                for result in &results {
+                    result.to_tlv(&mut writer, TagType::Anonymous)?;
+                }
+                writer.end_container()?;
+            }
+        }
+
+        writer.end_container()?;
+        writer.complete()?;
+
+        self.set_breadcrumb(req.bread_crumb);
+
+        Ok(())
+    }
+
+    fn handle_command_addorupdatewifinetwork(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("AddOrUpdateWiFiNetwork");
+
+        let req = AddWifiNetworkReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        self.check_failsafe()?;
+
+        let result = if !matches!(self.driver, NetworkDriver::Wifi(_)) {
+            Err(ErrorCode::CommandNotFound)?
+        } else if req.ssid.0.is_empty() || req.credentials.0.len() > MAX_WIFI_CREDENTIALS_LEN {
+            Err(NetworkCommissioningStatus::OutOfRange)
+        } else {
+            self.add_or_update(req.ssid.0, req.credentials.0)
+        };
+
+        self.network_config_resp(encoder, result, req.bread_crumb)
+    }
+
+    fn handle_command_addorupdatethreadnetwork(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("AddOrUpdateThreadNetwork");
+
+        let req = AddThreadNetworkReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        self.check_failsafe()?;
+
+        let result = if !matches!(self.driver, NetworkDriver::Thread(_)) {
+            Err(ErrorCode::CommandNotFound)?
+        } else if let Some(ext_pan_id) = thread_ext_pan_id(req.dataset.0) {
+            self.add_or_update(ext_pan_id, req.dataset.0)
+        } else {
+            Err(NetworkCommissioningStatus::OutOfRange)
+        };
+
+        self.network_config_resp(encoder, result, req.bread_crumb)
+    }
+
+    fn handle_command_removenetwork(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("RemoveNetwork");
+
+        let req = NetworkReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        self.check_failsafe()?;
+
+        let result = self.remove(req.network_id.0);
+
+        self.network_config_resp(encoder, result, req.bread_crumb)
+    }
+
+    fn handle_command_connectnetwork(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("ConnectNetwork");
+
+        let req = NetworkReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        self.check_failsafe()?;
+
+        let status = Self::status(self.connect(req.network_id.0));
+
+        encoder
+            .with_command(RespCommands::ConnectNetworkResp as _)?
+            .set(ConnectNetworkResp {
+                status: status as _,
+                debug_txt: None,
+                error_value: Nullable::Null,
+            })?;
+
+        if status == NetworkCommissioningStatus::Success {
+            self.set_breadcrumb(req.bread_crumb);
+        }
+
+        Ok(())
+    }
+
+    fn handle_command_reordernetwork(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("ReorderNetwork");
+
+        let req = ReorderNetworkReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+        self.check_failsafe()?;
+
+        let result = self.reorder(req.network_id.0, req.network_index);
+
+        self.network_config_resp(encoder, result, req.bread_crumb)
+    }
+
+    fn network_config_resp(
+        &self,
+        encoder: CmdDataEncoder,
+        result: Result<u8, NetworkCommissioningStatus>,
+        bread_crumb: Option<u64>,
+    ) -> Result<(), Error> {
+        let (status, network_index) = match result {
+            Ok(index) => (NetworkCommissioningStatus::Success, Some(index)),
+            Err(status) => (status, None),
+        };
+
+        encoder
+            .with_command(RespCommands::NetworkConfigResp as _)?
+            .set(NetworkConfigResp {
+                status: status as _,
+                debug_txt: None,
+                network_index,
+            })?;
+
+        if status == NetworkCommissioningStatus::Success {
+            self.set_breadcrumb(bread_crumb);
+        }
+
+        Ok(())
+    }
+
+    /// Add a network, or update the credentials of an existing one, returning its index
+    fn add_or_update(
+        &self,
+        network_id: &[u8],
+        credentials: &[u8],
+    ) -> Result<u8, NetworkCommissioningStatus> {
+        let id = heapless::Vec::from_slice(network_id)
+            .map_err(|_| NetworkCommissioningStatus::OutOfRange)?;
+        let credentials = heapless::Vec::from_slice(credentials)
+            .map_err(|_| NetworkCommissioningStatus::OutOfRange)?;
+
+        let mut networks = self.networks.borrow_mut();
+
+        if let Some(index) = networks.iter().position(|network| network.id == id) {
+            info!("Updating network {:x?}", network_id);
+            networks[index].credentials = credentials;
+
+            Ok(index as _)
+        } else {
+            info!("Adding network {:x?}", network_id);
+            networks
+                .push(Network { id, credentials })
+                .map_err(|_| NetworkCommissioningStatus::BoundsExceeded)?;
+
+            Ok((networks.len() - 1) as _)
+        }
+    }
+
+    /// Remove a network, returning the index it had
+    fn remove(&self, network_id: &[u8]) -> Result<u8, NetworkCommissioningStatus> {
+        let mut networks = self.networks.borrow_mut();
+
+        let index = Self::find(&networks, network_id)?;
+        networks.remove(index);
+
+        let mut connected = self.connected.borrow_mut();
+        if connected.as_deref() == Some(network_id) {
+            *connected = None;
+        }
+
+        Ok(index as _)
+    }
+
+    fn connect(&self, network_id: &[u8]) -> Result<(), NetworkCommissioningStatus> {
+        let networks = self.networks.borrow();
+
+        let network = &networks[Self::find(&networks, network_id)?];
+
+        match self.driver {
+            NetworkDriver::Wifi(driver) => driver.connect(&network.id, &network.credentials)?,
+            NetworkDriver::Thread(driver) => driver.connect(&network.credentials)?,
+        }
+
+        *self.connected.borrow_mut() = Some(network.id.clone());
+
+        Ok(())
+    }
+
+    /// Move a network to a new index in the list of networks, returning that index
+    fn reorder(&self, network_id: &[u8], index: u8) -> Result<u8, NetworkCommissioningStatus> {
+        let mut networks = self.networks.borrow_mut();
+
+        let current = Self::find(&networks, network_id)?;
+        let index = index as usize;
+        if index >= networks.len() {
+            Err(NetworkCommissioningStatus::OutOfRange)?;
+        }
+
+        if current < index {
+            networks[current..=index].rotate_left(1);
+        } else {
+            networks[index..=current].rotate_right(1);
+        }
+
+        Ok(index as _)
+    }
+
+    fn find(networks: &[Network], network_id: &[u8]) -> Result<usize, NetworkCommissioningStatus> {
+        networks
+            .iter()
+            .position(|network| network.id.as_slice() == network_id)
+            .ok_or(NetworkCommissioningStatus::NetworkIDNotFound)
+    }
+
+    fn status(result: Result<(), NetworkCommissioningStatus>) -> NetworkCommissioningStatus {
+        result.err().unwrap_or(NetworkCommissioningStatus::Success)
+    }
+
+    /// Check that the fail-safe is armed, taking a backup of the networks if this is
+    /// the first change under it
+    fn check_failsafe(&self) -> Result<(), Error> {
+        let mut failsafe = self.failsafe.borrow_mut();
+
+        let Some(arming) = failsafe.arming() else {
+            return Err(ErrorCode::FailSafeRequired.into());
+        };
+
+        let mut backup = self.backup.borrow_mut();
+        if backup.is_none() {
+            *backup = Some(NetworksBackup {
+                networks: self.networks.borrow().clone(),
+                connected: self.connected.borrow().clone(),
+                arming,
+                rollbacks: failsafe.network_rollbacks(),
+            });
+        }
+
+        failsafe.record_network_change()
+    }
+
+    /// Revert the networks to their backup if the fail-safe expired since it was taken,
+    /// or drop the backup if the changes were committed with `CommissioningComplete`
+    fn sync_failsafe(&self) {
+        let failsafe = self.failsafe.borrow();
+        let mut slot = self.backup.borrow_mut();
+
+        let Some(backup) = slot.take() else {
+            return;
+        };
+
+        if failsafe.network_rollbacks() != backup.rollbacks {
+            info!("Fail-Safe rollback: restoring the networks");
+
+            if backup.connected.is_some() && backup.connected != *self.connected.borrow() {
+                let network = backup
+                    .networks
+                    .iter()
+                    .find(|network| Some(&network.id) == backup.connected.as_ref());
+
+                if let Some(network) = network {
+                    let result = match self.driver {
+                        NetworkDriver::Wifi(driver) => {
+                            driver.connect(&network.id, &network.credentials)
+                        }
+                        NetworkDriver::Thread(driver) => driver.connect(&network.credentials),
+                    };
+
+                    if let Err(status) = result {
+                        error!("Reconnecting to {:x?} failed: {:?}", network.id, status);
+                    }
+                }
+            }
+
+            *self.networks.borrow_mut() = backup.networks;
+            *self.connected.borrow_mut() = backup.connected;
+
+            self.data_ver.changed();
+        } else if failsafe.arming() == Some(backup.arming) {
+            // Still under the same arming of the fail-safe
+            *slot = Some(backup);
+        }
+    }
+
+    fn set_breadcrumb(&self, bread_crumb: Option<u64>) {
+        if let Some(bread_crumb) = bread_crumb {
+            self.failsafe.borrow_mut().set_breadcrumb(bread_crumb);
+        }
+    }
+}
+
+impl<'a> Handler for WirelessNwCommCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        WirelessNwCommCluster::read(self, attr, encoder)
+    }
+
+    fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        WirelessNwCommCluster::write(self, attr, data)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        WirelessNwCommCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for WirelessNwCommCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for WirelessNwCommCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+/// The Extended PAN ID of a Thread Operational Dataset, which is the ID of its network
+fn thread_ext_pan_id(dataset: &[u8]) -> Option<&[u8]> {
+    if dataset.len() > MAX_THREAD_DATASET_LEN {
+        return None;
+    }
+
+    // The dataset is a sequence of MeshCoP TLVs, each with a one byte type and length
+    let mut rest = dataset;
+    while rest.len() >= 2 {
+        let (tlv_type, len) = (rest[0], rest[1] as usize);
+        let value = rest.get(2..2 + len)?;
+
+        if tlv_type == THREAD_TLV_EXT_PAN_ID {
+            return (len == 8).then_some(value);
+        }
+
+        rest = &rest[2 + len..];
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use crate::{
+        acl::AclMgr,
+        data_model::{
+            objects::{AttrDataEncoder, AttrDetails, Node},
+            sdm::failsafe::FailSafe,
+        },
+        error::ErrorCode,
+        tlv::{get_root_node_struct, TLVWriter},
+        transport::session::{CaseDetails, SessionMode},
+        utils::{epoch::dummy_epoch, rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{
+        AttributesDiscriminants, NetworkCommissioningStatus, NetworkDriver, ThreadDriver,
+        ThreadScanResult, WifiDriver, WifiScanResult, WirelessNwCommCluster, ID, MAX_NETWORKS,
+        MAX_SCAN_RESULTS,
+    };
+
+    /// Records the network that it was last connected to
+    #[derive(Default)]
+    struct MockDriver {
+        connected: RefCell<heapless::Vec<u8, 64>>,
+    }
+
+    impl WifiDriver for MockDriver {
+        fn scan(
+            &self,
+            _ssid: Option<&[u8]>,
+            _results: &mut heapless::Vec<WifiScanResult, MAX_SCAN_RESULTS>,
+        ) -> Result<(), NetworkCommissioningStatus> {
+            Ok(())
+        }
+
+        fn connect(
+            &self,
+            ssid: &[u8],
+            credentials: &[u8],
+        ) -> Result<(), NetworkCommissioningStatus> {
+            if credentials != b"secret" {
+                Err(NetworkCommissioningStatus::AuthFailure)?;
+            }
+
+            *self.connected.borrow_mut() = heapless::Vec::from_slice(ssid).unwrap();
+
+            Ok(())
+        }
+    }
+
+    impl ThreadDriver for MockDriver {
+        fn scan(
+            &self,
+            _results: &mut heapless::Vec<ThreadScanResult, MAX_SCAN_RESULTS>,
+        ) -> Result<(), NetworkCommissioningStatus> {
+            Ok(())
+        }
+
+        fn connect(&self, dataset: &[u8]) -> Result<(), NetworkCommissioningStatus> {
+            *self.connected.borrow_mut() = heapless::Vec::from_slice(dataset).unwrap();
+
+            Ok(())
+        }
+    }
+
+    /// Read the Networks attribute, as a list of network IDs and their connection state
+    fn read_networks(
+        cluster: &WirelessNwCommCluster,
+    ) -> heapless::Vec<(heapless::Vec<u8, 32>, bool), 4> {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 0,
+            cluster_id: ID,
+            attr_id: AttributesDiscriminants::Networks as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 300];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .unwrap();
+
+        get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap()
+            .enter()
+            .unwrap()
+            .map(|network| {
+                (
+                    heapless::Vec::from_slice(network.find_tag(0).unwrap().slice().unwrap())
+                        .unwrap(),
+                    network.find_tag(1).unwrap().bool().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wifi_add_connect() {
+        let driver = MockDriver::default();
+        let failsafe = RefCell::new(FailSafe::new(dummy_epoch));
+        let cluster =
+            WirelessNwCommCluster::new(NetworkDriver::Wifi(&driver), &failsafe, dummy_rand);
+
+        // Network configuration is only possible under the fail-safe
+        assert_eq!(
+            cluster.check_failsafe().map_err(|e| e.code()),
+            Err(ErrorCode::FailSafeRequired)
+        );
+        failsafe
+            .borrow_mut()
+            .arm(60, SessionMode::Pase, &AclMgr::new())
+            .unwrap();
+        assert!(cluster.check_failsafe().is_ok());
+
+        assert_eq!(cluster.add_or_update(b"home", b"wrong"), Ok(0));
+        assert_eq!(cluster.add_or_update(b"office", b"secret"), Ok(1));
+        assert_eq!(
+            cluster.connect(b"home"),
+            Err(NetworkCommissioningStatus::AuthFailure)
+        );
+
+        // Updating the credentials keeps the index of the network
+        assert_eq!(cluster.add_or_update(b"home", b"secret"), Ok(0));
+        assert_eq!(cluster.connect(b"home"), Ok(()));
+        assert_eq!(driver.connected.borrow().as_slice(), b"home");
+
+        let networks = read_networks(&cluster);
+        assert_eq!(networks.len(), 2);
+        assert_eq!(networks[0].0.as_slice(), b"home");
+        assert!(networks[0].1);
+        assert_eq!(networks[1].0.as_slice(), b"office");
+        assert!(!networks[1].1);
+
+        assert_eq!(cluster.reorder(b"office", 0), Ok(0));
+        assert_eq!(read_networks(&cluster)[0].0.as_slice(), b"office");
+    }
+
+    #[test]
+    /// The networks changed under the fail-safe are reverted when it expires, and kept
+    /// when commissioning completes
+    fn failsafe_rollback() {
+        let driver = MockDriver::default();
+        let failsafe = RefCell::new(FailSafe::new(dummy_epoch));
+        let cluster =
+            WirelessNwCommCluster::new(NetworkDriver::Wifi(&driver), &failsafe, dummy_rand);
+        let acl_mgr = AclMgr::new();
+
+        failsafe
+            .borrow_mut()
+            .arm(60, SessionMode::Pase, &acl_mgr)
+            .unwrap();
+        cluster.check_failsafe().unwrap();
+        cluster.add_or_update(b"home", b"secret").unwrap();
+        cluster.connect(b"home").unwrap();
+
+        failsafe.borrow_mut().record_add_noc(1).unwrap();
+        failsafe
+            .borrow_mut()
+            .disarm(SessionMode::Case(CaseDetails::new(1, &[0; 3])))
+            .unwrap();
+
+        // Committed
+        assert_eq!(read_networks(&cluster).len(), 1);
+
+        failsafe
+            .borrow_mut()
+            .arm(60, SessionMode::Pase, &acl_mgr)
+            .unwrap();
+        cluster.check_failsafe().unwrap();
+        cluster.add_or_update(b"office", b"secret").unwrap();
+        cluster.connect(b"office").unwrap();
+        cluster.remove(b"home").unwrap();
+        assert_eq!(read_networks(&cluster).len(), 1);
+
+        // Expired, by disarming without completing the commissioning
+        assert!(failsafe
+            .borrow_mut()
+            .arm(0, SessionMode::Pase, &acl_mgr)
+            .unwrap()
+            .is_some());
+
+        let networks = read_networks(&cluster);
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks[0].0.as_slice(), b"home");
+        assert!(networks[0].1);
+        assert_eq!(driver.connected.borrow().as_slice(), b"home");
+    }
+
+    #[test]
+    fn unknown_network() {
+        let driver = MockDriver::default();
+        let failsafe = RefCell::new(FailSafe::new(dummy_epoch));
+        let cluster =
+            WirelessNwCommCluster::new(NetworkDriver::Wifi(&driver), &failsafe, dummy_rand);
+
+        cluster.add_or_update(b"home", b"secret").unwrap();
+
+        assert_eq!(
+            cluster.connect(b"office"),
+            Err(NetworkCommissioningStatus::NetworkIDNotFound)
+        );
+        assert_eq!(
+            cluster.remove(b"office"),
+            Err(NetworkCommissioningStatus::NetworkIDNotFound)
+        );
+        assert_eq!(
+            cluster.reorder(b"office", 0),
+            Err(NetworkCommissioningStatus::NetworkIDNotFound)
+        );
+        assert_eq!(
+            cluster.reorder(b"home", 1),
+            Err(NetworkCommissioningStatus::OutOfRange)
+        );
+        assert!(driver.connected.borrow().is_empty());
+
+        assert_eq!(cluster.remove(b"home"), Ok(0));
+        assert!(read_networks(&cluster).is_empty());
+    }
+
+    #[test]
+    fn max_networks() {
+        let driver = MockDriver::default();
+        let failsafe = RefCell::new(FailSafe::new(dummy_epoch));
+        let cluster =
+            WirelessNwCommCluster::new(NetworkDriver::Wifi(&driver), &failsafe, dummy_rand);
+
+        for index in 0..MAX_NETWORKS {
+            assert_eq!(
+                cluster.add_or_update(&[b'a' + index as u8], b"secret"),
+                Ok(index as _)
+            );
+        }
+
+        assert_eq!(
+            cluster.add_or_update(b"z", b"secret"),
+            Err(NetworkCommissioningStatus::BoundsExceeded)
+        );
+        assert_eq!(read_networks(&cluster).len(), MAX_NETWORKS);
+    }
+
+    #[test]
+    /// A Thread network is identified by the Extended PAN ID of its dataset
+    fn thread_add_connect() {
+        let driver = MockDriver::default();
+        let failsafe = RefCell::new(FailSafe::new(dummy_epoch));
+        let cluster =
+            WirelessNwCommCluster::new(NetworkDriver::Thread(&driver), &failsafe, dummy_rand);
+
+        // Channel, Extended PAN ID and PAN ID TLVs
+        let dataset = [
+            0x00, 0x03, 0x00, 0x00, 0x0f, 0x02, 0x08, 0x11, 0x11, 0x11, 0x11, 0x22, 0x22, 0x22,
+            0x22, 0x01, 0x02, 0x12, 0x34,
+        ];
+        let ext_pan_id = &dataset[7..15];
+
+        assert_eq!(super::thread_ext_pan_id(&dataset), Some(ext_pan_id));
+        assert_eq!(super::thread_ext_pan_id(&dataset[..10]), None);
+
+        assert_eq!(cluster.add_or_update(ext_pan_id, &dataset), Ok(0));
+        assert_eq!(cluster.connect(ext_pan_id), Ok(()));
+        assert_eq!(driver.connected.borrow().as_slice(), &dataset);
+
+        let networks = read_networks(&cluster);
+        assert_eq!(networks[0].0.as_slice(), ext_pan_id);
+        assert!(networks[0].1);
+    }
+}
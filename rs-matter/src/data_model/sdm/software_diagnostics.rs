@@ -0,0 +1,434 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::convert::TryInto;
+
+use crate::data_model::objects::*;
+use crate::tlv::TLVElement;
+use crate::transport::exchange::Exchange;
+use crate::utils::rand::Rand;
+use crate::{attribute_enum, cmd_enter, command_enum, error::*};
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0034;
+
+/// The Watermarks feature
+pub const FEATURE_WATERMARKS: u32 = 0x01;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    CurrentHeapFree(AttrType<u64>) = 0x01,
+    CurrentHeapUsed(AttrType<u64>) = 0x02,
+    CurrentHeapHighWatermark(AttrType<u64>) = 0x03,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    ResetWatermarks = 0x00,
+}
+
+command_enum!(Commands);
+
+/// The cluster of a platform which tracks its heap usage, as well as its high watermark
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 1,
+    feature_map: FEATURE_WATERMARKS,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::CurrentHeapFree as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::CurrentHeapUsed as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::CurrentHeapHighWatermark as u16,
+            Access::RV,
+            Quality::NONE,
+        )
+        .with_feature(FEATURE_WATERMARKS),
+    ],
+    commands: &[Commands::ResetWatermarks as _],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[Commands::ResetWatermarks as _],
+    admin_commands: &[],
+};
+
+/// The cluster of a platform which tracks its heap usage, but not its high watermark
+pub const CLUSTER_NO_WATERMARKS: Cluster<'static> = Cluster {
+    feature_map: 0,
+    commands: &[],
+    manage_commands: &[],
+    ..CLUSTER
+};
+
+/// The cluster of a platform which does not track its heap at all
+pub const CLUSTER_NO_HEAP_STATS: Cluster<'static> = Cluster {
+    attributes: &[FEATURE_MAP, ATTRIBUTE_LIST],
+    ..CLUSTER_NO_WATERMARKS
+};
+
+/// The Heap Statistics Trait
+///
+/// Objects that implement this trait report the statistics of the allocator of the device,
+/// in bytes. A statistic which the allocator does not track should be reported as `None`,
+/// in which case its attribute is absent.
+///
+/// The free and the used heap are either both tracked or both not, and the high watermark
+/// is only tracked along with them.
+pub trait HeapStats {
+    fn heap_free(&self) -> Option<u64>;
+
+    fn heap_used(&self) -> Option<u64>;
+
+    /// The maximum heap usage since boot, or since the last reset of the watermarks
+    fn heap_high_watermark(&self) -> Option<u64>;
+
+    /// Reset the high watermark to the current heap usage
+    fn reset_watermarks(&self);
+}
+
+pub struct SwDiagCluster<'a> {
+    data_ver: Dataver,
+    heap_stats: &'a dyn HeapStats,
+    cluster: &'static Cluster<'static>,
+}
+
+impl<'a> SwDiagCluster<'a> {
+    pub fn new(heap_stats: &'a dyn HeapStats, rand: Rand) -> Self {
+        let cluster = if heap_stats.heap_used().is_none() {
+            &CLUSTER_NO_HEAP_STATS
+        } else if heap_stats.heap_high_watermark().is_none() {
+            &CLUSTER_NO_WATERMARKS
+        } else {
+            &CLUSTER
+        };
+
+        Self {
+            data_ver: Dataver::new(rand),
+            heap_stats,
+            cluster,
+        }
+    }
+
+    /// The cluster matching the statistics the platform tracks
+    pub fn cluster(&self) -> &'static Cluster<'static> {
+        self.cluster
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                self.cluster.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::CurrentHeapFree(codec) => {
+                        codec.encode(writer, Self::stat(self.heap_stats.heap_free())?)
+                    }
+                    Attributes::CurrentHeapUsed(codec) => {
+                        codec.encode(writer, Self::stat(self.heap_stats.heap_used())?)
+                    }
+                    Attributes::CurrentHeapHighWatermark(codec) => {
+                        codec.encode(writer, Self::stat(self.heap_stats.heap_high_watermark())?)
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        _data: &TLVElement,
+        _encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::ResetWatermarks => self.handle_command_resetwatermarks(),
+        }
+
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    fn handle_command_resetwatermarks(&self) {
+        cmd_enter!("ResetWatermarks");
+
+        self.heap_stats.reset_watermarks();
+    }
+
+    /// A statistic which is not available is reported as an absent attribute
+    fn stat(value: Option<u64>) -> Result<u64, Error> {
+        value.ok_or_else(|| ErrorCode::AttributeNotFound.into())
+    }
+}
+
+impl<'a> Handler for SwDiagCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        SwDiagCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        SwDiagCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for SwDiagCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for SwDiagCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use crate::{
+        data_model::objects::{AttrDataEncoder, AttrDetails, Node},
+        error::ErrorCode,
+        tlv::{get_root_node_struct, TLVWriter},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{AttributesDiscriminants, HeapStats, SwDiagCluster, FEATURE_WATERMARKS, ID};
+
+    struct MockHeap {
+        size: u64,
+        used: Cell<u64>,
+        watermark: Cell<u64>,
+    }
+
+    impl MockHeap {
+        fn alloc(&self, len: u64) {
+            self.used.set(self.used.get() + len);
+            self.watermark
+                .set(self.watermark.get().max(self.used.get()));
+        }
+
+        fn free(&self, len: u64) {
+            self.used.set(self.used.get() - len);
+        }
+    }
+
+    impl HeapStats for MockHeap {
+        fn heap_free(&self) -> Option<u64> {
+            Some(self.size - self.used.get())
+        }
+
+        fn heap_used(&self) -> Option<u64> {
+            Some(self.used.get())
+        }
+
+        fn heap_high_watermark(&self) -> Option<u64> {
+            Some(self.watermark.get())
+        }
+
+        fn reset_watermarks(&self) {
+            self.watermark.set(self.used.get());
+        }
+    }
+
+    struct NoStats;
+
+    impl HeapStats for NoStats {
+        fn heap_free(&self) -> Option<u64> {
+            None
+        }
+
+        fn heap_used(&self) -> Option<u64> {
+            None
+        }
+
+        fn heap_high_watermark(&self) -> Option<u64> {
+            None
+        }
+
+        fn reset_watermarks(&self) {}
+    }
+
+    struct NoWatermark(MockHeap);
+
+    impl HeapStats for NoWatermark {
+        fn heap_free(&self) -> Option<u64> {
+            self.0.heap_free()
+        }
+
+        fn heap_used(&self) -> Option<u64> {
+            self.0.heap_used()
+        }
+
+        fn heap_high_watermark(&self) -> Option<u64> {
+            None
+        }
+
+        fn reset_watermarks(&self) {}
+    }
+
+    fn read(cluster: &SwDiagCluster, attr_id: AttributesDiscriminants) -> Result<u64, ErrorCode> {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 0,
+            cluster_id: ID,
+            attr_id: attr_id as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .map_err(|e| e.code())?;
+
+        Ok(get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap()
+            .find_tag(2)
+            .unwrap()
+            .u64()
+            .unwrap())
+    }
+
+    #[test]
+    fn reset_watermarks() {
+        let heap = MockHeap {
+            size: 1000,
+            used: Cell::new(0),
+            watermark: Cell::new(0),
+        };
+        heap.alloc(300);
+        heap.alloc(200);
+        heap.free(400);
+
+        let cluster = SwDiagCluster::new(&heap, dummy_rand);
+
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::CurrentHeapFree),
+            Ok(900)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::CurrentHeapUsed),
+            Ok(100)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::CurrentHeapHighWatermark),
+            Ok(500)
+        );
+
+        cluster.handle_command_resetwatermarks();
+
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::CurrentHeapHighWatermark),
+            Ok(100)
+        );
+    }
+
+    fn attribute_ids(cluster: &SwDiagCluster) -> heapless::Vec<u16, 8> {
+        cluster
+            .cluster()
+            .supported_attributes()
+            .filter(|attr| !attr.is_system())
+            .map(|attr| attr.id)
+            .collect()
+    }
+
+    #[test]
+    /// The attributes and the features follow the statistics the platform tracks
+    fn attribute_list() {
+        let heap = MockHeap {
+            size: 1000,
+            used: Cell::new(0),
+            watermark: Cell::new(0),
+        };
+        let cluster = SwDiagCluster::new(&heap, dummy_rand);
+
+        assert_eq!(cluster.cluster().feature_map, FEATURE_WATERMARKS);
+        assert_eq!(
+            attribute_ids(&cluster).as_slice(),
+            &[
+                AttributesDiscriminants::CurrentHeapFree as u16,
+                AttributesDiscriminants::CurrentHeapUsed as u16,
+                AttributesDiscriminants::CurrentHeapHighWatermark as u16
+            ]
+        );
+
+        let cluster = SwDiagCluster::new(&NoWatermark(heap), dummy_rand);
+
+        assert_eq!(cluster.cluster().feature_map, 0);
+        assert!(cluster.cluster().commands.is_empty());
+        assert_eq!(
+            attribute_ids(&cluster).as_slice(),
+            &[
+                AttributesDiscriminants::CurrentHeapFree as u16,
+                AttributesDiscriminants::CurrentHeapUsed as u16
+            ]
+        );
+
+        let cluster = SwDiagCluster::new(&NoStats, dummy_rand);
+
+        assert_eq!(cluster.cluster().feature_map, 0);
+        assert!(attribute_ids(&cluster).is_empty());
+    }
+
+    #[test]
+    /// Statistics which are not available are absent, rather than zero
+    fn no_heap_stats() {
+        let cluster = SwDiagCluster::new(&NoStats, dummy_rand);
+
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::CurrentHeapUsed),
+            Err(ErrorCode::AttributeNotFound)
+        );
+        assert_eq!(
+            read(&cluster, AttributesDiscriminants::CurrentHeapHighWatermark),
+            Err(ErrorCode::AttributeNotFound)
+        );
+    }
+}
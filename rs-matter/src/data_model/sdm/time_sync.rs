@@ -0,0 +1,658 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::{
+    cell::{Cell, RefCell},
+    convert::TryInto,
+    time::Duration,
+};
+
+use crate::{
+    attribute_enum, cmd_enter, command_enum,
+    data_model::objects::*,
+    error::{Error, ErrorCode},
+    tlv::{FromTLV, Nullable, TLVArray, TLVElement, ToTLV, UtfStr},
+    transport::exchange::Exchange,
+    utils::{epoch::Epoch, rand::Rand},
+};
+use log::info;
+use strum::{EnumDiscriminants, FromRepr};
+
+pub const ID: u32 = 0x0038;
+
+/// The Time Zone feature
+pub const FEATURE_TIME_ZONE: u32 = 0x01;
+
+/// The number of entries of the TimeZone list
+pub const TIME_ZONE_LIST_MAX_SIZE: usize = 2;
+/// The number of entries of the DSTOffset list
+pub const DST_OFFSET_LIST_MAX_SIZE: usize = 2;
+
+pub const MAX_TIME_ZONE_NAME_LEN: usize = 64;
+
+/// The time zone offsets allowed by the spec, in seconds
+const MIN_TIME_ZONE_OFFSET: i32 = -12 * 60 * 60;
+const MAX_TIME_ZONE_OFFSET: i32 = 14 * 60 * 60;
+
+#[derive(FromRepr, EnumDiscriminants)]
+#[repr(u16)]
+pub enum Attributes {
+    UTCTime(AttrType<Nullable<u64>>) = 0x00,
+    Granularity(AttrType<u8>) = 0x01,
+    TimeSource(AttrType<u8>) = 0x02,
+    TimeZone(()) = 0x05,
+    DSTOffset(()) = 0x06,
+    LocalTime(AttrType<Nullable<u64>>) = 0x07,
+    TimeZoneDatabase(AttrType<u8>) = 0x08,
+    TimeZoneListMaxSize(AttrType<u8>) = 0x0a,
+    DSTOffsetListMaxSize(AttrType<u8>) = 0x0b,
+}
+
+attribute_enum!(Attributes);
+
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Commands {
+    SetUTCTime = 0x00,
+    SetTimeZone = 0x02,
+    SetDSTOffset = 0x04,
+}
+
+command_enum!(Commands);
+
+#[repr(u16)]
+pub enum RespCommands {
+    SetTimeZoneResponse = 0x03,
+}
+
+pub const CLUSTER: Cluster<'static> = Cluster {
+    id: ID as _,
+    revision: 2,
+    feature_map: FEATURE_TIME_ZONE,
+    attributes: &[
+        FEATURE_MAP,
+        ATTRIBUTE_LIST,
+        Attribute::new(
+            AttributesDiscriminants::UTCTime as u16,
+            Access::RV,
+            Quality::NULLABLE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::Granularity as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::TimeSource as u16,
+            Access::RV,
+            Quality::NONE,
+        ),
+        Attribute::new(
+            AttributesDiscriminants::TimeZone as u16,
+            Access::RV,
+            Quality::NONE,
+        )
+        .with_feature(FEATURE_TIME_ZONE),
+        Attribute::new(
+            AttributesDiscriminants::DSTOffset as u16,
+            Access::RV,
+            Quality::NONE,
+        )
+        .with_feature(FEATURE_TIME_ZONE),
+        Attribute::new(
+            AttributesDiscriminants::LocalTime as u16,
+            Access::RV,
+            Quality::NULLABLE,
+        )
+        .with_feature(FEATURE_TIME_ZONE),
+        Attribute::new(
+            AttributesDiscriminants::TimeZoneDatabase as u16,
+            Access::RV,
+            Quality::FIXED,
+        )
+        .with_feature(FEATURE_TIME_ZONE),
+        Attribute::new(
+            AttributesDiscriminants::TimeZoneListMaxSize as u16,
+            Access::RV,
+            Quality::FIXED,
+        )
+        .with_feature(FEATURE_TIME_ZONE),
+        Attribute::new(
+            AttributesDiscriminants::DSTOffsetListMaxSize as u16,
+            Access::RV,
+            Quality::FIXED,
+        )
+        .with_feature(FEATURE_TIME_ZONE),
+    ],
+    commands: &[
+        Commands::SetUTCTime as _,
+        Commands::SetTimeZone as _,
+        Commands::SetDSTOffset as _,
+    ],
+    generated_commands: &[RespCommands::SetTimeZoneResponse as _],
+    timed_commands: &[],
+    response_commands: &[Commands::SetTimeZone as _],
+    manage_commands: &[Commands::SetTimeZone as _, Commands::SetDSTOffset as _],
+    admin_commands: &[Commands::SetUTCTime as _],
+};
+
+/// The cluster-specific status codes
+#[repr(u8)]
+pub enum StatusCode {
+    TimeNotAccepted = 0x02,
+}
+
+/// The granularity of the UTC time of the device, from the coarsest to the finest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, FromTLV, ToTLV)]
+#[repr(u8)]
+pub enum Granularity {
+    NoTimeGranularity = 0,
+    MinutesGranularity = 1,
+    SecondsGranularity = 2,
+    MillisecondsGranularity = 3,
+    MicrosecondsGranularity = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromTLV, ToTLV)]
+#[repr(u8)]
+pub enum TimeSource {
+    None = 0,
+    Unknown = 1,
+    /// The time was set by an administrator, with SetUTCTime
+    Admin = 2,
+    NodeTimeCluster = 3,
+    NonMatterSntp = 4,
+    NonMatterNtp = 5,
+    MatterSntp = 6,
+    MatterNtp = 7,
+    MixedNtp = 8,
+    NonMatterSntpNts = 9,
+    NonMatterNtpNts = 10,
+    MatterNtpNts = 11,
+    MixedNtpNts = 12,
+    CloudSource = 13,
+    Ptp = 14,
+    Gnss = 15,
+}
+
+/// The time zone database of the device
+#[repr(u8)]
+pub enum TimeZoneDatabase {
+    Full = 0,
+    Partial = 1,
+    None = 2,
+}
+
+#[derive(FromTLV, ToTLV, Clone)]
+#[tlvargs(lifetime = "'a")]
+pub struct TimeZoneStruct<'a> {
+    /// The offset from UTC, in seconds
+    pub offset: i32,
+    /// The UTC time from which the offset is applicable, in microseconds since the Matter epoch
+    pub valid_at: u64,
+    pub name: Option<UtfStr<'a>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, FromTLV, ToTLV)]
+pub struct DSTOffsetStruct {
+    /// The daylight saving offset, in seconds
+    pub offset: i32,
+    pub valid_starting: u64,
+    /// Null for an offset which is applicable indefinitely
+    pub valid_until: Nullable<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct TimeZone {
+    offset: i32,
+    valid_at: u64,
+    name: Option<heapless::String<MAX_TIME_ZONE_NAME_LEN>>,
+}
+
+impl TimeZone {
+    fn from_struct(tz: &TimeZoneStruct) -> Result<Self, Error> {
+        let name = tz
+            .name
+            .as_ref()
+            .map(|name| {
+                let mut s = heapless::String::new();
+                s.push_str(name.as_str()?)
+                    .map_err(|_| ErrorCode::ConstraintError)?;
+
+                Ok::<_, Error>(s)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            offset: tz.offset,
+            valid_at: tz.valid_at,
+            name,
+        })
+    }
+
+    fn as_struct(&self) -> TimeZoneStruct<'_> {
+        TimeZoneStruct {
+            offset: self.offset,
+            valid_at: self.valid_at,
+            name: self.name.as_ref().map(|name| UtfStr::new(name.as_bytes())),
+        }
+    }
+}
+
+#[derive(FromTLV)]
+struct SetUTCTimeReq {
+    utc_time: u64,
+    granularity: Granularity,
+    time_source: Option<TimeSource>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct SetTimeZoneReq<'a> {
+    time_zone: TLVArray<'a, TimeZoneStruct<'a>>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct SetDSTOffsetReq<'a> {
+    dst_offset: TLVArray<'a, DSTOffsetStruct>,
+}
+
+#[derive(ToTLV)]
+struct SetTimeZoneResp {
+    dst_offset_required: bool,
+}
+
+/// The RTC Driver Trait
+///
+/// Objects that implement this trait keep the real-time clock of the device.
+pub trait RtcDriver {
+    /// Set the real-time clock to the provided UTC time, in microseconds since the Matter epoch
+    fn set_utc_time(&self, utc_time: u64);
+}
+
+pub struct TimeSyncCluster<'a> {
+    data_ver: Dataver,
+    rtc: &'a dyn RtcDriver,
+    epoch: Epoch,
+    /// The UTC time which was last set, and the time since the epoch at which it was set
+    utc_time: Cell<Option<(u64, Duration)>>,
+    granularity: Cell<Granularity>,
+    time_source: Cell<TimeSource>,
+    time_zones: RefCell<heapless::Vec<TimeZone, TIME_ZONE_LIST_MAX_SIZE>>,
+    dst_offsets: RefCell<heapless::Vec<DSTOffsetStruct, DST_OFFSET_LIST_MAX_SIZE>>,
+}
+
+impl<'a> TimeSyncCluster<'a> {
+    pub fn new(rtc: &'a dyn RtcDriver, epoch: Epoch, rand: Rand) -> Self {
+        Self {
+            data_ver: Dataver::new(rand),
+            rtc,
+            epoch,
+            utc_time: Cell::new(None),
+            granularity: Cell::new(Granularity::NoTimeGranularity),
+            time_source: Cell::new(TimeSource::None),
+            time_zones: RefCell::new(heapless::Vec::new()),
+            dst_offsets: RefCell::new(heapless::Vec::new()),
+        }
+    }
+
+    /// The current UTC time, in microseconds since the Matter epoch, if it was ever set
+    pub fn utc_time(&self) -> Option<u64> {
+        self.utc_time.get().map(|(utc_time, at)| {
+            let elapsed = (self.epoch)().saturating_sub(at);
+
+            utc_time + elapsed.as_micros() as u64
+        })
+    }
+
+    /// The current local time, in microseconds since the Matter epoch, if the UTC time
+    /// was ever set
+    pub fn local_time(&self) -> Option<u64> {
+        let utc_time = self.utc_time()?;
+
+        // The applicable time zone is the last one which became valid
+        let offset = self
+            .time_zones
+            .borrow()
+            .iter()
+            .filter(|tz| tz.valid_at <= utc_time)
+            .last()
+            .map(|tz| tz.offset)
+            .unwrap_or(0);
+
+        let dst_offset = self
+            .dst_offsets
+            .borrow()
+            .iter()
+            .find(|dst| {
+                dst.valid_starting <= utc_time
+                    && match dst.valid_until {
+                        Nullable::Null => true,
+                        Nullable::NotNull(until) => utc_time < until,
+                    }
+            })
+            .map(|dst| dst.offset)
+            .unwrap_or(0);
+
+        let offset = (offset as i64 + dst_offset as i64) * 1_000_000;
+
+        Some((utc_time as i64 + offset).max(0) as u64)
+    }
+
+    /// Set the UTC time, unless the device already has a time with a finer granularity
+    pub fn set_utc_time(
+        &self,
+        utc_time: u64,
+        granularity: Granularity,
+        time_source: Option<TimeSource>,
+    ) -> Result<(), Error> {
+        let current = self.granularity.get();
+
+        if current != Granularity::NoTimeGranularity && granularity < current {
+            Err(ErrorCode::ClusterStatus(StatusCode::TimeNotAccepted as _))?;
+        }
+
+        self.utc_time.set(Some((utc_time, (self.epoch)())));
+        self.granularity.set(granularity);
+        self.time_source
+            .set(time_source.unwrap_or(TimeSource::Admin));
+
+        self.rtc.set_utc_time(utc_time);
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
+            if attr.is_system() {
+                CLUSTER.read(attr.attr_id, writer)
+            } else {
+                match attr.attr_id.try_into()? {
+                    Attributes::UTCTime(codec) => {
+                        codec.encode(writer, Self::nullable(self.utc_time()))
+                    }
+                    Attributes::Granularity(codec) => {
+                        codec.encode(writer, self.granularity.get() as _)
+                    }
+                    Attributes::TimeSource(codec) => {
+                        codec.encode(writer, self.time_source.get() as _)
+                    }
+                    Attributes::TimeZone(_) => {
+                        writer.set_list(self.time_zones.borrow().iter().map(|tz| tz.as_struct()))
+                    }
+                    Attributes::DSTOffset(_) => writer.set_list(self.dst_offsets.borrow().iter()),
+                    Attributes::LocalTime(codec) => {
+                        codec.encode(writer, Self::nullable(self.local_time()))
+                    }
+                    Attributes::TimeZoneDatabase(codec) => {
+                        codec.encode(writer, TimeZoneDatabase::None as _)
+                    }
+                    Attributes::TimeZoneListMaxSize(codec) => {
+                        codec.encode(writer, TIME_ZONE_LIST_MAX_SIZE as _)
+                    }
+                    Attributes::DSTOffsetListMaxSize(codec) => {
+                        codec.encode(writer, DST_OFFSET_LIST_MAX_SIZE as _)
+                    }
+                }
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn invoke(
+        &self,
+        _exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        match cmd.cmd_id.try_into()? {
+            Commands::SetUTCTime => self.handle_command_setutctime(data)?,
+            Commands::SetTimeZone => self.handle_command_settimezone(data, encoder)?,
+            Commands::SetDSTOffset => self.handle_command_setdstoffset(data)?,
+        }
+
+        Ok(())
+    }
+
+    fn handle_command_setutctime(&self, data: &TLVElement) -> Result<(), Error> {
+        cmd_enter!("SetUTCTime");
+
+        let req = SetUTCTimeReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        self.set_utc_time(req.utc_time, req.granularity, req.time_source)
+    }
+
+    fn handle_command_settimezone(
+        &self,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        cmd_enter!("SetTimeZone");
+
+        let req = SetTimeZoneReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let mut time_zones = heapless::Vec::<_, TIME_ZONE_LIST_MAX_SIZE>::new();
+        for (index, tz) in req.time_zone.iter().enumerate() {
+            // The first time zone has to be valid right away
+            if !(MIN_TIME_ZONE_OFFSET..=MAX_TIME_ZONE_OFFSET).contains(&tz.offset)
+                || index == 0 && tz.valid_at != 0
+            {
+                Err(ErrorCode::ConstraintError)?;
+            }
+
+            time_zones
+                .push(TimeZone::from_struct(&tz)?)
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+        }
+
+        if time_zones.is_empty() {
+            Err(ErrorCode::ConstraintError)?;
+        }
+
+        *self.time_zones.borrow_mut() = time_zones;
+
+        // Without a time zone database, the DST offsets of the new time zones are not known
+        self.dst_offsets.borrow_mut().clear();
+        self.data_ver.changed();
+
+        encoder
+            .with_command(RespCommands::SetTimeZoneResponse as _)?
+            .set(SetTimeZoneResp {
+                dst_offset_required: true,
+            })
+    }
+
+    fn handle_command_setdstoffset(&self, data: &TLVElement) -> Result<(), Error> {
+        cmd_enter!("SetDSTOffset");
+
+        let req = SetDSTOffsetReq::from_tlv(data).map_err(Error::map_invalid_command)?;
+
+        let mut dst_offsets = heapless::Vec::<_, DST_OFFSET_LIST_MAX_SIZE>::new();
+        for dst in req.dst_offset.iter() {
+            if let Some(last) = dst_offsets.last() {
+                // The offsets have to be sorted and must not overlap; only the last one
+                // can be applicable indefinitely
+                match last.valid_until {
+                    Nullable::NotNull(until) if until <= dst.valid_starting => (),
+                    _ => Err(ErrorCode::ConstraintError)?,
+                }
+            }
+
+            dst_offsets
+                .push(dst)
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+        }
+
+        *self.dst_offsets.borrow_mut() = dst_offsets;
+        self.data_ver.changed();
+
+        Ok(())
+    }
+
+    fn nullable(value: Option<u64>) -> Nullable<u64> {
+        match value {
+            Some(value) => Nullable::NotNull(value),
+            None => Nullable::Null,
+        }
+    }
+}
+
+impl<'a> Handler for TimeSyncCluster<'a> {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        TimeSyncCluster::read(self, attr, encoder)
+    }
+
+    fn invoke(
+        &self,
+        exchange: &Exchange,
+        cmd: &CmdDetails,
+        data: &TLVElement,
+        encoder: CmdDataEncoder,
+    ) -> Result<(), Error> {
+        TimeSyncCluster::invoke(self, exchange, cmd, data, encoder)
+    }
+}
+
+impl<'a> NonBlockingHandler for TimeSyncCluster<'a> {}
+
+impl<'a> ChangeNotifier<()> for TimeSyncCluster<'a> {
+    fn consume_change(&mut self) -> Option<()> {
+        self.data_ver.consume_change(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::Cell, sync::atomic::Ordering};
+
+    use crate::{
+        data_model::objects::{AttrDataEncoder, AttrDetails, Node},
+        error::ErrorCode,
+        test_support::mock_epoch,
+        tlv::{get_root_node_struct, ElementType, TLVWriter},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{
+        AttributesDiscriminants, Granularity, RtcDriver, StatusCode, TimeSource, TimeSyncCluster,
+        ID,
+    };
+
+    mock_epoch!(MOCK_NOW_SECS, mock_epoch, secs);
+
+    #[derive(Default)]
+    struct MockRtc {
+        utc_time: Cell<Option<u64>>,
+    }
+
+    impl RtcDriver for MockRtc {
+        fn set_utc_time(&self, utc_time: u64) {
+            self.utc_time.set(Some(utc_time));
+        }
+    }
+
+    /// Read the UTCTime attribute, None standing for a Null value
+    fn read_utc_time(cluster: &TimeSyncCluster) -> Option<u64> {
+        let attr = AttrDetails {
+            node: &Node {
+                id: 0,
+                endpoints: &[],
+            },
+            endpoint_id: 0,
+            cluster_id: ID,
+            attr_id: AttributesDiscriminants::UTCTime as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .unwrap();
+
+        let root = get_root_node_struct(writebuf.as_slice()).unwrap();
+        let data = root.find_tag(1).unwrap().find_tag(2).unwrap();
+
+        match data.get_element_type() {
+            ElementType::Null => None,
+            _ => Some(data.u64().unwrap()),
+        }
+    }
+
+    #[test]
+    /// The UTC time is Null until it is set, and then advances with the clock
+    fn utc_time_null_before_set() {
+        MOCK_NOW_SECS.store(100, Ordering::SeqCst);
+
+        let rtc = MockRtc::default();
+        let cluster = TimeSyncCluster::new(&rtc, mock_epoch, dummy_rand);
+
+        assert_eq!(read_utc_time(&cluster), None);
+        assert_eq!(cluster.local_time(), None);
+
+        cluster
+            .set_utc_time(5_000_000, Granularity::SecondsGranularity, None)
+            .unwrap();
+        assert_eq!(rtc.utc_time.get(), Some(5_000_000));
+        assert_eq!(cluster.time_source.get(), TimeSource::Admin);
+        assert_eq!(read_utc_time(&cluster), Some(5_000_000));
+
+        MOCK_NOW_SECS.store(102, Ordering::SeqCst);
+        assert_eq!(read_utc_time(&cluster), Some(7_000_000));
+    }
+
+    #[test]
+    /// A UTC time coarser than the current one is not accepted
+    fn coarser_granularity_rejected() {
+        let rtc = MockRtc::default();
+        let cluster = TimeSyncCluster::new(&rtc, mock_epoch, dummy_rand);
+
+        cluster
+            .set_utc_time(1_000_000, Granularity::MillisecondsGranularity, None)
+            .unwrap();
+
+        let result = cluster.set_utc_time(9_000_000, Granularity::MinutesGranularity, None);
+        assert_eq!(
+            result.map_err(|e| e.code()),
+            Err(ErrorCode::ClusterStatus(StatusCode::TimeNotAccepted as _))
+        );
+        assert_eq!(rtc.utc_time.get(), Some(1_000_000));
+        assert_eq!(
+            cluster.granularity.get(),
+            Granularity::MillisecondsGranularity
+        );
+
+        // The same or a finer granularity is accepted
+        cluster
+            .set_utc_time(2_000_000, Granularity::MillisecondsGranularity, None)
+            .unwrap();
+        cluster
+            .set_utc_time(3_000_000, Granularity::MicrosecondsGranularity, None)
+            .unwrap();
+        assert_eq!(rtc.utc_time.get(), Some(3_000_000));
+    }
+}
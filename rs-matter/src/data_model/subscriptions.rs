@@ -0,0 +1,999 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::{cell::RefCell, time::Duration};
+
+use embassy_futures::select::select;
+use log::info;
+
+use crate::{
+    data_model::{
+        events::Event,
+        objects::{AttrId, ClusterId, EndptId},
+    },
+    error::{Error, ErrorCode},
+    fabric,
+    interaction_model::messages::msg::SubscribeReq,
+    tlv::{get_root_node_struct, FromTLV, TLVWriter, TagType, ToTLV},
+    utils::{clock::Clock, select::Notification, timer::Timer, writebuf::WriteBuf},
+};
+
+/// The minimum number of subscriptions the spec requires us to support per fabric
+pub const SUBSCRIPTIONS_PER_FABRIC: usize = 3;
+
+pub const MAX_SUBSCRIPTIONS: usize = SUBSCRIPTIONS_PER_FABRIC * fabric::MAX_SUPPORTED_FABRICS;
+
+/// The upper bound (in seconds) that we impose on the max interval if the
+/// client asked for something higher
+pub const SUBSCRIPTION_MAX_INTERVAL_PUBLISHER_LIMIT: u16 = 3600;
+
+/// The maximum size of the TLV-encoded subscribe request that we keep around, so as
+/// to be able to build the subsequent reports of a subscription
+pub const MAX_SUBSCRIBE_REQ_LEN: usize = 256;
+
+/// The maximum number of changed paths tracked per subscription, before falling back
+/// to reporting all subscribed attributes
+pub const MAX_DIRTY_PATHS: usize = 8;
+
+/// The maximum size of the TLV-encoded value of an attribute, for its changes to be
+/// tracked by value
+const MAX_VALUE_LEN: usize = 64;
+
+/// A changed attribute, or - without an attribute ID - all attributes of a cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyPath {
+    pub endpoint: EndptId,
+    pub cluster: ClusterId,
+    pub attr: Option<AttrId>,
+}
+
+impl DirtyPath {
+    fn contains(&self, other: &DirtyPath) -> bool {
+        self.endpoint == other.endpoint
+            && self.cluster == other.cluster
+            && (self.attr.is_none() || self.attr == other.attr)
+    }
+}
+
+/// The attributes which changed since the last report of a subscription
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirtySet {
+    paths: heapless::Vec<DirtyPath, MAX_DIRTY_PATHS>,
+    /// Set when more paths changed than we can track, in which case everything is dirty
+    all: bool,
+}
+
+impl DirtySet {
+    pub const fn new() -> Self {
+        Self {
+            paths: heapless::Vec::new(),
+            all: false,
+        }
+    }
+
+    pub fn mark(&mut self, path: DirtyPath) {
+        if self.all || self.paths.iter().any(|p| p.contains(&path)) {
+            return;
+        }
+
+        self.paths.retain(|p| !path.contains(p));
+
+        if self.paths.push(path).is_err() {
+            self.paths.clear();
+            self.all = true;
+        }
+    }
+
+    pub fn is_dirty(&self, endpoint: EndptId, cluster: ClusterId, attr: AttrId) -> bool {
+        let path = DirtyPath {
+            endpoint,
+            cluster,
+            attr: Some(attr),
+        };
+
+        self.all || self.paths.iter().any(|p| p.contains(&path))
+    }
+
+    /// Drop a path marked earlier, because it changed back to its reported value.
+    /// Does nothing if the path is only dirty as part of its cluster.
+    pub fn unmark(&mut self, path: &DirtyPath) {
+        self.paths.retain(|p| p != path);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.paths.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.paths.clear();
+        self.all = false;
+    }
+}
+
+/// The value of a subscribed attribute, tracked as a fingerprint of its TLV encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttrValue {
+    pub path: DirtyPath,
+    /// The value which went with the last report, if known
+    pub reported: Option<u32>,
+    /// The value as of the latest change
+    pub current: u32,
+}
+
+/// An active subscription, along with the intervals negotiated with the subscriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub id: u32,
+    pub fab_idx: u8,
+    pub peer_node_id: u64,
+    /// The local ID of the session on which the subscription was established
+    pub sess_id: u16,
+    pub min_int_floor: u16,
+    pub max_int: u16,
+    /// When the last report (including the priming one) was sent, as per the clock
+    /// of the subscription manager
+    pub last_report: Duration,
+    /// The subscribed attributes which changed since the last report
+    pub dirty: DirtySet,
+    /// The values of the subscribed attributes whose changes are tracked by value
+    pub values: heapless::Vec<AttrValue, MAX_DIRTY_PATHS>,
+    /// The lowest event number which is yet to be reported
+    pub event_from: u64,
+    /// Whether subscribed events were logged since the last report
+    pub events_pending: bool,
+    /// Whether any of these events is urgent, and thus to be reported right away
+    pub urgent: bool,
+    /// The TLV-encoded subscribe request which established the subscription
+    pub req: heapless::Vec<u8, MAX_SUBSCRIBE_REQ_LEN>,
+}
+
+impl Subscription {
+    /// The subscribe request which established the subscription
+    pub fn req(&self) -> Result<SubscribeReq<'_>, Error> {
+        SubscribeReq::from_tlv(&get_root_node_struct(&self.req)?)
+    }
+
+    /// Whether a report has to be sent now, either because a subscribed attribute
+    /// changed or a subscribed event was logged and the min interval elapsed, because
+    /// an urgent event was logged, or because the max interval elapsed
+    pub fn is_report_pending(&self, now: Duration) -> bool {
+        self.urgent
+            || (!self.dirty.is_empty() || self.events_pending) && self.min_int_elapsed(now)
+            || self.is_report_due(now)
+    }
+
+    /// Record the new value of an attribute, which is only dirty if it differs from
+    /// the value of the last report
+    fn mark_value(&mut self, path: DirtyPath, fingerprint: u32) {
+        let value = match self.values.iter().position(|v| v.path == path) {
+            Some(index) => &mut self.values[index],
+            None => {
+                let value = AttrValue {
+                    path,
+                    reported: None,
+                    current: fingerprint,
+                };
+
+                if self.values.push(value).is_err() {
+                    // Too many values to track, so any change is reported
+                    self.dirty.mark(path);
+                    return;
+                }
+
+                self.values.last_mut().unwrap()
+            }
+        };
+
+        value.current = fingerprint;
+
+        if value.reported == Some(fingerprint) {
+            self.dirty.unmark(&path);
+        } else {
+            self.dirty.mark(path);
+        }
+    }
+
+    /// Whether the subscription requests the provided event
+    fn covers_event(&self, event: &Event) -> bool {
+        let Ok(req) = self.req() else {
+            return false;
+        };
+
+        let Some(paths) = &req.event_requests else {
+            return false;
+        };
+
+        paths.iter().any(|path| event.matches(&path))
+    }
+
+    /// Whether the subscription intersects with the provided path
+    fn covers(&self, path: &DirtyPath) -> bool {
+        let Ok(req) = self.req() else {
+            return false;
+        };
+
+        let Some(paths) = &req.attr_requests else {
+            return false;
+        };
+
+        paths.iter().any(|p| {
+            p.endpoint.map(|e| e == path.endpoint).unwrap_or(true)
+                && p.cluster.map(|c| c == path.cluster).unwrap_or(true)
+                && p.attr
+                    .zip(path.attr)
+                    .map(|(a1, a2)| a1 == a2)
+                    .unwrap_or(true)
+        })
+    }
+
+    /// Whether enough time passed since the last report for a new one to be sent
+    pub fn min_int_elapsed(&self, now: Duration) -> bool {
+        now >= self.last_report + Duration::from_secs(self.min_int_floor as _)
+    }
+
+    /// Whether the subscription reached its max interval without a report, so that
+    /// one has to be sent now to keep it alive
+    pub fn is_report_due(&self, now: Duration) -> bool {
+        now >= self.last_report + Duration::from_secs(self.max_int as _)
+    }
+
+    /// When the next report is to be sent, as per the clock of the subscription manager,
+    /// unless something changes meanwhile
+    pub fn next_report_at(&self) -> Duration {
+        let max_int = self.last_report + Duration::from_secs(self.max_int as _);
+
+        if self.urgent {
+            self.last_report
+        } else if !self.dirty.is_empty() || self.events_pending {
+            max_int.min(self.last_report + Duration::from_secs(self.min_int_floor as _))
+        } else {
+            max_int
+        }
+    }
+}
+
+/// A snapshot of an active subscription, as exposed to the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionInfo {
+    pub id: u32,
+    pub fab_idx: u8,
+    pub peer_node_id: u64,
+    pub max_int: u16,
+}
+
+impl From<&Subscription> for SubscriptionInfo {
+    fn from(sub: &Subscription) -> Self {
+        Self {
+            id: sub.id,
+            fab_idx: sub.fab_idx,
+            peer_node_id: sub.peer_node_id,
+            max_int: sub.max_int,
+        }
+    }
+}
+
+pub struct SubscriptionMgr {
+    clock: &'static dyn Clock,
+    next_id: u32,
+    evict_oldest: bool,
+    /// The event number following the last event we were notified of
+    next_event: u64,
+    /// Kept in the order in which the subscriptions were established
+    subscriptions: heapless::Vec<Subscription, MAX_SUBSCRIPTIONS>,
+}
+
+impl SubscriptionMgr {
+    #[inline(always)]
+    pub const fn new(clock: &'static dyn Clock) -> Self {
+        Self {
+            clock,
+            next_id: 1,
+            evict_oldest: false,
+            next_event: 0,
+            subscriptions: heapless::Vec::new(),
+        }
+    }
+
+    /// Whether a new subscription on a fabric which already has `SUBSCRIPTIONS_PER_FABRIC`
+    /// subscriptions evicts the oldest of these, rather than being rejected
+    pub fn set_evict_oldest(&mut self, evict_oldest: bool) {
+        self.evict_oldest = evict_oldest;
+    }
+
+    pub fn reset(&mut self) {
+        self.subscriptions.clear();
+    }
+
+    /// Register a new subscription for the provided request and return it.
+    ///
+    /// Unless the request asks to keep them, all other subscriptions of the same
+    /// peer on the same fabric are removed, as per the spec.
+    pub fn add(
+        &mut self,
+        fab_idx: u8,
+        peer_node_id: u64,
+        sess_id: u16,
+        req: &SubscribeReq,
+    ) -> Result<Subscription, Error> {
+        if !req.keep_subs {
+            self.remove_for_peer(fab_idx, peer_node_id);
+        }
+
+        let mut fabric_subs = self
+            .subscriptions
+            .iter()
+            .filter(|sub| sub.fab_idx == fab_idx);
+
+        if fabric_subs.clone().count() >= SUBSCRIPTIONS_PER_FABRIC {
+            if !self.evict_oldest {
+                Err(ErrorCode::ResourceExhausted)?;
+            }
+
+            let oldest = fabric_subs.next().unwrap().id;
+
+            info!("Evicting subscription {} of fabric {}", oldest, fab_idx);
+            self.remove(oldest);
+        }
+
+        let mut buf = [0; MAX_SUBSCRIBE_REQ_LEN];
+        let mut wb = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut wb);
+
+        req.to_tlv(&mut tw, TagType::Anonymous)
+            .map_err(|_| ErrorCode::ResourceExhausted)?;
+
+        let subscription = Subscription {
+            id: self.next_id(),
+            fab_idx,
+            peer_node_id,
+            sess_id,
+            min_int_floor: req.min_int_floor,
+            max_int: Self::negotiate_max_int(req.min_int_floor, req.max_int_ceil),
+            last_report: self.clock.now(),
+            dirty: DirtySet::new(),
+            values: heapless::Vec::new(),
+            // The events logged so far go with the priming report
+            event_from: self.next_event,
+            events_pending: false,
+            urgent: false,
+            req: heapless::Vec::from_slice(wb.as_slice()).unwrap(),
+        };
+
+        self.subscriptions
+            .push(subscription.clone())
+            .map_err(|_| ErrorCode::ResourceExhausted)?;
+
+        info!(
+            "Added subscription {}, intervals: {}s - {}s",
+            subscription.id, subscription.min_int_floor, subscription.max_int
+        );
+
+        Ok(subscription)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Subscription> {
+        self.subscriptions.iter().find(|sub| sub.id == id)
+    }
+
+    /// Return a snapshot of the subscription, for building its next report, and drain
+    /// its dirty attributes, so that the ones changing meanwhile go to the report after
+    pub fn start_report(&mut self, id: u32) -> Option<Subscription> {
+        let sub = self.subscriptions.iter_mut().find(|sub| sub.id == id)?;
+
+        let snapshot = sub.clone();
+
+        for value in sub.values.iter_mut() {
+            let path = value.path;
+
+            if sub
+                .dirty
+                .is_dirty(path.endpoint, path.cluster, path.attr.unwrap_or_default())
+            {
+                value.reported = Some(value.current);
+            }
+        }
+
+        sub.dirty.clear();
+        sub.event_from = self.next_event;
+        sub.events_pending = false;
+        sub.urgent = false;
+
+        Some(snapshot)
+    }
+
+    /// Record that a report was just sent for the subscription, which restarts its intervals
+    pub fn report_sent(&mut self, id: u32) {
+        let now = self.clock.now();
+
+        if let Some(sub) = self.subscriptions.iter_mut().find(|sub| sub.id == id) {
+            sub.last_report = now;
+        }
+    }
+
+    /// Record that an attribute changed, so that all subscriptions covering it
+    /// get it reported, once their min interval elapses.
+    ///
+    /// Returns `true` if any subscription is affected by the change.
+    pub fn mark_dirty(&mut self, endpoint: EndptId, cluster: ClusterId, attr: AttrId) -> bool {
+        self.mark(DirtyPath {
+            endpoint,
+            cluster,
+            attr: Some(attr),
+        })
+    }
+
+    /// Same as `mark_dirty`, but with the new value of the attribute, so that changes
+    /// which cancel each other out before the next report do not trigger a report.
+    ///
+    /// Values which cannot be encoded in `MAX_VALUE_LEN` bytes are always reported.
+    pub fn mark_value(
+        &mut self,
+        endpoint: EndptId,
+        cluster: ClusterId,
+        attr: AttrId,
+        value: &dyn ToTLV,
+    ) -> bool {
+        let path = DirtyPath {
+            endpoint,
+            cluster,
+            attr: Some(attr),
+        };
+
+        let Some(fingerprint) = Self::fingerprint(value) else {
+            return self.mark(path);
+        };
+
+        let mut affected = false;
+
+        for sub in self.subscriptions.iter_mut() {
+            if sub.covers(&path) {
+                sub.mark_value(path, fingerprint);
+                affected = true;
+            }
+        }
+
+        affected
+    }
+
+    /// Same as `mark_dirty`, but for all attributes of the provided cluster
+    pub fn notify_change(&mut self, endpoint: EndptId, cluster: ClusterId) -> bool {
+        self.mark(DirtyPath {
+            endpoint,
+            cluster,
+            attr: None,
+        })
+    }
+
+    fn mark(&mut self, path: DirtyPath) -> bool {
+        let mut affected = false;
+
+        for sub in self.subscriptions.iter_mut() {
+            if sub.covers(&path) {
+                sub.dirty.mark(path);
+                affected = true;
+            }
+        }
+
+        affected
+    }
+
+    /// Record that an event was logged, so that all subscriptions requesting it
+    /// get it reported, once their min interval elapses or - for an urgent event -
+    /// right away.
+    ///
+    /// Returns `true` if any subscription is affected by the event.
+    pub fn notify_event(&mut self, event: &Event) -> bool {
+        self.next_event = self.next_event.max(event.number + 1);
+
+        let mut affected = false;
+
+        for sub in self.subscriptions.iter_mut() {
+            if sub.covers_event(event) {
+                sub.events_pending = true;
+                sub.urgent |= event.urgent;
+                affected = true;
+            }
+        }
+
+        affected
+    }
+
+    /// The IDs of all subscriptions for which a report is to be sent now
+    pub fn pending(&self) -> heapless::Vec<u32, MAX_SUBSCRIPTIONS> {
+        let now = self.clock.now();
+
+        self.subscriptions
+            .iter()
+            .filter(|sub| sub.is_report_pending(now))
+            .map(|sub| sub.id)
+            .collect()
+    }
+
+    /// When the next report of any of the subscriptions is to be sent, as per the clock
+    pub fn next_report_at(&self) -> Option<Duration> {
+        self.subscriptions
+            .iter()
+            .map(Subscription::next_report_at)
+            .min()
+    }
+
+    /// The IDs of all subscriptions which reached their max interval and thus
+    /// have to be resumed with a new report
+    pub fn due(&self) -> heapless::Vec<u32, MAX_SUBSCRIPTIONS> {
+        let now = self.clock.now();
+
+        self.subscriptions
+            .iter()
+            .filter(|sub| sub.is_report_due(now))
+            .map(|sub| sub.id)
+            .collect()
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Subscription> {
+        let index = self.subscriptions.iter().position(|sub| sub.id == id)?;
+
+        Some(self.subscriptions.remove(index))
+    }
+
+    /// Remove all subscriptions that were established on the session with
+    /// the provided local session ID. To be called when the session goes away.
+    pub fn evict_session(&mut self, sess_id: u16) {
+        self.subscriptions.retain(|sub| sub.sess_id != sess_id);
+    }
+
+    pub fn remove_for_fabric(&mut self, fab_idx: u8) {
+        self.subscriptions.retain(|sub| sub.fab_idx != fab_idx);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Subscription> {
+        self.subscriptions.iter()
+    }
+
+    /// Return a snapshot of all active subscriptions, so that the caller does not
+    /// have to keep the manager borrowed while going over them
+    pub fn infos(&self) -> heapless::Vec<SubscriptionInfo, MAX_SUBSCRIPTIONS> {
+        self.subscriptions
+            .iter()
+            .map(SubscriptionInfo::from)
+            .collect()
+    }
+
+    fn remove_for_peer(&mut self, fab_idx: u8, peer_node_id: u64) {
+        self.subscriptions
+            .retain(|sub| sub.fab_idx != fab_idx || sub.peer_node_id != peer_node_id);
+    }
+
+    fn next_id(&mut self) -> u32 {
+        loop {
+            let id = self.next_id;
+
+            self.next_id = self.next_id.overflowing_add(1).0;
+            if self.next_id == 0 {
+                self.next_id = 1;
+            }
+
+            if self.get(id).is_none() {
+                break id;
+            }
+        }
+    }
+
+    /// The FNV-1a hash of the TLV encoding of the value
+    fn fingerprint(value: &dyn ToTLV) -> Option<u32> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        let mut wb = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut wb);
+
+        value.to_tlv(&mut tw, TagType::Anonymous).ok()?;
+
+        Some(wb.as_slice().iter().fold(0x811c9dc5, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x01000193)
+        }))
+    }
+
+    /// The max interval has to be at least the min interval floor, and we honour the
+    /// ceiling requested by the client, unless it is above our own limit
+    fn negotiate_max_int(min_int_floor: u16, max_int_ceil: u16) -> u16 {
+        max_int_ceil
+            .min(SUBSCRIPTION_MAX_INTERVAL_PUBLISHER_LIMIT)
+            .max(min_int_floor)
+    }
+}
+
+/// Wait until reports are to be sent for some of the subscriptions, and return their IDs.
+///
+/// The next interval of the subscriptions is awaited with the provided timer, and the
+/// notification cuts the wait short whenever subscribed attributes change or events get logged.
+pub async fn wait_pending<T>(
+    mgr: &RefCell<SubscriptionMgr>,
+    notification: &Notification,
+    timer: &T,
+) -> heapless::Vec<u32, MAX_SUBSCRIPTIONS>
+where
+    T: Timer,
+{
+    loop {
+        let (pending, wait) = {
+            let mgr = mgr.borrow();
+            let now = mgr.clock.now();
+
+            (
+                mgr.pending(),
+                mgr.next_report_at().map(|at| at.saturating_sub(now)),
+            )
+        };
+
+        if !pending.is_empty() {
+            break pending;
+        }
+
+        if let Some(wait) = wait {
+            select(notification.wait(), timer.after(wait)).await;
+        } else {
+            notification.wait().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        cell::{Cell, RefCell},
+        future::Future,
+        pin::{pin, Pin},
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use embassy_futures::poll_once;
+
+    use crate::{
+        error::ErrorCode,
+        interaction_model::messages::{ib::AttrPath, msg::SubscribeReq, GenericPath},
+        utils::{
+            clock::{Clock, DummyClock, MockClock},
+            select::Notification,
+            timer::Timer,
+        },
+    };
+
+    use super::{
+        wait_pending, SubscriptionMgr, SUBSCRIPTIONS_PER_FABRIC,
+        SUBSCRIPTION_MAX_INTERVAL_PUBLISHER_LIMIT,
+    };
+
+    #[test]
+    fn test_max_int_negotiation() {
+        let mut mgr = SubscriptionMgr::new(&DummyClock);
+
+        let sub = mgr.add(1, 10, 1, &SubscribeReq::new(true, 1, 60)).unwrap();
+        assert_eq!(sub.max_int, 60);
+
+        let sub = mgr
+            .add(1, 11, 1, &SubscribeReq::new(true, 1, 7200))
+            .unwrap();
+        assert_eq!(sub.max_int, SUBSCRIPTION_MAX_INTERVAL_PUBLISHER_LIMIT);
+
+        let sub = mgr.add(1, 12, 1, &SubscribeReq::new(true, 30, 10)).unwrap();
+        assert_eq!(sub.max_int, 30);
+    }
+
+    #[test]
+    fn test_ids_and_lifecycle() {
+        let mut mgr = SubscriptionMgr::new(&DummyClock);
+
+        let sub1 = mgr.add(1, 10, 1, &SubscribeReq::new(true, 1, 60)).unwrap();
+        let sub2 = mgr.add(2, 20, 2, &SubscribeReq::new(true, 1, 60)).unwrap();
+        assert_eq!(sub1.id, 1);
+        assert_eq!(sub2.id, 2);
+
+        // Not keeping the subscriptions evicts the older ones of the same peer only
+        let sub3 = mgr.add(1, 10, 1, &SubscribeReq::new(true, 1, 60)).unwrap();
+        assert_eq!(sub3.id, 3);
+        assert!(mgr.get(sub1.id).is_none());
+        assert!(mgr.get(sub2.id).is_some());
+
+        mgr.evict_session(2);
+        assert!(mgr.get(sub2.id).is_none());
+        assert!(mgr.get(sub3.id).is_some());
+
+        assert_eq!(mgr.remove(sub3.id), Some(sub3));
+        assert_eq!(mgr.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_report_due_after_max_int() {
+        static CLOCK: MockClock = MockClock::new(100_000);
+
+        let mut mgr = SubscriptionMgr::new(&CLOCK);
+
+        let sub = mgr.add(1, 10, 1, &SubscribeReq::new(true, 5, 60)).unwrap();
+        assert!(mgr.due().is_empty());
+
+        CLOCK.advance(Duration::from_secs(4));
+        assert!(!mgr.get(sub.id).unwrap().min_int_elapsed(CLOCK.now()));
+
+        CLOCK.set(Duration::from_secs(159));
+        assert!(mgr.get(sub.id).unwrap().min_int_elapsed(CLOCK.now()));
+        assert!(mgr.due().is_empty());
+
+        // Just past the max interval, the subscription has to be resumed with a new report
+        CLOCK.advance(Duration::from_millis(1001));
+        assert_eq!(mgr.due().as_slice(), &[sub.id]);
+
+        // Reporting restarts the intervals
+        mgr.report_sent(sub.id);
+        assert!(mgr.due().is_empty());
+
+        CLOCK.advance(Duration::from_secs(60));
+        assert_eq!(mgr.due().as_slice(), &[sub.id]);
+    }
+
+    #[test]
+    fn test_change_reported_after_min_int() {
+        static CLOCK: MockClock = MockClock::new(100_000);
+
+        let mut mgr = SubscriptionMgr::new(&CLOCK);
+
+        let paths = [AttrPath::new(&GenericPath::new(Some(1), Some(6), None))];
+        let sub = mgr
+            .add(
+                1,
+                10,
+                1,
+                &SubscribeReq::new(true, 5, 60).set_attr_requests(&paths),
+            )
+            .unwrap();
+
+        // A change is not reported sooner than the min interval
+        CLOCK.set(Duration::from_secs(101));
+        assert!(mgr.notify_change(1, 6));
+        assert!(mgr.pending().is_empty());
+
+        CLOCK.set(Duration::from_secs(104));
+        assert!(mgr.pending().is_empty());
+
+        CLOCK.set(Duration::from_secs(105));
+        assert_eq!(mgr.pending().as_slice(), &[sub.id]);
+
+        let report = mgr.start_report(sub.id).unwrap();
+        assert!(report.dirty.is_dirty(1, 6, 0));
+        mgr.report_sent(sub.id);
+        assert!(mgr.pending().is_empty());
+
+        // Changes outside of the subscribed paths are not reported
+        CLOCK.set(Duration::from_secs(111));
+        assert!(!mgr.notify_change(1, 8));
+        assert!(!mgr.notify_change(0, 6));
+        assert!(mgr.pending().is_empty());
+
+        // Without changes, the report is only sent after the max interval
+        CLOCK.set(Duration::from_secs(165));
+        assert_eq!(mgr.pending().as_slice(), &[sub.id]);
+    }
+
+    #[test]
+    fn test_rapid_changes_coalesced() {
+        static CLOCK: MockClock = MockClock::new(100_000);
+
+        let mut mgr = SubscriptionMgr::new(&CLOCK);
+
+        let paths = [AttrPath::new(&GenericPath::new(Some(1), Some(8), None))];
+        let sub = mgr
+            .add(
+                1,
+                10,
+                1,
+                &SubscribeReq::new(true, 10, 60).set_attr_requests(&paths),
+            )
+            .unwrap();
+
+        // Three changes within the min interval
+        for (secs, level) in [(101, 10u8), (102, 20), (103, 30)] {
+            CLOCK.set(Duration::from_secs(secs));
+            assert!(mgr.mark_value(1, 8, 0, &level));
+            assert!(mgr.pending().is_empty());
+        }
+
+        // ... go with a single report, once the min interval elapsed
+        CLOCK.set(Duration::from_secs(110));
+        assert_eq!(mgr.pending().as_slice(), &[sub.id]);
+
+        let report = mgr.start_report(sub.id).unwrap();
+        assert!(report.dirty.is_dirty(1, 8, 0));
+        mgr.report_sent(sub.id);
+
+        assert!(mgr.pending().is_empty());
+        assert_eq!(
+            mgr.get(sub.id).unwrap().values[0].reported,
+            Some(report.values[0].current)
+        );
+
+        CLOCK.set(Duration::from_secs(125));
+        assert!(mgr.pending().is_empty());
+    }
+
+    #[test]
+    fn test_no_net_change_suppressed() {
+        static CLOCK: MockClock = MockClock::new(100_000);
+
+        let mut mgr = SubscriptionMgr::new(&CLOCK);
+
+        let paths = [AttrPath::new(&GenericPath::new(Some(1), Some(8), None))];
+        let sub = mgr
+            .add(
+                1,
+                10,
+                1,
+                &SubscribeReq::new(true, 10, 60).set_attr_requests(&paths),
+            )
+            .unwrap();
+
+        CLOCK.set(Duration::from_secs(101));
+        mgr.mark_value(1, 8, 0, &10u8);
+
+        CLOCK.set(Duration::from_secs(110));
+        mgr.start_report(sub.id).unwrap();
+        mgr.report_sent(sub.id);
+
+        // A change and a change back to the reported value within the min interval
+        CLOCK.set(Duration::from_secs(111));
+        mgr.mark_value(1, 8, 0, &20u8);
+        CLOCK.set(Duration::from_secs(112));
+        mgr.mark_value(1, 8, 0, &10u8);
+
+        CLOCK.set(Duration::from_secs(120));
+        assert!(mgr.pending().is_empty());
+
+        // Changes which end up with another value are reported
+        CLOCK.set(Duration::from_secs(121));
+        mgr.mark_value(1, 8, 0, &20u8);
+        CLOCK.set(Duration::from_secs(122));
+        mgr.mark_value(1, 8, 0, &30u8);
+
+        CLOCK.set(Duration::from_secs(123));
+        assert_eq!(mgr.pending().as_slice(), &[sub.id]);
+    }
+
+    /// A timer which fires once the clock of the test reaches the deadline,
+    /// recording the duration it was asked to wait
+    struct MockTimer {
+        clock: &'static MockClock,
+        requested: Cell<Option<Duration>>,
+    }
+
+    impl MockTimer {
+        const fn new(clock: &'static MockClock) -> Self {
+            Self {
+                clock,
+                requested: Cell::new(None),
+            }
+        }
+    }
+
+    struct MockTimerFuture {
+        clock: &'static MockClock,
+        at: Duration,
+    }
+
+    impl Future for MockTimerFuture {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+            if self.clock.now() >= self.at {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    impl Timer for MockTimer {
+        type Future = MockTimerFuture;
+
+        fn after(&self, duration: Duration) -> Self::Future {
+            self.requested.set(Some(duration));
+
+            MockTimerFuture {
+                clock: self.clock,
+                at: self.clock.now() + duration,
+            }
+        }
+    }
+
+    #[test]
+    fn test_report_awaits_max_int_timer() {
+        static CLOCK: MockClock = MockClock::new(100_000);
+
+        let mgr = RefCell::new(SubscriptionMgr::new(&CLOCK));
+        let notification = Notification::new();
+        let timer = MockTimer::new(&CLOCK);
+
+        let mut wait = pin!(wait_pending(&mgr, &notification, &timer));
+
+        // Without subscriptions, there is nothing to time
+        assert!(poll_once(&mut wait).is_pending());
+        assert_eq!(timer.requested.get(), None);
+
+        let sub = mgr
+            .borrow_mut()
+            .add(1, 10, 1, &SubscribeReq::new(true, 1, 60))
+            .unwrap();
+        notification.signal(());
+
+        // Nothing changed, so the wait is for the max interval
+        assert!(poll_once(&mut wait).is_pending());
+        assert_eq!(timer.requested.get(), Some(Duration::from_secs(60)));
+
+        CLOCK.set(Duration::from_secs(159));
+        assert!(poll_once(&mut wait).is_pending());
+
+        CLOCK.set(Duration::from_secs(160));
+        match poll_once(&mut wait) {
+            Poll::Ready(pending) => assert_eq!(pending.as_slice(), &[sub.id]),
+            Poll::Pending => panic!("The report is not pending after the max interval"),
+        }
+    }
+
+    fn keep_subs_req() -> SubscribeReq<'static> {
+        let mut req = SubscribeReq::new(true, 1, 60);
+        req.keep_subs = true;
+
+        req
+    }
+
+    #[test]
+    fn test_fabric_limit() {
+        let mut mgr = SubscriptionMgr::new(&DummyClock);
+
+        for peer in 0..SUBSCRIPTIONS_PER_FABRIC as u64 {
+            assert!(mgr.add(1, 10 + peer, 1, &keep_subs_req()).is_ok());
+        }
+
+        assert_eq!(
+            mgr.add(1, 20, 1, &keep_subs_req()).map_err(|e| e.code()),
+            Err(ErrorCode::ResourceExhausted)
+        );
+        assert_eq!(mgr.iter().count(), SUBSCRIPTIONS_PER_FABRIC);
+
+        // The other fabrics have their own share
+        assert!(mgr.add(2, 20, 2, &keep_subs_req()).is_ok());
+    }
+
+    #[test]
+    fn test_fabric_limit_eviction() {
+        let mut mgr = SubscriptionMgr::new(&DummyClock);
+        mgr.set_evict_oldest(true);
+
+        let oldest = mgr.add(1, 10, 1, &keep_subs_req()).unwrap();
+        let other = mgr.add(2, 10, 2, &keep_subs_req()).unwrap();
+
+        for peer in 1..SUBSCRIPTIONS_PER_FABRIC as u64 {
+            mgr.add(1, 10 + peer, 1, &keep_subs_req()).unwrap();
+        }
+
+        let newest = mgr.add(1, 20, 1, &keep_subs_req()).unwrap();
+
+        assert!(mgr.get(oldest.id).is_none());
+        assert!(mgr.get(newest.id).is_some());
+        assert!(mgr.get(other.id).is_some());
+        assert_eq!(
+            mgr.iter().filter(|sub| sub.fab_idx == 1).count(),
+            SUBSCRIPTIONS_PER_FABRIC
+        );
+    }
+}
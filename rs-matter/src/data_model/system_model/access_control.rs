@@ -21,15 +21,20 @@ use core::convert::TryInto;
 use strum::{EnumDiscriminants, FromRepr};
 
 use crate::acl::{self, AclEntry, AclMgr};
+use crate::data_model::events::{EventLogger, EventPriority};
 use crate::data_model::objects::*;
+use crate::fabric::MAX_SUPPORTED_FABRICS;
 use crate::interaction_model::messages::ib::{attr_list_write, ListOperation};
-use crate::tlv::{FromTLV, TLVElement, TagType, ToTLV};
+use crate::tlv::{FromTLV, Nullable, OctetStr, TLVElement, ToTLV};
 use crate::utils::rand::Rand;
 use crate::{attribute_enum, error::*};
 use log::{error, info};
 
 pub const ID: u32 = 0x001F;
 
+/// The maximum length of the data of an extension
+pub const MAX_EXTENSION_DATA_LEN: usize = 128;
+
 #[derive(FromRepr, EnumDiscriminants)]
 #[repr(u16)]
 pub enum Attributes {
@@ -42,8 +47,24 @@ pub enum Attributes {
 
 attribute_enum!(Attributes);
 
+#[derive(FromRepr)]
+#[repr(u32)]
+pub enum Events {
+    AccessControlEntryChanged = 0x00,
+    AccessControlExtensionChanged = 0x01,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ChangeType {
+    Changed = 0,
+    Added = 1,
+    Removed = 2,
+}
+
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID,
+    revision: 1,
     feature_map: 0,
     attributes: &[
         FEATURE_MAP,
@@ -75,47 +96,87 @@ pub const CLUSTER: Cluster<'static> = Cluster {
         ),
     ],
     commands: &[],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
 };
 
+#[derive(FromTLV, ToTLV, Debug, Clone, PartialEq)]
+#[tlvargs(lifetime = "'a", start = 1)]
+pub struct AclExtension<'a> {
+    pub data: OctetStr<'a>,
+    #[tagval(0xFE)]
+    pub fab_idx: Option<u8>,
+}
+
+// TODO: The node ID or the passcode ID of the administrator making the change
+// is not available to the handlers yet, so both are reported as null
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
+struct EntryChangedEvent<'a> {
+    admin_node_id: Nullable<u64>,
+    admin_passcode_id: Nullable<u16>,
+    change_type: u8,
+    latest_value: Nullable<&'a AclEntry>,
+    #[tagval(0xFE)]
+    fab_idx: u8,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
+struct ExtensionChangedEvent<'a> {
+    admin_node_id: Nullable<u64>,
+    admin_passcode_id: Nullable<u16>,
+    change_type: u8,
+    latest_value: Nullable<AclExtension<'a>>,
+    #[tagval(0xFE)]
+    fab_idx: u8,
+}
+
+/// The extension of a fabric: there is at most one per fabric
+type Extension = (u8, heapless::Vec<u8, MAX_EXTENSION_DATA_LEN>);
+
 pub struct AccessControlCluster<'a> {
     data_ver: Dataver,
     acl_mgr: &'a RefCell<AclMgr>,
+    events: &'a dyn EventLogger,
+    // TODO: The extensions are not persisted yet
+    extensions: RefCell<heapless::Vec<Extension, MAX_SUPPORTED_FABRICS>>,
 }
 
 impl<'a> AccessControlCluster<'a> {
-    pub fn new(acl_mgr: &'a RefCell<AclMgr>, rand: Rand) -> Self {
+    pub fn new(acl_mgr: &'a RefCell<AclMgr>, events: &'a dyn EventLogger, rand: Rand) -> Self {
         Self {
             data_ver: Dataver::new(rand),
             acl_mgr,
+            events,
+            extensions: RefCell::new(heapless::Vec::new()),
         }
     }
 
     pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
-        if let Some(mut writer) = encoder.with_dataver(self.data_ver.get())? {
+        if let Some(writer) = encoder.with_dataver(self.data_ver.get())? {
             if attr.is_system() {
                 CLUSTER.read(attr.attr_id, writer)
             } else {
                 match attr.attr_id.try_into()? {
                     Attributes::Acl(_) => {
-                        writer.start_array(AttrDataWriter::TAG)?;
-                        self.acl_mgr.borrow().for_each_acl(|entry| {
-                            if !attr.fab_filter || Some(attr.fab_idx) == entry.fab_idx {
-                                entry.to_tlv(&mut writer, TagType::Anonymous)?;
-                            }
-
-                            Ok(())
-                        })?;
-                        writer.end_container()?;
-
-                        writer.complete()
-                    }
-                    Attributes::Extension(_) => {
-                        // Empty for now
-                        writer.start_array(AttrDataWriter::TAG)?;
-                        writer.end_container()?;
-
-                        writer.complete()
+                        writer.set_list(self.acl_mgr.borrow().acls().filter(|entry| {
+                            !attr.fab_filter || Some(attr.fab_idx) == entry.fab_idx
+                        }))
                     }
+                    Attributes::Extension(_) => writer.set_list(
+                        self.extensions
+                            .borrow()
+                            .iter()
+                            .filter(|(fab_idx, _)| attr.is_visible_to(*fab_idx))
+                            .map(|(fab_idx, data)| AclExtension {
+                                data: OctetStr::new(data),
+                                fab_idx: Some(*fab_idx),
+                            }),
+                    ),
                     Attributes::SubjectsPerEntry(codec) => {
                         codec.encode(writer, acl::SUBJECTS_PER_ENTRY as u16)
                     }
@@ -136,14 +197,23 @@ impl<'a> AccessControlCluster<'a> {
         match attr.attr_id.try_into()? {
             Attributes::Acl(_) => {
                 attr_list_write(attr, data.with_dataver(self.data_ver.get())?, |op, data| {
-                    self.write_acl_attr(&op, data, attr.fab_idx)
-                })
+                    self.write_acl_attr(&op, data, attr.endpoint_id, attr.fab_idx)
+                })?
+            }
+            Attributes::Extension(_) => {
+                attr_list_write(attr, data.with_dataver(self.data_ver.get())?, |op, data| {
+                    self.write_extension_attr(&op, data, attr.endpoint_id, attr.fab_idx)
+                })?
             }
             _ => {
                 error!("Attribute not yet supported: this shouldn't happen");
-                Err(ErrorCode::AttributeNotFound.into())
+                Err(ErrorCode::AttributeNotFound)?
             }
         }
+
+        self.data_ver.changed();
+
+        Ok(())
     }
 
     /// Write the ACL Attribute
@@ -154,12 +224,20 @@ impl<'a> AccessControlCluster<'a> {
         &self,
         op: &ListOperation,
         data: &TLVElement,
+        endpoint_id: EndptId,
         fab_idx: u8,
     ) -> Result<(), Error> {
         info!("Performing ACL operation {:?}", op);
         match op {
             ListOperation::AddItem | ListOperation::EditItem(_) => {
-                let mut acl_entry = AclEntry::from_tlv(data)?;
+                // An entry with more subjects or targets than we support does not fit
+                let mut acl_entry = AclEntry::from_tlv(data).map_err(|e| {
+                    if e.code() == ErrorCode::NoSpace {
+                        ErrorCode::ResourceExhausted.into()
+                    } else {
+                        e
+                    }
+                })?;
                 info!("ACL  {:?}", acl_entry);
                 // Overwrite the fabric index with our accessing fabric index
                 acl_entry.fab_idx = Some(fab_idx);
@@ -167,17 +245,150 @@ impl<'a> AccessControlCluster<'a> {
                 if let ListOperation::EditItem(index) = op {
                     self.acl_mgr
                         .borrow_mut()
-                        .edit(*index as u8, fab_idx, acl_entry)
+                        .edit(*index as u8, fab_idx, acl_entry.clone())?;
+                    self.log_entry_changed(endpoint_id, fab_idx, ChangeType::Changed, &acl_entry)
                 } else {
-                    self.acl_mgr.borrow_mut().add(acl_entry)
+                    self.acl_mgr.borrow_mut().add(acl_entry.clone())?;
+                    self.log_entry_changed(endpoint_id, fab_idx, ChangeType::Added, &acl_entry)
                 }
             }
             ListOperation::DeleteItem(index) => {
-                self.acl_mgr.borrow_mut().delete(*index as u8, fab_idx)
+                let old = self.acl_mgr.borrow_mut().delete(*index as u8, fab_idx)?;
+                self.log_entry_changed(endpoint_id, fab_idx, ChangeType::Removed, &old)
+            }
+            ListOperation::DeleteList => {
+                self.acl_mgr.borrow().for_each_acl(|entry| {
+                    if entry.fab_idx == Some(fab_idx) {
+                        self.log_entry_changed(endpoint_id, fab_idx, ChangeType::Removed, entry)?;
+                    }
+
+                    Ok(())
+                })?;
+
+                self.acl_mgr.borrow_mut().delete_for_fabric(fab_idx)
             }
-            ListOperation::DeleteList => self.acl_mgr.borrow_mut().delete_for_fabric(fab_idx),
         }
     }
+
+    /// Write the Extension Attribute
+    ///
+    /// Every fabric can have at most one extension, so the only valid list index is 0
+    fn write_extension_attr(
+        &self,
+        op: &ListOperation,
+        data: &TLVElement,
+        endpoint_id: EndptId,
+        fab_idx: u8,
+    ) -> Result<(), Error> {
+        let mut extensions = self.extensions.borrow_mut();
+        let existing = extensions.iter().position(|(f, _)| *f == fab_idx);
+
+        match op {
+            ListOperation::AddItem | ListOperation::EditItem(_) => {
+                let extension = AclExtension::from_tlv(data)?;
+                let ext_data = heapless::Vec::from_slice(extension.data.0)
+                    .map_err(|_| ErrorCode::ConstraintError)?;
+
+                let change_type = if let ListOperation::EditItem(index) = op {
+                    let existing = existing
+                        .filter(|_| *index == 0)
+                        .ok_or(ErrorCode::NotFound)?;
+                    extensions[existing].1 = ext_data;
+
+                    ChangeType::Changed
+                } else {
+                    if existing.is_some() {
+                        Err(ErrorCode::ConstraintError)?;
+                    }
+                    extensions
+                        .push((fab_idx, ext_data))
+                        .map_err(|_| ErrorCode::ResourceExhausted)?;
+
+                    ChangeType::Added
+                };
+
+                self.log_extension_changed(endpoint_id, fab_idx, change_type, extension.data)
+            }
+            ListOperation::DeleteItem(index) => match existing {
+                Some(existing) if *index == 0 => {
+                    let (_, old) = extensions.swap_remove(existing);
+                    self.log_extension_changed(
+                        endpoint_id,
+                        fab_idx,
+                        ChangeType::Removed,
+                        OctetStr::new(&old),
+                    )
+                }
+                _ => Err(ErrorCode::NotFound.into()),
+            },
+            ListOperation::DeleteList => {
+                if let Some(existing) = existing {
+                    let (_, old) = extensions.swap_remove(existing);
+                    self.log_extension_changed(
+                        endpoint_id,
+                        fab_idx,
+                        ChangeType::Removed,
+                        OctetStr::new(&old),
+                    )?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn log_entry_changed(
+        &self,
+        endpoint_id: EndptId,
+        fab_idx: u8,
+        change_type: ChangeType,
+        entry: &AclEntry,
+    ) -> Result<(), Error> {
+        self.events.log_event(
+            endpoint_id,
+            ID,
+            Events::AccessControlEntryChanged as _,
+            EventPriority::Info,
+            false,
+            &EntryChangedEvent {
+                admin_node_id: Nullable::Null,
+                admin_passcode_id: Nullable::Null,
+                change_type: change_type as _,
+                latest_value: Nullable::NotNull(entry),
+                fab_idx,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn log_extension_changed(
+        &self,
+        endpoint_id: EndptId,
+        fab_idx: u8,
+        change_type: ChangeType,
+        data: OctetStr,
+    ) -> Result<(), Error> {
+        self.events.log_event(
+            endpoint_id,
+            ID,
+            Events::AccessControlExtensionChanged as _,
+            EventPriority::Info,
+            false,
+            &ExtensionChangedEvent {
+                admin_node_id: Nullable::Null,
+                admin_passcode_id: Nullable::Null,
+                change_type: change_type as _,
+                latest_value: Nullable::NotNull(AclExtension {
+                    data,
+                    fab_idx: Some(fab_idx),
+                }),
+                fab_idx,
+            },
+        )?;
+
+        Ok(())
+    }
 }
 
 impl<'a> Handler for AccessControlCluster<'a> {
@@ -203,14 +414,18 @@ mod tests {
     use core::cell::RefCell;
 
     use crate::{
-        acl::{AclEntry, AclMgr, AuthMode},
-        data_model::objects::{AttrDataEncoder, AttrDetails, Node, Privilege},
+        acl::{AclEntry, AclMgr, AuthMode, ENTRIES_PER_FABRIC, SUBJECTS_PER_ENTRY},
+        data_model::{
+            events::EventMgr,
+            objects::{AttrDataEncoder, AttrDetails, Node, Privilege},
+        },
+        error::ErrorCode,
         interaction_model::messages::ib::ListOperation,
         tlv::{get_root_node_struct, ElementType, TLVElement, TLVWriter, TagType, ToTLV},
-        utils::{rand::dummy_rand, writebuf::WriteBuf},
+        utils::{epoch::dummy_epoch, rand::dummy_rand, writebuf::WriteBuf},
     };
 
-    use super::AccessControlCluster;
+    use super::{AccessControlCluster, ChangeType, Events, ID};
 
     #[test]
     /// Add an ACL entry
@@ -220,7 +435,8 @@ mod tests {
         let mut tw = TLVWriter::new(&mut writebuf);
 
         let acl_mgr = RefCell::new(AclMgr::new());
-        let acl = AccessControlCluster::new(&acl_mgr, dummy_rand);
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let acl = AccessControlCluster::new(&acl_mgr, &event_mgr, dummy_rand);
 
         let new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
         new.to_tlv(&mut tw, TagType::Anonymous).unwrap();
@@ -228,7 +444,7 @@ mod tests {
 
         // Test, ACL has fabric index 2, but the accessing fabric is 1
         //    the fabric index in the TLV should be ignored and the ACL should be created with entry 1
-        let result = acl.write_acl_attr(&ListOperation::AddItem, &data, 1);
+        let result = acl.write_acl_attr(&ListOperation::AddItem, &data, 0, 1);
         assert!(result.is_ok());
 
         let verifier = AclEntry::new(1, Privilege::VIEW, AuthMode::Case);
@@ -250,6 +466,7 @@ mod tests {
 
         // Add 3 ACLs, belonging to fabric index 2, 1 and 2, in that order
         let acl_mgr = RefCell::new(AclMgr::new());
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
         let mut verifier = [
             AclEntry::new(2, Privilege::VIEW, AuthMode::Case),
             AclEntry::new(1, Privilege::VIEW, AuthMode::Case),
@@ -258,14 +475,14 @@ mod tests {
         for i in &verifier {
             acl_mgr.borrow_mut().add(i.clone()).unwrap();
         }
-        let acl = AccessControlCluster::new(&acl_mgr, dummy_rand);
+        let acl = AccessControlCluster::new(&acl_mgr, &event_mgr, dummy_rand);
 
         let new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
         new.to_tlv(&mut tw, TagType::Anonymous).unwrap();
         let data = get_root_node_struct(writebuf.as_slice()).unwrap();
 
         // Test, Edit Fabric 2's index 1 - with accessing fabring as 2 - allow
-        let result = acl.write_acl_attr(&ListOperation::EditItem(1), &data, 2);
+        let result = acl.write_acl_attr(&ListOperation::EditItem(1), &data, 0, 2);
         // Fabric 2's index 1, is actually our index 2, update the verifier
         verifier[2] = new;
         assert!(result.is_ok());
@@ -287,6 +504,7 @@ mod tests {
     fn acl_cluster_delete() {
         // Add 3 ACLs, belonging to fabric index 2, 1 and 2, in that order
         let acl_mgr = RefCell::new(AclMgr::new());
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
         let input = [
             AclEntry::new(2, Privilege::VIEW, AuthMode::Case),
             AclEntry::new(1, Privilege::VIEW, AuthMode::Case),
@@ -295,12 +513,12 @@ mod tests {
         for i in &input {
             acl_mgr.borrow_mut().add(i.clone()).unwrap();
         }
-        let acl = AccessControlCluster::new(&acl_mgr, dummy_rand);
+        let acl = AccessControlCluster::new(&acl_mgr, &event_mgr, dummy_rand);
         // data is don't-care actually
         let data = TLVElement::new(TagType::Anonymous, ElementType::True);
 
         // Test , Delete Fabric 1's index 0
-        let result = acl.write_acl_attr(&ListOperation::DeleteItem(0), &data, 1);
+        let result = acl.write_acl_attr(&ListOperation::DeleteItem(0), &data, 0, 1);
         assert!(result.is_ok());
 
         let verifier = [input[0].clone(), input[2].clone()];
@@ -316,6 +534,136 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    /// - Adding more entries than the per-fabric cap is rejected, without affecting other fabrics
+    fn acl_cluster_entries_per_fabric() {
+        let mut buf: [u8; 100] = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let acl_mgr = RefCell::new(AclMgr::new());
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let acl = AccessControlCluster::new(&acl_mgr, &event_mgr, dummy_rand);
+
+        let new = AclEntry::new(1, Privilege::VIEW, AuthMode::Case);
+        new.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+        let data = get_root_node_struct(writebuf.as_slice()).unwrap();
+
+        for _ in 0..ENTRIES_PER_FABRIC {
+            assert!(acl
+                .write_acl_attr(&ListOperation::AddItem, &data, 0, 1)
+                .is_ok());
+        }
+
+        // Test, one more entry for fabric 1 doesn't fit, but fabric 2 still has room
+        assert_eq!(
+            acl.write_acl_attr(&ListOperation::AddItem, &data, 0, 1)
+                .map_err(|e| e.code()),
+            Err(ErrorCode::ResourceExhausted)
+        );
+        assert!(acl
+            .write_acl_attr(&ListOperation::AddItem, &data, 0, 2)
+            .is_ok());
+    }
+
+    #[test]
+    /// - An entry with more subjects than supported is rejected
+    fn acl_cluster_subjects_per_entry() {
+        let mut buf: [u8; 100] = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let acl_mgr = RefCell::new(AclMgr::new());
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let acl = AccessControlCluster::new(&acl_mgr, &event_mgr, dummy_rand);
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.u8(TagType::Context(1), 1).unwrap();
+        tw.u8(TagType::Context(2), 2).unwrap();
+        tw.start_array(TagType::Context(3)).unwrap();
+        for subject in 0..=SUBJECTS_PER_ENTRY as u64 {
+            tw.u64(TagType::Anonymous, 100 + subject).unwrap();
+        }
+        tw.end_container().unwrap();
+        tw.end_container().unwrap();
+        let data = get_root_node_struct(writebuf.as_slice()).unwrap();
+
+        assert_eq!(
+            acl.write_acl_attr(&ListOperation::AddItem, &data, 0, 1)
+                .map_err(|e| e.code()),
+            Err(ErrorCode::ResourceExhausted)
+        );
+        assert_eq!(event_mgr.borrow().iter().count(), 0);
+    }
+
+    /// The event IDs and change types of the logged events, oldest first
+    fn logged_changes(event_mgr: &RefCell<EventMgr>) -> heapless::Vec<(u32, u8), 8> {
+        let mut changes = heapless::Vec::new();
+        for event in event_mgr.borrow().iter() {
+            assert_eq!(event.cluster, ID);
+
+            let mut buf: [u8; 200] = [0; 200];
+            let mut writebuf = WriteBuf::new(&mut buf);
+            let mut tw = TLVWriter::new(&mut writebuf);
+            event.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+
+            let change_type = get_root_node_struct(writebuf.as_slice())
+                .unwrap()
+                .find_tag(1)
+                .unwrap()
+                .find_tag(7)
+                .unwrap()
+                .find_tag(3)
+                .unwrap()
+                .u8()
+                .unwrap();
+            changes.push((event.event_id, change_type)).unwrap();
+        }
+
+        changes
+    }
+
+    #[test]
+    /// - Every change of the ACL is logged as an event
+    fn acl_cluster_change_events() {
+        let mut buf: [u8; 100] = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let acl_mgr = RefCell::new(AclMgr::new());
+        acl_mgr
+            .borrow_mut()
+            .add(AclEntry::new(2, Privilege::VIEW, AuthMode::Case))
+            .unwrap();
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
+        let acl = AccessControlCluster::new(&acl_mgr, &event_mgr, dummy_rand);
+
+        let new = AclEntry::new(1, Privilege::ADMIN, AuthMode::Case);
+        new.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+        let data = get_root_node_struct(writebuf.as_slice()).unwrap();
+
+        acl.write_acl_attr(&ListOperation::AddItem, &data, 0, 1)
+            .unwrap();
+        acl.write_acl_attr(&ListOperation::EditItem(0), &data, 0, 1)
+            .unwrap();
+        // Only the entry of fabric 1 is removed, and logged as such
+        acl.write_acl_attr(&ListOperation::DeleteList, &data, 0, 1)
+            .unwrap();
+        acl.write_acl_attr(&ListOperation::DeleteItem(0), &data, 0, 2)
+            .unwrap();
+
+        let entry_changed = Events::AccessControlEntryChanged as u32;
+        assert_eq!(
+            logged_changes(&event_mgr),
+            [
+                (entry_changed, ChangeType::Added as u8),
+                (entry_changed, ChangeType::Changed as u8),
+                (entry_changed, ChangeType::Removed as u8),
+                (entry_changed, ChangeType::Removed as u8),
+            ]
+        );
+    }
+
     #[test]
     /// - acl read with and without fabric filtering
     fn acl_cluster_read() {
@@ -324,6 +672,7 @@ mod tests {
 
         // Add 3 ACLs, belonging to fabric index 2, 1 and 2, in that order
         let acl_mgr = RefCell::new(AclMgr::new());
+        let event_mgr = RefCell::new(EventMgr::new(dummy_epoch));
         let input = [
             AclEntry::new(2, Privilege::VIEW, AuthMode::Case),
             AclEntry::new(1, Privilege::VIEW, AuthMode::Case),
@@ -332,7 +681,7 @@ mod tests {
         for i in input {
             acl_mgr.borrow_mut().add(i).unwrap();
         }
-        let acl = AccessControlCluster::new(&acl_mgr, dummy_rand);
+        let acl = AccessControlCluster::new(&acl_mgr, &event_mgr, dummy_rand);
         // Test 1, all 3 entries are read in the response without fabric filtering
         {
             let attr = AttrDetails {
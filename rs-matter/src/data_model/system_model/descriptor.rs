@@ -15,6 +15,7 @@
  *    limitations under the License.
  */
 
+use core::cell::Cell;
 use core::convert::TryInto;
 
 use strum::FromRepr;
@@ -24,9 +25,13 @@ use crate::data_model::objects::*;
 use crate::error::Error;
 use crate::tlv::{TLVWriter, TagType, ToTLV};
 use crate::utils::rand::Rand;
+use crate::Matter;
 
 pub const ID: u32 = 0x001D;
 
+/// The TagList feature, for endpoints which report their semantic tags
+pub const FEATURE_TAG_LIST: u32 = 0x01;
+
 #[derive(FromRepr)]
 #[repr(u16)]
 #[allow(clippy::enum_variant_names)]
@@ -35,12 +40,14 @@ pub enum Attributes {
     ServerList = 1,
     ClientList = 2,
     PartsList = 3,
+    TagList = 4,
 }
 
 attribute_enum!(Attributes);
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID as _,
+    revision: 2,
     feature_map: 0,
     attributes: &[
         FEATURE_MAP,
@@ -49,8 +56,21 @@ pub const CLUSTER: Cluster<'static> = Cluster {
         Attribute::new(Attributes::ServerList as u16, Access::RV, Quality::NONE),
         Attribute::new(Attributes::PartsList as u16, Access::RV, Quality::NONE),
         Attribute::new(Attributes::ClientList as u16, Access::RV, Quality::NONE),
+        Attribute::new(Attributes::TagList as u16, Access::RV, Quality::FIXED)
+            .with_feature(FEATURE_TAG_LIST),
     ],
     commands: &[],
+    generated_commands: &[],
+    timed_commands: &[],
+    response_commands: &[],
+    manage_commands: &[],
+    admin_commands: &[],
+};
+
+/// The Descriptor cluster of an endpoint which has semantic tags
+pub const CLUSTER_TAG_LIST: Cluster<'static> = Cluster {
+    feature_map: FEATURE_TAG_LIST,
+    ..CLUSTER
 };
 
 struct StandardPartsMatcher;
@@ -94,27 +114,46 @@ where
 pub struct DescriptorCluster<'a> {
     matcher: &'a dyn PartsMatcher,
     data_ver: Dataver,
+    /// A fingerprint of the composition of the node as of the last refresh, so that the
+    /// data version changes whenever endpoints or clusters are added or removed
+    composition: Cell<u32>,
 }
 
 impl DescriptorCluster<'static> {
-    pub fn new(rand: Rand) -> Self {
-        Self::new_matching(&StandardPartsMatcher, rand)
+    pub fn new(node: &Node, rand: Rand) -> Self {
+        Self::new_matching(&StandardPartsMatcher, node, rand)
     }
 
-    pub fn new_aggregator(rand: Rand) -> Self {
-        Self::new_matching(&AggregatorPartsMatcher, rand)
+    pub fn new_aggregator(node: &Node, rand: Rand) -> Self {
+        Self::new_matching(&AggregatorPartsMatcher, node, rand)
     }
 }
 
 impl<'a> DescriptorCluster<'a> {
-    pub fn new_matching(matcher: &'a dyn PartsMatcher, rand: Rand) -> DescriptorCluster<'a> {
+    pub fn new_matching(
+        matcher: &'a dyn PartsMatcher,
+        node: &Node,
+        rand: Rand,
+    ) -> DescriptorCluster<'a> {
         Self {
             matcher,
             data_ver: Dataver::new(rand),
+            composition: Cell::new(Self::composition(node)),
+        }
+    }
+
+    /// To be called by the owner of the node after it adds or removes endpoints or clusters:
+    /// bumps the data version and reports the Descriptor of `endpoint_id` to its subscribers
+    /// if the node is now composed differently
+    pub fn notify_composition_changed(&self, node: &Node, endpoint_id: EndptId, matter: &Matter) {
+        if self.refresh(node) {
+            matter.notify_attribute_changed(endpoint_id, ID as _);
         }
     }
 
     pub fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        self.refresh(attr.node);
+
         if let Some(mut writer) = encoder.with_dataver(self.data_ver.get())? {
             if attr.is_system() {
                 CLUSTER.read(attr.attr_id, writer)
@@ -156,6 +195,15 @@ impl<'a> DescriptorCluster<'a> {
                         )?;
                         writer.complete()
                     }
+                    Attributes::TagList => {
+                        self.encode_tag_list(
+                            attr.node,
+                            attr.endpoint_id,
+                            AttrDataWriter::TAG,
+                            &mut writer,
+                        )?;
+                        writer.complete()
+                    }
                 }
             }
         } else {
@@ -163,6 +211,41 @@ impl<'a> DescriptorCluster<'a> {
         }
     }
 
+    /// Bump the data version if the node was composed differently since the last refresh
+    fn refresh(&self, node: &Node) -> bool {
+        let composition = Self::composition(node);
+
+        if composition != self.composition.replace(composition) {
+            self.data_ver.changed();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// FNV-1a over the endpoints, their device types and their clusters
+    fn composition(node: &Node) -> u32 {
+        fn hash(acc: u32, value: u32) -> u32 {
+            value
+                .to_le_bytes()
+                .iter()
+                .fold(acc, |acc, b| (acc ^ *b as u32).wrapping_mul(0x0100_0193))
+        }
+
+        node.endpoints.iter().fold(0x811c_9dc5, |acc, endpoint| {
+            let acc = hash(acc, endpoint.id as _);
+            let acc = endpoint
+                .device_types
+                .iter()
+                .fold(acc, |acc, dev_type| hash(acc, dev_type.dtype as _));
+
+            endpoint
+                .clusters
+                .iter()
+                .fold(acc, |acc, cluster| hash(acc, cluster.id))
+        })
+    }
+
     fn encode_devtype_list(
         &self,
         node: &Node,
@@ -173,8 +256,9 @@ impl<'a> DescriptorCluster<'a> {
         tw.start_array(tag)?;
         for endpoint in node.endpoints {
             if endpoint.id == endpoint_id {
-                let dev_type = endpoint.device_type;
-                dev_type.to_tlv(tw, TagType::Anonymous)?;
+                for dev_type in endpoint.device_types {
+                    dev_type.to_tlv(tw, TagType::Anonymous)?;
+                }
             }
         }
 
@@ -218,6 +302,25 @@ impl<'a> DescriptorCluster<'a> {
         tw.end_container()
     }
 
+    fn encode_tag_list(
+        &self,
+        node: &Node,
+        endpoint_id: u16,
+        tag: TagType,
+        tw: &mut TLVWriter,
+    ) -> Result<(), Error> {
+        tw.start_array(tag)?;
+        for endpoint in node.endpoints {
+            if endpoint.id == endpoint_id {
+                for semantic_tag in endpoint.tags {
+                    semantic_tag.to_tlv(tw, TagType::Anonymous)?;
+                }
+            }
+        }
+
+        tw.end_container()
+    }
+
     fn encode_client_list(
         &self,
         _node: &Node,
@@ -244,3 +347,194 @@ impl<'a> ChangeNotifier<()> for DescriptorCluster<'a> {
         self.data_ver.consume_change(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_model::{
+            cluster_basic_information, cluster_on_off,
+            device_types::{DEV_TYPE_DIMMABLE_LIGHT, DEV_TYPE_ON_OFF_LIGHT, DEV_TYPE_ROOT_NODE},
+            objects::{AttrDataEncoder, AttrDetails, DeviceType, Endpoint, Node, SemanticTag},
+        },
+        tlv::{get_root_node_struct, TLVElement, TLVWriter},
+        utils::{rand::dummy_rand, writebuf::WriteBuf},
+    };
+
+    use super::{Attributes, DescriptorCluster, CLUSTER_TAG_LIST, ID};
+
+    const ROOT: Endpoint<'static> = Endpoint {
+        id: 0,
+        device_types: &[DEV_TYPE_ROOT_NODE],
+        tags: &[],
+        clusters: &[super::CLUSTER, cluster_basic_information::CLUSTER],
+    };
+
+    const LIGHT: Endpoint<'static> = Endpoint {
+        id: 1,
+        device_types: &[DEV_TYPE_ON_OFF_LIGHT],
+        tags: &[],
+        clusters: &[super::CLUSTER, cluster_on_off::CLUSTER],
+    };
+
+    /// Read a list attribute of the descriptor of the endpoint, returning its
+    /// elements and the data version
+    fn read(
+        cluster: &DescriptorCluster,
+        node: &Node,
+        endpoint_id: u16,
+        attr: Attributes,
+    ) -> (heapless::Vec<u32, 8>, u32) {
+        read_with(cluster, node, endpoint_id, attr, |element| {
+            element.u32().unwrap()
+        })
+    }
+
+    fn read_with<T>(
+        cluster: &DescriptorCluster,
+        node: &Node,
+        endpoint_id: u16,
+        attr: Attributes,
+        f: impl Fn(TLVElement) -> T,
+    ) -> (heapless::Vec<T, 8>, u32) {
+        let attr = AttrDetails {
+            node,
+            endpoint_id,
+            cluster_id: ID,
+            attr_id: attr as _,
+            list_index: None,
+            fab_idx: 1,
+            fab_filter: false,
+            dataver: None,
+            wildcard: false,
+        };
+
+        let mut buf = [0; 100];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        cluster
+            .read(&attr, AttrDataEncoder::new(&attr, &mut tw))
+            .unwrap();
+
+        let data = get_root_node_struct(writebuf.as_slice())
+            .unwrap()
+            .find_tag(1)
+            .unwrap();
+
+        let dataver = data.find_tag(0).unwrap().u32().unwrap();
+        let list = data.find_tag(2).unwrap().enter().unwrap().map(f).collect();
+
+        (list, dataver)
+    }
+
+    #[test]
+    fn test_two_endpoints() {
+        let node = Node {
+            id: 0,
+            endpoints: &[ROOT, LIGHT],
+        };
+
+        let cluster = DescriptorCluster::new(&node, dummy_rand);
+
+        let (parts, _) = read(&cluster, &node, 0, Attributes::PartsList);
+        assert_eq!(parts.as_slice(), &[1]);
+
+        let (parts, _) = read(&cluster, &node, 1, Attributes::PartsList);
+        assert!(parts.is_empty());
+
+        let (servers, _) = read(&cluster, &node, 1, Attributes::ServerList);
+        assert_eq!(servers.as_slice(), &[ID, cluster_on_off::ID]);
+
+        let (servers, _) = read(&cluster, &node, 0, Attributes::ServerList);
+        assert_eq!(servers.as_slice(), &[ID, cluster_basic_information::ID]);
+    }
+
+    #[test]
+    /// Composing the node differently changes the data version of the descriptor
+    fn test_dynamic_composition() {
+        let root_only = Node {
+            id: 0,
+            endpoints: &[ROOT],
+        };
+
+        let cluster = DescriptorCluster::new(&root_only, dummy_rand);
+        let dataver = cluster.data_ver.get();
+
+        let (parts, first_dataver) = read(&cluster, &root_only, 0, Attributes::PartsList);
+        assert!(parts.is_empty());
+        assert_eq!(first_dataver, dataver);
+        assert_eq!(
+            read(&cluster, &root_only, 0, Attributes::PartsList).1,
+            dataver
+        );
+
+        let composed = Node {
+            id: 0,
+            endpoints: &[ROOT, LIGHT],
+        };
+
+        let (parts, new_dataver) = read(&cluster, &composed, 0, Attributes::PartsList);
+        assert_eq!(parts.as_slice(), &[1]);
+        assert_ne!(new_dataver, dataver);
+        assert_eq!(
+            read(&cluster, &composed, 0, Attributes::PartsList).1,
+            new_dataver
+        );
+
+        // An explicit refresh by the owner of the node bumps the version only once
+        assert!(cluster.refresh(&root_only));
+        assert!(!cluster.refresh(&root_only));
+        assert_ne!(cluster.data_ver.get(), new_dataver);
+    }
+
+    #[test]
+    /// A composed endpoint reports all of its device types, and its semantic tags
+    fn test_composed_endpoint() {
+        const FAN: DeviceType = DeviceType {
+            dtype: 0x002b,
+            drev: 2,
+        };
+
+        const COMPOSED: Endpoint<'static> = Endpoint {
+            id: 1,
+            device_types: &[DEV_TYPE_DIMMABLE_LIGHT, FAN],
+            tags: &[SemanticTag {
+                mfg_code: None,
+                namespace_id: 0x08,
+                tag: 0x02,
+                label: Some("Ceiling"),
+            }],
+            clusters: &[CLUSTER_TAG_LIST, cluster_on_off::CLUSTER],
+        };
+
+        let node = Node {
+            id: 0,
+            endpoints: &[ROOT, COMPOSED],
+        };
+
+        let cluster = DescriptorCluster::new(&node, dummy_rand);
+
+        let (dev_types, _) = read_with(&cluster, &node, 1, Attributes::DeviceTypeList, |dt| {
+            (
+                dt.find_tag(0).unwrap().u16().unwrap(),
+                dt.find_tag(1).unwrap().u16().unwrap(),
+            )
+        });
+        assert_eq!(
+            dev_types.as_slice(),
+            &[
+                (DEV_TYPE_DIMMABLE_LIGHT.dtype, DEV_TYPE_DIMMABLE_LIGHT.drev),
+                (0x002b, 2)
+            ]
+        );
+
+        let (tags, _) = read_with(&cluster, &node, 1, Attributes::TagList, |tag| {
+            (
+                tag.find_tag(0).unwrap().null().is_ok(),
+                tag.find_tag(1).unwrap().u8().unwrap(),
+                tag.find_tag(2).unwrap().u8().unwrap(),
+            )
+        });
+        assert_eq!(tags.as_slice(), &[(true, 0x08, 0x02)]);
+    }
+}
@@ -17,6 +17,8 @@
 
 use core::{array::TryFromSliceError, fmt, str::Utf8Error};
 
+use crate::interaction_model::messages::ib::Status;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ErrorCode {
     AttributeNotFound,
@@ -24,8 +26,10 @@ pub enum ErrorCode {
     BufferTooSmall,
     ClusterNotFound,
     CommandNotFound,
+    ConstraintError,
     Duplicate,
     EndpointNotFound,
+    FailSafeRequired,
     InvalidAction,
     InvalidCommand,
     InvalidDataType,
@@ -49,10 +53,13 @@ pub enum ErrorCode {
     NoSpace,
     NoSpaceAckTable,
     NoSpaceRetransTable,
+    SendFailed,
     NoTagFound,
     NotFound,
     PacketPoolExhaust,
     StdIoError,
+    // A persisted record was written by a newer version of the storage format
+    StorageVersion,
     SysTimeFail,
     Invalid,
     InvalidAAD,
@@ -68,9 +75,17 @@ pub enum ErrorCode {
     InvalidArgument,
     RwLock,
     TLVNotFound,
+    TLVTagOrder,
+    TLVTooNested,
     TLVTypeMismatch,
     TruncatedPacket,
+    // A bulk data transfer was aborted with a status report, by us or by the peer
+    TransferAborted,
     Utf8Fail,
+    // Not implemented by this build, e.g. by its crypto backend
+    Unsupported,
+    // A cluster-specific failure, reported with the cluster status code
+    ClusterStatus(u8),
 }
 
 impl From<ErrorCode> for Error {
@@ -83,6 +98,8 @@ pub struct Error {
     code: ErrorCode,
     #[cfg(all(feature = "std", feature = "backtrace"))]
     backtrace: std::backtrace::Backtrace,
+    #[cfg(feature = "std")]
+    inner: Option<Box<dyn std::error::Error + Send + Sync>>,
 }
 
 impl Error {
@@ -91,6 +108,20 @@ impl Error {
             code,
             #[cfg(all(feature = "std", feature = "backtrace"))]
             backtrace: std::backtrace::Backtrace::capture(),
+            #[cfg(feature = "std")]
+            inner: None,
+        }
+    }
+
+    /// An error caused by an error of the OS or of a backend, which is kept as its source
+    #[cfg(feature = "std")]
+    pub fn new_with_inner(
+        code: ErrorCode,
+        inner: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self {
+            inner: Some(inner.into()),
+            ..Self::new(code)
         }
     }
 
@@ -103,6 +134,16 @@ impl Error {
         &self.backtrace
     }
 
+    /// The Interaction Model status with which this error is reported to the peer
+    pub fn to_im_status(&self) -> Status {
+        let cluster_status = match self.code {
+            ErrorCode::ClusterStatus(cluster_status) => cluster_status as u16,
+            _ => 0,
+        };
+
+        Status::new(self.code.into(), cluster_status)
+    }
+
     pub fn remap<F>(self, matcher: F, to: Self) -> Self
     where
         F: FnOnce(&Self) -> bool,
@@ -136,9 +177,8 @@ impl Error {
 
 #[cfg(feature = "std")]
 impl From<std::io::Error> for Error {
-    fn from(_e: std::io::Error) -> Self {
-        // Keep things simple for now
-        Self::new(ErrorCode::StdIoError)
+    fn from(e: std::io::Error) -> Self {
+        Self::new_with_inner(ErrorCode::StdIoError, e)
     }
 }
 
@@ -149,7 +189,15 @@ impl<T> From<std::sync::PoisonError<T>> for Error {
     }
 }
 
-#[cfg(feature = "openssl")]
+#[cfg(all(feature = "openssl", feature = "std"))]
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        ::log::error!("Error in TLS: {}", e);
+        Self::new_with_inner(ErrorCode::TLSStack, e)
+    }
+}
+
+#[cfg(all(feature = "openssl", not(feature = "std")))]
 impl From<openssl::error::ErrorStack> for Error {
     fn from(e: openssl::error::ErrorStack) -> Self {
         ::log::error!("Error in TLS: {}", e);
@@ -182,8 +230,8 @@ impl From<ccm::aead::Error> for Error {
 
 #[cfg(feature = "std")]
 impl From<std::time::SystemTimeError> for Error {
-    fn from(_e: std::time::SystemTimeError) -> Self {
-        Error::new(ErrorCode::SysTimeFail)
+    fn from(e: std::time::SystemTimeError) -> Self {
+        Error::new_with_inner(ErrorCode::SysTimeFail, e)
     }
 }
 
@@ -224,4 +272,74 @@ impl fmt::Display for Error {
 }
 
 #[cfg(feature = "std")]
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner
+            .as_deref()
+            .map(|inner| inner as &(dyn std::error::Error + 'static))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interaction_model::core::IMStatusCode;
+
+    use super::{Error, ErrorCode};
+
+    #[test]
+    fn test_im_status() {
+        let cases = [
+            (ErrorCode::EndpointNotFound, 0x7f),
+            (ErrorCode::ClusterNotFound, 0xc3),
+            (ErrorCode::AttributeNotFound, 0x86),
+            (ErrorCode::CommandNotFound, 0x81),
+            (ErrorCode::UnsupportedAccess, 0x7e),
+            (ErrorCode::InvalidData, 0x80),
+            (ErrorCode::InvalidDataType, 0x8d),
+            (ErrorCode::ConstraintError, 0x87),
+            (ErrorCode::NotFound, 0x8b),
+            (ErrorCode::ResourceExhausted, 0x89),
+            (ErrorCode::FailSafeRequired, 0xca),
+            (ErrorCode::NoSpace, 0x01),
+        ];
+
+        for (code, im_status) in cases {
+            let status = Error::new(code).to_im_status();
+            assert_eq!(status.status as u16, im_status, "{:?}", code);
+            assert_eq!(status.cluster_status, 0);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_source() {
+        use std::error::Error as _;
+
+        let io_error = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed");
+        let error: Error = io_error.into();
+
+        assert_eq!(error.code(), ErrorCode::StdIoError);
+        assert_eq!(error.to_string(), "StdIoError");
+
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "pipe closed");
+        assert_eq!(
+            source.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::BrokenPipe
+        );
+
+        // Errors raised by the stack itself have no source
+        assert!(Error::new(ErrorCode::NoSpace).source().is_none());
+
+        // And the error can be propagated into a boxed std error
+        let boxed: Box<dyn std::error::Error> = error.into();
+        assert!(boxed.source().is_some());
+    }
+
+    #[test]
+    fn test_im_cluster_status() {
+        let status = Error::new(ErrorCode::ClusterStatus(2)).to_im_status();
+        assert_eq!(status.status, IMStatusCode::Failure);
+        assert_eq!(status.cluster_status, 2);
+    }
+}
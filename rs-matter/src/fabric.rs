@@ -19,19 +19,27 @@ use core::fmt::Write;
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use heapless::{String, Vec};
-use log::info;
+use log::{error, info};
 
 use crate::{
+    acl::AclMgr,
     cert::{Cert, MAX_CERT_TLV_LEN},
     crypto::{self, hkdf_sha256, HmacSha256, KeyPair},
+    data_model::{
+        cluster_binding::BindingMgr, cluster_ota_requestor::OtaProviderMgr,
+        sdm::icd_management::IcdClientMgr, subscriptions::SubscriptionMgr,
+    },
     error::{Error, ErrorCode},
     group_keys::KeySet,
+    groups::GroupMgr,
     mdns::{Mdns, ServiceMode},
-    tlv::{self, FromTLV, OctetStr, TLVList, TLVWriter, TagType, ToTLV, UtfStr},
+    secure_channel::case::ResumptionMgr,
+    tlv::{self, FromTLV, OctetStr, TLVElement, TLVList, TLVWriter, TagType, ToTLV, UtfStr},
+    transport::session::SessionMgr,
     utils::writebuf::WriteBuf,
 };
 
-const COMPRESSED_FABRIC_ID_LEN: usize = 8;
+pub const COMPRESSED_FABRIC_ID_LEN: usize = 8;
 
 #[allow(dead_code)]
 #[derive(Debug, ToTLV)]
@@ -47,6 +55,13 @@ pub struct FabricDescriptor<'a> {
     pub fab_idx: Option<u8>,
 }
 
+/// The operational credentials of a node on a fabric
+pub struct OpCredentials {
+    pub key_pair: KeyPair,
+    pub icac: Option<Vec<u8, { MAX_CERT_TLV_LEN }>>,
+    pub noc: Vec<u8, { MAX_CERT_TLV_LEN }>,
+}
+
 #[derive(Debug, ToTLV, FromTLV)]
 pub struct Fabric {
     node_id: u64,
@@ -59,6 +74,30 @@ pub struct Fabric {
     pub ipk: KeySet,
     label: String<32>,
     mdns_service_name: String<33>,
+    compressed_id: CompressedId,
+}
+
+/// The compressed fabric ID, which is not persisted with the fabric, but derived
+/// again from its root certificate and fabric ID when the fabric is loaded
+///
+/// The records of version 1 of the storage format do carry it, and it is skipped there.
+#[derive(Debug, Default, Clone, Copy)]
+struct CompressedId([u8; COMPRESSED_FABRIC_ID_LEN]);
+
+impl ToTLV for CompressedId {
+    fn to_tlv(&self, _tw: &mut TLVWriter, _tag: TagType) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> FromTLV<'a> for CompressedId {
+    fn from_tlv(_t: &TLVElement<'a>) -> Result<Self, Error> {
+        Ok(Self::default())
+    }
+
+    fn tlv_not_found() -> Result<Self, Error> {
+        Ok(Self::default())
+    }
 }
 
 impl Fabric {
@@ -76,14 +115,59 @@ impl Fabric {
             (noc_p.get_node_id()?, noc_p.get_fabric_id()?)
         };
 
-        let mut compressed_id = [0_u8; COMPRESSED_FABRIC_ID_LEN];
-
-        let ipk = {
+        let compressed_id = {
             let root_ca_p = Cert::new(&root_ca)?;
-            Fabric::get_compressed_id(root_ca_p.get_pubkey(), fabric_id, &mut compressed_id)?;
-            KeySet::new(ipk, &compressed_id)?
+            Fabric::get_compressed_id(root_ca_p.get_pubkey(), fabric_id)?
+        };
+
+        let ipk = KeySet::new(ipk, &compressed_id)?;
+
+        let mdns_service_name = Self::get_mdns_service_name(&compressed_id, node_id);
+
+        Ok(Self {
+            node_id,
+            fabric_id,
+            vendor_id,
+            key_pair,
+            root_ca,
+            icac,
+            noc,
+            ipk,
+            label: label.into(),
+            mdns_service_name,
+            compressed_id: CompressedId(compressed_id),
+        })
+    }
+
+    /// Replace the operational credentials of the fabric, returning the previous ones
+    ///
+    /// The root certificate and the IPK of the fabric are retained, so the new NOC
+    /// has to be for the same fabric ID.
+    pub fn update_noc(&mut self, creds: OpCredentials) -> Result<OpCredentials, Error> {
+        let (node_id, fabric_id) = {
+            let noc_p = Cert::new(&creds.noc)?;
+            (noc_p.get_node_id()?, noc_p.get_fabric_id()?)
         };
 
+        if fabric_id != self.fabric_id {
+            error!(
+                "NOC fabric ID {:x} does not match the fabric ID {:x}",
+                fabric_id, self.fabric_id
+            );
+            Err(ErrorCode::Invalid)?;
+        }
+
+        self.node_id = node_id;
+        self.mdns_service_name = Self::get_mdns_service_name(&self.compressed_id.0, node_id);
+
+        Ok(OpCredentials {
+            key_pair: core::mem::replace(&mut self.key_pair, creds.key_pair),
+            icac: core::mem::replace(&mut self.icac, creds.icac),
+            noc: core::mem::replace(&mut self.noc, creds.noc),
+        })
+    }
+
+    fn get_mdns_service_name(compressed_id: &[u8], node_id: u64) -> String<33> {
         let mut mdns_service_name = heapless::String::<33>::new();
         for c in compressed_id {
             let mut hex = heapless::String::<4>::new();
@@ -100,21 +184,13 @@ impl Fabric {
         }
         info!("MDNS Service Name: {}", mdns_service_name);
 
-        Ok(Self {
-            node_id,
-            fabric_id,
-            vendor_id,
-            key_pair,
-            root_ca,
-            icac,
-            noc,
-            ipk,
-            label: label.into(),
-            mdns_service_name,
-        })
+        mdns_service_name
     }
 
-    fn get_compressed_id(root_pubkey: &[u8], fabric_id: u64, out: &mut [u8]) -> Result<(), Error> {
+    fn get_compressed_id(
+        root_pubkey: &[u8],
+        fabric_id: u64,
+    ) -> Result<[u8; COMPRESSED_FABRIC_ID_LEN], Error> {
         let root_pubkey = &root_pubkey[1..];
         let mut fabric_id_be: [u8; 8] = [0; 8];
         BigEndian::write_u64(&mut fabric_id_be, fabric_id);
@@ -122,8 +198,16 @@ impl Fabric {
             0x43, 0x6f, 0x6d, 0x70, 0x72, 0x65, 0x73, 0x73, 0x65, 0x64, 0x46, 0x61, 0x62, 0x72,
             0x69, 0x63,
         ];
-        hkdf_sha256(&fabric_id_be, root_pubkey, &COMPRESSED_FABRIC_ID_INFO, out)
-            .map_err(|_| Error::from(ErrorCode::NoSpace))
+        let mut compressed_id = [0; COMPRESSED_FABRIC_ID_LEN];
+        hkdf_sha256(
+            &fabric_id_be,
+            root_pubkey,
+            &COMPRESSED_FABRIC_ID_INFO,
+            &mut compressed_id,
+        )
+        .map_err(|_| Error::from(ErrorCode::NoSpace))?;
+
+        Ok(compressed_id)
     }
 
     pub fn match_dest_id(&self, random: &[u8], target: &[u8]) -> Result<(), Error> {
@@ -160,6 +244,23 @@ impl Fabric {
         self.fabric_id
     }
 
+    pub fn get_vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    /// The compressed fabric ID, derived from the root public key and the fabric ID
+    ///
+    /// The operational instance name and the operational group keys of the fabric are
+    /// built from it. It is computed once, when the fabric is added or loaded.
+    pub fn compressed_id(&self) -> [u8; COMPRESSED_FABRIC_ID_LEN] {
+        self.compressed_id.0
+    }
+
+    /// The instance name of the operational mDNS service of the node on the fabric
+    pub fn mdns_service_name(&self) -> &str {
+        &self.mdns_service_name
+    }
+
     pub fn get_root_ca(&self) -> Result<Cert<'_>, Error> {
         Cert::new(&self.root_ca)
     }
@@ -191,6 +292,36 @@ pub struct FabricMgr {
     changed: bool,
 }
 
+/// The managers which keep state per fabric, all of which goes away with the fabric
+pub struct FabricScoped<'a> {
+    pub acl_mgr: &'a mut AclMgr,
+    pub group_mgr: &'a mut GroupMgr,
+    pub subscription_mgr: &'a mut SubscriptionMgr,
+    pub session_mgr: &'a mut SessionMgr,
+    pub binding_mgr: &'a mut BindingMgr,
+    pub ota_provider_mgr: &'a mut OtaProviderMgr,
+    pub icd_client_mgr: &'a mut IcdClientMgr,
+    pub resumption_mgr: &'a mut ResumptionMgr,
+}
+
+impl<'a> FabricScoped<'a> {
+    fn remove_for_fabric(&mut self, fab_idx: u8, except_sess_id: Option<u16>) -> Result<(), Error> {
+        self.acl_mgr.delete_for_fabric(fab_idx)?;
+        self.group_mgr.remove_for_fabric(fab_idx);
+        self.subscription_mgr.remove_for_fabric(fab_idx);
+        self.session_mgr.remove_for_fabric(fab_idx, except_sess_id);
+        for sess_id in self.session_mgr.take_removed() {
+            self.subscription_mgr.evict_session(sess_id);
+        }
+        self.binding_mgr.remove_for_fabric(fab_idx);
+        self.ota_provider_mgr.remove_for_fabric(fab_idx);
+        self.icd_client_mgr.remove_for_fabric(fab_idx);
+        self.resumption_mgr.remove_for_fabric(fab_idx);
+
+        Ok(())
+    }
+}
+
 impl FabricMgr {
     #[inline(always)]
     pub const fn new() -> Self {
@@ -209,6 +340,13 @@ impl FabricMgr {
 
         tlv::from_tlv(&mut self.fabrics, &root)?;
 
+        for fabric in self.fabrics.iter_mut().flatten() {
+            fabric.compressed_id = CompressedId(Fabric::get_compressed_id(
+                fabric.get_root_ca()?.get_pubkey(),
+                fabric.fabric_id,
+            )?);
+        }
+
         for fabric in self.fabrics.iter().flatten() {
             mdns.add(&fabric.mdns_service_name, ServiceMode::Commissioned)?;
         }
@@ -265,11 +403,24 @@ impl FabricMgr {
         }
     }
 
-    pub fn remove(&mut self, fab_idx: u8, mdns: &dyn Mdns) -> Result<(), Error> {
+    /// Remove a fabric, together with all of its state in the other managers
+    ///
+    /// The sessions of the fabric are removed too, except for the one with the local
+    /// session ID `except_sess_id`, if any.
+    pub fn remove(
+        &mut self,
+        fab_idx: u8,
+        scoped: &mut FabricScoped,
+        except_sess_id: Option<u16>,
+        mdns: &dyn Mdns,
+    ) -> Result<(), Error> {
         if fab_idx > 0 && fab_idx as usize <= self.fabrics.len() {
             if let Some(f) = self.fabrics[(fab_idx - 1) as usize].take() {
-                mdns.remove(&f.mdns_service_name)?;
                 self.changed = true;
+
+                scoped.remove_for_fabric(fab_idx, except_sess_id)?;
+                mdns.remove(&f.mdns_service_name)?;
+
                 Ok(())
             } else {
                 Err(ErrorCode::NotFound.into())
@@ -279,6 +430,51 @@ impl FabricMgr {
         }
     }
 
+    pub fn update_noc(
+        &mut self,
+        fab_idx: u8,
+        creds: OpCredentials,
+        mdns: &dyn Mdns,
+    ) -> Result<OpCredentials, Error> {
+        if fab_idx == 0 || fab_idx as usize > self.fabrics.len() {
+            Err(ErrorCode::NotFound)?;
+        }
+
+        let fabric = self.fabrics[(fab_idx - 1) as usize]
+            .as_mut()
+            .ok_or(ErrorCode::NotFound)?;
+
+        // The operational instance name changes with the node ID
+        mdns.remove(&fabric.mdns_service_name)?;
+        let result = fabric.update_noc(creds);
+        mdns.add(&fabric.mdns_service_name, ServiceMode::Commissioned)?;
+
+        if result.is_ok() {
+            self.changed = true;
+        }
+
+        result
+    }
+
+    /// (Re)publish the operational mDNS service of a fabric
+    ///
+    /// The instance name is recomputed from the compressed fabric ID and the node ID,
+    /// and the service published under the previous name is withdrawn first.
+    pub fn advertise(&mut self, fab_idx: u8, mdns: &dyn Mdns) -> Result<(), Error> {
+        if fab_idx == 0 || fab_idx as usize > self.fabrics.len() {
+            Err(ErrorCode::NotFound)?;
+        }
+
+        let fabric = self.fabrics[(fab_idx - 1) as usize]
+            .as_mut()
+            .ok_or(ErrorCode::NotFound)?;
+
+        mdns.remove(&fabric.mdns_service_name)?;
+        fabric.mdns_service_name =
+            Fabric::get_mdns_service_name(&fabric.compressed_id.0, fabric.node_id);
+        mdns.add(&fabric.mdns_service_name, ServiceMode::Commissioned)
+    }
+
     pub fn match_dest_id(&self, random: &[u8], target: &[u8]) -> Result<usize, Error> {
         for (index, fabric) in self.fabrics.iter().enumerate() {
             if let Some(fabric) = fabric {
@@ -338,3 +534,323 @@ impl FabricMgr {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use crate::{
+        acl::{AclEntry, AclMgr, AuthMode, Privilege},
+        cert::{
+            tests::test_vectors::{ICAC1_SUCCESS, NOC1_SUCCESS, RCA1_SUCCESS},
+            MAX_CERT_TLV_LEN,
+        },
+        crypto::KeyPair,
+        data_model::{
+            cluster_basic_information::BasicInfoConfig, cluster_binding::BindingMgr,
+            cluster_ota_requestor::OtaProviderMgr, sdm::icd_management::IcdClientMgr,
+            subscriptions::SubscriptionMgr,
+        },
+        error::Error,
+        groups::{GroupKeySet, GroupMgr},
+        interaction_model::messages::msg::SubscribeReq,
+        mdns::{DummyMdns, Mdns, ServiceMode},
+        secure_channel::case::ResumptionMgr,
+        test_support::seq_rand,
+        transport::{
+            network::Address,
+            session::{CaseDetails, CloneData, SessionMgr, SessionMode},
+        },
+        utils::{clock::DummyClock, epoch::dummy_epoch, rand::dummy_rand},
+    };
+
+    use super::{Fabric, FabricMgr, FabricScoped, COMPRESSED_FABRIC_ID_LEN};
+
+    /// Records the names of the services currently published
+    struct MockMdns {
+        services: RefCell<heapless::Vec<heapless::String<40>, 4>>,
+    }
+
+    impl Mdns for MockMdns {
+        fn add(&self, service: &str, _mode: ServiceMode) -> Result<(), Error> {
+            self.services.borrow_mut().push(service.into()).unwrap();
+            Ok(())
+        }
+
+        fn remove(&self, service: &str) -> Result<(), Error> {
+            self.services.borrow_mut().retain(|s| s != service);
+            Ok(())
+        }
+    }
+
+    fn test_fabric() -> Fabric {
+        Fabric::new(
+            KeyPair::new(seq_rand).unwrap(),
+            heapless::Vec::from_slice(&RCA1_SUCCESS).unwrap(),
+            Some(heapless::Vec::from_slice(&ICAC1_SUCCESS).unwrap()),
+            heapless::Vec::from_slice(&NOC1_SUCCESS).unwrap(),
+            &[0x11; 16],
+            0xFFF1,
+            "",
+        )
+        .unwrap()
+    }
+
+    // The fabric of the spec's example
+    const SPEC_ROOT_PUBKEY: [u8; 65] = [
+        0x04, 0x4a, 0x9f, 0x42, 0xb1, 0xca, 0x48, 0x40, 0xd3, 0x72, 0x92, 0xbb, 0xc7, 0xf6, 0xa7,
+        0xe1, 0x1e, 0x22, 0x20, 0x0c, 0x97, 0x6f, 0xc9, 0x00, 0xdb, 0xc9, 0x8a, 0x7a, 0x38, 0x3a,
+        0x64, 0x1c, 0xb8, 0x25, 0x4a, 0x2e, 0x56, 0xd4, 0xe2, 0x95, 0xa8, 0x47, 0x94, 0x3b, 0x4e,
+        0x38, 0x97, 0xc4, 0xa7, 0x73, 0xe9, 0x30, 0x27, 0x7b, 0x4d, 0x9f, 0xbe, 0xde, 0x8a, 0x05,
+        0x26, 0x86, 0xbf, 0xac, 0xfa,
+    ];
+    const SPEC_FABRIC_ID: u64 = 0x2906_C908_D115_D362;
+    const SPEC_NODE_ID: u64 = 0x8FC7_7724_01CD_0696;
+    const SPEC_COMPRESSED_ID: [u8; COMPRESSED_FABRIC_ID_LEN] =
+        [0x87, 0xe1, 0xb0, 0x04, 0xe2, 0x35, 0xa1, 0x30];
+
+    #[test]
+    /// The operational instance name and TXT records, for the fabric of the spec's example
+    fn operational_service() {
+        let compressed_id = Fabric::get_compressed_id(&SPEC_ROOT_PUBKEY, SPEC_FABRIC_ID).unwrap();
+        assert_eq!(compressed_id, SPEC_COMPRESSED_ID);
+
+        let name = Fabric::get_mdns_service_name(&compressed_id, SPEC_NODE_ID);
+        assert_eq!(name, "87E1B004E235A130-8FC7772401CD0696");
+
+        let dev_att = BasicInfoConfig {
+            vid: 0xFFF1,
+            pid: 0x8000,
+            device_name: "Test",
+            ..Default::default()
+        };
+
+        ServiceMode::Commissioned
+            .service(&dev_att, 5540, &name, |service| {
+                assert_eq!(service.name, "87E1B004E235A130-8FC7772401CD0696");
+                assert_eq!(service.service, "_matter");
+                assert_eq!(service.protocol, "_tcp");
+                assert_eq!(service.port, 5540);
+                assert!(service.service_subtypes.is_empty());
+                assert_eq!(
+                    service.txt_kvs,
+                    &[("SII", "5000"), ("SAI", "300"), ("SAT", "4000"), ("T", "0")]
+                );
+
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    /// The compressed fabric ID is derived from the root public key and the fabric ID of the
+    /// NOC, and prefixes the operational instance name
+    fn compressed_id() {
+        // The test certificates, with the root public key, the fabric ID and the node ID
+        // of the spec's example. Their signatures are not verified by the fabric.
+        let mut root_ca = heapless::Vec::from_slice(&RCA1_SUCCESS).unwrap();
+        // The public key is the octet string of context tag 9
+        let at = root_ca
+            .windows(3)
+            .position(|w| w == [0x30, 0x09, 0x41])
+            .unwrap()
+            + 3;
+        root_ca[at..at + SPEC_ROOT_PUBKEY.len()].copy_from_slice(&SPEC_ROOT_PUBKEY);
+
+        // The subject is the list of context tag 6
+        let subject = NOC1_SUCCESS
+            .windows(2)
+            .position(|w| w == [0x37, 0x06])
+            .unwrap()
+            + 2;
+        let subject_end = subject
+            + NOC1_SUCCESS[subject..]
+                .iter()
+                .position(|b| *b == 0x18)
+                .unwrap();
+        let mut noc = heapless::Vec::<u8, MAX_CERT_TLV_LEN>::new();
+        noc.extend_from_slice(&NOC1_SUCCESS[..subject]).unwrap();
+        noc.extend_from_slice(&[0x27, 0x11]).unwrap();
+        noc.extend_from_slice(&SPEC_NODE_ID.to_le_bytes()).unwrap();
+        noc.extend_from_slice(&[0x27, 0x15]).unwrap();
+        noc.extend_from_slice(&SPEC_FABRIC_ID.to_le_bytes())
+            .unwrap();
+        noc.extend_from_slice(&NOC1_SUCCESS[subject_end..]).unwrap();
+
+        let fabric = Fabric::new(
+            KeyPair::new(seq_rand).unwrap(),
+            root_ca,
+            None,
+            noc,
+            &[0x11; 16],
+            0xFFF1,
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(fabric.get_fabric_id(), SPEC_FABRIC_ID);
+        assert_eq!(fabric.compressed_id(), SPEC_COMPRESSED_ID);
+        assert_eq!(
+            fabric.mdns_service_name(),
+            "87E1B004E235A130-8FC7772401CD0696"
+        );
+    }
+
+    #[test]
+    /// The operational service is republished under the same name, and withdrawn with the fabric
+    fn advertise_and_withdraw() {
+        let mdns = MockMdns {
+            services: RefCell::new(heapless::Vec::new()),
+        };
+
+        let fabric = test_fabric();
+
+        let mut fabric_mgr = FabricMgr::new();
+        let fab_idx = fabric_mgr.add(fabric, &mdns).unwrap();
+
+        let name: heapless::String<40> = fabric_mgr
+            .get_fabric(fab_idx as _)
+            .unwrap()
+            .unwrap()
+            .mdns_service_name()
+            .into();
+        assert_eq!(mdns.services.borrow().as_slice(), &[name.clone()]);
+
+        fabric_mgr.advertise(fab_idx, &mdns).unwrap();
+        assert_eq!(mdns.services.borrow().as_slice(), &[name]);
+
+        let mut acl_mgr = AclMgr::new();
+        let mut group_mgr = GroupMgr::new();
+        let mut subscription_mgr = SubscriptionMgr::new(&DummyClock);
+        let mut session_mgr = SessionMgr::new(dummy_epoch, dummy_rand);
+        let mut binding_mgr = BindingMgr::new();
+        let mut resumption_mgr = ResumptionMgr::new();
+        fabric_mgr
+            .remove(
+                fab_idx,
+                &mut FabricScoped {
+                    acl_mgr: &mut acl_mgr,
+                    group_mgr: &mut group_mgr,
+                    subscription_mgr: &mut subscription_mgr,
+                    session_mgr: &mut session_mgr,
+                    binding_mgr: &mut binding_mgr,
+                    ota_provider_mgr: &mut OtaProviderMgr::new(),
+                    icd_client_mgr: &mut IcdClientMgr::new(),
+                    resumption_mgr: &mut resumption_mgr,
+                },
+                None,
+                &mdns,
+            )
+            .unwrap();
+        assert!(mdns.services.borrow().is_empty());
+
+        assert!(fabric_mgr.advertise(fab_idx, &mdns).is_err());
+    }
+
+    #[test]
+    /// Removing a fabric removes its ACLs, group keys, subscriptions and sessions, and
+    /// leaves those of the other fabrics alone
+    fn remove_cascades() {
+        let mut fabric_mgr = FabricMgr::new();
+        let mut acl_mgr = AclMgr::new();
+        let mut group_mgr = GroupMgr::new();
+        let mut subscription_mgr = SubscriptionMgr::new(&DummyClock);
+        let mut session_mgr = SessionMgr::new(dummy_epoch, dummy_rand);
+        let mut binding_mgr = BindingMgr::new();
+        let mut resumption_mgr = ResumptionMgr::new();
+
+        let fab_idxs = [
+            fabric_mgr.add(test_fabric(), &DummyMdns).unwrap(),
+            fabric_mgr.add(test_fabric(), &DummyMdns).unwrap(),
+        ];
+
+        for (index, fab_idx) in fab_idxs.into_iter().enumerate() {
+            let sess_id = 10 + index as u16;
+
+            acl_mgr
+                .add(AclEntry::new(fab_idx, Privilege::ADMIN, AuthMode::Case))
+                .unwrap();
+            group_mgr
+                .set_key_set(GroupKeySet {
+                    fab_idx,
+                    key_set_id: 1,
+                    policy: 0,
+                    epoch_keys: heapless::Vec::new(),
+                })
+                .unwrap();
+            subscription_mgr
+                .add(fab_idx, 0x1234, sess_id, &SubscribeReq::new(false, 0, 60))
+                .unwrap();
+            session_mgr
+                .clone_session(&CloneData::new(
+                    0x5678,
+                    0x1234,
+                    sess_id,
+                    sess_id,
+                    Address::default(),
+                    SessionMode::Case(CaseDetails::new(fab_idx, &[0; 3])),
+                ))
+                .unwrap();
+        }
+
+        fabric_mgr
+            .remove(
+                fab_idxs[0],
+                &mut FabricScoped {
+                    acl_mgr: &mut acl_mgr,
+                    group_mgr: &mut group_mgr,
+                    subscription_mgr: &mut subscription_mgr,
+                    session_mgr: &mut session_mgr,
+                    binding_mgr: &mut binding_mgr,
+                    ota_provider_mgr: &mut OtaProviderMgr::new(),
+                    icd_client_mgr: &mut IcdClientMgr::new(),
+                    resumption_mgr: &mut resumption_mgr,
+                },
+                None,
+                &DummyMdns,
+            )
+            .unwrap();
+
+        assert!(fabric_mgr.get_fabric(fab_idxs[0] as _).unwrap().is_none());
+        assert!(fabric_mgr.get_fabric(fab_idxs[1] as _).unwrap().is_some());
+
+        let mut acls = [0; 2];
+        acl_mgr
+            .for_each_acl(|entry| {
+                acls[(entry.fab_idx.unwrap() - 1) as usize] += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(acls, [0, 1]);
+
+        assert_eq!(group_mgr.key_sets(fab_idxs[0]).count(), 0);
+        assert_eq!(group_mgr.key_sets(fab_idxs[1]).count(), 1);
+
+        assert!(subscription_mgr
+            .iter()
+            .all(|sub| sub.fab_idx == fab_idxs[1]));
+        assert_eq!(subscription_mgr.iter().count(), 1);
+
+        assert!(session_mgr.get_with_id(10).is_none());
+        assert!(session_mgr.get_with_id(11).is_some());
+
+        // The removed fabric is no longer there to be removed
+        assert!(fabric_mgr
+            .remove(
+                fab_idxs[0],
+                &mut FabricScoped {
+                    acl_mgr: &mut acl_mgr,
+                    group_mgr: &mut group_mgr,
+                    subscription_mgr: &mut subscription_mgr,
+                    session_mgr: &mut session_mgr,
+                    binding_mgr: &mut binding_mgr,
+                    ota_provider_mgr: &mut OtaProviderMgr::new(),
+                    icd_client_mgr: &mut IcdClientMgr::new(),
+                    resumption_mgr: &mut resumption_mgr,
+                },
+                None,
+                &DummyMdns,
+            )
+            .is_err());
+    }
+}
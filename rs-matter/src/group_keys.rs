@@ -46,6 +46,18 @@ impl KeySet {
             .map_err(|_| ErrorCode::NoSpace.into())
     }
 
+    /// The session ID of the group messages encrypted with the operational key,
+    /// which allows for quickly finding the candidate keys of a received message
+    pub fn group_session_id(&self) -> Result<u16, Error> {
+        const GRP_KEY_HASH_INFO: [u8; 12] = *b"GroupKeyHash";
+
+        let mut hash = [0; 2];
+        crypto::hkdf_sha256(&[], &self.op_key, &GRP_KEY_HASH_INFO, &mut hash)
+            .map_err(|_| Error::from(ErrorCode::NoSpace))?;
+
+        Ok(u16::from_be_bytes(hash))
+    }
+
     pub fn op_key(&self) -> &[u8] {
         &self.op_key
     }
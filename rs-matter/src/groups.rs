@@ -0,0 +1,600 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use log::info;
+
+use crate::{
+    crypto::SYMM_KEY_LEN_BYTES,
+    data_model::objects::EndptId,
+    error::{Error, ErrorCode},
+    fabric::MAX_SUPPORTED_FABRICS,
+    group_keys::KeySet,
+    tlv::{self, FromTLV, TLVElement, TLVList, TLVWriter, TagType, ToTLV},
+    utils::writebuf::WriteBuf,
+};
+
+/// The number of group key sets we support per fabric, on top of the IPK
+pub const MAX_GROUP_KEY_SETS_PER_FABRIC: usize = 3;
+
+/// The number of groups we support per fabric
+pub const MAX_GROUPS_PER_FABRIC: usize = 4;
+
+/// A group key set carries up to 3 epoch keys, so that keys can be rotated
+pub const MAX_EPOCH_KEYS: usize = 3;
+
+/// The number of endpoints which can be members of the same group
+pub const MAX_GROUP_ENDPOINTS: usize = 4;
+
+pub const MAX_GROUP_NAME_LEN: usize = 16;
+
+const MAX_GROUP_KEY_SETS: usize = MAX_GROUP_KEY_SETS_PER_FABRIC * MAX_SUPPORTED_FABRICS;
+const MAX_GROUPS: usize = MAX_GROUPS_PER_FABRIC * MAX_SUPPORTED_FABRICS;
+
+/// An epoch key, along with the operational key derived from it
+#[derive(Debug, FromTLV, ToTLV)]
+pub struct EpochKey {
+    /// Microseconds since the Matter epoch, from which on the key is to be used for sending
+    pub start_time: u64,
+    keys: KeySet,
+    session_id: u16,
+}
+
+impl EpochKey {
+    pub fn new(start_time: u64, epoch_key: &[u8], compressed_id: &[u8]) -> Result<Self, Error> {
+        if epoch_key.len() != SYMM_KEY_LEN_BYTES {
+            Err(ErrorCode::InvalidKeyLength)?;
+        }
+
+        let keys = KeySet::new(epoch_key, compressed_id)?;
+        let session_id = keys.group_session_id()?;
+
+        Ok(Self {
+            start_time,
+            keys,
+            session_id,
+        })
+    }
+
+    pub fn op_key(&self) -> &[u8] {
+        self.keys.op_key()
+    }
+
+    pub fn session_id(&self) -> u16 {
+        self.session_id
+    }
+}
+
+#[derive(Debug)]
+pub struct GroupKeySet {
+    pub fab_idx: u8,
+    pub key_set_id: u16,
+    /// The group key security policy of the spec (0 - TrustFirst, 1 - CacheAndSync)
+    pub policy: u8,
+    pub epoch_keys: heapless::Vec<EpochKey, MAX_EPOCH_KEYS>,
+}
+
+impl ToTLV for GroupKeySet {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag: TagType) -> Result<(), Error> {
+        tw.start_struct(tag)?;
+        tw.u8(TagType::Context(0), self.fab_idx)?;
+        tw.u16(TagType::Context(1), self.key_set_id)?;
+        tw.u8(TagType::Context(2), self.policy)?;
+        self.epoch_keys.as_slice().to_tlv(tw, TagType::Context(3))?;
+        tw.end_container()
+    }
+}
+
+impl<'a> FromTLV<'a> for GroupKeySet {
+    fn from_tlv(t: &TLVElement<'a>) -> Result<Self, Error> {
+        let mut epoch_keys = heapless::Vec::new();
+        tlv::from_tlv(&mut epoch_keys, &t.find_tag(3)?)?;
+
+        Ok(Self {
+            fab_idx: t.find_tag(0)?.u8()?,
+            key_set_id: t.find_tag(1)?.u16()?,
+            policy: t.find_tag(2)?.u8()?,
+            epoch_keys,
+        })
+    }
+}
+
+/// Which key set is used for the messages of a group
+#[derive(Debug, Clone, PartialEq, Eq, FromTLV, ToTLV)]
+pub struct GroupKeyMapEntry {
+    pub fab_idx: u8,
+    pub group_id: u16,
+    pub key_set_id: u16,
+}
+
+/// A group, along with the local endpoints which are members of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub fab_idx: u8,
+    pub group_id: u16,
+    pub endpoints: heapless::Vec<EndptId, MAX_GROUP_ENDPOINTS>,
+    pub name: heapless::String<MAX_GROUP_NAME_LEN>,
+}
+
+impl ToTLV for GroupEntry {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag: TagType) -> Result<(), Error> {
+        tw.start_struct(tag)?;
+        tw.u8(TagType::Context(0), self.fab_idx)?;
+        tw.u16(TagType::Context(1), self.group_id)?;
+        self.endpoints.as_slice().to_tlv(tw, TagType::Context(2))?;
+        self.name.to_tlv(tw, TagType::Context(3))?;
+        tw.end_container()
+    }
+}
+
+impl<'a> FromTLV<'a> for GroupEntry {
+    fn from_tlv(t: &TLVElement<'a>) -> Result<Self, Error> {
+        let mut endpoints = heapless::Vec::new();
+        tlv::from_tlv(&mut endpoints, &t.find_tag(2)?)?;
+
+        Ok(Self {
+            fab_idx: t.find_tag(0)?.u8()?,
+            group_id: t.find_tag(1)?.u16()?,
+            endpoints,
+            name: FromTLV::from_tlv(&t.find_tag(3)?)?,
+        })
+    }
+}
+
+/// The group key sets, the mapping of groups to key sets and the group table of the node,
+/// all of which are persisted
+pub struct GroupMgr {
+    key_sets: heapless::Vec<GroupKeySet, MAX_GROUP_KEY_SETS>,
+    key_map: heapless::Vec<GroupKeyMapEntry, MAX_GROUPS>,
+    groups: heapless::Vec<GroupEntry, MAX_GROUPS>,
+    changed: bool,
+}
+
+impl GroupMgr {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            key_sets: heapless::Vec::new(),
+            key_map: heapless::Vec::new(),
+            groups: heapless::Vec::new(),
+            changed: false,
+        }
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        let root = TLVList::new(data).iter().next().ok_or(ErrorCode::Invalid)?;
+
+        tlv::from_tlv(&mut self.key_sets, &root.find_tag(0)?)?;
+        tlv::from_tlv(&mut self.key_map, &root.find_tag(1)?)?;
+        tlv::from_tlv(&mut self.groups, &root.find_tag(2)?)?;
+        self.changed = false;
+
+        Ok(())
+    }
+
+    pub fn store<'a>(&mut self, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        if self.changed {
+            let mut wb = WriteBuf::new(buf);
+            let mut tw = TLVWriter::new(&mut wb);
+
+            tw.start_struct(TagType::Anonymous)?;
+            self.key_sets
+                .as_slice()
+                .to_tlv(&mut tw, TagType::Context(0))?;
+            self.key_map
+                .as_slice()
+                .to_tlv(&mut tw, TagType::Context(1))?;
+            self.groups
+                .as_slice()
+                .to_tlv(&mut tw, TagType::Context(2))?;
+            tw.end_container()?;
+
+            self.changed = false;
+
+            let len = tw.get_tail();
+
+            Ok(Some(&buf[..len]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Add a key set, or replace the one of the fabric with the same key set ID
+    pub fn set_key_set(&mut self, key_set: GroupKeySet) -> Result<(), Error> {
+        if let Some(existing) = self
+            .key_sets
+            .iter_mut()
+            .find(|ks| ks.fab_idx == key_set.fab_idx && ks.key_set_id == key_set.key_set_id)
+        {
+            *existing = key_set;
+        } else {
+            if self
+                .key_sets
+                .iter()
+                .filter(|ks| ks.fab_idx == key_set.fab_idx)
+                .count()
+                >= MAX_GROUP_KEY_SETS_PER_FABRIC
+            {
+                Err(ErrorCode::ResourceExhausted)?;
+            }
+
+            self.key_sets
+                .push(key_set)
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+        }
+
+        self.changed = true;
+
+        Ok(())
+    }
+
+    pub fn key_set(&self, fab_idx: u8, key_set_id: u16) -> Option<&GroupKeySet> {
+        self.key_sets
+            .iter()
+            .find(|ks| ks.fab_idx == fab_idx && ks.key_set_id == key_set_id)
+    }
+
+    /// Remove the key set, along with the mappings of groups to it
+    pub fn remove_key_set(&mut self, fab_idx: u8, key_set_id: u16) -> Result<(), Error> {
+        let index = self
+            .key_sets
+            .iter()
+            .position(|ks| ks.fab_idx == fab_idx && ks.key_set_id == key_set_id)
+            .ok_or(ErrorCode::NotFound)?;
+
+        self.key_sets.swap_remove(index);
+        self.key_map
+            .retain(|e| e.fab_idx != fab_idx || e.key_set_id != key_set_id);
+
+        self.changed = true;
+
+        Ok(())
+    }
+
+    pub fn key_sets(&self, fab_idx: u8) -> impl Iterator<Item = &GroupKeySet> {
+        self.key_sets.iter().filter(move |ks| ks.fab_idx == fab_idx)
+    }
+
+    pub fn key_map(&self) -> impl Iterator<Item = &GroupKeyMapEntry> {
+        self.key_map.iter()
+    }
+
+    /// Remove all mappings of groups to key sets of the fabric
+    pub fn clear_key_map(&mut self, fab_idx: u8) {
+        let len = self.key_map.len();
+
+        self.key_map.retain(|e| e.fab_idx != fab_idx);
+
+        self.changed |= self.key_map.len() != len;
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = &GroupEntry> {
+        self.groups.iter()
+    }
+
+    /// Use the provided key set for the messages of the group
+    pub fn map_group_key(
+        &mut self,
+        fab_idx: u8,
+        group_id: u16,
+        key_set_id: u16,
+    ) -> Result<(), Error> {
+        let entry = GroupKeyMapEntry {
+            fab_idx,
+            group_id,
+            key_set_id,
+        };
+
+        if let Some(existing) = self
+            .key_map
+            .iter_mut()
+            .find(|e| e.fab_idx == fab_idx && e.group_id == group_id)
+        {
+            *existing = entry;
+        } else {
+            self.key_map
+                .push(entry)
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+        }
+
+        self.changed = true;
+
+        Ok(())
+    }
+
+    /// Make the endpoint a member of the group, adding the group if necessary
+    pub fn add_group_endpoint(
+        &mut self,
+        fab_idx: u8,
+        group_id: u16,
+        endpoint: EndptId,
+        name: &str,
+    ) -> Result<(), Error> {
+        let name = name.try_into().map_err(|_| ErrorCode::InvalidData)?;
+
+        let index = if let Some(index) = self
+            .groups
+            .iter()
+            .position(|g| g.fab_idx == fab_idx && g.group_id == group_id)
+        {
+            index
+        } else {
+            self.groups
+                .push(GroupEntry {
+                    fab_idx,
+                    group_id,
+                    endpoints: heapless::Vec::new(),
+                    name: heapless::String::new(),
+                })
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+
+            self.groups.len() - 1
+        };
+
+        let group = &mut self.groups[index];
+
+        if !group.endpoints.contains(&endpoint) {
+            group
+                .endpoints
+                .push(endpoint)
+                .map_err(|_| ErrorCode::ResourceExhausted)?;
+        }
+
+        group.name = name;
+
+        self.changed = true;
+
+        info!(
+            "Endpoint {} is now a member of group {} on fabric {}",
+            endpoint, group_id, fab_idx
+        );
+
+        Ok(())
+    }
+
+    pub fn group(&self, fab_idx: u8, group_id: u16) -> Option<&GroupEntry> {
+        self.groups
+            .iter()
+            .find(|g| g.fab_idx == fab_idx && g.group_id == group_id)
+    }
+
+    /// Remove the endpoint from the group, removing the group once it has no members left
+    pub fn remove_group_endpoint(
+        &mut self,
+        fab_idx: u8,
+        group_id: u16,
+        endpoint: EndptId,
+    ) -> Result<(), Error> {
+        let group = self
+            .groups
+            .iter_mut()
+            .find(|g| g.fab_idx == fab_idx && g.group_id == group_id)
+            .ok_or(ErrorCode::NotFound)?;
+
+        let index = group
+            .endpoints
+            .iter()
+            .position(|ep| *ep == endpoint)
+            .ok_or(ErrorCode::NotFound)?;
+        group.endpoints.swap_remove(index);
+
+        self.groups.retain(|g| !g.endpoints.is_empty());
+
+        self.changed = true;
+
+        Ok(())
+    }
+
+    /// Remove the endpoint from all groups of the fabric
+    pub fn remove_endpoint(&mut self, fab_idx: u8, endpoint: EndptId) {
+        for group in self.groups.iter_mut().filter(|g| g.fab_idx == fab_idx) {
+            let len = group.endpoints.len();
+
+            group.endpoints.retain(|ep| *ep != endpoint);
+
+            self.changed |= group.endpoints.len() != len;
+        }
+
+        self.groups.retain(|g| !g.endpoints.is_empty());
+    }
+
+    pub fn is_member(&self, fab_idx: u8, group_id: u16, endpoint: EndptId) -> bool {
+        self.groups.iter().any(|g| {
+            g.fab_idx == fab_idx && g.group_id == group_id && g.endpoints.contains(&endpoint)
+        })
+    }
+
+    /// Find the fabric and the operational key of a received group message,
+    /// based on its group session ID and its destination group
+    pub fn find_op_key(
+        &self,
+        session_id: u16,
+        group_id: u16,
+    ) -> Option<(u8, [u8; SYMM_KEY_LEN_BYTES])> {
+        // TODO: Try all candidates, in case more than one key has the session ID
+        self.key_map
+            .iter()
+            .filter(|e| e.group_id == group_id)
+            .filter_map(|e| self.key_set(e.fab_idx, e.key_set_id))
+            .flat_map(|ks| {
+                ks.epoch_keys
+                    .iter()
+                    .filter(|key| key.session_id == session_id)
+                    .map(move |key| (ks.fab_idx, key))
+            })
+            .map(|(fab_idx, key)| {
+                let mut op_key = [0; SYMM_KEY_LEN_BYTES];
+                op_key.copy_from_slice(key.op_key());
+
+                (fab_idx, op_key)
+            })
+            .next()
+    }
+
+    /// Find the operational key of a received group message, based on its group session ID alone
+    ///
+    /// This is for messages with an obfuscated header, where the destination group is only
+    /// known once the header is deobfuscated with the privacy key derived from this key.
+    pub fn find_op_key_by_session(&self, session_id: u16) -> Option<[u8; SYMM_KEY_LEN_BYTES]> {
+        self.key_sets
+            .iter()
+            .flat_map(|ks| ks.epoch_keys.iter())
+            .find(|key| key.session_id == session_id)
+            .map(|key| {
+                let mut op_key = [0; SYMM_KEY_LEN_BYTES];
+                op_key.copy_from_slice(key.op_key());
+
+                op_key
+            })
+    }
+
+    /// The epoch key to encrypt the messages sent to the group with, which is
+    /// the one with the latest start time
+    pub fn send_key(&self, fab_idx: u8, group_id: u16) -> Option<&EpochKey> {
+        let entry = self
+            .key_map
+            .iter()
+            .find(|e| e.fab_idx == fab_idx && e.group_id == group_id)?;
+
+        self.key_set(fab_idx, entry.key_set_id)?
+            .epoch_keys
+            .iter()
+            .max_by_key(|key| key.start_time)
+    }
+
+    pub fn remove_for_fabric(&mut self, fab_idx: u8) {
+        let len = self.key_sets.len() + self.key_map.len() + self.groups.len();
+
+        self.key_sets.retain(|ks| ks.fab_idx != fab_idx);
+        self.key_map.retain(|e| e.fab_idx != fab_idx);
+        self.groups.retain(|g| g.fab_idx != fab_idx);
+
+        self.changed |= self.key_sets.len() + self.key_map.len() + self.groups.len() != len;
+    }
+}
+
+impl Default for GroupMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EpochKey, GroupKeySet, GroupMgr};
+
+    const COMPRESSED_ID: [u8; 8] = [0x87, 0xe1, 0xb0, 0x04, 0xe2, 0x35, 0xa1, 0x30];
+
+    fn key_set(fab_idx: u8, key_set_id: u16, epoch_key: &[u8]) -> GroupKeySet {
+        let mut epoch_keys = heapless::Vec::new();
+        epoch_keys
+            .push(EpochKey::new(1, epoch_key, &COMPRESSED_ID).unwrap())
+            .unwrap();
+
+        GroupKeySet {
+            fab_idx,
+            key_set_id,
+            policy: 0,
+            epoch_keys,
+        }
+    }
+
+    #[test]
+    fn test_op_key_derivation() {
+        // Reference values, as computed with a standalone HKDF-SHA256 implementation
+        let key = EpochKey::new(
+            1,
+            &[
+                0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xab, 0xac, 0xad,
+                0xae, 0xaf,
+            ],
+            &COMPRESSED_ID,
+        )
+        .unwrap();
+
+        assert_eq!(
+            key.op_key(),
+            &[
+                0x89, 0xd6, 0x9b, 0xc7, 0x34, 0xfb, 0x54, 0xf8, 0xe8, 0x28, 0x9e, 0xbf, 0xa1, 0x09,
+                0x47, 0x42
+            ]
+        );
+        assert_eq!(key.session_id(), 0x6ee8);
+
+        assert!(EpochKey::new(1, &[0; 8], &COMPRESSED_ID).is_err());
+    }
+
+    #[test]
+    fn test_find_op_key() {
+        let mut mgr = GroupMgr::new();
+
+        mgr.set_key_set(key_set(1, 42, &[1; 16])).unwrap();
+        mgr.map_group_key(1, 0x0101, 42).unwrap();
+        mgr.add_group_endpoint(1, 0x0101, 1, "Lights").unwrap();
+
+        let session_id = mgr.key_set(1, 42).unwrap().epoch_keys[0].session_id();
+
+        assert_eq!(mgr.find_op_key(session_id, 0x0101).map(|(f, _)| f), Some(1));
+        assert!(mgr.find_op_key(session_id, 0x0102).is_none());
+        assert_eq!(
+            mgr.send_key(1, 0x0101).map(|k| k.session_id()),
+            Some(session_id)
+        );
+        assert!(mgr.is_member(1, 0x0101, 1));
+        assert!(!mgr.is_member(1, 0x0101, 0));
+
+        mgr.remove_for_fabric(1);
+        assert!(mgr.find_op_key(session_id, 0x0101).is_none());
+        assert!(!mgr.is_member(1, 0x0101, 1));
+    }
+
+    #[test]
+    /// The key sets, the key map and the groups are stored, and loaded back by a new manager
+    fn test_reload() {
+        let mut mgr = GroupMgr::new();
+
+        mgr.set_key_set(key_set(1, 42, &[1; 16])).unwrap();
+        mgr.set_key_set(key_set(2, 43, &[2; 16])).unwrap();
+        mgr.map_group_key(1, 0x0101, 42).unwrap();
+        mgr.add_group_endpoint(1, 0x0101, 1, "Lights").unwrap();
+        mgr.add_group_endpoint(1, 0x0101, 2, "Lights").unwrap();
+        assert!(mgr.is_changed());
+
+        let mut buf = [0; 1024];
+        let data = mgr.store(&mut buf).unwrap().unwrap();
+
+        let mut reloaded = GroupMgr::new();
+        reloaded.load(data).unwrap();
+        assert!(!reloaded.is_changed());
+
+        let session_id = mgr.key_set(1, 42).unwrap().epoch_keys[0].session_id();
+        assert_eq!(
+            reloaded.find_op_key(session_id, 0x0101),
+            mgr.find_op_key(session_id, 0x0101)
+        );
+        assert_eq!(reloaded.key_set(2, 43).unwrap().epoch_keys.len(), 1);
+        assert!(reloaded.key_map().eq(mgr.key_map()));
+        assert!(reloaded.groups().eq(mgr.groups()));
+
+        // Nothing is stored when nothing has changed
+        assert!(!mgr.is_changed());
+        assert!(mgr.store(&mut buf).unwrap().is_none());
+    }
+}
@@ -19,17 +19,22 @@ use core::time::Duration;
 
 use crate::{
     acl::Accessor,
+    data_model::{objects::EndptId, subscriptions::Subscription},
     error::*,
-    tlv::{get_root_node_struct, FromTLV, TLVElement, TLVWriter, TagType, ToTLV},
-    transport::{exchange::Exchange, packet::Packet},
+    tlv::{get_root_node_struct, FromTLV, TLVArray, TLVElement, TLVWriter, TagType, ToTLV},
+    transport::{exchange::Exchange, packet::Packet, session::GroupDetails},
     utils::epoch::Epoch,
 };
-use log::error;
+use log::{error, warn};
 use num::{self, FromPrimitive};
 use num_derive::FromPrimitive;
 
-use super::messages::msg::{
-    self, InvReq, ReadReq, StatusResp, SubscribeReq, SubscribeResp, TimedReq, WriteReq,
+use super::messages::{
+    ib::{AttrStatus, EventFilter, EventPath},
+    msg::{
+        self, InvReq, ReadReq, StatusResp, SubscribeReq, SubscribeResp, TimedReq, WriteReq,
+        WriteResp,
+    },
 };
 
 #[macro_export]
@@ -79,10 +84,15 @@ impl From<ErrorCode> for IMStatusCode {
             ErrorCode::CommandNotFound => IMStatusCode::UnsupportedCommand,
             ErrorCode::InvalidAction => IMStatusCode::InvalidAction,
             ErrorCode::InvalidCommand => IMStatusCode::InvalidCommand,
+            ErrorCode::InvalidData => IMStatusCode::InvalidAction,
+            ErrorCode::InvalidDataType => IMStatusCode::InvalidDataType,
+            ErrorCode::ConstraintError => IMStatusCode::ConstraintError,
+            ErrorCode::NotFound => IMStatusCode::NotFound,
             ErrorCode::UnsupportedAccess => IMStatusCode::UnsupportedAccess,
             ErrorCode::Busy => IMStatusCode::Busy,
             ErrorCode::DataVersionMismatch => IMStatusCode::DataVersionMismatch,
             ErrorCode::ResourceExhausted => IMStatusCode::ResourceExhausted,
+            ErrorCode::FailSafeRequired => IMStatusCode::FailSafeRequired,
             _ => IMStatusCode::Failure,
         }
     }
@@ -128,8 +138,30 @@ pub const PROTO_ID_INTERACTION_MODEL: u16 = 0x01;
 // the end of long reads.
 const LONG_READS_TLV_RESERVE_SIZE: usize = 24;
 
+/// The maximum number of commands we accept in a single (batched) invoke request
+pub const MAX_PATHS_PER_INVOKE: usize = 4;
+
+/// The maximum number of attribute statuses a chunked write collects over its chunks
+pub const MAX_CHUNKED_WRITE_STATUSES: usize = 32;
+
+/// The outcome of the chunks of a chunked write received so far.
+///
+/// Every chunk but the last one is only acknowledged with a status response, so the statuses
+/// of all chunks go out together, in the write response to the last one.
+#[derive(Default)]
+pub struct WriteChunks {
+    statuses: heapless::Vec<AttrStatus, MAX_CHUNKED_WRITE_STATUSES>,
+    failed: bool,
+}
+
 impl<'a> ReadReq<'a> {
-    pub fn tx_start<'r, 'p>(&self, tx: &'r mut Packet<'p>) -> Result<TLVWriter<'r, 'p>, Error> {
+    /// Start a new report chunk, with the event reports open if `events` is set,
+    /// or with the attribute reports open otherwise
+    pub fn tx_start<'r, 'p>(
+        &self,
+        tx: &'r mut Packet<'p>,
+        events: bool,
+    ) -> Result<TLVWriter<'r, 'p>, Error> {
         tx.reset();
         tx.set_proto_id(PROTO_ID_INTERACTION_MODEL);
         tx.set_proto_opcode(OpCode::ReportData as u8);
@@ -138,25 +170,33 @@ impl<'a> ReadReq<'a> {
 
         tw.start_struct(TagType::Anonymous)?;
 
-        if self.attr_requests.is_some() {
+        if events {
+            tw.start_array(TagType::Context(msg::ReportDataTag::EventReports as u8))?;
+        } else if self.attr_requests.is_some() {
             tw.start_array(TagType::Context(msg::ReportDataTag::AttributeReports as u8))?;
         }
 
         Ok(tw)
     }
 
-    pub fn tx_finish_chunk(&self, tx: &mut Packet) -> Result<(), Error> {
-        self.complete(tx, true)
+    /// Close the attribute reports of the current chunk and open its event reports.
+    /// Returns `false` if there is no space left in the chunk for that.
+    pub fn tx_start_events(&self, tx: &mut Packet) -> Result<bool, Error> {
+        start_event_reports(tx, self.attr_requests.is_some())
     }
 
-    pub fn tx_finish(&self, tx: &mut Packet) -> Result<(), Error> {
-        self.complete(tx, false)
+    pub fn tx_finish_chunk(&self, tx: &mut Packet, events: bool) -> Result<(), Error> {
+        self.complete(tx, true, events)
     }
 
-    fn complete(&self, tx: &mut Packet<'_>, more_chunks: bool) -> Result<(), Error> {
+    pub fn tx_finish(&self, tx: &mut Packet, events: bool) -> Result<(), Error> {
+        self.complete(tx, false, events)
+    }
+
+    fn complete(&self, tx: &mut Packet<'_>, more_chunks: bool, events: bool) -> Result<(), Error> {
         let mut tw = Self::restore_long_read_space(tx)?;
 
-        if self.attr_requests.is_some() {
+        if events || self.attr_requests.is_some() {
             tw.end_container()?;
         }
 
@@ -242,6 +282,10 @@ impl<'a> InvReq<'a> {
             if timed_tx != timed_request {
                 Interaction::status_response(tx, IMStatusCode::TimedRequestMisMatch)?;
 
+                Ok(None)
+            } else if !self.is_valid_batch() {
+                Interaction::status_response(tx, IMStatusCode::InvalidAction)?;
+
                 Ok(None)
             } else {
                 tx.reset();
@@ -276,6 +320,33 @@ impl<'a> InvReq<'a> {
 
         tw.end_container()
     }
+
+    /// A batch should not carry more commands than what we support, nor two commands
+    /// with the same command reference, as their responses could not be told apart
+    fn is_valid_batch(&self) -> bool {
+        let Some(inv_requests) = self.inv_requests.as_ref() else {
+            return true;
+        };
+
+        if inv_requests.iter().count() > MAX_PATHS_PER_INVOKE {
+            return false;
+        }
+
+        let mut refs = heapless::Vec::<u16, MAX_PATHS_PER_INVOKE>::new();
+
+        for command_ref in inv_requests
+            .iter()
+            .filter_map(|cmd_data| cmd_data.command_ref)
+        {
+            if refs.contains(&command_ref) {
+                return false;
+            }
+
+            refs.push(command_ref).unwrap();
+        }
+
+        true
+    }
 }
 
 impl TimedReq {
@@ -295,10 +366,13 @@ impl TimedReq {
 }
 
 impl<'a> SubscribeReq<'a> {
+    /// Start a new report chunk, with the event reports open if `events` is set,
+    /// or with the attribute reports open otherwise
     pub fn tx_start<'r, 'p>(
         &self,
         tx: &'r mut Packet<'p>,
         subscription_id: u32,
+        events: bool,
     ) -> Result<TLVWriter<'r, 'p>, Error> {
         tx.reset();
         tx.set_proto_id(PROTO_ID_INTERACTION_MODEL);
@@ -313,17 +387,30 @@ impl<'a> SubscribeReq<'a> {
             subscription_id,
         )?;
 
-        if self.attr_requests.is_some() {
+        if events {
+            tw.start_array(TagType::Context(msg::ReportDataTag::EventReports as u8))?;
+        } else if self.attr_requests.is_some() {
             tw.start_array(TagType::Context(msg::ReportDataTag::AttributeReports as u8))?;
         }
 
         Ok(tw)
     }
 
-    pub fn tx_finish_chunk(&self, tx: &mut Packet<'_>, more_chunks: bool) -> Result<(), Error> {
+    /// Close the attribute reports of the current chunk and open its event reports.
+    /// Returns `false` if there is no space left in the chunk for that.
+    pub fn tx_start_events(&self, tx: &mut Packet) -> Result<bool, Error> {
+        start_event_reports(tx, self.attr_requests.is_some())
+    }
+
+    pub fn tx_finish_chunk(
+        &self,
+        tx: &mut Packet<'_>,
+        more_chunks: bool,
+        events: bool,
+    ) -> Result<(), Error> {
         let mut tw = ReadReq::restore_long_read_space(tx)?;
 
-        if self.attr_requests.is_some() {
+        if events || self.attr_requests.is_some() {
             tw.end_container()?;
         }
 
@@ -342,14 +429,19 @@ impl<'a> SubscribeReq<'a> {
         tw.end_container()
     }
 
-    pub fn tx_process_final(&self, tx: &mut Packet, subscription_id: u32) -> Result<(), Error> {
+    pub fn tx_process_final(
+        &self,
+        tx: &mut Packet,
+        subscription_id: u32,
+        max_int: u16,
+    ) -> Result<(), Error> {
         tx.reset();
         tx.set_proto_id(PROTO_ID_INTERACTION_MODEL);
         tx.set_proto_opcode(OpCode::SubscribeResponse as u8);
 
         let mut tw = TLVWriter::new(tx.get_writebuf()?);
 
-        let resp = SubscribeResp::new(subscription_id, 40);
+        let resp = SubscribeResp::new(subscription_id, max_int);
         resp.to_tlv(&mut tw, TagType::Anonymous)
     }
 }
@@ -358,6 +450,7 @@ pub struct ReadDriver<'a, 'r, 'p> {
     exchange: &'r mut Exchange<'a>,
     tx: &'r mut Packet<'p>,
     rx: &'r mut Packet<'p>,
+    events: bool,
     completed: bool,
 }
 
@@ -367,12 +460,13 @@ impl<'a, 'r, 'p> ReadDriver<'a, 'r, 'p> {
             exchange,
             tx,
             rx,
+            events: false,
             completed: false,
         }
     }
 
     fn start(&mut self, req: &ReadReq) -> Result<(), Error> {
-        req.tx_start(self.tx)?;
+        req.tx_start(self.tx, false)?;
 
         Ok(())
     }
@@ -390,23 +484,63 @@ impl<'a, 'r, 'p> ReadDriver<'a, 'r, 'p> {
     }
 
     pub async fn send_chunk(&mut self, req: &ReadReq<'_>) -> Result<bool, Error> {
-        req.tx_finish_chunk(self.tx)?;
+        req.tx_finish_chunk(self.tx, self.events)?;
 
         if exchange_confirm(self.exchange, self.tx, self.rx).await? != IMStatusCode::Success {
             self.completed = true;
             Ok(false)
         } else {
-            req.tx_start(self.tx)?;
+            req.tx_start(self.tx, self.events)?;
 
             Ok(true)
         }
     }
 
     pub async fn complete(&mut self, req: &ReadReq<'_>) -> Result<(), Error> {
-        req.tx_finish(self.tx)?;
+        if !self.completed && !self.report_events(req).await? {
+            return Ok(());
+        }
+
+        req.tx_finish(self.tx, self.events)?;
 
         self.exchange.send_complete(self.tx).await
     }
+
+    /// Append the requested events after the attributes, chunking as necessary.
+    /// Returns `false` if the peer did not accept one of the chunks.
+    async fn report_events(&mut self, req: &ReadReq<'_>) -> Result<bool, Error> {
+        let Some(paths) = &req.event_requests else {
+            return Ok(true);
+        };
+
+        if !req.tx_start_events(self.tx)? {
+            // Not even an empty list of event reports fits in this chunk,
+            // so the events go to the next one
+            if !self.send_chunk(req).await? {
+                return Ok(false);
+            }
+
+            self.events = true;
+            req.tx_start(self.tx, true)?;
+        }
+
+        self.events = true;
+
+        let mut from = 0;
+        while !write_events(
+            self.exchange,
+            self.tx,
+            paths,
+            req.event_filters.as_ref(),
+            &mut from,
+        )? {
+            if !self.send_chunk(req).await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
 }
 
 pub struct WriteDriver<'a, 'r, 'p> {
@@ -445,17 +579,74 @@ impl<'a, 'r, 'p> WriteDriver<'a, 'r, 'p> {
         self.exchange.accessor()
     }
 
+    /// Whether the write is part of a timed interaction, whose window did not expire
+    pub fn is_timed(&self) -> bool {
+        self.timeout.is_some()
+    }
+
     pub fn writer(&mut self) -> Result<TLVWriter<'_, 'p>, Error> {
         Ok(TLVWriter::new(self.tx.get_writebuf()?))
     }
 
-    pub async fn complete(&mut self, req: &WriteReq<'_>) -> Result<(), Error> {
-        if !req.supress_response.unwrap_or_default() {
+    /// Wake up the reporting of the subscriptions to the attributes written
+    pub(crate) fn notify_subscriptions(&self) {
+        self.exchange.notify_subscriptions();
+    }
+
+    /// Put the statuses of the previous chunks of a chunked write back in the response
+    pub fn resume(&mut self, chunks: &WriteChunks) -> Result<(), Error> {
+        let mut tw = self.writer()?;
+
+        for status in &chunks.statuses {
+            status.to_tlv(&mut tw, TagType::Anonymous)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the peer is going to send more chunks of this write.
+    ///
+    /// In that case the statuses of the current chunk are kept in `chunks`, and only a status
+    /// response acknowledging the chunk is prepared in the TX buffer. It is up to the caller
+    /// to exchange it for the next chunk.
+    ///
+    /// If some of the writes `failed`, the response is sent even if the peer asked us to
+    /// suppress it, so that the errors are not lost.
+    pub async fn complete(
+        &mut self,
+        req: &WriteReq<'_>,
+        failed: bool,
+        chunks: &mut WriteChunks,
+    ) -> Result<bool, Error> {
+        chunks.failed |= failed;
+
+        if req.more_chunked.unwrap_or_default() {
+            req.tx_finish(self.tx)?;
+
+            let resp = WriteResp::from_tlv(&get_root_node_struct(self.tx.as_slice())?)?;
+
+            chunks.statuses.clear();
+            for status in resp.write_responses.iter() {
+                chunks
+                    .statuses
+                    .push(status)
+                    .map_err(|_| ErrorCode::NoSpace)?;
+            }
+
+            Interaction::status_response(self.tx, IMStatusCode::Success)?;
+
+            return Ok(true);
+        }
+
+        if chunks.failed || !req.supress_response.unwrap_or_default() {
             req.tx_finish(self.tx)?;
             self.exchange.send_complete(self.tx).await?;
+        } else {
+            // No response, but the peer still needs the request acknowledged
+            self.exchange.acknowledge().await?;
         }
 
-        Ok(())
+        Ok(false)
     }
 }
 
@@ -485,12 +676,33 @@ impl<'a, 'r, 'p> InvokeDriver<'a, 'r, 'p> {
         if req.tx_start(self.tx, self.epoch, self.timeout)?.is_some() {
             Ok(true)
         } else {
-            self.exchange.send_complete(self.tx).await?;
+            if !self.is_group() {
+                self.exchange.send_complete(self.tx).await?;
+            }
 
             Ok(false)
         }
     }
 
+    /// Whether the invoke arrived over groupcast, in which case nothing is ever sent back
+    pub fn is_group(&self) -> bool {
+        self.exchange.id().session_id.is_group
+    }
+
+    /// The group of the invoke, if it arrived over groupcast
+    pub fn group(&self) -> Result<Option<GroupDetails>, Error> {
+        self.exchange
+            .with_session(|sess| Ok(sess.get_group().cloned()))
+    }
+
+    pub fn is_group_member(&self, group: &GroupDetails, endpoint: EndptId) -> bool {
+        self.exchange
+            .matter
+            .group_mgr
+            .borrow()
+            .is_member(group.fab_idx, group.group_id, endpoint)
+    }
+
     pub fn accessor(&self) -> Result<Accessor<'a>, Error> {
         self.exchange.accessor()
     }
@@ -504,9 +716,14 @@ impl<'a, 'r, 'p> InvokeDriver<'a, 'r, 'p> {
     }
 
     pub async fn complete(&mut self, req: &InvReq<'_>) -> Result<(), Error> {
-        if !req.suppress_response.unwrap_or_default() {
+        if self.is_group() {
+            // Neither responses, nor acknowledgements for group messages
+        } else if !req.suppress_response.unwrap_or_default() {
             req.tx_finish(self.tx)?;
             self.exchange.send_complete(self.tx).await?;
+        } else {
+            // No response, but the peer still needs the request acknowledged
+            self.exchange.acknowledge().await?;
         }
 
         Ok(())
@@ -518,13 +735,19 @@ pub struct SubscribeDriver<'a, 'r, 'p> {
     tx: &'r mut Packet<'p>,
     rx: &'r mut Packet<'p>,
     subscription_id: u32,
+    max_int: u16,
+    priming: bool,
+    events: bool,
+    /// The lowest event number to report
+    event_from: u64,
     completed: bool,
+    rejected: Option<IMStatusCode>,
 }
 
 impl<'a, 'r, 'p> SubscribeDriver<'a, 'r, 'p> {
     fn new(
         exchange: &'r mut Exchange<'a>,
-        subscription_id: u32,
+        subscription: &Subscription,
         tx: &'r mut Packet<'p>,
         rx: &'r mut Packet<'p>,
     ) -> Self {
@@ -532,15 +755,71 @@ impl<'a, 'r, 'p> SubscribeDriver<'a, 'r, 'p> {
             exchange,
             tx,
             rx,
-            subscription_id,
+            subscription_id: subscription.id,
+            max_int: subscription.max_int,
+            priming: true,
+            events: false,
+            event_from: 0,
             completed: false,
+            rejected: None,
+        }
+    }
+
+    /// Create a driver which only responds to the subscribe request with the provided
+    /// error status, as the subscription could not be established
+    fn new_rejected(
+        exchange: &'r mut Exchange<'a>,
+        status: IMStatusCode,
+        tx: &'r mut Packet<'p>,
+        rx: &'r mut Packet<'p>,
+    ) -> Self {
+        Self {
+            exchange,
+            tx,
+            rx,
+            subscription_id: 0,
+            max_int: 0,
+            priming: true,
+            events: false,
+            event_from: 0,
+            completed: true,
+            rejected: Some(status),
         }
     }
 
-    fn start(&mut self, req: &SubscribeReq) -> Result<(), Error> {
-        req.tx_start(self.tx, self.subscription_id)?;
+    /// Create a driver for a report of an established subscription, which we push
+    /// to the subscriber on an exchange that we initiated
+    pub fn new_report(
+        exchange: &'r mut Exchange<'a>,
+        subscription: &Subscription,
+        req: &SubscribeReq,
+        tx: &'r mut Packet<'p>,
+        rx: &'r mut Packet<'p>,
+    ) -> Result<Self, Error> {
+        let mut driver = Self::new(exchange, subscription, tx, rx);
+        driver.priming = false;
+        driver.event_from = subscription.event_from;
 
-        Ok(())
+        req.tx_start(driver.tx, driver.subscription_id, false)?;
+
+        Ok(driver)
+    }
+
+    pub fn subscription_id(&self) -> u32 {
+        self.subscription_id
+    }
+
+    async fn start(&mut self, req: &SubscribeReq<'_>) -> Result<bool, Error> {
+        if let Some(status) = self.rejected {
+            Interaction::status_response(self.tx, status)?;
+            self.exchange.send_complete(self.tx).await?;
+
+            Ok(false)
+        } else {
+            req.tx_start(self.tx, self.subscription_id, false)?;
+
+            Ok(true)
+        }
     }
 
     pub fn accessor(&self) -> Result<Accessor<'a>, Error> {
@@ -556,31 +835,77 @@ impl<'a, 'r, 'p> SubscribeDriver<'a, 'r, 'p> {
     }
 
     pub async fn send_chunk(&mut self, req: &SubscribeReq<'_>) -> Result<bool, Error> {
-        req.tx_finish_chunk(self.tx, true)?;
+        req.tx_finish_chunk(self.tx, true, self.events)?;
 
         if exchange_confirm(self.exchange, self.tx, self.rx).await? != IMStatusCode::Success {
             self.completed = true;
             Ok(false)
         } else {
-            req.tx_start(self.tx, self.subscription_id)?;
+            req.tx_start(self.tx, self.subscription_id, self.events)?;
 
             Ok(true)
         }
     }
 
-    pub async fn complete(&mut self, req: &SubscribeReq<'_>) -> Result<(), Error> {
-        if !self.completed {
-            req.tx_finish_chunk(self.tx, false)?;
+    /// Returns `true` if the subscription was successfully established with the peer,
+    /// or - for a report of an established subscription - if the peer accepted the report
+    pub async fn complete(&mut self, req: &SubscribeReq<'_>) -> Result<bool, Error> {
+        if !self.completed && self.report_events(req).await? {
+            req.tx_finish_chunk(self.tx, false, self.events)?;
 
             if exchange_confirm(self.exchange, self.tx, self.rx).await? != IMStatusCode::Success {
                 self.completed = true;
             } else {
-                req.tx_process_final(self.tx, self.subscription_id)?;
-                self.exchange.send_complete(self.tx).await?;
+                if self.priming {
+                    req.tx_process_final(self.tx, self.subscription_id, self.max_int)?;
+                    self.exchange.send_complete(self.tx).await?;
+                } else {
+                    // Nothing follows the status response of the peer, which still
+                    // has to be acknowledged though
+                    self.exchange.acknowledge().await?;
+                }
+
+                return Ok(true);
             }
         }
 
-        Ok(())
+        Ok(false)
+    }
+
+    /// Append the requested events after the attributes, chunking as necessary.
+    /// Returns `false` if the peer did not accept one of the chunks.
+    async fn report_events(&mut self, req: &SubscribeReq<'_>) -> Result<bool, Error> {
+        let Some(paths) = &req.event_requests else {
+            return Ok(true);
+        };
+
+        if !req.tx_start_events(self.tx)? {
+            // Not even an empty list of event reports fits in this chunk,
+            // so the events go to the next one
+            if !self.send_chunk(req).await? {
+                return Ok(false);
+            }
+
+            self.events = true;
+            req.tx_start(self.tx, self.subscription_id, true)?;
+        }
+
+        self.events = true;
+
+        let mut from = self.event_from;
+        while !write_events(
+            self.exchange,
+            self.tx,
+            paths,
+            req.event_filters.as_ref(),
+            &mut from,
+        )? {
+            if !self.send_chunk(req).await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
     }
 }
 
@@ -635,11 +960,11 @@ impl<'a, 'r, 'p> Interaction<'a, 'r, 'p> {
         rx: &'r Packet<'p>,
         tx: &'r mut Packet<'p>,
         rx_status: &'r mut Packet<'p>,
-        subscription_id: S,
+        subscription: S,
         timeout: Option<Duration>,
     ) -> Result<Interaction<'a, 'r, 'p>, Error>
     where
-        S: FnOnce() -> u32,
+        S: FnOnce(&Exchange, &SubscribeReq) -> Result<Subscription, Error>,
     {
         let epoch = exchange.matter.epoch;
 
@@ -667,7 +992,22 @@ impl<'a, 'r, 'p> Interaction<'a, 'r, 'p> {
             }
             OpCode::SubscribeRequest => {
                 let req = SubscribeReq::from_tlv(&get_root_node_struct(rx_data)?)?;
-                let driver = SubscribeDriver::new(exchange, subscription_id(), tx, rx_status);
+                let driver = match subscription(exchange, &req) {
+                    Ok(subscription) => {
+                        SubscribeDriver::new(exchange, &subscription, tx, rx_status)
+                    }
+                    Err(err) if err.code() == ErrorCode::ResourceExhausted => {
+                        warn!("Rejecting subscription: {:?}", err);
+
+                        SubscribeDriver::new_rejected(
+                            exchange,
+                            IMStatusCode::ResourceExhausted,
+                            tx,
+                            rx_status,
+                        )
+                    }
+                    Err(err) => Err(err)?,
+                };
 
                 Ok(Self::Subscribe { req, driver })
             }
@@ -686,10 +1026,7 @@ impl<'a, 'r, 'p> Interaction<'a, 'r, 'p> {
             }
             Self::Write { req, driver } => driver.start(req).await?,
             Self::Invoke { req, driver } => driver.start(req).await?,
-            Self::Subscribe { req, driver } => {
-                driver.start(req)?;
-                true
-            }
+            Self::Subscribe { req, driver } => driver.start(req).await?,
         };
 
         Ok(started)
@@ -731,3 +1068,64 @@ async fn exchange_confirm(
 fn has_timed_out(epoch: Epoch, timeout: Option<Duration>) -> bool {
     timeout.map(|timeout| epoch() > timeout).unwrap_or(false)
 }
+
+/// Close the attribute reports (if any) of the report chunk in `tx` and open its event reports.
+/// Returns `false`, leaving the chunk untouched, if there is no space left for that.
+fn start_event_reports(tx: &mut Packet, attr_reports: bool) -> Result<bool, Error> {
+    // No need to touch the space reserved for finishing the chunk, as the event
+    // reports just take the place of the attribute reports in there
+    let mut tw = TLVWriter::new(tx.get_writebuf()?);
+    let anchor = tw.get_tail();
+
+    let result = (|| {
+        if attr_reports {
+            tw.end_container()?;
+        }
+
+        tw.start_array(TagType::Context(msg::ReportDataTag::EventReports as u8))
+    })();
+
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if e.code() == ErrorCode::NoSpace => {
+            tw.rewind_to(anchor);
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Write to the report chunk in `tx` as many of the requested events (with an event number of
+/// at least `from`) as would fit, advancing `from` past the written ones.
+/// Returns `true` once there are no more events to write.
+fn write_events(
+    exchange: &Exchange,
+    tx: &mut Packet,
+    paths: &TLVArray<EventPath>,
+    filters: Option<&TLVArray<EventFilter>>,
+    from: &mut u64,
+) -> Result<bool, Error> {
+    let accessor = exchange.accessor()?;
+    let event_mgr = exchange.matter.event_mgr.borrow();
+
+    let mut tw = TLVWriter::new(tx.get_writebuf()?);
+
+    while let Some(event) = event_mgr.next(*from, |event| {
+        paths.iter().any(|path| event.matches(&path))
+            && event.is_filtered_in(filters)
+            && event.is_accessible(&accessor)
+    }) {
+        let anchor = tw.get_tail();
+
+        match event.to_tlv(&mut tw, TagType::Anonymous) {
+            Ok(()) => *from = event.number + 1,
+            Err(e) if e.code() == ErrorCode::NoSpace => {
+                tw.rewind_to(anchor);
+                return Ok(false);
+            }
+            Err(e) => Err(e)?,
+        }
+    }
+
+    Ok(true)
+}
@@ -74,7 +74,7 @@ pub mod msg {
 
     use super::ib::{
         self, AttrData, AttrPath, AttrResp, AttrStatus, CmdData, DataVersionFilter, EventFilter,
-        EventPath,
+        EventPath, EventResp,
     };
 
     #[derive(Debug, Default, FromTLV, ToTLV)]
@@ -84,8 +84,8 @@ pub mod msg {
         pub min_int_floor: u16,
         pub max_int_ceil: u16,
         pub attr_requests: Option<TLVArray<'a, AttrPath>>,
-        event_requests: Option<TLVArray<'a, EventPath>>,
-        event_filters: Option<TLVArray<'a, EventFilter>>,
+        pub event_requests: Option<TLVArray<'a, EventPath>>,
+        pub event_filters: Option<TLVArray<'a, EventFilter>>,
         // The Context Tags are discontiguous for some reason
         _dummy: Option<bool>,
         pub fabric_filtered: bool,
@@ -106,6 +106,16 @@ pub mod msg {
             self.attr_requests = Some(TLVArray::new(requests));
             self
         }
+
+        pub fn set_event_requests(mut self, requests: &'a [EventPath]) -> Self {
+            self.event_requests = Some(TLVArray::new(requests));
+            self
+        }
+
+        pub fn set_event_filters(mut self, filters: &'a [EventFilter]) -> Self {
+            self.event_filters = Some(TLVArray::new(filters));
+            self
+        }
     }
 
     #[derive(Debug, FromTLV, ToTLV)]
@@ -168,8 +178,8 @@ pub mod msg {
     #[tlvargs(lifetime = "'a")]
     pub struct ReadReq<'a> {
         pub attr_requests: Option<TLVArray<'a, AttrPath>>,
-        event_requests: Option<TLVArray<'a, EventPath>>,
-        event_filters: Option<TLVArray<'a, EventFilter>>,
+        pub event_requests: Option<TLVArray<'a, EventPath>>,
+        pub event_filters: Option<TLVArray<'a, EventFilter>>,
         pub fabric_filtered: bool,
         pub dataver_filters: Option<TLVArray<'a, DataVersionFilter>>,
     }
@@ -186,6 +196,16 @@ pub mod msg {
             self.attr_requests = Some(TLVArray::new(requests));
             self
         }
+
+        pub fn set_event_requests(mut self, requests: &'a [EventPath]) -> Self {
+            self.event_requests = Some(TLVArray::new(requests));
+            self
+        }
+
+        pub fn set_event_filters(mut self, filters: &'a [EventFilter]) -> Self {
+            self.event_filters = Some(TLVArray::new(filters));
+            self
+        }
     }
 
     #[derive(FromTLV, ToTLV, Debug)]
@@ -194,7 +214,7 @@ pub mod msg {
         pub supress_response: Option<bool>,
         timed_request: Option<bool>,
         pub write_requests: TLVArray<'a, AttrData<'a>>,
-        more_chunked: Option<bool>,
+        pub more_chunked: Option<bool>,
     }
 
     impl<'a> WriteReq<'a> {
@@ -218,8 +238,7 @@ pub mod msg {
     pub struct ReportDataMsg<'a> {
         pub subscription_id: Option<u32>,
         pub attr_reports: Option<TLVArray<'a, AttrResp<'a>>>,
-        // TODO
-        pub event_reports: Option<bool>,
+        pub event_reports: Option<TLVArray<'a, EventResp<'a>>>,
         pub more_chunks: Option<bool>,
         pub suppress_response: Option<bool>,
     }
@@ -227,7 +246,7 @@ pub mod msg {
     pub enum ReportDataTag {
         SubscriptionId = 0,
         AttributeReports = 1,
-        _EventReport = 2,
+        EventReports = 2,
         MoreChunkedMsgs = 3,
         SupressResponse = 4,
     }
@@ -248,7 +267,9 @@ pub mod ib {
     use core::fmt::Debug;
 
     use crate::{
-        data_model::objects::{AttrDetails, AttrId, ClusterId, CmdId, EncodeValue, EndptId},
+        data_model::objects::{
+            AttrDetails, AttrId, ClusterId, CmdId, EncodeValue, EndptId, ListOp,
+        },
         error::{Error, ErrorCode},
         interaction_model::core::IMStatusCode,
         tlv::{FromTLV, Nullable, TLVElement, TLVWriter, TagType, ToTLV},
@@ -270,6 +291,7 @@ pub mod ib {
             Self::Status(CmdStatus {
                 path: cmd_path,
                 status: Status::new(status, cluster_status),
+                command_ref: None,
             })
         }
     }
@@ -295,6 +317,7 @@ pub mod ib {
     pub struct CmdStatus {
         path: CmdPath,
         status: Status,
+        command_ref: Option<u16>,
     }
 
     impl CmdStatus {
@@ -305,8 +328,19 @@ pub mod ib {
                     status,
                     cluster_status,
                 },
+                command_ref: None,
             }
         }
+
+        /// Echo the reference of the command this is a status for (batched commands only)
+        pub fn set_command_ref(mut self, command_ref: Option<u16>) -> Self {
+            self.command_ref = command_ref;
+            self
+        }
+
+        pub fn command_ref(&self) -> Option<u16> {
+            self.command_ref
+        }
     }
 
     #[derive(Debug, Clone, FromTLV, ToTLV)]
@@ -314,17 +348,29 @@ pub mod ib {
     pub struct CmdData<'a> {
         pub path: CmdPath,
         pub data: EncodeValue<'a>,
+        /// Correlates the responses with the commands of a batched invoke
+        pub command_ref: Option<u16>,
     }
 
     impl<'a> CmdData<'a> {
         pub fn new(path: CmdPath, data: EncodeValue<'a>) -> Self {
-            Self { path, data }
+            Self {
+                path,
+                data,
+                command_ref: None,
+            }
+        }
+
+        pub fn set_command_ref(mut self, command_ref: u16) -> Self {
+            self.command_ref = Some(command_ref);
+            self
         }
     }
 
     pub enum CmdDataTag {
         Path = 0,
         Data = 1,
+        Ref = 2,
     }
 
     // Status
@@ -422,29 +468,23 @@ pub mod ib {
     where
         F: FnMut(ListOperation, &TLVElement) -> Result<(), Error>,
     {
-        if let Some(Nullable::NotNull(index)) = attr.list_index {
-            // If list index is valid,
-            //    - this is a modify item or delete item operation
-            if data.null().is_ok() {
-                // If data is NULL, delete item
-                f(ListOperation::DeleteItem(index), data)
-            } else {
-                f(ListOperation::EditItem(index), data)
-            }
-        } else if data.confirm_array().is_ok() {
-            // If data is list, this is either Delete List or OverWrite List operation
-            // in either case, we have to first delete the whole list
-            f(ListOperation::DeleteList, data)?;
-            // Now the data must be a list, that should be added item by item
-
-            let container = data.enter().ok_or(ErrorCode::Invalid)?;
-            for d in container {
-                f(ListOperation::AddItem, &d)?;
+        match ListOp::new(attr.list_index, data) {
+            ListOp::DeleteAt(index) => f(ListOperation::DeleteItem(index as _), data),
+            ListOp::EditAt(index) => f(ListOperation::EditItem(index as _), data),
+            ListOp::Append => f(ListOperation::AddItem, data),
+            ListOp::Replace => {
+                // Either a Delete List or an OverWrite List operation; in either case,
+                // we have to first delete the whole list
+                f(ListOperation::DeleteList, data)?;
+
+                // Now the data must be a list, that should be added item by item
+                let container = data.enter().ok_or(ErrorCode::Invalid)?;
+                for d in container {
+                    f(ListOperation::AddItem, &d)?;
+                }
+
+                Ok(())
             }
-            Ok(())
-        } else {
-            // If data is not a list, this must be an add operation
-            f(ListOperation::AddItem, data)
         }
     }
 
@@ -560,7 +600,7 @@ pub mod ib {
         pub data_ver: u32,
     }
 
-    #[derive(FromTLV, ToTLV, Clone, Debug)]
+    #[derive(Default, FromTLV, ToTLV, Clone, Debug, PartialEq)]
     #[tlvargs(datatype = "list")]
     pub struct EventPath {
         pub node: Option<u64>,
@@ -570,9 +610,79 @@ pub mod ib {
         pub is_urgent: Option<bool>,
     }
 
-    #[derive(FromTLV, ToTLV, Clone, Debug)]
+    impl EventPath {
+        pub fn new(path: &GenericPath) -> Self {
+            Self {
+                endpoint: path.endpoint,
+                cluster: path.cluster,
+                event: path.leaf,
+                ..Default::default()
+            }
+        }
+
+        pub fn to_gp(&self) -> GenericPath {
+            GenericPath::new(self.endpoint, self.cluster, self.event)
+        }
+    }
+
+    #[derive(Default, FromTLV, ToTLV, Clone, Debug, PartialEq)]
     pub struct EventFilter {
         pub node: Option<u64>,
         pub event_min: Option<u64>,
     }
+
+    // Event Response
+    #[derive(Clone, FromTLV, ToTLV, PartialEq, Debug)]
+    #[tlvargs(lifetime = "'a")]
+    pub enum EventResp<'a> {
+        Status(EventStatus),
+        Data(EventData<'a>),
+    }
+
+    impl<'a> EventResp<'a> {
+        pub fn unwrap_data(self) -> EventData<'a> {
+            match self {
+                EventResp::Data(d) => d,
+                _ => {
+                    panic!("No data exists");
+                }
+            }
+        }
+    }
+
+    pub enum EventRespTag {
+        Status = 0,
+        Data = 1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, FromTLV, ToTLV)]
+    pub struct EventStatus {
+        pub path: EventPath,
+        pub status: Status,
+    }
+
+    // Event Data
+    #[derive(Clone, PartialEq, FromTLV, ToTLV, Debug)]
+    #[tlvargs(lifetime = "'a")]
+    pub struct EventData<'a> {
+        pub path: EventPath,
+        pub event_number: u64,
+        pub priority: u8,
+        pub epoch_ts: Option<u64>,
+        pub system_ts: Option<u64>,
+        pub delta_epoch_ts: Option<u64>,
+        pub delta_system_ts: Option<u64>,
+        pub data: EncodeValue<'a>,
+    }
+
+    pub enum EventDataTag {
+        Path = 0,
+        EventNumber = 1,
+        Priority = 2,
+        EpochTs = 3,
+        SystemTs = 4,
+        DeltaEpochTs = 5,
+        DeltaSystemTs = 6,
+        Data = 7,
+    }
 }
@@ -73,6 +73,7 @@
 #![cfg_attr(feature = "nightly", allow(incomplete_features))]
 
 pub mod acl;
+pub mod bdx;
 pub mod cert;
 pub mod codec;
 pub mod core;
@@ -81,13 +82,17 @@ pub mod data_model;
 pub mod error;
 pub mod fabric;
 pub mod group_keys;
+pub mod groups;
 pub mod interaction_model;
 pub mod mdns;
 pub mod pairing;
 pub mod persist;
 pub mod secure_channel;
+#[cfg(test)]
+pub(crate) mod test_support;
 pub mod tlv;
 pub mod transport;
+pub mod udc;
 pub mod utils;
 
 pub use crate::core::*;
@@ -21,6 +21,7 @@ use crate::{data_model::cluster_basic_information::BasicInfoConfig, error::Error
 
 #[cfg(all(feature = "std", target_os = "macos"))]
 pub mod astro;
+pub mod browse;
 pub mod builtin;
 pub mod proto;
 
@@ -79,12 +80,30 @@ impl Mdns for DummyMdns {
 
 pub type Service<'a> = proto::Service<'a>;
 
+/// The TXT records of the operational (`_matter._tcp`) service
+const OPERATIONAL_TXT_KVS: &[(&str, &str)] = &[
+    ("SII", "5000"), /* Session Idle Interval */
+    ("SAI", "300"),  /* Session Active Interval */
+    ("SAT", "4000"), /* Session Active Threshold */
+    ("T", "0"),      /* TCP not supported */
+];
+
+/// The kind of the open commissioning window, as advertised in the CM TXT record
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CommissioningMode {
+    /// The window opened with the onboarding passcode of the device
+    Basic = 1,
+    /// The window opened with a verifier supplied by OpenCommissioningWindow
+    Enhanced = 2,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ServiceMode {
     /// The commissioned state
     Commissioned,
-    /// The commissionable state with the discriminator that should be used
-    Commissionable(u16),
+    /// The commissionable state with the discriminator that should be used,
+    /// and the kind of the commissioning window that is open
+    Commissionable(u16, CommissioningMode),
 }
 
 impl ServiceMode {
@@ -102,22 +121,33 @@ impl ServiceMode {
                 protocol: "_tcp",
                 port: matter_port,
                 service_subtypes: &[],
-                txt_kvs: &[],
+                txt_kvs: OPERATIONAL_TXT_KVS,
             }),
-            ServiceMode::Commissionable(discriminator) => {
+            ServiceMode::Commissionable(discriminator, mode) => {
                 let discriminator_str = Self::get_discriminator_str(*discriminator);
                 let vp = Self::get_vp(dev_att.vid, dev_att.pid);
-
-                let txt_kvs = &[
-                    ("D", discriminator_str.as_str()),
-                    ("CM", "1"),
-                    ("DN", dev_att.device_name),
-                    ("VP", &vp),
-                    ("SII", "5000"), /* Sleepy Idle Interval */
-                    ("SAI", "300"),  /* Sleepy Active Interval */
-                    ("PH", "33"),    /* Pairing Hint */
-                    ("PI", ""),      /* Pairing Instruction */
-                ];
+                let dt: Option<heapless::String<5>> = dev_att.device_type.map(Into::into);
+
+                let mut txt_kvs = heapless::Vec::<_, 9>::new();
+                txt_kvs
+                    .extend_from_slice(&[
+                        ("D", discriminator_str.as_str()),
+                        ("CM", Self::get_cm(*mode)),
+                        ("DN", dev_att.device_name),
+                        ("VP", &vp),
+                    ])
+                    .unwrap();
+                if let Some(dt) = &dt {
+                    txt_kvs.push(("DT", dt.as_str())).unwrap();
+                }
+                txt_kvs
+                    .extend_from_slice(&[
+                        ("SII", "5000"), /* Sleepy Idle Interval */
+                        ("SAI", "300"),  /* Sleepy Active Interval */
+                        ("PH", "33"),    /* Pairing Hint */
+                        ("PI", ""),      /* Pairing Instruction */
+                    ])
+                    .unwrap();
 
                 f(&Service {
                     name,
@@ -127,8 +157,9 @@ impl ServiceMode {
                     service_subtypes: &[
                         &Self::get_long_service_subtype(*discriminator),
                         &Self::get_short_service_type(*discriminator),
+                        "_CM",
                     ],
-                    txt_kvs,
+                    txt_kvs: &txt_kvs,
                 })
             }
         }
@@ -154,6 +185,13 @@ impl ServiceMode {
         discriminator.into()
     }
 
+    fn get_cm(mode: CommissioningMode) -> &'static str {
+        match mode {
+            CommissioningMode::Basic => "1",
+            CommissioningMode::Enhanced => "2",
+        }
+    }
+
     fn get_vp(vid: u16, pid: u16) -> heapless::String<11> {
         let mut vp = heapless::String::new();
 
@@ -184,4 +222,64 @@ mod tests {
         let short = ServiceMode::compute_short_discriminator(discriminator);
         assert_eq!(short, 3);
     }
+
+    type Txt = heapless::Vec<(heapless::String<16>, heapless::String<16>), 9>;
+
+    fn commissionable_txt(mode: CommissioningMode) -> Txt {
+        let dev_att = BasicInfoConfig {
+            vid: 0xFFF1,
+            pid: 0x8000,
+            device_name: "Test",
+            device_type: Some(0x0100),
+            ..Default::default()
+        };
+
+        ServiceMode::Commissionable(840, mode)
+            .service(&dev_att, 5540, "0123456789ABCDEF", |service| {
+                assert_eq!(service.service, "_matterc");
+                assert_eq!(service.protocol, "_udp");
+                assert_eq!(service.service_subtypes, &["_L840", "_S3", "_CM"]);
+
+                Ok(service
+                    .txt_kvs
+                    .iter()
+                    .map(|(k, v)| ((*k).into(), (*v).into()))
+                    .collect())
+            })
+            .unwrap()
+    }
+
+    fn txt(kvs: &[(&str, &str)]) -> Txt {
+        kvs.iter()
+            .map(|(k, v)| ((*k).into(), (*v).into()))
+            .collect()
+    }
+
+    #[test]
+    /// The commissioning window opened with the passcode of the device is advertised as CM=1
+    fn basic_commissioning_txt() {
+        assert_eq!(
+            commissionable_txt(CommissioningMode::Basic),
+            txt(&[
+                ("D", "840"),
+                ("CM", "1"),
+                ("DN", "Test"),
+                ("VP", "65521+32768"),
+                ("DT", "256"),
+                ("SII", "5000"),
+                ("SAI", "300"),
+                ("PH", "33"),
+                ("PI", ""),
+            ])
+        );
+    }
+
+    #[test]
+    /// The commissioning window opened by OpenCommissioningWindow is advertised as CM=2
+    fn enhanced_commissioning_txt() {
+        let kvs = commissionable_txt(CommissioningMode::Enhanced);
+
+        assert!(kvs.contains(&("CM".into(), "2".into())));
+        assert!(!kvs.contains(&("CM".into(), "1".into())));
+    }
 }
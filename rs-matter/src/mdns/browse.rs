@@ -0,0 +1,496 @@
+use core::fmt::Write;
+use core::str::FromStr;
+use core::time::Duration;
+
+use domain::{
+    base::{octets::Octets64, Dname, Message, MessageBuilder, Rtype, ToDname},
+    rdata::{Aaaa, Ptr, Srv, Txt, A},
+};
+use log::{info, trace};
+
+use crate::error::{Error, ErrorCode};
+use crate::transport::network::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::proto::Buf;
+
+/// The service of the commissioners, i.e. of the nodes which accept being asked to
+/// commission another node
+pub const COMMISSIONER_SERVICE: &str = "_matterd._udp.local";
+
+/// The number of commissioners remembered at the same time
+pub const MAX_COMMISSIONERS: usize = 8;
+
+const COMMISSIONER_SUFFIX: &str = "._matterd._udp.local";
+
+type Fqdn = heapless::String<96>;
+
+/// A commissioner discovered over mDNS, along with the fields of its TXT record
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Commissioner {
+    pub instance: heapless::String<64>,
+    pub hostname: heapless::String<64>,
+    pub port: u16,
+    pub ip: Option<IpAddr>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub device_type: Option<u32>,
+    pub device_name: Option<heapless::String<32>>,
+    /// When the commissioner is forgotten, unless it is announced again
+    expires: Duration,
+}
+
+impl Commissioner {
+    fn set_txt(&mut self, key: &str, value: &str) {
+        match key {
+            "VP" => {
+                let (vid, pid) = value
+                    .split_once('+')
+                    .map(|(vid, pid)| (vid, Some(pid)))
+                    .unwrap_or((value, None));
+
+                self.vendor_id = vid.parse().ok();
+                self.product_id = pid.and_then(|pid| pid.parse().ok());
+            }
+            "DT" => self.device_type = value.parse().ok(),
+            "DN" => self.device_name = heapless::String::from_str(value).ok(),
+            _ => (),
+        }
+    }
+}
+
+/// Which commissioners to return; an empty filter matches all of them
+#[derive(Debug, Clone, Default)]
+pub struct CommissionerFilter {
+    pub vendor_id: Option<u16>,
+    pub device_type: Option<u32>,
+}
+
+impl CommissionerFilter {
+    pub fn matches(&self, commissioner: &Commissioner) -> bool {
+        self.vendor_id
+            .map(|vid| commissioner.vendor_id == Some(vid))
+            .unwrap_or(true)
+            && self
+                .device_type
+                .map(|dt| commissioner.device_type == Some(dt))
+                .unwrap_or(true)
+    }
+}
+
+/// The commissioners discovered from the mDNS responses, deduplicated by instance
+/// name and forgotten once the TTL of their PTR record expires
+pub struct CommissionerCache {
+    commissioners: heapless::Vec<Commissioner, MAX_COMMISSIONERS>,
+}
+
+impl CommissionerCache {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            commissioners: heapless::Vec::new(),
+        }
+    }
+
+    /// Build the query looking for the commissioners
+    pub fn query(id: u16, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut message = MessageBuilder::from_target(Buf(buf, 0))?;
+        message.header_mut().set_id(id);
+
+        let mut question = message.question();
+        question.push((
+            Dname::<Octets64>::from_str(COMMISSIONER_SERVICE)?,
+            Rtype::Ptr,
+        ))?;
+
+        Ok(question.finish().1)
+    }
+
+    /// Update the commissioners with the records of an mDNS message, at the provided time
+    ///
+    /// Queries are ignored.
+    pub fn handle(&mut self, data: &[u8], now: Duration) -> Result<(), Error> {
+        let message = Message::from_octets(data)?;
+
+        if !message.header().qr() {
+            return Ok(());
+        }
+
+        self.expire(now);
+
+        let service = Dname::<Octets64>::from_str(COMMISSIONER_SERVICE)?;
+
+        // The PTR records name the instances, the SRV and TXT records of which name their
+        // host, the A and AAAA records of which carry its address, in any order
+        for pass in 0..3 {
+            for section in [message.answer()?, message.additional()?] {
+                for record in section {
+                    let record = record?;
+
+                    match (pass, record.rtype()) {
+                        (0, Rtype::Ptr) if record.owner().name_eq(&service) => {
+                            if let Some(record) = record.to_record::<Ptr<_>>()? {
+                                let fqdn = Self::name(record.data().ptrdname())?;
+                                self.announce(&fqdn, record.ttl(), now)?;
+                            }
+                        }
+                        (1, Rtype::Srv) => {
+                            if let Some(record) = record.to_record::<Srv<_>>()? {
+                                let fqdn = Self::name(record.owner())?;
+                                let hostname = Self::name(record.data().target())?;
+
+                                if let Some(commissioner) = self.find_mut(&fqdn) {
+                                    commissioner.port = record.data().port();
+                                    commissioner.hostname = heapless::String::from_str(&hostname)
+                                        .map_err(|_| ErrorCode::NoSpace)?;
+                                }
+                            }
+                        }
+                        (1, Rtype::Txt) => {
+                            if let Some(record) = record.to_record::<Txt<_>>()? {
+                                let fqdn = Self::name(record.owner())?;
+
+                                if let Some(commissioner) = self.find_mut(&fqdn) {
+                                    for kv in record.data().iter() {
+                                        let Ok(kv) = core::str::from_utf8(kv) else {
+                                            continue;
+                                        };
+
+                                        let (key, value) = kv.split_once('=').unwrap_or((kv, ""));
+                                        commissioner.set_txt(key, value);
+                                    }
+                                }
+                            }
+                        }
+                        (2, Rtype::A) => {
+                            if let Some(record) = record.to_record::<A>()? {
+                                let ip = IpAddr::V4(Ipv4Addr::from(record.data().addr().octets()));
+                                self.set_ip(&Self::name(record.owner())?, ip);
+                            }
+                        }
+                        (2, Rtype::Aaaa) => {
+                            if let Some(record) = record.to_record::<Aaaa>()? {
+                                let ip = IpAddr::V6(Ipv6Addr::from(record.data().addr().octets()));
+                                self.set_ip(&Self::name(record.owner())?, ip);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The commissioners known at the provided time which pass the filter
+    pub fn browse_commissioners<'a>(
+        &'a self,
+        filter: &'a CommissionerFilter,
+        now: Duration,
+    ) -> impl Iterator<Item = &'a Commissioner> + 'a {
+        self.commissioners
+            .iter()
+            .filter(move |commissioner| commissioner.expires > now && filter.matches(commissioner))
+    }
+
+    pub fn expire(&mut self, now: Duration) {
+        self.commissioners
+            .retain(|commissioner| commissioner.expires > now);
+    }
+
+    fn announce(&mut self, fqdn: &str, ttl: u32, now: Duration) -> Result<(), Error> {
+        let Some(instance) = fqdn.strip_suffix(COMMISSIONER_SUFFIX) else {
+            return Ok(());
+        };
+
+        // A TTL of 0 says goodbye
+        if ttl == 0 {
+            self.commissioners
+                .retain(|commissioner| commissioner.instance != instance);
+            return Ok(());
+        }
+
+        let expires = now + Duration::from_secs(ttl as _);
+
+        if let Some(commissioner) = self.find_mut(fqdn) {
+            commissioner.expires = expires;
+        } else {
+            trace!("Discovered commissioner {}", instance);
+
+            if self.commissioners.is_full() {
+                // Make room by forgetting the commissioner which expires first
+                if let Some(index) = self
+                    .commissioners
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, commissioner)| commissioner.expires)
+                    .map(|(index, _)| index)
+                {
+                    info!(
+                        "Forgetting commissioner {}",
+                        self.commissioners[index].instance
+                    );
+                    self.commissioners.remove(index);
+                }
+            }
+
+            self.commissioners
+                .push(Commissioner {
+                    instance: heapless::String::from_str(instance)
+                        .map_err(|_| ErrorCode::NoSpace)?,
+                    expires,
+                    ..Default::default()
+                })
+                .map_err(|_| ErrorCode::NoSpace)?;
+        }
+
+        Ok(())
+    }
+
+    fn find_mut(&mut self, fqdn: &str) -> Option<&mut Commissioner> {
+        let instance = fqdn.strip_suffix(COMMISSIONER_SUFFIX)?;
+
+        self.commissioners
+            .iter_mut()
+            .find(|commissioner| commissioner.instance == instance)
+    }
+
+    fn set_ip(&mut self, hostname: &str, ip: IpAddr) {
+        for commissioner in self
+            .commissioners
+            .iter_mut()
+            .filter(|commissioner| commissioner.hostname == hostname)
+        {
+            commissioner.ip = Some(ip);
+        }
+    }
+
+    /// The name, without its trailing dot
+    fn name<N: core::fmt::Display>(name: N) -> Result<Fqdn, Error> {
+        let mut fqdn = Fqdn::new();
+        write!(fqdn, "{}", name).map_err(|_| ErrorCode::NoSpace)?;
+
+        if fqdn.ends_with('.') {
+            fqdn.pop();
+        }
+
+        Ok(fqdn)
+    }
+}
+
+impl Default for CommissionerCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use crate::error::Error;
+    use crate::mdns::proto::{Host, Services};
+    use crate::mdns::Service;
+    use crate::transport::network::{IpAddr, Ipv4Addr};
+
+    use super::{CommissionerCache, CommissionerFilter};
+
+    struct Commissioners<'a>(&'a [Service<'a>]);
+
+    impl<'a> Services for Commissioners<'a> {
+        type Error = Error;
+
+        fn for_each<F>(&self, mut callback: F) -> Result<(), Error>
+        where
+            F: FnMut(&Service) -> Result<(), Error>,
+        {
+            self.0.iter().try_for_each(&mut callback)
+        }
+    }
+
+    fn commissioner<'a>(name: &'a str, txt_kvs: &'a [(&'a str, &'a str)]) -> Service<'a> {
+        Service {
+            name,
+            service: "_matterd",
+            protocol: "_udp",
+            port: 5550,
+            service_subtypes: &[],
+            txt_kvs,
+        }
+    }
+
+    /// The response which a host announcing the commissioners would send
+    fn respond(
+        cache: &mut CommissionerCache,
+        hostname: &str,
+        ip: [u8; 4],
+        commissioners: &[Service],
+        ttl_sec: u32,
+        now: Duration,
+    ) {
+        let host = Host {
+            id: 0,
+            hostname,
+            ip,
+            ipv6: None,
+        };
+
+        let mut buf = [0; 1500];
+        let len = host
+            .broadcast(Commissioners(commissioners), &mut buf, ttl_sec)
+            .unwrap();
+
+        cache.handle(&buf[..len], now).unwrap();
+    }
+
+    #[test]
+    fn filter_by_vendor() {
+        let mut cache = CommissionerCache::new();
+        let now = Duration::from_secs(10);
+
+        respond(
+            &mut cache,
+            "tv",
+            [192, 168, 1, 10],
+            &[commissioner(
+                "C0FFEE0001",
+                &[
+                    ("VP", "65521+32769"),
+                    ("DT", "35"),
+                    ("DN", "Living Room TV"),
+                ],
+            )],
+            120,
+            now,
+        );
+        respond(
+            &mut cache,
+            "hub",
+            [192, 168, 1, 11],
+            &[commissioner("C0FFEE0002", &[("VP", "4660"), ("DT", "35")])],
+            120,
+            now,
+        );
+
+        let filter = CommissionerFilter {
+            vendor_id: Some(0xfff1),
+            device_type: None,
+        };
+        let mut found = cache.browse_commissioners(&filter, now);
+
+        let tv = found.next().unwrap();
+        assert_eq!(tv.instance, "C0FFEE0001");
+        assert_eq!(tv.hostname, "tv.local");
+        assert_eq!(tv.port, 5550);
+        assert_eq!(tv.ip, Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10))));
+        assert_eq!(tv.product_id, Some(32769));
+        assert_eq!(tv.device_type, Some(35));
+        assert_eq!(tv.device_name.as_deref(), Some("Living Room TV"));
+        assert!(found.next().is_none());
+
+        // Both are video players
+        let filter = CommissionerFilter {
+            vendor_id: None,
+            device_type: Some(35),
+        };
+        assert_eq!(cache.browse_commissioners(&filter, now).count(), 2);
+
+        let filter = CommissionerFilter {
+            vendor_id: Some(4660),
+            device_type: Some(35),
+        };
+        assert_eq!(
+            cache
+                .browse_commissioners(&filter, now)
+                .map(|commissioner| commissioner.instance.as_str())
+                .next(),
+            Some("C0FFEE0002")
+        );
+    }
+
+    #[test]
+    fn dedup_and_expire() {
+        let mut cache = CommissionerCache::new();
+        let all = CommissionerFilter::default();
+
+        let tv = [commissioner("C0FFEE0001", &[("VP", "65521")])];
+
+        respond(
+            &mut cache,
+            "tv",
+            [10, 0, 0, 1],
+            &tv,
+            60,
+            Duration::from_secs(0),
+        );
+        // Announced again, with a new address
+        respond(
+            &mut cache,
+            "tv",
+            [10, 0, 0, 2],
+            &tv,
+            60,
+            Duration::from_secs(30),
+        );
+
+        let mut found = cache.browse_commissioners(&all, Duration::from_secs(80));
+        assert_eq!(
+            found.next().unwrap().ip,
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+        );
+        assert!(found.next().is_none());
+
+        // The second announcement pushed the expiry, but not forever
+        assert_eq!(
+            cache
+                .browse_commissioners(&all, Duration::from_secs(90))
+                .count(),
+            0
+        );
+
+        // A TTL of 0 withdraws a commissioner right away
+        respond(
+            &mut cache,
+            "tv",
+            [10, 0, 0, 2],
+            &tv,
+            60,
+            Duration::from_secs(100),
+        );
+        respond(
+            &mut cache,
+            "tv",
+            [10, 0, 0, 2],
+            &tv,
+            0,
+            Duration::from_secs(101),
+        );
+        assert_eq!(
+            cache
+                .browse_commissioners(&all, Duration::from_secs(101))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn query() {
+        let mut buf = [0; 100];
+        let len = CommissionerCache::query(7, &mut buf).unwrap();
+
+        // A query is not taken for a response
+        let mut cache = CommissionerCache::new();
+        cache.handle(&buf[..len], Duration::ZERO).unwrap();
+        assert_eq!(
+            cache
+                .browse_commissioners(&CommissionerFilter::default(), Duration::ZERO)
+                .count(),
+            0
+        );
+
+        // The header, followed by the question
+        assert_eq!(&buf[..2], &[0, 7]);
+        assert_eq!(&buf[4..6], &[0, 1]);
+        assert_eq!(&buf[12..21], b"\x08_matterd");
+    }
+}
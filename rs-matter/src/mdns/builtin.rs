@@ -1,10 +1,13 @@
-use core::{cell::RefCell, pin::pin};
+use core::{
+    cell::{Cell, RefCell},
+    pin::pin,
+};
 
 use domain::base::name::FromStrError;
 use domain::base::{octets::ParseError, ShortBuf};
 use embassy_futures::select::select;
-use embassy_time::{Duration, Timer};
-use log::info;
+use embassy_time::{Duration, Instant, Timer};
+use log::{info, warn};
 
 use crate::data_model::cluster_basic_information::BasicInfoConfig;
 use crate::error::{Error, ErrorCode};
@@ -13,6 +16,7 @@ use crate::transport::pipe::{Chunk, Pipe};
 use crate::utils::select::{EitherUnwrap, Notification};
 
 use super::{
+    browse::{Commissioner, CommissionerCache, CommissionerFilter, MAX_COMMISSIONERS},
     proto::{Host, Services},
     Service, ServiceMode,
 };
@@ -48,6 +52,9 @@ pub struct MdnsService<'a> {
     dev_det: &'a BasicInfoConfig<'a>,
     matter_port: u16,
     services: RefCell<heapless::Vec<(heapless::String<40>, ServiceMode), 4>>,
+    commissioners: RefCell<CommissionerCache>,
+    /// Whether a query for the commissioners is to be sent
+    query: Cell<bool>,
     notification: Notification,
 }
 
@@ -80,6 +87,8 @@ impl<'a> MdnsService<'a> {
             dev_det,
             matter_port,
             services: RefCell::new(heapless::Vec::new()),
+            commissioners: RefCell::new(CommissionerCache::new()),
+            query: Cell::new(false),
             notification: Notification::new(),
         }
     }
@@ -105,6 +114,28 @@ impl<'a> MdnsService<'a> {
         Ok(())
     }
 
+    /// The commissioners discovered so far which pass the filter
+    ///
+    /// This also queries for the commissioners, so that the ones which are not
+    /// discovered yet answer, for the next call to return them.
+    pub fn browse_commissioners(
+        &self,
+        filter: &CommissionerFilter,
+    ) -> heapless::Vec<Commissioner, MAX_COMMISSIONERS> {
+        self.query.set(true);
+        self.notification.signal(());
+
+        self.commissioners
+            .borrow()
+            .browse_commissioners(filter, Self::now())
+            .cloned()
+            .collect()
+    }
+
+    fn now() -> core::time::Duration {
+        core::time::Duration::from_millis(Instant::now().as_millis())
+    }
+
     pub fn for_each<F>(&self, mut callback: F) -> Result<(), Error>
     where
         F: FnMut(&Service) -> Result<(), Error>,
@@ -162,8 +193,14 @@ impl<'a> MdnsService<'a> {
                     let mut data = tx_pipe.data.lock().await;
 
                     if let Some(chunk) = data.chunk {
-                        udp.send(chunk.addr.unwrap_udp(), &data.buf[chunk.start..chunk.end])
-                            .await?;
+                        if let Some(addr) = chunk.addr.udp() {
+                            udp.send(addr, &data.buf[chunk.start..chunk.end]).await?;
+                        } else {
+                            warn!(
+                                "Dropping a message to {}, which is not reachable over UDP",
+                                chunk.addr
+                            );
+                        }
                         data.chunk = None;
                         tx_pipe.data_consumed_notification.signal(());
                     }
@@ -217,6 +254,41 @@ impl<'a> MdnsService<'a> {
             )
             .await;
 
+            if self.query.take() {
+                loop {
+                    let sent = {
+                        let mut data = tx_pipe.data.lock().await;
+
+                        if data.chunk.is_none() {
+                            let len = CommissionerCache::query(self.host.id, data.buf)?;
+
+                            info!("Querying for commissioners");
+
+                            data.chunk = Some(Chunk {
+                                start: 0,
+                                end: len,
+                                addr: Address::Udp(SocketAddr::new(
+                                    IpAddr::V4(IP_BROADCAST_ADDR),
+                                    PORT,
+                                )),
+                            });
+
+                            tx_pipe.data_supplied_notification.signal(());
+
+                            true
+                        } else {
+                            false
+                        }
+                    };
+
+                    if sent {
+                        break;
+                    } else {
+                        tx_pipe.data_consumed_notification.wait().await;
+                    }
+                }
+            }
+
             for addr in [
                 IpAddr::V4(IP_BROADCAST_ADDR),
                 IpAddr::V6(IPV6_BROADCAST_ADDR),
@@ -267,6 +339,10 @@ impl<'a> MdnsService<'a> {
                 if let Some(rx_chunk) = rx_data.chunk {
                     let data = &rx_data.buf[rx_chunk.start..rx_chunk.end];
 
+                    if let Err(e) = self.commissioners.borrow_mut().handle(data, Self::now()) {
+                        warn!("Ignoring malformed mDNS message: {:?}", e);
+                    }
+
                     loop {
                         let sent = {
                             let mut tx_data = tx_pipe.data.lock().await;
@@ -467,7 +467,7 @@ impl<'a> Service<'a> {
     }
 }
 
-struct Buf<'a>(pub &'a mut [u8], pub usize);
+pub(crate) struct Buf<'a>(pub &'a mut [u8], pub usize);
 
 impl<'a> OctetsBuilder for Buf<'a> {
     type Octets = Self;
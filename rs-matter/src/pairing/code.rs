@@ -15,37 +15,19 @@
  *    limitations under the License.
  */
 
-use core::fmt::Write;
-
-use super::*;
+use super::{
+    onboarding::{Discriminator, ManualCode},
+    *,
+};
 
 pub fn compute_pairing_code(comm_data: &CommissioningData) -> heapless::String<32> {
-    // 0: no Vendor ID and Product ID present in Manual Pairing Code
-    const VID_PID_PRESENT: u8 = 0;
-
     let passwd = passwd_from_comm_data(comm_data);
-    let CommissioningData { discriminator, .. } = comm_data;
-
-    let mut digits = heapless::String::<32>::new();
-    write!(
-        &mut digits,
-        "{}{:0>5}{:0>4}",
-        (VID_PID_PRESENT << 2) | (discriminator >> 10) as u8,
-        ((discriminator & 0x300) << 6) | (passwd & 0x3FFF) as u16,
-        passwd >> 14
-    )
-    .unwrap();
-
-    let mut final_digits = heapless::String::<32>::new();
-    write!(
-        &mut final_digits,
-        "{}{}",
-        digits,
-        digits.calculate_verhoeff_check_digit()
-    )
-    .unwrap();
+    let discriminator = Discriminator::Long(comm_data.discriminator);
 
-    final_digits
+    // No Vendor ID and Product ID present in Manual Pairing Code
+    ManualCode::encode(discriminator.short(), passwd, None)
+        .as_str()
+        .into()
 }
 
 pub(super) fn pretty_print_pairing_code(pairing_code: &str) {
@@ -18,11 +18,11 @@
 //! This module contains the logic for generating the pairing code and the QR code for easy pairing.
 
 pub mod code;
+pub mod onboarding;
 pub mod qr;
 pub mod vendor_identifiers;
 
 use log::info;
-use verhoeff::Verhoeff;
 
 use crate::{
     codec::base38, data_model::cluster_basic_information::BasicInfoConfig, error::Error,
@@ -34,6 +34,7 @@ use self::{
     qr::{compute_qr_code, print_qr_code},
 };
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct DiscoveryCapabilities {
     on_ip_network: bool,
     ble: bool,
@@ -78,6 +79,14 @@ impl DiscoveryCapabilities {
         }
         bits
     }
+
+    fn from_bits(bits: u8) -> Self {
+        DiscoveryCapabilities {
+            on_ip_network: bits & (1 << 2) != 0,
+            ble: bits & (1 << 1) != 0,
+            soft_access_point: bits & (1 << 0) != 0,
+        }
+    }
 }
 
 /// Prepares and prints the pairing code and the QR code for easy pairing.
@@ -0,0 +1,580 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The onboarding payloads of a device, i.e. the setup QR code and the manual pairing code.
+//!
+//! See section 5.1 Onboarding Payload in the Matter specification.
+
+use core::fmt::Write;
+
+use verhoeff::Verhoeff;
+
+use crate::{
+    codec::base38,
+    error::{Error, ErrorCode},
+};
+
+use super::{
+    qr::{self, CommissionningFlowType, SETUP_PINCODE_FIELD_LENGTH_IN_BITS},
+    vendor_identifiers::{is_vendor_id_valid_operationally, VendorId},
+    DiscoveryCapabilities,
+};
+
+/// The maximum length of a QR code without optional TLV data, including the `MT:` prefix
+pub const MAX_QR_CODE_LEN: usize = 22;
+
+const QR_CODE_PREFIX: &str = "MT:";
+
+/// The maximum length of the decoded QR code payload, including its optional TLV data
+const MAX_QR_PAYLOAD_LEN: usize = 128;
+
+/// The length of a manual pairing code, without and with the vendor and product IDs
+pub const SHORT_MANUAL_CODE_LEN: usize = 11;
+pub const LONG_MANUAL_CODE_LEN: usize = 21;
+
+/// The discriminator of a device, as carried by the onboarding payload
+///
+/// The manual pairing code only carries the upper 4 bits of the discriminator.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Discriminator {
+    /// The full 12-bit discriminator
+    Long(u16),
+    /// The upper 4 bits of the discriminator
+    Short(u8),
+}
+
+impl Discriminator {
+    pub fn short(&self) -> u8 {
+        match self {
+            Self::Long(discriminator) => (discriminator >> 8) as u8 & 0x0F,
+            Self::Short(short) => *short,
+        }
+    }
+}
+
+/// The ways in which parsing an onboarding payload fails
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PayloadError {
+    /// The QR code does not start with `MT:`
+    InvalidPrefix,
+    /// A character outside of the base-38 alphabet, or a non-digit in a manual pairing code
+    InvalidCharacter,
+    /// The QR code payload is truncated, or the manual pairing code is neither 11 nor 21
+    /// digits long (or its length does not match its VID_PID_PRESENT flag)
+    InvalidLength,
+    /// The check digit of the manual pairing code does not match its other digits
+    InvalidCheckDigit,
+    /// A QR code payload of a version other than 0
+    UnsupportedVersion,
+    /// Fields which decode into disallowed values, e.g. an invalid passcode
+    InvalidPayload,
+}
+
+impl From<PayloadError> for Error {
+    fn from(_e: PayloadError) -> Self {
+        Self::new(ErrorCode::InvalidData)
+    }
+}
+
+/// The data that a commissioner needs to discover and to commission a device
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupPayload {
+    /// The vendor and product IDs are absent from the short manual pairing code
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub flow: CommissionningFlowType,
+    /// The discovery capabilities are absent from the manual pairing code
+    pub discovery_capabilities: Option<DiscoveryCapabilities>,
+    pub discriminator: Discriminator,
+    pub passcode: u32,
+}
+
+impl SetupPayload {
+    /// A payload for the standard commissioning flow
+    pub fn new(
+        vendor_id: u16,
+        product_id: u16,
+        discriminator: u16,
+        passcode: u32,
+        discovery_capabilities: DiscoveryCapabilities,
+    ) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            product_id: Some(product_id),
+            flow: CommissionningFlowType::Standard,
+            discovery_capabilities: Some(discovery_capabilities),
+            discriminator: Discriminator::Long(discriminator),
+            passcode,
+        }
+    }
+
+    /// Decode an `MT:`-prefixed QR code
+    ///
+    /// The optional TLV data following the payload is not decoded.
+    pub fn from_qr(qr_code: &str) -> Result<Self, PayloadError> {
+        let encoded = qr_code
+            .strip_prefix(QR_CODE_PREFIX)
+            .ok_or(PayloadError::InvalidPrefix)?;
+
+        // Only chunks of 5, 4 and 2 characters decode into whole bytes
+        if matches!(encoded.len() % 5, 1 | 3) {
+            Err(PayloadError::InvalidLength)?;
+        }
+
+        let bits = base38::decode_vec::<MAX_QR_PAYLOAD_LEN>(encoded).map_err(|e| {
+            if e.code() == ErrorCode::NoSpace {
+                PayloadError::InvalidLength
+            } else {
+                PayloadError::InvalidCharacter
+            }
+        })?;
+
+        let payload = qr::parse_bit_set(&bits)?;
+
+        if !payload.is_valid() {
+            Err(PayloadError::InvalidPayload)?;
+        }
+
+        Ok(payload)
+    }
+
+    /// Decode a manual pairing code, of 11 or 21 digits
+    ///
+    /// Dashes and spaces, as used when presenting the code, are skipped. The code only
+    /// carries the short discriminator, and no discovery capabilities.
+    pub fn from_manual(manual_code: &str) -> Result<Self, PayloadError> {
+        let mut digits = heapless::String::<LONG_MANUAL_CODE_LEN>::new();
+        for c in manual_code.chars().filter(|c| *c != '-' && *c != ' ') {
+            if !c.is_ascii_digit() {
+                Err(PayloadError::InvalidCharacter)?;
+            }
+
+            digits.push(c).map_err(|_| PayloadError::InvalidLength)?;
+        }
+
+        if digits.len() != SHORT_MANUAL_CODE_LEN && digits.len() != LONG_MANUAL_CODE_LEN {
+            Err(PayloadError::InvalidLength)?;
+        }
+
+        let (digits, check_digit) = digits.split_at(digits.len() - 1);
+
+        let mut expected = heapless::String::<1>::new();
+        write!(&mut expected, "{}", digits.calculate_verhoeff_check_digit()).unwrap();
+        if expected != check_digit {
+            Err(PayloadError::InvalidCheckDigit)?;
+        }
+
+        // Only digits, and at most 5 of them
+        let chunk =
+            |start: usize, len: usize| -> u32 { digits[start..start + len].parse().unwrap() };
+
+        let first = chunk(0, 1);
+        let second = chunk(1, 5);
+        let third = chunk(6, 4);
+
+        if first > 7 || second > 0xFFFF {
+            Err(PayloadError::InvalidPayload)?;
+        }
+
+        let vid_pid_present = first & (1 << 2) != 0;
+        if vid_pid_present != (digits.len() + 1 == LONG_MANUAL_CODE_LEN) {
+            Err(PayloadError::InvalidLength)?;
+        }
+
+        let (vendor_id, product_id, flow) = if vid_pid_present {
+            let vid = chunk(10, 5);
+            let pid = chunk(15, 5);
+
+            if vid > 0xFFFF || pid > 0xFFFF {
+                Err(PayloadError::InvalidPayload)?;
+            }
+
+            (
+                Some(vid as u16),
+                Some(pid as u16),
+                CommissionningFlowType::Custom,
+            )
+        } else {
+            (None, None, CommissionningFlowType::Standard)
+        };
+
+        let payload = Self {
+            vendor_id,
+            product_id,
+            flow,
+            discovery_capabilities: None,
+            discriminator: Discriminator::Short((((first & 0x03) << 2) | (second >> 14)) as u8),
+            passcode: (second & 0x3FFF) | (third << 14),
+        };
+
+        if !payload.is_valid() {
+            Err(PayloadError::InvalidPayload)?;
+        }
+
+        Ok(payload)
+    }
+
+    pub(super) fn is_valid(&self) -> bool {
+        if !is_valid_passcode(self.passcode) {
+            return false;
+        }
+
+        if let Discriminator::Long(discriminator) = self.discriminator {
+            if discriminator > 0xFFF {
+                return false;
+            }
+        }
+
+        let vid = self.vendor_id.unwrap_or_default();
+        let pid = self.product_id.unwrap_or_default();
+
+        // VendorID must be unspecified (0) or in valid range expected.
+        if !is_vendor_id_valid_operationally(vid) && (vid != VendorId::CommonOrUnspecified as u16) {
+            return false;
+        }
+
+        // A value of 0x0000 SHALL NOT be assigned to a product since Product ID = 0x0000 is used for these specific cases:
+        //  * To announce an anonymized Product ID as part of device discovery
+        //  * To indicate an OTA software update file applies to multiple Product IDs equally.
+        //  * To avoid confusion when presenting the Onboarding Payload for ECM with multiple nodes
+        if pid == 0 && vid != VendorId::CommonOrUnspecified as u16 {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// The setup QR code (`MT:` followed by the base-38 encoding of the payload)
+pub struct QrCode;
+
+impl QrCode {
+    /// Encode the payload into a QR code string
+    ///
+    /// The payload has to carry the full discriminator and the discovery capabilities.
+    pub fn from_payload(
+        payload: &SetupPayload,
+    ) -> Result<heapless::String<MAX_QR_CODE_LEN>, Error> {
+        let valid = payload.is_valid()
+            && matches!(payload.discriminator, Discriminator::Long(_))
+            && payload
+                .discovery_capabilities
+                .map(|caps| caps.has_value())
+                .unwrap_or(false);
+
+        if !valid {
+            Err(ErrorCode::InvalidArgument)?;
+        }
+
+        let mut str_buf = [0; MAX_QR_CODE_LEN];
+        let mut bits_buf = [0; qr::TOTAL_PAYLOAD_DATA_SIZE_IN_BYTES];
+
+        let qr_code = qr::base38_representation(payload, &mut str_buf, &mut bits_buf, None)?;
+
+        Ok(qr_code.into())
+    }
+}
+
+/// The numeric manual pairing code, of 11 digits, or of 21 digits when it carries
+/// the vendor and product IDs (for the non-standard commissioning flows)
+pub struct ManualCode;
+
+impl ManualCode {
+    /// Encode the payload into a manual pairing code, including its Verhoeff check digit
+    pub fn from_payload(
+        payload: &SetupPayload,
+    ) -> Result<heapless::String<LONG_MANUAL_CODE_LEN>, Error> {
+        if !payload.is_valid() {
+            Err(ErrorCode::InvalidArgument)?;
+        }
+
+        let vid_pid = if matches!(payload.flow, CommissionningFlowType::Standard) {
+            None
+        } else {
+            Some((
+                payload.vendor_id.unwrap_or_default(),
+                payload.product_id.unwrap_or_default(),
+            ))
+        };
+
+        Ok(Self::encode(
+            payload.discriminator.short(),
+            payload.passcode,
+            vid_pid,
+        ))
+    }
+
+    pub(super) fn encode(
+        short_discriminator: u8,
+        passcode: u32,
+        vid_pid: Option<(u16, u16)>,
+    ) -> heapless::String<LONG_MANUAL_CODE_LEN> {
+        let mut digits = heapless::String::<LONG_MANUAL_CODE_LEN>::new();
+        write!(
+            &mut digits,
+            "{}{:0>5}{:0>4}",
+            ((vid_pid.is_some() as u8) << 2) | (short_discriminator >> 2),
+            (((short_discriminator & 0x03) as u32) << 14) | (passcode & 0x3FFF),
+            passcode >> 14
+        )
+        .unwrap();
+
+        if let Some((vid, pid)) = vid_pid {
+            write!(&mut digits, "{:0>5}{:0>5}", vid, pid).unwrap();
+        }
+
+        let check_digit = digits.calculate_verhoeff_check_digit();
+        write!(&mut digits, "{}", check_digit).unwrap();
+
+        digits
+    }
+}
+
+pub(super) fn is_valid_passcode(passcode: u32) -> bool {
+    const SETUP_PINCODE_MAXIMUM_VALUE: u32 = 99999998;
+    const SETUP_PINCODE_UNDEFINED_VALUE: u32 = 0;
+
+    // SHALL be restricted to the values 0x0000001 to 0x5F5E0FE (00000001 to 99999998 in decimal), excluding the invalid Passcode
+    // values.
+    !(passcode == SETUP_PINCODE_UNDEFINED_VALUE
+        || passcode > SETUP_PINCODE_MAXIMUM_VALUE
+        || passcode >= 1 << SETUP_PINCODE_FIELD_LENGTH_IN_BITS
+        || passcode == 11111111
+        || passcode == 22222222
+        || passcode == 33333333
+        || passcode == 44444444
+        || passcode == 55555555
+        || passcode == 66666666
+        || passcode == 77777777
+        || passcode == 88888888
+        || passcode == 12345678
+        || passcode == 87654321)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of section 5.1.3 and 5.1.4 of the Matter specification
+    fn spec_payload() -> SetupPayload {
+        SetupPayload::new(
+            0xFFF1,
+            0x8000,
+            3840,
+            20202021,
+            DiscoveryCapabilities::new(false, true, false),
+        )
+    }
+
+    #[test]
+    fn spec_qr_code() {
+        assert_eq!(
+            QrCode::from_payload(&spec_payload()).unwrap(),
+            "MT:Y.K9042C00KA0648G00"
+        );
+
+        let payload = SetupPayload {
+            product_id: Some(0x8001),
+            discovery_capabilities: Some(DiscoveryCapabilities::new(true, false, false)),
+            ..spec_payload()
+        };
+        assert_eq!(
+            QrCode::from_payload(&payload).unwrap(),
+            "MT:-24J0AFN00KA0648G00"
+        );
+    }
+
+    #[test]
+    fn spec_manual_code() {
+        assert_eq!(
+            ManualCode::from_payload(&spec_payload()).unwrap(),
+            "34970112332"
+        );
+
+        // The custom flow carries the vendor and product IDs
+        let payload = SetupPayload {
+            flow: CommissionningFlowType::Custom,
+            ..spec_payload()
+        };
+        assert_eq!(
+            ManualCode::from_payload(&payload).unwrap(),
+            "749701123365521327688"
+        );
+    }
+
+    #[test]
+    fn invalid_payload() {
+        let payload = SetupPayload {
+            passcode: 12345678,
+            ..spec_payload()
+        };
+        assert!(QrCode::from_payload(&payload).is_err());
+        assert!(ManualCode::from_payload(&payload).is_err());
+
+        // The QR code needs the full discriminator
+        let payload = SetupPayload {
+            discriminator: Discriminator::Short(15),
+            ..spec_payload()
+        };
+        assert!(QrCode::from_payload(&payload).is_err());
+        assert_eq!(ManualCode::from_payload(&payload).unwrap(), "34970112332");
+    }
+
+    #[test]
+    fn parse_spec_qr_code() {
+        assert_eq!(
+            SetupPayload::from_qr("MT:Y.K9042C00KA0648G00"),
+            Ok(spec_payload())
+        );
+
+        // The optional TLV data is skipped
+        assert_eq!(
+            SetupPayload::from_qr("MT:-24J0AFN00KA064IJ3P0IXZB0DK5N1K8SQ1RYCU1-A40"),
+            Ok(SetupPayload {
+                product_id: Some(0x8001),
+                discovery_capabilities: Some(DiscoveryCapabilities::new(true, false, false)),
+                ..spec_payload()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_spec_manual_code() {
+        let short = SetupPayload {
+            vendor_id: None,
+            product_id: None,
+            discovery_capabilities: None,
+            discriminator: Discriminator::Short(15),
+            ..spec_payload()
+        };
+
+        assert_eq!(SetupPayload::from_manual("34970112332"), Ok(short.clone()));
+        // As presented to the user
+        assert_eq!(
+            SetupPayload::from_manual("3497-011-2332"),
+            Ok(short.clone())
+        );
+
+        assert_eq!(
+            SetupPayload::from_manual("749701123365521327688"),
+            Ok(SetupPayload {
+                vendor_id: Some(0xFFF1),
+                product_id: Some(0x8000),
+                flow: CommissionningFlowType::Custom,
+                ..short
+            })
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let payloads = [
+            spec_payload(),
+            SetupPayload::new(0, 0, 0, 1, DiscoveryCapabilities::new(true, true, true)),
+            SetupPayload {
+                flow: CommissionningFlowType::UserIntent,
+                ..SetupPayload::new(
+                    9050,
+                    65279,
+                    0xFFF,
+                    99999998,
+                    DiscoveryCapabilities::new(false, false, true),
+                )
+            },
+        ];
+
+        for payload in payloads {
+            let qr_code = QrCode::from_payload(&payload).unwrap();
+            assert_eq!(SetupPayload::from_qr(&qr_code), Ok(payload.clone()));
+
+            // The manual pairing code only has the short discriminator, and the vendor
+            // and product IDs of the non-standard flows
+            let manual_code = ManualCode::from_payload(&payload).unwrap();
+            let parsed = SetupPayload::from_manual(&manual_code).unwrap();
+
+            let standard = matches!(payload.flow, CommissionningFlowType::Standard);
+            assert_eq!(parsed.passcode, payload.passcode);
+            assert_eq!(parsed.discriminator.short(), payload.discriminator.short());
+            assert_eq!(parsed.vendor_id, payload.vendor_id.filter(|_| !standard));
+            assert_eq!(parsed.product_id, payload.product_id.filter(|_| !standard));
+            assert_eq!(parsed.discovery_capabilities, None);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_qr_code() {
+        assert_eq!(
+            SetupPayload::from_qr("Y.K9042C00KA0648G00"),
+            Err(PayloadError::InvalidPrefix)
+        );
+        assert_eq!(
+            SetupPayload::from_qr("XT:Y.K9042C00KA0648G00"),
+            Err(PayloadError::InvalidPrefix)
+        );
+
+        // Truncated, to a length which does and does not decode into whole bytes
+        assert_eq!(
+            SetupPayload::from_qr("MT:Y.K9042C00KA0648G"),
+            Err(PayloadError::InvalidLength)
+        );
+        assert_eq!(
+            SetupPayload::from_qr("MT:Y.K9042C00KA0648"),
+            Err(PayloadError::InvalidLength)
+        );
+        assert_eq!(
+            SetupPayload::from_qr("MT:"),
+            Err(PayloadError::InvalidLength)
+        );
+
+        // Lowercase letters are not in the base-38 alphabet
+        assert_eq!(
+            SetupPayload::from_qr("MT:y.K9042C00KA0648G00"),
+            Err(PayloadError::InvalidCharacter)
+        );
+    }
+
+    #[test]
+    fn parse_invalid_manual_code() {
+        assert_eq!(
+            SetupPayload::from_manual("34970112333"),
+            Err(PayloadError::InvalidCheckDigit)
+        );
+        assert_eq!(
+            SetupPayload::from_manual("749701123365521327689"),
+            Err(PayloadError::InvalidCheckDigit)
+        );
+
+        // Truncated
+        assert_eq!(
+            SetupPayload::from_manual("3497011233"),
+            Err(PayloadError::InvalidLength)
+        );
+        assert_eq!(
+            SetupPayload::from_manual("74970112336552132768"),
+            Err(PayloadError::InvalidLength)
+        );
+        assert_eq!(
+            SetupPayload::from_manual(""),
+            Err(PayloadError::InvalidLength)
+        );
+
+        assert_eq!(
+            SetupPayload::from_manual("3497O112332"),
+            Err(PayloadError::InvalidCharacter)
+        );
+    }
+}
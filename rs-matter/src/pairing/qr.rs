@@ -22,7 +22,7 @@ use crate::{
 };
 
 use super::{
-    vendor_identifiers::{is_vendor_id_valid_operationally, VendorId},
+    onboarding::{Discriminator, PayloadError, SetupPayload},
     *,
 };
 
@@ -34,7 +34,7 @@ const PRODUCT_IDFIELD_LENGTH_IN_BITS: usize = 16;
 const COMMISSIONING_FLOW_FIELD_LENGTH_IN_BITS: usize = 2;
 const RENDEZVOUS_INFO_FIELD_LENGTH_IN_BITS: usize = 8;
 const PAYLOAD_DISCRIMINATOR_FIELD_LENGTH_IN_BITS: usize = LONG_BITS;
-const SETUP_PINCODE_FIELD_LENGTH_IN_BITS: usize = 27;
+pub(super) const SETUP_PINCODE_FIELD_LENGTH_IN_BITS: usize = 27;
 const PADDING_FIELD_LENGTH_IN_BITS: usize = 4;
 const TOTAL_PAYLOAD_DATA_SIZE_IN_BITS: usize = VERSION_FIELD_LENGTH_IN_BITS
     + VENDOR_IDFIELD_LENGTH_IN_BITS
@@ -45,7 +45,7 @@ const TOTAL_PAYLOAD_DATA_SIZE_IN_BITS: usize = VERSION_FIELD_LENGTH_IN_BITS
     + SETUP_PINCODE_FIELD_LENGTH_IN_BITS
     + PADDING_FIELD_LENGTH_IN_BITS;
 
-const TOTAL_PAYLOAD_DATA_SIZE_IN_BYTES: usize = TOTAL_PAYLOAD_DATA_SIZE_IN_BITS / 8;
+pub(super) const TOTAL_PAYLOAD_DATA_SIZE_IN_BYTES: usize = TOTAL_PAYLOAD_DATA_SIZE_IN_BITS / 8;
 
 // Spec 5.1.4.2 CHIP-Common Reserved Tags
 const SERIAL_NUMBER_TAG: u8 = 0x00;
@@ -109,22 +109,32 @@ impl<'data> QrSetupPayload<'data> {
     }
 
     fn is_valid(&self) -> bool {
-        let passwd = passwd_from_comm_data(self.comm_data);
-
         // 3-bit value specifying the QR code payload version.
         if self.version >= 1 << VERSION_FIELD_LENGTH_IN_BITS {
             return false;
         }
 
-        if !self.discovery_capabilities.has_value() {
+        // A version not equal to 0 would be invalid for v1 and would indicate new format (e.g. version 2)
+        if self.version != 0 {
             return false;
         }
 
-        if passwd >= 1 << SETUP_PINCODE_FIELD_LENGTH_IN_BITS {
+        if !self.discovery_capabilities.has_value() {
             return false;
         }
 
-        self.check_payload_common_constraints()
+        self.setup_payload().is_valid()
+    }
+
+    fn setup_payload(&self) -> SetupPayload {
+        SetupPayload {
+            vendor_id: Some(self.dev_det.vid),
+            product_id: Some(self.dev_det.pid),
+            flow: self.flow_type,
+            discovery_capabilities: Some(self.discovery_capabilities),
+            discriminator: Discriminator::Long(self.comm_data.discriminator),
+            passcode: passwd_from_comm_data(self.comm_data),
+        }
     }
 
     /// A function to add an optional vendor data
@@ -185,68 +195,13 @@ impl<'data> QrSetupPayload<'data> {
         .expect("can not add serial number");
     }
 
-    fn check_payload_common_constraints(&self) -> bool {
-        // A version not equal to 0 would be invalid for v1 and would indicate new format (e.g. version 2)
-        if self.version != 0 {
-            return false;
-        }
-
-        let passwd = passwd_from_comm_data(self.comm_data);
-
-        if !Self::is_valid_setup_pin(passwd) {
-            return false;
-        }
-
-        // VendorID must be unspecified (0) or in valid range expected.
-        if !is_vendor_id_valid_operationally(self.dev_det.vid)
-            && (self.dev_det.vid != VendorId::CommonOrUnspecified as u16)
-        {
-            return false;
-        }
-
-        // A value of 0x0000 SHALL NOT be assigned to a product since Product ID = 0x0000 is used for these specific cases:
-        //  * To announce an anonymized Product ID as part of device discovery
-        //  * To indicate an OTA software update file applies to multiple Product IDs equally.
-        //  * To avoid confusion when presenting the Onboarding Payload for ECM with multiple nodes
-        if self.dev_det.pid == 0 && self.dev_det.vid != VendorId::CommonOrUnspecified as u16 {
-            return false;
-        }
-
-        true
-    }
-
-    fn is_valid_setup_pin(setup_pin: u32) -> bool {
-        const SETUP_PINCODE_MAXIMUM_VALUE: u32 = 99999998;
-        const SETUP_PINCODE_UNDEFINED_VALUE: u32 = 0;
-
-        // SHALL be restricted to the values 0x0000001 to 0x5F5E0FE (00000001 to 99999998 in decimal), excluding the invalid Passcode
-        // values.
-        if setup_pin == SETUP_PINCODE_UNDEFINED_VALUE
-            || setup_pin > SETUP_PINCODE_MAXIMUM_VALUE
-            || setup_pin == 11111111
-            || setup_pin == 22222222
-            || setup_pin == 33333333
-            || setup_pin == 44444444
-            || setup_pin == 55555555
-            || setup_pin == 66666666
-            || setup_pin == 77777777
-            || setup_pin == 88888888
-            || setup_pin == 12345678
-            || setup_pin == 87654321
-        {
-            return false;
-        }
-
-        true
-    }
-
     fn has_tlv(&self) -> bool {
         !self.optional_data.is_empty()
     }
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum CommissionningFlowType {
     Standard = 0,
     UserIntent = 1,
@@ -270,7 +225,13 @@ pub(super) fn payload_base38_representation<'a>(
             (str_buf, buf, None)
         };
 
-        payload_base38_representation_with_tlv(payload, str_buf, bits_buf, tlv_buf)
+        let tlv_data = if let Some(tlv_buf) = tlv_buf {
+            Some(generate_tlv_from_optional_data(payload, tlv_buf)?)
+        } else {
+            None
+        };
+
+        base38_representation(&payload.setup_payload(), str_buf, bits_buf, tlv_data)
     } else {
         Err(ErrorCode::InvalidArgument.into())
     }
@@ -390,18 +351,12 @@ fn populate_bits(
     Ok(())
 }
 
-fn payload_base38_representation_with_tlv<'a>(
-    payload: &QrSetupPayload,
+pub(super) fn base38_representation<'a>(
+    payload: &SetupPayload,
     str_buf: &'a mut [u8],
     bits_buf: &mut [u8],
-    tlv_buf: Option<&mut [u8]>,
+    tlv_data: Option<&[u8]>,
 ) -> Result<&'a str, Error> {
-    let tlv_data = if let Some(tlv_buf) = tlv_buf {
-        Some(generate_tlv_from_optional_data(payload, tlv_buf)?)
-    } else {
-        None
-    };
-
     let bits = generate_bit_set(payload, bits_buf, tlv_data)?;
 
     let prefix = "MT:";
@@ -457,10 +412,12 @@ fn generate_tlv_from_optional_data<'a>(
 }
 
 fn generate_bit_set<'a>(
-    payload: &QrSetupPayload,
+    payload: &SetupPayload,
     bits_buf: &'a mut [u8],
     tlv_data: Option<&[u8]>,
 ) -> Result<&'a [u8], Error> {
+    const VERSION: u8 = 0;
+
     let total_payload_size_in_bits =
         TOTAL_PAYLOAD_DATA_SIZE_IN_BITS + tlv_data.map(|tlv_data| tlv_data.len() * 8).unwrap_or(0);
 
@@ -468,14 +425,21 @@ fn generate_bit_set<'a>(
         Err(ErrorCode::BufferTooSmall)?;
     };
 
-    let passwd = passwd_from_comm_data(payload.comm_data);
+    let (Some(vid), Some(pid), Some(discovery_capabilities), Discriminator::Long(discriminator)) = (
+        payload.vendor_id,
+        payload.product_id,
+        payload.discovery_capabilities,
+        payload.discriminator,
+    ) else {
+        Err(ErrorCode::InvalidArgument)?
+    };
 
     let mut offset: usize = 0;
 
     populate_bits(
         bits_buf,
         &mut offset,
-        payload.version as u64,
+        VERSION as u64,
         VERSION_FIELD_LENGTH_IN_BITS,
         total_payload_size_in_bits,
     )?;
@@ -483,7 +447,7 @@ fn generate_bit_set<'a>(
     populate_bits(
         bits_buf,
         &mut offset,
-        payload.dev_det.vid as u64,
+        vid as u64,
         VENDOR_IDFIELD_LENGTH_IN_BITS,
         total_payload_size_in_bits,
     )?;
@@ -491,7 +455,7 @@ fn generate_bit_set<'a>(
     populate_bits(
         bits_buf,
         &mut offset,
-        payload.dev_det.pid as u64,
+        pid as u64,
         PRODUCT_IDFIELD_LENGTH_IN_BITS,
         total_payload_size_in_bits,
     )?;
@@ -499,7 +463,7 @@ fn generate_bit_set<'a>(
     populate_bits(
         bits_buf,
         &mut offset,
-        payload.flow_type as u64,
+        payload.flow as u64,
         COMMISSIONING_FLOW_FIELD_LENGTH_IN_BITS,
         total_payload_size_in_bits,
     )?;
@@ -507,7 +471,7 @@ fn generate_bit_set<'a>(
     populate_bits(
         bits_buf,
         &mut offset,
-        payload.discovery_capabilities.as_bits() as u64,
+        discovery_capabilities.as_bits() as u64,
         RENDEZVOUS_INFO_FIELD_LENGTH_IN_BITS,
         total_payload_size_in_bits,
     )?;
@@ -515,7 +479,7 @@ fn generate_bit_set<'a>(
     populate_bits(
         bits_buf,
         &mut offset,
-        payload.comm_data.discriminator as u64,
+        discriminator as u64,
         PAYLOAD_DISCRIMINATOR_FIELD_LENGTH_IN_BITS,
         total_payload_size_in_bits,
     )?;
@@ -523,7 +487,7 @@ fn generate_bit_set<'a>(
     populate_bits(
         bits_buf,
         &mut offset,
-        passwd as u64,
+        payload.passcode as u64,
         SETUP_PINCODE_FIELD_LENGTH_IN_BITS,
         total_payload_size_in_bits,
     )?;
@@ -545,6 +509,61 @@ fn generate_bit_set<'a>(
     Ok(&bits_buf[..bytes_written])
 }
 
+/// The inverse of `generate_bit_set`, skipping the optional TLV data which follows the payload
+pub(super) fn parse_bit_set(bits: &[u8]) -> Result<SetupPayload, PayloadError> {
+    if bits.len() < TOTAL_PAYLOAD_DATA_SIZE_IN_BYTES {
+        Err(PayloadError::InvalidLength)?;
+    }
+
+    let mut offset: usize = 0;
+
+    if read_bits(bits, &mut offset, VERSION_FIELD_LENGTH_IN_BITS) != 0 {
+        Err(PayloadError::UnsupportedVersion)?;
+    }
+
+    let vid = read_bits(bits, &mut offset, VENDOR_IDFIELD_LENGTH_IN_BITS) as u16;
+    let pid = read_bits(bits, &mut offset, PRODUCT_IDFIELD_LENGTH_IN_BITS) as u16;
+
+    let flow = match read_bits(bits, &mut offset, COMMISSIONING_FLOW_FIELD_LENGTH_IN_BITS) {
+        0 => CommissionningFlowType::Standard,
+        1 => CommissionningFlowType::UserIntent,
+        2 => CommissionningFlowType::Custom,
+        _ => Err(PayloadError::InvalidPayload)?,
+    };
+
+    let rendezvous = read_bits(bits, &mut offset, RENDEZVOUS_INFO_FIELD_LENGTH_IN_BITS) as u8;
+    let discriminator = read_bits(
+        bits,
+        &mut offset,
+        PAYLOAD_DISCRIMINATOR_FIELD_LENGTH_IN_BITS,
+    ) as u16;
+    let passcode = read_bits(bits, &mut offset, SETUP_PINCODE_FIELD_LENGTH_IN_BITS) as u32;
+
+    Ok(SetupPayload {
+        vendor_id: Some(vid),
+        product_id: Some(pid),
+        flow,
+        discovery_capabilities: Some(DiscoveryCapabilities::from_bits(rendezvous)),
+        discriminator: Discriminator::Long(discriminator),
+        passcode,
+    })
+}
+
+fn read_bits(bits: &[u8], offset: &mut usize, number_of_bits: usize) -> u64 {
+    let mut value = 0;
+
+    for index in 0..number_of_bits {
+        let bit = *offset + index;
+        if bits[bit / 8] & (1 << (bit % 8)) != 0 {
+            value |= 1 << index;
+        }
+    }
+
+    *offset += number_of_bits;
+
+    value
+}
+
 fn populate_tlv_bits(
     bits_buf: &mut [u8],
     offset: &mut usize,
@@ -14,64 +14,365 @@
  *    See the License for the specific language governing permissions and
  *    limitations under the License.
  */
+use heapless::{String, Vec};
+
+use crate::error::{Error, ErrorCode};
+use crate::Matter;
+
 #[cfg(feature = "std")]
 pub use fileio::*;
 
-#[cfg(feature = "std")]
-pub mod fileio {
-    use std::fs;
-    use std::io::{Read, Write};
-    use std::path::{Path, PathBuf};
+/// The version of the format of the persisted records
+///
+/// This is to be bumped with every incompatible change of the TLV encoding of any
+/// of the persisted managers, along with a migration of the older records in [`load`].
+///
+/// - 0: The records of the legacy keys, which had no header, see [`migrate`]
+/// - 1: The initial format
+/// - 2: The fabrics no longer carry their compressed fabric ID, which is derived when
+///   they are loaded. The version 1 records of the fabrics are loaded as they are.
+pub const STORAGE_VERSION: u8 = 2;
 
-    use log::info;
+/// Every record starts with a header, which is the version of the format of the record
+pub const RECORD_HEADER_LEN: usize = 1;
 
-    use crate::error::{Error, ErrorCode};
-    use crate::Matter;
+pub const MAX_KEY_LEN: usize = 32;
+pub const MAX_RECORD_LEN: usize = 4096;
 
-    pub struct Psm<'a> {
-        matter: &'a Matter<'a>,
-        dir: PathBuf,
-        buf: [u8; 4096],
+/// The namespace of all keys of the Matter stack, so that the store can be shared with
+/// the application
+pub const NAMESPACE: &str = "matter.";
+
+pub const KEY_ACLS: &str = "matter.acls";
+pub const KEY_FABRICS: &str = "matter.fabrics";
+pub const KEY_DIAG: &str = "matter.diag";
+pub const KEY_BINDINGS: &str = "matter.bindings";
+pub const KEY_OTA_PROVIDERS: &str = "matter.ota_providers";
+pub const KEY_ICD_CLIENTS: &str = "matter.icd_clients";
+pub const KEY_GROUPS: &str = "matter.groups";
+pub const KEY_GROUP_DATA_CTR: &str = "matter.ctr.group_data";
+pub const KEY_GROUP_CTRL_CTR: &str = "matter.ctr.group_ctrl";
+
+/// The keys of the records written before the keys were namespaced and the records
+/// got a header, along with their current keys
+pub const LEGACY_KEYS: [(&str, &str); 2] = [("acls", KEY_ACLS), ("fabrics", KEY_FABRICS)];
+
+/// A key-value store for the records, which survive a reboot
+pub trait KvStore {
+    /// Read the record of `key` into `buf`, if there is such a record
+    fn get<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error>;
+
+    /// Create or overwrite the record of `key`
+    fn set(&mut self, key: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Remove the record of `key`, if there is such a record
+    fn remove(&mut self, key: &str) -> Result<(), Error>;
+
+    /// Call `f` with the key of every record, which starts with `prefix`
+    fn iter_prefix(
+        &self,
+        prefix: &str,
+        f: &mut dyn FnMut(&str) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+}
+
+/// Load the record of `key`, and return its data without the header
+///
+/// A record written by a newer version of the storage format is rejected, rather
+/// than being parsed as if it was of the current version.
+pub fn load<'a>(
+    store: &dyn KvStore,
+    key: &str,
+    buf: &'a mut [u8],
+) -> Result<Option<&'a [u8]>, Error> {
+    if let Some(record) = store.get(key, buf)? {
+        let (version, data) = record.split_first().ok_or(ErrorCode::Invalid)?;
+
+        if *version > STORAGE_VERSION {
+            Err(ErrorCode::StorageVersion)?;
+        }
+
+        Ok(Some(data))
+    } else {
+        Ok(None)
     }
+}
 
-    impl<'a> Psm<'a> {
-        #[inline(always)]
-        pub fn new(matter: &'a Matter<'a>, dir: PathBuf) -> Result<Self, Error> {
-            fs::create_dir_all(&dir)?;
+/// Store the record of `key`, with the data written by `f` in the buffer it is called with
+///
+/// Nothing is stored if `f` returns no data, i.e. the data has not changed.
+pub fn store<F>(store: &mut dyn KvStore, key: &str, buf: &mut [u8], f: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut [u8]) -> Result<Option<&[u8]>, Error>,
+{
+    if buf.len() < RECORD_HEADER_LEN {
+        Err(ErrorCode::NoSpace)?;
+    }
 
-            info!("Persisting from/to {}", dir.display());
+    let len = f(&mut buf[RECORD_HEADER_LEN..])?.map(|data| data.len());
 
-            let mut buf = [0; 4096];
+    if let Some(len) = len {
+        buf[0] = STORAGE_VERSION;
+        store.set(key, &buf[..RECORD_HEADER_LEN + len])?;
+    }
 
-            if let Some(data) = Self::load(&dir, "acls", &mut buf)? {
-                matter.load_acls(data)?;
-            }
+    Ok(())
+}
+
+/// Move the record of `legacy_key` to `key`, unless there is a record of `key` already
+///
+/// The legacy records had no header, so the moved record gets the version 0 header.
+/// The fabrics of the legacy records never carried their compressed fabric ID, so all of
+/// the legacy records are loaded as they are.
+pub fn migrate(
+    store: &mut dyn KvStore,
+    legacy_key: &str,
+    key: &str,
+    buf: &mut [u8],
+) -> Result<(), Error> {
+    if buf.len() < RECORD_HEADER_LEN {
+        Err(ErrorCode::NoSpace)?;
+    }
+
+    if store.get(key, buf)?.is_none() {
+        let len = store
+            .get(legacy_key, &mut buf[RECORD_HEADER_LEN..])?
+            .map(|data| data.len());
+
+        if let Some(len) = len {
+            buf[0] = 0;
+            store.set(key, &buf[..RECORD_HEADER_LEN + len])?;
+        }
+    }
+
+    store.remove(legacy_key)
+}
+
+const MAX_RAM_RECORDS: usize = 8;
+
+/// A store which keeps the records in RAM, i.e. they don't really survive a reboot
+///
+/// Useful for tests, and for devices which are OK with being re-commissioned after every boot.
+pub struct RamStore {
+    records: Vec<(String<MAX_KEY_LEN>, Vec<u8, MAX_RECORD_LEN>), MAX_RAM_RECORDS>,
+}
+
+impl RamStore {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            records: Vec::new(),
+        }
+    }
+}
+
+impl Default for RamStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            if let Some(data) = Self::load(&dir, "fabrics", &mut buf)? {
-                matter.load_fabrics(data)?;
+impl KvStore for RamStore {
+    fn get<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        if let Some((_, data)) = self.records.iter().find(|(k, _)| k == key) {
+            let buf = buf.get_mut(..data.len()).ok_or(ErrorCode::NoSpace)?;
+            buf.copy_from_slice(data);
+
+            Ok(Some(buf))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn set(&mut self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let data = Vec::from_slice(data).map_err(|_| ErrorCode::NoSpace)?;
+
+        if let Some((_, existing)) = self.records.iter_mut().find(|(k, _)| k == key) {
+            *existing = data;
+        } else {
+            let mut k = String::new();
+            k.push_str(key).map_err(|_| ErrorCode::InvalidArgument)?;
+
+            self.records
+                .push((k, data))
+                .map_err(|_| ErrorCode::NoSpace)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Error> {
+        self.records.retain(|(k, _)| k != key);
+
+        Ok(())
+    }
+
+    fn iter_prefix(
+        &self,
+        prefix: &str,
+        f: &mut dyn FnMut(&str) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for (key, _) in &self.records {
+            if key.starts_with(prefix) {
+                f(key)?;
             }
+        }
+
+        Ok(())
+    }
+}
 
-            Ok(Self { matter, dir, buf })
+pub struct Psm<'a, S> {
+    matter: &'a Matter<'a>,
+    store: S,
+    buf: [u8; MAX_RECORD_LEN],
+}
+
+impl<'a, S> Psm<'a, S>
+where
+    S: KvStore,
+{
+    #[inline(always)]
+    pub fn new(matter: &'a Matter<'a>, mut kv_store: S) -> Result<Self, Error> {
+        let mut buf = [0; MAX_RECORD_LEN];
+
+        for (legacy_key, key) in LEGACY_KEYS {
+            migrate(&mut kv_store, legacy_key, key, &mut buf)?;
         }
 
-        pub async fn run(&mut self) -> Result<(), Error> {
-            loop {
-                self.matter.wait_changed().await;
+        if let Some(data) = load(&kv_store, KEY_ACLS, &mut buf)? {
+            matter.load_acls(data)?;
+        }
 
-                if self.matter.is_changed() {
-                    if let Some(data) = self.matter.store_acls(&mut self.buf)? {
-                        Self::store(&self.dir, "acls", data)?;
-                    }
+        if let Some(data) = load(&kv_store, KEY_FABRICS, &mut buf)? {
+            matter.load_fabrics(data)?;
+        }
 
-                    if let Some(data) = self.matter.store_fabrics(&mut self.buf)? {
-                        Self::store(&self.dir, "fabrics", data)?;
-                    }
-                }
+        if let Some(data) = load(&kv_store, KEY_DIAG, &mut buf)? {
+            matter.load_diag(data)?;
+        }
+
+        if let Some(data) = load(&kv_store, KEY_BINDINGS, &mut buf)? {
+            matter.load_bindings(data)?;
+        }
+
+        if let Some(data) = load(&kv_store, KEY_OTA_PROVIDERS, &mut buf)? {
+            matter.load_ota_providers(data)?;
+        }
+
+        if let Some(data) = load(&kv_store, KEY_ICD_CLIENTS, &mut buf)? {
+            matter.load_icd_clients(data)?;
+        }
+
+        if let Some(data) = load(&kv_store, KEY_GROUPS, &mut buf)? {
+            matter.load_groups(data)?;
+        }
+
+        if let Some(data) = load(&kv_store, KEY_GROUP_DATA_CTR, &mut buf)? {
+            matter.load_group_data_ctr(data)?;
+        }
+
+        if let Some(data) = load(&kv_store, KEY_GROUP_CTRL_CTR, &mut buf)? {
+            matter.load_group_ctrl_ctr(data)?;
+        }
+
+        // Persist the reboot count right away, rather than with the next change
+        store(&mut kv_store, KEY_DIAG, &mut buf, |buf| {
+            matter.store_diag(buf)
+        })?;
+
+        // Reserve the first batch of the counters right away, as no value is handed out
+        // before it is reserved
+        store(&mut kv_store, KEY_GROUP_DATA_CTR, &mut buf, |buf| {
+            matter.store_group_data_ctr(buf)
+        })?;
+
+        store(&mut kv_store, KEY_GROUP_CTRL_CTR, &mut buf, |buf| {
+            matter.store_group_ctrl_ctr(buf)
+        })?;
+
+        Ok(Self {
+            matter,
+            store: kv_store,
+            buf,
+        })
+    }
+
+    pub async fn run(&mut self) -> Result<(), Error> {
+        loop {
+            self.matter.wait_changed().await;
+
+            if self.matter.is_changed() {
+                store(&mut self.store, KEY_ACLS, &mut self.buf, |buf| {
+                    self.matter.store_acls(buf)
+                })?;
+
+                store(&mut self.store, KEY_FABRICS, &mut self.buf, |buf| {
+                    self.matter.store_fabrics(buf)
+                })?;
+
+                store(&mut self.store, KEY_DIAG, &mut self.buf, |buf| {
+                    self.matter.store_diag(buf)
+                })?;
+
+                store(&mut self.store, KEY_BINDINGS, &mut self.buf, |buf| {
+                    self.matter.store_bindings(buf)
+                })?;
+
+                store(&mut self.store, KEY_OTA_PROVIDERS, &mut self.buf, |buf| {
+                    self.matter.store_ota_providers(buf)
+                })?;
+
+                store(&mut self.store, KEY_ICD_CLIENTS, &mut self.buf, |buf| {
+                    self.matter.store_icd_clients(buf)
+                })?;
+
+                store(&mut self.store, KEY_GROUPS, &mut self.buf, |buf| {
+                    self.matter.store_groups(buf)
+                })?;
+
+                store(&mut self.store, KEY_GROUP_DATA_CTR, &mut self.buf, |buf| {
+                    self.matter.store_group_data_ctr(buf)
+                })?;
+
+                store(&mut self.store, KEY_GROUP_CTRL_CTR, &mut self.buf, |buf| {
+                    self.matter.store_group_ctrl_ctr(buf)
+                })?;
             }
         }
+    }
+}
 
-        fn load<'b>(dir: &Path, key: &str, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
-            let path = dir.join(key);
+#[cfg(feature = "std")]
+pub mod fileio {
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    use log::info;
+
+    use crate::error::{Error, ErrorCode};
+
+    use super::KvStore;
+
+    /// A store which keeps every record in a file of its own, named after the key
+    pub struct FileStore {
+        dir: PathBuf,
+    }
+
+    impl FileStore {
+        pub fn new(dir: PathBuf) -> Result<Self, Error> {
+            fs::create_dir_all(&dir)?;
+
+            info!("Persisting from/to {}", dir.display());
+
+            Ok(Self { dir })
+        }
+    }
+
+    impl KvStore for FileStore {
+        fn get<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+            let path = self.dir.join(key);
 
             match fs::File::open(path) {
                 Ok(mut file) => {
@@ -101,8 +402,8 @@ pub mod fileio {
             }
         }
 
-        fn store(dir: &Path, key: &str, data: &[u8]) -> Result<(), Error> {
-            let path = dir.join(key);
+        fn set(&mut self, key: &str, data: &[u8]) -> Result<(), Error> {
+            let path = self.dir.join(key);
 
             let mut file = fs::File::create(path)?;
 
@@ -112,5 +413,255 @@ pub mod fileio {
 
             Ok(())
         }
+
+        fn remove(&mut self, key: &str) -> Result<(), Error> {
+            let path = self.dir.join(key);
+
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+
+            Ok(())
+        }
+
+        fn iter_prefix(
+            &self,
+            prefix: &str,
+            f: &mut dyn FnMut(&str) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            for entry in fs::read_dir(&self.dir)? {
+                if let Some(key) = entry?.file_name().to_str() {
+                    if key.starts_with(prefix) {
+                        f(key)?;
+                    }
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cert::tests::test_vectors::{ICAC1_SUCCESS, NOC1_SUCCESS, RCA1_SUCCESS},
+        crypto::KeyPair,
+        error::ErrorCode,
+        fabric::{Fabric, FabricMgr},
+        mdns::DummyMdns,
+        test_support::seq_rand,
+        tlv::{TLVWriter, TagType, ToTLV},
+        utils::writebuf::WriteBuf,
+    };
+
+    use super::{
+        load, migrate, store, KvStore, RamStore, KEY_ACLS, KEY_FABRICS, MAX_RECORD_LEN, NAMESPACE,
+        STORAGE_VERSION,
+    };
+
+    fn test_fabric() -> Fabric {
+        Fabric::new(
+            KeyPair::new(seq_rand).unwrap(),
+            heapless::Vec::from_slice(&RCA1_SUCCESS).unwrap(),
+            Some(heapless::Vec::from_slice(&ICAC1_SUCCESS).unwrap()),
+            heapless::Vec::from_slice(&NOC1_SUCCESS).unwrap(),
+            &[0x11; 16],
+            0xFFF1,
+            "",
+        )
+        .unwrap()
+    }
+
+    /// The TLV encoding of the fabric with the provided index
+    fn encoded(fabric_mgr: &FabricMgr, fab_idx: usize, buf: &mut [u8]) -> usize {
+        let mut wb = WriteBuf::new(buf);
+        let mut tw = TLVWriter::new(&mut wb);
+
+        fabric_mgr
+            .get_fabric(fab_idx)
+            .unwrap()
+            .to_tlv(&mut tw, TagType::Anonymous)
+            .unwrap();
+
+        tw.get_tail()
+    }
+
+    #[test]
+    /// The fabrics stored into a store are loaded back identically by a new manager
+    fn fabrics_reload() {
+        let mut ram_store = RamStore::new();
+        let mut buf = [0; MAX_RECORD_LEN];
+
+        let mut fabric_mgr = FabricMgr::new();
+        fabric_mgr.add(test_fabric(), &DummyMdns).unwrap();
+        fabric_mgr.add(test_fabric(), &DummyMdns).unwrap();
+        fabric_mgr.set_label(2, "Second").unwrap();
+
+        store(&mut ram_store, KEY_FABRICS, &mut buf, |buf| {
+            fabric_mgr.store(buf)
+        })
+        .unwrap();
+        assert!(!fabric_mgr.is_changed());
+
+        let mut reloaded = FabricMgr::new();
+        let data = load(&ram_store, KEY_FABRICS, &mut buf).unwrap().unwrap();
+        reloaded.load(data, &DummyMdns).unwrap();
+
+        assert_eq!(reloaded.used_count(), 2);
+
+        let mut expected = [0; MAX_RECORD_LEN];
+        let mut actual = [0; MAX_RECORD_LEN];
+        for fab_idx in 1..=2 {
+            let expected_len = encoded(&fabric_mgr, fab_idx, &mut expected);
+            let actual_len = encoded(&reloaded, fab_idx, &mut actual);
+
+            assert_eq!(&actual[..actual_len], &expected[..expected_len]);
+
+            // Derived rather than stored
+            assert_eq!(
+                reloaded
+                    .get_fabric(fab_idx)
+                    .unwrap()
+                    .unwrap()
+                    .compressed_id(),
+                fabric_mgr
+                    .get_fabric(fab_idx)
+                    .unwrap()
+                    .unwrap()
+                    .compressed_id()
+            );
+        }
+
+        // Nothing is stored when nothing has changed
+        ram_store.remove(KEY_FABRICS).unwrap();
+        store(&mut ram_store, KEY_FABRICS, &mut buf, |buf| {
+            fabric_mgr.store(buf)
+        })
+        .unwrap();
+        assert!(load(&ram_store, KEY_FABRICS, &mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    /// The fabrics of the version 1 records still carry their compressed fabric ID
+    fn fabrics_version_1() {
+        let mut buf = [0; MAX_RECORD_LEN];
+
+        let mut fabric_mgr = FabricMgr::new();
+        fabric_mgr.add(test_fabric(), &DummyMdns).unwrap();
+        let compressed_id = fabric_mgr.get_fabric(1).unwrap().unwrap().compressed_id();
+        let data = fabric_mgr.store(&mut buf).unwrap().unwrap();
+
+        // The compressed fabric ID was the last member of the fabric, as an array of
+        // context tag 10
+        let mut record = heapless::Vec::<u8, MAX_RECORD_LEN>::new();
+        record.push(1).unwrap();
+        record.extend_from_slice(&data[..data.len() - 2]).unwrap();
+        record.extend_from_slice(&[0x36, 0x0A]).unwrap();
+        for b in compressed_id {
+            record.extend_from_slice(&[0x04, b]).unwrap();
+        }
+        record.extend_from_slice(&[0x18, 0x18, 0x18]).unwrap();
+
+        let mut ram_store = RamStore::new();
+        ram_store.set(KEY_FABRICS, &record).unwrap();
+
+        let mut reloaded = FabricMgr::new();
+        let data = load(&ram_store, KEY_FABRICS, &mut buf).unwrap().unwrap();
+        reloaded.load(data, &DummyMdns).unwrap();
+
+        let fabric = reloaded.get_fabric(1).unwrap().unwrap();
+        assert_eq!(fabric.compressed_id(), compressed_id);
+        assert_eq!(
+            fabric.mdns_service_name(),
+            fabric_mgr
+                .get_fabric(1)
+                .unwrap()
+                .unwrap()
+                .mdns_service_name()
+        );
+    }
+
+    #[test]
+    /// The headerless records of the legacy keys are moved to the namespaced keys
+    fn legacy_keys_migrated() {
+        let mut buf = [0; MAX_RECORD_LEN];
+
+        let mut fabric_mgr = FabricMgr::new();
+        fabric_mgr.add(test_fabric(), &DummyMdns).unwrap();
+        let data = fabric_mgr.store(&mut buf).unwrap().unwrap();
+
+        let mut ram_store = RamStore::new();
+        ram_store.set("fabrics", data).unwrap();
+
+        migrate(&mut ram_store, "fabrics", KEY_FABRICS, &mut buf).unwrap();
+        assert!(ram_store.get("fabrics", &mut buf).unwrap().is_none());
+
+        let mut reloaded = FabricMgr::new();
+        let data = load(&ram_store, KEY_FABRICS, &mut buf).unwrap().unwrap();
+        let len = data.len();
+        reloaded.load(data, &DummyMdns).unwrap();
+
+        let mut expected = [0; MAX_RECORD_LEN];
+        let mut actual = [0; MAX_RECORD_LEN];
+        let expected_len = encoded(&fabric_mgr, 1, &mut expected);
+        let actual_len = encoded(&reloaded, 1, &mut actual);
+        assert_eq!(&actual[..actual_len], &expected[..expected_len]);
+
+        // A leftover legacy record does not overwrite the record of the namespaced key
+        ram_store.set("fabrics", &[0x15, 0x18]).unwrap();
+        migrate(&mut ram_store, "fabrics", KEY_FABRICS, &mut buf).unwrap();
+        assert!(ram_store.get("fabrics", &mut buf).unwrap().is_none());
+        assert_eq!(
+            load(&ram_store, KEY_FABRICS, &mut buf)
+                .unwrap()
+                .unwrap()
+                .len(),
+            len
+        );
+    }
+
+    #[test]
+    /// A record of a newer version of the format is not parsed
+    fn newer_version_rejected() {
+        let mut ram_store = RamStore::new();
+        let mut buf = [0; MAX_RECORD_LEN];
+
+        ram_store
+            .set(KEY_FABRICS, &[STORAGE_VERSION + 1, 0x16, 0x18])
+            .unwrap();
+
+        assert_eq!(
+            load(&ram_store, KEY_FABRICS, &mut buf).map_err(|e| e.code()),
+            Err(ErrorCode::StorageVersion)
+        );
+
+        ram_store
+            .set(KEY_FABRICS, &[STORAGE_VERSION, 0x16, 0x18])
+            .unwrap();
+
+        assert_eq!(
+            load(&ram_store, KEY_FABRICS, &mut buf).unwrap(),
+            Some([0x16, 0x18].as_slice())
+        );
+    }
+
+    #[test]
+    fn iter_prefix() {
+        let mut ram_store = RamStore::new();
+
+        ram_store.set(KEY_ACLS, &[STORAGE_VERSION]).unwrap();
+        ram_store.set("app.settings", &[0]).unwrap();
+        ram_store.set(KEY_FABRICS, &[STORAGE_VERSION]).unwrap();
+
+        let mut keys = heapless::Vec::<heapless::String<32>, 4>::new();
+        ram_store
+            .iter_prefix(NAMESPACE, &mut |key| {
+                keys.push(key.into()).unwrap();
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(keys, [KEY_ACLS, KEY_FABRICS]);
     }
 }
@@ -17,7 +17,8 @@
 
 use core::cell::RefCell;
 
-use log::{error, trace};
+use log::{error, info, trace};
+use subtle::ConstantTimeEq;
 
 use crate::{
     alloc,
@@ -27,6 +28,7 @@ use crate::{
     fabric::{Fabric, FabricMgr},
     secure_channel::common::{self, OpCode, PROTO_ID_SECURE_CHANNEL},
     secure_channel::common::{complete_with_status, SCStatusCodes},
+    secure_channel::status_report::GeneralCode,
     tlv::{get_root_node_struct, FromTLV, OctetStr, TLVWriter, TagType},
     transport::{
         exchange::Exchange,
@@ -37,6 +39,11 @@ use crate::{
     utils::{rand::Rand, writebuf::WriteBuf},
 };
 
+/// The number of CASE sessions that can be resumed, without a full CASE handshake
+pub const MAX_RESUMPTION_RECORDS: usize = 8;
+
+const RESUMPTION_ID_LEN: usize = 16;
+
 #[derive(Debug, Clone)]
 struct CaseSession {
     peer_sessid: u16,
@@ -46,6 +53,7 @@ struct CaseSession {
     our_pub_key: [u8; crypto::EC_POINT_LEN_BYTES],
     peer_pub_key: [u8; crypto::EC_POINT_LEN_BYTES],
     local_fabric_idx: usize,
+    resumption_id: [u8; RESUMPTION_ID_LEN],
 }
 
 impl CaseSession {
@@ -59,19 +67,84 @@ impl CaseSession {
             our_pub_key: [0; crypto::EC_POINT_LEN_BYTES],
             peer_pub_key: [0; crypto::EC_POINT_LEN_BYTES],
             local_fabric_idx: 0,
+            resumption_id: [0; RESUMPTION_ID_LEN],
         })
     }
 }
 
+/// What we need to remember from an established CASE session, so that the peer can
+/// later resume it
+#[derive(Debug, Clone)]
+pub struct ResumptionRecord {
+    pub resumption_id: [u8; RESUMPTION_ID_LEN],
+    shared_secret: [u8; crypto::ECDH_SHARED_SECRET_LEN_BYTES],
+    pub peer_nodeid: u64,
+    pub fab_idx: u8,
+    pub peer_catids: NocCatIds,
+}
+
+pub struct ResumptionMgr {
+    records: heapless::Vec<ResumptionRecord, MAX_RESUMPTION_RECORDS>,
+}
+
+impl ResumptionMgr {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            records: heapless::Vec::new(),
+        }
+    }
+
+    /// Store the record, replacing the one of the same peer on the same fabric, if any.
+    ///
+    /// Once there is no more room, the oldest record is evicted.
+    pub fn store(&mut self, record: ResumptionRecord) {
+        self.records.retain(|existing| {
+            existing.peer_nodeid != record.peer_nodeid || existing.fab_idx != record.fab_idx
+        });
+
+        if self.records.is_full() {
+            self.records.remove(0);
+        }
+
+        let _ = self.records.push(record);
+    }
+
+    pub fn get(&self, resumption_id: &[u8]) -> Option<&ResumptionRecord> {
+        self.records
+            .iter()
+            .find(|record| record.resumption_id == resumption_id)
+    }
+
+    pub fn remove_for_fabric(&mut self, fab_idx: u8) {
+        self.records.retain(|record| record.fab_idx != fab_idx);
+    }
+}
+
+impl Default for ResumptionMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Case<'a> {
     fabric_mgr: &'a RefCell<FabricMgr>,
+    resumption_mgr: &'a RefCell<ResumptionMgr>,
     rand: Rand,
 }
 
 impl<'a> Case<'a> {
     #[inline(always)]
-    pub fn new(fabric_mgr: &'a RefCell<FabricMgr>, rand: Rand) -> Self {
-        Self { fabric_mgr, rand }
+    pub fn new(
+        fabric_mgr: &'a RefCell<FabricMgr>,
+        resumption_mgr: &'a RefCell<ResumptionMgr>,
+        rand: Rand,
+    ) -> Self {
+        Self {
+            fabric_mgr,
+            resumption_mgr,
+            rand,
+        }
     }
 
     pub async fn handle(
@@ -80,6 +153,10 @@ impl<'a> Case<'a> {
         rx: &mut Packet<'_>,
         tx: &mut Packet<'_>,
     ) -> Result<(), Error> {
+        if self.handle_casesigma1_resume(exchange, rx, tx).await? {
+            return Ok(());
+        }
+
         let mut session = alloc!(CaseSession::new()?);
 
         self.handle_casesigma1(exchange, rx, tx, &mut session)
@@ -181,6 +258,14 @@ impl<'a> Case<'a> {
         // TODO: Handle NoSpace
         exchange.with_session_mgr_mut(|sess_mgr| sess_mgr.clone_session(&clone_data))?;
 
+        self.resumption_mgr.borrow_mut().store(ResumptionRecord {
+            resumption_id: case_session.resumption_id,
+            shared_secret: case_session.shared_secret,
+            peer_nodeid: initiator_noc.get_node_id()?,
+            fab_idx: case_session.local_fabric_idx as u8,
+            peer_catids,
+        });
+
         complete_with_status(
             exchange,
             tx,
@@ -190,6 +275,138 @@ impl<'a> Case<'a> {
         .await
     }
 
+    /// Try to resume a previous session, if the Sigma1 asks for it.
+    ///
+    /// Returns `false` if the full CASE handshake has to be done instead, which is also
+    /// the case when the resumption ID is unknown or the resumption MIC is invalid.
+    async fn handle_casesigma1_resume(
+        &mut self,
+        exchange: &mut Exchange<'_>,
+        rx: &mut Packet<'_>,
+        tx: &mut Packet<'_>,
+    ) -> Result<bool, Error> {
+        rx.check_proto_opcode(OpCode::CASESigma1 as _)?;
+
+        let root = get_root_node_struct(rx.as_slice())?;
+        let r = Sigma1Req::from_tlv(&root)?;
+
+        let (resumption_id, initiator_resume_mic) = match (
+            root.find_tag(6).and_then(|t| t.slice()),
+            root.find_tag(7).and_then(|t| t.slice()),
+        ) {
+            (Ok(resumption_id), Ok(initiator_resume_mic)) => (resumption_id, initiator_resume_mic),
+            _ => return Ok(false),
+        };
+
+        let record = self.resumption_mgr.borrow().get(resumption_id).cloned();
+        let mut record = match record {
+            Some(record) => record,
+            None => {
+                info!("Unknown resumption ID, falling back to a full CASE");
+                return Ok(false);
+            }
+        };
+
+        if Case::validate_resume_mic(
+            &record.shared_secret,
+            r.initiator_random.0,
+            resumption_id,
+            &SIGMA1_RESUME_INFO,
+            &SIGMA1_RESUME_NONCE,
+            initiator_resume_mic,
+        )
+        .is_err()
+        {
+            error!("Sigma1 resume MIC doesn't match, falling back to a full CASE");
+            return Ok(false);
+        }
+
+        // Checked before replying, as the session cannot be resumed without its fabric
+        let local_nodeid = match self
+            .fabric_mgr
+            .borrow()
+            .get_fabric(record.fab_idx as usize)?
+        {
+            Some(fabric) => fabric.get_node_id(),
+            None => {
+                info!("The fabric of the resumed session is gone, falling back to a full CASE");
+                return Ok(false);
+            }
+        };
+
+        let mut initiator_random = [0; 32];
+        if r.initiator_random.0.len() != initiator_random.len() {
+            Err(ErrorCode::Invalid)?;
+        }
+        initiator_random.copy_from_slice(r.initiator_random.0);
+        let peer_sessid = r.initiator_sessid;
+
+        let mut new_resumption_id = [0; RESUMPTION_ID_LEN];
+        (self.rand)(&mut new_resumption_id);
+
+        let sigma2_resume_mic = Case::get_resume_mic(
+            &record.shared_secret,
+            &initiator_random,
+            &new_resumption_id,
+            &SIGMA2_RESUME_INFO,
+            &SIGMA2_RESUME_NONCE,
+        )?;
+
+        let local_sessid = exchange.with_session_mgr_mut(|mgr| Ok(mgr.get_next_sess_id()))?;
+
+        tx.reset();
+        tx.set_proto_id(PROTO_ID_SECURE_CHANNEL);
+        tx.set_proto_opcode(OpCode::CASESigma2Resume as u8);
+
+        let mut tw = TLVWriter::new(tx.get_writebuf()?);
+        tw.start_struct(TagType::Anonymous)?;
+        tw.str8(TagType::Context(1), &new_resumption_id)?;
+        tw.str8(TagType::Context(2), &sigma2_resume_mic)?;
+        tw.u16(TagType::Context(3), local_sessid)?;
+        tw.end_container()?;
+
+        exchange.exchange(tx, rx).await?;
+
+        // The initiator confirms the resumption with a status report
+        rx.check_proto_opcode(OpCode::StatusReport as _)?;
+        let status = rx.as_slice();
+        if status.len() < 2
+            || u16::from_le_bytes([status[0], status[1]]) != GeneralCode::Success as u16
+        {
+            error!("Session resumption rejected by the initiator");
+            return Ok(true);
+        }
+
+        let mut session_keys = [0_u8; 3 * crypto::SYMM_KEY_LEN_BYTES];
+        Case::get_resumption_session_keys(
+            &record.shared_secret,
+            &initiator_random,
+            &new_resumption_id,
+            &mut session_keys,
+        )?;
+
+        let clone_data = Case::get_clone_data_with_keys(
+            local_nodeid,
+            record.peer_nodeid,
+            peer_sessid,
+            local_sessid,
+            exchange.with_session(|sess| Ok(sess.get_peer_addr()))?,
+            record.fab_idx,
+            &record.peer_catids,
+            &session_keys,
+        );
+
+        exchange.with_session_mgr_mut(|sess_mgr| sess_mgr.clone_session(&clone_data))?;
+
+        // The record can only be used once, the next resumption has to use the new ID
+        record.resumption_id = new_resumption_id;
+        self.resumption_mgr.borrow_mut().store(record);
+
+        exchange.acknowledge().await?;
+
+        Ok(true)
+    }
+
     #[allow(clippy::await_holding_refcell_ref)]
     async fn handle_casesigma1(
         &mut self,
@@ -337,16 +554,36 @@ impl<'a> Case<'a> {
             &mut session_keys,
         )?;
 
-        let mut clone_data = CloneData::new(
+        Ok(Case::get_clone_data_with_keys(
             local_nodeid,
             peer_nodeid,
             case_session.peer_sessid,
             case_session.local_sessid,
             peer_addr,
-            SessionMode::Case(CaseDetails::new(
-                case_session.local_fabric_idx as u8,
-                peer_catids,
-            )),
+            case_session.local_fabric_idx as u8,
+            peer_catids,
+            &session_keys,
+        ))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn get_clone_data_with_keys(
+        local_nodeid: u64,
+        peer_nodeid: u64,
+        peer_sessid: u16,
+        local_sessid: u16,
+        peer_addr: Address,
+        fab_idx: u8,
+        peer_catids: &NocCatIds,
+        session_keys: &[u8; 3 * crypto::SYMM_KEY_LEN_BYTES],
+    ) -> CloneData {
+        let mut clone_data = CloneData::new(
+            local_nodeid,
+            peer_nodeid,
+            peer_sessid,
+            local_sessid,
+            peer_addr,
+            SessionMode::Case(CaseDetails::new(fab_idx, peer_catids)),
         );
 
         clone_data.dec_key.copy_from_slice(&session_keys[0..16]);
@@ -354,7 +591,76 @@ impl<'a> Case<'a> {
         clone_data
             .att_challenge
             .copy_from_slice(&session_keys[32..48]);
-        Ok(clone_data)
+        clone_data
+    }
+
+    fn get_resumption_session_keys(
+        shared_secret: &[u8],
+        initiator_random: &[u8],
+        resumption_id: &[u8],
+        key: &mut [u8],
+    ) -> Result<(), Error> {
+        const SESSION_RESUMPTION_KEYS_INFO: &[u8] = b"SessionResumptionKeys";
+
+        let mut salt = heapless::Vec::<u8, 64>::new();
+        salt.extend_from_slice(initiator_random)
+            .map_err(|_| ErrorCode::NoSpace)?;
+        salt.extend_from_slice(resumption_id)
+            .map_err(|_| ErrorCode::NoSpace)?;
+
+        crypto::hkdf_sha256(
+            salt.as_slice(),
+            shared_secret,
+            SESSION_RESUMPTION_KEYS_INFO,
+            key,
+        )
+        .map_err(|_x| ErrorCode::NoSpace)?;
+
+        Ok(())
+    }
+
+    /// The MIC of the Sigma1 and Sigma2 resume messages, which proves that the sender
+    /// knows the shared secret of the session being resumed
+    fn get_resume_mic(
+        shared_secret: &[u8],
+        initiator_random: &[u8],
+        resumption_id: &[u8],
+        info: &[u8],
+        nonce: &[u8; crypto::AEAD_NONCE_LEN_BYTES],
+    ) -> Result<[u8; crypto::AEAD_MIC_LEN_BYTES], Error> {
+        let mut salt = heapless::Vec::<u8, 64>::new();
+        salt.extend_from_slice(initiator_random)
+            .map_err(|_| ErrorCode::NoSpace)?;
+        salt.extend_from_slice(resumption_id)
+            .map_err(|_| ErrorCode::NoSpace)?;
+
+        let mut resume_key = [0_u8; crypto::SYMM_KEY_LEN_BYTES];
+        crypto::hkdf_sha256(salt.as_slice(), shared_secret, info, &mut resume_key)
+            .map_err(|_x| ErrorCode::NoSpace)?;
+
+        // An empty message, so all we get is the MIC
+        let mut mic = [0_u8; crypto::AEAD_MIC_LEN_BYTES];
+        crypto::encrypt_in_place(&resume_key, nonce, &[], &mut mic, 0)?;
+
+        Ok(mic)
+    }
+
+    fn validate_resume_mic(
+        shared_secret: &[u8],
+        initiator_random: &[u8],
+        resumption_id: &[u8],
+        info: &[u8],
+        nonce: &[u8; crypto::AEAD_NONCE_LEN_BYTES],
+        mic: &[u8],
+    ) -> Result<(), Error> {
+        let expected =
+            Case::get_resume_mic(shared_secret, initiator_random, resumption_id, info, nonce)?;
+
+        if expected[..].ct_eq(mic).into() {
+            Ok(())
+        } else {
+            Err(ErrorCode::InvalidSignature.into())
+        }
     }
 
     fn validate_sigma3_sign(
@@ -519,8 +825,7 @@ impl<'a> Case<'a> {
         signature: &[u8],
         out: &mut [u8],
     ) -> Result<usize, Error> {
-        let mut resumption_id: [u8; 16] = [0; 16];
-        rand(&mut resumption_id);
+        rand(&mut case_session.resumption_id);
 
         let mut sigma2_key = [0_u8; crypto::SYMM_KEY_LEN_BYTES];
         Case::get_sigma2_key(
@@ -539,7 +844,7 @@ impl<'a> Case<'a> {
         };
 
         tw.str8(TagType::Context(3), signature)?;
-        tw.str8(TagType::Context(4), &resumption_id)?;
+        tw.str8(TagType::Context(4), &case_session.resumption_id)?;
         tw.end_container()?;
         //println!("TBE is {:x?}", write_buf.as_borrow_slice());
         let nonce: [u8; crypto::AEAD_NONCE_LEN_BYTES] = [
@@ -587,6 +892,11 @@ impl<'a> Case<'a> {
     }
 }
 
+const SIGMA1_RESUME_INFO: [u8; 13] = *b"Sigma1_Resume";
+const SIGMA2_RESUME_INFO: [u8; 13] = *b"Sigma2_Resume";
+const SIGMA1_RESUME_NONCE: [u8; crypto::AEAD_NONCE_LEN_BYTES] = *b"NCASE_SigmaS1";
+const SIGMA2_RESUME_NONCE: [u8; crypto::AEAD_NONCE_LEN_BYTES] = *b"NCASE_SigmaS2";
+
 #[derive(FromTLV)]
 #[tlvargs(start = 1, lifetime = "'a")]
 struct Sigma1Req<'a> {
@@ -603,3 +913,325 @@ struct Sigma3Decrypt<'a> {
     initiator_icac: Option<OctetStr<'a>>,
     signature: OctetStr<'a>,
 }
+
+#[cfg(test)]
+mod tests {
+    use core::{borrow::Borrow, cell::RefCell, pin::pin};
+
+    use embassy_futures::select::{select, Either};
+
+    use crate::{
+        cert::tests::test_vectors::{ICAC1_SUCCESS, NOC1_SUCCESS, RCA1_SUCCESS},
+        crypto::{self, KeyPair},
+        data_model::sdm::dev_att::tests::TestDevAtt,
+        fabric::{Fabric, FabricMgr, FabricScoped},
+        mdns::DummyMdns,
+        secure_channel::{
+            common::{OpCode, SCStatusCodes, PROTO_ID_SECURE_CHANNEL},
+            spake2p::VerifierData,
+            status_report::GeneralCode,
+        },
+        test_support::{seq_rand, BASIC_INFO, HANDLER},
+        tlv::{get_root_node_struct, TLVWriter, TagType},
+        transport::{
+            core::PacketBuffers,
+            network::Address,
+            packet::{Packet, MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+            pipe::Pipe,
+            proto_hdr::ExchFlags,
+        },
+        utils::{epoch::dummy_epoch, rand::dummy_rand},
+        CommissioningData, Matter,
+    };
+
+    use super::{
+        Case, ResumptionMgr, ResumptionRecord, MAX_RESUMPTION_RECORDS, SIGMA1_RESUME_INFO,
+        SIGMA1_RESUME_NONCE, SIGMA2_RESUME_INFO, SIGMA2_RESUME_NONCE,
+    };
+
+    const PEER_NODE_ID: u64 = 0x1234;
+    const RESUMPTION_ID: [u8; 16] = [7; 16];
+    const SHARED_SECRET: [u8; crypto::ECDH_SHARED_SECRET_LEN_BYTES] =
+        [0x55; crypto::ECDH_SHARED_SECRET_LEN_BYTES];
+    const INITIATOR_RANDOM: [u8; 32] = [0x33; 32];
+
+    fn record(peer_nodeid: u64, fab_idx: u8, id: u8) -> ResumptionRecord {
+        ResumptionRecord {
+            resumption_id: [id; 16],
+            shared_secret: [0x55; crypto::ECDH_SHARED_SECRET_LEN_BYTES],
+            peer_nodeid,
+            fab_idx,
+            peer_catids: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_resumption_records_are_bounded() {
+        let mut mgr = ResumptionMgr::new();
+
+        for id in 0..MAX_RESUMPTION_RECORDS as u8 {
+            mgr.store(record(id as u64, 1, id));
+        }
+
+        // A new record of the same peer replaces the existing one
+        mgr.store(record(1, 1, 100));
+        assert!(mgr.get(&[1; 16]).is_none());
+        assert!(mgr.get(&[100; 16]).is_some());
+
+        // Once full, the oldest record is evicted
+        mgr.store(record(200, 1, 200));
+        assert!(mgr.get(&[0; 16]).is_none());
+        assert!(mgr.get(&[2; 16]).is_some());
+        assert!(mgr.get(&[200; 16]).is_some());
+    }
+
+    /// Resume the session of `RESUMPTION_ID` with a Sigma1, returning the new resumption ID
+    /// and the local session ID of the resumed session, or `None` if the device answered
+    /// with anything else than a Sigma2Resume
+    fn resume(matter: &Matter) -> Option<([u8; 16], u16)> {
+        let mut buffers = PacketBuffers::new();
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+
+        let tx_pipe = Pipe::new(&mut tx_buf);
+        let rx_pipe = Pipe::new(&mut rx_buf);
+
+        let dev_comm = CommissioningData {
+            verifier: VerifierData::new_with_pw(123456, dummy_rand),
+            discriminator: 250,
+        };
+
+        let run = matter.run_piped(&mut buffers, &tx_pipe, &rx_pipe, dev_comm, &HANDLER);
+
+        let initiator = async {
+            let mut buf = [0; MAX_TX_BUF_SIZE];
+
+            let len = sigma1_resume(&mut buf);
+            rx_pipe.send(Address::default(), &buf[..len]).await;
+
+            let mut reply = [0; MAX_TX_BUF_SIZE];
+            let (len, ctr) = recv(&tx_pipe, &mut reply).await;
+            let mut reply = Packet::new_rx(&mut reply[..len]);
+            reply.plain_hdr_decode().unwrap();
+            reply.proto_decode(0, None).unwrap();
+
+            if reply.get_proto_raw_opcode() != OpCode::CASESigma2Resume as u8 {
+                return None;
+            }
+
+            let root = get_root_node_struct(reply.as_slice()).unwrap();
+            let mut new_resumption_id = [0; 16];
+            new_resumption_id.copy_from_slice(root.find_tag(1).unwrap().slice().unwrap());
+            let sigma2_resume_mic = root.find_tag(2).unwrap().slice().unwrap();
+            let local_sessid = root.find_tag(3).unwrap().u16().unwrap();
+
+            // The device proves that it knows the shared secret too
+            assert!(Case::validate_resume_mic(
+                &SHARED_SECRET,
+                &INITIATOR_RANDOM,
+                &new_resumption_id,
+                &SIGMA2_RESUME_INFO,
+                &SIGMA2_RESUME_NONCE,
+                sigma2_resume_mic,
+            )
+            .is_ok());
+
+            let len = status_report(&mut buf, ctr);
+            rx_pipe.send(Address::default(), &buf[..len]).await;
+
+            // The device acknowledges the status report once the session is resumed
+            let mut ack = [0; MAX_TX_BUF_SIZE];
+            tx_pipe.recv(&mut ack).await;
+
+            Some((new_resumption_id, local_sessid))
+        };
+
+        match embassy_futures::block_on(select(pin!(run), pin!(initiator))) {
+            Either::First(result) => panic!("The transport exited: {:?}", result),
+            Either::Second(resumed) => resumed,
+        }
+    }
+
+    /// Receive the next message from the device, other than a standalone ack,
+    /// returning its length and message counter
+    async fn recv(tx_pipe: &Pipe<'_>, buf: &mut [u8]) -> (usize, u32) {
+        loop {
+            let (len, _) = tx_pipe.recv(buf).await;
+
+            let mut packet = Packet::new_rx(&mut buf[..len]);
+            packet.plain_hdr_decode().unwrap();
+            packet.proto_decode(0, None).unwrap();
+
+            if packet.get_proto_raw_opcode() != OpCode::MRPStandAloneAck as u8 {
+                break (len, packet.plain.ctr);
+            }
+        }
+    }
+
+    fn initiator_packet<'a>(buf: &'a mut [u8], ctr: u32, opcode: OpCode) -> Packet<'a> {
+        let mut packet = Packet::new_tx(buf);
+        packet.plain.ctr = ctr;
+        packet.plain.set_src_u64(PEER_NODE_ID);
+        packet.set_proto_id(PROTO_ID_SECURE_CHANNEL);
+        packet.set_proto_opcode(opcode as u8);
+        packet.proto.exch_id = 1;
+        packet.proto.exch_flags |= ExchFlags::INITIATOR;
+        packet.proto.set_reliable();
+
+        packet
+    }
+
+    fn sigma1_resume(buf: &mut [u8]) -> usize {
+        let sigma1_resume_mic = Case::get_resume_mic(
+            &SHARED_SECRET,
+            &INITIATOR_RANDOM,
+            &RESUMPTION_ID,
+            &SIGMA1_RESUME_INFO,
+            &SIGMA1_RESUME_NONCE,
+        )
+        .unwrap();
+
+        let mut packet = initiator_packet(buf, 1, OpCode::CASESigma1);
+
+        let mut tw = TLVWriter::new(packet.get_writebuf().unwrap());
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.str8(TagType::Context(1), &INITIATOR_RANDOM).unwrap();
+        tw.u16(TagType::Context(2), 0x4242).unwrap();
+        // Neither of which is looked at, when resuming
+        tw.str8(TagType::Context(3), &[0; 32]).unwrap();
+        tw.str8(TagType::Context(4), &[0x04; crypto::EC_POINT_LEN_BYTES])
+            .unwrap();
+        tw.str8(TagType::Context(6), &RESUMPTION_ID).unwrap();
+        tw.str8(TagType::Context(7), &sigma1_resume_mic).unwrap();
+        tw.end_container().unwrap();
+
+        packet
+            .proto_encode(Address::default(), None, 0, true, None, None)
+            .unwrap();
+
+        packet.as_slice().len()
+    }
+
+    fn status_report(buf: &mut [u8], ack_ctr: u32) -> usize {
+        let mut packet = initiator_packet(buf, 2, OpCode::StatusReport);
+        packet.proto.set_ack(ack_ctr);
+
+        let wb = packet.get_writebuf().unwrap();
+        wb.le_u16(GeneralCode::Success as u16).unwrap();
+        wb.le_u32(PROTO_ID_SECURE_CHANNEL as u32).unwrap();
+        wb.le_u16(SCStatusCodes::SessionEstablishmentSuccess as u16)
+            .unwrap();
+
+        packet
+            .proto_encode(Address::default(), None, 0, true, None, None)
+            .unwrap();
+
+        packet.as_slice().len()
+    }
+
+    fn device_with_record(dev_att: &TestDevAtt) -> Matter<'_> {
+        let matter = Matter::new(
+            &BASIC_INFO,
+            dev_att,
+            &DummyMdns,
+            dummy_epoch,
+            dummy_rand,
+            5540,
+        );
+
+        let fabric = Fabric::new(
+            KeyPair::new(seq_rand).unwrap(),
+            heapless::Vec::from_slice(&RCA1_SUCCESS).unwrap(),
+            Some(heapless::Vec::from_slice(&ICAC1_SUCCESS).unwrap()),
+            heapless::Vec::from_slice(&NOC1_SUCCESS).unwrap(),
+            &[0x11; 16],
+            0xFFF1,
+            "",
+        )
+        .unwrap();
+
+        let fabric_mgr: &RefCell<FabricMgr> = matter.borrow();
+        let fab_idx = fabric_mgr.borrow_mut().add(fabric, &DummyMdns).unwrap();
+
+        let resumption_mgr: &RefCell<ResumptionMgr> = matter.borrow();
+        resumption_mgr.borrow_mut().store(ResumptionRecord {
+            resumption_id: RESUMPTION_ID,
+            shared_secret: SHARED_SECRET,
+            peer_nodeid: PEER_NODE_ID,
+            fab_idx,
+            peer_catids: Default::default(),
+        });
+
+        matter
+    }
+
+    #[test]
+    /// A Sigma1 carrying the ID and MIC of a stored record resumes the session, with
+    /// the keys the initiator derives from the shared secret
+    fn test_sigma1_resumes_session() {
+        let dev_att = TestDevAtt::new(&KeyPair::new(seq_rand).unwrap());
+        let matter = device_with_record(&dev_att);
+
+        let (new_resumption_id, local_sessid) = resume(&matter).unwrap();
+
+        let mut keys = [0; 3 * crypto::SYMM_KEY_LEN_BYTES];
+        Case::get_resumption_session_keys(
+            &SHARED_SECRET,
+            &INITIATOR_RANDOM,
+            &new_resumption_id,
+            &mut keys,
+        )
+        .unwrap();
+
+        {
+            let mut session_mgr = matter.session_mgr.borrow_mut();
+            let session = session_mgr.get_with_id(local_sessid).unwrap();
+
+            assert!(session.is_encrypted());
+            assert_eq!(session.get_peer_node_id(), Some(PEER_NODE_ID));
+            // The I2R key decrypts what the initiator sends
+            assert_eq!(session.get_dec_key(), Some(&keys[0..16]));
+        }
+
+        // The record can only be used once
+        let resumption_mgr: &RefCell<ResumptionMgr> = matter.borrow();
+        assert!(resumption_mgr.borrow().get(&RESUMPTION_ID).is_none());
+        assert!(resumption_mgr.borrow().get(&new_resumption_id).is_some());
+    }
+
+    #[test]
+    /// The records of a removed fabric go away with it, so that its sessions can no
+    /// longer be resumed
+    fn test_no_resumption_after_fabric_removed() {
+        let dev_att = TestDevAtt::new(&KeyPair::new(seq_rand).unwrap());
+        let matter = device_with_record(&dev_att);
+
+        let fabric_mgr: &RefCell<FabricMgr> = matter.borrow();
+        let resumption_mgr: &RefCell<ResumptionMgr> = matter.borrow();
+        fabric_mgr
+            .borrow_mut()
+            .remove(
+                1,
+                &mut FabricScoped {
+                    acl_mgr: &mut matter.acl_mgr.borrow_mut(),
+                    group_mgr: &mut matter.group_mgr.borrow_mut(),
+                    subscription_mgr: &mut matter.subscription_mgr.borrow_mut(),
+                    session_mgr: &mut matter.session_mgr.borrow_mut(),
+                    binding_mgr: &mut matter.binding_mgr.borrow_mut(),
+                    ota_provider_mgr: &mut matter.ota_provider_mgr.borrow_mut(),
+                    icd_client_mgr: &mut matter.icd_client_mgr.borrow_mut(),
+                    resumption_mgr: &mut resumption_mgr.borrow_mut(),
+                },
+                None,
+                &DummyMdns,
+            )
+            .unwrap();
+
+        assert!(resumption_mgr.borrow().get(&RESUMPTION_ID).is_none());
+
+        // The device does not answer with a Sigma2Resume, but goes on with a full CASE
+        // session establishment, for which there is no fabric either
+        assert!(resume(&matter).is_none());
+    }
+}
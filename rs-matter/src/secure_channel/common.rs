@@ -42,6 +42,7 @@ pub enum OpCode {
     CASESigma3 = 0x32,
     CASESigma2Resume = 0x33,
     StatusReport = 0x40,
+    ICDCheckIn = 0x50,
 }
 
 #[derive(PartialEq)]
@@ -29,7 +29,10 @@ use crate::{
     utils::{epoch::Epoch, rand::Rand},
 };
 
-use super::{case::Case, pake::PaseMgr};
+use super::{
+    case::{Case, ResumptionMgr},
+    pake::PaseMgr,
+};
 
 /* Handle messages related to the Secure Channel
  */
@@ -37,6 +40,7 @@ use super::{case::Case, pake::PaseMgr};
 pub struct SecureChannel<'a> {
     pase: &'a RefCell<PaseMgr>,
     fabric: &'a RefCell<FabricMgr>,
+    resumption: &'a RefCell<ResumptionMgr>,
     mdns: &'a dyn Mdns,
     rand: Rand,
 }
@@ -46,6 +50,7 @@ impl<'a> SecureChannel<'a> {
     pub fn new<
         T: Borrow<RefCell<FabricMgr>>
             + Borrow<RefCell<PaseMgr>>
+            + Borrow<RefCell<ResumptionMgr>>
             + Borrow<dyn Mdns + 'a>
             + Borrow<Epoch>
             + Borrow<Rand>,
@@ -56,6 +61,7 @@ impl<'a> SecureChannel<'a> {
             matter.borrow(),
             matter.borrow(),
             matter.borrow(),
+            matter.borrow(),
             *matter.borrow(),
         )
     }
@@ -64,12 +70,14 @@ impl<'a> SecureChannel<'a> {
     pub fn wrap(
         pase: &'a RefCell<PaseMgr>,
         fabric: &'a RefCell<FabricMgr>,
+        resumption: &'a RefCell<ResumptionMgr>,
         mdns: &'a dyn Mdns,
         rand: Rand,
     ) -> Self {
         Self {
             fabric,
             pase,
+            resumption,
             mdns,
             rand,
         }
@@ -88,7 +96,7 @@ impl<'a> SecureChannel<'a> {
                     .await
             }
             OpCode::CASESigma1 => {
-                Case::new(self.fabric, self.rand)
+                Case::new(self.fabric, self.resumption, self.rand)
                     .handle(exchange, rx, tx)
                     .await
             }
@@ -15,7 +15,7 @@
  *    limitations under the License.
  */
 
-use crate::error::Error;
+use crate::error::{Error, ErrorCode};
 use crate::utils::rand::Rand;
 
 const MATTER_M_BIN: [u8; 65] = [
@@ -68,6 +68,14 @@ impl CryptoSpake2 {
         Ok(())
     }
 
+    /// Use a known random scalar (x or y), so that the computations can be checked against test vectors
+    ///
+    /// Not implemented by this backend yet, so the test vectors cannot be run against it.
+    #[cfg(test)]
+    pub fn set_xy(&mut self, _xy: &[u8]) -> Result<(), Error> {
+        Err(ErrorCode::Unsupported.into())
+    }
+
     #[allow(non_snake_case)]
     #[allow(dead_code)]
     pub fn set_L(&mut self, w1s: &[u8]) -> Result<(), Error> {
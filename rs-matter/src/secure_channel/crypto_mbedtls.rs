@@ -15,25 +15,17 @@
  *    limitations under the License.
  */
 
-use alloc::sync::Arc;
 use core::ops::{Mul, Sub};
 
 use crate::{
+    crypto::rand_scalar,
     error::{Error, ErrorCode},
     utils::rand::Rand,
 };
 
 use byteorder::{ByteOrder, LittleEndian};
 use log::error;
-use mbedtls::{
-    bignum::Mpi,
-    ecp::EcPoint,
-    hash::Md,
-    pk::{EcGroup, EcGroupId, Pk},
-    rng::{CtrDrbg, OsEntropy},
-};
-
-extern crate alloc;
+use mbedtls::{bignum::Mpi, ecp::EcPoint, hash::Md, pk::EcGroup};
 
 const MATTER_M_BIN: [u8; 65] = [
     0x04, 0x88, 0x6e, 0x2f, 0x97, 0xac, 0xe4, 0x6e, 0x55, 0xba, 0x9d, 0xd7, 0x24, 0x25, 0x79, 0xf2,
@@ -116,6 +108,13 @@ impl CryptoSpake2 {
         Ok(())
     }
 
+    /// Use a known random scalar (x or y), so that the computations can be checked against test vectors
+    #[cfg(test)]
+    pub fn set_xy(&mut self, xy: &[u8]) -> Result<(), Error> {
+        self.xy = Mpi::from_binary(xy)?;
+        Ok(())
+    }
+
     #[allow(non_snake_case)]
     pub fn set_L(&mut self, l: &[u8]) -> Result<(), Error> {
         self.L = EcPoint::from_binary(&self.group, l)?;
@@ -135,7 +134,7 @@ impl CryptoSpake2 {
     }
 
     #[allow(non_snake_case)]
-    pub fn get_pB(&mut self, pB: &mut [u8], _rand: Rand) -> Result<(), Error> {
+    pub fn get_pB(&mut self, pB: &mut [u8], rand: Rand) -> Result<(), Error> {
         // From the SPAKE2+ spec (https://datatracker.ietf.org/doc/draft-bar-cfrg-spake2plus/)
         //   for y
         //   - select random y between 0 to p
@@ -143,8 +142,7 @@ impl CryptoSpake2 {
         //   - pB = Y
 
         // A private key on this curve is a random number between 0 to p
-        let mut ctr_drbg = CtrDrbg::new(Arc::new(OsEntropy::new()), None)?;
-        self.xy = Pk::generate_ec(&mut ctr_drbg, EcGroupId::SecP256R1)?.ec_private()?;
+        self.xy = rand_scalar(&self.group, rand)?;
 
         let P = self.group.generator()?;
         self.pB = EcPoint::muladd(&mut self.group, &P, &self.xy, &self.N, &self.w0)?;
@@ -182,7 +180,11 @@ impl CryptoSpake2 {
         // Y = pB
         Self::add_to_tt(&mut TT, pB)?;
 
-        let X = EcPoint::from_binary(&self.group, pA)?;
+        // The share of the peer must be a point on the curve
+        let X = EcPoint::from_binary(&self.group, pA).map_err(|_| ErrorCode::InvalidData)?;
+        if !self.group.contains_point(&X)? {
+            Err(ErrorCode::InvalidData)?;
+        }
         let (Z, V) = Self::get_ZV_as_verifier(
             &self.w0,
             &self.L,
@@ -16,6 +16,7 @@
  */
 
 use crate::{
+    crypto::rand_scalar,
     error::{Error, ErrorCode},
     utils::rand::Rand,
 };
@@ -119,6 +120,13 @@ impl CryptoSpake2 {
         Ok(())
     }
 
+    /// Use a known random scalar (x or y), so that the computations can be checked against test vectors
+    #[cfg(test)]
+    pub fn set_xy(&mut self, xy: &[u8]) -> Result<(), Error> {
+        self.xy = BigNum::from_slice(xy)?;
+        Ok(())
+    }
+
     #[allow(non_snake_case)]
     pub fn set_L(&mut self, l: &[u8]) -> Result<(), Error> {
         self.L = EcPoint::from_bytes(&self.group, l, &mut self.bn_ctx)?;
@@ -138,13 +146,13 @@ impl CryptoSpake2 {
     }
 
     #[allow(non_snake_case)]
-    pub fn get_pB(&mut self, pB: &mut [u8], _rand: Rand) -> Result<(), Error> {
+    pub fn get_pB(&mut self, pB: &mut [u8], rand: Rand) -> Result<(), Error> {
         // From the SPAKE2+ spec (https://datatracker.ietf.org/doc/draft-bar-cfrg-spake2plus/)
         //   for y
         //   - select random y between 0 to p
         //   - Y = y*P + w0*N
         //   - pB = Y
-        self.order.rand_range(&mut self.xy)?;
+        self.xy = rand_scalar(&self.order, rand)?;
         let P = self.group.generator();
         self.pB = Self::do_add_mul(
             P,
@@ -191,7 +199,12 @@ impl CryptoSpake2 {
         // Y = pB
         Self::add_to_tt(&mut TT, pB)?;
 
-        let X = EcPoint::from_bytes(&self.group, pA, &mut self.bn_ctx)?;
+        // The share of the peer must be a point on the curve
+        let X = EcPoint::from_bytes(&self.group, pA, &mut self.bn_ctx)
+            .map_err(|_| ErrorCode::InvalidData)?;
+        if !X.is_on_curve(&self.group, &mut self.bn_ctx)? {
+            Err(ErrorCode::InvalidData)?;
+        }
         let (Z, V) = Self::get_ZV_as_verifier(
             &self.w0,
             &self.L,
@@ -19,13 +19,13 @@ use crypto_bigint::Encoding;
 use crypto_bigint::U384;
 use elliptic_curve::ops::*;
 use elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
-use elliptic_curve::Field;
 use elliptic_curve::PrimeField;
 use rand_core::CryptoRng;
 use rand_core::RngCore;
 use sha2::Digest;
 
-use crate::error::Error;
+use crate::crypto::rand_scalar;
+use crate::error::{Error, ErrorCode};
 use crate::utils::rand::Rand;
 
 const MATTER_M_BIN: [u8; 65] = [
@@ -147,6 +147,16 @@ impl CryptoSpake2 {
         Ok(())
     }
 
+    /// Use a known random scalar (x or y), so that the computations can be checked against test vectors
+    #[cfg(test)]
+    pub fn set_xy(&mut self, xy: &[u8]) -> Result<(), Error> {
+        self.xy = Option::from(p256::Scalar::from_repr(
+            *elliptic_curve::generic_array::GenericArray::from_slice(xy),
+        ))
+        .ok_or(ErrorCode::InvalidData)?;
+        Ok(())
+    }
+
     #[allow(non_snake_case)]
     #[allow(dead_code)]
     pub fn set_L(&mut self, l: &[u8]) -> Result<(), Error> {
@@ -171,8 +181,7 @@ impl CryptoSpake2 {
         //   - select random y between 0 to p
         //   - Y = y*P + w0*N
         //   - pB = Y
-        let mut rand = RandRngCore(rand);
-        self.xy = p256::Scalar::random(&mut rand);
+        self.xy = *rand_scalar(rand)?.to_nonzero_scalar();
 
         let P = p256::AffinePoint::GENERATOR;
         let N = p256::AffinePoint::from_encoded_point(&self.N).unwrap();
@@ -206,8 +215,10 @@ impl CryptoSpake2 {
         // Y = pB
         Self::add_to_tt(&mut TT, pB)?;
 
-        let X = p256::EncodedPoint::from_bytes(pA).unwrap();
-        let X = p256::AffinePoint::from_encoded_point(&X).unwrap();
+        // The share of the peer must be a point on the curve
+        let X = p256::EncodedPoint::from_bytes(pA).map_err(|_| ErrorCode::InvalidData)?;
+        let X = Option::<p256::AffinePoint>::from(p256::AffinePoint::from_encoded_point(&X))
+            .ok_or(ErrorCode::InvalidData)?;
         let L = p256::AffinePoint::from_encoded_point(&self.L).unwrap();
         let M = p256::AffinePoint::from_encoded_point(&self.M).unwrap();
         let (Z, V) = Self::get_ZV_as_verifier(self.w0, L, M, X, self.xy)?;
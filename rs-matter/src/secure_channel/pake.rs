@@ -24,7 +24,7 @@ use super::{
 use crate::{
     alloc, crypto,
     error::{Error, ErrorCode},
-    mdns::{Mdns, ServiceMode},
+    mdns::{CommissioningMode, Mdns, ServiceMode},
     secure_channel::common::{complete_with_status, OpCode},
     tlv::{self, get_root_node_struct, FromTLV, OctetStr, TLVWriter, TagType, ToTLV},
     transport::{
@@ -36,13 +36,26 @@ use crate::{
 };
 use log::{error, info};
 
+/// The administrator which opened a commissioning window
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WindowAdmin {
+    pub fab_idx: u8,
+    pub vendor_id: u16,
+}
+
 struct PaseSession {
     mdns_service_name: heapless::String<16>,
     verifier: VerifierData,
+    mode: CommissioningMode,
+    // When the window closes on its own, if ever
+    expires_at: Option<Duration>,
+    admin: Option<WindowAdmin>,
 }
 
 pub struct PaseMgr {
     session: Option<PaseSession>,
+    // The passcode verifier and the discriminator of the device, for basic commissioning windows
+    basic: Option<(VerifierData, u16)>,
     timeout: Option<Timeout>,
     epoch: Epoch,
     rand: Rand,
@@ -53,6 +66,7 @@ impl PaseMgr {
     pub fn new(epoch: Epoch, rand: Rand) -> Self {
         Self {
             session: None,
+            basic: None,
             timeout: None,
             epoch,
             rand,
@@ -63,10 +77,81 @@ impl PaseMgr {
         self.session.is_some()
     }
 
+    /// The kind of the commissioning window that is open, if any
+    pub fn commissioning_mode(&self) -> Option<CommissioningMode> {
+        self.session.as_ref().map(|session| session.mode)
+    }
+
+    /// The administrator which opened the current commissioning window, if any
+    pub fn window_admin(&self) -> Option<WindowAdmin> {
+        self.session.as_ref().and_then(|session| session.admin)
+    }
+
+    /// Set the passcode verifier and the discriminator of the device, with which
+    /// basic commissioning windows are opened
+    pub fn set_basic_commissioning(&mut self, verifier: VerifierData, discriminator: u16) {
+        self.basic = Some((verifier, discriminator));
+    }
+
+    pub fn basic_commissioning(&self) -> Option<(VerifierData, u16)> {
+        self.basic.clone()
+    }
+
+    /// Open a commissioning window on behalf of an administrator, which closes on its own
+    /// after `timeout`
+    ///
+    /// Fails with `Busy` if a window is already open.
+    pub fn open_window(
+        &mut self,
+        verifier: VerifierData,
+        discriminator: u16,
+        mode: CommissioningMode,
+        timeout: Duration,
+        admin: WindowAdmin,
+        mdns: &dyn Mdns,
+    ) -> Result<(), Error> {
+        if self.session.is_some() {
+            Err(ErrorCode::Busy)?;
+        }
+
+        self.enable_pase_session(verifier, discriminator, mode, mdns)?;
+
+        let expires_at = (self.epoch)() + timeout;
+        if let Some(session) = self.session.as_mut() {
+            session.expires_at = Some(expires_at);
+            session.admin = Some(admin);
+        }
+
+        Ok(())
+    }
+
+    /// When the commissioning window closes on its own, as per the epoch
+    pub fn window_expires_at(&self) -> Option<Duration> {
+        self.session.as_ref().and_then(|session| session.expires_at)
+    }
+
+    /// Close the commissioning window, if its timeout has elapsed
+    pub fn expire_window(&mut self, mdns: &dyn Mdns) -> Result<(), Error> {
+        let expired = self
+            .session
+            .as_ref()
+            .and_then(|session| session.expires_at)
+            .map(|expires_at| (self.epoch)() >= expires_at)
+            .unwrap_or(false);
+
+        if expired {
+            info!("Commissioning window timed out");
+            self.disable_pase_session(mdns)?;
+        }
+
+        Ok(())
+    }
+
     pub fn enable_pase_session(
         &mut self,
         verifier: VerifierData,
         discriminator: u16,
+        mode: CommissioningMode,
         mdns: &dyn Mdns,
     ) -> Result<(), Error> {
         let mut buf = [0; 8];
@@ -78,12 +163,15 @@ impl PaseMgr {
 
         mdns.add(
             &mdns_service_name,
-            ServiceMode::Commissionable(discriminator),
+            ServiceMode::Commissionable(discriminator, mode),
         )?;
 
         self.session = Some(PaseSession {
             mdns_service_name,
             verifier,
+            mode,
+            expires_at: None,
+            admin: None,
         });
 
         Ok(())
@@ -146,12 +234,34 @@ impl<'a> Pake<'a> {
     ) -> Result<(), Error> {
         let mut spake2p = alloc!(Spake2P::new());
 
-        self.handle_pbkdfparamrequest(exchange, rx, tx, &mut spake2p)
-            .await?;
-        self.handle_pasepake1(exchange, rx, tx, &mut spake2p)
+        let result = self
+            .handle_steps(exchange, rx, tx, mdns, &mut spake2p)
+            .await;
+
+        match result.as_ref().err().and_then(failure_status) {
+            Some(status) => {
+                error!(
+                    "PASE handshake failed: {:?}, reporting an invalid parameter",
+                    result.unwrap_err()
+                );
+                complete_with_status(exchange, tx, status, None).await
+            }
+            None => result,
+        }
+    }
+
+    async fn handle_steps(
+        &mut self,
+        exchange: &mut Exchange<'_>,
+        rx: &mut Packet<'_>,
+        tx: &mut Packet<'_>,
+        mdns: &dyn Mdns,
+        spake2p: &mut Spake2P,
+    ) -> Result<(), Error> {
+        self.handle_pbkdfparamrequest(exchange, rx, tx, spake2p)
             .await?;
-        self.handle_pasepake3(exchange, rx, tx, mdns, &mut spake2p)
-            .await
+        self.handle_pasepake1(exchange, rx, tx, spake2p).await?;
+        self.handle_pasepake3(exchange, rx, tx, mdns, spake2p).await
     }
 
     #[allow(non_snake_case)]
@@ -263,40 +373,13 @@ impl<'a> Pake<'a> {
         let pase = self.pase.borrow();
         let session = pase.session.as_ref().ok_or(ErrorCode::NoSession)?;
 
-        let root = tlv::get_root_node(rx.as_slice())?;
-        let a = PBKDFParamReq::from_tlv(&root)?;
-        if a.passcode_id != 0 {
-            error!("Can't yet handle passcode_id != 0");
-            Err(ErrorCode::Invalid)?;
-        }
-
-        let mut our_random: [u8; 32] = [0; 32];
-        (self.pase.borrow().rand)(&mut our_random);
+        let a = extract_pbkdfparamrequest(rx.as_slice())?;
 
         let local_sessid = exchange.with_session_mgr_mut(|mgr| Ok(mgr.get_next_sess_id()))?;
         let spake2p_data: u32 = ((local_sessid as u32) << 16) | a.initiator_ssid as u32;
         spake2p.set_app_data(spake2p_data);
 
-        // Generate response
-        tx.reset();
-        tx.set_proto_id(PROTO_ID_SECURE_CHANNEL);
-        tx.set_proto_opcode(OpCode::PBKDFParamResponse as u8);
-
-        let mut tw = TLVWriter::new(tx.get_writebuf()?);
-        let mut resp = PBKDFParamResp {
-            init_random: a.initiator_random,
-            our_random: OctetStr(&our_random),
-            local_sessid,
-            params: None,
-        };
-        if !a.has_params {
-            let params_resp = PBKDFParamRespParams {
-                count: session.verifier.count,
-                salt: OctetStr(&session.verifier.salt),
-            };
-            resp.params = Some(params_resp);
-        }
-        resp.to_tlv(&mut tw, TagType::Anonymous)?;
+        create_pbkdfparamresponse(tx, &a, &session.verifier, local_sessid, pase.rand)?;
 
         spake2p.set_context(rx.as_slice(), tx.as_mut_slice())?;
 
@@ -387,6 +470,67 @@ struct PBKDFParamResp<'a> {
     params: Option<PBKDFParamRespParams<'a>>,
 }
 
+/// The status to report back to the initiator for errors caused by a malformed
+/// or unexpected handshake message, so that it knows why PASE failed.
+///
+/// Other errors are local ones, which are not worth reporting.
+fn failure_status(err: &Error) -> Option<SCStatusCodes> {
+    match err.code() {
+        ErrorCode::Invalid
+        | ErrorCode::InvalidData
+        | ErrorCode::InvalidOpcode
+        | ErrorCode::NoTagFound
+        | ErrorCode::TLVNotFound
+        | ErrorCode::TLVTypeMismatch
+        | ErrorCode::TruncatedPacket
+        | ErrorCode::Crypto => Some(SCStatusCodes::InvalidParameter),
+        _ => None,
+    }
+}
+
+fn extract_pbkdfparamrequest(buf: &[u8]) -> Result<PBKDFParamReq, Error> {
+    let root = tlv::get_root_node(buf)?;
+    let req = PBKDFParamReq::from_tlv(&root)?;
+    if req.passcode_id != 0 {
+        error!("Can't yet handle passcode_id != 0");
+        Err(ErrorCode::Invalid)?;
+    }
+
+    Ok(req)
+}
+
+/// Encode the PBKDFParamResponse to `req`, with a random of ours drawn from `rand`
+fn create_pbkdfparamresponse(
+    tx: &mut Packet,
+    req: &PBKDFParamReq,
+    verifier: &VerifierData,
+    local_sessid: u16,
+    rand: Rand,
+) -> Result<(), Error> {
+    let mut our_random: [u8; 32] = [0; 32];
+    rand(&mut our_random);
+
+    tx.reset();
+    tx.set_proto_id(PROTO_ID_SECURE_CHANNEL);
+    tx.set_proto_opcode(OpCode::PBKDFParamResponse as u8);
+
+    let mut tw = TLVWriter::new(tx.get_writebuf()?);
+    let mut resp = PBKDFParamResp {
+        init_random: req.initiator_random,
+        our_random: OctetStr(&our_random),
+        local_sessid,
+        params: None,
+    };
+    if !req.has_params {
+        let params_resp = PBKDFParamRespParams {
+            count: verifier.count,
+            salt: OctetStr(verifier.salt()),
+        };
+        resp.params = Some(params_resp);
+    }
+    resp.to_tlv(&mut tw, TagType::Anonymous)
+}
+
 #[allow(non_snake_case)]
 fn extract_pasepake_1_or_3_params(buf: &[u8]) -> Result<&[u8], Error> {
     let root = get_root_node_struct(buf)?;
@@ -402,3 +546,266 @@ struct PBKDFParamReq<'a> {
     passcode_id: u16,
     has_params: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use core::{borrow::Borrow, cell::RefCell, pin::pin};
+
+    use embassy_futures::select::{select, Either};
+
+    use crate::{
+        crypto::KeyPair,
+        data_model::sdm::dev_att::tests::TestDevAtt,
+        error::{Error, ErrorCode},
+        mdns::{CommissioningMode, DummyMdns, Mdns, ServiceMode},
+        secure_channel::{
+            common::{OpCode, PROTO_ID_SECURE_CHANNEL},
+            spake2p::VerifierData,
+        },
+        test_support::{seq_rand, BASIC_INFO, HANDLER},
+        tlv::{get_root_node_struct, TLVWriter, TagType},
+        transport::{
+            core::PacketBuffers,
+            network::Address,
+            packet::{Packet, MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+            pipe::Pipe,
+            proto_hdr::ExchFlags,
+        },
+        utils::{epoch::dummy_epoch, rand::dummy_rand, writebuf::WriteBuf},
+        CommissioningData, Matter,
+    };
+
+    use super::{failure_status, PaseMgr};
+
+    const PEER_NODE_ID: u64 = 0x1234;
+
+    /// Records the mode of the last published service
+    struct MockMdns(RefCell<Option<ServiceMode>>);
+
+    impl Mdns for MockMdns {
+        fn add(&self, _service: &str, mode: ServiceMode) -> Result<(), Error> {
+            *self.0.borrow_mut() = Some(mode);
+            Ok(())
+        }
+
+        fn remove(&self, _service: &str) -> Result<(), Error> {
+            *self.0.borrow_mut() = None;
+            Ok(())
+        }
+    }
+
+    /// A device with no fabrics, i.e. with its commissioning window open
+    fn device(dev_att: &TestDevAtt) -> Matter<'_> {
+        Matter::new(
+            &BASIC_INFO,
+            dev_att,
+            &DummyMdns,
+            dummy_epoch,
+            dummy_rand,
+            5540,
+        )
+    }
+
+    fn dev_comm() -> CommissioningData {
+        CommissioningData {
+            verifier: VerifierData::new_with_pw(123456, dummy_rand),
+            discriminator: 250,
+        }
+    }
+
+    /// Start PASE with a PBKDFParamRequest of `payload`, returning the opcode and
+    /// the payload of the first message, other than a standalone ack, which the
+    /// device answers with
+    fn reply_to(matter: &Matter, payload: &[u8]) -> (u8, heapless::Vec<u8, MAX_TX_BUF_SIZE>) {
+        let mut buffers = PacketBuffers::new();
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+
+        let tx_pipe = Pipe::new(&mut tx_buf);
+        let rx_pipe = Pipe::new(&mut rx_buf);
+
+        let run = matter.run_piped(&mut buffers, &tx_pipe, &rx_pipe, dev_comm(), &HANDLER);
+
+        let initiator = async {
+            let mut buf = [0; MAX_TX_BUF_SIZE];
+            let len = pbkdfparamrequest(&mut buf, payload);
+            rx_pipe.send(Address::default(), &buf[..len]).await;
+
+            loop {
+                let mut reply = [0; MAX_TX_BUF_SIZE];
+                let (len, _) = tx_pipe.recv(&mut reply).await;
+
+                let mut reply = Packet::new_rx(&mut reply[..len]);
+                reply.plain_hdr_decode().unwrap();
+                reply.proto_decode(0, None).unwrap();
+
+                assert_eq!(reply.get_proto_id(), PROTO_ID_SECURE_CHANNEL);
+
+                let opcode = reply.get_proto_raw_opcode();
+                if opcode != OpCode::MRPStandAloneAck as u8 {
+                    break (opcode, heapless::Vec::from_slice(reply.as_slice()).unwrap());
+                }
+            }
+        };
+
+        match embassy_futures::block_on(select(pin!(run), pin!(initiator))) {
+            Either::First(result) => panic!("The transport exited: {:?}", result),
+            Either::Second(reply) => reply,
+        }
+    }
+
+    /// Encode a PBKDFParamRequest of `payload`, returning its length
+    fn pbkdfparamrequest(buf: &mut [u8], payload: &[u8]) -> usize {
+        let mut packet = Packet::new_tx(buf);
+        packet.plain.ctr = 1;
+        packet.plain.set_src_u64(PEER_NODE_ID);
+        packet.set_proto_id(PROTO_ID_SECURE_CHANNEL);
+        packet.set_proto_opcode(OpCode::PBKDFParamRequest as u8);
+        packet.proto.exch_id = 1;
+        packet.proto.exch_flags |= ExchFlags::INITIATOR;
+        packet.proto.set_reliable();
+
+        packet.get_writebuf().unwrap().append(payload).unwrap();
+
+        packet
+            .proto_encode(Address::default(), None, 0, true, None, None)
+            .unwrap();
+
+        packet.as_slice().len()
+    }
+
+    /// A well-formed request, without the PBKDF parameters of the device
+    #[cfg(feature = "std")]
+    fn valid_pbkdfparamrequest(buf: &mut [u8]) -> &[u8] {
+        let mut wb = WriteBuf::new(buf);
+        let mut tw = TLVWriter::new(&mut wb);
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.str8(TagType::Context(1), &[0x55; 32]).unwrap();
+        tw.u16(TagType::Context(2), 1).unwrap();
+        tw.u16(TagType::Context(3), 0).unwrap();
+        tw.bool(TagType::Context(4), false).unwrap();
+        tw.end_container().unwrap();
+
+        let len = wb.as_slice().len();
+        &buf[..len]
+    }
+
+    #[test]
+    /// Malformed requests are answered with a StatusReport, so that the initiator
+    /// knows why PASE failed
+    fn test_malformed_pbkdfparamrequest() {
+        // General code FAILURE, protocol ID of the secure channel, protocol code INVALID_PARAMETER
+        const INVALID_PARAMETER_REPORT: [u8; 8] = [1, 0, 0, 0, 0, 0, 2, 0];
+
+        let malformed: [&[u8]; 4] = [
+            // Truncated in the middle of the initiator random
+            &[0x15, 0x30, 0x01, 0x20, 0x00, 0x01],
+            // The initiator session ID is missing
+            &[0x15, 0x30, 0x01, 0x01, 0xaa, 0x18],
+            // Not even a TLV element
+            &[],
+            // A well-formed request with a passcode ID we do not support
+            &[
+                0x15, 0x30, 0x01, 0x01, 0xaa, 0x25, 0x02, 0x01, 0x00, 0x25, 0x03, 0x01, 0x00, 0x28,
+                0x04, 0x18,
+            ],
+        ];
+
+        let dev_att = TestDevAtt::new(&KeyPair::new(seq_rand).unwrap());
+
+        for payload in malformed {
+            let matter = device(&dev_att);
+
+            let (opcode, report) = reply_to(&matter, payload);
+            assert_eq!(opcode, OpCode::StatusReport as u8);
+            assert_eq!(report.as_slice(), &INVALID_PARAMETER_REPORT);
+        }
+    }
+
+    #[test]
+    fn test_local_errors_are_not_reported() {
+        assert!(failure_status(&ErrorCode::NoSpace.into()).is_none());
+        assert!(failure_status(&ErrorCode::NoExchange.into()).is_none());
+    }
+
+    #[test]
+    /// The commissionable service is advertised with the kind of the window that is open
+    fn commissioning_window_mode() {
+        let mdns = MockMdns(RefCell::new(None));
+        let mut pase_mgr = PaseMgr::new(dummy_epoch, dummy_rand);
+
+        pase_mgr
+            .enable_pase_session(
+                VerifierData::new_with_pw(123456, dummy_rand),
+                840,
+                CommissioningMode::Basic,
+                &mdns,
+            )
+            .unwrap();
+        assert_eq!(
+            *mdns.0.borrow(),
+            Some(ServiceMode::Commissionable(840, CommissioningMode::Basic))
+        );
+        assert_eq!(
+            pase_mgr.commissioning_mode(),
+            Some(CommissioningMode::Basic)
+        );
+
+        pase_mgr.disable_pase_session(&mdns).unwrap();
+        assert!(mdns.0.borrow().is_none());
+        assert!(pase_mgr.commissioning_mode().is_none());
+
+        pase_mgr
+            .enable_pase_session(
+                VerifierData::new(&[0; 97], 1000, &[0; 32]),
+                840,
+                CommissioningMode::Enhanced,
+                &mdns,
+            )
+            .unwrap();
+        assert_eq!(
+            *mdns.0.borrow(),
+            Some(ServiceMode::Commissionable(
+                840,
+                CommissioningMode::Enhanced
+            ))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    /// A seeded generator makes the random of the device in the handshake reproducible
+    fn seeded_pbkdfparamresponse() {
+        use crate::utils::rand::{test_rand, TestRand};
+
+        // The first 32 bytes of SplitMix64, seeded with 0x5eed
+        const OUR_RANDOM: [u8; 32] = [
+            0xb4, 0xa9, 0xf0, 0x03, 0x9d, 0xfd, 0xf1, 0x09, 0x75, 0x84, 0xbf, 0x1b, 0x16, 0x74,
+            0x32, 0x55, 0xb3, 0x43, 0xb3, 0x96, 0x46, 0xca, 0x5b, 0x5d, 0x8d, 0x52, 0x22, 0x7d,
+            0x6c, 0x9b, 0xd2, 0x70,
+        ];
+
+        let dev_att = TestDevAtt::new(&KeyPair::new(seq_rand).unwrap());
+        let matter = device(&dev_att);
+
+        // Only PASE draws from the seeded generator, so that the transport does not
+        // consume any of its output
+        let pase_mgr: &RefCell<PaseMgr> = matter.borrow();
+        *pase_mgr.borrow_mut() = PaseMgr::new(dummy_epoch, test_rand);
+
+        // Seeded only once the commissioning window is open, as its mDNS
+        // service name is drawn from the generator too
+        let mut buf = [0; MAX_RX_BUF_SIZE];
+        matter.start_comissioning(dev_comm(), &mut buf).unwrap();
+        TestRand::seed(0x5eed);
+
+        let mut req = [0; 64];
+        let (opcode, resp) = reply_to(&matter, valid_pbkdfparamrequest(&mut req));
+        assert_eq!(opcode, OpCode::PBKDFParamResponse as u8);
+
+        let root = get_root_node_struct(&resp).unwrap();
+        assert_eq!(root.find_tag(1).unwrap().slice().unwrap(), &[0x55; 32]);
+        assert_eq!(root.find_tag(2).unwrap().slice().unwrap(), &OUR_RANDOM);
+    }
+}
@@ -73,8 +73,13 @@ const CRYPTO_GROUP_SIZE_BYTES: usize = 32;
 const CRYPTO_W_SIZE_BYTES: usize = CRYPTO_GROUP_SIZE_BYTES + 8;
 const CRYPTO_PUBLIC_KEY_SIZE_BYTES: usize = (2 * CRYPTO_GROUP_SIZE_BYTES) + 1;
 
-const MAX_SALT_SIZE_BYTES: usize = 32;
-const VERIFIER_SIZE_BYTES: usize = CRYPTO_GROUP_SIZE_BYTES + CRYPTO_PUBLIC_KEY_SIZE_BYTES;
+pub const MIN_SALT_SIZE_BYTES: usize = 16;
+pub const MAX_SALT_SIZE_BYTES: usize = 32;
+pub const VERIFIER_SIZE_BYTES: usize = CRYPTO_GROUP_SIZE_BYTES + CRYPTO_PUBLIC_KEY_SIZE_BYTES;
+
+/// The range of the PBKDF2 iteration counts that a verifier may be computed with
+pub const MIN_ITERATION_COUNT: u32 = 1000;
+pub const MAX_ITERATION_COUNT: u32 = 100000;
 
 fn crypto_spake2_new() -> Result<CryptoSpake2, Error> {
     CryptoSpake2::new()
@@ -86,14 +91,17 @@ impl Default for Spake2P {
     }
 }
 
+#[derive(Clone)]
 pub struct VerifierData {
     pub data: VerifierOption,
     // For the VerifierOption::Verifier, the following fields only serve
     // information purposes
     pub salt: [u8; MAX_SALT_SIZE_BYTES],
+    pub salt_len: usize,
     pub count: u32,
 }
 
+#[derive(Clone)]
 pub enum VerifierOption {
     /// With Password
     Password(u32),
@@ -105,6 +113,7 @@ impl VerifierData {
     pub fn new_with_pw(pw: u32, rand: Rand) -> Self {
         let mut s = Self {
             salt: [0; MAX_SALT_SIZE_BYTES],
+            salt_len: MAX_SALT_SIZE_BYTES,
             count: SPAKE2_ITERATION_COUNT,
             data: VerifierOption::Password(pw),
         };
@@ -126,8 +135,13 @@ impl VerifierData {
             data: VerifierOption::Verifier(v),
             count,
             salt: s,
+            salt_len: salt.len(),
         }
     }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt[..self.salt_len]
+    }
 }
 
 impl Spake2P {
@@ -172,7 +186,7 @@ impl Spake2P {
             VerifierOption::Password(pw) => {
                 // Derive w0 and L from the password
                 let mut w0w1s: [u8; 2 * CRYPTO_W_SIZE_BYTES] = [0; (2 * CRYPTO_W_SIZE_BYTES)];
-                Spake2P::get_w0w1s(pw, verifier.count, &verifier.salt, &mut w0w1s);
+                Spake2P::get_w0w1s(pw, verifier.count, verifier.salt(), &mut w0w1s);
 
                 let w0s_len = w0w1s.len() / 2;
                 if let Some(crypto_spake2) = &mut self.crypto_spake2 {
@@ -207,6 +221,12 @@ impl Spake2P {
             Err(ErrorCode::InvalidState)?;
         }
 
+        // pA must be an uncompressed point; whether it is on the curve is checked by the backend
+        if pA.len() != CRYPTO_PUBLIC_KEY_SIZE_BYTES || pA[0] != 0x04 {
+            error!("Invalid pA");
+            Err(ErrorCode::InvalidData)?;
+        }
+
         if let Some(crypto_spake2) = &mut self.crypto_spake2 {
             crypto_spake2.get_pB(pB, rand)?;
             if let Some(context) = self.context.take() {
@@ -283,12 +303,21 @@ impl Spake2P {
 #[cfg(test)]
 mod tests {
 
-    use super::Spake2P;
+    use super::{Spake2P, VerifierData, CRYPTO_GROUP_SIZE_BYTES, VERIFIER_SIZE_BYTES};
     use crate::{
         crypto,
-        secure_channel::{spake2p::CRYPTO_W_SIZE_BYTES, spake2p_test_vectors::test_vectors::*},
+        error::ErrorCode,
+        secure_channel::{
+            crypto::CryptoSpake2, spake2p::CRYPTO_W_SIZE_BYTES,
+            spake2p_test_vectors::test_vectors::*,
+        },
+        test_support::seq_rand,
+        utils::rand::dummy_rand,
     };
 
+    // The context of the RFC vectors
+    const RFC_CONTEXT: &[u8] = b"SPAKE2+-P256-SHA256-HKDF draft-01";
+
     #[test]
     fn test_pbkdf2() {
         // These are the vectors from one sample run of chip-tool along with our PBKDFParamResponse
@@ -328,4 +357,86 @@ mod tests {
             assert_eq!(cB, t.cB);
         }
     }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_verifier_vectors() {
+        // The verifier always uses empty identifiers, which is the last of the RFC vectors
+        let t = &RFC_T[3];
+
+        let mut c = CryptoSpake2::new().unwrap();
+        c.set_w0(&t.w0).unwrap();
+        c.set_L(&t.L).unwrap();
+        c.set_xy(&t.y).unwrap();
+
+        let mut TT_hash = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        c.get_TT_as_verifier(RFC_CONTEXT, &t.X, &t.Y, &mut TT_hash)
+            .unwrap();
+
+        let mut expected = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        let mut h = crypto::Sha256::new().unwrap();
+        h.update(&t.TT[0..t.TT_len]).unwrap();
+        h.finish(&mut expected).unwrap();
+        assert_eq!(TT_hash, expected);
+
+        // The shared secret and the confirmation values
+        let mut Ke: [u8; 16] = [0; 16];
+        let mut cA: [u8; 32] = [0; 32];
+        let mut cB: [u8; 32] = [0; 32];
+        Spake2P::get_Ke_and_cAcB(&TT_hash, &t.X, &t.Y, &mut Ke, &mut cA, &mut cB).unwrap();
+        assert_eq!(Ke, t.Ke);
+        assert_eq!(cA, t.cA);
+        assert_eq!(cB, t.cB);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    /// A `Rand` which never produces a valid y fails the handshake, rather than spinning forever
+    fn test_get_pB_with_broken_rand() {
+        let t = &RFC_T[3];
+
+        let mut c = CryptoSpake2::new().unwrap();
+        c.set_w0(&t.w0).unwrap();
+        c.set_L(&t.L).unwrap();
+
+        let mut pB = [0; 65];
+        assert_eq!(
+            c.get_pB(&mut pB, dummy_rand).map_err(|e| e.code()),
+            Err(ErrorCode::Crypto)
+        );
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_invalid_pA() {
+        let t = &RFC_T[3];
+
+        let mut verifier = [0; VERIFIER_SIZE_BYTES];
+        verifier[..CRYPTO_GROUP_SIZE_BYTES].copy_from_slice(&t.w0);
+        verifier[CRYPTO_GROUP_SIZE_BYTES..].copy_from_slice(&t.L);
+        let verifier = VerifierData::new(&verifier, 1000, &[0; 16]);
+
+        // Not on the curve
+        let mut off_curve = t.X;
+        off_curve[64] ^= 0x01;
+        // Not an uncompressed point
+        let mut compressed = [0; 33];
+        compressed.copy_from_slice(&t.X[..33]);
+        compressed[0] = 0x02 | (t.X[64] & 0x01);
+
+        for pA in [&off_curve[..], &compressed[..], &t.X[..64]] {
+            let mut spake2p = Spake2P::new();
+            spake2p.set_context(&[], &[]).unwrap();
+            spake2p.start_verifier(&verifier).unwrap();
+
+            let mut pB = [0; 65];
+            let mut cB = [0; 32];
+            assert_eq!(
+                spake2p
+                    .handle_pA(pA, &mut pB, &mut cB, seq_rand)
+                    .map_err(|e| e.code()),
+                Err(ErrorCode::InvalidData)
+            );
+        }
+    }
 }
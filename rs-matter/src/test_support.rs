@@ -0,0 +1,77 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! What the unit tests of the modules of the crate share
+
+use crate::data_model::{
+    cluster_basic_information::{BasicInfoConfig, ProductAppearance, ProductFinish},
+    objects::{EmptyHandler, Node},
+};
+
+/// The basic information of the device under test
+pub const BASIC_INFO: BasicInfoConfig<'static> = BasicInfoConfig {
+    vid: 10,
+    pid: 11,
+    hw_ver: 12,
+    sw_ver: 13,
+    sw_ver_str: "13",
+    serial_no: "aabbccdd",
+    device_name: "Test Device",
+    product_appearance: ProductAppearance::new(ProductFinish::Other, None),
+    device_type: None,
+};
+
+/// A node without endpoints, for the tests of the transport and the secure channel
+pub const HANDLER: (Node<'static>, EmptyHandler) = (
+    Node {
+        id: 0,
+        endpoints: &[],
+    },
+    EmptyHandler,
+);
+
+/// A [`Rand`](crate::utils::rand::Rand) which fills the buffer with 1, 2, 3, ..., so that
+/// e.g. the generated keys are valid, and the same with every run
+pub fn seq_rand(buf: &mut [u8]) {
+    for (index, b) in buf.iter_mut().enumerate() {
+        *b = index as u8 + 1;
+    }
+}
+
+/// Define a mock epoch: the static `$now`, which is the current time, and the
+/// [`Epoch`](crate::utils::epoch::Epoch) `$epoch`, which reads it
+///
+/// The time is in milliseconds, or in seconds with `secs`. Tests run in parallel, so a
+/// test which advances the time defines the mock epoch inside its body, i.e. has a clock
+/// of its own.
+macro_rules! mock_epoch {
+    ($now:ident, $epoch:ident) => {
+        $crate::test_support::mock_epoch!($now, $epoch, from_millis);
+    };
+    ($now:ident, $epoch:ident, secs) => {
+        $crate::test_support::mock_epoch!($now, $epoch, from_secs);
+    };
+    ($now:ident, $epoch:ident, $from:ident) => {
+        static $now: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+        fn $epoch() -> core::time::Duration {
+            core::time::Duration::$from($now.load(core::sync::atomic::Ordering::SeqCst))
+        }
+    };
+}
+
+pub(crate) use mock_epoch;
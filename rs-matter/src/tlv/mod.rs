@@ -27,6 +27,58 @@ pub enum TagType {
     FullQual48(u64),
     FullQual64(u64),
 }
+
+impl TagType {
+    /// A tag of the Matter Common Profile, encoded in 2 bytes if it fits
+    pub const fn common(tag: u32) -> Self {
+        if tag <= u16::MAX as u32 {
+            Self::CommonPrf16(tag as u16)
+        } else {
+            Self::CommonPrf32(tag)
+        }
+    }
+
+    /// A tag of the profile implied by the context, encoded in 2 bytes if it fits
+    pub const fn implicit(tag: u32) -> Self {
+        if tag <= u16::MAX as u32 {
+            Self::ImplPrf16(tag as u16)
+        } else {
+            Self::ImplPrf32(tag)
+        }
+    }
+
+    /// A fully-qualified tag of a vendor profile, encoded in 6 bytes if the tag
+    /// number fits in 2 bytes
+    pub const fn full_qual(vendor_id: u16, profile: u16, tag: u32) -> Self {
+        let qualifier = ((profile as u64) << 16) | vendor_id as u64;
+
+        if tag <= u16::MAX as u32 {
+            Self::FullQual48(((tag as u64) << 32) | qualifier)
+        } else {
+            Self::FullQual64(((tag as u64) << 32) | qualifier)
+        }
+    }
+
+    /// The tag number, if this is not an anonymous tag
+    pub const fn tag_number(&self) -> Option<u32> {
+        match *self {
+            Self::Anonymous => None,
+            Self::Context(tag) => Some(tag as u32),
+            Self::CommonPrf16(tag) | Self::ImplPrf16(tag) => Some(tag as u32),
+            Self::CommonPrf32(tag) | Self::ImplPrf32(tag) => Some(tag),
+            Self::FullQual48(tag) | Self::FullQual64(tag) => Some((tag >> 32) as u32),
+        }
+    }
+
+    /// The (vendor id, profile number) of a fully-qualified tag
+    pub const fn profile(&self) -> Option<(u16, u16)> {
+        match *self {
+            Self::FullQual48(tag) | Self::FullQual64(tag) => Some((tag as u16, (tag >> 16) as u16)),
+            _ => None,
+        }
+    }
+}
+
 pub const TAG_SHIFT_BITS: u8 = 5;
 pub const TAG_MASK: u8 = 0xe0;
 pub const TYPE_MASK: u8 = 0x1f;
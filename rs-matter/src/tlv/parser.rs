@@ -23,13 +23,82 @@ use log::{error, info};
 
 use super::{TagType, MAX_TAG_INDEX, TAG_MASK, TAG_SHIFT_BITS, TAG_SIZE_MAP, TYPE_MASK};
 
+/// The maximum nesting depth of containers, which is accepted by default when checking
+/// untrusted data. None of the messages of the spec come close to it.
+pub const MAX_NESTING_DEPTH: usize = 16;
+
 pub struct TLVList<'a> {
     buf: &'a [u8],
+    max_depth: usize,
+    strict_order: bool,
 }
 
 impl<'a> TLVList<'a> {
     pub fn new(buf: &'a [u8]) -> TLVList<'a> {
-        TLVList { buf }
+        TLVList {
+            buf,
+            max_depth: MAX_NESTING_DEPTH,
+            strict_order: false,
+        }
+    }
+
+    pub fn with_max_depth(self, max_depth: usize) -> TLVList<'a> {
+        TLVList { max_depth, ..self }
+    }
+
+    /// Require the context tags of the members of all structures to be strictly increasing
+    ///
+    /// This is not required for interoperability, but helps catching encoder bugs
+    pub fn with_strict_order(self) -> TLVList<'a> {
+        TLVList {
+            strict_order: true,
+            ..self
+        }
+    }
+
+    /// Check that the containers in the list are not nested deeper than the maximum depth,
+    /// and in strict mode, that the members of the structures are in order
+    ///
+    /// The check walks the list linearly, so that arbitrarily deep data can't exhaust the stack
+    pub fn check(&self) -> Result<(), Error> {
+        let mut depth = 0_usize;
+
+        for element in self.iter() {
+            if is_container(&element.element_type) {
+                depth += 1;
+                if depth > self.max_depth {
+                    error!("TLV containers nested deeper than {}", self.max_depth);
+                    Err(ErrorCode::TLVTooNested)?;
+                }
+
+                if self.strict_order {
+                    Self::check_order(&element)?;
+                }
+            } else if element.element_type == ElementType::EndCnt {
+                depth = depth.saturating_sub(1);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_order(element: &TLVElement) -> Result<(), Error> {
+        if let ElementType::Struct(_) = element.element_type {
+            let mut prev_tag = None;
+
+            for member in element.enter().into_iter().flatten() {
+                if let TagType::Context(tag) = member.tag_type {
+                    if prev_tag.map(|prev| tag <= prev).unwrap_or(false) {
+                        error!("TLV context tag {} out of order", tag);
+                        Err(ErrorCode::TLVTagOrder)?;
+                    }
+
+                    prev_tag = Some(tag);
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -155,9 +224,23 @@ static VALUE_EXTRACTOR: [ExtractValue; MAX_VALUE_INDEX] = [
     // True 9
     { |_t| (0, ElementType::True) },
     // F32  10
-    { |_t| (0, ElementType::Last) },
+    {
+        |t| {
+            (
+                0,
+                ElementType::F32(LittleEndian::read_f32(&t.buf[t.current..])),
+            )
+        }
+    },
     // F64  11
-    { |_t| (0, ElementType::Last) },
+    {
+        |t| {
+            (
+                0,
+                ElementType::F64(LittleEndian::read_f64(&t.buf[t.current..])),
+            )
+        }
+    },
     // Utf8l 12
     {
         |t| match read_length_value(1, t) {
@@ -274,7 +357,7 @@ impl<'a> PartialEq for TLVElement<'a> {
                         return false;
                     }
                 };
-                let mut nest_level = 0_u8;
+                let mut nest_level = 0_usize;
                 loop {
                     let ours = our_iter.next();
                     let theirs = their.next();
@@ -411,6 +494,22 @@ impl<'a> TLVElement<'a> {
         }
     }
 
+    pub fn f32(&self) -> Result<f32, Error> {
+        match self.element_type {
+            ElementType::F32(a) => Ok(a),
+            _ => Err(ErrorCode::TLVTypeMismatch.into()),
+        }
+    }
+
+    pub fn f64(&self) -> Result<f64, Error> {
+        match self.element_type {
+            ElementType::F32(a) => Ok(a.into()),
+            ElementType::F64(a) => Ok(a),
+            _ => Err(ErrorCode::TLVTypeMismatch.into()),
+        }
+    }
+
+    /// The contents of a string element, borrowed from the TLV buffer without copying
     pub fn slice(&self) -> Result<&'a [u8], Error> {
         match self.element_type {
             ElementType::Str8l(s)
@@ -421,18 +520,27 @@ impl<'a> TLVElement<'a> {
         }
     }
 
+    /// Like `slice`, but the contents are also validated as UTF-8
     pub fn str(&self) -> Result<&'a str, Error> {
         match self.element_type {
             ElementType::Str8l(s)
             | ElementType::Utf8l(s)
             | ElementType::Str16l(s)
-            | ElementType::Utf16l(s) => {
-                Ok(core::str::from_utf8(s).map_err(|_| Error::from(ErrorCode::InvalidData))?)
-            }
+            | ElementType::Utf16l(s) => Ok(core::str::from_utf8(s)?),
             _ => Err(ErrorCode::TLVTypeMismatch.into()),
         }
     }
 
+    /// Same as `slice`: the returned bytes point into the TLV buffer
+    pub fn get_bytes_ref(&self) -> Result<&'a [u8], Error> {
+        self.slice()
+    }
+
+    /// Same as `str`: the returned string points into the TLV buffer
+    pub fn get_str_ref(&self) -> Result<&'a str, Error> {
+        self.str()
+    }
+
     pub fn bool(&self) -> Result<bool, Error> {
         match self.element_type {
             ElementType::False => Ok(false),
@@ -693,17 +801,14 @@ impl<'a> Iterator for TLVContainerIterator<'a> {
 }
 
 pub fn get_root_node(b: &[u8]) -> Result<TLVElement, Error> {
-    Ok(TLVList::new(b)
-        .iter()
-        .next()
-        .ok_or(ErrorCode::InvalidData)?)
+    let list = TLVList::new(b);
+    list.check()?;
+
+    Ok(list.iter().next().ok_or(ErrorCode::InvalidData)?)
 }
 
 pub fn get_root_node_struct(b: &[u8]) -> Result<TLVElement, Error> {
-    let root = TLVList::new(b)
-        .iter()
-        .next()
-        .ok_or(ErrorCode::InvalidData)?;
+    let root = get_root_node(b)?;
 
     root.confirm_struct()?;
 
@@ -711,10 +816,7 @@ pub fn get_root_node_struct(b: &[u8]) -> Result<TLVElement, Error> {
 }
 
 pub fn get_root_node_list(b: &[u8]) -> Result<TLVElement, Error> {
-    let root = TLVList::new(b)
-        .iter()
-        .next()
-        .ok_or(ErrorCode::InvalidData)?;
+    let root = get_root_node(b)?;
 
     root.confirm_list()?;
 
@@ -775,12 +877,89 @@ pub fn print_tlv_list(b: &[u8]) {
     info!("---------");
 }
 
+/// Render the TLV payload as an indented tree, with the tag, the type and the value of
+/// every element
+#[cfg(feature = "std")]
+pub fn pretty_print(b: &[u8]) -> Result<String, Error> {
+    use core::fmt::Write;
+
+    let mut out = String::new();
+    let mut closers = Vec::new();
+    let mut iter = TLVList::new(b).iter();
+
+    for element in iter.by_ref() {
+        if let ElementType::EndCnt = element.element_type {
+            let closer = closers.pop().ok_or(ErrorCode::InvalidData)?;
+            writeln!(out, "{:indent$}{}", "", closer, indent = closers.len() * 4)
+                .map_err(|_| ErrorCode::NoSpace)?;
+            continue;
+        }
+
+        write!(
+            out,
+            "{:indent$}{:?}: ",
+            "",
+            element.tag_type,
+            indent = closers.len() * 4
+        )
+        .map_err(|_| ErrorCode::NoSpace)?;
+
+        let result = match element.element_type {
+            ElementType::S8(v) => writeln!(out, "S8 {}", v),
+            ElementType::S16(v) => writeln!(out, "S16 {}", v),
+            ElementType::S32(v) => writeln!(out, "S32 {}", v),
+            ElementType::S64(v) => writeln!(out, "S64 {}", v),
+            ElementType::U8(v) => writeln!(out, "U8 {}", v),
+            ElementType::U16(v) => writeln!(out, "U16 {}", v),
+            ElementType::U32(v) => writeln!(out, "U32 {}", v),
+            ElementType::U64(v) => writeln!(out, "U64 {}", v),
+            ElementType::F32(v) => writeln!(out, "F32 {}", v),
+            ElementType::F64(v) => writeln!(out, "F64 {}", v),
+            ElementType::False => writeln!(out, "False"),
+            ElementType::True => writeln!(out, "True"),
+            ElementType::Null => writeln!(out, "Null"),
+            ElementType::Utf8l(v) | ElementType::Utf16l(v) => match core::str::from_utf8(v) {
+                Ok(v) => writeln!(out, "UTF8 len[{}] {:?}", v.len(), v),
+                Err(_) => writeln!(out, "UTF8 len[{}] (invalid) {:02x?}", v.len(), v),
+            },
+            ElementType::Str8l(v) | ElementType::Str16l(v) => write!(out, "Bytes len[{}]", v.len())
+                .and_then(|_| {
+                    v.iter().try_for_each(|byte| write!(out, " {:02x}", byte))?;
+                    writeln!(out)
+                }),
+            ElementType::Struct(_) => {
+                closers.push('}');
+                writeln!(out, "Struct {{")
+            }
+            ElementType::Array(_) => {
+                closers.push(']');
+                writeln!(out, "Array [")
+            }
+            ElementType::List(_) => {
+                closers.push(']');
+                writeln!(out, "List [")
+            }
+            _ => Err(ErrorCode::InvalidData)?,
+        };
+
+        result.map_err(|_| ErrorCode::NoSpace)?;
+    }
+
+    // All of the payload must be valid, with all containers closed
+    if iter.current < b.len() || !closers.is_empty() {
+        Err(ErrorCode::InvalidData)?;
+    }
+
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
     use log::info;
 
     use super::{
         get_root_node_list, get_root_node_struct, ElementType, TLVElement, TLVList, TagType,
+        MAX_NESTING_DEPTH,
     };
     use crate::error::ErrorCode;
 
@@ -879,6 +1058,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_string_no_copy() {
+        let b = [
+            0x15, 0x30, 0x1, 0x2, 0xaa, 0xbb, 0x2c, 0x2, 0x3, 0x61, 0x62, 0x63, 0x18,
+        ];
+        let root = get_root_node_struct(&b).unwrap();
+
+        let bytes = root.find_tag(1).unwrap().get_bytes_ref().unwrap();
+        assert_eq!(bytes, [0xaa, 0xbb]);
+        assert_eq!(bytes.as_ptr(), b[4..].as_ptr());
+
+        let string = root.find_tag(2).unwrap().get_str_ref().unwrap();
+        assert_eq!(string, "abc");
+        assert_eq!(string.as_ptr(), b[9..].as_ptr());
+
+        // Not a string
+        assert_eq!(
+            root.find_tag(1).unwrap().u8().map_err(|e| e.code()),
+            Err(ErrorCode::TLVTypeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_string_invalid_utf8() {
+        let b = [0x15, 0x2c, 0x1, 0x3, 0x61, 0xc3, 0x28, 0x18];
+        let root = get_root_node_struct(&b).unwrap();
+        let element = root.find_tag(1).unwrap();
+
+        assert_eq!(element.get_bytes_ref().unwrap(), [0x61, 0xc3, 0x28]);
+        assert_eq!(
+            element.get_str_ref().map_err(|e| e.code()),
+            Err(ErrorCode::Utf8Fail)
+        );
+    }
+
     #[test]
     fn test_valid_value_string16() {
         // This is a tagged string, with tag 0 and length 4, and we have 4 bytes in the string
@@ -1204,4 +1418,86 @@ mod tests {
         assert_eq!(list_iter.next(), None);
         assert_eq!(list_iter.next(), None);
     }
+
+    #[test]
+    fn test_nesting_depth() {
+        // 200 nested anonymous structures
+        let mut b = [0x15; 400];
+        b[200..].fill(0x18);
+
+        assert_eq!(
+            get_root_node_struct(&b).map_err(|e| e.code()),
+            Err(ErrorCode::TLVTooNested)
+        );
+
+        let mut b = [0x15; 2 * MAX_NESTING_DEPTH];
+        b[MAX_NESTING_DEPTH..].fill(0x18);
+        assert!(get_root_node_struct(&b).is_ok());
+
+        assert_eq!(
+            TLVList::new(&b)
+                .with_max_depth(MAX_NESTING_DEPTH - 1)
+                .check()
+                .map_err(|e| e.code()),
+            Err(ErrorCode::TLVTooNested)
+        );
+    }
+
+    #[test]
+    fn test_strict_tag_order() {
+        // {0: 1, 1: {0: 2, 2: 3}}
+        let b = [
+            0x15, 0x24, 0x0, 0x1, 0x35, 0x1, 0x24, 0x0, 0x2, 0x24, 0x2, 0x3, 0x18, 0x18,
+        ];
+        assert!(TLVList::new(&b).check().is_ok());
+        assert!(TLVList::new(&b).with_strict_order().check().is_ok());
+
+        // {0: 1, 1: {2: 2, 0: 3}}
+        let b = [
+            0x15, 0x24, 0x0, 0x1, 0x35, 0x1, 0x24, 0x2, 0x2, 0x24, 0x0, 0x3, 0x18, 0x18,
+        ];
+        assert!(TLVList::new(&b).check().is_ok());
+        assert_eq!(
+            TLVList::new(&b)
+                .with_strict_order()
+                .check()
+                .map_err(|e| e.code()),
+            Err(ErrorCode::TLVTagOrder)
+        );
+
+        // Repeated tags are out of order too
+        let b = [0x15, 0x24, 0x0, 0x1, 0x24, 0x0, 0x1, 0x18];
+        assert!(TLVList::new(&b).check().is_ok());
+        assert!(TLVList::new(&b).with_strict_order().check().is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_pretty_print() {
+        use super::pretty_print;
+
+        // An InvokeResponse, with the command path {0, 0x30, 1}, then an F32 and an octet string
+        let b = [
+            0x15, 0x28, 0x00, 0x36, 0x01, 0x15, 0x35, 0x00, 0x37, 0x00, 0x24, 0x00, 0x00, 0x24,
+            0x01, 0x30, 0x24, 0x02, 0x01, 0x18, 0x35, 0x01, 0x24, 0x00, 0x00, 0x2c, 0x01, 0x02,
+            0x6f, 0x6b, 0x18, 0x18, 0x18, 0x18, 0x24, 0xff, 0x01, 0x2a, 0x02, 0x00, 0x00, 0xc0,
+            0x3f, 0x30, 0x03, 0x02, 0xab, 0xcd, 0x18,
+        ];
+
+        let tree = pretty_print(&b).unwrap();
+        info!("{}", tree);
+
+        assert!(tree.contains("    Context(1): Array [\n"));
+        assert!(tree.contains(
+            "                    Context(0): U8 0\n                    Context(1): U8 48\n                    Context(2): U8 1\n"
+        ));
+        assert!(tree.contains("Context(1): UTF8 len[2] \"ok\"\n"));
+        assert!(tree.contains("Context(255): U8 1\n"));
+        assert!(tree.contains("Context(2): F32 1.5\n"));
+        assert!(tree.contains("Context(3): Bytes len[2] ab cd\n"));
+        assert!(tree.ends_with("}\n"));
+
+        // Unterminated containers are reported
+        assert!(pretty_print(&b[..b.len() - 1]).is_err());
+    }
 }
@@ -91,7 +91,7 @@ macro_rules! fromtlv_for {
     };
 }
 
-fromtlv_for!(i8 u8 i16 u16 i32 u32 i64 u64 bool);
+fromtlv_for!(i8 u8 i16 u16 i32 u32 i64 u64 f32 f64 bool);
 
 pub trait ToTLV {
     fn to_tlv(&self, tw: &mut TLVWriter, tag: TagType) -> Result<(), Error>;
@@ -139,7 +139,7 @@ impl<'a, T: ToTLV> ToTLV for &'a [T] {
 }
 
 // Generate ToTLV for standard data types
-totlv_for!(i8 u8 i16 u16 i32 u32 i64 u64 bool);
+totlv_for!(i8 u8 i16 u16 i32 u32 i64 u64 f32 f64 bool);
 
 // We define a few common data types that will be required here
 //
@@ -429,6 +429,8 @@ impl<'a> ToTLV for TLVElement<'a> {
             ElementType::U16(v) => v.to_tlv(tw, self.get_tag()),
             ElementType::U32(v) => v.to_tlv(tw, self.get_tag()),
             ElementType::U64(v) => v.to_tlv(tw, self.get_tag()),
+            ElementType::F32(v) => v.to_tlv(tw, self.get_tag()),
+            ElementType::F64(v) => v.to_tlv(tw, self.get_tag()),
             ElementType::False => tw.bool(self.get_tag(), false),
             ElementType::True => tw.bool(self.get_tag(), true),
             ElementType::Utf8l(v) | ElementType::Utf16l(v) => tw.utf16(self.get_tag(), v),
@@ -448,7 +450,7 @@ impl<'a> ToTLV for TLVElement<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{FromTLV, OctetStr, TLVWriter, TagType, ToTLV};
+    use super::{FromTLV, Nullable, OctetStr, TLVWriter, TagType, ToTLV};
     use crate::{error::Error, tlv::TLVList, utils::writebuf::WriteBuf};
     use rs_matter_macros::{FromTLV, ToTLV};
 
@@ -480,6 +482,39 @@ mod tests {
         b: u32,
     }
 
+    #[test]
+    fn test_float_round_trip() {
+        // A NaN with a payload, which must survive as is
+        let nan = f64::from_bits(0x7ff8_0000_dead_beef);
+        let values = [1.5, -0.0, nan, f64::INFINITY, f64::NEG_INFINITY];
+
+        for value in values {
+            let mut buf = [0; 20];
+            let mut writebuf = WriteBuf::new(&mut buf);
+            let mut tw = TLVWriter::new(&mut writebuf);
+
+            tw.start_struct(TagType::Anonymous).unwrap();
+            value.to_tlv(&mut tw, TagType::Context(0)).unwrap();
+            (value as f32).to_tlv(&mut tw, TagType::Context(1)).unwrap();
+            tw.end_container().unwrap();
+
+            let root = TLVList::new(writebuf.as_slice()).iter().next().unwrap();
+
+            let decoded = f64::from_tlv(&root.find_tag(0).unwrap()).unwrap();
+            assert_eq!(decoded.to_bits(), value.to_bits());
+
+            let decoded = f32::from_tlv(&root.find_tag(1).unwrap()).unwrap();
+            assert_eq!(decoded.to_bits(), (value as f32).to_bits());
+        }
+
+        // Doubles are never written as floats, even if they would fit
+        let mut buf = [0; 20];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+        1.5_f64.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+        assert_eq!(writebuf.as_slice(), [0x0b, 0, 0, 0, 0, 0, 0, 0xf8, 0x3f]);
+    }
+
     #[test]
     fn test_derive_fromtlv() {
         let b = [
@@ -524,6 +559,71 @@ mod tests {
         assert_eq!(test.c, Some(11));
     }
 
+    #[derive(FromTLV, ToTLV, PartialEq, Debug)]
+    struct TestDeriveNullable {
+        a: u16,
+        b: Option<u16>,
+        c: Nullable<u16>,
+        d: Option<super::Nullable<u16>>,
+    }
+
+    #[test]
+    fn test_derive_from_to_tlv_nullable() {
+        let cases = [
+            // Absent optional fields, and a null mandatory field
+            (
+                TestDeriveNullable {
+                    a: 1,
+                    b: None,
+                    c: Nullable::Null,
+                    d: None,
+                },
+                &[21, 36, 0, 1, 52, 2, 24][..],
+            ),
+            // Present optional fields, the last one being null
+            (
+                TestDeriveNullable {
+                    a: 1,
+                    b: Some(2),
+                    c: Nullable::NotNull(3),
+                    d: Some(Nullable::Null),
+                },
+                &[21, 36, 0, 1, 36, 1, 2, 36, 2, 3, 52, 3, 24],
+            ),
+            (
+                TestDeriveNullable {
+                    a: 1,
+                    b: None,
+                    c: Nullable::NotNull(3),
+                    d: Some(Nullable::NotNull(4)),
+                },
+                &[21, 36, 0, 1, 36, 2, 3, 36, 3, 4, 24],
+            ),
+        ];
+
+        for (value, encoded) in cases {
+            let mut buf = [0; 20];
+            let mut writebuf = WriteBuf::new(&mut buf);
+            let mut tw = TLVWriter::new(&mut writebuf);
+
+            value.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+            assert_eq!(writebuf.as_slice(), encoded);
+
+            let root = TLVList::new(encoded).iter().next().unwrap();
+            assert_eq!(TestDeriveNullable::from_tlv(&root).unwrap(), value);
+        }
+
+        // A nullable field which is not optional cannot be absent
+        let b = [21, 36, 0, 1, 36, 1, 2, 24];
+        let root = TLVList::new(&b).iter().next().unwrap();
+        assert!(TestDeriveNullable::from_tlv(&root).is_err());
+
+        // And an optional field which is not nullable cannot be null
+        let b = [21, 36, 0, 1, 52, 1, 52, 2, 24];
+        let root = TLVList::new(&b).iter().next().unwrap();
+        assert!(TestDeriveNullable::from_tlv(&root).is_err());
+    }
+
     #[derive(FromTLV, ToTLV, Debug)]
     struct TestDeriveFabScoped {
         a: u16,
@@ -582,4 +682,64 @@ mod tests {
             [21, 36, 1, 10, 24, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
         );
     }
+
+    #[derive(ToTLV, FromTLV, PartialEq, Debug)]
+    enum TestDeriveCEnum {
+        ValueA = 1,
+        ValueB,
+        ValueC = 0x1234,
+    }
+
+    #[derive(ToTLV, FromTLV, PartialEq, Debug)]
+    #[repr(u16)]
+    enum TestDeriveCEnumUnknown {
+        ValueA = 0,
+        ValueB = 5,
+        #[tlvargs(unknown)]
+        Unknown(u32),
+    }
+
+    #[test]
+    fn test_derive_from_to_tlv_c_enum() {
+        for (value, encoded) in [
+            (TestDeriveCEnum::ValueA, &[4, 1][..]),
+            (TestDeriveCEnum::ValueB, &[4, 2]),
+            (TestDeriveCEnum::ValueC, &[5, 0x34, 0x12]),
+        ] {
+            let mut buf = [0; 20];
+            let mut writebuf = WriteBuf::new(&mut buf);
+            let mut tw = TLVWriter::new(&mut writebuf);
+
+            value.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+            assert_eq!(writebuf.as_slice(), encoded);
+
+            let root = TLVList::new(encoded).iter().next().unwrap();
+            assert_eq!(TestDeriveCEnum::from_tlv(&root).unwrap(), value);
+        }
+
+        // Without a catch-all, unknown discriminants are rejected
+        let root = TLVList::new(&[4, 3]).iter().next().unwrap();
+        assert!(TestDeriveCEnum::from_tlv(&root).is_err());
+    }
+
+    #[test]
+    fn test_derive_fromtlv_c_enum_unknown() {
+        let root = TLVList::new(&[4, 5]).iter().next().unwrap();
+        assert_eq!(
+            TestDeriveCEnumUnknown::from_tlv(&root).unwrap(),
+            TestDeriveCEnumUnknown::ValueB
+        );
+
+        let root = TLVList::new(&[5, 0x00, 0x01]).iter().next().unwrap();
+        let value = TestDeriveCEnumUnknown::from_tlv(&root).unwrap();
+        assert_eq!(value, TestDeriveCEnumUnknown::Unknown(0x100));
+
+        // The unknown discriminant is written back as is
+        let mut buf = [0; 20];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        value.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+        assert_eq!(writebuf.as_slice(), [5, 0x00, 0x01]);
+    }
 }
@@ -20,6 +20,7 @@ use crate::{error::*, utils::writebuf::WriteBuf};
 use log::error;
 
 #[allow(dead_code)]
+#[derive(Clone, Copy)]
 enum WriteElementType {
     S8 = 0,
     S16 = 1,
@@ -49,13 +50,79 @@ enum WriteElementType {
     Last,
 }
 
+/// A destination for TLV data which doesn't fit in the buffer of a `TLVWriter`,
+/// like a sequence of packets
+pub trait TLVSink {
+    /// Take the data the writer has buffered so far
+    fn flush(&mut self, data: &[u8]) -> Result<(), Error>;
+}
+
 pub struct TLVWriter<'a, 'b> {
     buf: &'a mut WriteBuf<'b>,
+    sink: Option<&'a mut dyn TLVSink>,
 }
 
 impl<'a, 'b> TLVWriter<'a, 'b> {
     pub fn new(buf: &'a mut WriteBuf<'b>) -> Self {
-        TLVWriter { buf }
+        TLVWriter { buf, sink: None }
+    }
+
+    /// Create a writer which flushes its buffer to the sink, whenever the next element
+    /// doesn't fit in it anymore. Elements are never split across flushes.
+    ///
+    /// Call `flush()` once done, to also hand the remaining data over to the sink.
+    /// Note that anchors obtained with `get_tail()` are not valid across flushes.
+    pub fn new_with_sink(buf: &'a mut WriteBuf<'b>, sink: &'a mut dyn TLVSink) -> Self {
+        TLVWriter {
+            buf,
+            sink: Some(sink),
+        }
+    }
+
+    /// Hand the buffered data over to the sink, if any, and empty the buffer
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(sink) = self.sink.as_mut() {
+            sink.flush(self.buf.as_slice())?;
+            self.buf.rewind_tail_to(self.buf.get_start());
+        }
+
+        Ok(())
+    }
+
+    /// Write a complete element, flushing the buffer to the sink once if the element
+    /// doesn't fit in it
+    fn put<F>(&mut self, tag_type: TagType, val_type: WriteElementType, f: F) -> Result<(), Error>
+    where
+        F: Fn(&mut WriteBuf<'b>) -> Result<(), Error>,
+    {
+        let anchor = self.buf.get_tail();
+
+        match self.put_element(tag_type, val_type, &f) {
+            Err(e)
+                if e.code() == ErrorCode::NoSpace
+                    && self.sink.is_some()
+                    && anchor > self.buf.get_start() =>
+            {
+                self.buf.rewind_tail_to(anchor);
+                self.flush()?;
+
+                self.put_element(tag_type, val_type, &f)
+            }
+            result => result,
+        }
+    }
+
+    fn put_element<F>(
+        &mut self,
+        tag_type: TagType,
+        val_type: WriteElementType,
+        f: &F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&mut WriteBuf<'b>) -> Result<(), Error>,
+    {
+        self.put_control_tag(tag_type, val_type)?;
+        f(self.buf)
     }
 
     // TODO: The current method of using writebuf's put methods force us to do
@@ -86,21 +153,18 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
     }
 
     pub fn i8(&mut self, tag_type: TagType, data: i8) -> Result<(), Error> {
-        self.put_control_tag(tag_type, WriteElementType::S8)?;
-        self.buf.le_i8(data)
+        self.put(tag_type, WriteElementType::S8, |buf| buf.le_i8(data))
     }
 
     pub fn u8(&mut self, tag_type: TagType, data: u8) -> Result<(), Error> {
-        self.put_control_tag(tag_type, WriteElementType::U8)?;
-        self.buf.le_u8(data)
+        self.put(tag_type, WriteElementType::U8, |buf| buf.le_u8(data))
     }
 
     pub fn i16(&mut self, tag_type: TagType, data: i16) -> Result<(), Error> {
         if data >= i8::MIN as i16 && data <= i8::MAX as i16 {
             self.i8(tag_type, data as i8)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::S16)?;
-            self.buf.le_i16(data)
+            self.put(tag_type, WriteElementType::S16, |buf| buf.le_i16(data))
         }
     }
 
@@ -108,8 +172,7 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
         if data <= 0xff {
             self.u8(tag_type, data as u8)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::U16)?;
-            self.buf.le_u16(data)
+            self.put(tag_type, WriteElementType::U16, |buf| buf.le_u16(data))
         }
     }
 
@@ -119,8 +182,7 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
         } else if data >= i16::MIN as i32 && data <= i16::MAX as i32 {
             self.i16(tag_type, data as i16)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::S32)?;
-            self.buf.le_i32(data)
+            self.put(tag_type, WriteElementType::S32, |buf| buf.le_i32(data))
         }
     }
 
@@ -130,8 +192,7 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
         } else if data <= 0xffff {
             self.u16(tag_type, data as u16)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::U32)?;
-            self.buf.le_u32(data)
+            self.put(tag_type, WriteElementType::U32, |buf| buf.le_u32(data))
         }
     }
 
@@ -143,8 +204,7 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
         } else if data >= i32::MIN as i64 && data <= i32::MAX as i64 {
             self.i32(tag_type, data as i32)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::S64)?;
-            self.buf.le_i64(data)
+            self.put(tag_type, WriteElementType::S64, |buf| buf.le_i64(data))
         }
     }
 
@@ -156,32 +216,43 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
         } else if data <= 0xffffffff {
             self.u32(tag_type, data as u32)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::U64)?;
-            self.buf.le_u64(data)
+            self.put(tag_type, WriteElementType::U64, |buf| buf.le_u64(data))
         }
     }
 
+    // Floats are always written with their own precision, so that they round-trip bit-exactly
+    pub fn f32(&mut self, tag_type: TagType, data: f32) -> Result<(), Error> {
+        self.put(tag_type, WriteElementType::F32, |buf| buf.le_f32(data))
+    }
+
+    pub fn f64(&mut self, tag_type: TagType, data: f64) -> Result<(), Error> {
+        self.put(tag_type, WriteElementType::F64, |buf| buf.le_f64(data))
+    }
+
     pub fn str8(&mut self, tag_type: TagType, data: &[u8]) -> Result<(), Error> {
         if data.len() > 256 {
             error!("use str16() instead");
             return Err(ErrorCode::Invalid.into());
         }
-        self.put_control_tag(tag_type, WriteElementType::Str8l)?;
-        self.buf.le_u8(data.len() as u8)?;
-        self.buf.copy_from_slice(data)
+        self.put(tag_type, WriteElementType::Str8l, |buf| {
+            buf.le_u8(data.len() as u8)?;
+            buf.copy_from_slice(data)
+        })
     }
 
     pub fn str16(&mut self, tag_type: TagType, data: &[u8]) -> Result<(), Error> {
         if data.len() <= 0xff {
             self.str8(tag_type, data)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::Str16l)?;
-            self.buf.le_u16(data.len() as u16)?;
-            self.buf.copy_from_slice(data)
+            self.put(tag_type, WriteElementType::Str16l, |buf| {
+                buf.le_u16(data.len() as u16)?;
+                buf.copy_from_slice(data)
+            })
         }
     }
 
     // This is quite hacky
+    // Note that this never flushes to the sink, as the data can only be generated once
     pub fn str16_as<F>(&mut self, tag_type: TagType, data_gen: F) -> Result<(), Error>
     where
         F: FnOnce(&mut [u8]) -> Result<usize, Error>,
@@ -210,23 +281,25 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
     }
 
     pub fn utf8(&mut self, tag_type: TagType, data: &[u8]) -> Result<(), Error> {
-        self.put_control_tag(tag_type, WriteElementType::Utf8l)?;
-        self.buf.le_u8(data.len() as u8)?;
-        self.buf.copy_from_slice(data)
+        self.put(tag_type, WriteElementType::Utf8l, |buf| {
+            buf.le_u8(data.len() as u8)?;
+            buf.copy_from_slice(data)
+        })
     }
 
     pub fn utf16(&mut self, tag_type: TagType, data: &[u8]) -> Result<(), Error> {
         if data.len() <= 0xff {
             self.utf8(tag_type, data)
         } else {
-            self.put_control_tag(tag_type, WriteElementType::Utf16l)?;
-            self.buf.le_u16(data.len() as u16)?;
-            self.buf.copy_from_slice(data)
+            self.put(tag_type, WriteElementType::Utf16l, |buf| {
+                buf.le_u16(data.len() as u16)?;
+                buf.copy_from_slice(data)
+            })
         }
     }
 
     fn no_val(&mut self, tag_type: TagType, element: WriteElementType) -> Result<(), Error> {
-        self.put_control_tag(tag_type, element)
+        self.put(tag_type, element, |_| Ok(()))
     }
 
     pub fn start_struct(&mut self, tag_type: TagType) -> Result<(), Error> {
@@ -272,7 +345,9 @@ impl<'a, 'b> TLVWriter<'a, 'b> {
 
 #[cfg(test)]
 mod tests {
-    use super::{TLVWriter, TagType};
+    use super::{TLVSink, TLVWriter, TagType};
+    use crate::error::Error;
+    use crate::tlv::get_root_node;
     use crate::utils::writebuf::WriteBuf;
 
     #[test]
@@ -350,4 +425,102 @@ mod tests {
             [36, 1, 13, 48, 2, 5, 10, 11, 12, 13, 14, 48, 3, 2, 10, 11, 36, 4, 13, 0]
         );
     }
+
+    #[test]
+    fn test_profile_tags() {
+        let mut buf = [0; 30];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new(&mut writebuf);
+
+        let tags = [
+            TagType::common(0x1234),
+            TagType::full_qual(0xfff1, 0xdeed, 0xaa55),
+            TagType::full_qual(0xfff1, 0xdeed, 0x12345678),
+        ];
+
+        tw.start_struct(TagType::Anonymous).unwrap();
+        for (i, tag) in tags.iter().enumerate() {
+            tw.u8(*tag, i as u8).unwrap();
+        }
+        tw.end_container().unwrap();
+
+        assert_eq!(
+            writebuf.as_slice(),
+            [
+                0x15, 0x44, 0x34, 0x12, 0, 0xc4, 0xf1, 0xff, 0xed, 0xde, 0x55, 0xaa, 1, 0xe4, 0xf1,
+                0xff, 0xed, 0xde, 0x78, 0x56, 0x34, 0x12, 2, 0x18
+            ]
+        );
+
+        let root = get_root_node(writebuf.as_slice()).unwrap();
+        let mut elements = root.enter().unwrap();
+
+        let element = elements.next().unwrap();
+        assert_eq!(element.get_tag(), TagType::CommonPrf16(0x1234));
+        assert_eq!(element.get_tag().tag_number(), Some(0x1234));
+        assert_eq!(element.get_tag().profile(), None);
+        assert_eq!(element.u8().unwrap(), 0);
+
+        let element = elements.next().unwrap();
+        assert_eq!(element.get_tag(), tags[1]);
+        assert!(matches!(element.get_tag(), TagType::FullQual48(_)));
+        assert_eq!(element.get_tag().tag_number(), Some(0xaa55));
+        assert_eq!(element.get_tag().profile(), Some((0xfff1, 0xdeed)));
+        assert_eq!(element.u8().unwrap(), 1);
+
+        let element = elements.next().unwrap();
+        assert_eq!(element.get_tag(), tags[2]);
+        assert!(matches!(element.get_tag(), TagType::FullQual64(_)));
+        assert_eq!(element.get_tag().tag_number(), Some(0x12345678));
+        assert_eq!(element.get_tag().profile(), Some((0xfff1, 0xdeed)));
+        assert_eq!(element.u8().unwrap(), 2);
+
+        assert!(elements.next().is_none());
+    }
+
+    #[derive(Default)]
+    struct PacketSink {
+        packets: heapless::Vec<heapless::Vec<u8, 128>, 4>,
+    }
+
+    impl TLVSink for PacketSink {
+        fn flush(&mut self, data: &[u8]) -> Result<(), Error> {
+            self.packets
+                .push(heapless::Vec::from_slice(data).unwrap())
+                .unwrap();
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_to_sink() {
+        let mut sink = PacketSink::default();
+
+        let mut buf = [0; 128];
+        let mut writebuf = WriteBuf::new(&mut buf);
+        let mut tw = TLVWriter::new_with_sink(&mut writebuf, &mut sink);
+
+        tw.start_array(TagType::Anonymous).unwrap();
+        for i in 0..64 {
+            tw.u16(TagType::Anonymous, 0x1000 + i).unwrap();
+        }
+        tw.end_container().unwrap();
+        tw.flush().unwrap();
+
+        assert_eq!(sink.packets.len(), 2);
+
+        let mut stream = heapless::Vec::<u8, 256>::new();
+        for packet in &sink.packets {
+            stream.extend_from_slice(packet).unwrap();
+        }
+
+        let root = get_root_node(&stream).unwrap();
+        let mut count = 0;
+        for (i, element) in root.enter().unwrap().enumerate() {
+            assert_eq!(element.u16().unwrap(), 0x1000 + i as u16);
+            count += 1;
+        }
+        assert_eq!(count, 64);
+    }
 }
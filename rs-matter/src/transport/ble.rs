@@ -0,0 +1,253 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The BLE advertising of a commissionable device
+//!
+//! The advertising data carries the Matter service data, which the commissioner uses
+//! to find the device by its discriminator before connecting and opening a BTP session.
+
+use core::time::Duration;
+
+use crate::{data_model::cluster_basic_information::BasicInfoConfig, mdns::ServiceMode};
+
+/// The 16-bit UUID of the Matter BLE service
+pub const MATTER_SERVICE_UUID: u16 = 0xfff6;
+
+/// The length of the Matter service data
+pub const SERVICE_DATA_LEN: usize = 8;
+
+/// The length of the advertising data: the flags, and the Matter service data
+pub const ADV_DATA_LEN: usize = 3 + 4 + SERVICE_DATA_LEN;
+
+/// How long the device advertises with the fast interval after it starts advertising
+pub const FAST_ADV_DURATION: Duration = Duration::from_secs(30);
+
+/// How long the device advertises at most, unless it uses the Extended Announcement
+pub const MAX_ADV_DURATION: Duration = Duration::from_secs(15 * 60);
+
+const OPCODE_COMMISSIONABLE: u8 = 0x00;
+const ADV_VERSION: u16 = 0;
+const DISCRIMINATOR_MASK: u16 = 0x0fff;
+
+const FLAG_ADDITIONAL_DATA: u8 = 0x01;
+const FLAG_EXTENDED_ANNOUNCEMENT: u8 = 0x02;
+
+const AD_TYPE_FLAGS: u8 = 0x01;
+const AD_TYPE_SERVICE_DATA: u8 = 0x16;
+/// LE General Discoverable, BR/EDR not supported
+const AD_FLAGS: u8 = 0x06;
+
+/// The range of the advertising interval, as suggested to the BLE stack
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AdvInterval {
+    pub min_ms: u16,
+    pub max_ms: u16,
+}
+
+impl AdvInterval {
+    pub const FAST: Self = Self {
+        min_ms: 20,
+        max_ms: 60,
+    };
+    pub const SLOW: Self = Self {
+        min_ms: 150,
+        max_ms: 1200,
+    };
+    pub const EXTENDED: Self = Self {
+        min_ms: 1285,
+        max_ms: 1285,
+    };
+}
+
+/// The advertisement of a commissionable device
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BleAdvertisement {
+    discriminator: u16,
+    vid: u16,
+    pid: u16,
+    additional_data: bool,
+    extended_announcement: bool,
+}
+
+impl BleAdvertisement {
+    /// The advertisement of the device in the provided commissioning state, if it
+    /// is commissionable
+    pub fn new(dev_det: &BasicInfoConfig, mode: &ServiceMode) -> Option<Self> {
+        match mode {
+            ServiceMode::Commissioned => None,
+            ServiceMode::Commissionable(discriminator, _) => Some(Self {
+                discriminator: discriminator & DISCRIMINATOR_MASK,
+                vid: dev_det.vid,
+                pid: dev_det.pid,
+                additional_data: false,
+                extended_announcement: false,
+            }),
+        }
+    }
+
+    /// Announce that the device serves the C3 characteristic, with the additional
+    /// commissioning data
+    pub fn with_additional_data(mut self) -> Self {
+        self.additional_data = true;
+        self
+    }
+
+    /// Keep advertising after [`MAX_ADV_DURATION`], with the Extended Announcement
+    pub fn with_extended_announcement(mut self) -> Self {
+        self.extended_announcement = true;
+        self
+    }
+
+    /// The Matter service data, for the BLE stacks which take it apart from the
+    /// rest of the advertising data
+    pub fn service_data(&self) -> [u8; SERVICE_DATA_LEN] {
+        let [disc_lo, disc_hi] = (self.discriminator | (ADV_VERSION << 12)).to_le_bytes();
+        let [vid_lo, vid_hi] = self.vid.to_le_bytes();
+        let [pid_lo, pid_hi] = self.pid.to_le_bytes();
+
+        let mut flags = 0;
+        if self.additional_data {
+            flags |= FLAG_ADDITIONAL_DATA;
+        }
+        if self.extended_announcement {
+            flags |= FLAG_EXTENDED_ANNOUNCEMENT;
+        }
+
+        [
+            OPCODE_COMMISSIONABLE,
+            disc_lo,
+            disc_hi,
+            vid_lo,
+            vid_hi,
+            pid_lo,
+            pid_hi,
+            flags,
+        ]
+    }
+
+    /// The raw advertising data
+    pub fn adv_data(&self) -> [u8; ADV_DATA_LEN] {
+        let [uuid_lo, uuid_hi] = MATTER_SERVICE_UUID.to_le_bytes();
+
+        let mut data = [0; ADV_DATA_LEN];
+        data[..7].copy_from_slice(&[
+            2,
+            AD_TYPE_FLAGS,
+            AD_FLAGS,
+            (3 + SERVICE_DATA_LEN) as u8,
+            AD_TYPE_SERVICE_DATA,
+            uuid_lo,
+            uuid_hi,
+        ]);
+        data[7..].copy_from_slice(&self.service_data());
+
+        data
+    }
+
+    /// The advertising interval after advertising for the provided time, or `None`
+    /// if the device should stop advertising
+    pub fn interval(&self, elapsed: Duration) -> Option<AdvInterval> {
+        if elapsed < FAST_ADV_DURATION {
+            Some(AdvInterval::FAST)
+        } else if elapsed < MAX_ADV_DURATION {
+            Some(AdvInterval::SLOW)
+        } else if self.extended_announcement {
+            Some(AdvInterval::EXTENDED)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use crate::{
+        data_model::cluster_basic_information::BasicInfoConfig,
+        mdns::{CommissioningMode, ServiceMode},
+    };
+
+    use super::{AdvInterval, BleAdvertisement};
+
+    fn dev_det() -> BasicInfoConfig<'static> {
+        BasicInfoConfig {
+            vid: 0xfff1,
+            pid: 0x8000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn commissionable_adv_data() {
+        let adv = BleAdvertisement::new(
+            &dev_det(),
+            &ServiceMode::Commissionable(3840, CommissioningMode::Basic),
+        )
+        .unwrap();
+
+        assert_eq!(
+            adv.adv_data(),
+            [
+                0x02, 0x01, 0x06, // Flags
+                0x0b, 0x16, 0xf6, 0xff, // Service data of the Matter service
+                0x00, // Commissionable
+                0x00, 0x0f, // Discriminator and version
+                0xf1, 0xff, // Vendor ID
+                0x00, 0x80, // Product ID
+                0x00, // No additional data
+            ]
+        );
+
+        let adv = adv.with_additional_data();
+        assert_eq!(
+            adv.service_data(),
+            [0x00, 0x00, 0x0f, 0xf1, 0xff, 0x00, 0x80, 0x01]
+        );
+    }
+
+    #[test]
+    fn not_advertised_when_commissioned() {
+        assert_eq!(
+            BleAdvertisement::new(&dev_det(), &ServiceMode::Commissioned),
+            None
+        );
+    }
+
+    #[test]
+    fn interval_slows_down() {
+        let adv = BleAdvertisement::new(
+            &dev_det(),
+            &ServiceMode::Commissionable(3840, CommissioningMode::Basic),
+        )
+        .unwrap();
+
+        assert_eq!(adv.interval(Duration::ZERO), Some(AdvInterval::FAST));
+        assert_eq!(
+            adv.interval(Duration::from_secs(60)),
+            Some(AdvInterval::SLOW)
+        );
+        assert_eq!(adv.interval(Duration::from_secs(16 * 60)), None);
+
+        let adv = adv.with_extended_announcement();
+        assert_eq!(adv.service_data()[7], 0x02);
+        assert_eq!(
+            adv.interval(Duration::from_secs(16 * 60)),
+            Some(AdvInterval::EXTENDED)
+        );
+    }
+}
@@ -0,0 +1,671 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The Bluetooth Transport Protocol (BTP), in its peripheral role
+//!
+//! BTP carries the Matter messages of the commissioning over BLE. The central writes
+//! its BTP packets to the C1 characteristic, and the peripheral indicates its own ones
+//! on the C2 characteristic. After the handshake, each message is split in segments
+//! of up to the negotiated segment size, and a side has at most as many packets in
+//! flight as the negotiated window, until the other side acknowledges them.
+
+use byteorder::{ByteOrder, LittleEndian};
+use log::{error, info, warn};
+
+use crate::error::{Error, ErrorCode};
+
+use super::packet::{MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE};
+
+/// The version of BTP for Matter
+pub const BTP_VERSION: u8 = 4;
+
+/// The ATT MTU which every BLE link supports
+pub const MIN_ATT_MTU: u16 = 23;
+
+/// The largest segment size supported, which fits in the largest LE data packet
+pub const MAX_SEGMENT_SIZE: u16 = 244;
+
+/// The largest window supported, in packets
+pub const MAX_WINDOW_SIZE: u8 = 6;
+
+/// The time after which a received packet has to be acknowledged, if no packet of
+/// ours acknowledges it earlier
+pub const ACK_TIMEOUT_MS: u32 = 2500;
+
+const ATT_HDR_SIZE: u16 = 3;
+
+const FLAG_BEGINNING: u8 = 0x01;
+const FLAG_CONTINUING: u8 = 0x02;
+const FLAG_ENDING: u8 = 0x04;
+const FLAG_ACK: u8 = 0x08;
+const FLAG_MGMT: u8 = 0x20;
+const FLAG_HANDSHAKE: u8 = 0x40;
+
+const HANDSHAKE_FLAGS: u8 = FLAG_HANDSHAKE | FLAG_MGMT | FLAG_ENDING | FLAG_BEGINNING;
+const HANDSHAKE_OPCODE: u8 = 0x6c;
+
+const HANDSHAKE_REQ_LEN: usize = 9;
+
+/// The GATT server of the Matter service, as provided by the BLE stack of the platform
+///
+/// The writes of the central to the C1 characteristic are fed to [`Btp::on_write`].
+pub trait GattTransport {
+    /// The ATT MTU negotiated with the central, or 0 if not known
+    fn att_mtu(&self) -> u16;
+
+    /// Indicate the packet to the central on the C2 characteristic
+    ///
+    /// The BLE stack is expected to queue the indications, as the central confirms
+    /// them one at a time.
+    fn indicate(&self, data: &[u8]) -> Result<(), Error>;
+}
+
+/// The parameters of the session negotiated by the handshake
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BtpParams {
+    pub version: u8,
+    pub segment_size: u16,
+    pub window_size: u8,
+}
+
+/// A BTP session with a central
+pub struct Btp<'a> {
+    gatt: &'a dyn GattTransport,
+    params: Option<BtpParams>,
+    /// The sequence number of our next packet
+    tx_seq: u8,
+    /// The newest of our packets acknowledged by the central
+    tx_acked: u8,
+    /// The sequence number of the next packet of the central
+    rx_seq: u8,
+    /// The newest packet of the central we acknowledged
+    rx_acked: u8,
+    rx: heapless::Vec<u8, MAX_RX_BUF_SIZE>,
+    /// The length of the message being reassembled, if any
+    rx_len: Option<u16>,
+    tx: heapless::Vec<u8, MAX_TX_BUF_SIZE>,
+    /// How much of the message being sent is already sent, if any
+    tx_offset: Option<usize>,
+}
+
+impl<'a> Btp<'a> {
+    pub fn new(gatt: &'a dyn GattTransport) -> Self {
+        Self {
+            gatt,
+            params: None,
+            tx_seq: 0,
+            tx_acked: 0,
+            rx_seq: 0,
+            rx_acked: 0,
+            rx: heapless::Vec::new(),
+            rx_len: None,
+            tx: heapless::Vec::new(),
+            tx_offset: None,
+        }
+    }
+
+    /// The parameters of the session, if the handshake is complete
+    pub fn params(&self) -> Option<BtpParams> {
+        self.params
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.params.is_some()
+    }
+
+    /// Whether the message passed to [`Btp::send`] is not completely sent yet
+    pub fn is_sending(&self) -> bool {
+        self.tx_offset.is_some()
+    }
+
+    /// Close the session, e.g. when the central disconnects, dropping the messages
+    /// being sent or reassembled
+    pub fn close(&mut self) {
+        self.params = None;
+        self.rx.clear();
+        self.rx_len = None;
+        self.tx.clear();
+        self.tx_offset = None;
+    }
+
+    /// Handle a write of the central to the C1 characteristic, returning the message
+    /// of the central once its last segment is received
+    ///
+    /// A handshake request resets the session. Any error closes the session, as BTP
+    /// does not recover from a lost or unexpected packet.
+    pub fn on_write(&mut self, data: &[u8]) -> Result<Option<&[u8]>, Error> {
+        let complete = match self.handle_write(data) {
+            Ok(complete) => complete,
+            Err(err) => {
+                error!("Closing the BTP session: {:?}", err);
+                self.close();
+                return Err(err);
+            }
+        };
+
+        Ok(complete.then_some(self.rx.as_slice()))
+    }
+
+    /// Send the message to the central, as many of its segments as the window of
+    /// the central allows now, and the others as the central acknowledges them
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), Error> {
+        if !self.is_open() {
+            Err(ErrorCode::InvalidState)?;
+        }
+
+        if self.is_sending() {
+            Err(ErrorCode::Busy)?;
+        }
+
+        self.tx.clear();
+        self.tx
+            .extend_from_slice(msg)
+            .map_err(|_| ErrorCode::NoSpace)?;
+        self.tx_offset = Some(0);
+
+        self.flush()
+    }
+
+    /// Acknowledge the packets of the central which are not yet acknowledged
+    ///
+    /// To be called when no packet of ours acknowledged them for [`ACK_TIMEOUT_MS`].
+    pub fn send_ack(&mut self) -> Result<(), Error> {
+        if self.rx_unacked() > 0 && self.can_send(true) {
+            self.send_packet(true)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_write(&mut self, data: &[u8]) -> Result<bool, Error> {
+        let flags = *data.first().ok_or(ErrorCode::TruncatedPacket)?;
+
+        if flags & FLAG_HANDSHAKE != 0 {
+            self.handle_handshake(data)?;
+            return Ok(false);
+        }
+
+        let Some(params) = self.params else {
+            return Err(ErrorCode::InvalidState.into());
+        };
+
+        let mut offset = 1;
+        let mut take = |len: usize| {
+            let field = data
+                .get(offset..offset + len)
+                .ok_or(ErrorCode::TruncatedPacket);
+            offset += len;
+            field
+        };
+
+        if flags & FLAG_ACK != 0 {
+            let ack = take(1)?[0];
+            self.handle_ack(ack)?;
+        }
+
+        let seq = take(1)?[0];
+        if seq != self.rx_seq {
+            error!("Expected BTP packet {}, got {}", self.rx_seq, seq);
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        self.rx_seq = seq.wrapping_add(1);
+
+        if self.rx_unacked() > params.window_size {
+            error!("The central overran the BTP window");
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        let complete = if flags & FLAG_BEGINNING != 0 {
+            if self.rx_len.is_some() {
+                error!("A BTP message began before the previous one ended");
+                Err(ErrorCode::InvalidData)?;
+            }
+
+            let len = LittleEndian::read_u16(take(2)?);
+
+            self.rx.clear();
+            self.rx_len = Some(len);
+
+            self.reassemble(len, flags, &data[offset..])?
+        } else if flags & (FLAG_CONTINUING | FLAG_ENDING) != 0 {
+            let Some(len) = self.rx_len else {
+                error!("A BTP segment arrived outside of a message");
+                return Err(ErrorCode::InvalidData.into());
+            };
+
+            self.reassemble(len, flags, &data[offset..])?
+        } else {
+            // A standalone acknowledgement
+            false
+        };
+
+        self.flush()?;
+
+        if self.rx_unacked() > 0 && self.rx_unacked() + 1 >= params.window_size {
+            // The window of the central is about to close
+            self.send_ack()?;
+        }
+
+        Ok(complete)
+    }
+
+    fn reassemble(&mut self, len: u16, flags: u8, payload: &[u8]) -> Result<bool, Error> {
+        let len = len as usize;
+
+        if self.rx.len() + payload.len() > len {
+            error!("The BTP message is longer than announced");
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        self.rx
+            .extend_from_slice(payload)
+            .map_err(|_| ErrorCode::NoSpace)?;
+
+        if flags & FLAG_ENDING != 0 {
+            if self.rx.len() != len {
+                error!("The BTP message is shorter than announced");
+                Err(ErrorCode::InvalidData)?;
+            }
+
+            self.rx_len = None;
+
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn handle_handshake(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() < HANDSHAKE_REQ_LEN || data[1] != HANDSHAKE_OPCODE {
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        if self.is_open() {
+            warn!("The central restarted the BTP session");
+            self.close();
+        }
+
+        // The versions the central supports, a nibble each
+        let supported = data[2..6]
+            .iter()
+            .flat_map(|byte| [byte & 0x0f, byte >> 4])
+            .any(|version| version == BTP_VERSION);
+
+        if !supported {
+            error!("The central does not support BTP version {}", BTP_VERSION);
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        let mtu = match LittleEndian::read_u16(&data[6..8]) {
+            0 => MIN_ATT_MTU,
+            mtu => mtu,
+        };
+        let mtu = match self.gatt.att_mtu() {
+            0 => mtu,
+            gatt_mtu => mtu.min(gatt_mtu),
+        };
+
+        let params = BtpParams {
+            version: BTP_VERSION,
+            segment_size: (mtu.max(MIN_ATT_MTU) - ATT_HDR_SIZE).min(MAX_SEGMENT_SIZE),
+            window_size: data[8].clamp(1, MAX_WINDOW_SIZE),
+        };
+
+        let mut resp = [HANDSHAKE_FLAGS, HANDSHAKE_OPCODE, params.version, 0, 0, 0];
+        LittleEndian::write_u16(&mut resp[3..5], params.segment_size);
+        resp[5] = params.window_size;
+
+        self.gatt.indicate(&resp)?;
+
+        info!("BTP session open: {:?}", params);
+
+        // The handshake response counts as our packet 0, and the central acknowledges it
+        self.params = Some(params);
+        self.tx_seq = 1;
+        self.tx_acked = u8::MAX;
+        self.rx_seq = 0;
+        self.rx_acked = u8::MAX;
+
+        Ok(())
+    }
+
+    fn handle_ack(&mut self, ack: u8) -> Result<(), Error> {
+        let acked = ack.wrapping_sub(self.tx_acked);
+
+        if acked == 0 || acked > self.tx_in_flight() {
+            error!(
+                "The central acknowledged BTP packet {}, which is not in flight",
+                ack
+            );
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        self.tx_acked = ack;
+
+        Ok(())
+    }
+
+    /// Send the segments of the message being sent which the window allows
+    fn flush(&mut self) -> Result<(), Error> {
+        while self.is_sending() && self.can_send(self.rx_unacked() > 0) {
+            self.send_packet(self.rx_unacked() > 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether a packet can be sent, keeping the last slot of the window of the
+    /// central for a packet which acknowledges, so that both windows never block
+    fn can_send(&self, with_ack: bool) -> bool {
+        let Some(params) = self.params else {
+            return false;
+        };
+
+        let in_flight = self.tx_in_flight();
+
+        in_flight + 1 < params.window_size || (with_ack && in_flight < params.window_size)
+    }
+
+    /// Send the next segment of the message being sent, or a standalone
+    /// acknowledgement if none
+    fn send_packet(&mut self, with_ack: bool) -> Result<(), Error> {
+        let params = self.params.ok_or(ErrorCode::InvalidState)?;
+
+        let mut buf = [0; MAX_SEGMENT_SIZE as usize];
+        let mut flags = 0;
+        let mut len = 1;
+
+        if with_ack {
+            flags |= FLAG_ACK;
+            buf[len] = self.rx_seq.wrapping_sub(1);
+            len += 1;
+        }
+
+        buf[len] = self.tx_seq;
+        len += 1;
+
+        if let Some(offset) = self.tx_offset {
+            if offset == 0 {
+                flags |= FLAG_BEGINNING;
+                LittleEndian::write_u16(&mut buf[len..len + 2], self.tx.len() as _);
+                len += 2;
+            }
+
+            let seg_len = (params.segment_size as usize - len).min(self.tx.len() - offset);
+            buf[len..len + seg_len].copy_from_slice(&self.tx[offset..offset + seg_len]);
+            len += seg_len;
+
+            if offset + seg_len == self.tx.len() {
+                flags |= FLAG_ENDING;
+                self.tx_offset = None;
+            } else {
+                if offset > 0 {
+                    flags |= FLAG_CONTINUING;
+                }
+                self.tx_offset = Some(offset + seg_len);
+            }
+        }
+
+        buf[0] = flags;
+
+        self.gatt.indicate(&buf[..len])?;
+
+        if with_ack {
+            self.rx_acked = self.rx_seq.wrapping_sub(1);
+        }
+        self.tx_seq = self.tx_seq.wrapping_add(1);
+
+        Ok(())
+    }
+
+    fn tx_in_flight(&self) -> u8 {
+        self.tx_seq.wrapping_sub(self.tx_acked).wrapping_sub(1)
+    }
+
+    fn rx_unacked(&self) -> u8 {
+        self.rx_seq.wrapping_sub(self.rx_acked).wrapping_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use crate::error::{Error, ErrorCode};
+
+    use super::{Btp, BtpParams, GattTransport, BTP_VERSION};
+
+    type Packet = heapless::Vec<u8, 244>;
+
+    struct Gatt {
+        indications: RefCell<heapless::Vec<Packet, 8>>,
+    }
+
+    impl Gatt {
+        const fn new() -> Self {
+            Self {
+                indications: RefCell::new(heapless::Vec::new()),
+            }
+        }
+
+        fn take(&self) -> heapless::Vec<Packet, 8> {
+            core::mem::take(&mut *self.indications.borrow_mut())
+        }
+    }
+
+    impl GattTransport for Gatt {
+        fn att_mtu(&self) -> u16 {
+            0
+        }
+
+        fn indicate(&self, data: &[u8]) -> Result<(), Error> {
+            self.indications
+                .borrow_mut()
+                .push(Packet::from_slice(data).unwrap())
+                .unwrap();
+
+            Ok(())
+        }
+    }
+
+    /// The handshake of a central supporting the versions 4 and 3, on the minimum MTU
+    fn handshake(btp: &mut Btp, gatt: &Gatt, window_size: u8) {
+        let req = [0x65, 0x6c, 0x34, 0, 0, 0, 23, 0, window_size];
+        assert_eq!(btp.on_write(&req).map(|m| m.is_some()).ok(), Some(false));
+
+        assert_eq!(
+            btp.params(),
+            Some(BtpParams {
+                version: BTP_VERSION,
+                segment_size: 20,
+                window_size,
+            })
+        );
+        assert_eq!(
+            gatt.take().as_slice(),
+            &[Packet::from_slice(&[0x65, 0x6c, 4, 20, 0, window_size]).unwrap()]
+        );
+    }
+
+    /// A packet of the central, with the length of the message for its first segment
+    fn packet(flags: u8, ack: Option<u8>, seq: u8, len: Option<u16>, payload: &[u8]) -> Packet {
+        let mut packet = Packet::new();
+
+        packet
+            .push(flags | if ack.is_some() { 0x08 } else { 0 })
+            .unwrap();
+        packet.extend(ack);
+        packet.push(seq).unwrap();
+        packet.extend(len.into_iter().flat_map(u16::to_le_bytes));
+        packet.extend_from_slice(payload).unwrap();
+
+        packet
+    }
+
+    fn write(btp: &mut Btp, packet: &Packet) -> Result<Option<heapless::Vec<u8, 256>>, ErrorCode> {
+        btp.on_write(packet)
+            .map(|msg| msg.map(|msg| heapless::Vec::from_slice(msg).unwrap()))
+            .map_err(|e| e.code())
+    }
+
+    fn message() -> heapless::Vec<u8, 256> {
+        (0..60).collect()
+    }
+
+    #[test]
+    fn reassemble_across_window() {
+        let gatt = Gatt::new();
+        let mut btp = Btp::new(&gatt);
+
+        handshake(&mut btp, &gatt, 3);
+
+        // 5 bytes of header in the first segment and 2 in the others
+        let msg = message();
+
+        let segment = packet(0x01, Some(0), 0, Some(60), &msg[..15]);
+        assert_eq!(write(&mut btp, &segment), Ok(None));
+        assert!(gatt.take().is_empty());
+
+        // With 2 packets unacknowledged, the window of the central is about to close
+        let segment = packet(0x02, None, 1, None, &msg[15..33]);
+        assert_eq!(write(&mut btp, &segment), Ok(None));
+        assert_eq!(
+            gatt.take().as_slice(),
+            &[Packet::from_slice(&[0x08, 1, 1]).unwrap()]
+        );
+
+        let segment = packet(0x02, Some(1), 2, None, &msg[33..51]);
+        assert_eq!(write(&mut btp, &segment), Ok(None));
+        assert!(gatt.take().is_empty());
+
+        let segment = packet(0x04, None, 3, None, &msg[51..]);
+        assert_eq!(write(&mut btp, &segment), Ok(Some(msg)));
+        assert_eq!(
+            gatt.take().as_slice(),
+            &[Packet::from_slice(&[0x08, 3, 2]).unwrap()]
+        );
+    }
+
+    #[test]
+    fn out_of_order_segment() {
+        let gatt = Gatt::new();
+        let mut btp = Btp::new(&gatt);
+
+        handshake(&mut btp, &gatt, 4);
+
+        let msg = message();
+
+        let segment = packet(0x01, Some(0), 0, Some(60), &msg[..15]);
+        assert_eq!(write(&mut btp, &segment), Ok(None));
+
+        // Packet 1 is skipped, which closes the session
+        let segment = packet(0x02, None, 2, None, &msg[15..33]);
+        assert_eq!(write(&mut btp, &segment), Err(ErrorCode::InvalidData));
+        assert!(!btp.is_open());
+
+        let segment = packet(0x02, None, 1, None, &msg[15..33]);
+        assert_eq!(write(&mut btp, &segment), Err(ErrorCode::InvalidState));
+    }
+
+    #[test]
+    fn reset_mid_message() {
+        let gatt = Gatt::new();
+        let mut btp = Btp::new(&gatt);
+
+        handshake(&mut btp, &gatt, 4);
+
+        let msg = message();
+
+        let segment = packet(0x01, Some(0), 0, Some(60), &msg[..15]);
+        assert_eq!(write(&mut btp, &segment), Ok(None));
+
+        // The central starts over, and the sequence numbers with it
+        handshake(&mut btp, &gatt, 4);
+
+        let segment = packet(0x05, Some(0), 0, Some(5), &msg[..5]);
+        assert_eq!(
+            write(&mut btp, &segment),
+            Ok(Some(heapless::Vec::from_slice(&msg[..5]).unwrap()))
+        );
+    }
+
+    #[test]
+    fn send_waits_for_acks() {
+        let gatt = Gatt::new();
+        let mut btp = Btp::new(&gatt);
+
+        handshake(&mut btp, &gatt, 3);
+
+        assert_eq!(write(&mut btp, &packet(0, Some(0), 0, None, &[])), Ok(None));
+
+        let msg = message();
+        btp.send(&msg).unwrap();
+
+        // The last slot of the window is kept for a packet which acknowledges
+        let sent = gatt.take();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(&sent[0][..5], &[0x09, 0, 1, 60, 0]);
+        assert_eq!(&sent[1][..2], &[0x02, 2]);
+        assert!(btp.is_sending());
+
+        assert_eq!(write(&mut btp, &packet(0, Some(2), 1, None, &[])), Ok(None));
+
+        let rest = gatt.take();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(&rest[0][..3], &[0x0a, 1, 3]);
+        assert_eq!(&rest[1][..2], &[0x04, 4]);
+        assert!(!btp.is_sending());
+
+        let received: heapless::Vec<u8, 256> =
+            [&sent[0][5..], &sent[1][2..], &rest[0][3..], &rest[1][2..]]
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect();
+        assert_eq!(received, msg);
+    }
+
+    #[test]
+    fn sequence_wraparound() {
+        let gatt = Gatt::new();
+        let mut btp = Btp::new(&gatt);
+
+        handshake(&mut btp, &gatt, 4);
+
+        // The handshake response is packet 0 of the peripheral
+        let mut ack = Some(0);
+        let mut seq = 0u8;
+
+        for i in 0..300 {
+            let segment = packet(0x05, ack.take(), seq, Some(1), &[i as u8]);
+            assert_eq!(
+                write(&mut btp, &segment),
+                Ok(Some(heapless::Vec::from_slice(&[i as u8]).unwrap()))
+            );
+
+            seq = seq.wrapping_add(1);
+
+            // Acknowledge the standalone acknowledgements of the peripheral
+            if let Some(sent) = gatt.take().last() {
+                assert_eq!(sent[0], 0x08);
+                assert_eq!(sent[1], seq.wrapping_sub(1));
+                ack = Some(sent[2]);
+            }
+        }
+    }
+}
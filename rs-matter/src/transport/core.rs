@@ -19,9 +19,9 @@ use core::borrow::Borrow;
 use core::mem::MaybeUninit;
 use core::pin::pin;
 
-use embassy_futures::select::{select, select_slice, Either};
+use core::time::Duration;
+use embassy_futures::select::{select, select3, select_slice, Either};
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
-use embassy_time::{Duration, Timer};
 
 use log::{error, info, warn};
 
@@ -29,7 +29,9 @@ use crate::utils::select::Notification;
 use crate::CommissioningData;
 use crate::{
     alloc,
-    data_model::{core::DataModel, objects::DataModelHandler},
+    bdx::{self, PROTO_ID_BDX},
+    crypto::SYMM_KEY_LEN_BYTES,
+    data_model::{core::DataModel, objects::DataModelHandler, subscriptions::wait_pending},
     error::{Error, ErrorCode},
     interaction_model::core::PROTO_ID_INTERACTION_MODEL,
     secure_channel::{
@@ -37,19 +39,29 @@ use crate::{
         core::SecureChannel,
     },
     transport::packet::Packet,
-    utils::select::EitherUnwrap,
+    utils::{
+        select::EitherUnwrap,
+        timer::{EmbassyTimer, Timer},
+    },
     Matter,
 };
 
 use super::{
     exchange::{
-        Exchange, ExchangeCtr, ExchangeCtx, ExchangeId, ExchangeState, Role, MAX_EXCHANGES,
+        Exchange, ExchangeCtr, ExchangeCtx, ExchangeId, ExchangeState, Role, SessionId,
+        MAX_EXCHANGES,
     },
     mrp::ReliableMessage,
     packet::{MAX_RX_BUF_SIZE, MAX_RX_STATUS_BUF_SIZE, MAX_TX_BUF_SIZE},
     pipe::{Chunk, Pipe},
+    plain_hdr,
+    session::GroupDetails,
 };
 
+/// The longest the transport waits without a deadline, so as to purge the closed exchanges
+/// and the idle sessions
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 enum OpCodeDescriptor {
     SecureChannel(OpCode),
@@ -76,9 +88,12 @@ type SxBuf = MaybeUninit<[u8; MAX_RX_STATUS_BUF_SIZE]>;
 #[cfg(any(feature = "std", feature = "embassy-net"))]
 pub struct RunBuffers {
     udp_bufs: crate::transport::udp::UdpBuffers,
+    tcp_bufs: crate::transport::tcp::TcpBuffers,
     run_bufs: PacketBuffers,
     tx_buf: TxBuf,
     rx_buf: RxBuf,
+    tcp_tx_buf: TxBuf,
+    tcp_rx_buf: RxBuf,
 }
 
 #[cfg(any(feature = "std", feature = "embassy-net"))]
@@ -87,9 +102,12 @@ impl RunBuffers {
     pub const fn new() -> Self {
         Self {
             udp_bufs: crate::transport::udp::UdpBuffers::new(),
+            tcp_bufs: crate::transport::tcp::TcpBuffers::new(),
             run_bufs: PacketBuffers::new(),
             tx_buf: core::mem::MaybeUninit::uninit(),
             rx_buf: core::mem::MaybeUninit::uninit(),
+            tcp_tx_buf: core::mem::MaybeUninit::uninit(),
+            tcp_rx_buf: core::mem::MaybeUninit::uninit(),
         }
     }
 }
@@ -98,6 +116,8 @@ pub struct PacketBuffers {
     tx: [TxBuf; MAX_EXCHANGES],
     rx: [RxBuf; MAX_EXCHANGES],
     sx: [SxBuf; MAX_EXCHANGES],
+    report_tx: TxBuf,
+    report_sx: SxBuf,
 }
 
 impl PacketBuffers {
@@ -115,6 +135,8 @@ impl PacketBuffers {
             tx: Self::TX_INIT,
             rx: Self::RX_INIT,
             sx: Self::SX_INIT,
+            report_tx: Self::TX_ELEM,
+            report_sx: Self::SX_ELEM,
         }
     }
 }
@@ -132,23 +154,29 @@ impl<'a> Matter<'a> {
         D: crate::transport::network::NetworkStackDriver,
         H: DataModelHandler,
     {
-        let udp = crate::transport::udp::UdpListener::new(
-            stack,
-            crate::transport::network::SocketAddr::new(
-                crate::transport::network::IpAddr::V6(
-                    crate::transport::network::Ipv6Addr::UNSPECIFIED,
-                ),
-                self.port,
-            ),
-            &mut buffers.udp_bufs,
-        )
-        .await?;
+        let addr = crate::transport::network::SocketAddr::new(
+            crate::transport::network::IpAddr::V6(crate::transport::network::Ipv6Addr::UNSPECIFIED),
+            self.port,
+        );
+
+        let udp =
+            crate::transport::udp::UdpListener::new(stack, addr, &mut buffers.udp_bufs).await?;
+        let mut tcp =
+            crate::transport::tcp::TcpListener::new(stack, addr, &mut buffers.tcp_bufs).await?;
 
         let tx_pipe = Pipe::new(unsafe { buffers.tx_buf.assume_init_mut() });
         let rx_pipe = Pipe::new(unsafe { buffers.rx_buf.assume_init_mut() });
+        let tcp_tx_pipe = Pipe::new(unsafe { buffers.tcp_tx_buf.assume_init_mut() });
+        let tcp_rx_pipe = Pipe::new(unsafe { buffers.tcp_rx_buf.assume_init_mut() });
+
+        // The (normalized) address of the peer currently connected over TCP, if any
+        let tcp_peer = core::cell::Cell::new(None);
 
         let tx_pipe = &tx_pipe;
         let rx_pipe = &rx_pipe;
+        let tcp_tx_pipe = &tcp_tx_pipe;
+        let tcp_rx_pipe = &tcp_rx_pipe;
+        let tcp_peer = &tcp_peer;
         let udp = &udp;
         let run_bufs = &mut buffers.run_bufs;
 
@@ -158,8 +186,17 @@ impl<'a> Matter<'a> {
                     let mut data = tx_pipe.data.lock().await;
 
                     if let Some(chunk) = data.chunk {
-                        udp.send(chunk.addr.unwrap_udp(), &data.buf[chunk.start..chunk.end])
-                            .await?;
+                        let buf = &data.buf[chunk.start..chunk.end];
+
+                        match chunk.addr {
+                            crate::transport::network::Address::Udp(addr) => {
+                                udp.send(addr, buf).await?;
+                            }
+                            crate::transport::network::Address::Tcp(_) => {
+                                Self::send_tcp(tcp_tx_pipe, tcp_peer, chunk.addr, buf).await;
+                            }
+                        }
+
                         data.chunk = None;
                         tx_pipe.data_consumed_notification.signal(());
                     }
@@ -175,14 +212,43 @@ impl<'a> Matter<'a> {
                     let mut data = rx_pipe.data.lock().await;
 
                     if data.chunk.is_none() {
-                        let (len, addr) = udp.recv(data.buf).await?;
-
-                        data.chunk = Some(Chunk {
-                            start: 0,
-                            end: len,
-                            addr: crate::transport::network::Address::Udp(addr),
-                        });
-                        rx_pipe.data_supplied_notification.signal(());
+                        let received = select(
+                            udp.recv(data.buf),
+                            tcp_rx_pipe.data_supplied_notification.wait(),
+                        )
+                        .await;
+
+                        match received {
+                            Either::First(received) => {
+                                let (len, addr) = received?;
+
+                                data.chunk = Some(Chunk {
+                                    start: 0,
+                                    end: len,
+                                    addr: crate::transport::network::Address::Udp(addr),
+                                });
+                            }
+                            Either::Second(_) => {
+                                let mut tcp_data = tcp_rx_pipe.data.lock().await;
+
+                                if let Some(chunk) = tcp_data.chunk.take() {
+                                    let len = chunk.end - chunk.start;
+
+                                    data.buf[..len]
+                                        .copy_from_slice(&tcp_data.buf[chunk.start..chunk.end]);
+                                    data.chunk = Some(Chunk {
+                                        start: 0,
+                                        end: len,
+                                        addr: chunk.addr,
+                                    });
+                                    tcp_rx_pipe.data_consumed_notification.signal(());
+                                }
+                            }
+                        }
+
+                        if data.chunk.is_some() {
+                            rx_pipe.data_supplied_notification.signal(());
+                        }
                     }
                 }
 
@@ -190,16 +256,141 @@ impl<'a> Matter<'a> {
             }
         });
 
+        let mut tcp_conn = pin!(async move {
+            loop {
+                let mut conn = match tcp.accept().await {
+                    Ok(conn) => conn,
+                    Err(_) => {
+                        // Back off, as the error might not go away by itself
+                        EmbassyTimer.after(HOUSEKEEPING_INTERVAL).await;
+                        continue;
+                    }
+                };
+
+                let peer = crate::transport::network::Address::Tcp(conn.peer_addr()).normalize();
+
+                // Whatever was left for the previous peer is not meant for this one
+                tcp_tx_pipe.data.lock().await.chunk = None;
+                tcp_peer.set(Some(peer));
+
+                let (mut reader, mut writer) = conn.split();
+
+                let (Either::First(result) | Either::Second(result)) = select(
+                    Self::recv_tcp(tcp_rx_pipe, &mut reader),
+                    Self::send_tcp_conn(tcp_tx_pipe, peer, &mut writer),
+                )
+                .await;
+
+                if let Err(err) = result {
+                    info!("TCP connection to {} dropped: {:?}", peer, err);
+                }
+
+                tcp_peer.set(None);
+
+                tcp_tx_pipe.data.lock().await.chunk = None;
+                tcp_tx_pipe.data_consumed_notification.signal(());
+            }
+        });
+
         let mut run = pin!(async move {
             self.run_piped(run_bufs, tx_pipe, rx_pipe, dev_comm, handler)
                 .await
         });
 
-        embassy_futures::select::select3(&mut tx, &mut rx, &mut run)
+        embassy_futures::select::select4(&mut tx, &mut rx, &mut tcp_conn, &mut run)
             .await
             .unwrap()
     }
 
+    /// Receive the messages of a TCP connection, until the connection fails
+    #[cfg(any(feature = "std", feature = "embassy-net"))]
+    async fn recv_tcp(
+        tcp_rx_pipe: &Pipe<'_>,
+        reader: &mut crate::transport::tcp::TcpReader<'_>,
+    ) -> Result<(), Error> {
+        loop {
+            {
+                let mut data = tcp_rx_pipe.data.lock().await;
+
+                if data.chunk.is_none() {
+                    let (len, addr) = reader.recv(data.buf).await?;
+
+                    data.chunk = Some(Chunk {
+                        start: 0,
+                        end: len,
+                        addr: crate::transport::network::Address::Tcp(addr),
+                    });
+                    tcp_rx_pipe.data_supplied_notification.signal(());
+                }
+            }
+
+            tcp_rx_pipe.data_consumed_notification.wait().await;
+        }
+    }
+
+    /// Send the messages handed over by `send_tcp` to the peer of a TCP connection,
+    /// until the connection fails
+    #[cfg(any(feature = "std", feature = "embassy-net"))]
+    async fn send_tcp_conn(
+        tcp_tx_pipe: &Pipe<'_>,
+        peer: crate::transport::network::Address,
+        writer: &mut crate::transport::tcp::TcpWriter<'_>,
+    ) -> Result<(), Error> {
+        loop {
+            {
+                let mut data = tcp_tx_pipe.data.lock().await;
+
+                if let Some(chunk) = data.chunk {
+                    if chunk.addr == peer {
+                        writer.send(&data.buf[chunk.start..chunk.end]).await?;
+                    }
+
+                    data.chunk = None;
+                    tcp_tx_pipe.data_consumed_notification.signal(());
+                }
+            }
+
+            tcp_tx_pipe.data_supplied_notification.wait().await;
+        }
+    }
+
+    /// Hand a message over to the TCP connection of its peer, dropping it if the
+    /// peer is no longer connected, as TCP messages cannot be routed anywhere else
+    #[cfg(any(feature = "std", feature = "embassy-net"))]
+    async fn send_tcp(
+        tcp_tx_pipe: &Pipe<'_>,
+        tcp_peer: &core::cell::Cell<Option<crate::transport::network::Address>>,
+        addr: crate::transport::network::Address,
+        buf: &[u8],
+    ) {
+        loop {
+            if tcp_peer.get() != Some(addr) {
+                warn!(
+                    "Dropping a message to {}, which is not connected over TCP",
+                    addr
+                );
+                break;
+            }
+
+            {
+                let mut data = tcp_tx_pipe.data.lock().await;
+
+                if data.chunk.is_none() {
+                    data.buf[..buf.len()].copy_from_slice(buf);
+                    data.chunk = Some(Chunk {
+                        start: 0,
+                        end: buf.len(),
+                        addr,
+                    });
+                    tcp_tx_pipe.data_supplied_notification.signal(());
+                    break;
+                }
+            }
+
+            tcp_tx_pipe.data_consumed_notification.wait().await;
+        }
+    }
+
     pub async fn run_piped<H>(
         &self,
         buffers: &mut PacketBuffers,
@@ -221,10 +412,110 @@ impl<'a> Matter<'a> {
 
         let construction_notification = Notification::new();
 
+        // Unsafely allow mutable aliasing of the report buffers, which the exchange handlers never use
+        let pools: *mut PacketBuffers = buffers;
+        let report_pools = unsafe { pools.as_mut() }.unwrap();
+
+        let report_tx = unsafe { report_pools.report_tx.assume_init_mut() };
+        let report_sx = unsafe { report_pools.report_sx.assume_init_mut() };
+
+        let mut reports = pin!(self.handle_reports(report_tx, report_sx, handler));
         let mut rx = pin!(self.handle_rx(buffers, rx_pipe, &construction_notification, handler));
         let mut tx = pin!(self.handle_tx(tx_pipe));
 
-        select(&mut rx, &mut tx).await.unwrap()
+        select3(&mut rx, &mut tx, &mut reports).await.unwrap()
+    }
+
+    /// Push the reports of the subscriptions, whenever a subscribed attribute changed
+    /// (but no sooner than the min interval) or the max interval elapsed
+    #[inline(always)]
+    pub async fn handle_reports<H>(
+        &self,
+        tx_buf: &mut [u8; MAX_TX_BUF_SIZE],
+        sx_buf: &mut [u8; MAX_RX_STATUS_BUF_SIZE],
+        handler: &H,
+    ) -> Result<(), Error>
+    where
+        H: DataModelHandler,
+    {
+        self.handle_reports_with(tx_buf, sx_buf, handler, &EmbassyTimer)
+            .await
+    }
+
+    /// Same as `handle_reports`, but with the intervals of the subscriptions awaited
+    /// with the provided timer
+    pub async fn handle_reports_with<H, T>(
+        &self,
+        tx_buf: &mut [u8; MAX_TX_BUF_SIZE],
+        sx_buf: &mut [u8; MAX_RX_STATUS_BUF_SIZE],
+        handler: &H,
+        timer: &T,
+    ) -> Result<(), Error>
+    where
+        H: DataModelHandler,
+        T: Timer,
+    {
+        loop {
+            self.evict_removed_sessions();
+
+            let pending =
+                wait_pending(&self.subscription_mgr, &self.report_notification, timer).await;
+
+            for id in pending {
+                if let Err(err) = self.report(tx_buf, sx_buf, id, handler).await {
+                    warn!("Subscription {}: report failed: {:?}", id, err);
+
+                    self.subscription_mgr.borrow_mut().remove(id);
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    async fn report<H>(
+        &self,
+        tx_buf: &mut [u8; MAX_TX_BUF_SIZE],
+        sx_buf: &mut [u8; MAX_RX_STATUS_BUF_SIZE],
+        id: u32,
+        handler: &H,
+    ) -> Result<(), Error>
+    where
+        H: DataModelHandler,
+    {
+        let Some(subscription) = self.subscription_mgr.borrow_mut().start_report(id) else {
+            return Ok(());
+        };
+
+        let session_id = self
+            .session_mgr
+            .borrow_mut()
+            .get_with_id(subscription.sess_id)
+            .map(|sess| sess.get_session_id())
+            .ok_or(ErrorCode::NoSession)?;
+
+        let mut tx = alloc!(Packet::new_tx(tx_buf.as_mut()));
+        tx.set_max_len(session_id.peer_addr.max_payload_len())?;
+
+        let mut rx_status = alloc!(Packet::new_rx(sx_buf.as_mut()));
+
+        let mut exchange = alloc!(self.new_exchange(&session_id)?);
+
+        info!("Subscription {}: sending report", id);
+
+        let dm = DataModel::new(handler, &self.subscription_mgr);
+
+        if dm
+            .report(&mut exchange, &subscription, &mut tx, &mut rx_status)
+            .await?
+        {
+            self.subscription_mgr.borrow_mut().report_sent(id);
+        } else {
+            info!("Subscription {}: report rejected by the peer", id);
+
+            self.subscription_mgr.borrow_mut().remove(id);
+        }
+
+        Ok(())
     }
 
     #[inline(always)]
@@ -329,7 +620,7 @@ impl<'a> Matter<'a> {
 
                 if let Some(chunk) = data.chunk {
                     let mut rx = alloc!(Packet::new_rx(&mut data.buf[chunk.start..chunk.end]));
-                    rx.peer = chunk.addr;
+                    rx.peer = chunk.addr.normalize();
 
                     if let Some(exchange_ctr) =
                         self.process_rx(construction_notification, &mut rx)?
@@ -414,6 +705,16 @@ impl<'a> Matter<'a> {
 
         let mut exchange = alloc!(exchange_ctr.get(&mut rx).await?);
 
+        tx.set_max_len(exchange.id().session_id.peer_addr.max_payload_len())?;
+
+        if exchange.id().session_id.is_group && rx.get_proto_id() != PROTO_ID_INTERACTION_MODEL {
+            warn!(
+                "Dropping group message with Proto-ID: {}",
+                rx.get_proto_id()
+            );
+            return Ok(());
+        }
+
         match rx.get_proto_id() {
             PROTO_ID_SECURE_CHANNEL => {
                 let sc = SecureChannel::new(self);
@@ -423,7 +724,8 @@ impl<'a> Matter<'a> {
                 self.notify_changed();
             }
             PROTO_ID_INTERACTION_MODEL => {
-                let dm = DataModel::new(handler);
+                let dm = DataModel::new(handler, &self.subscription_mgr)
+                    .with_cmd_timeout(self.cmd_timeout());
 
                 let mut rx_status = alloc!(Packet::new_rx(sx_buf));
 
@@ -432,6 +734,9 @@ impl<'a> Matter<'a> {
 
                 self.notify_changed();
             }
+            PROTO_ID_BDX => {
+                bdx::respond(&mut exchange, &mut tx, &mut rx, self.bdx_files()).await?;
+            }
             other => {
                 error!("Unknown Proto-ID: {}", other);
             }
@@ -443,6 +748,7 @@ impl<'a> Matter<'a> {
     pub fn reset_transport(&self) {
         self.exchanges.borrow_mut().clear();
         self.session_mgr.borrow_mut().reset();
+        self.subscription_mgr.borrow_mut().reset();
     }
 
     pub fn process_rx<'r>(
@@ -460,6 +766,11 @@ impl<'a> Matter<'a> {
                     self.send_notification.signal(());
                     return Ok(None);
                 }
+                _ if src_rx.plain.is_group() => {
+                    // Nobody is to be told about a group message we cannot process
+                    warn!("Dropping group message: {:?}", e);
+                    return Ok(None);
+                }
                 _ => Err(e)?,
             },
         };
@@ -482,6 +793,9 @@ impl<'a> Matter<'a> {
                         unsafe { notification.as_ref() }.unwrap().signal(());
                         ctx.state = ExchangeState::Closed;
                     }
+                    ExchangeState::Failed => {
+                        // Too late, we already gave up on the exchange
+                    }
                     _ => {
                         // TODO: Error handling
                         todo!()
@@ -535,6 +849,52 @@ impl<'a> Matter<'a> {
         }
     }
 
+    /// Open a new exchange on the session, which we are the initiator of
+    ///
+    /// This is for the messages which we send unsolicited, rather than in response to the peer,
+    /// like the reports of subscriptions.
+    pub fn new_exchange<'r>(&'r self, session_id: &SessionId) -> Result<Exchange<'r>, Error> {
+        if session_id.is_group {
+            // Group messages are never acknowledged, and never get a response
+            Err(ErrorCode::Invalid)?;
+        }
+
+        let exch_id = {
+            let mut session_mgr = self.session_mgr.borrow_mut();
+            let sess_index = session_mgr
+                .get(
+                    session_id.id,
+                    session_id.peer_addr,
+                    session_id.peer_nodeid,
+                    session_id.is_encrypted,
+                    session_id.is_group,
+                )
+                .ok_or(ErrorCode::NoSession)?;
+
+            session_mgr
+                .mut_by_index(sess_index)
+                .unwrap()
+                .get_next_exch_id()
+        };
+
+        let id = ExchangeId {
+            id: exch_id,
+            session_id: session_id.clone(),
+        };
+
+        let mut exchanges = self.exchanges.borrow_mut();
+        let (_, new) = Self::register(&mut exchanges, id.clone(), Role::Initiator, true)?;
+        if !new {
+            Err(ErrorCode::NoExchange)?;
+        }
+
+        Ok(Exchange {
+            id,
+            matter: self,
+            notification: Notification::new(),
+        })
+    }
+
     pub async fn wait_construction(
         &self,
         construction_notification: &Notification,
@@ -564,17 +924,33 @@ impl<'a> Matter<'a> {
     }
 
     pub async fn wait_tx(&self) -> Result<(), Error> {
-        select(
-            self.send_notification.wait(),
-            Timer::after(Duration::from_millis(100)),
-        )
-        .await;
+        self.wait_tx_with(&EmbassyTimer).await
+    }
+
+    /// Wait until there is something to send, or until the next MRP, fail-safe or
+    /// commissioning window deadline, as awaited with the provided timer
+    pub async fn wait_tx_with<T>(&self, timer: &T) -> Result<(), Error>
+    where
+        T: Timer,
+    {
+        let now = (self.epoch)();
+        let wait = self
+            .next_deadline()
+            .map(|deadline| deadline.saturating_sub(now))
+            .unwrap_or(HOUSEKEEPING_INTERVAL)
+            .min(HOUSEKEEPING_INTERVAL);
+
+        select(self.send_notification.wait(), timer.after(wait)).await;
 
         Ok(())
     }
 
     pub async fn pull_tx(&self, dest_tx: &mut Packet<'_>) -> Result<bool, Error> {
+        // Before the purge, so that the subscriptions of the idle sessions go with them
+        self.expire_idle_sessions();
         self.purge()?;
+        self.expire_failsafe()?;
+        self.expire_commissioning_window()?;
 
         let mut exchanges = self.exchanges.borrow_mut();
 
@@ -583,12 +959,15 @@ impl<'a> Matter<'a> {
                 &ctx.state,
                 ExchangeState::Acknowledge { .. }
                     | ExchangeState::ExchangeSend { .. }
-                    // | ExchangeState::ExchangeRecv {
-                    //     tx_acknowledged: false,
-                    //     ..
-                    // }
-                    | ExchangeState::Complete { .. } // | ExchangeState::CompleteAcknowledge { .. }
-            ) || ctx.mrp.is_ack_ready(*self.borrow())
+                    | ExchangeState::Complete { .. }
+            ) || matches!(
+                &ctx.state,
+                ExchangeState::ExchangeRecv {
+                    tx_acknowledged: false,
+                    ..
+                } | ExchangeState::CompleteAcknowledge { .. }
+            ) && ctx.mrp.is_retrans_due(self.epoch)
+                || ctx.mrp.is_ack_ready(*self.borrow())
         });
 
         if let Some(ctx) = ctx {
@@ -596,6 +975,9 @@ impl<'a> Matter<'a> {
 
             let state = &mut ctx.state;
 
+            // The msg counter of the message, in case it is a retransmission
+            let mut retrans_ctr = None;
+
             let send = match state {
                 ExchangeState::Acknowledge { notification } => {
                     ReliableMessage::prepare_ack(ctx.id.id, dest_tx);
@@ -614,7 +996,7 @@ impl<'a> Matter<'a> {
                     dest_tx.load(tx)?;
 
                     *state = ExchangeState::ExchangeRecv {
-                        _tx: tx,
+                        tx,
                         tx_acknowledged: false,
                         rx: *rx,
                         notification: *notification,
@@ -622,25 +1004,47 @@ impl<'a> Matter<'a> {
 
                     true
                 }
-                // ExchangeState::ExchangeRecv { .. } => {
-                //     // TODO: Re-send the tx package if due
-                //     false
-                // }
+                ExchangeState::ExchangeRecv {
+                    tx,
+                    tx_acknowledged: false,
+                    notification,
+                    ..
+                }
+                | ExchangeState::CompleteAcknowledge { tx, notification }
+                    if ctx.mrp.is_retrans_due(self.epoch) =>
+                {
+                    match ctx.mrp.retry(self.epoch, self.rand) {
+                        Ok(msg_ctr) => {
+                            let tx = unsafe { tx.as_ref() }.unwrap();
+                            dest_tx.load(tx)?;
+
+                            retrans_ctr = Some(msg_ctr);
+
+                            true
+                        }
+                        Err(err) => {
+                            warn!("Exchange {:?}: giving up on sending: {:?}", ctx.id, err);
+
+                            unsafe { notification.as_ref() }.unwrap().signal(());
+                            *state = ExchangeState::Failed;
+
+                            self.send_notification.signal(());
+
+                            false
+                        }
+                    }
+                }
                 ExchangeState::Complete { tx, notification } => {
                     let tx = unsafe { tx.as_ref() }.unwrap();
                     dest_tx.load(tx)?;
 
                     *state = ExchangeState::CompleteAcknowledge {
-                        _tx: tx as *const _,
+                        tx: tx as *const _,
                         notification: *notification,
                     };
 
                     true
                 }
-                // ExchangeState::CompleteAcknowledge { .. } => {
-                //     // TODO: Re-send the tx package if due
-                //     false
-                // }
                 _ => {
                     ReliableMessage::prepare_ack(ctx.id.id, dest_tx);
                     true
@@ -650,7 +1054,7 @@ impl<'a> Matter<'a> {
             if send {
                 dest_tx.log("Sending packet");
 
-                self.pre_send(ctx, dest_tx)?;
+                self.pre_send(ctx, dest_tx, retrans_ctr)?;
                 self.notify_changed();
 
                 return Ok(true);
@@ -673,9 +1077,21 @@ impl<'a> Matter<'a> {
             }
         }
 
+        self.evict_removed_sessions();
+
         Ok(())
     }
 
+    /// Drop the subscriptions of the sessions removed since the last call
+    pub(crate) fn evict_removed_sessions(&self) {
+        let removed = self.session_mgr.borrow_mut().take_removed();
+
+        let mut subscription_mgr = self.subscription_mgr.borrow_mut();
+        for sess_id in removed {
+            subscription_mgr.evict_session(sess_id);
+        }
+    }
+
     fn post_recv<'r>(
         &self,
         exchanges: &'r mut heapless::Vec<ExchangeCtx, MAX_EXCHANGES>,
@@ -683,11 +1099,42 @@ impl<'a> Matter<'a> {
     ) -> Result<(&'r mut ExchangeCtx, bool), Error> {
         rx.plain_hdr_decode()?;
 
+        let mut session_mgr = self.session_mgr.borrow_mut();
+
+        if rx.plain.is_private() {
+            // Deobfuscate the rest of the header
+            let privacy_key = if rx.plain.is_group() {
+                let op_key = self
+                    .group_mgr
+                    .borrow()
+                    .find_op_key_by_session(rx.plain.sess_id)
+                    .ok_or(ErrorCode::NoSession)?;
+
+                let mut privacy_key = [0; SYMM_KEY_LEN_BYTES];
+                plain_hdr::privacy_key(&op_key, &mut privacy_key)?;
+
+                privacy_key
+            } else {
+                session_mgr.get_dec_privacy_key(rx.plain.sess_id)?
+            };
+
+            rx.plain_hdr_decode_private(&privacy_key)?;
+        }
+
         // Get the session
 
-        let mut session_mgr = self.session_mgr.borrow_mut();
+        let sess_index = if rx.plain.is_group() {
+            let group_id = rx.plain.get_dest_group_id().ok_or(ErrorCode::Invalid)?;
+            let (fab_idx, op_key) = self
+                .group_mgr
+                .borrow()
+                .find_op_key(rx.plain.sess_id, group_id)
+                .ok_or(ErrorCode::NoSession)?;
 
-        let sess_index = session_mgr.post_recv(rx)?;
+            session_mgr.post_recv_group(rx, GroupDetails::new(fab_idx, group_id), &op_key)?
+        } else {
+            session_mgr.post_recv(rx)?
+        };
         let session = session_mgr.mut_by_index(sess_index).unwrap();
 
         // Decrypt the message
@@ -703,13 +1150,21 @@ impl<'a> Matter<'a> {
             rx.proto.is_initiator(),
         )?;
 
-        // Message Reliability Protocol
-        exch.mrp.recv(rx, self.epoch)?;
+        // Message Reliability Protocol, unless the transport is reliable by itself
+        // Group messages are never acknowledged
+        if !rx.peer.is_reliable() && !rx.plain.is_group() {
+            exch.mrp.recv(rx, self.epoch)?;
+        }
 
         Ok((exch, new))
     }
 
-    fn pre_send(&self, ctx: &mut ExchangeCtx, tx: &mut Packet) -> Result<(), Error> {
+    fn pre_send(
+        &self,
+        ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+        retrans_ctr: Option<u32>,
+    ) -> Result<(), Error> {
         let mut session_mgr = self.session_mgr.borrow_mut();
         let sess_index = session_mgr
             .get(
@@ -717,6 +1172,7 @@ impl<'a> Matter<'a> {
                 ctx.id.session_id.peer_addr,
                 ctx.id.session_id.peer_nodeid,
                 ctx.id.session_id.is_encrypted,
+                ctx.id.session_id.is_group,
             )
             .ok_or(ErrorCode::NoSession)?;
 
@@ -728,7 +1184,20 @@ impl<'a> Matter<'a> {
         }
 
         session.pre_send(tx)?;
-        ctx.mrp.pre_send(tx)?;
+        if ctx.id.session_id.peer_addr.is_reliable() {
+            // No acknowledgements and retransmissions over TCP
+            tx.unset_reliable();
+        }
+
+        if let Some(msg_ctr) = retrans_ctr {
+            // Retransmissions have to carry the msg counter of the original message
+            tx.plain.ctr = msg_ctr;
+        }
+
+        let retrans_interval = session.get_retrans_interval(self.epoch);
+
+        ctx.mrp
+            .pre_send(tx, retrans_interval, self.epoch, self.rand)?;
         session_mgr.send(sess_index, tx)
     }
 
@@ -0,0 +1,229 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::{
+    error::{Error, ErrorCode},
+    tlv::{self, TLVWriter, TagType},
+    utils::{rand::Rand, writebuf::WriteBuf},
+};
+
+use super::session::MATTER_MSG_CTR_RANGE;
+
+/// How many counter values are reserved with every write to the storage
+pub const COUNTER_BATCH: u32 = 1000;
+
+/// A new batch is reserved once fewer values than this are left in the reserved one,
+/// so that the batch is stored before the counter runs out of reserved values
+pub const COUNTER_LOW_WATER: u32 = COUNTER_BATCH / 2;
+
+/// A message counter, which never reuses a value, not even across reboots
+///
+/// Rather than persisting every value, a ceiling is reserved in the storage ahead of
+/// time, in batches of [`COUNTER_BATCH`] values. After a reboot, the counter resumes at
+/// the last reserved ceiling, which is above any value used before the reboot.
+///
+/// Only values below the ceiling which was stored last are handed out, so the counter
+/// has to be stored whenever it reports being changed, i.e. when it is running
+/// low on reserved values.
+///
+/// The counter wraps around at `u32::MAX`, which the receivers of the messages handle
+/// as all counters are compared modulo 2^32.
+pub struct CounterMgr {
+    value: u32,
+    ceiling: u32,
+}
+
+impl CounterMgr {
+    /// A new counter, starting at a random value, and with no values reserved yet
+    pub fn new(rand: Rand) -> Self {
+        let mut buf = [0; 4];
+        rand(&mut buf);
+
+        let value = u32::from_be_bytes(buf) & MATTER_MSG_CTR_RANGE;
+
+        Self {
+            value,
+            ceiling: value,
+        }
+    }
+
+    /// Resume the counter at the ceiling reserved before the reboot
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.value = tlv::get_root_node(data)?.u32()?;
+
+        // Nothing is reserved yet, so the next store reserves a batch
+        self.ceiling = self.value;
+
+        Ok(())
+    }
+
+    /// Reserve the next batch, if the counter is running low on reserved values
+    pub fn store<'a>(&mut self, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        if self.is_changed() {
+            let ceiling = self.value.wrapping_add(COUNTER_BATCH);
+
+            let mut wb = WriteBuf::new(buf);
+            let mut tw = TLVWriter::new(&mut wb);
+            tw.u32(TagType::Anonymous, ceiling)?;
+
+            self.ceiling = ceiling;
+
+            let len = tw.get_tail();
+
+            Ok(Some(&buf[..len]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.ceiling.wrapping_sub(self.value) < COUNTER_LOW_WATER
+    }
+
+    /// The next value of the counter
+    ///
+    /// Fails with [`ErrorCode::Busy`] once all reserved values are used up, until the
+    /// counter is stored again.
+    pub fn next(&mut self) -> Result<u32, Error> {
+        if self.value == self.ceiling {
+            Err(ErrorCode::Busy)?;
+        }
+
+        let value = self.value;
+        self.value = self.value.wrapping_add(1);
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ErrorCode,
+        persist::{self, KvStore, RamStore, KEY_GROUP_DATA_CTR},
+        utils::rand::dummy_rand,
+    };
+
+    use super::{CounterMgr, COUNTER_BATCH};
+
+    /// What the persister does on boot: resume the counter, and reserve a batch right away
+    fn boot(store: &mut RamStore) -> CounterMgr {
+        let mut counter = CounterMgr::new(dummy_rand);
+        let mut buf = [0; 8];
+
+        if let Some(data) = persist::load(store, KEY_GROUP_DATA_CTR, &mut buf).unwrap() {
+            counter.load(data).unwrap();
+        }
+
+        persist::store(store, KEY_GROUP_DATA_CTR, &mut buf, |buf| {
+            counter.store(buf)
+        })
+        .unwrap();
+        assert!(!counter.is_changed());
+
+        counter
+    }
+
+    /// What the persister does whenever the counter has changed
+    fn persist(store: &mut RamStore, counter: &mut CounterMgr) -> bool {
+        let changed = counter.is_changed();
+
+        let mut buf = [0; 8];
+        persist::store(store, KEY_GROUP_DATA_CTR, &mut buf, |buf| {
+            counter.store(buf)
+        })
+        .unwrap();
+
+        changed
+    }
+
+    #[test]
+    /// The counter resumes strictly above the values used before the reboot
+    fn resume_after_reboot() {
+        let mut store = RamStore::new();
+
+        let mut counter = boot(&mut store);
+        let first = counter.next().unwrap();
+
+        let mut last = first;
+        let mut writes = 0;
+        for _ in 0..COUNTER_BATCH + 10 {
+            let value = counter.next().unwrap();
+            assert_eq!(value, last + 1);
+            last = value;
+
+            if persist(&mut store, &mut counter) {
+                writes += 1;
+            }
+        }
+
+        // Only one write per batch
+        assert_eq!(writes, 2);
+
+        // Reboot
+        let mut counter = boot(&mut store);
+        let resumed = counter.next().unwrap();
+        assert!(resumed > last);
+        assert!(resumed <= last + COUNTER_BATCH);
+
+        // Rebooting before the reserved batch is used up skips the rest of it
+        let mut counter = boot(&mut store);
+        assert_eq!(counter.next().unwrap(), resumed + COUNTER_BATCH);
+    }
+
+    #[test]
+    /// No value is handed out which is not below the ceiling stored last
+    fn unreserved_values() {
+        let mut counter = CounterMgr::new(dummy_rand);
+        assert!(counter.is_changed());
+        assert_eq!(counter.next().map_err(|e| e.code()), Err(ErrorCode::Busy));
+
+        let mut buf = [0; 8];
+        counter.store(&mut buf).unwrap().unwrap();
+
+        for _ in 0..COUNTER_BATCH {
+            counter.next().unwrap();
+        }
+
+        assert!(counter.is_changed());
+        assert_eq!(counter.next().map_err(|e| e.code()), Err(ErrorCode::Busy));
+
+        counter.store(&mut buf).unwrap().unwrap();
+        assert_eq!(counter.next().unwrap(), COUNTER_BATCH);
+    }
+
+    #[test]
+    /// The counter, and its reserved ceiling, wrap around
+    fn rollover() {
+        let mut store = RamStore::new();
+        let mut buf = [0; 8];
+        persist::store(&mut store, KEY_GROUP_DATA_CTR, &mut buf, |buf| {
+            // An anonymous u32 of u32::MAX - 1
+            buf[..5].copy_from_slice(&[0x06, 0xFE, 0xFF, 0xFF, 0xFF]);
+            Ok(Some(&buf[..5]))
+        })
+        .unwrap();
+
+        let mut counter = boot(&mut store);
+        assert_eq!(counter.next().unwrap(), u32::MAX - 1);
+        assert_eq!(counter.next().unwrap(), u32::MAX);
+        assert_eq!(counter.next().unwrap(), 0);
+
+        let mut counter = boot(&mut store);
+        assert_eq!(counter.next().unwrap(), COUNTER_BATCH - 2);
+    }
+}
@@ -15,22 +15,42 @@
  *    limitations under the License.
  */
 
-const MSG_RX_STATE_BITMAP_LEN: u32 = 16;
+/// The size of the message counter window of the spec, for unicast sessions
+pub const MSG_COUNTER_WINDOW_SIZE: u32 = 32;
+
+/// Group messages are more prone to reordering, as they are multicast, so their window
+/// is larger. It is still tracked per source node, as every node has its own counter.
+pub const GROUP_MSG_COUNTER_WINDOW_SIZE: u32 = 64;
 
 #[derive(Debug)]
 pub struct RxCtrState {
     max_ctr: u32,
-    ctr_bitmap: u16,
+    ctr_bitmap: u64,
+    window: u32,
 }
 
 impl RxCtrState {
     pub fn new(max_ctr: u32) -> Self {
+        Self::new_with_window(max_ctr, MSG_COUNTER_WINDOW_SIZE)
+    }
+
+    pub fn new_group(max_ctr: u32) -> Self {
+        Self::new_with_window(max_ctr, GROUP_MSG_COUNTER_WINDOW_SIZE)
+    }
+
+    fn new_with_window(max_ctr: u32, window: u32) -> Self {
         Self {
             max_ctr,
-            ctr_bitmap: 0xffff,
+            ctr_bitmap: Self::full(window),
+            window,
         }
     }
 
+    /// The bitmap with all counters of the window marked as received
+    fn full(window: u32) -> u64 {
+        u64::MAX >> (u64::BITS - window)
+    }
+
     fn contains(&self, bit_number: u32) -> bool {
         (self.ctr_bitmap & (1 << bit_number)) != 0
     }
@@ -41,14 +61,17 @@ impl RxCtrState {
 
     /// Receive a message and update Rx State accordingly
     /// Returns a bool indicating whether the message is a duplicate
+    ///
+    /// For encrypted messages, counters behind the window are reported as duplicates
+    /// too, as there is no telling whether they were received already.
     pub fn recv(&mut self, msg_ctr: u32, is_encrypted: bool) -> bool {
-        let idiff = (msg_ctr as i32) - (self.max_ctr as i32);
+        let idiff = (msg_ctr as i32).wrapping_sub(self.max_ctr as i32);
         let udiff = idiff.unsigned_abs();
 
         if msg_ctr == self.max_ctr {
             // Duplicate
             true
-        } else if (-(MSG_RX_STATE_BITMAP_LEN as i32)..0).contains(&idiff) {
+        } else if (-(self.window as i32)..0).contains(&idiff) {
             // In Rx Bitmap
             let index = udiff - 1;
             if self.contains(index) {
@@ -63,19 +86,19 @@ impl RxCtrState {
         // in either direction. Encrypted only allows in forward direction
         else if msg_ctr > self.max_ctr {
             self.max_ctr = msg_ctr;
-            if udiff < MSG_RX_STATE_BITMAP_LEN {
+            if udiff < self.window {
                 // The previous max_ctr is now the actual counter
-                self.ctr_bitmap <<= udiff;
+                self.ctr_bitmap = (self.ctr_bitmap << udiff) & Self::full(self.window);
                 self.insert(udiff - 1);
             } else {
-                self.ctr_bitmap = 0xffff;
+                self.ctr_bitmap = Self::full(self.window);
             }
             false
         } else if !is_encrypted {
             // This is the case where the peer possibly rebooted and chose a different
             // random counter
             self.max_ctr = msg_ctr;
-            self.ctr_bitmap = 0xffff;
+            self.ctr_bitmap = Self::full(self.window);
             false
         } else {
             true
@@ -88,7 +111,7 @@ mod tests {
 
     use log::info;
 
-    use super::RxCtrState;
+    use super::{RxCtrState, GROUP_MSG_COUNTER_WINDOW_SIZE, MSG_COUNTER_WINDOW_SIZE};
 
     const ENCRYPTED: bool = true;
     const NOT_ENCRYPTED: bool = false;
@@ -109,13 +132,13 @@ mod tests {
         assert_ndup(s.recv(104, ENCRYPTED));
         assert_ndup(s.recv(106, ENCRYPTED));
         assert_eq!(s.max_ctr, 106);
-        assert_eq!(s.ctr_bitmap, 0b1111_1111_1111_0110);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_FFF6);
 
         assert_ndup(s.recv(118, NOT_ENCRYPTED));
-        assert_eq!(s.ctr_bitmap, 0b0110_1000_0000_0000);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_6800);
         assert_ndup(s.recv(119, NOT_ENCRYPTED));
         assert_ndup(s.recv(121, NOT_ENCRYPTED));
-        assert_eq!(s.ctr_bitmap, 0b0100_0000_0000_0110);
+        assert_eq!(s.ctr_bitmap, 0xFFFB_4006);
     }
 
     #[test]
@@ -127,7 +150,7 @@ mod tests {
         assert_dup(s.recv(103, NOT_ENCRYPTED));
 
         assert_eq!(s.max_ctr, 103);
-        assert_eq!(s.ctr_bitmap, 0b1111_1111_1111_1110);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_FFFE);
     }
 
     #[test]
@@ -141,9 +164,9 @@ mod tests {
         assert_ndup(s.recv(116, ENCRYPTED));
         assert_ndup(s.recv(117, ENCRYPTED));
         assert_eq!(s.max_ctr, 117);
-        assert_eq!(s.ctr_bitmap, 0b1010_1010_1010_1011);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_AAAB);
 
-        // duplicate on the left corner
+        // duplicate far behind the max counter
         assert_dup(s.recv(101, ENCRYPTED));
         assert_dup(s.recv(101, NOT_ENCRYPTED));
 
@@ -154,7 +177,7 @@ mod tests {
         // valid insert
         assert_ndup(s.recv(102, ENCRYPTED));
         assert_dup(s.recv(102, ENCRYPTED));
-        assert_eq!(s.ctr_bitmap, 0b1110_1010_1010_1011);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_EAAB);
     }
 
     #[test]
@@ -166,15 +189,69 @@ mod tests {
             assert_ndup(s.recv(ctr, ENCRYPTED));
         }
         assert_eq!(s.max_ctr, 118);
-        assert_eq!(s.ctr_bitmap, 0b0010_1010_1010_1010);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_2AAA);
 
-        // valid insert on the left corner
+        // valid insert far behind the max counter
         assert_ndup(s.recv(102, ENCRYPTED));
-        assert_eq!(s.ctr_bitmap, 0b1010_1010_1010_1010);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_AAAA);
 
         // valid insert on the right corner
         assert_ndup(s.recv(117, ENCRYPTED));
-        assert_eq!(s.ctr_bitmap, 0b1010_1010_1010_1011);
+        assert_eq!(s.ctr_bitmap, 0xFFFF_AAAB);
+
+        // valid insert on the left corner
+        assert_ndup(s.recv(147, ENCRYPTED));
+        assert_ndup(s.recv(147 - MSG_COUNTER_WINDOW_SIZE, ENCRYPTED));
+        assert_dup(s.recv(147 - MSG_COUNTER_WINDOW_SIZE, ENCRYPTED));
+    }
+
+    #[test]
+    fn in_order_delivery() {
+        let mut s = RxCtrState::new(1000);
+
+        for ctr in 1001..1100 {
+            assert_ndup(s.recv(ctr, ENCRYPTED));
+        }
+        assert_eq!(s.max_ctr, 1099);
+
+        // Every one of them is a replay now
+        for ctr in 1099 - MSG_COUNTER_WINDOW_SIZE..1100 {
+            assert_dup(s.recv(ctr, ENCRYPTED));
+        }
+    }
+
+    #[test]
+    fn out_of_window() {
+        let mut s = RxCtrState::new(100);
+
+        assert_ndup(s.recv(120, ENCRYPTED));
+        assert_ndup(s.recv(140, ENCRYPTED));
+
+        // Too old to tell whether it was received, even though it wasn't
+        assert_dup(s.recv(140 - MSG_COUNTER_WINDOW_SIZE - 1, ENCRYPTED));
+        assert_ndup(s.recv(140 - MSG_COUNTER_WINDOW_SIZE, ENCRYPTED));
+
+        // A plain text peer is assumed to have rebooted instead
+        assert_ndup(s.recv(50, NOT_ENCRYPTED));
+        assert_eq!(s.max_ctr, 50);
+    }
+
+    #[test]
+    fn group_window() {
+        let mut s = RxCtrState::new_group(100);
+
+        assert_ndup(s.recv(150, ENCRYPTED));
+        assert_ndup(s.recv(200, ENCRYPTED));
+        assert_ndup(s.recv(200 - GROUP_MSG_COUNTER_WINDOW_SIZE, ENCRYPTED));
+        assert_dup(s.recv(200 - GROUP_MSG_COUNTER_WINDOW_SIZE, ENCRYPTED));
+        assert_dup(s.recv(200 - GROUP_MSG_COUNTER_WINDOW_SIZE - 1, ENCRYPTED));
+
+        // Which is beyond the window of unicast sessions
+        let mut s = RxCtrState::new(100);
+
+        assert_ndup(s.recv(150, ENCRYPTED));
+        assert_ndup(s.recv(200, ENCRYPTED));
+        assert_dup(s.recv(200 - GROUP_MSG_COUNTER_WINDOW_SIZE, ENCRYPTED));
     }
 
     #[test]
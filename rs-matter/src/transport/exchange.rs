@@ -64,7 +64,7 @@ pub(crate) enum ExchangeState {
         notification: *const Notification,
     },
     ExchangeRecv {
-        _tx: *const Packet<'static>,
+        tx: *const Packet<'static>,
         tx_acknowledged: bool,
         rx: *mut Packet<'static>,
         notification: *const Notification,
@@ -74,9 +74,11 @@ pub(crate) enum ExchangeState {
         notification: *const Notification,
     },
     CompleteAcknowledge {
-        _tx: *const Packet<'static>,
+        tx: *const Packet<'static>,
         notification: *const Notification,
     },
+    // The peer did not acknowledge the sent message, even after retransmitting it
+    Failed,
     Closed,
 }
 
@@ -135,6 +137,7 @@ pub struct SessionId {
     pub peer_addr: Address,
     pub peer_nodeid: Option<u64>,
     pub is_encrypted: bool,
+    pub is_group: bool,
 }
 
 impl SessionId {
@@ -144,6 +147,7 @@ impl SessionId {
             peer_addr: rx.peer,
             peer_nodeid: rx.plain.get_src_u64(),
             is_encrypted: rx.plain.is_encrypted(),
+            is_group: rx.plain.is_group(),
         }
     }
 }
@@ -175,6 +179,7 @@ impl<'a> Exchange<'a> {
                     ctx.id.session_id.peer_addr,
                     ctx.id.session_id.peer_nodeid,
                     ctx.id.session_id.is_encrypted,
+                    ctx.id.session_id.is_group,
                 )
                 .ok_or(ErrorCode::NoSession)?;
 
@@ -189,13 +194,20 @@ impl<'a> Exchange<'a> {
         self.with_session_mut(|sess| f(sess))
     }
 
+    /// Wake up the reporting of the subscriptions, because some of them changed
+    pub(crate) fn notify_subscriptions(&self) {
+        self.matter.report_notification.signal(());
+    }
+
     pub fn with_session_mgr_mut<F, T>(&self, f: F) -> Result<T, Error>
     where
         F: FnOnce(&mut SessionMgr) -> Result<T, Error>,
     {
-        let mut session_mgr = self.matter.session_mgr.borrow_mut();
+        let result = f(&mut self.matter.session_mgr.borrow_mut());
 
-        f(&mut session_mgr)
+        self.matter.evict_removed_sessions();
+
+        result
     }
 
     pub async fn acknowledge(&mut self) -> Result<(), Error> {
@@ -244,7 +256,33 @@ impl<'a> Exchange<'a> {
 
         self.notification.wait().await;
 
-        Ok(())
+        self.check_failed()
+    }
+
+    /// Send a message with the provided payload and wait for the response of the peer
+    ///
+    /// The message is reliable, so the response also acknowledges it, unless the peer
+    /// acknowledges it separately first.
+    pub async fn send(
+        &mut self,
+        proto_id: u16,
+        opcode: u8,
+        payload: &[u8],
+        tx: &mut Packet<'_>,
+        rx: &mut Packet<'_>,
+    ) -> Result<(), Error> {
+        tx.reset();
+        tx.set_proto_id(proto_id);
+        tx.set_proto_opcode(opcode);
+        tx.get_writebuf()?.append(payload)?;
+
+        self.exchange(tx, rx).await
+    }
+
+    /// The msg counter of the last message sent on the exchange, as long as the peer
+    /// did not acknowledge it
+    pub fn pending_ack(&self) -> Result<Option<u32>, Error> {
+        self.with_ctx(|_, ctx| Ok(ctx.mrp.pending_ack()))
     }
 
     pub async fn complete(mut self, tx: &Packet<'_>) -> Result<(), Error> {
@@ -270,6 +308,19 @@ impl<'a> Exchange<'a> {
 
         self.notification.wait().await;
 
+        self.check_failed()
+    }
+
+    fn check_failed(&self) -> Result<(), Error> {
+        let mut exchanges = self.matter.exchanges.borrow_mut();
+
+        // The exchange might be gone already, if it got closed upon a successful completion
+        if let Some(ctx) = ExchangeCtx::get(&mut exchanges, &self.id) {
+            if matches!(ctx.state, ExchangeState::Failed) {
+                Err(ErrorCode::SendFailed)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -306,3 +357,237 @@ impl<'a> Drop for Exchange<'a> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use core::time::Duration;
+
+    use embassy_futures::select::select;
+
+    use crate::{
+        crypto::KeyPair,
+        data_model::sdm::dev_att::tests::TestDevAtt,
+        interaction_model::{
+            core::{OpCode, PROTO_ID_INTERACTION_MODEL},
+            messages::msg::SubscribeReq,
+        },
+        mdns::DummyMdns,
+        test_support::{seq_rand, BASIC_INFO},
+        transport::{
+            mrp::ReliableMessage,
+            network::Address,
+            packet::{Packet, MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+            session::MAX_SESSION_IDLE_TIME,
+        },
+        utils::{epoch::dummy_epoch, rand::dummy_rand, select::Notification},
+        Matter,
+    };
+
+    use super::{ExchangeCtx, ExchangeState};
+
+    const PEER_NODE_ID: u64 = 0x12344321;
+
+    #[test]
+    /// A message sent on an exchange we initiated stays pending until the peer acknowledges it
+    fn send_tracks_ack() {
+        let dev_att = TestDevAtt::new(&KeyPair::new(seq_rand).unwrap());
+        let matter = Matter::new(
+            &BASIC_INFO,
+            &dev_att,
+            &DummyMdns,
+            dummy_epoch,
+            dummy_rand,
+            5540,
+        );
+
+        let session_id = {
+            let mut session_mgr = matter.session_mgr.borrow_mut();
+            let sess_index = session_mgr
+                .add(Address::default(), Some(PEER_NODE_ID))
+                .unwrap();
+
+            session_mgr
+                .mut_by_index(sess_index)
+                .unwrap()
+                .get_session_id()
+        };
+
+        let mut exchange = matter.new_exchange(&session_id).unwrap();
+        let id = exchange.id().clone();
+        assert_eq!(exchange.pending_ack().unwrap(), None);
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+        let mut rx = Packet::new_rx(&mut rx_buf);
+
+        let mut sent_buf = [0; MAX_TX_BUF_SIZE];
+        let mut sent = Packet::new_tx(&mut sent_buf);
+
+        embassy_futures::block_on(select(
+            exchange.send(
+                PROTO_ID_INTERACTION_MODEL,
+                OpCode::ReportData as u8,
+                &[0x15, 0x18],
+                &mut tx,
+                &mut rx,
+            ),
+            async {
+                // The message goes out on the exchange, with the next msg counter of the session
+                assert!(matter.pull_tx(&mut sent).await.unwrap());
+                assert_eq!(sent.proto.exch_id, id.id);
+                assert!(sent.proto.is_initiator());
+                assert!(sent.is_reliable());
+
+                let msg_ctr = sent.plain.ctr;
+                {
+                    let mut exchanges = matter.exchanges.borrow_mut();
+                    let ctx = ExchangeCtx::get(&mut exchanges, &id).unwrap();
+
+                    assert_eq!(ctx.mrp.pending_ack(), Some(msg_ctr));
+                    assert!(matches!(
+                        ctx.state,
+                        ExchangeState::ExchangeRecv {
+                            tx_acknowledged: false,
+                            ..
+                        }
+                    ));
+                }
+
+                // The peer acknowledges the message, without responding yet
+                let mut ack_buf = [0; MAX_TX_BUF_SIZE];
+                let mut ack = Packet::new_tx(&mut ack_buf);
+                ReliableMessage::prepare_ack(id.id, &mut ack);
+                ack.proto.exch_id = id.id;
+                ack.proto.set_ack(msg_ctr);
+                ack.plain.ctr = 1;
+                ack.plain.set_src_u64(PEER_NODE_ID);
+                ack.proto_encode(Address::default(), None, PEER_NODE_ID, true, None, None)
+                    .unwrap();
+
+                let mut ack_rx_buf = [0; MAX_RX_BUF_SIZE];
+                let len = ack.as_slice().len();
+                ack_rx_buf[..len].copy_from_slice(ack.as_slice());
+                let mut ack_rx = Packet::new_rx(&mut ack_rx_buf[..len]);
+
+                let notification = Notification::new();
+                assert!(matter
+                    .process_rx(&notification, &mut ack_rx)
+                    .unwrap()
+                    .is_none());
+
+                let mut exchanges = matter.exchanges.borrow_mut();
+                let ctx = ExchangeCtx::get(&mut exchanges, &id).unwrap();
+
+                assert_eq!(ctx.mrp.pending_ack(), None);
+                assert!(matches!(
+                    ctx.state,
+                    ExchangeState::ExchangeRecv {
+                        tx_acknowledged: true,
+                        ..
+                    }
+                ));
+            },
+        ));
+
+        assert_eq!(exchange.pending_ack().unwrap(), None);
+    }
+
+    #[test]
+    /// Removing a session through an exchange drops the subscriptions made on it
+    fn removed_session_evicts_subscriptions() {
+        let dev_att = TestDevAtt::new(&KeyPair::new(seq_rand).unwrap());
+        let matter = Matter::new(
+            &BASIC_INFO,
+            &dev_att,
+            &DummyMdns,
+            dummy_epoch,
+            dummy_rand,
+            5540,
+        );
+
+        let (sess_index, session_id, sess_id) = {
+            let mut session_mgr = matter.session_mgr.borrow_mut();
+            let sess_index = session_mgr
+                .add(Address::default(), Some(PEER_NODE_ID))
+                .unwrap();
+
+            let session = session_mgr.mut_by_index(sess_index).unwrap();
+            (
+                sess_index,
+                session.get_session_id(),
+                session.get_local_sess_id(),
+            )
+        };
+
+        matter
+            .subscription_mgr
+            .borrow_mut()
+            .add(1, PEER_NODE_ID, sess_id, &SubscribeReq::new(false, 0, 60))
+            .unwrap();
+
+        let exchange = matter.new_exchange(&session_id).unwrap();
+        exchange
+            .with_session_mgr_mut(|session_mgr| {
+                session_mgr.remove(sess_index);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(matter.subscription_mgr.borrow().iter().count(), 0);
+    }
+
+    #[test]
+    /// The transport removes the sessions which went idle, along with their subscriptions
+    fn idle_session_removed() {
+        static NOW_SECS: AtomicU64 = AtomicU64::new(1000);
+
+        fn epoch() -> Duration {
+            Duration::from_secs(NOW_SECS.load(Ordering::SeqCst))
+        }
+
+        let dev_att = TestDevAtt::new(&KeyPair::new(seq_rand).unwrap());
+        let matter = Matter::new(&BASIC_INFO, &dev_att, &DummyMdns, epoch, dummy_rand, 5540);
+
+        let sess_id = {
+            let mut session_mgr = matter.session_mgr.borrow_mut();
+            let sess_index = session_mgr
+                .add(Address::default(), Some(PEER_NODE_ID))
+                .unwrap();
+
+            session_mgr
+                .mut_by_index(sess_index)
+                .unwrap()
+                .get_local_sess_id()
+        };
+
+        matter
+            .subscription_mgr
+            .borrow_mut()
+            .add(1, PEER_NODE_ID, sess_id, &SubscribeReq::new(false, 0, 60))
+            .unwrap();
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+
+        // Still within the idle time
+        NOW_SECS.fetch_add(MAX_SESSION_IDLE_TIME.as_secs(), Ordering::SeqCst);
+        assert!(!embassy_futures::block_on(matter.pull_tx(&mut tx)).unwrap());
+        assert!(matter
+            .session_mgr
+            .borrow_mut()
+            .get_with_id(sess_id)
+            .is_some());
+        assert_eq!(matter.subscription_mgr.borrow().iter().count(), 1);
+
+        NOW_SECS.fetch_add(1, Ordering::SeqCst);
+        assert!(!embassy_futures::block_on(matter.pull_tx(&mut tx)).unwrap());
+        assert!(matter
+            .session_mgr
+            .borrow_mut()
+            .get_with_id(sess_id)
+            .is_none());
+        assert_eq!(matter.subscription_mgr.borrow().iter().count(), 0);
+    }
+}
@@ -15,7 +15,10 @@
  *    limitations under the License.
  */
 
+pub mod ble;
+pub mod btp;
 pub mod core;
+pub mod counter;
 mod dedup;
 pub mod exchange;
 pub mod mrp;
@@ -24,5 +27,8 @@ pub mod packet;
 pub mod pipe;
 pub mod plain_hdr;
 pub mod proto_hdr;
+#[cfg(feature = "tokio")]
+pub mod runner;
 pub mod session;
+pub mod tcp;
 pub mod udp;
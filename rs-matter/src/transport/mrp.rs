@@ -15,7 +15,7 @@
  *    limitations under the License.
  */
 
-use crate::utils::epoch::Epoch;
+use crate::utils::{epoch::Epoch, rand::Rand};
 use core::time::Duration;
 
 use crate::{error::*, secure_channel, transport::packet::Packet};
@@ -24,21 +24,110 @@ use log::error;
 // 200 ms
 const MRP_STANDALONE_ACK_TIMEOUT: u64 = 200;
 
+// The default of the SESSION_ACTIVE_INTERVAL, 300 ms, for the sessions
+// whose peer did not announce its MRP parameters
+const MRP_RETRY_INTERVAL: u64 = 300;
+
+// The parameters of the retransmission backoff, as per the spec
+const MRP_BACKOFF_BASE: f32 = 1.6;
+const MRP_BACKOFF_JITTER: f32 = 0.25;
+const MRP_BACKOFF_MARGIN: f32 = 1.1;
+const MRP_BACKOFF_THRESHOLD: u32 = 1;
+
+/// The number of times a message is sent (including the first transmission)
+/// before giving up on it being acknowledged
+pub const MRP_MAX_TRANSMISSIONS: u32 = 5;
+
+/// The MRP parameters which the peer of a session announced during the session establishment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MrpParams {
+    /// The SESSION_IDLE_INTERVAL of the peer
+    pub idle_interval: Duration,
+    /// The SESSION_ACTIVE_INTERVAL of the peer
+    pub active_interval: Duration,
+    /// The SESSION_ACTIVE_THRESHOLD of the peer
+    pub active_threshold: Duration,
+}
+
+impl MrpParams {
+    /// The base retransmission interval towards the peer, which is active if it sent
+    /// us a message within its active threshold, i.e. `since_rx` ago
+    pub fn retrans_interval(&self, since_rx: Duration) -> Duration {
+        if since_rx < self.active_threshold {
+            self.active_interval
+        } else {
+            self.idle_interval
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RetransEntry {
     // The msg counter that we are waiting to be acknowledged
     msg_ctr: u32,
-    // This will additionally have retransmission count and periods once we implement it
+    // The number of retransmissions done so far
+    retries: u32,
+    // The base interval of the backoff
+    interval: Duration,
+    // The time after which the message should be re-sent, unless acknowledged
+    retrans_timeout: Duration,
 }
 
 impl RetransEntry {
-    pub fn new(msg_ctr: u32) -> Self {
-        Self { msg_ctr }
+    pub fn new(msg_ctr: u32, interval: Duration, epoch: Epoch, rand: Rand) -> Self {
+        Self {
+            msg_ctr,
+            retries: 0,
+            interval,
+            retrans_timeout: epoch() + Self::backoff(interval, 0, rand),
+        }
     }
 
     pub fn get_msg_ctr(&self) -> u32 {
         self.msg_ctr
     }
+
+    pub fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub fn is_due(&self, epoch: Epoch) -> bool {
+        epoch() >= self.retrans_timeout
+    }
+
+    /// Account for a retransmission of the message and schedule the next one.
+    ///
+    /// Fails once the message had been sent `MRP_MAX_TRANSMISSIONS` times.
+    pub fn retry(&mut self, epoch: Epoch, rand: Rand) -> Result<(), Error> {
+        if self.retries + 1 >= MRP_MAX_TRANSMISSIONS {
+            error!(
+                "Send failed after {} retries for msg counter {}",
+                self.retries, self.msg_ctr
+            );
+            Err(ErrorCode::SendFailed)?;
+        }
+
+        self.retries += 1;
+        self.retrans_timeout = epoch() + Self::backoff(self.interval, self.retries, rand);
+
+        Ok(())
+    }
+
+    // t = i * MARGIN * BASE^max(0, n - THRESHOLD) * (1 + random * JITTER)
+    fn backoff(interval: Duration, retries: u32, rand: Rand) -> Duration {
+        let mut interval = interval.as_millis() as f32 * MRP_BACKOFF_MARGIN;
+
+        for _ in MRP_BACKOFF_THRESHOLD..retries {
+            interval *= MRP_BACKOFF_BASE;
+        }
+
+        let mut random = [0; 1];
+        rand(&mut random);
+
+        let jitter = 1.0 + random[0] as f32 / u8::MAX as f32 * MRP_BACKOFF_JITTER;
+
+        Duration::from_millis((interval * jitter) as u64)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -68,7 +157,7 @@ impl AckEntry {
     }
 
     pub fn has_timed_out(&self, epoch: Epoch) -> bool {
-        self.ack_timeout > epoch()
+        epoch() >= self.ack_timeout
     }
 }
 
@@ -99,11 +188,54 @@ impl ReliableMessage {
         }
     }
 
+    /// The msg counter of the sent message which is not acknowledged yet, if any
+    pub fn pending_ack(&self) -> Option<u32> {
+        self.retrans.as_ref().map(RetransEntry::get_msg_ctr)
+    }
+
+    /// The earliest time at which a retransmission or a standalone acknowledgement
+    /// is due, as per the epoch
+    pub fn deadline(&self) -> Option<Duration> {
+        let retrans = self.retrans.as_ref().map(|entry| entry.retrans_timeout);
+        let ack = self.ack.as_ref().map(|entry| entry.ack_timeout);
+
+        match (retrans, ack) {
+            (Some(retrans), Some(ack)) => Some(retrans.min(ack)),
+            (retrans, ack) => retrans.or(ack),
+        }
+    }
+
+    pub fn is_retrans_due(&self, epoch: Epoch) -> bool {
+        self.retrans
+            .as_ref()
+            .map(|entry| entry.is_due(epoch))
+            .unwrap_or(false)
+    }
+
+    /// Prepare for retransmitting the unacknowledged message and return its msg counter,
+    /// which the retransmission has to re-use
+    pub fn retry(&mut self, epoch: Epoch, rand: Rand) -> Result<u32, Error> {
+        let entry = self.retrans.as_mut().ok_or(ErrorCode::Invalid)?;
+
+        entry.retry(epoch, rand)?;
+
+        Ok(entry.get_msg_ctr())
+    }
+
     pub fn prepare_ack(_exch_id: u16, proto_tx: &mut Packet) {
         secure_channel::common::create_mrp_standalone_ack(proto_tx);
     }
 
-    pub fn pre_send(&mut self, proto_tx: &mut Packet) -> Result<(), Error> {
+    /// Track the message for its retransmission, with the backoff starting from
+    /// `retrans_interval` or - if the session has no MRP parameters - from the default
+    /// one of the spec
+    pub fn pre_send(
+        &mut self,
+        proto_tx: &mut Packet,
+        retrans_interval: Option<Duration>,
+        epoch: Epoch,
+        rand: Rand,
+    ) -> Result<(), Error> {
         // Check if any acknowledgements are pending for this exchange,
 
         // if so, piggy back in the encoded header here
@@ -117,13 +249,20 @@ impl ReliableMessage {
             return Ok(());
         }
 
-        if self.retrans.is_some() {
+        if let Some(entry) = &self.retrans {
+            if entry.get_msg_ctr() == proto_tx.plain.ctr {
+                // A retransmission, which is already accounted for
+                return Ok(());
+            }
+
             // This indicates there was some existing entry for same sess-id/exch-id, which shouldnt happen
             error!("Previous retrans entry for this exchange already exists");
             Err(ErrorCode::Invalid)?;
         }
 
-        self.retrans = Some(RetransEntry::new(proto_tx.plain.ctr));
+        let interval = retrans_interval.unwrap_or(Duration::from_millis(MRP_RETRY_INTERVAL));
+
+        self.retrans = Some(RetransEntry::new(proto_tx.plain.ctr, interval, epoch, rand));
         Ok(())
     }
 
@@ -158,3 +297,116 @@ impl ReliableMessage {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::{sync::atomic::Ordering, time::Duration};
+
+    use crate::{
+        error::ErrorCode,
+        test_support::mock_epoch,
+        transport::packet::{Packet, MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+        utils::rand::dummy_rand,
+    };
+
+    use super::{MrpParams, ReliableMessage, MRP_MAX_TRANSMISSIONS};
+
+    // Each test has its own clock, as tests run in parallel
+    mock_epoch!(MOCK_NOW_MS, mock_epoch);
+    mock_epoch!(MOCK_NOW_MS_GIVE_UP, mock_epoch_give_up);
+    mock_epoch!(MOCK_NOW_MS_INTERVAL, mock_epoch_interval);
+
+    #[test]
+    fn test_retrans_until_ack() {
+        let mut mrp = ReliableMessage::new();
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+        tx.plain.ctr = 10;
+
+        mrp.pre_send(&mut tx, None, mock_epoch, dummy_rand).unwrap();
+        assert!(!mrp.is_retrans_due(mock_epoch));
+
+        // The first transmission is dropped, so the message is re-sent with the same counter
+        MOCK_NOW_MS.fetch_add(400, Ordering::SeqCst);
+        assert!(mrp.is_retrans_due(mock_epoch));
+        assert_eq!(mrp.retry(mock_epoch, dummy_rand).unwrap(), 10);
+        mrp.pre_send(&mut tx, None, mock_epoch, dummy_rand).unwrap();
+        assert!(!mrp.is_retrans_due(mock_epoch));
+
+        // Once the message is acknowledged, there are no more retransmissions
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+        let mut rx = Packet::new_rx(&mut rx_buf);
+        rx.proto.set_ack(10);
+
+        mrp.recv(&rx, mock_epoch).unwrap();
+        MOCK_NOW_MS.fetch_add(10000, Ordering::SeqCst);
+        assert!(!mrp.is_retrans_due(mock_epoch));
+        assert!(mrp.is_empty());
+    }
+
+    #[test]
+    fn test_retrans_gives_up() {
+        let mut mrp = ReliableMessage::new();
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+        tx.plain.ctr = 20;
+
+        mrp.pre_send(&mut tx, None, mock_epoch_give_up, dummy_rand)
+            .unwrap();
+
+        for _ in 1..MRP_MAX_TRANSMISSIONS {
+            MOCK_NOW_MS_GIVE_UP.fetch_add(10000, Ordering::SeqCst);
+            assert!(mrp.is_retrans_due(mock_epoch_give_up));
+            assert_eq!(mrp.retry(mock_epoch_give_up, dummy_rand).unwrap(), 20);
+        }
+
+        MOCK_NOW_MS_GIVE_UP.fetch_add(10000, Ordering::SeqCst);
+        assert_eq!(
+            mrp.retry(mock_epoch_give_up, dummy_rand)
+                .map_err(|e| e.code()),
+            Err(ErrorCode::SendFailed)
+        );
+    }
+
+    #[test]
+    fn test_retrans_interval_of_session() {
+        let params = MrpParams {
+            idle_interval: Duration::from_millis(5000),
+            active_interval: Duration::from_millis(2000),
+            active_threshold: Duration::from_millis(4000),
+        };
+
+        // The peer is active within its threshold, and idle past it
+        assert_eq!(
+            params.retrans_interval(Duration::from_millis(1000)),
+            params.active_interval
+        );
+        assert_eq!(
+            params.retrans_interval(Duration::from_millis(4000)),
+            params.idle_interval
+        );
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+        tx.plain.ctr = 30;
+
+        // With no jitter, the first retransmission is due after the interval and the margin
+        let mut mrp = ReliableMessage::new();
+        mrp.pre_send(
+            &mut tx,
+            Some(params.active_interval),
+            mock_epoch_interval,
+            dummy_rand,
+        )
+        .unwrap();
+        assert_eq!(mrp.deadline(), Some(Duration::from_millis(2200)));
+
+        // Without MRP parameters, the default interval of the spec applies
+        let mut mrp = ReliableMessage::new();
+        mrp.pre_send(&mut tx, None, mock_epoch_interval, dummy_rand)
+            .unwrap();
+        assert_eq!(mrp.deadline(), Some(Duration::from_millis(330)));
+    }
+}
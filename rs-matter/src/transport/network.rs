@@ -17,20 +17,95 @@
 
 use core::fmt::{Debug, Display};
 #[cfg(not(feature = "std"))]
-pub use no_std_net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+pub use no_std_net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 #[cfg(feature = "std")]
-pub use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+pub use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use super::packet::MAX_UDP_PAYLOAD_SIZE;
+
+/// Whether the IPv6 address is a unicast link-local one (`fe80::/10`), which can
+/// only be routed with the scope ID of the interface the peer is reachable on
+pub fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
 
 #[derive(Eq, PartialEq, Copy, Clone)]
 pub enum Address {
     Udp(SocketAddr),
+    Tcp(SocketAddr),
 }
 
 impl Address {
+    /// The socket address of a UDP peer, or `None` for any other transport
+    pub fn udp(self) -> Option<SocketAddr> {
+        match self {
+            Self::Udp(addr) => Some(addr),
+            Self::Tcp(_) => None,
+        }
+    }
+
+    /// The socket address of a TCP peer, or `None` for any other transport
+    pub fn tcp(self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(addr) => Some(addr),
+            Self::Udp(_) => None,
+        }
+    }
+
     pub fn unwrap_udp(self) -> SocketAddr {
         match self {
             Self::Udp(addr) => addr,
+            Self::Tcp(_) => panic!("Not a UDP address"),
+        }
+    }
+
+    pub fn unwrap_tcp(self) -> SocketAddr {
+        match self {
+            Self::Tcp(addr) => addr,
+            Self::Udp(_) => panic!("Not a TCP address"),
+        }
+    }
+
+    /// The address of a peer, as tracked by its sessions and exchanges
+    ///
+    /// Only link-local IPv6 peers keep the scope ID they were received with, so that
+    /// the replies to them are sent over the same interface. The flow information
+    /// is dropped, as it may change from one packet of the peer to the next.
+    pub fn normalize(self) -> Self {
+        match self {
+            Self::Udp(addr) => Self::Udp(normalize_socket_addr(addr)),
+            Self::Tcp(addr) => Self::Tcp(normalize_socket_addr(addr)),
+        }
+    }
+
+    /// The longest message the transport of the address carries: a single datagram
+    /// for UDP, while TCP carries messages of any length
+    pub fn max_payload_len(&self) -> usize {
+        match self {
+            Self::Udp(_) => MAX_UDP_PAYLOAD_SIZE,
+            Self::Tcp(_) => usize::MAX,
+        }
+    }
+
+    /// Whether the transport of the address is reliable by itself, in which case
+    /// the Message Reliability Protocol is not used
+    pub fn is_reliable(&self) -> bool {
+        matches!(self, Self::Tcp(_))
+    }
+}
+
+fn normalize_socket_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => {
+            let scope_id = if is_link_local(v6.ip()) {
+                v6.scope_id()
+            } else {
+                0
+            };
+
+            SocketAddr::V6(SocketAddrV6::new(*v6.ip(), v6.port(), 0, scope_id))
         }
+        v4 => v4,
     }
 }
 
@@ -44,6 +119,7 @@ impl Display for Address {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Address::Udp(addr) => writeln!(f, "{}", addr),
+            Address::Tcp(addr) => writeln!(f, "{} (TCP)", addr),
         }
     }
 }
@@ -52,6 +128,7 @@ impl Debug for Address {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Address::Udp(addr) => writeln!(f, "{}", addr),
+            Address::Tcp(addr) => writeln!(f, "{} (TCP)", addr),
         }
     }
 }
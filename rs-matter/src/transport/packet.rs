@@ -19,6 +19,7 @@ use log::{error, info, trace};
 use owo_colors::OwoColorize;
 
 use crate::{
+    crypto::AEAD_MIC_LEN_BYTES,
     error::{Error, ErrorCode},
     interaction_model::core::PROTO_ID_INTERACTION_MODEL,
     secure_channel::common::PROTO_ID_SECURE_CHANNEL,
@@ -34,7 +35,17 @@ use super::{
 
 pub const MAX_RX_BUF_SIZE: usize = 1583;
 pub const MAX_RX_STATUS_BUF_SIZE: usize = 100;
-pub const MAX_TX_BUF_SIZE: usize = 1280 - 40/*IPV6 header size*/ - 8/*UDP header size*/;
+/// The largest message which fits in a UDP datagram on the minimum MTU of IPv6
+pub const MAX_UDP_PAYLOAD_SIZE: usize = 1280 - 40/*IPV6 header size*/ - 8/*UDP header size*/;
+/// The size of the buffers of the outgoing messages
+///
+/// With the `tcp` feature, this is larger than a UDP datagram, so that peers over TCP get
+/// larger messages, e.g. larger chunks of long reads. The messages to UDP peers are still
+/// limited to a datagram. Without it, the buffers only ever hold a datagram.
+#[cfg(feature = "tcp")]
+pub const MAX_TX_BUF_SIZE: usize = 2048;
+#[cfg(not(feature = "tcp"))]
+pub const MAX_TX_BUF_SIZE: usize = MAX_UDP_PAYLOAD_SIZE;
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 enum RxState {
@@ -77,6 +88,8 @@ pub struct Packet<'a> {
     pub proto: ProtoHdr,
     pub peer: Address,
     data: Direction<'a>,
+    /// The longest outgoing message the transport of the peer carries
+    max_len: usize,
 }
 
 impl<'a> Packet<'a> {
@@ -88,6 +101,7 @@ impl<'a> Packet<'a> {
             proto: Default::default(),
             peer: Address::default(),
             data: Direction::Rx(ParseBuf::new(buf), RxState::Uninit),
+            max_len: usize::MAX,
         }
     }
 
@@ -104,13 +118,36 @@ impl<'a> Packet<'a> {
             proto,
             peer: Address::default(),
             data: Direction::Tx(wb),
+            max_len: usize::MAX,
         }
     }
 
+    /// Limit the outgoing messages to the provided length, which is the most the
+    /// transport of the peer carries. The limit is kept across resets.
+    pub fn set_max_len(&mut self, max_len: usize) -> Result<(), Error> {
+        self.max_len = max_len;
+
+        if let Direction::Tx(wb) = &mut self.data {
+            Self::limit(wb, max_len)?;
+        }
+
+        Ok(())
+    }
+
+    fn limit(wb: &mut WriteBuf, max_len: usize) -> Result<(), Error> {
+        let capacity = wb.capacity();
+        if max_len < capacity {
+            wb.shrink(capacity - max_len)?;
+        }
+
+        Ok(())
+    }
+
     pub fn reset(&mut self) {
         if let Direction::Tx(wb) = &mut self.data {
             wb.reset();
             wb.reserve(Packet::HDR_RESERVE).unwrap();
+            Self::limit(wb, self.max_len).unwrap();
 
             self.plain = Default::default();
             self.proto = Default::default();
@@ -220,6 +257,7 @@ impl<'a> Packet<'a> {
         local_nodeid: u64,
         plain_text: bool,
         enc_key: Option<&[u8]>,
+        privacy_key: Option<&[u8]>,
     ) -> Result<(), Error> {
         self.peer = peer;
 
@@ -236,15 +274,23 @@ impl<'a> Packet<'a> {
             }
         }
 
+        // Only the headers of encrypted messages can be obfuscated
+        let privacy_key = privacy_key.filter(|_| enc_key.is_some());
+        if privacy_key.is_some() {
+            self.plain.set_privacy();
+        }
+
         let mut tmp_buf = [0_u8; plain_hdr::max_plain_hdr_len()];
         let mut write_buf = WriteBuf::new(&mut tmp_buf);
         self.plain.encode(&mut write_buf)?;
-        let plain_hdr_bytes = write_buf.as_slice();
+        let plain_hdr_len = write_buf.as_slice().len();
+        let plain_hdr_bytes = &mut tmp_buf[..plain_hdr_len];
 
         trace!("unencrypted packet: {:x?}", self.as_mut_slice());
         let ctr = self.plain.ctr;
         if let Some(e) = enc_key {
             proto_hdr::encrypt_in_place(
+                self.plain.sec_flags(),
                 ctr,
                 local_nodeid,
                 plain_hdr_bytes,
@@ -253,6 +299,20 @@ impl<'a> Packet<'a> {
             )?;
         }
 
+        if let Some(privacy_key) = privacy_key {
+            // The header is obfuscated after the encryption, as the MIC is part of the nonce
+            let sess_id = self.plain.sess_id;
+            let payload = self.get_writebuf()?.as_slice();
+            let mic = &payload[payload.len() - AEAD_MIC_LEN_BYTES..];
+
+            plain_hdr::privacy_transform(
+                privacy_key,
+                sess_id,
+                mic,
+                &mut plain_hdr_bytes[plain_hdr::PRIVACY_HDR_OFFSET..],
+            )?;
+        }
+
         self.get_writebuf()?.prepend(plain_hdr_bytes)?;
         trace!("Full encrypted packet: {:x?}", self.as_mut_slice());
 
@@ -284,6 +344,32 @@ impl<'a> Packet<'a> {
         }
     }
 
+    /// Deobfuscate and decode the rest of the plain-text header of a message with the privacy flag
+    pub fn plain_hdr_decode_private(&mut self, privacy_key: &[u8]) -> Result<(), Error> {
+        match &mut self.data {
+            Direction::Rx(pb, state) => {
+                if *state == RxState::PlainDecode && self.plain.is_private() {
+                    let len = self.plain.privacy_hdr_len();
+                    let data = pb.as_mut_slice();
+                    if data.len() < len + AEAD_MIC_LEN_BYTES {
+                        Err(ErrorCode::TruncatedPacket)?;
+                    }
+
+                    let (hdr, rest) = data.split_at_mut(len);
+                    let mic = &rest[rest.len() - AEAD_MIC_LEN_BYTES..];
+
+                    plain_hdr::privacy_transform(privacy_key, self.plain.sess_id, mic, hdr)?;
+
+                    self.plain.decode_private(pb)
+                } else {
+                    error!("Invalid state for plain_decode_private");
+                    Err(ErrorCode::InvalidState.into())
+                }
+            }
+            _ => Err(ErrorCode::InvalidState.into()),
+        }
+    }
+
     pub fn log(&self, operation: &str) {
         match self.get_proto_id() {
             PROTO_ID_SECURE_CHANNEL => {
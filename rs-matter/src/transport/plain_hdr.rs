@@ -15,6 +15,7 @@
  *    limitations under the License.
  */
 
+use crate::crypto::{self, AEAD_MIC_LEN_BYTES, AEAD_NONCE_LEN_BYTES};
 use crate::error::*;
 use crate::utils::parsebuf::ParseBuf;
 use crate::utils::writebuf::WriteBuf;
@@ -26,6 +27,7 @@ pub enum SessionType {
     #[default]
     None,
     Encrypted,
+    Group,
 }
 
 bitflags! {
@@ -37,6 +39,19 @@ bitflags! {
     }
 }
 
+/// The session type bits of the security flags of a group message
+const SEC_FLAGS_GROUP_SESSION: u8 = 0x01;
+const SEC_FLAGS_SESSION_TYPE_MASK: u8 = 0x03;
+/// Set when the header is obfuscated with the privacy key of the session
+const SEC_FLAGS_PRIVACY: u8 = 0x80;
+
+/// The header is obfuscated from the message counter on, i.e. after the flags,
+/// the session ID and the security flags
+pub const PRIVACY_HDR_OFFSET: usize = 4;
+const MAX_PRIVACY_HDR_LEN: usize = max_plain_hdr_len() - 2 - PRIVACY_HDR_OFFSET;
+
+const PRIVACY_KEY_INFO: &[u8] = b"PrivacyKey";
+
 // This is the unencrypted message
 #[derive(Debug, Default, Clone)]
 pub struct PlainHdr {
@@ -45,6 +60,8 @@ pub struct PlainHdr {
     pub sess_id: u16,
     pub ctr: u32,
     peer_nodeid: Option<u64>,
+    dest_group_id: Option<u16>,
+    privacy: bool,
 }
 
 impl PlainHdr {
@@ -53,6 +70,17 @@ impl PlainHdr {
         self.peer_nodeid = Some(id);
     }
 
+    pub fn set_src_u64(&mut self, id: u64) {
+        self.flags |= MsgFlags::SRC_ADDR_PRESENT;
+        self.peer_nodeid = Some(id);
+    }
+
+    pub fn set_dest_group(&mut self, group_id: u16) {
+        self.flags |= MsgFlags::DSIZ_GROUPCAST_NODEID;
+        self.sess_type = SessionType::Group;
+        self.dest_group_id = Some(group_id);
+    }
+
     pub fn get_src_u64(&self) -> Option<u64> {
         if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
             self.peer_nodeid
@@ -60,6 +88,51 @@ impl PlainHdr {
             None
         }
     }
+
+    pub fn get_dest_group_id(&self) -> Option<u16> {
+        self.dest_group_id
+    }
+
+    /// Obfuscate the header of the message with the privacy key of the session
+    pub fn set_privacy(&mut self) {
+        self.privacy = true;
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.privacy
+    }
+
+    /// The security flags, which are also part of the nonce of encrypted messages
+    pub fn sec_flags(&self) -> u8 {
+        let sec_flags = if self.is_group() {
+            SEC_FLAGS_GROUP_SESSION
+        } else {
+            0
+        };
+
+        if self.privacy {
+            sec_flags | SEC_FLAGS_PRIVACY
+        } else {
+            sec_flags
+        }
+    }
+
+    /// The length of the obfuscated part of the header
+    pub fn privacy_hdr_len(&self) -> usize {
+        let mut len = 4;
+
+        if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
+            len += 8;
+        }
+
+        if self.flags.contains(MsgFlags::DSIZ_UNICAST_NODEID) {
+            len += 8;
+        } else if self.flags.contains(MsgFlags::DSIZ_GROUPCAST_NODEID) {
+            len += 2;
+        }
+
+        len
+    }
 }
 
 impl PlainHdr {
@@ -67,18 +140,41 @@ impl PlainHdr {
     pub fn decode(&mut self, msg: &mut ParseBuf) -> Result<(), Error> {
         self.flags = MsgFlags::from_bits(msg.le_u8()?).ok_or(ErrorCode::Invalid)?;
         self.sess_id = msg.le_u16()?;
-        let _sec_flags = msg.le_u8()?;
-        self.sess_type = if self.sess_id != 0 {
+        let sec_flags = msg.le_u8()?;
+        self.sess_type = if sec_flags & SEC_FLAGS_SESSION_TYPE_MASK == SEC_FLAGS_GROUP_SESSION {
+            SessionType::Group
+        } else if self.sess_id != 0 {
             SessionType::Encrypted
         } else {
             SessionType::None
         };
+        self.privacy = sec_flags & SEC_FLAGS_PRIVACY != 0;
+
+        if self.privacy {
+            // The rest of the header can only be decoded once it is deobfuscated
+            // with the privacy key of the session
+            Ok(())
+        } else {
+            self.decode_private(msg)
+        }
+    }
+
+    /// Decode the part of the header after the security flags, which is obfuscated
+    /// if the message has the privacy flag
+    pub fn decode_private(&mut self, msg: &mut ParseBuf) -> Result<(), Error> {
         self.ctr = msg.le_u32()?;
 
         if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
             self.peer_nodeid = Some(msg.le_u64()?);
         }
 
+        if self.flags.contains(MsgFlags::DSIZ_UNICAST_NODEID) {
+            // We are the destination, so there is nothing to keep
+            let _dest_nodeid = msg.le_u64()?;
+        } else if self.flags.contains(MsgFlags::DSIZ_GROUPCAST_NODEID) {
+            self.dest_group_id = Some(msg.le_u16()?);
+        }
+
         info!(
             "[decode] flags: {:?}, session type: {:#?}, sess_id: {}, ctr: {}",
             self.flags, self.sess_type, self.sess_id, self.ctr
@@ -89,16 +185,23 @@ impl PlainHdr {
     pub fn encode(&mut self, resp_buf: &mut WriteBuf) -> Result<(), Error> {
         resp_buf.le_u8(self.flags.bits())?;
         resp_buf.le_u16(self.sess_id)?;
-        resp_buf.le_u8(0)?;
+        resp_buf.le_u8(self.sec_flags())?;
         resp_buf.le_u32(self.ctr)?;
         if let Some(d) = self.peer_nodeid {
             resp_buf.le_u64(d)?;
         }
+        if let Some(group_id) = self.dest_group_id {
+            resp_buf.le_u16(group_id)?;
+        }
         Ok(())
     }
 
     pub fn is_encrypted(&self) -> bool {
-        self.sess_type == SessionType::Encrypted
+        matches!(self.sess_type, SessionType::Encrypted | SessionType::Group)
+    }
+
+    pub fn is_group(&self) -> bool {
+        self.sess_type == SessionType::Group
     }
 }
 
@@ -118,3 +221,120 @@ pub const fn max_plain_hdr_len() -> usize {
     // [optional] destination node ID
         8
 }
+
+/// Derive the privacy key from the encryption key of a session, as per the key schedule of the spec
+pub fn privacy_key(enc_key: &[u8], privacy_key: &mut [u8]) -> Result<(), Error> {
+    crypto::hkdf_sha256(&[], enc_key, PRIVACY_KEY_INFO, privacy_key)
+}
+
+/// Obfuscate the part of the header after the security flags, or deobfuscate it, as the
+/// transformation is its own inverse
+///
+/// The nonce is the session ID (big endian), followed by bytes 5 to 15 of the MIC of the message.
+pub fn privacy_transform(
+    privacy_key: &[u8],
+    sess_id: u16,
+    mic: &[u8],
+    hdr: &mut [u8],
+) -> Result<(), Error> {
+    if mic.len() != AEAD_MIC_LEN_BYTES || hdr.len() > MAX_PRIVACY_HDR_LEN {
+        Err(ErrorCode::Invalid)?;
+    }
+
+    let mut nonce = [0; AEAD_NONCE_LEN_BYTES];
+    nonce[..2].copy_from_slice(&sess_id.to_be_bytes());
+    nonce[2..].copy_from_slice(&mic[5..]);
+
+    // The transformation is AES-CTR, with the counter blocks of AES-CCM; so it is the cipher
+    // text of an AES-CCM encryption without additional data, minus the MIC
+    let mut buf = [0; MAX_PRIVACY_HDR_LEN + AEAD_MIC_LEN_BYTES];
+    buf[..hdr.len()].copy_from_slice(hdr);
+
+    crypto::encrypt_in_place(
+        privacy_key,
+        &nonce,
+        &[],
+        &mut buf[..hdr.len() + AEAD_MIC_LEN_BYTES],
+        hdr.len(),
+    )?;
+
+    hdr.copy_from_slice(&buf[..hdr.len()]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::{parsebuf::ParseBuf, writebuf::WriteBuf};
+
+    use super::{privacy_key, privacy_transform, PlainHdr, SessionType, PRIVACY_HDR_OFFSET};
+
+    const ENC_KEY: [u8; 16] = [
+        0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+        0x1f,
+    ];
+    const PRIVACY_KEY: [u8; 16] = [
+        0x16, 0x53, 0x1c, 0x6b, 0x54, 0x32, 0x65, 0xf8, 0x96, 0x7e, 0x22, 0x60, 0x3d, 0xfc, 0x17,
+        0xb2,
+    ];
+    const MIC: [u8; 16] = [
+        0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xab, 0xac, 0xad, 0xae,
+        0xaf,
+    ];
+    // The message counter and the source node ID
+    const PRIVATE_HDR: [u8; 12] = [
+        0x78, 0x56, 0x34, 0x12, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+    ];
+    const OBFUSCATED_HDR: [u8; 12] = [
+        0x1e, 0x05, 0x02, 0x7f, 0x6c, 0x84, 0x94, 0xa7, 0x47, 0x14, 0x32, 0x4d,
+    ];
+
+    #[test]
+    fn test_privacy_key() {
+        let mut key = [0; 16];
+        privacy_key(&ENC_KEY, &mut key).unwrap();
+
+        assert_eq!(key, PRIVACY_KEY);
+    }
+
+    #[test]
+    fn test_privacy_round_trip() {
+        let mut plain = PlainHdr {
+            sess_id: 0x1234,
+            sess_type: SessionType::Encrypted,
+            ctr: 0x12345678,
+            ..Default::default()
+        };
+        plain.set_src_u64(0x0807060504030201);
+        plain.set_privacy();
+
+        let mut buf = [0; 30];
+        let mut wb = WriteBuf::new(&mut buf);
+        plain.encode(&mut wb).unwrap();
+        let len = wb.as_slice().len();
+        let hdr = &mut buf[..len];
+
+        assert_eq!(hdr[3], 0x80);
+        assert_eq!(&hdr[PRIVACY_HDR_OFFSET..], &PRIVATE_HDR);
+        assert_eq!(plain.privacy_hdr_len(), PRIVATE_HDR.len());
+
+        // Obfuscate
+        privacy_transform(&PRIVACY_KEY, 0x1234, &MIC, &mut hdr[PRIVACY_HDR_OFFSET..]).unwrap();
+        assert_eq!(&hdr[PRIVACY_HDR_OFFSET..], &OBFUSCATED_HDR);
+
+        // Only the fields before the obfuscated part can be decoded without the privacy key
+        let mut rx = PlainHdr::default();
+        let mut pb = ParseBuf::new(hdr);
+        rx.decode(&mut pb).unwrap();
+        assert!(rx.is_private());
+        assert_eq!(rx.sess_id, 0x1234);
+        assert_eq!(rx.ctr, 0);
+        assert_eq!(rx.get_src_u64(), None);
+
+        // Deobfuscate
+        privacy_transform(&PRIVACY_KEY, 0x1234, &MIC, pb.as_mut_slice()).unwrap();
+        rx.decode_private(&mut pb).unwrap();
+        assert_eq!(rx.ctr, 0x12345678);
+        assert_eq!(rx.get_src_u64(), Some(0x0807060504030201));
+    }
+}
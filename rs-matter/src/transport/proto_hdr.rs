@@ -21,7 +21,10 @@ use core::fmt;
 use crate::transport::plain_hdr;
 use crate::utils::parsebuf::ParseBuf;
 use crate::utils::writebuf::WriteBuf;
-use crate::{crypto, error::*};
+use crate::{
+    crypto::{self, Crypto},
+    error::*,
+};
 
 use log::{info, trace};
 
@@ -102,7 +105,13 @@ impl ProtoHdr {
     ) -> Result<(), Error> {
         if let Some(d) = dec_key {
             // We decrypt only if the decryption key is valid
-            decrypt_in_place(plain_hdr.ctr, peer_nodeid, parsebuf, d)?;
+            decrypt_in_place(
+                plain_hdr.sec_flags(),
+                plain_hdr.ctr,
+                peer_nodeid,
+                parsebuf,
+                d,
+            )?;
         }
 
         self.exch_flags = ExchFlags::from_bits(parsebuf.le_u8()?).ok_or(ErrorCode::Invalid)?;
@@ -163,17 +172,18 @@ impl fmt::Display for ProtoHdr {
     }
 }
 
-fn get_iv(recvd_ctr: u32, peer_nodeid: u64, iv: &mut [u8]) -> Result<(), Error> {
-    // The IV is the source address (64-bit) followed by the message counter (32-bit)
+fn get_iv(sec_flags: u8, recvd_ctr: u32, peer_nodeid: u64, iv: &mut [u8]) -> Result<(), Error> {
+    // The IV is the security flags, followed by the message counter (32-bit) and
+    // the source address (64-bit)
     let mut write_buf = WriteBuf::new(iv);
-    // For some reason, this is 0 in the 'bypass' mode
-    write_buf.le_u8(0)?;
+    write_buf.le_u8(sec_flags)?;
     write_buf.le_u32(recvd_ctr)?;
     write_buf.le_u64(peer_nodeid)?;
     Ok(())
 }
 
 pub fn encrypt_in_place(
+    sec_flags: u8,
     send_ctr: u32,
     peer_nodeid: u64,
     plain_hdr: &[u8],
@@ -182,14 +192,14 @@ pub fn encrypt_in_place(
 ) -> Result<(), Error> {
     // IV
     let mut iv = [0_u8; crypto::AEAD_NONCE_LEN_BYTES];
-    get_iv(send_ctr, peer_nodeid, &mut iv)?;
+    get_iv(sec_flags, send_ctr, peer_nodeid, &mut iv)?;
 
     // Cipher Text
     let tag_space = [0u8; crypto::AEAD_MIC_LEN_BYTES];
     writebuf.append(&tag_space)?;
     let cipher_text = writebuf.as_mut_slice();
 
-    crypto::encrypt_in_place(
+    crypto::Backend::encrypt_in_place(
         key,
         &iv,
         plain_hdr,
@@ -202,27 +212,28 @@ pub fn encrypt_in_place(
 }
 
 fn decrypt_in_place(
+    sec_flags: u8,
     recvd_ctr: u32,
     peer_nodeid: u64,
     parsebuf: &mut ParseBuf,
     key: &[u8],
 ) -> Result<(), Error> {
     // AAD:
-    //    the unencrypted header of this packet
-    let mut aad = [0_u8; crypto::AEAD_AAD_LEN_BYTES];
+    //    the unencrypted header of this packet, which is longer than the minimum
+    //    when it carries the source node ID and the destination (e.g. for group messages)
+    let mut aad_buf = [0_u8; plain_hdr::max_plain_hdr_len()];
     let parsed_slice = parsebuf.parsed_as_slice();
-    if parsed_slice.len() == aad.len() {
-        // The plain_header is variable sized in length, I wonder if the AAD is fixed at 8, or the variable size.
-        // If so, we need to handle it cleanly here.
-        aad.copy_from_slice(parsed_slice);
-    } else {
+    if parsed_slice.len() < crypto::AEAD_AAD_LEN_BYTES || parsed_slice.len() > aad_buf.len() {
         Err(ErrorCode::InvalidAAD)?;
     }
 
+    let aad = &mut aad_buf[..parsed_slice.len()];
+    aad.copy_from_slice(parsed_slice);
+
     // IV:
     //   the specific way for creating IV is in get_iv
     let mut iv = [0_u8; crypto::AEAD_NONCE_LEN_BYTES];
-    get_iv(recvd_ctr, peer_nodeid, &mut iv)?;
+    get_iv(sec_flags, recvd_ctr, peer_nodeid, &mut iv)?;
 
     let cipher_text = parsebuf.as_mut_slice();
     //println!("AAD: {:x?}", aad);
@@ -230,7 +241,7 @@ fn decrypt_in_place(
     //println!("IV: {:x?}", iv);
     //println!("Key: {:x?}", key);
 
-    crypto::decrypt_in_place(key, &iv, &aad, cipher_text)?;
+    crypto::Backend::decrypt_in_place(key, &iv, aad, cipher_text)?;
     // println!("Plain Text: {:x?}", cipher_text);
     parsebuf.tail(crypto::AEAD_MIC_LEN_BYTES)?;
     Ok(())
@@ -276,7 +287,7 @@ mod tests {
         parsebuf.le_u32().unwrap();
         parsebuf.le_u32().unwrap();
 
-        decrypt_in_place(recvd_ctr, 0, &mut parsebuf, &key).unwrap();
+        decrypt_in_place(0, recvd_ctr, 0, &mut parsebuf, &key).unwrap();
         assert_eq!(
             parsebuf.as_slice(),
             [
@@ -309,7 +320,7 @@ mod tests {
             0x1b, 0x33,
         ];
 
-        encrypt_in_place(send_ctr, 0, &plain_hdr, &mut writebuf, &key).unwrap();
+        encrypt_in_place(0, send_ctr, 0, &plain_hdr, &mut writebuf, &key).unwrap();
         assert_eq!(
             writebuf.as_slice(),
             [
@@ -319,4 +330,109 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    /// The nonce of the spec: the security flags, the message counter and the source node ID
+    pub fn test_nonce() {
+        let mut iv = [0_u8; crypto::AEAD_NONCE_LEN_BYTES];
+        get_iv(0x01, 0x1234_5678, 0x0102_0304_0506_0708, &mut iv).unwrap();
+
+        assert_eq!(
+            iv,
+            [0x01, 0x78, 0x56, 0x34, 0x12, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]
+        );
+    }
+
+    /// A group message, whose unencrypted header carries the source node ID, so the AAD
+    /// is longer than the minimal header
+    const GROUP_HDR: [u8; 16] = [
+        0x04, 0x34, 0x12, 0x01, 0x78, 0x56, 0x34, 0x12, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02,
+        0x01,
+    ];
+    const GROUP_PLAIN_TEXT: [u8; 10] = [0x05, 0x08, 0x70, 0x00, 0x01, 0x00, 0x15, 0x28, 0x00, 0x18];
+    const GROUP_CIPHER_TEXT: [u8; 26] = [
+        0x1c, 0xc7, 0xfd, 0x92, 0x0f, 0x57, 0x73, 0x44, 0xeb, 0xfc, 0x13, 0x74, 0xba, 0xd6, 0x6d,
+        0x88, 0x15, 0x61, 0x6e, 0x60, 0x26, 0xdb, 0xd5, 0x92, 0x50, 0xe2,
+    ];
+    const GROUP_KEY: [u8; 16] = [
+        0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+        0x1f,
+    ];
+
+    #[test]
+    pub fn test_group_encrypt_decrypt() {
+        let mut main_buf = [0; 26];
+        let mut writebuf = WriteBuf::new(&mut main_buf);
+        writebuf.append(&GROUP_PLAIN_TEXT).unwrap();
+
+        encrypt_in_place(
+            0x01,
+            0x1234_5678,
+            0x0102_0304_0506_0708,
+            &GROUP_HDR,
+            &mut writebuf,
+            &GROUP_KEY,
+        )
+        .unwrap();
+        assert_eq!(writebuf.as_slice(), GROUP_CIPHER_TEXT);
+
+        let mut input_buf = [0; 42];
+        input_buf[..16].copy_from_slice(&GROUP_HDR);
+        input_buf[16..].copy_from_slice(&GROUP_CIPHER_TEXT);
+
+        let mut parsebuf = ParseBuf::new(&mut input_buf);
+        parsebuf.le_u64().unwrap();
+        parsebuf.le_u64().unwrap();
+
+        decrypt_in_place(
+            0x01,
+            0x1234_5678,
+            0x0102_0304_0506_0708,
+            &mut parsebuf,
+            &GROUP_KEY,
+        )
+        .unwrap();
+        assert_eq!(parsebuf.as_slice(), GROUP_PLAIN_TEXT);
+    }
+
+    #[test]
+    /// Both the header and the nonce are authenticated
+    pub fn test_decrypt_tampered() {
+        let mut input_buf = [0; 42];
+        input_buf[..16].copy_from_slice(&GROUP_HDR);
+        input_buf[16..].copy_from_slice(&GROUP_CIPHER_TEXT);
+        // Another session ID
+        input_buf[1] = 0x35;
+
+        let mut parsebuf = ParseBuf::new(&mut input_buf);
+        parsebuf.le_u64().unwrap();
+        parsebuf.le_u64().unwrap();
+
+        assert!(decrypt_in_place(
+            0x01,
+            0x1234_5678,
+            0x0102_0304_0506_0708,
+            &mut parsebuf,
+            &GROUP_KEY
+        )
+        .is_err());
+
+        let mut input_buf = [0; 42];
+        input_buf[..16].copy_from_slice(&GROUP_HDR);
+        input_buf[16..].copy_from_slice(&GROUP_CIPHER_TEXT);
+
+        let mut parsebuf = ParseBuf::new(&mut input_buf);
+        parsebuf.le_u64().unwrap();
+        parsebuf.le_u64().unwrap();
+
+        // Another message counter
+        assert!(decrypt_in_place(
+            0x01,
+            0x1234_5679,
+            0x0102_0304_0506_0708,
+            &mut parsebuf,
+            &GROUP_KEY
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,228 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! A ready-made runner of the Matter transport over the UDP sockets of Tokio
+//!
+//! The runner owns the UDP sockets of the device, feeds the packets it receives
+//! to the session and exchange layers, and sends the packets these produce.
+//! The MRP retransmissions and the subscription reports are driven by the timers
+//! of the transport itself, see [`Matter::run_piped`].
+
+use core::pin::pin;
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+use embassy_futures::select::{select, select3, Either};
+
+use log::{info, warn};
+
+use tokio::net::UdpSocket;
+
+use crate::{
+    data_model::objects::DataModelHandler, error::Error, utils::select::EitherUnwrap,
+    CommissioningData, Matter, MATTER_PORT,
+};
+
+use super::{
+    core::PacketBuffers,
+    network::Address,
+    packet::{MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+    pipe::{Chunk, Pipe},
+};
+
+/// The addresses the UDP sockets of the runner are bound to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BindAddr {
+    /// The IPv6 address, which Matter always requires
+    pub ipv6: SocketAddrV6,
+    /// The IPv4 address, if the device should also be reachable over IPv4
+    ///
+    /// On dual-stack hosts a socket bound to the unspecified IPv6 address already
+    /// receives the IPv4 traffic, in which case a separate IPv4 socket on the same
+    /// port cannot be bound.
+    pub ipv4: Option<SocketAddrV4>,
+}
+
+impl BindAddr {
+    /// All the IPv6 interfaces, on the given port
+    pub fn new(port: u16) -> Self {
+        Self {
+            ipv6: SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0),
+            ipv4: None,
+        }
+    }
+
+    pub fn with_ipv4(self, ipv4: SocketAddrV4) -> Self {
+        Self {
+            ipv4: Some(ipv4),
+            ..self
+        }
+    }
+}
+
+impl Default for BindAddr {
+    /// All the IPv6 interfaces, on the Matter port
+    fn default() -> Self {
+        Self::new(MATTER_PORT)
+    }
+}
+
+/// Everything the runner needs, so as to run the Matter stack of a device
+pub struct Stack<'a, H> {
+    pub matter: &'a Matter<'a>,
+    pub dev_comm: CommissioningData,
+    pub handler: H,
+}
+
+/// Run the Matter stack over UDP, until the transport fails
+pub async fn run<H>(stack: Stack<'_, H>, bind_addr: BindAddr) -> Result<(), Error>
+where
+    H: DataModelHandler,
+{
+    let udp = Sockets::bind(&bind_addr).await?;
+
+    let mut buffers = Box::new(PacketBuffers::new());
+    let mut tx_buf = vec![0; MAX_TX_BUF_SIZE];
+    let mut rx_buf = vec![0; MAX_RX_BUF_SIZE];
+
+    let tx_pipe = Pipe::new(&mut tx_buf);
+    let rx_pipe = Pipe::new(&mut rx_buf);
+
+    let tx_pipe = &tx_pipe;
+    let rx_pipe = &rx_pipe;
+    let udp = &udp;
+
+    let mut tx = pin!(async move {
+        loop {
+            {
+                let mut data = tx_pipe.data.lock().await;
+
+                if let Some(chunk) = data.chunk {
+                    if let Some(addr) = chunk.addr.udp() {
+                        udp.send(addr, &data.buf[chunk.start..chunk.end]).await?;
+                    } else {
+                        warn!(
+                            "Dropping a message to {}, which is not reachable over UDP",
+                            chunk.addr
+                        );
+                    }
+                    data.chunk = None;
+                    tx_pipe.data_consumed_notification.signal(());
+                }
+            }
+
+            tx_pipe.data_supplied_notification.wait().await;
+        }
+    });
+
+    let mut rx = pin!(async move {
+        loop {
+            {
+                let mut data = rx_pipe.data.lock().await;
+
+                if data.chunk.is_none() {
+                    let (len, addr) = udp.recv(data.buf).await?;
+
+                    data.chunk = Some(Chunk {
+                        start: 0,
+                        end: len,
+                        addr: Address::Udp(addr),
+                    });
+                    rx_pipe.data_supplied_notification.signal(());
+                }
+            }
+
+            rx_pipe.data_consumed_notification.wait().await;
+        }
+    });
+
+    let mut run = pin!(stack.matter.run_piped(
+        &mut buffers,
+        tx_pipe,
+        rx_pipe,
+        stack.dev_comm,
+        &stack.handler
+    ));
+
+    select3(&mut tx, &mut rx, &mut run).await.unwrap()
+}
+
+struct Sockets {
+    ipv6: UdpSocket,
+    ipv4: Option<UdpSocket>,
+}
+
+impl Sockets {
+    async fn bind(addr: &BindAddr) -> Result<Self, Error> {
+        let ipv6 = UdpSocket::bind(addr.ipv6).await?;
+        info!("Listening on {}", addr.ipv6);
+
+        let ipv4 = if let Some(ipv4) = addr.ipv4 {
+            let socket = UdpSocket::bind(ipv4).await?;
+            info!("Listening on {}", ipv4);
+
+            Some(socket)
+        } else {
+            None
+        };
+
+        Ok(Self { ipv6, ipv4 })
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr), Error> {
+        let ipv4 = match &self.ipv4 {
+            Some(ipv4) => ipv4,
+            None => return Ok(self.ipv6.recv_from(buf).await?),
+        };
+
+        loop {
+            let socket = match select(self.ipv6.readable(), ipv4.readable()).await {
+                Either::First(ready) => ready.map(|_| &self.ipv6)?,
+                Either::Second(ready) => ready.map(|_| ipv4)?,
+            };
+
+            match socket.try_recv_from(buf) {
+                Ok(received) => break Ok(received),
+                // A spurious wakeup, wait for the sockets to be readable again
+                Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
+                Err(err) => break Err(err.into()),
+            }
+        }
+    }
+
+    async fn send(&self, addr: SocketAddr, buf: &[u8]) -> Result<usize, Error> {
+        let len = match (addr, &self.ipv4) {
+            (SocketAddr::V4(_), Some(ipv4)) => ipv4.send_to(buf, addr).await,
+            // Without an IPv4 socket, IPv4 peers can only be reached through a dual-stack IPv6 one
+            (SocketAddr::V4(v4), None) => {
+                self.ipv6
+                    .send_to(
+                        buf,
+                        SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+                    )
+                    .await
+            }
+            (SocketAddr::V6(_), _) => self.ipv6.send_to(buf, addr).await,
+        };
+
+        Ok(len?)
+    }
+}
@@ -22,11 +22,11 @@ use core::fmt;
 use core::ops::{Deref, DerefMut};
 use core::time::Duration;
 
-use crate::{error::*, transport::plain_hdr};
+use crate::{error::*, fabric, transport::plain_hdr};
 use log::info;
 
 use super::dedup::RxCtrState;
-use super::{network::Address, packet::Packet};
+use super::{exchange::SessionId, mrp::MrpParams, network::Address, packet::Packet};
 
 pub const MAX_CAT_IDS_PER_NOC: usize = 3;
 pub type NocCatIds = [u32; MAX_CAT_IDS_PER_NOC];
@@ -48,11 +48,27 @@ impl CaseDetails {
     }
 }
 
+/// The group a group session receives messages for
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GroupDetails {
+    pub fab_idx: u8,
+    pub group_id: u16,
+}
+
+impl GroupDetails {
+    pub fn new(fab_idx: u8, group_id: u16) -> Self {
+        Self { fab_idx, group_id }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Default)]
 pub enum SessionMode {
     // The Case session will capture the local fabric index
     Case(CaseDetails),
     Pase,
+    // A session of a peer sending group messages, which are encrypted with the
+    // operational key of the group
+    Group(GroupDetails),
     #[default]
     PlainText,
 }
@@ -73,6 +89,14 @@ pub struct Session {
     mode: SessionMode,
     data: Option<NocData>,
     last_use: Duration,
+    // When the peer sent us its last message, which tells whether it is active
+    last_rx: Duration,
+    // The MRP parameters of the peer, if it announced them
+    mrp_params: Option<MrpParams>,
+    // Whether the headers of the messages sent on this session are obfuscated
+    privacy: bool,
+    // The ID of the next exchange initiated by us on this session
+    exch_ctr: u16,
 }
 
 #[derive(Debug)]
@@ -80,6 +104,7 @@ pub struct CloneData {
     pub dec_key: [u8; MATTER_AES128_KEY_SIZE],
     pub enc_key: [u8; MATTER_AES128_KEY_SIZE],
     pub att_challenge: [u8; MATTER_AES128_KEY_SIZE],
+    pub mrp_params: Option<MrpParams>,
     local_sess_id: u16,
     peer_sess_id: u16,
     local_nodeid: u64,
@@ -101,6 +126,7 @@ impl CloneData {
             dec_key: [0; MATTER_AES128_KEY_SIZE],
             enc_key: [0; MATTER_AES128_KEY_SIZE],
             att_challenge: [0; MATTER_AES128_KEY_SIZE],
+            mrp_params: None,
             local_nodeid,
             peer_nodeid,
             peer_addr,
@@ -111,7 +137,7 @@ impl CloneData {
     }
 }
 
-const MATTER_MSG_CTR_RANGE: u32 = 0x0fffffff;
+pub(crate) const MATTER_MSG_CTR_RANGE: u32 = 0x0fffffff;
 
 impl Session {
     pub fn new(peer_addr: Address, peer_nodeid: Option<u64>, epoch: Epoch, rand: Rand) -> Self {
@@ -129,6 +155,10 @@ impl Session {
             mode: SessionMode::PlainText,
             data: None,
             last_use: epoch(),
+            last_rx: epoch(),
+            mrp_params: None,
+            privacy: false,
+            exch_ctr: Self::rand_exch_ctr(rand),
         }
     }
 
@@ -148,6 +178,10 @@ impl Session {
             mode: clone_from.mode.clone(),
             data: None,
             last_use: epoch(),
+            last_rx: epoch(),
+            mrp_params: clone_from.mrp_params,
+            privacy: false,
+            exch_ctr: Self::rand_exch_ctr(rand),
         }
     }
 
@@ -186,11 +220,27 @@ impl Session {
 
     pub fn is_encrypted(&self) -> bool {
         match self.mode {
-            SessionMode::Case(_) | SessionMode::Pase => true,
+            SessionMode::Case(_) | SessionMode::Pase | SessionMode::Group(_) => true,
             SessionMode::PlainText => false,
         }
     }
 
+    pub fn is_group(&self) -> bool {
+        matches!(self.mode, SessionMode::Group(_))
+    }
+
+    pub fn get_group(&self) -> Option<&GroupDetails> {
+        match &self.mode {
+            SessionMode::Group(group) => Some(group),
+            _ => None,
+        }
+    }
+
+    /// Our node ID on the fabric of the session, or 0 for a session without a fabric
+    pub fn get_local_node_id(&self) -> u64 {
+        self.local_nodeid
+    }
+
     pub fn get_peer_node_id(&self) -> Option<u64> {
         self.peer_nodeid
     }
@@ -205,6 +255,7 @@ impl Session {
     pub fn get_local_fabric_idx(&self) -> Option<u8> {
         match &self.mode {
             SessionMode::Case(a) => Some(a.fab_idx),
+            SessionMode::Group(g) => Some(g.fab_idx),
             _ => None,
         }
     }
@@ -221,27 +272,69 @@ impl Session {
 
     pub fn get_dec_key(&self) -> Option<&[u8]> {
         match self.mode {
-            SessionMode::Case(_) | SessionMode::Pase => Some(&self.dec_key),
+            SessionMode::Case(_) | SessionMode::Pase | SessionMode::Group(_) => Some(&self.dec_key),
             SessionMode::PlainText => None,
         }
     }
 
     pub fn get_enc_key(&self) -> Option<&[u8]> {
         match self.mode {
-            SessionMode::Case(_) | SessionMode::Pase => Some(&self.enc_key),
+            SessionMode::Case(_) | SessionMode::Pase | SessionMode::Group(_) => Some(&self.enc_key),
             SessionMode::PlainText => None,
         }
     }
 
+    /// Obfuscate the headers of the messages sent on this session
+    ///
+    /// This is also enabled once the peer sends a message with an obfuscated header.
+    pub fn set_privacy(&mut self, privacy: bool) {
+        self.privacy = privacy;
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.privacy
+    }
+
+    pub fn get_dec_privacy_key(&self) -> Result<Option<[u8; MATTER_AES128_KEY_SIZE]>, Error> {
+        Self::privacy_key(self.get_dec_key())
+    }
+
+    pub fn get_enc_privacy_key(&self) -> Result<Option<[u8; MATTER_AES128_KEY_SIZE]>, Error> {
+        Self::privacy_key(self.get_enc_key())
+    }
+
+    fn privacy_key(key: Option<&[u8]>) -> Result<Option<[u8; MATTER_AES128_KEY_SIZE]>, Error> {
+        if let Some(key) = key {
+            let mut privacy_key = [0; MATTER_AES128_KEY_SIZE];
+            plain_hdr::privacy_key(key, &mut privacy_key)?;
+
+            Ok(Some(privacy_key))
+        } else {
+            Ok(None)
+        }
+    }
+
     pub fn get_att_challenge(&self) -> &[u8] {
         &self.att_challenge
     }
 
     pub fn recv(&mut self, epoch: Epoch, rx: &mut Packet) -> Result<(), Error> {
         self.last_use = epoch();
+        self.last_rx = self.last_use;
+        if rx.plain.is_private() {
+            self.privacy = true;
+        }
+
         rx.proto_decode(self.peer_nodeid.unwrap_or_default(), self.get_dec_key())
     }
 
+    /// The base retransmission interval towards the peer as per its MRP parameters,
+    /// if it announced them for this session
+    pub fn get_retrans_interval(&self, epoch: Epoch) -> Option<Duration> {
+        self.mrp_params
+            .map(|params| params.retrans_interval(epoch().saturating_sub(self.last_rx)))
+    }
+
     pub fn pre_send(&mut self, tx: &mut Packet) -> Result<(), Error> {
         tx.plain.sess_id = self.get_peer_sess_id();
         tx.plain.ctr = self.get_msg_ctr();
@@ -254,15 +347,52 @@ impl Session {
     fn send(&mut self, epoch: Epoch, tx: &mut Packet) -> Result<(), Error> {
         self.last_use = epoch();
 
+        let privacy_key = if self.privacy {
+            self.get_enc_privacy_key()?
+        } else {
+            None
+        };
+
         tx.proto_encode(
             self.peer_addr,
             self.peer_nodeid,
             self.local_nodeid,
             self.mode == SessionMode::PlainText,
             self.get_enc_key(),
+            privacy_key.as_ref().map(|key| key.as_slice()),
         )
     }
 
+    /// The ID of a new exchange initiated by us on this session
+    pub fn get_next_exch_id(&mut self) -> u16 {
+        let exch_id = self.exch_ctr;
+        self.exch_ctr = self.exch_ctr.wrapping_add(1);
+        exch_id
+    }
+
+    /// The session ID of the exchanges on this session, as loaded from the messages of the peer
+    ///
+    /// The peer only sends its node ID on unencrypted sessions.
+    pub fn get_session_id(&self) -> SessionId {
+        SessionId {
+            id: self.local_sess_id,
+            peer_addr: self.peer_addr,
+            peer_nodeid: if self.is_encrypted() {
+                None
+            } else {
+                self.peer_nodeid
+            },
+            is_encrypted: self.is_encrypted(),
+            is_group: self.is_group(),
+        }
+    }
+
+    fn rand_exch_ctr(rand: Rand) -> u16 {
+        let mut buf = [0; 2];
+        rand(&mut buf);
+        u16::from_be_bytes(buf)
+    }
+
     fn rand_msg_ctr(rand: Rand) -> u32 {
         let mut buf = [0; 4];
         rand(&mut buf);
@@ -288,11 +418,40 @@ impl fmt::Display for Session {
 
 pub const MAX_SESSIONS: usize = 16;
 
+/// How long a session may go unused before it is removed. Longer than the
+/// longest max interval of a subscription (60 minutes), so that the reports
+/// keep the sessions of their subscriptions in use.
+pub const MAX_SESSION_IDLE_TIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// The number of CASE sessions each fabric is guaranteed to be able to establish,
+/// as all fabrics share the same session table
+pub const CASE_SESSIONS_PER_FABRIC: usize = MAX_SESSIONS / fabric::MAX_SUPPORTED_FABRICS;
+
+/// Why a session got removed from the session manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalReason {
+    /// The session was not used for longer than the allowed idle time
+    IdleTimeout,
+    /// The session table is full, and the session was the least recently used one
+    LruEviction,
+    /// The session was closed on purpose
+    ExplicitClose,
+    /// The fabric of the session was removed
+    FabricRemoval,
+}
+
+/// Called synchronously whenever a session is removed, so that the application
+/// can release any resources it keeps for the session
+pub type SessionRemovedCallback = fn(&SessionId, RemovalReason);
+
 pub struct SessionMgr {
     next_sess_id: u16,
     sessions: heapless::Vec<Option<Session>, MAX_SESSIONS>,
     epoch: Epoch,
     rand: Rand,
+    on_session_removed: Option<SessionRemovedCallback>,
+    /// The local IDs of the sessions removed since the last `take_removed`
+    removed: heapless::Vec<u16, MAX_SESSIONS>,
 }
 
 impl SessionMgr {
@@ -303,12 +462,25 @@ impl SessionMgr {
             next_sess_id: 1,
             epoch,
             rand,
+            on_session_removed: None,
+            removed: heapless::Vec::new(),
         }
     }
 
+    pub fn set_on_session_removed(&mut self, callback: SessionRemovedCallback) {
+        self.on_session_removed = Some(callback);
+    }
+
     pub fn reset(&mut self) {
         self.sessions.clear();
         self.next_sess_id = 1;
+        self.removed.clear();
+    }
+
+    /// Return the local IDs of the sessions removed since the last call, for whatever
+    /// state was kept along with these sessions (e.g. subscriptions) to go away too
+    pub fn take_removed(&mut self) -> heapless::Vec<u16, MAX_SESSIONS> {
+        core::mem::take(&mut self.removed)
     }
 
     pub fn mut_by_index(&mut self, index: usize) -> Option<&mut Session> {
@@ -368,7 +540,64 @@ impl SessionMgr {
     /// This assumes that the higher layer has taken care of doing anything required
     /// as per the spec before the session is erased
     pub fn remove(&mut self, idx: usize) {
-        self.sessions[idx] = None;
+        self.remove_with_reason(idx, RemovalReason::ExplicitClose);
+    }
+
+    /// Remove all sessions which were not used for longer than `max_idle`
+    pub fn remove_idle(&mut self, max_idle: Duration) {
+        let now = (self.epoch)();
+
+        for idx in 0..self.sessions.len() {
+            if let Some(session) = &self.sessions[idx] {
+                if session.last_use + max_idle < now {
+                    self.remove_with_reason(idx, RemovalReason::IdleTimeout);
+                }
+            }
+        }
+    }
+
+    /// Remove all CASE sessions of the provided fabric, except for the one with
+    /// local session ID `except_sess_id`, if any
+    pub fn remove_for_fabric(&mut self, fab_idx: u8, except_sess_id: Option<u16>) {
+        for idx in 0..self.sessions.len() {
+            if let Some(session) = &self.sessions[idx] {
+                if session.get_local_fabric_idx() == Some(fab_idx)
+                    && Some(session.local_sess_id) != except_sess_id
+                {
+                    self.remove_with_reason(idx, RemovalReason::FabricRemoval);
+                }
+            }
+        }
+    }
+
+    /// Drop the NOC data (pending CSR and trusted root) of all sessions
+    pub fn clear_noc_data(&mut self) {
+        for session in self.sessions.iter_mut().flatten() {
+            session.clear_noc_data();
+        }
+    }
+
+    fn remove_with_reason(&mut self, idx: usize, reason: RemovalReason) {
+        if let Some(session) = self.sessions[idx].take() {
+            info!("Removing session {} because of {:?}", session, reason);
+
+            if self.removed.is_full() {
+                self.removed.remove(0);
+            }
+            self.removed.push(session.local_sess_id).unwrap();
+
+            if let Some(on_session_removed) = self.on_session_removed {
+                let id = SessionId {
+                    id: session.local_sess_id,
+                    peer_addr: session.peer_addr,
+                    peer_nodeid: session.peer_nodeid,
+                    is_encrypted: session.is_encrypted(),
+                    is_group: session.is_group(),
+                };
+
+                on_session_removed(&id, reason);
+            }
+        }
     }
 
     /// We could have returned a SessionHandle here. But the borrow checker doesn't support
@@ -386,7 +615,11 @@ impl SessionMgr {
 
             Ok(self.sessions.len() - 1)
         } else {
-            Err(ErrorCode::NoSpace.into())
+            let index = self.get_lru();
+            self.remove_with_reason(index, RemovalReason::LruEviction);
+
+            self.sessions[index] = Some(session);
+            Ok(index)
         }
     }
 
@@ -401,6 +634,7 @@ impl SessionMgr {
         peer_addr: Address,
         peer_nodeid: Option<u64>,
         is_encrypted: bool,
+        is_group: bool,
     ) -> Option<usize> {
         self.sessions.iter().position(|x| {
             if let Some(x) = x {
@@ -412,6 +646,7 @@ impl SessionMgr {
                 x.local_sess_id == sess_id
                     && x.peer_addr == peer_addr
                     && x.is_encrypted() == is_encrypted
+                    && x.is_group() == is_group
                     && nodeid_matches
             } else {
                 false
@@ -427,6 +662,18 @@ impl SessionMgr {
         Some(self.get_session_handle(index))
     }
 
+    /// The privacy key to deobfuscate the header of a message received on the
+    /// encrypted unicast session with the given local session ID
+    pub fn get_dec_privacy_key(&self, sess_id: u16) -> Result<[u8; MATTER_AES128_KEY_SIZE], Error> {
+        self.sessions
+            .iter()
+            .flatten()
+            .find(|s| s.local_sess_id == sess_id && s.is_encrypted() && !s.is_group())
+            .ok_or(ErrorCode::NoSession)?
+            .get_dec_privacy_key()?
+            .ok_or_else(|| ErrorCode::NoSession.into())
+    }
+
     pub fn get_or_add(
         &mut self,
         sess_id: u16,
@@ -434,7 +681,7 @@ impl SessionMgr {
         peer_nodeid: Option<u64>,
         is_encrypted: bool,
     ) -> Result<usize, Error> {
-        if let Some(index) = self.get(sess_id, peer_addr, peer_nodeid, is_encrypted) {
+        if let Some(index) = self.get(sess_id, peer_addr, peer_nodeid, is_encrypted, false) {
             Ok(index)
         } else if sess_id == 0 && !is_encrypted {
             // We must create a new session for this case
@@ -466,6 +713,46 @@ impl SessionMgr {
         }
     }
 
+    /// Same as `post_recv`, but for group messages, which are decrypted with the
+    /// provided operational key of the group
+    pub fn post_recv_group(
+        &mut self,
+        rx: &Packet,
+        group: GroupDetails,
+        op_key: &[u8],
+    ) -> Result<usize, Error> {
+        let peer_nodeid = rx.plain.get_src_u64().ok_or(ErrorCode::Invalid)?;
+
+        if let Some(sess_index) = self.get(rx.plain.sess_id, rx.peer, Some(peer_nodeid), true, true)
+        {
+            let session = self.sessions[sess_index].as_mut().unwrap();
+
+            // Different groups might share a group session ID
+            session.dec_key.copy_from_slice(op_key);
+            session.mode = SessionMode::Group(group);
+
+            if session.rx_ctr_state.recv(rx.plain.ctr, true) {
+                info!("Dropping duplicate group packet");
+                Err(ErrorCode::Duplicate)?;
+            }
+
+            Ok(sess_index)
+        } else {
+            info!("Creating new group session");
+
+            let mut session = Session::new(rx.peer, Some(peer_nodeid), self.epoch, self.rand);
+            session.local_sess_id = rx.plain.sess_id;
+            session.peer_sess_id = rx.plain.sess_id;
+            session.dec_key.copy_from_slice(op_key);
+            session.enc_key.copy_from_slice(op_key);
+            // Trust the message counter of the first message, marking it as received
+            session.rx_ctr_state = RxCtrState::new_group(rx.plain.ctr);
+            session.mode = SessionMode::Group(group);
+
+            self.add_session(session)
+        }
+    }
+
     pub fn send(&mut self, sess_idx: usize, tx: &mut Packet) -> Result<(), Error> {
         self.sessions[sess_idx]
             .as_mut()
@@ -533,13 +820,28 @@ impl<'a> DerefMut for SessionHandle<'a> {
 
 #[cfg(test)]
 mod tests {
+    use core::{
+        sync::atomic::{AtomicU16, AtomicU8, Ordering},
+        time::Duration,
+    };
 
     use crate::{
-        transport::network::Address,
+        test_support::mock_epoch,
+        transport::{exchange::SessionId, mrp::MrpParams, network::Address},
         utils::{epoch::dummy_epoch, rand::dummy_rand},
     };
 
-    use super::SessionMgr;
+    use super::{CloneData, RemovalReason, Session, SessionMgr, SessionMode, MAX_SESSIONS};
+
+    mock_epoch!(MOCK_NOW_MS, mock_epoch);
+
+    static REMOVED_SESS_ID: AtomicU16 = AtomicU16::new(0);
+    static REMOVAL_REASON: AtomicU8 = AtomicU8::new(u8::MAX);
+
+    fn on_session_removed(id: &SessionId, reason: RemovalReason) {
+        REMOVED_SESS_ID.store(id.id, Ordering::SeqCst);
+        REMOVAL_REASON.store(reason as u8, Ordering::SeqCst);
+    }
 
     #[test]
     fn test_next_sess_id_doesnt_reuse() {
@@ -567,4 +869,78 @@ mod tests {
         assert_eq!(sm.get_next_sess_id(), 65535);
         assert_eq!(sm.get_next_sess_id(), 2);
     }
+
+    #[test]
+    fn test_lru_eviction_callback() {
+        let mut sm = SessionMgr::new(dummy_epoch, dummy_rand);
+        sm.set_on_session_removed(on_session_removed);
+
+        for sess_id in 1..=MAX_SESSIONS as u16 {
+            let sess_idx = sm.add(Address::default(), None).unwrap();
+            sm.get_session_handle(sess_idx).set_local_sess_id(sess_id);
+        }
+
+        assert_eq!(REMOVAL_REASON.load(Ordering::SeqCst), u8::MAX);
+
+        // The table is full, so adding one more session evicts the least recently used one
+        let sess_idx = sm.add(Address::default(), None).unwrap();
+        assert_eq!(sess_idx, 0);
+        assert_eq!(REMOVED_SESS_ID.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            REMOVAL_REASON.load(Ordering::SeqCst),
+            RemovalReason::LruEviction as u8
+        );
+    }
+
+    #[test]
+    /// Evicted and removed sessions are reported once, to drop what was kept along with them
+    fn test_removed_sessions_taken() {
+        let mut sm = SessionMgr::new(dummy_epoch, dummy_rand);
+
+        for sess_id in 1..=MAX_SESSIONS as u16 {
+            let sess_idx = sm.add(Address::default(), None).unwrap();
+            sm.get_session_handle(sess_idx).set_local_sess_id(sess_id);
+        }
+        assert!(sm.take_removed().is_empty());
+
+        let sess_idx = sm.add(Address::default(), None).unwrap();
+        sm.get_session_handle(sess_idx).set_local_sess_id(100);
+
+        // Session 2 is right after the evicted one
+        sm.remove(1);
+
+        assert_eq!(sm.take_removed().as_slice(), &[1, 2]);
+        assert!(sm.take_removed().is_empty());
+    }
+
+    #[test]
+    /// The retransmissions follow the MRP parameters of the peer, as per whether it is active
+    fn test_retrans_interval() {
+        let params = MrpParams {
+            idle_interval: Duration::from_millis(5000),
+            active_interval: Duration::from_millis(2000),
+            active_threshold: Duration::from_millis(4000),
+        };
+
+        let mut clone_data = CloneData::new(1, 2, 3, 4, Address::default(), SessionMode::Pase);
+        assert_eq!(
+            Session::clone(&clone_data, mock_epoch, dummy_rand).get_retrans_interval(mock_epoch),
+            None
+        );
+
+        clone_data.mrp_params = Some(params);
+        let session = Session::clone(&clone_data, mock_epoch, dummy_rand);
+
+        // The peer just established the session
+        assert_eq!(
+            session.get_retrans_interval(mock_epoch),
+            Some(params.active_interval)
+        );
+
+        MOCK_NOW_MS.fetch_add(4000, Ordering::SeqCst);
+        assert_eq!(
+            session.get_retrans_interval(mock_epoch),
+            Some(params.idle_interval)
+        );
+    }
 }
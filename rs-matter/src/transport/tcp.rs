@@ -0,0 +1,443 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+/// Over TCP, each Matter message is preceded by its length, as a 4-byte little-endian number
+pub const TCP_FRAME_LEN_SIZE: usize = 4;
+
+#[cfg(all(feature = "std", not(feature = "embassy-net")))]
+pub use self::async_io::*;
+
+#[cfg(feature = "embassy-net")]
+pub use self::embassy_net::*;
+
+#[cfg(feature = "std")]
+pub mod async_io {
+    use crate::error::*;
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener as StdTcpListener, TcpStream};
+
+    use async_io::Async;
+
+    use log::{debug, info, warn};
+
+    use crate::transport::network::std_stack::{NetworkStack, NetworkStackDriver};
+    use crate::transport::network::SocketAddr;
+
+    use super::TCP_FRAME_LEN_SIZE;
+
+    pub struct TcpBuffers(());
+
+    impl TcpBuffers {
+        pub const fn new() -> Self {
+            Self(())
+        }
+    }
+
+    pub struct TcpListener<'a, D>(Async<StdTcpListener>, &'a NetworkStack<D>)
+    where
+        D: NetworkStackDriver + 'static;
+
+    impl<'a, D> TcpListener<'a, D>
+    where
+        D: NetworkStackDriver + 'a + 'static,
+    {
+        pub async fn new(
+            stack: &'a NetworkStack<D>,
+            addr: SocketAddr,
+            _buffers: &'a mut TcpBuffers,
+        ) -> Result<TcpListener<'a, D>, Error> {
+            let listener = TcpListener(Async::<StdTcpListener>::bind(addr)?, stack);
+
+            info!("Listening on {:?} (TCP)", addr);
+
+            Ok(listener)
+        }
+
+        pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+            Ok(self.0.get_ref().local_addr()?)
+        }
+
+        /// Wait for the next peer to connect
+        pub async fn accept(&mut self) -> Result<TcpConnection, Error> {
+            let (stream, addr) = self.0.accept().await.map_err(|e| {
+                warn!("Error on the network: {:?}", e);
+                ErrorCode::Network
+            })?;
+
+            info!("Accepted TCP connection from {:?}", addr);
+
+            Ok(TcpConnection(stream, addr))
+        }
+    }
+
+    /// A TCP connection carrying length-prefixed Matter messages
+    pub struct TcpConnection(Async<TcpStream>, SocketAddr);
+
+    impl TcpConnection {
+        pub async fn connect(addr: SocketAddr) -> Result<Self, Error> {
+            let stream = Async::<TcpStream>::connect(addr).await.map_err(|e| {
+                warn!("Error on the network: {:?}", e);
+                ErrorCode::Network
+            })?;
+
+            Ok(Self(stream, addr))
+        }
+
+        pub fn peer_addr(&self) -> SocketAddr {
+            self.1
+        }
+
+        /// Split the connection, so that messages can be received while others are being sent
+        pub fn split(&mut self) -> (TcpReader<'_>, TcpWriter<'_>) {
+            (TcpReader(&*self), TcpWriter(&*self))
+        }
+
+        /// Receive the next message into the provided buffer.
+        ///
+        /// Messages which do not fit in the buffer are rejected, as we have no place to put them.
+        pub async fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, SocketAddr), Error> {
+            let mut len_buf = [0; TCP_FRAME_LEN_SIZE];
+            self.read_exact(&mut len_buf).await?;
+
+            let len = u32::from_le_bytes(len_buf) as usize;
+            if len > in_buf.len() {
+                warn!(
+                    "Got a TCP message of {} bytes, which does not fit in {} bytes",
+                    len,
+                    in_buf.len()
+                );
+                Err(ErrorCode::NoSpace)?;
+            }
+
+            self.read_exact(&mut in_buf[..len]).await?;
+
+            debug!("Got packet {:?} from addr {:?}", &in_buf[..len], self.1);
+
+            Ok((len, self.1))
+        }
+
+        /// Send a message, waiting for the peer to make room for it if necessary
+        pub async fn send(&self, out_buf: &[u8]) -> Result<usize, Error> {
+            let len: u32 = out_buf.len().try_into().map_err(|_| ErrorCode::NoSpace)?;
+
+            self.write_all(&len.to_le_bytes()).await?;
+            self.write_all(out_buf).await?;
+
+            debug!(
+                "Send packet {:?} ({}) to addr {:?}",
+                out_buf,
+                out_buf.len(),
+                self.1
+            );
+
+            Ok(out_buf.len())
+        }
+
+        async fn read_exact(&self, buf: &mut [u8]) -> Result<(), Error> {
+            let mut offset = 0;
+
+            while offset < buf.len() {
+                let len = self
+                    .0
+                    .read_with(|mut stream| stream.read(&mut buf[offset..]))
+                    .await
+                    .map_err(|e| {
+                        warn!("Error on the network: {:?}", e);
+                        ErrorCode::Network
+                    })?;
+
+                if len == 0 {
+                    warn!("TCP connection to {:?} closed", self.1);
+                    Err(ErrorCode::Network)?;
+                }
+
+                offset += len;
+            }
+
+            Ok(())
+        }
+
+        async fn write_all(&self, buf: &[u8]) -> Result<(), Error> {
+            let mut offset = 0;
+
+            while offset < buf.len() {
+                offset += self
+                    .0
+                    .write_with(|mut stream| stream.write(&buf[offset..]))
+                    .await
+                    .map_err(|e| {
+                        warn!("Error on the network: {:?}", e);
+                        ErrorCode::Network
+                    })?;
+            }
+
+            Ok(())
+        }
+    }
+
+    pub struct TcpReader<'a>(&'a TcpConnection);
+
+    impl<'a> TcpReader<'a> {
+        pub async fn recv(&mut self, in_buf: &mut [u8]) -> Result<(usize, SocketAddr), Error> {
+            self.0.recv(in_buf).await
+        }
+    }
+
+    pub struct TcpWriter<'a>(&'a TcpConnection);
+
+    impl<'a> TcpWriter<'a> {
+        pub async fn send(&mut self, out_buf: &[u8]) -> Result<usize, Error> {
+            self.0.send(out_buf).await
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::transport::network::{std_stack::NetworkStack, IpAddr, Ipv4Addr, SocketAddr};
+
+        use super::{TcpBuffers, TcpConnection, TcpListener};
+
+        #[test]
+        fn test_framed_roundtrip() {
+            // Larger than what fits in a single UDP datagram on a typical MTU
+            let mut msg = [0; 4000];
+            for (index, byte) in msg.iter_mut().enumerate() {
+                *byte = index as u8;
+            }
+
+            let stack = NetworkStack::new();
+            let mut buffers = TcpBuffers::new();
+
+            async_io::block_on(async {
+                let mut listener = TcpListener::new(
+                    &stack,
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+                    &mut buffers,
+                )
+                .await
+                .unwrap();
+                let addr = listener.local_addr().unwrap();
+
+                let (server, client) =
+                    embassy_futures::join::join(listener.accept(), TcpConnection::connect(addr))
+                        .await;
+                let server = server.unwrap();
+                let client = client.unwrap();
+
+                let (sent, received) = embassy_futures::join::join(client.send(&msg), async {
+                    let mut buf = [0; 4096];
+                    let (len, _) = server.recv(&mut buf).await.unwrap();
+
+                    buf[..len] == msg[..]
+                })
+                .await;
+
+                assert_eq!(sent.unwrap(), msg.len());
+                assert!(received);
+
+                // Messages which do not fit in the buffer are rejected
+                let (_, result) = embassy_futures::join::join(client.send(&msg), async {
+                    let mut buf = [0; 100];
+                    server.recv(&mut buf).await.map(|_| ())
+                })
+                .await;
+
+                assert!(result.is_err());
+            });
+        }
+    }
+}
+
+#[cfg(feature = "embassy-net")]
+pub mod embassy_net {
+    use core::mem::MaybeUninit;
+
+    use embassy_net::tcp::{self, TcpSocket};
+
+    use crate::error::*;
+
+    use log::{debug, info, warn};
+
+    use crate::transport::network::embassy_net_stack::{NetworkStack, NetworkStackDriver};
+    use crate::transport::network::SocketAddr;
+    use crate::transport::udp::embassy_net::to_socket_addr;
+
+    use super::TCP_FRAME_LEN_SIZE;
+
+    const RX_BUF_SIZE: usize = 4096;
+    const TX_BUF_SIZE: usize = 4096;
+
+    pub struct TcpBuffers {
+        rx_buffer: MaybeUninit<[u8; RX_BUF_SIZE]>,
+        tx_buffer: MaybeUninit<[u8; TX_BUF_SIZE]>,
+    }
+
+    impl TcpBuffers {
+        pub const fn new() -> Self {
+            Self {
+                rx_buffer: MaybeUninit::uninit(),
+                tx_buffer: MaybeUninit::uninit(),
+            }
+        }
+    }
+
+    /// A listener serving one TCP connection at a time, as the socket of the
+    /// embassy-net stack becomes the connection once a peer connects to it
+    pub struct TcpListener<'a, D>(TcpSocket<'a>, u16, &'a NetworkStack<D>)
+    where
+        D: NetworkStackDriver + 'static;
+
+    impl<'a, D> TcpListener<'a, D>
+    where
+        D: NetworkStackDriver + 'a + 'static,
+    {
+        pub async fn new(
+            stack: &'a NetworkStack<D>,
+            addr: SocketAddr,
+            buffers: &'a mut TcpBuffers,
+        ) -> Result<TcpListener<'a, D>, Error> {
+            let socket = TcpSocket::new(
+                stack,
+                unsafe { buffers.rx_buffer.assume_init_mut() },
+                unsafe { buffers.tx_buffer.assume_init_mut() },
+            );
+
+            info!("Listening on {:?} (TCP)", addr);
+
+            Ok(TcpListener(socket, addr.port(), stack))
+        }
+
+        /// Wait for the next peer to connect, dropping the connection of the previous one
+        pub async fn accept(&mut self) -> Result<TcpConnection<'_, 'a>, Error> {
+            self.0.abort();
+
+            self.0.accept(self.1).await.map_err(|e| {
+                warn!("Error on the network: {:?}", e);
+                ErrorCode::Network
+            })?;
+
+            let addr = self
+                .0
+                .remote_endpoint()
+                .map(to_socket_addr)
+                .ok_or(ErrorCode::Network)?;
+
+            info!("Accepted TCP connection from {:?}", addr);
+
+            Ok(TcpConnection(&mut self.0, addr))
+        }
+    }
+
+    /// A TCP connection carrying length-prefixed Matter messages
+    pub struct TcpConnection<'s, 'a>(&'s mut TcpSocket<'a>, SocketAddr);
+
+    impl<'s, 'a> TcpConnection<'s, 'a> {
+        pub fn peer_addr(&self) -> SocketAddr {
+            self.1
+        }
+
+        /// Split the connection, so that messages can be received while others are being sent
+        pub fn split(&mut self) -> (TcpReader<'_>, TcpWriter<'_>) {
+            let (reader, writer) = self.0.split();
+
+            (TcpReader(reader, self.1), TcpWriter(writer, self.1))
+        }
+    }
+
+    pub struct TcpReader<'a>(tcp::TcpReader<'a>, SocketAddr);
+
+    impl<'a> TcpReader<'a> {
+        /// Receive the next message into the provided buffer.
+        ///
+        /// Messages which do not fit in the buffer are rejected, as we have no place to put them.
+        pub async fn recv(&mut self, in_buf: &mut [u8]) -> Result<(usize, SocketAddr), Error> {
+            let mut len_buf = [0; TCP_FRAME_LEN_SIZE];
+            self.read_exact(&mut len_buf).await?;
+
+            let len = u32::from_le_bytes(len_buf) as usize;
+            if len > in_buf.len() {
+                warn!(
+                    "Got a TCP message of {} bytes, which does not fit in {} bytes",
+                    len,
+                    in_buf.len()
+                );
+                Err(ErrorCode::NoSpace)?;
+            }
+
+            self.read_exact(&mut in_buf[..len]).await?;
+
+            debug!("Got packet {:?} from addr {:?}", &in_buf[..len], self.1);
+
+            Ok((len, self.1))
+        }
+
+        async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+            let mut offset = 0;
+
+            while offset < buf.len() {
+                let len = self.0.read(&mut buf[offset..]).await.map_err(|e| {
+                    warn!("Error on the network: {:?}", e);
+                    ErrorCode::Network
+                })?;
+
+                if len == 0 {
+                    warn!("TCP connection to {:?} closed", self.1);
+                    Err(ErrorCode::Network)?;
+                }
+
+                offset += len;
+            }
+
+            Ok(())
+        }
+    }
+
+    pub struct TcpWriter<'a>(tcp::TcpWriter<'a>, SocketAddr);
+
+    impl<'a> TcpWriter<'a> {
+        /// Send a message, waiting for the peer to make room for it if necessary
+        pub async fn send(&mut self, out_buf: &[u8]) -> Result<usize, Error> {
+            let len: u32 = out_buf.len().try_into().map_err(|_| ErrorCode::NoSpace)?;
+
+            self.write_all(&len.to_le_bytes()).await?;
+            self.write_all(out_buf).await?;
+
+            debug!(
+                "Send packet {:?} ({}) to addr {:?}",
+                out_buf,
+                out_buf.len(),
+                self.1
+            );
+
+            Ok(out_buf.len())
+        }
+
+        async fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+            let mut offset = 0;
+
+            while offset < buf.len() {
+                offset += self.0.write(&buf[offset..]).await.map_err(|e| {
+                    warn!("Error on the network: {:?}", e);
+                    ErrorCode::Network
+                })?;
+            }
+
+            Ok(())
+        }
+    }
+}
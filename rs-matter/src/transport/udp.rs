@@ -235,7 +235,7 @@ pub mod embassy_net {
             _interface: u32,
         ) -> Result<(), Error> {
             self.1
-                .join_multicast_group(Self::from_ip_addr(IpAddr::V6(multiaddr)))
+                .join_multicast_group(from_ip_addr(IpAddr::V6(multiaddr)))
                 .await
                 .map_err(|e| {
                     warn!("Error on the network: {:?}", e);
@@ -253,7 +253,7 @@ pub mod embassy_net {
             _interface: Ipv4Addr,
         ) -> Result<(), Error> {
             self.1
-                .join_multicast_group(Self::from_ip_addr(IpAddr::V4(multiaddr)))
+                .join_multicast_group(from_ip_addr(IpAddr::V4(multiaddr)))
                 .await
                 .map_err(|e| {
                     warn!("Error on the network: {:?}", e);
@@ -271,7 +271,7 @@ pub mod embassy_net {
                 ErrorCode::Network
             })?;
 
-            let addr = Self::to_socket_addr(ep);
+            let addr = to_socket_addr(ep);
 
             debug!("Got packet {:?} from addr {:?}", &in_buf[..len], addr);
 
@@ -280,7 +280,7 @@ pub mod embassy_net {
 
         pub async fn send(&self, addr: SocketAddr, out_buf: &[u8]) -> Result<usize, Error> {
             self.0
-                .send_to(out_buf, Self::from_socket_addr(addr))
+                .send_to(out_buf, from_socket_addr(addr))
                 .await
                 .map_err(|e| {
                     warn!("Error on the network: {:?}", e);
@@ -297,27 +297,29 @@ pub mod embassy_net {
 
             Ok(out_buf.len())
         }
+    }
 
-        fn to_socket_addr(ep: IpEndpoint) -> SocketAddr {
-            SocketAddr::new(Self::to_ip_addr(ep.addr), ep.port)
-        }
+    // The endpoints of smoltcp carry no scope ID, which is not needed to route
+    // link-local peers either, as the stack only has the one interface
+    pub(crate) fn to_socket_addr(ep: IpEndpoint) -> SocketAddr {
+        SocketAddr::new(to_ip_addr(ep.addr), ep.port)
+    }
 
-        fn from_socket_addr(addr: SocketAddr) -> IpEndpoint {
-            IpEndpoint::new(Self::from_ip_addr(addr.ip()), addr.port())
-        }
+    pub(crate) fn from_socket_addr(addr: SocketAddr) -> IpEndpoint {
+        IpEndpoint::new(from_ip_addr(addr.ip()), addr.port())
+    }
 
-        fn to_ip_addr(ip: IpAddress) -> IpAddr {
-            match ip {
-                IpAddress::Ipv4(addr) => IpAddr::V4(Ipv4Addr::from(addr.0)),
-                IpAddress::Ipv6(addr) => IpAddr::V6(Ipv6Addr::from(addr.0)),
-            }
+    pub(crate) fn to_ip_addr(ip: IpAddress) -> IpAddr {
+        match ip {
+            IpAddress::Ipv4(addr) => IpAddr::V4(Ipv4Addr::from(addr.0)),
+            IpAddress::Ipv6(addr) => IpAddr::V6(Ipv6Addr::from(addr.0)),
         }
+    }
 
-        fn from_ip_addr(ip: IpAddr) -> IpAddress {
-            match ip {
-                IpAddr::V4(v4) => IpAddress::Ipv4(Ipv4Address::from_bytes(&v4.octets())),
-                IpAddr::V6(v6) => IpAddress::Ipv6(Ipv6Address::from_bytes(&v6.octets())),
-            }
+    pub(crate) fn from_ip_addr(ip: IpAddr) -> IpAddress {
+        match ip {
+            IpAddr::V4(v4) => IpAddress::Ipv4(Ipv4Address::from_bytes(&v4.octets())),
+            IpAddr::V6(v6) => IpAddress::Ipv6(Ipv6Address::from_bytes(&v6.octets())),
         }
     }
 }
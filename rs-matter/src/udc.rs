@@ -0,0 +1,362 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! User-Directed Commissioning (UDC), with which a commissionee asks a commissioner
+//! to commission it
+
+use num_derive::FromPrimitive;
+
+use crate::{
+    error::Error,
+    error::ErrorCode,
+    tlv::{TLVWriter, TagType, ToTLV},
+    transport::{network::Address, packet::Packet},
+    utils::writebuf::WriteBuf,
+};
+
+pub const PROTO_ID_UDC: u16 = 0x03;
+
+/// The port on which the commissioners listen for UDC messages, unless they advertise
+/// another one
+pub const UDC_PORT: u16 = 5550;
+
+/// The maximum length of the instance name of a commissionable service
+pub const MAX_INSTANCE_NAME_LEN: usize = 16;
+
+#[derive(FromPrimitive, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OpCode {
+    IdentificationDeclaration = 0x00,
+    CommissionerDeclaration = 0x01,
+}
+
+#[repr(u8)]
+enum Tag {
+    VendorId = 1,
+    ProductId = 2,
+    DeviceName = 3,
+    DeviceType = 4,
+    PairingInstruction = 5,
+    PairingHint = 6,
+    RotatingId = 7,
+    Port = 8,
+    TargetAppList = 9,
+    TargetApp = 10,
+    AppVendorId = 11,
+    AppProductId = 12,
+    NoPasscode = 13,
+    CdUponPasscodeDialog = 14,
+    CommissionerPasscode = 15,
+    CommissionerPasscodeReady = 16,
+    CancelPasscode = 17,
+}
+
+/// An app of the commissioner, which the commissionee targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetApp {
+    pub vendor_id: u16,
+    pub product_id: Option<u16>,
+}
+
+/// The IdentificationDeclaration message, which a commissionee sends to the UDC port
+/// of the commissioner it selected
+///
+/// The payload starts with the instance name, NUL-padded to `MAX_INSTANCE_NAME_LEN + 1`
+/// bytes, which is followed by the TLV structure of the other fields.
+///
+/// All fields but the instance name are optional, and the flags are only encoded
+/// when they are set.
+#[derive(Debug, Clone, Default)]
+pub struct IdentificationDeclaration<'a> {
+    /// The instance name of the commissionable service of the commissionee
+    pub instance_name: &'a str,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub device_name: Option<&'a str>,
+    pub device_type: Option<u32>,
+    pub pairing_instruction: Option<&'a str>,
+    pub pairing_hint: Option<u16>,
+    pub rotating_id: Option<&'a [u8]>,
+    /// The port on which the commissionee listens for the CommissionerDeclaration
+    pub port: Option<u16>,
+    pub target_apps: &'a [TargetApp],
+    /// The commissionee does not need a passcode, e.g. as an app of the commissioner
+    /// already knows it
+    pub no_passcode: bool,
+    /// Ask for a CommissionerDeclaration once the passcode dialog is displayed
+    pub cd_upon_passcode_dialog: bool,
+    /// Ask the commissioner to display a passcode, to be entered on the commissionee
+    pub commissioner_passcode: bool,
+    /// The commissionee is ready for the commissioner to use the passcode it displays
+    pub commissioner_passcode_ready: bool,
+    /// Cancel a previous declaration, e.g. as the user dismissed the passcode dialog
+    pub cancel_passcode: bool,
+}
+
+impl<'a> IdentificationDeclaration<'a> {
+    /// Prepare the message, as an unsecured message with the provided message counter
+    pub fn encode(&self, tx: &mut Packet, peer: Address, ctr: u32) -> Result<(), Error> {
+        tx.reset();
+        tx.set_proto_id(PROTO_ID_UDC);
+        tx.set_proto_opcode(OpCode::IdentificationDeclaration as _);
+        // There is no exchange to acknowledge the message on
+        tx.unset_reliable();
+        tx.proto.set_initiator();
+        tx.plain.ctr = ctr;
+
+        self.write(tx.get_writebuf()?)?;
+
+        tx.proto_encode(peer, None, 0, true, None, None)
+    }
+
+    /// Write the payload of the message: the instance name, followed by the TLV of
+    /// the other fields
+    pub fn write(&self, wb: &mut WriteBuf) -> Result<(), Error> {
+        let name = self.instance_name.as_bytes();
+        if name.len() > MAX_INSTANCE_NAME_LEN {
+            Err(ErrorCode::InvalidData)?;
+        }
+
+        let mut prefix = [0; MAX_INSTANCE_NAME_LEN + 1];
+        prefix[..name.len()].copy_from_slice(name);
+        wb.append(&prefix)?;
+
+        let mut tw = TLVWriter::new(wb);
+        self.to_tlv(&mut tw, TagType::Anonymous)
+    }
+
+    /// Send the message to the commissioner at the provided address
+    #[cfg(any(feature = "std", feature = "embassy-net"))]
+    pub async fn send<D>(
+        &self,
+        udp: &crate::transport::udp::UdpListener<'_, D>,
+        addr: crate::transport::network::SocketAddr,
+        ctr: u32,
+    ) -> Result<(), Error>
+    where
+        D: crate::transport::network::NetworkStackDriver,
+    {
+        let mut buf = [0; crate::transport::packet::MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut buf);
+
+        self.encode(&mut tx, Address::Udp(addr), ctr)?;
+
+        log::info!("Sending UDC IdentificationDeclaration to {}", addr);
+
+        udp.send(addr, tx.as_slice()).await?;
+
+        Ok(())
+    }
+}
+
+impl<'a> ToTLV for IdentificationDeclaration<'a> {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag_type: TagType) -> Result<(), Error> {
+        tw.start_struct(tag_type)?;
+
+        if let Some(vendor_id) = self.vendor_id {
+            tw.u16(TagType::Context(Tag::VendorId as _), vendor_id)?;
+        }
+        if let Some(product_id) = self.product_id {
+            tw.u16(TagType::Context(Tag::ProductId as _), product_id)?;
+        }
+        if let Some(device_name) = self.device_name {
+            tw.utf8(
+                TagType::Context(Tag::DeviceName as _),
+                device_name.as_bytes(),
+            )?;
+        }
+        if let Some(device_type) = self.device_type {
+            tw.u32(TagType::Context(Tag::DeviceType as _), device_type)?;
+        }
+        if let Some(pairing_instruction) = self.pairing_instruction {
+            tw.utf8(
+                TagType::Context(Tag::PairingInstruction as _),
+                pairing_instruction.as_bytes(),
+            )?;
+        }
+        if let Some(pairing_hint) = self.pairing_hint {
+            tw.u16(TagType::Context(Tag::PairingHint as _), pairing_hint)?;
+        }
+        if let Some(rotating_id) = self.rotating_id {
+            tw.str8(TagType::Context(Tag::RotatingId as _), rotating_id)?;
+        }
+        if let Some(port) = self.port {
+            tw.u16(TagType::Context(Tag::Port as _), port)?;
+        }
+
+        if !self.target_apps.is_empty() {
+            tw.start_list(TagType::Context(Tag::TargetAppList as _))?;
+            for app in self.target_apps {
+                tw.start_struct(TagType::Context(Tag::TargetApp as _))?;
+                tw.u16(TagType::Context(Tag::AppVendorId as _), app.vendor_id)?;
+                if let Some(product_id) = app.product_id {
+                    tw.u16(TagType::Context(Tag::AppProductId as _), product_id)?;
+                }
+                tw.end_container()?;
+            }
+            tw.end_container()?;
+        }
+
+        for (tag, set) in [
+            (Tag::NoPasscode, self.no_passcode),
+            (Tag::CdUponPasscodeDialog, self.cd_upon_passcode_dialog),
+            (Tag::CommissionerPasscode, self.commissioner_passcode),
+            (
+                Tag::CommissionerPasscodeReady,
+                self.commissioner_passcode_ready,
+            ),
+            (Tag::CancelPasscode, self.cancel_passcode),
+        ] {
+            if set {
+                tw.bool(TagType::Context(tag as _), true)?;
+            }
+        }
+
+        tw.end_container()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tlv::get_root_node_struct,
+        transport::{
+            network::Address,
+            packet::{Packet, MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+        },
+        utils::writebuf::WriteBuf,
+    };
+
+    use super::{
+        IdentificationDeclaration, OpCode, TargetApp, MAX_INSTANCE_NAME_LEN, PROTO_ID_UDC,
+    };
+
+    // An IdentificationDeclaration laid out as by the reference implementation, with the fields
+    // which are not set left out
+    #[rustfmt::skip]
+    const REFERENCE_PAYLOAD: &[u8] = &[
+        // The instance name, NUL-padded
+        b'C', b'0', b'F', b'F', b'E', b'E', b'0', b'0', b'0', b'1', 0, 0, 0, 0, 0, 0, 0,
+        // The anonymous struct
+        0x15,
+        // VendorId, ProductId
+        0x25, 0x01, 0xf1, 0xff, 0x25, 0x02, 0x00, 0x80,
+        // DeviceName
+        0x2c, 0x03, 0x07, b'K', b'i', b't', b'c', b'h', b'e', b'n',
+        // DeviceType
+        0x25, 0x04, 0x00, 0x01,
+        // PairingInstruction, PairingHint
+        0x2c, 0x05, 0x02, b'1', b'0', 0x24, 0x06, 0x21,
+        // RotatingId
+        0x30, 0x07, 0x02, 0x0a, 0x0b,
+        // CdPort
+        0x25, 0x08, 0xa4, 0x15,
+        // TargetAppList
+        0x37, 0x09,
+        0x35, 0x0a, 0x25, 0x0b, 0x34, 0x12, 0x24, 0x0c, 0x01, 0x18,
+        0x35, 0x0a, 0x25, 0x0b, 0x78, 0x56, 0x18,
+        0x18,
+        // CommissionerPasscodeReady
+        0x29, 0x10,
+        0x18,
+    ];
+
+    #[test]
+    fn declaration_payload() {
+        let declaration = IdentificationDeclaration {
+            instance_name: "C0FFEE0001",
+            vendor_id: Some(0xfff1),
+            product_id: Some(0x8000),
+            device_name: Some("Kitchen"),
+            device_type: Some(0x0100),
+            pairing_instruction: Some("10"),
+            pairing_hint: Some(0x21),
+            rotating_id: Some(&[0x0a, 0x0b]),
+            port: Some(5540),
+            target_apps: &[
+                TargetApp {
+                    vendor_id: 0x1234,
+                    product_id: Some(1),
+                },
+                TargetApp {
+                    vendor_id: 0x5678,
+                    product_id: None,
+                },
+            ],
+            commissioner_passcode_ready: true,
+            ..Default::default()
+        };
+
+        let mut buf = [0; 200];
+        let mut wb = WriteBuf::new(&mut buf);
+        declaration.write(&mut wb).unwrap();
+
+        assert_eq!(wb.as_slice(), REFERENCE_PAYLOAD);
+    }
+
+    #[test]
+    fn declaration_instance_name_too_long() {
+        let declaration = IdentificationDeclaration {
+            instance_name: "C0FFEE0001C0FFEE0",
+            ..Default::default()
+        };
+
+        let mut buf = [0; 200];
+        let mut wb = WriteBuf::new(&mut buf);
+        assert!(declaration.write(&mut wb).is_err());
+    }
+
+    #[test]
+    fn declaration_packet() {
+        let declaration = IdentificationDeclaration {
+            instance_name: "C0FFEE0001",
+            no_passcode: true,
+            cancel_passcode: true,
+            ..Default::default()
+        };
+
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx = Packet::new_tx(&mut tx_buf);
+        declaration.encode(&mut tx, Address::default(), 42).unwrap();
+
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+        let len = tx.as_slice().len();
+        rx_buf[..len].copy_from_slice(tx.as_slice());
+
+        let mut rx = Packet::new_rx(&mut rx_buf[..len]);
+        rx.plain_hdr_decode().unwrap();
+        rx.proto_decode(0, None).unwrap();
+
+        assert_eq!(rx.plain.ctr, 42);
+        assert!(!rx.plain.is_encrypted());
+        assert!(rx.proto.is_initiator());
+        assert!(!rx.proto.is_reliable());
+        assert_eq!(rx.get_proto_id(), PROTO_ID_UDC);
+        assert_eq!(
+            rx.get_proto_raw_opcode(),
+            OpCode::IdentificationDeclaration as u8
+        );
+
+        let (name, tlv) = rx.as_slice().split_at(MAX_INSTANCE_NAME_LEN + 1);
+        assert_eq!(&name[..10], b"C0FFEE0001");
+        assert!(name[10..].iter().all(|b| *b == 0));
+
+        let root = get_root_node_struct(tlv).unwrap();
+        assert!(root.find_tag(13).unwrap().bool().unwrap());
+        assert!(root.find_tag(17).unwrap().bool().unwrap());
+        assert!(root.find_tag(16).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::{cell::Cell, time::Duration};
+
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+use super::epoch::Epoch;
+
+/// A monotonic time source, which the subscriptions measure their intervals against
+pub trait Clock {
+    /// The milliseconds elapsed since an arbitrary, but fixed point in time
+    fn now_millis(&self) -> u64;
+
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.now_millis())
+    }
+}
+
+/// The clock of the time driver of `embassy-time`, i.e. the one `EmbassyTimer` runs on
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        embassy_time::Instant::now().as_millis()
+    }
+}
+
+/// A clock which never moves
+pub struct DummyClock;
+
+impl Clock for DummyClock {
+    fn now_millis(&self) -> u64 {
+        0
+    }
+}
+
+/// A clock reading the provided epoch
+pub struct EpochClock(pub Epoch);
+
+impl Clock for EpochClock {
+    fn now_millis(&self) -> u64 {
+        (self.0)().as_millis() as _
+    }
+}
+
+/// A clock which only moves when told to, for tests
+pub struct MockClock(CriticalSectionMutex<Cell<u64>>);
+
+impl MockClock {
+    pub const fn new(now_millis: u64) -> Self {
+        Self(CriticalSectionMutex::new(Cell::new(now_millis)))
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.0.lock(|millis| millis.set(now.as_millis() as _));
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.0
+            .lock(|millis| millis.set(millis.get() + by.as_millis() as u64));
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> u64 {
+        self.0.lock(Cell::get)
+    }
+}
@@ -15,8 +15,10 @@
  *    limitations under the License.
  */
 
+pub mod clock;
 pub mod epoch;
 pub mod parsebuf;
 pub mod rand;
 pub mod select;
+pub mod timer;
 pub mod writebuf;
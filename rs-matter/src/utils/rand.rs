@@ -1,5 +1,20 @@
+/// The source of the randomness of the stack, from handshakes to data versions
+///
+/// Devices use a CSPRNG, such as `sys_rand`, while tests may use `test_rand`
+/// for reproducible handshakes.
 pub type Rand = fn(&mut [u8]);
 
+/// What the crypto backends draw their random scalars and nonces from
+pub trait RandSource {
+    fn fill_bytes(&self, buf: &mut [u8]);
+}
+
+impl RandSource for Rand {
+    fn fill_bytes(&self, buf: &mut [u8]) {
+        self(buf)
+    }
+}
+
 pub fn dummy_rand(_buf: &mut [u8]) {}
 
 #[cfg(feature = "std")]
@@ -8,3 +23,51 @@ pub fn sys_rand(buf: &mut [u8]) {
 
     thread_rng().fill_bytes(buf);
 }
+
+/// A deterministic generator, for reproducing handshakes and their test vectors in tests
+///
+/// Its output is fully predictable from its seed, so it must never be used by a device.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct TestRand(u64);
+
+#[cfg(test)]
+impl TestRand {
+    pub const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Seed the generator which [test_rand] draws from on the current thread
+    #[cfg(feature = "std")]
+    pub fn seed(seed: u64) {
+        TEST_RAND.with(|rand| *rand.borrow_mut() = Self::new(seed));
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    // SplitMix64
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+std::thread_local! {
+    static TEST_RAND: core::cell::RefCell<TestRand> = core::cell::RefCell::new(TestRand::new(0));
+}
+
+/// A [Rand] drawing from the [TestRand] of the current thread, as seeded with [TestRand::seed]
+#[cfg(all(test, feature = "std"))]
+pub fn test_rand(buf: &mut [u8]) {
+    TEST_RAND.with(|rand| rand.borrow_mut().fill_bytes(buf));
+}
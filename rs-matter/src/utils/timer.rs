@@ -0,0 +1,35 @@
+use core::future::Future;
+use core::time::Duration;
+
+/// A cooperative timer, which the subscription, MRP and fail-safe engines await
+/// for their intervals and timeouts, rather than polling
+pub trait Timer {
+    type Future: Future<Output = ()>;
+
+    /// A future which completes once `duration` has elapsed
+    fn after(&self, duration: Duration) -> Self::Future;
+}
+
+impl<T> Timer for &T
+where
+    T: Timer,
+{
+    type Future = T::Future;
+
+    fn after(&self, duration: Duration) -> Self::Future {
+        (**self).after(duration)
+    }
+}
+
+/// The timer of the time driver of `embassy-time`
+pub struct EmbassyTimer;
+
+impl Timer for EmbassyTimer {
+    type Future = embassy_time::Timer;
+
+    fn after(&self, duration: Duration) -> Self::Future {
+        embassy_time::Timer::after(embassy_time::Duration::from_micros(
+            duration.as_micros() as _
+        ))
+    }
+}
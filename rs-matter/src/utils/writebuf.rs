@@ -96,6 +96,11 @@ impl<'a> WriteBuf<'a> {
         }
     }
 
+    /// The length the data can currently grow to, including the reserved space
+    pub fn capacity(&self) -> usize {
+        self.buf_size
+    }
+
     pub fn shrink(&mut self, with: usize) -> Result<(), Error> {
         if self.end + with <= self.buf_size {
             self.buf_size -= with;
@@ -200,6 +205,18 @@ impl<'a> WriteBuf<'a> {
         })
     }
 
+    pub fn le_f32(&mut self, data: f32) -> Result<(), Error> {
+        self.append_with(4, |x| {
+            LittleEndian::write_f32(&mut x.buf[x.end..], data);
+        })
+    }
+
+    pub fn le_f64(&mut self, data: f64) -> Result<(), Error> {
+        self.append_with(8, |x| {
+            LittleEndian::write_f64(&mut x.buf[x.end..], data);
+        })
+    }
+
     pub fn le_uint(&mut self, nbytes: usize, data: u64) -> Result<(), Error> {
         self.append_with(nbytes, |x| {
             LittleEndian::write_uint(&mut x.buf[x.end..], data, nbytes);
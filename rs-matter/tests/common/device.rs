@@ -0,0 +1,46 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The device under test, which is shared by the data model and the transport tests
+
+use rs_matter::{
+    data_model::{
+        cluster_basic_information::{BasicInfoConfig, ProductAppearance, ProductFinish},
+        sdm::dev_att::{DataType, DevAttDataFetcher},
+    },
+    error::Error,
+};
+
+pub const BASIC_INFO: BasicInfoConfig<'static> = BasicInfoConfig {
+    vid: 10,
+    pid: 11,
+    hw_ver: 12,
+    sw_ver: 13,
+    sw_ver_str: "13",
+    serial_no: "aabbccdd",
+    device_name: "Test Device",
+    product_appearance: ProductAppearance::new(ProductFinish::Other, None),
+    device_type: None,
+};
+
+pub struct DummyDevAtt;
+
+impl DevAttDataFetcher for DummyDevAtt {
+    fn get_devatt_data(&self, _data_type: DataType, _data: &mut [u8]) -> Result<usize, Error> {
+        Ok(2)
+    }
+}
@@ -24,11 +24,11 @@ use rs_matter::{
     attribute_enum, command_enum,
     data_model::objects::{
         Access, AttrData, AttrDataEncoder, AttrDataWriter, AttrDetails, AttrType, Attribute,
-        Cluster, CmdDataEncoder, CmdDataWriter, CmdDetails, Dataver, Handler, NonBlockingHandler,
-        Quality, ATTRIBUTE_LIST, FEATURE_MAP,
+        Cluster, CmdDataEncoder, CmdDataWriter, CmdDetails, Constraint, Dataver, Handler, ListOp,
+        NonBlockingHandler, Quality, ATTRIBUTE_LIST, FEATURE_MAP,
     },
     error::{Error, ErrorCode},
-    interaction_model::messages::ib::{attr_list_write, ListOperation},
+    interaction_model::messages::GenericPath,
     tlv::{TLVElement, TagType},
     transport::exchange::Exchange,
     utils::rand::Rand,
@@ -53,6 +53,7 @@ attribute_enum!(Attributes);
 #[repr(u32)]
 pub enum Commands {
     EchoReq = 0x00,
+    TimedEchoReq = 0x02,
 }
 
 command_enum!(Commands);
@@ -64,6 +65,7 @@ pub enum RespCommands {
 
 pub const CLUSTER: Cluster<'static> = Cluster {
     id: ID,
+    revision: 1,
     feature_map: 0,
     attributes: &[
         FEATURE_MAP,
@@ -82,7 +84,8 @@ pub const CLUSTER: Cluster<'static> = Cluster {
             AttributesDiscriminants::AttWrite as u16,
             Access::WRITE.union(Access::NEED_ADMIN),
             Quality::NONE,
-        ),
+        )
+        .with_constraint(Constraint::Range(0, ATTR_WRITE_MAX_VALUE as _)),
         Attribute::new(
             AttributesDiscriminants::AttCustom as u16,
             Access::READ.union(Access::NEED_VIEW),
@@ -94,7 +97,12 @@ pub const CLUSTER: Cluster<'static> = Cluster {
             Quality::NONE,
         ),
     ],
-    commands: &[Commands::EchoReq as _],
+    commands: &[Commands::EchoReq as _, Commands::TimedEchoReq as _],
+    generated_commands: &[RespCommands::EchoResp as _],
+    timed_commands: &[Commands::TimedEchoReq as _],
+    response_commands: &[Commands::EchoReq as _, Commands::TimedEchoReq as _],
+    manage_commands: &[],
+    admin_commands: &[],
 };
 
 /// This is used in the tests to validate any settings that may have happened
@@ -133,6 +141,8 @@ pub struct EchoCluster {
     pub att2: Cell<u16>,
     pub att_write: Cell<u16>,
     pub att_custom: Cell<u32>,
+    /// When set, the cluster is left out of wildcard reads
+    pub prune_reads: Cell<bool>,
 }
 
 impl EchoCluster {
@@ -144,6 +154,7 @@ impl EchoCluster {
             att2: Cell::new(0x5678),
             att_write: Cell::new(ATTR_WRITE_DEFAULT_VALUE),
             att_custom: Cell::new(ATTR_CUSTOM_VALUE),
+            prune_reads: Cell::new(false),
         }
     }
 
@@ -177,6 +188,7 @@ impl EchoCluster {
     }
 
     pub fn write(&self, attr: &AttrDetails, data: AttrData) -> Result<(), Error> {
+        let list_op = data.list_op();
         let data = data.with_dataver(self.data_ver.get())?;
 
         match attr.attr_id.try_into()? {
@@ -184,9 +196,7 @@ impl EchoCluster {
             Attributes::Att2(codec) => self.att2.set(codec.decode(data)?),
             Attributes::AttWrite(codec) => self.att_write.set(codec.decode(data)?),
             Attributes::AttCustom(codec) => self.att_custom.set(codec.decode(data)?),
-            Attributes::AttWriteList(_) => {
-                attr_list_write(attr, data, |op, data| self.write_attr_list(&op, data))?
-            }
+            Attributes::AttWriteList(_) => self.write_attr_list(list_op, data)?,
         }
 
         self.data_ver.changed();
@@ -204,7 +214,7 @@ impl EchoCluster {
         match cmd.cmd_id.try_into()? {
             // This will generate an echo response on the same endpoint
             // with data multiplied by the multiplier
-            Commands::EchoReq => {
+            Commands::EchoReq | Commands::TimedEchoReq => {
                 let a = data.u8()?;
 
                 let mut writer = encoder.with_command(RespCommands::EchoResp as _)?;
@@ -219,50 +229,63 @@ impl EchoCluster {
         }
     }
 
-    fn write_attr_list(&self, op: &ListOperation, data: &TLVElement) -> Result<(), Error> {
+    fn write_attr_list(&self, op: ListOp, data: &TLVElement) -> Result<(), Error> {
         let tc_handle = TestChecker::get().unwrap();
         let mut tc = tc_handle.lock().unwrap();
         match op {
-            ListOperation::AddItem => {
-                let data = data.u16()?;
-                for i in 0..WRITE_LIST_MAX {
-                    if tc.write_list[i].is_none() {
-                        tc.write_list[i] = Some(data);
-                        return Ok(());
-                    }
-                }
-
-                Err(ErrorCode::ResourceExhausted.into())
-            }
-            ListOperation::EditItem(index) => {
+            ListOp::Append => Self::append_attr_list(&mut tc.write_list, data.u16()?),
+            ListOp::EditAt(index) => {
                 let data = data.u16()?;
-                if tc.write_list[*index as usize].is_some() {
-                    tc.write_list[*index as usize] = Some(data);
+                let entry = tc
+                    .write_list
+                    .get_mut(index)
+                    .ok_or(ErrorCode::ConstraintError)?;
+                if entry.is_some() {
+                    *entry = Some(data);
                     Ok(())
                 } else {
                     Err(ErrorCode::InvalidAction.into())
                 }
             }
-            ListOperation::DeleteItem(index) => {
-                if tc.write_list[*index as usize].is_some() {
-                    tc.write_list[*index as usize] = None;
+            ListOp::DeleteAt(index) => {
+                let entry = tc
+                    .write_list
+                    .get_mut(index)
+                    .ok_or(ErrorCode::ConstraintError)?;
+                if entry.is_some() {
+                    *entry = None;
                     Ok(())
                 } else {
                     Err(ErrorCode::InvalidAction.into())
                 }
             }
-            ListOperation::DeleteList => {
-                for i in 0..WRITE_LIST_MAX {
-                    tc.write_list[i] = None;
+            ListOp::Replace => {
+                tc.write_list = [None; WRITE_LIST_MAX];
+
+                for element in data.enter().ok_or(ErrorCode::Invalid)? {
+                    Self::append_attr_list(&mut tc.write_list, element.u16()?)?;
                 }
+
                 Ok(())
             }
         }
     }
+
+    fn append_attr_list(list: &mut [Option<u16>], data: u16) -> Result<(), Error> {
+        let entry = list
+            .iter_mut()
+            .find(|entry| entry.is_none())
+            .ok_or(ErrorCode::ResourceExhausted)?;
+        *entry = Some(data);
+
+        Ok(())
+    }
 }
 
 pub const ATTR_CUSTOM_VALUE: u32 = 0xcafebeef;
 pub const ATTR_WRITE_DEFAULT_VALUE: u16 = 0xcafe;
+/// The largest value a write to `AttWrite` accepts
+pub const ATTR_WRITE_MAX_VALUE: u16 = 1000;
 
 impl Handler for EchoCluster {
     fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
@@ -282,6 +305,10 @@ impl Handler for EchoCluster {
     ) -> Result<(), Error> {
         EchoCluster::invoke(self, exchange, cmd, data, encoder)
     }
+
+    fn prune_read(&self, _path: &GenericPath) -> bool {
+        self.prune_reads.get()
+    }
 }
 
 impl NonBlockingHandler for EchoCluster {}
@@ -95,25 +95,58 @@ impl<'a> ImEngine<'a> {
             tlv::print_tlv_list(&o.data);
         }
 
-        let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+        assert_write_response(&out[0], expected);
+    }
+
+    /// Sends each of the inputs as a separate chunk of the same write interaction,
+    /// and checks that every chunk but the last one is acknowledged with a status response,
+    /// while the write response to the last one carries the statuses of all chunks
+    pub fn handle_chunked_write_reqs(
+        &self,
+        handler: &ImEngineHandler,
+        input: &[&[AttrData]],
+        expected: &[AttrStatus],
+    ) {
+        let write_reqs: heapless::Vec<_, 4> = input
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut write_req = WriteReq::new(false, chunk);
+                if index < input.len() - 1 {
+                    write_req.more_chunked = Some(true);
+                }
+
+                write_req
+            })
+            .collect();
+
+        let inputs: heapless::Vec<_, 4> = write_reqs
+            .iter()
+            .map(|write_req| ImInput::new(OpCode::WriteRequest, write_req))
+            .collect();
+        let inputs: heapless::Vec<_, 4> = inputs.iter().collect();
+
+        let mut out = heapless::Vec::<_, 4>::new();
+        self.process(handler, &inputs, &mut out).unwrap();
 
-        let mut index = 0;
-        let response_iter = root
-            .find_tag(WriteRespTag::WriteResponses as u32)
-            .unwrap()
-            .confirm_array()
-            .unwrap()
-            .enter()
-            .unwrap();
-
-        for response in response_iter {
-            info!("Validating index {}", index);
-            let status = AttrStatus::from_tlv(&response).unwrap();
-            assert_eq!(expected[index], status);
-            info!("Index {} success", index);
-            index += 1;
+        assert_eq!(out.len(), input.len());
+
+        let (last, acks) = out.split_last().unwrap();
+
+        for ack in acks {
+            tlv::print_tlv_list(&ack.data);
+
+            assert_eq!(ack.action, OpCode::StatusResponse);
+
+            let root = tlv::get_root_node_struct(&ack.data).unwrap();
+            let status_resp = StatusResp::from_tlv(&root).unwrap();
+            assert_eq!(status_resp.status, IMStatusCode::Success);
         }
-        assert_eq!(index, expected.len());
+
+        tlv::print_tlv_list(&last.data);
+
+        assert_eq!(last.action, OpCode::WriteResponse);
+        assert_write_response(last, expected);
     }
 
     pub fn commands(input: &[CmdData], expected: &[ExpectedInvResp]) {
@@ -297,3 +330,25 @@ impl<'a> ImEngine<'a> {
         }
     }
 }
+
+pub fn assert_write_response(out: &ImOutput, expected: &[AttrStatus]) {
+    let root = tlv::get_root_node_struct(&out.data).unwrap();
+
+    let mut index = 0;
+    let response_iter = root
+        .find_tag(WriteRespTag::WriteResponses as u32)
+        .unwrap()
+        .confirm_array()
+        .unwrap()
+        .enter()
+        .unwrap();
+
+    for response in response_iter {
+        info!("Validating index {}", index);
+        let status = AttrStatus::from_tlv(&response).unwrap();
+        assert_eq!(expected[index], status);
+        info!("Index {} success", index);
+        index += 1;
+    }
+    assert_eq!(index, expected.len());
+}
@@ -15,35 +15,37 @@
  *    limitations under the License.
  */
 
+use crate::common::device::{DummyDevAtt, BASIC_INFO};
 use crate::common::echo_cluster;
 use core::borrow::Borrow;
+use core::cell::Cell;
 use core::future::pending;
 use core::time::Duration;
 use embassy_futures::select::select3;
 use rs_matter::{
     acl::{AclEntry, AuthMode},
     data_model::{
-        cluster_basic_information::{self, BasicInfoConfig},
+        cluster_basic_information,
         cluster_on_off::{self, OnOffCluster},
         device_types::{DEV_TYPE_ON_OFF_LIGHT, DEV_TYPE_ROOT_NODE},
         objects::{
-            AttrData, AttrDataEncoder, AttrDetails, Endpoint, Handler, HandlerCompat, Metadata,
-            Node, NonBlockingHandler, Privilege,
+            AttrData, AttrDataEncoder, AttrDetails, DataModelHandler, Endpoint, Handler,
+            HandlerCompat, Metadata, Node, NonBlockingHandler, Privilege,
         },
         root_endpoint::{self, RootEndpointHandler},
-        sdm::{
-            admin_commissioning,
-            dev_att::{DataType, DevAttDataFetcher},
-            general_commissioning, noc, nw_commissioning,
-        },
+        sdm::{admin_commissioning, general_commissioning, noc, nw_commissioning},
         system_model::{
             access_control,
             descriptor::{self, DescriptorCluster},
         },
     },
     error::{Error, ErrorCode},
+    groups::{EpochKey, GroupKeySet},
     handler_chain_type,
-    interaction_model::core::{OpCode, PROTO_ID_INTERACTION_MODEL},
+    interaction_model::{
+        core::{OpCode, PROTO_ID_INTERACTION_MODEL},
+        messages::GenericPath,
+    },
     mdns::DummyMdns,
     secure_channel::{self, common::PROTO_ID_SECURE_CHANNEL, spake2p::VerifierData},
     tlv::{TLVWriter, TagType, ToTLV},
@@ -56,33 +58,20 @@ use rs_matter::{
         network::Address,
         session::{CaseDetails, CloneData, NocCatIds, SessionMode},
     },
-    utils::select::{EitherUnwrap, Notification},
+    utils::{
+        clock::{Clock, SystemClock},
+        select::{EitherUnwrap, Notification},
+    },
     CommissioningData, Matter, MATTER_PORT,
 };
 
 use super::echo_cluster::EchoCluster;
 
-const BASIC_INFO: BasicInfoConfig<'static> = BasicInfoConfig {
-    vid: 10,
-    pid: 11,
-    hw_ver: 12,
-    sw_ver: 13,
-    sw_ver_str: "13",
-    serial_no: "aabbccdd",
-    device_name: "Test Device",
-};
-
-struct DummyDevAtt;
-
-impl DevAttDataFetcher for DummyDevAtt {
-    fn get_devatt_data(&self, _data_type: DataType, _data: &mut [u8]) -> Result<usize, Error> {
-        Ok(2)
-    }
-}
-
 pub const IM_ENGINE_PEER_ID: u64 = 445566;
 pub const IM_ENGINE_REMOTE_PEER_ID: u64 = 123456;
 
+const IM_ENGINE_COMPRESSED_FABRIC_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
 const NODE: Node<'static> = Node {
     id: 0,
     endpoints: &[
@@ -98,7 +87,8 @@ const NODE: Node<'static> = Node {
                 access_control::CLUSTER,
                 echo_cluster::CLUSTER,
             ],
-            device_type: DEV_TYPE_ROOT_NODE,
+            device_types: &[DEV_TYPE_ROOT_NODE],
+            tags: &[],
         },
         Endpoint {
             id: 1,
@@ -107,15 +97,19 @@ const NODE: Node<'static> = Node {
                 cluster_on_off::CLUSTER,
                 echo_cluster::CLUSTER,
             ],
-            device_type: DEV_TYPE_ON_OFF_LIGHT,
+            device_types: &[DEV_TYPE_ON_OFF_LIGHT],
+            tags: &[],
         },
     ],
 };
 
 pub struct ImInput<'a> {
-    action: OpCode,
+    proto_id: u16,
+    opcode: u8,
     data: &'a dyn ToTLV,
     delay: Option<u16>,
+    response: bool,
+    group: Option<u16>,
 }
 
 impl<'a> ImInput<'a> {
@@ -125,15 +119,57 @@ impl<'a> ImInput<'a> {
 
     pub fn new_delayed(action: OpCode, data: &'a dyn ToTLV, delay: Option<u16>) -> Self {
         Self {
-            action,
+            proto_id: PROTO_ID_INTERACTION_MODEL,
+            opcode: action as _,
             data,
             delay,
+            response: true,
+            group: None,
+        }
+    }
+
+    /// An input for which no response is expected (i.e. one with a suppressed response),
+    /// but only a standalone acknowledgement
+    pub fn new_unanswered(action: OpCode, data: &'a dyn ToTLV) -> Self {
+        Self {
+            response: false,
+            ..Self::new(action, data)
+        }
+    }
+
+    /// An input of another protocol than the Interaction Model (e.g. BDX), with its
+    /// payload already encoded
+    pub fn new_proto(proto_id: u16, opcode: u8, data: &'a dyn ToTLV) -> Self {
+        Self {
+            proto_id,
+            opcode,
+            ..Self::new(OpCode::Reserved, data)
+        }
+    }
+
+    /// Same as `new_proto`, but for an input which is only acknowledged
+    pub fn new_proto_unanswered(proto_id: u16, opcode: u8, data: &'a dyn ToTLV) -> Self {
+        Self {
+            response: false,
+            ..Self::new_proto(proto_id, opcode, data)
+        }
+    }
+
+    /// An input sent to the provided group, which is neither answered nor acknowledged
+    pub fn new_groupcast(action: OpCode, data: &'a dyn ToTLV, group_id: u16) -> Self {
+        Self {
+            response: false,
+            group: Some(group_id),
+            ..Self::new(action, data)
         }
     }
 }
 
 pub struct ImOutput {
+    /// The opcode of an Interaction Model output, or `Reserved` for the other protocols
     pub action: OpCode,
+    pub proto_id: u16,
+    pub opcode: u8,
     pub data: heapless::Vec<u8, MAX_TX_BUF_SIZE>,
 }
 
@@ -143,9 +179,13 @@ pub struct ImEngineHandler<'a> {
 
 impl<'a> ImEngineHandler<'a> {
     pub fn new(matter: &'a Matter<'a>) -> Self {
-        let handler = root_endpoint::handler(0, matter)
+        let handler = root_endpoint::handler(0, &NODE, matter)
             .chain(0, echo_cluster::ID, EchoCluster::new(2, *matter.borrow()))
-            .chain(1, descriptor::ID, DescriptorCluster::new(*matter.borrow()))
+            .chain(
+                1,
+                descriptor::ID,
+                DescriptorCluster::new(&NODE, *matter.borrow()),
+            )
             .chain(1, echo_cluster::ID, EchoCluster::new(3, *matter.borrow()))
             .chain(1, cluster_on_off::ID, OnOffCluster::new(*matter.borrow()));
 
@@ -179,12 +219,19 @@ impl<'a> Handler for ImEngineHandler<'a> {
     ) -> Result<(), Error> {
         self.handler.invoke(exchange, cmd, data, encoder)
     }
+
+    fn prune_read(&self, path: &GenericPath) -> bool {
+        self.handler.prune_read(path)
+    }
 }
 
 impl<'a> NonBlockingHandler for ImEngineHandler<'a> {}
 
 impl<'a> Metadata for ImEngineHandler<'a> {
-    type MetadataGuard<'g> = Node<'g> where Self: 'g;
+    type MetadataGuard<'g>
+        = Node<'g>
+    where
+        Self: 'g;
 
     fn lock(&self) -> Self::MetadataGuard<'_> {
         NODE
@@ -197,6 +244,7 @@ static mut DNS: DummyMdns = DummyMdns;
 pub struct ImEngine<'a> {
     pub matter: Matter<'a>,
     cat_ids: NocCatIds,
+    peer_addr: Address,
 }
 
 impl<'a> ImEngine<'a> {
@@ -206,6 +254,12 @@ impl<'a> ImEngine<'a> {
 
     /// Create the interaction model engine
     pub fn new(cat_ids: NocCatIds) -> Self {
+        Self::new_with_clock(cat_ids, &SystemClock)
+    }
+
+    /// Create the interaction model engine, with the subscriptions timed against
+    /// the clock provided by the test
+    pub fn new_with_clock(cat_ids: NocCatIds, clock: &'static dyn Clock) -> Self {
         #[cfg(feature = "std")]
         use rs_matter::utils::epoch::sys_epoch as epoch;
 
@@ -218,16 +272,28 @@ impl<'a> ImEngine<'a> {
         #[cfg(not(feature = "std"))]
         use rs_matter::utils::rand::dummy_rand as rand;
 
-        let matter = Matter::new(
+        let matter = Matter::new_with_clock(
             &BASIC_INFO,
             &DummyDevAtt,
             unsafe { &mut DNS },
             epoch,
             rand,
+            clock,
             MATTER_PORT,
         );
 
-        Self { matter, cat_ids }
+        Self {
+            matter,
+            cat_ids,
+            peer_addr: Address::default(),
+        }
+    }
+
+    /// Talk to the device from the provided address, e.g. a TCP one rather than the
+    /// default UDP one
+    pub fn with_peer_addr(mut self, peer_addr: Address) -> Self {
+        self.peer_addr = peer_addr;
+        self
     }
 
     pub fn add_default_acl(&self) {
@@ -237,6 +303,33 @@ impl<'a> ImEngine<'a> {
         self.matter.acl_mgr.borrow_mut().add(default_acl).unwrap();
     }
 
+    /// Make the endpoint a member of the group, with the group messages encrypted
+    /// with the provided epoch key and allowed to operate the endpoint
+    pub fn add_group(&self, group_id: u16, endpoint: u16, epoch_key: &[u8]) {
+        let mut epoch_keys = heapless::Vec::new();
+        epoch_keys
+            .push(EpochKey::new(0, epoch_key, &IM_ENGINE_COMPRESSED_FABRIC_ID).unwrap())
+            .unwrap();
+
+        let mut group_mgr = self.matter.group_mgr.borrow_mut();
+        group_mgr
+            .set_key_set(GroupKeySet {
+                fab_idx: 1,
+                key_set_id: 1,
+                policy: 0,
+                epoch_keys,
+            })
+            .unwrap();
+        group_mgr.map_group_key(1, group_id, 1).unwrap();
+        group_mgr
+            .add_group_endpoint(1, group_id, endpoint, "")
+            .unwrap();
+
+        let mut group_acl = AclEntry::new(1, Privilege::OPERATE, AuthMode::Group);
+        group_acl.add_subject(group_id as u64).unwrap();
+        self.matter.acl_mgr.borrow_mut().add(group_acl).unwrap();
+    }
+
     pub fn handler(&self) -> ImEngineHandler<'_> {
         ImEngineHandler::new(&self.matter)
     }
@@ -247,6 +340,37 @@ impl<'a> ImEngine<'a> {
         input: &[&ImInput],
         out: &mut heapless::Vec<ImOutput, N>,
     ) -> Result<(), Error> {
+        self.process_with(&HandlerCompat(handler), input, out)
+            .map(|_| ())
+    }
+
+    /// Same as `process`, but with a handler that is given to the data model as is.
+    ///
+    /// Returns the number of standalone acknowledgements the device sent until the last response.
+    pub fn process_with<H, const N: usize>(
+        &self,
+        handler: &H,
+        input: &[&ImInput],
+        out: &mut heapless::Vec<ImOutput, N>,
+    ) -> Result<usize, Error>
+    where
+        H: DataModelHandler,
+    {
+        self.process_with_reports(handler, input, 0, out)
+    }
+
+    /// Same as `process_with`, but also wait for the device to push `reports` reports
+    /// of its subscriptions, once the responses to the inputs came
+    pub fn process_with_reports<H, const N: usize>(
+        &self,
+        handler: &H,
+        input: &[&ImInput],
+        reports: usize,
+        out: &mut heapless::Vec<ImOutput, N>,
+    ) -> Result<usize, Error>
+    where
+        H: DataModelHandler,
+    {
         self.matter.reset_transport();
 
         let clone_data = CloneData::new(
@@ -254,7 +378,7 @@ impl<'a> ImEngine<'a> {
             IM_ENGINE_PEER_ID,
             1,
             1,
-            Address::default(),
+            self.peer_addr,
             SessionMode::Case(CaseDetails::new(1, &self.cat_ids)),
         );
 
@@ -268,8 +392,8 @@ impl<'a> ImEngine<'a> {
         let mut tx_pipe_buf = [0; MAX_RX_BUF_SIZE];
         let mut rx_pipe_buf = [0; MAX_TX_BUF_SIZE];
 
-        let mut tx_buf = [0; MAX_RX_BUF_SIZE];
-        let mut rx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
 
         let tx_pipe = Pipe::new(&mut tx_buf);
         let rx_pipe = Pipe::new(&mut rx_buf);
@@ -279,8 +403,6 @@ impl<'a> ImEngine<'a> {
         let tx_pipe_buf = &mut tx_pipe_buf;
         let rx_pipe_buf = &mut rx_pipe_buf;
 
-        let handler = &handler;
-
         let mut msg_ctr = self
             .matter
             .session_mgr
@@ -292,6 +414,9 @@ impl<'a> ImEngine<'a> {
         let resp_notif = Notification::new();
         let resp_notif = &resp_notif;
 
+        let standalone_acks = Cell::new(0);
+        let standalone_acks = &standalone_acks;
+
         let mut buffers = PacketBuffers::new();
         let buffers = &mut buffers;
 
@@ -306,12 +431,22 @@ impl<'a> ImEngine<'a> {
                         verifier: VerifierData::new_with_pw(123456, *self.matter.borrow()),
                         discriminator: 250,
                     },
-                    &HandlerCompat(handler),
+                    handler,
                 ),
                 async move {
                     let mut acknowledge = false;
+                    let mut group_ctr = 1;
                     for ip in input {
-                        Self::send(ip, tx_pipe_buf, rx_pipe, msg_ctr, acknowledge).await?;
+                        if let Some(group_id) = ip.group {
+                            // Nothing comes back for a group message
+                            self.send_group(ip, tx_pipe_buf, rx_pipe, group_ctr, group_id)
+                                .await?;
+                            group_ctr += 1;
+                            continue;
+                        }
+
+                        self.send(ip, tx_pipe_buf, rx_pipe, msg_ctr, acknowledge)
+                            .await?;
                         resp_notif.wait().await;
 
                         if let Some(delay) = ip.delay {
@@ -322,7 +457,7 @@ impl<'a> ImEngine<'a> {
                         }
 
                         msg_ctr += 2;
-                        acknowledge = true;
+                        acknowledge = ip.response;
                     }
 
                     pending::<()>().await;
@@ -332,7 +467,14 @@ impl<'a> ImEngine<'a> {
                 async move {
                     out.clear();
 
-                    while out.len() < input.len() {
+                    // Standalone acknowledgements only count for the inputs which are
+                    // not expected to get a response
+                    let mut acks = 0;
+
+                    let unicast = input.iter().filter(|ip| ip.group.is_none());
+                    let expected = unicast.clone().count();
+
+                    while out.len() + acks < expected + reports {
                         let (len, _) = tx_pipe.recv(rx_pipe_buf).await;
 
                         let mut rx = Packet::new_rx(&mut rx_pipe_buf[..len]);
@@ -344,14 +486,41 @@ impl<'a> ImEngine<'a> {
                             || rx.get_proto_opcode::<secure_channel::common::OpCode>()?
                                 != secure_channel::common::OpCode::MRPStandAloneAck
                         {
+                            let action = if rx.get_proto_id() == PROTO_ID_INTERACTION_MODEL {
+                                rx.get_proto_opcode()?
+                            } else {
+                                OpCode::Reserved
+                            };
+
+                            if out.len() + acks >= expected && action != OpCode::ReportData {
+                                // Past the responses, only the pushed reports count, rather
+                                // than the retransmissions of the unacknowledged responses
+                                continue;
+                            }
+
                             out.push(ImOutput {
-                                action: rx.get_proto_opcode()?,
+                                action,
+                                proto_id: rx.get_proto_id(),
+                                opcode: rx.get_proto_raw_opcode(),
                                 data: heapless::Vec::from_slice(rx.as_slice())
                                     .map_err(|_| ErrorCode::NoSpace)?,
                             })
                             .map_err(|_| ErrorCode::NoSpace)?;
 
                             resp_notif.signal(());
+                        } else {
+                            standalone_acks.set(standalone_acks.get() + 1);
+
+                            if unicast
+                                .clone()
+                                .nth(out.len() + acks)
+                                .map(|ip| !ip.response)
+                                .unwrap_or(false)
+                            {
+                                acks += 1;
+
+                                resp_notif.signal(());
+                            }
                         }
                     }
 
@@ -362,10 +531,11 @@ impl<'a> ImEngine<'a> {
             .unwrap()
         })?;
 
-        Ok(())
+        Ok(standalone_acks.get())
     }
 
     async fn send(
+        &self,
         input: &ImInput<'_>,
         tx_buf: &mut [u8],
         rx_pipe: &Pipe<'_>,
@@ -374,8 +544,8 @@ impl<'a> ImEngine<'a> {
     ) -> Result<(), Error> {
         let mut tx = Packet::new_tx(tx_buf);
 
-        tx.set_proto_id(PROTO_ID_INTERACTION_MODEL);
-        tx.set_proto_opcode(input.action as u8);
+        tx.set_proto_id(input.proto_id);
+        tx.set_proto_opcode(input.opcode);
 
         let mut tw = TLVWriter::new(tx.get_writebuf()?);
 
@@ -395,9 +565,57 @@ impl<'a> ImEngine<'a> {
             IM_ENGINE_PEER_ID,
             false,
             Some(&[0u8; 16]),
+            None,
+        )?;
+
+        rx_pipe.send(self.peer_addr, tx.as_slice()).await;
+
+        Ok(())
+    }
+
+    async fn send_group(
+        &self,
+        input: &ImInput<'_>,
+        tx_buf: &mut [u8],
+        rx_pipe: &Pipe<'_>,
+        group_ctr: u32,
+        group_id: u16,
+    ) -> Result<(), Error> {
+        let (session_id, op_key) = {
+            let group_mgr = self.matter.group_mgr.borrow();
+            let key = group_mgr.send_key(1, group_id).unwrap();
+
+            let mut op_key = [0; 16];
+            op_key.copy_from_slice(key.op_key());
+
+            (key.session_id(), op_key)
+        };
+
+        let mut tx = Packet::new_tx(tx_buf);
+
+        tx.set_proto_id(input.proto_id);
+        tx.set_proto_opcode(input.opcode);
+
+        let mut tw = TLVWriter::new(tx.get_writebuf()?);
+
+        input.data.to_tlv(&mut tw, TagType::Anonymous)?;
+
+        tx.plain.ctr = group_ctr;
+        tx.plain.sess_id = session_id;
+        tx.plain.set_src_u64(IM_ENGINE_PEER_ID);
+        tx.plain.set_dest_group(group_id);
+        tx.proto.set_initiator();
+
+        tx.proto_encode(
+            Address::default(),
+            None,
+            IM_ENGINE_PEER_ID,
+            false,
+            Some(&op_key),
+            None,
         )?;
 
-        rx_pipe.send(Address::default(), tx.as_slice()).await;
+        rx_pipe.send(self.peer_addr, tx.as_slice()).await;
 
         Ok(())
     }
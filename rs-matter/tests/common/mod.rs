@@ -17,9 +17,11 @@
 
 pub mod attributes;
 pub mod commands;
+pub mod device;
 pub mod echo_cluster;
 pub mod handlers;
 pub mod im_engine;
+pub mod test_util;
 
 pub fn init_env_logger() {
     #[cfg(all(feature = "std", not(target_os = "espidf")))]
@@ -0,0 +1,52 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use rs_matter::{
+    error::Error,
+    interaction_model::core::OpCode,
+    tlv::{TLVWriter, TagType, ToTLV},
+};
+
+use super::im_engine::{ImEngine, ImEngineHandler, ImInput};
+
+/// A payload which is already TLV-encoded, and which is sent as is
+pub struct RawTLV<'a>(pub &'a [u8]);
+
+impl<'a> ToTLV for RawTLV<'a> {
+    fn to_tlv(&self, tw: &mut TLVWriter, _tag_type: TagType) -> Result<(), Error> {
+        tw.get_buf().append(self.0)
+    }
+}
+
+/// Replay a raw Interaction Model message through the data model of the engine
+///
+/// The payload is sent with the provided opcode over the CASE session of the engine,
+/// exactly as it was captured (e.g. from chip-tool), and the TLV payload of the
+/// response of the device is returned.
+pub fn handle_raw(
+    im: &ImEngine,
+    handler: &ImEngineHandler,
+    opcode: OpCode,
+    payload: &[u8],
+) -> Vec<u8> {
+    let input = ImInput::new(opcode, &RawTLV(payload));
+    let mut out = heapless::Vec::<_, 1>::new();
+
+    im.process(handler, &[&input], &mut out).unwrap();
+
+    out[0].data.to_vec()
+}
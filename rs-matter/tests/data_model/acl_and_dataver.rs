@@ -19,24 +19,31 @@ use rs_matter::{
     acl::{gen_noc_cat, AclEntry, AuthMode, Target},
     data_model::{
         objects::{EncodeValue, Privilege},
+        sdm::general_commissioning,
         system_model::access_control,
     },
     interaction_model::{
-        core::IMStatusCode,
-        messages::ib::{AttrData, AttrPath, AttrResp, AttrStatus, ClusterPath, DataVersionFilter},
+        core::{IMStatusCode, OpCode},
+        messages::ib::{
+            AttrData, AttrPath, AttrResp, AttrStatus, ClusterPath, CmdData, CmdPath, CmdStatus,
+            DataVersionFilter,
+        },
+        messages::msg::{ReadReq, ReportDataMsg},
         messages::GenericPath,
     },
-    tlv::{ElementType, TLVArray, TLVElement, TLVWriter, TagType},
+    tlv::{self, ElementType, FromTLV, TLVArray, TLVElement, TLVWriter, TagType},
 };
 
 use crate::{
-    attr_data_path, attr_status,
+    attr_data_path, attr_status, cmd_data,
     common::{
         attributes::*,
+        commands::*,
         echo_cluster::{self, ATTR_WRITE_DEFAULT_VALUE},
-        im_engine::{ImEngine, IM_ENGINE_PEER_ID},
+        im_engine::{ImEngine, ImInput, IM_ENGINE_PEER_ID},
         init_env_logger,
     },
+    echo_req, echo_resp,
 };
 
 #[test]
@@ -371,6 +378,103 @@ fn insufficient_perms_write() {
     );
 }
 
+#[test]
+/// Ensure that the privilege required by the attribute or the command is enforced
+/// - a View accessor can read, but can't write or invoke
+/// - an Operate accessor can invoke, but not a command requiring Administer
+fn privilege_enforcement() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    let mut acl = AclEntry::new(1, Privilege::VIEW, AuthMode::Case);
+    acl.add_subject(IM_ENGINE_PEER_ID).unwrap();
+    im.matter.acl_mgr.borrow_mut().add(acl).unwrap();
+
+    let ep0_att1 = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+    );
+    im.handle_read_reqs(
+        &handler,
+        &[AttrPath::new(&ep0_att1)],
+        &[attr_data_path!(ep0_att1, ElementType::U16(0x1234))],
+    );
+
+    let val0 = 10;
+    let attr_data0 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val0);
+    };
+    let ep0_att_write = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+    im.handle_write_reqs(
+        &handler,
+        &[AttrData::new(
+            None,
+            AttrPath::new(&ep0_att_write),
+            EncodeValue::Closure(&attr_data0),
+        )],
+        &[AttrStatus::new(
+            &ep0_att_write,
+            IMStatusCode::UnsupportedAccess,
+            0,
+        )],
+    );
+    assert_eq!(
+        ATTR_WRITE_DEFAULT_VALUE,
+        handler.echo_cluster(0).att_write.get()
+    );
+
+    let echo_path = CmdPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::Commands::EchoReq as u32),
+    );
+    let arm_failsafe_path = CmdPath::new(
+        Some(0),
+        Some(general_commissioning::ID),
+        Some(general_commissioning::Commands::ArmFailsafe as u32),
+    );
+    im.handle_commands(
+        &handler,
+        &[echo_req!(0, 5), cmd_data!(arm_failsafe_path.clone(), 5)],
+        &[
+            ExpectedInvResp::Status(CmdStatus::new(
+                echo_path,
+                IMStatusCode::UnsupportedAccess,
+                0,
+            )),
+            ExpectedInvResp::Status(CmdStatus::new(
+                arm_failsafe_path.clone(),
+                IMStatusCode::UnsupportedAccess,
+                0,
+            )),
+        ],
+    );
+
+    let mut acl = AclEntry::new(1, Privilege::OPERATE, AuthMode::Case);
+    acl.add_subject(IM_ENGINE_PEER_ID).unwrap();
+    im.matter.acl_mgr.borrow_mut().add(acl).unwrap();
+
+    im.handle_commands(
+        &handler,
+        &[echo_req!(0, 5), cmd_data!(arm_failsafe_path.clone(), 5)],
+        &[
+            echo_resp!(0, 10),
+            ExpectedInvResp::Status(CmdStatus::new(
+                arm_failsafe_path,
+                IMStatusCode::UnsupportedAccess,
+                0,
+            )),
+        ],
+    );
+}
+
 #[test]
 /// Ensure that a write to the ACL attribute instantaneously grants permission
 /// Here we have 2 ACLs, the first (basic_acl) allows access only to the ACL cluster
@@ -625,3 +729,139 @@ fn test_write_data_ver() {
 
     assert_eq!(initial_data_ver + 1, new_data_ver);
 }
+
+#[test]
+/// Writing the ACL attribute should bump the data version of the Access Control cluster,
+/// so that a read filtered on the old data version reports the new ACLs
+fn test_acl_write_bumps_data_ver() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let acl_att = GenericPath::new(
+        Some(0),
+        Some(access_control::ID),
+        Some(access_control::AttributesDiscriminants::Acl as u32),
+    );
+    let input = &[AttrPath::new(&acl_att)];
+
+    // Test 1: Simple read to retrieve the current Data Version of the Access Control cluster
+    let mut out = heapless::Vec::new();
+    let received = im.gen_read_reqs_output::<1>(&handler, input, None, &mut out);
+    let initial_data_ver = received
+        .attr_reports
+        .as_ref()
+        .unwrap()
+        .get_index(0)
+        .unwrap_data()
+        .data_ver
+        .unwrap();
+
+    let dataver_filter = [DataVersionFilter {
+        path: ClusterPath {
+            node: None,
+            endpoint: 0,
+            cluster: access_control::ID,
+        },
+        data_ver: initial_data_ver,
+    }];
+
+    // Test 2: The filter matches the current Data Version, so nothing should be reported
+    let mut out = heapless::Vec::new();
+    let received = im.gen_read_reqs_output::<1>(
+        &handler,
+        input,
+        Some(TLVArray::Slice(&dataver_filter)),
+        &mut out,
+    );
+    assert_eq!(received.attr_reports.as_ref().unwrap().iter().count(), 0);
+
+    // Test 3: Add an ACL entry, after which the same filter should no longer match
+    let mut new_acl = AclEntry::new(1, Privilege::VIEW, AuthMode::Case);
+    new_acl.add_subject(IM_ENGINE_PEER_ID + 1).unwrap();
+    im.handle_write_reqs(
+        &handler,
+        &[AttrData::new(
+            None,
+            AttrPath::new(&acl_att),
+            EncodeValue::Value(&new_acl),
+        )],
+        &[AttrStatus::new(&acl_att, IMStatusCode::Success, 0)],
+    );
+
+    let mut out = heapless::Vec::new();
+    let received = im.gen_read_reqs_output::<1>(
+        &handler,
+        input,
+        Some(TLVArray::Slice(&dataver_filter)),
+        &mut out,
+    );
+    let new_data_ver = received
+        .attr_reports
+        .as_ref()
+        .unwrap()
+        .get_index(0)
+        .unwrap_data()
+        .data_ver
+        .unwrap();
+
+    assert_eq!(initial_data_ver.wrapping_add(1), new_data_ver);
+}
+
+/// Read the ACL and return the fabric index of each of its entries
+fn read_acl_fabrics(im: &ImEngine, fabric_filtered: bool) -> heapless::Vec<u8, 8> {
+    let path = GenericPath::new(
+        Some(0),
+        Some(access_control::ID),
+        Some(access_control::AttributesDiscriminants::Acl as u32),
+    );
+    let attr_paths = [AttrPath::new(&path)];
+    let read_req = ReadReq::new(fabric_filtered).set_attr_requests(&attr_paths);
+
+    let handler = im.handler();
+    let mut out = heapless::Vec::<_, 1>::new();
+
+    im.process(
+        &handler,
+        &[&ImInput::new(OpCode::ReadRequest, &read_req)],
+        &mut out,
+    )
+    .unwrap();
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let report = ReportDataMsg::from_tlv(&root).unwrap();
+
+    let mut attr_reports = report.attr_reports.unwrap().iter();
+    let AttrResp::Data(data) = attr_reports.next().unwrap() else {
+        panic!("Unexpected attribute status in the report");
+    };
+    assert!(attr_reports.next().is_none());
+
+    data.data
+        .unwrap_tlv()
+        .unwrap()
+        .enter()
+        .unwrap()
+        .map(|entry| AclEntry::from_tlv(&entry).unwrap().fab_idx.unwrap())
+        .collect()
+}
+
+#[test]
+/// An admin reading with `FabricFiltered=false` gets the entries of all fabrics,
+/// each tagged with its fabric index
+fn test_fabric_unfiltered_read() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let mut other = AclEntry::new(2, Privilege::ADMIN, AuthMode::Case);
+    other.add_subject(IM_ENGINE_PEER_ID + 1).unwrap();
+    im.matter.acl_mgr.borrow_mut().add(other).unwrap();
+
+    assert_eq!(read_acl_fabrics(&im, true).as_slice(), &[1]);
+    assert_eq!(read_acl_fabrics(&im, false).as_slice(), &[1, 2]);
+}
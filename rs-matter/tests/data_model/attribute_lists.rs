@@ -34,7 +34,7 @@ use crate::common::{
 // Helper for handling Write Attribute sequences
 #[test]
 /// This tests all the attribute list operations
-/// add item, edit item, delete item, overwrite list, delete list
+/// add item, edit item, delete item, overwrite list, delete list, append item
 fn attr_list_ops() {
     let val0: u16 = 10;
     let val1: u16 = 15;
@@ -129,7 +129,7 @@ fn attr_list_ops() {
 
     // Test 6: Overwrite Operation - delete whole list
     att_path.list_index = None;
-    let input = &[AttrData::new(None, att_path, delete_all)];
+    let input = &[AttrData::new(None, att_path.clone(), delete_all)];
     let expected = &[AttrStatus::new(&att_data, IMStatusCode::Success, 0)];
 
     ImEngine::write_reqs(input, expected);
@@ -137,4 +137,30 @@ fn attr_list_ops() {
         let tc = tc_handle.lock().unwrap();
         assert_eq!([None, None, None, None, None], tc.write_list);
     }
+
+    // Test 7: Append Operation - a null list index appends val1
+    att_path.list_index = Some(Nullable::Null);
+    let input = &[AttrData::new(
+        None,
+        att_path.clone(),
+        EncodeValue::Value(&val1),
+    )];
+    let expected = &[AttrStatus::new(&att_data, IMStatusCode::Success, 0)];
+
+    ImEngine::write_reqs(input, expected);
+    {
+        let tc = tc_handle.lock().unwrap();
+        assert_eq!([Some(val1), None, None, None, None], tc.write_list);
+    }
+
+    // Test 8: Edit Operation - an index past the end of the list is rejected
+    att_path.list_index = Some(Nullable::NotNull(echo_cluster::WRITE_LIST_MAX as _));
+    let input = &[AttrData::new(None, att_path, EncodeValue::Value(&val0))];
+    let expected = &[AttrStatus::new(&att_data, IMStatusCode::ConstraintError, 0)];
+
+    ImEngine::write_reqs(input, expected);
+    {
+        let tc = tc_handle.lock().unwrap();
+        assert_eq!([Some(val1), None, None, None, None], tc.write_list);
+    }
 }
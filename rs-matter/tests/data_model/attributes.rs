@@ -21,16 +21,25 @@ use rs_matter::{
         objects::{EncodeValue, GlobalElements},
     },
     interaction_model::{
-        core::IMStatusCode,
+        core::{IMStatusCode, OpCode},
         messages::ib::{AttrData, AttrPath, AttrResp, AttrStatus},
-        messages::GenericPath,
+        messages::{
+            msg::{ReadReq, ReportDataMsg, WriteReq},
+            GenericPath,
+        },
     },
-    tlv::{ElementType, TLVElement, TLVWriter, TagType},
+    tlv::{self, ElementType, FromTLV, TLVElement, TLVWriter, TagType},
 };
 
 use crate::{
     attr_data, attr_data_path, attr_status,
-    common::{attributes::*, echo_cluster, im_engine::ImEngine, init_env_logger},
+    common::{
+        attributes::*,
+        echo_cluster,
+        handlers::assert_write_response,
+        im_engine::{ImEngine, ImInput},
+        init_env_logger,
+    },
 };
 
 #[test]
@@ -72,6 +81,47 @@ fn test_read_success() {
     ImEngine::read_reqs(input, expected);
 }
 
+#[test]
+fn test_read_many_paths() {
+    // 30 Attr Read Requests, alternating between att1 on endpoint 0 and att2 on
+    // endpoint 1
+    init_env_logger();
+
+    let paths: Vec<_> = (0..30)
+        .map(|i| {
+            if i % 2 == 0 {
+                GenericPath::new(
+                    Some(0),
+                    Some(echo_cluster::ID),
+                    Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+                )
+            } else {
+                GenericPath::new(
+                    Some(1),
+                    Some(echo_cluster::ID),
+                    Some(echo_cluster::AttributesDiscriminants::Att2 as u32),
+                )
+            }
+        })
+        .collect();
+
+    let input: Vec<_> = paths.iter().map(AttrPath::new).collect();
+    let expected: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            let value = if path.endpoint == Some(0) {
+                0x1234
+            } else {
+                0x5678
+            };
+
+            attr_data_path!(path, ElementType::U16(value))
+        })
+        .collect();
+
+    ImEngine::read_reqs(&input, &expected);
+}
+
 #[test]
 fn test_read_unsupported_fields() {
     // 6 reads
@@ -150,6 +200,51 @@ fn test_read_wc_endpoint_all_have_clusters() {
     ImEngine::read_reqs(input, expected);
 }
 
+#[test]
+fn test_read_wc_endpoint_pruned_cluster() {
+    // 1 Attr Read Requests
+    // - wildcard endpoint, att1
+    // - the echo cluster on endpoint 1 prunes itself, so only 1 response is expected
+    init_env_logger();
+
+    let wc_ep_att1 = GenericPath::new(
+        None,
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+    );
+    let input = &[AttrPath::new(&wc_ep_att1)];
+
+    let expected = &[attr_data!(
+        0,
+        echo_cluster::ID,
+        echo_cluster::AttributesDiscriminants::Att1,
+        ElementType::U16(0x1234)
+    )];
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+    handler.echo_cluster(1).prune_reads.set(true);
+    im.handle_read_reqs(&handler, input, expected);
+
+    // Concrete paths are never pruned
+    let ep1_att1 = GenericPath::new(
+        Some(1),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+    );
+    let input = &[AttrPath::new(&ep1_att1)];
+
+    let expected = &[attr_data!(
+        1,
+        echo_cluster::ID,
+        echo_cluster::AttributesDiscriminants::Att1,
+        ElementType::U16(0x1234)
+    )];
+    im.handle_read_reqs(&handler, input, expected);
+}
+
 #[test]
 fn test_read_wc_endpoint_only_1_has_cluster() {
     // 1 Attr Read Requests
@@ -179,7 +274,7 @@ fn test_read_wc_endpoint_only_1_has_cluster() {
 fn test_read_wc_endpoint_wc_attribute() {
     // 1 Attr Read Request
     // - wildcard endpoint, wildcard attribute
-    // - 8 responses are expected, 1+3 attributes on endpoint 0, 1+3 on endpoint 1
+    // - 16 responses are expected, 8 attributes on endpoint 0, 8 on endpoint 1
     init_env_logger();
     let wc_ep_wc_attr = GenericPath::new(None, Some(echo_cluster::ID), None);
     let input = &[AttrPath::new(&wc_ep_wc_attr)];
@@ -197,6 +292,18 @@ fn test_read_wc_endpoint_wc_attribute() {
     );
     let attr_list_tlv = attr_list.to_tlv();
 
+    let accepted_cmds = TLVHolder::new_array(
+        2,
+        &[
+            echo_cluster::Commands::EchoReq as u32,
+            echo_cluster::Commands::TimedEchoReq as u32,
+        ],
+    );
+    let accepted_cmds_tlv = accepted_cmds.to_tlv();
+
+    let generated_cmds = TLVHolder::new_array(2, &[echo_cluster::RespCommands::EchoResp as u32]);
+    let generated_cmds_tlv = generated_cmds.to_tlv();
+
     let expected = &[
         attr_data_path!(
             GenericPath::new(
@@ -238,6 +345,30 @@ fn test_read_wc_endpoint_wc_attribute() {
             ),
             ElementType::U32(echo_cluster::ATTR_CUSTOM_VALUE)
         ),
+        attr_data_path!(
+            GenericPath::new(
+                Some(0),
+                Some(echo_cluster::ID),
+                Some(GlobalElements::GeneratedCommandList as u32),
+            ),
+            generated_cmds_tlv.get_element_type().clone()
+        ),
+        attr_data_path!(
+            GenericPath::new(
+                Some(0),
+                Some(echo_cluster::ID),
+                Some(GlobalElements::AcceptedCommandList as u32),
+            ),
+            accepted_cmds_tlv.get_element_type().clone()
+        ),
+        attr_data_path!(
+            GenericPath::new(
+                Some(0),
+                Some(echo_cluster::ID),
+                Some(GlobalElements::ClusterRevision as u32),
+            ),
+            ElementType::U8(1)
+        ),
         attr_data_path!(
             GenericPath::new(
                 Some(1),
@@ -278,6 +409,30 @@ fn test_read_wc_endpoint_wc_attribute() {
             ),
             ElementType::U32(echo_cluster::ATTR_CUSTOM_VALUE)
         ),
+        attr_data_path!(
+            GenericPath::new(
+                Some(1),
+                Some(echo_cluster::ID),
+                Some(GlobalElements::GeneratedCommandList as u32),
+            ),
+            generated_cmds_tlv.get_element_type().clone()
+        ),
+        attr_data_path!(
+            GenericPath::new(
+                Some(1),
+                Some(echo_cluster::ID),
+                Some(GlobalElements::AcceptedCommandList as u32),
+            ),
+            accepted_cmds_tlv.get_element_type().clone()
+        ),
+        attr_data_path!(
+            GenericPath::new(
+                Some(1),
+                Some(echo_cluster::ID),
+                Some(GlobalElements::ClusterRevision as u32),
+            ),
+            ElementType::U8(1)
+        ),
     ];
     ImEngine::read_reqs(input, expected);
 }
@@ -335,6 +490,126 @@ fn test_write_success() {
     assert_eq!(val1, handler.echo_cluster(1).att_write.get());
 }
 
+#[test]
+fn test_write_constraint() {
+    // 2 Attr Write Request
+    // - first on endpoint 0, AttWrite, above the attribute's range
+    // - second on endpoint 1, AttWrite, at the top of the attribute's range
+    //
+    // The first write is rejected before it reaches the cluster
+    let val0 = echo_cluster::ATTR_WRITE_MAX_VALUE + 1;
+    let val1 = echo_cluster::ATTR_WRITE_MAX_VALUE;
+    init_env_logger();
+    let attr_data0 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val0);
+    };
+    let attr_data1 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val1);
+    };
+
+    let ep0_att = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+    let ep1_att = GenericPath::new(
+        Some(1),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+
+    let input = &[
+        AttrData::new(
+            None,
+            AttrPath::new(&ep0_att),
+            EncodeValue::Closure(&attr_data0),
+        ),
+        AttrData::new(
+            None,
+            AttrPath::new(&ep1_att),
+            EncodeValue::Closure(&attr_data1),
+        ),
+    ];
+    let expected = &[
+        AttrStatus::new(&ep0_att, IMStatusCode::ConstraintError, 0),
+        AttrStatus::new(&ep1_att, IMStatusCode::Success, 0),
+    ];
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+    im.handle_write_reqs(&handler, input, expected);
+
+    assert_eq!(
+        echo_cluster::ATTR_WRITE_DEFAULT_VALUE,
+        handler.echo_cluster(0).att_write.get()
+    );
+    assert_eq!(val1, handler.echo_cluster(1).att_write.get());
+}
+
+#[test]
+fn test_write_chunked() {
+    // 1 Attr Write Request split in 3 chunks on the same exchange
+    // - first chunk on endpoint 0, AttWrite
+    // - second chunk on endpoint 0, an attribute which does not exist
+    // - third chunk on endpoint 1, AttWrite
+    //
+    // The first two chunks are only acknowledged, and the response to the last one
+    // has the statuses of all three
+    let val0 = 20;
+    let val1 = 25;
+    init_env_logger();
+    let attr_data0 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val0);
+    };
+    let attr_data1 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val1);
+    };
+
+    let ep0_att = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+    let ep0_unsupported = GenericPath::new(Some(0), Some(echo_cluster::ID), Some(0x1234));
+    let ep1_att = GenericPath::new(
+        Some(1),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+
+    let chunk0 = &[AttrData::new(
+        None,
+        AttrPath::new(&ep0_att),
+        EncodeValue::Closure(&attr_data0),
+    )];
+    let chunk1 = &[AttrData::new(
+        None,
+        AttrPath::new(&ep0_unsupported),
+        EncodeValue::Closure(&attr_data0),
+    )];
+    let chunk2 = &[AttrData::new(
+        None,
+        AttrPath::new(&ep1_att),
+        EncodeValue::Closure(&attr_data1),
+    )];
+    let expected = &[
+        AttrStatus::new(&ep0_att, IMStatusCode::Success, 0),
+        AttrStatus::new(&ep0_unsupported, IMStatusCode::UnsupportedAttribute, 0),
+        AttrStatus::new(&ep1_att, IMStatusCode::Success, 0),
+    ];
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+    im.handle_chunked_write_reqs(&handler, &[chunk0, chunk1, chunk2], expected);
+
+    assert_eq!(val0, handler.echo_cluster(0).att_write.get());
+    assert_eq!(val1, handler.echo_cluster(1).att_write.get());
+}
+
 #[test]
 fn test_write_wc_endpoint() {
     // 1 Attr Write Request
@@ -478,3 +753,282 @@ fn test_write_unsupported_fields() {
         handler.echo_cluster(0).att_write.get()
     );
 }
+
+#[test]
+fn test_write_suppressed_response_with_failure() {
+    // 2 Attr Write Requests with a suppressed response
+    // - first on endpoint 0, Att1 which is not writable
+    // - second on endpoint 0, AttWrite
+    // The failure still has to be reported, so a response is sent anyway
+    let val0 = 10;
+    init_env_logger();
+    let attr_data0 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val0);
+    };
+
+    let ep0_att1 = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+    );
+    let ep0_attwrite = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+
+    let input = &[
+        AttrData::new(
+            None,
+            AttrPath::new(&ep0_att1),
+            EncodeValue::Closure(&attr_data0),
+        ),
+        AttrData::new(
+            None,
+            AttrPath::new(&ep0_attwrite),
+            EncodeValue::Closure(&attr_data0),
+        ),
+    ];
+    let expected = &[
+        AttrStatus::new(&ep0_att1, IMStatusCode::UnsupportedWrite, 0),
+        AttrStatus::new(&ep0_attwrite, IMStatusCode::Success, 0),
+    ];
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let write_req = WriteReq::new(true, input);
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process(
+        &handler,
+        &[&ImInput::new(OpCode::WriteRequest, &write_req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].action, OpCode::WriteResponse);
+    assert_write_response(&out[0], expected);
+
+    assert_eq!(val0, handler.echo_cluster(0).att_write.get());
+}
+
+#[cfg(feature = "nightly")]
+#[test]
+fn test_read_sync_handler_as_async() {
+    // A sync handler wrapped in SyncAsAsync should produce exactly the same
+    // report as when it goes through the regular compat wrapper
+    use rs_matter::data_model::objects::SyncAsAsync;
+
+    init_env_logger();
+
+    let ep0_att1 = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+    );
+    let attr_paths = [AttrPath::new(&ep0_att1)];
+    let read_req = ReadReq::new(true).set_attr_requests(&attr_paths);
+    let input = ImInput::new(OpCode::ReadRequest, &read_req);
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let mut sync_out = heapless::Vec::<_, 1>::new();
+    im.process(&handler, &[&input], &mut sync_out).unwrap();
+
+    let mut async_out = heapless::Vec::<_, 1>::new();
+    im.process_with(&SyncAsAsync(&handler), &[&input], &mut async_out)
+        .unwrap();
+
+    assert_eq!(sync_out[0].action, OpCode::ReportData);
+    assert_eq!(sync_out[0].action, async_out[0].action);
+    assert_eq!(sync_out[0].data, async_out[0].data);
+}
+
+#[cfg(all(feature = "nightly", feature = "std"))]
+#[test]
+fn test_slow_read_sends_standalone_ack() {
+    // A read which takes longer than the MRP standalone ack timeout should get
+    // acknowledged before its data response is sent
+    use embassy_time::{Duration, Timer};
+    use rs_matter::{
+        data_model::objects::{
+            AsyncHandler, AsyncMetadata, AttrDataEncoder, AttrDetails, Handler, Metadata, Node,
+        },
+        error::Error,
+    };
+
+    use crate::common::im_engine::ImEngineHandler;
+
+    struct SlowHandler<'a>(ImEngineHandler<'a>);
+
+    impl<'a> AsyncHandler for SlowHandler<'a> {
+        async fn read<'b>(
+            &'b self,
+            attr: &'b AttrDetails<'_>,
+            encoder: AttrDataEncoder<'b, '_, '_>,
+        ) -> Result<(), Error> {
+            Timer::after(Duration::from_millis(500)).await;
+
+            Handler::read(&self.0, attr, encoder)
+        }
+    }
+
+    impl<'a> AsyncMetadata for SlowHandler<'a> {
+        type MetadataGuard<'g>
+            = Node<'g>
+        where
+            Self: 'g;
+
+        async fn lock(&self) -> Self::MetadataGuard<'_> {
+            Metadata::lock(&self.0)
+        }
+    }
+
+    init_env_logger();
+
+    let ep0_att1 = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+    );
+    let attr_paths = [AttrPath::new(&ep0_att1)];
+    let read_req = ReadReq::new(true).set_attr_requests(&attr_paths);
+    let input = ImInput::new(OpCode::ReadRequest, &read_req);
+
+    let im = ImEngine::new_default();
+    let handler = SlowHandler(im.handler());
+
+    im.add_default_acl();
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    let acks = im.process_with(&handler, &[&input], &mut out).unwrap();
+
+    assert_eq!(acks, 1);
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].action, OpCode::ReportData);
+}
+
+#[test]
+fn test_write_read_f64() {
+    // An f64 attribute written and read back through the IM, with the values
+    // which do not compare equal to themselves, or have no finite neighbours
+    use core::cell::Cell;
+
+    use rs_matter::{
+        data_model::objects::{
+            self, Access, AttrDataEncoder, AttrDetails, Attribute, Cluster, Endpoint, Handler,
+            HandlerCompat, Node, NonBlockingHandler, Quality,
+        },
+        error::Error,
+    };
+
+    const CLUSTER_ID: u32 = 0xFFF1_FC20;
+    const ATTR_ID: u16 = 0;
+
+    const ENDPOINTS: &[Endpoint<'static>] = &[Endpoint {
+        id: 0,
+        device_types: &[],
+        tags: &[],
+        clusters: &[Cluster {
+            id: CLUSTER_ID,
+            revision: 1,
+            feature_map: 0,
+            attributes: &[Attribute::new(ATTR_ID, Access::RWVA, Quality::NONE)],
+            commands: &[],
+            generated_commands: &[],
+            timed_commands: &[],
+            response_commands: &[],
+            manage_commands: &[],
+            admin_commands: &[],
+        }],
+    }];
+
+    struct FloatHandler(Cell<f64>);
+
+    impl Handler for FloatHandler {
+        fn read(&self, _attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+            if let Some(writer) = encoder.with_dataver(0)? {
+                writer.set(self.0.get())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn write(&self, _attr: &AttrDetails, data: objects::AttrData) -> Result<(), Error> {
+            self.0.set(data.with_dataver(0)?.f64()?);
+
+            Ok(())
+        }
+    }
+
+    impl NonBlockingHandler for FloatHandler {}
+
+    init_env_logger();
+
+    let path = GenericPath::new(Some(0), Some(CLUSTER_ID), Some(ATTR_ID as u32));
+    let attr_paths = [AttrPath::new(&path)];
+    let read_req = ReadReq::new(true).set_attr_requests(&attr_paths);
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let float = FloatHandler(Cell::new(0.0));
+    let node = Node {
+        id: 0,
+        endpoints: ENDPOINTS,
+    };
+    let handler = (node, &float);
+    let handler = HandlerCompat(&handler);
+
+    // A NaN with a payload, which must survive as is
+    let nan = f64::from_bits(0x7ff8_0000_dead_beef);
+
+    for value in [nan, f64::INFINITY, f64::NEG_INFINITY] {
+        let attr_data = |tag, t: &mut TLVWriter| {
+            let _ = t.f64(tag, value);
+        };
+        let input = &[AttrData::new(
+            None,
+            AttrPath::new(&path),
+            EncodeValue::Closure(&attr_data),
+        )];
+        let write_req = WriteReq::new(false, input);
+
+        let mut out = heapless::Vec::<_, 1>::new();
+        im.process_with(
+            &handler,
+            &[&ImInput::new(OpCode::WriteRequest, &write_req)],
+            &mut out,
+        )
+        .unwrap();
+
+        assert_write_response(&out[0], &[AttrStatus::new(&path, IMStatusCode::Success, 0)]);
+        assert_eq!(float.0.get().to_bits(), value.to_bits());
+
+        let mut out = heapless::Vec::<_, 1>::new();
+        im.process_with(
+            &handler,
+            &[&ImInput::new(OpCode::ReadRequest, &read_req)],
+            &mut out,
+        )
+        .unwrap();
+
+        let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+        let report = ReportDataMsg::from_tlv(&root).unwrap();
+        let mut reports = report.attr_reports.unwrap().iter();
+
+        let Some(AttrResp::Data(data)) = reports.next() else {
+            panic!("Expected the attribute data");
+        };
+        assert!(reports.next().is_none());
+
+        let read = data.data.unwrap_tlv().unwrap().f64().unwrap();
+        assert_eq!(read.to_bits(), value.to_bits());
+    }
+}
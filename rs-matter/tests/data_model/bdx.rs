@@ -0,0 +1,186 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+use rs_matter::{
+    bdx::{Drive, Message, StatusCode, TransferAccept, TransferInit, PROTO_ID_BDX},
+    data_model::cluster_ota_provider::{ImageInfo, ImageStore, OtaProviderCluster},
+    error::{Error, ErrorCode},
+    secure_channel::{
+        common::{OpCode, PROTO_ID_SECURE_CHANNEL},
+        status_report::GeneralCode,
+    },
+    utils::{rand::dummy_rand, writebuf::WriteBuf},
+};
+
+use crate::common::{
+    im_engine::{ImEngine, ImInput},
+    init_env_logger,
+    test_util::RawTLV,
+};
+
+const IMAGE_LEN: usize = 700;
+
+/// The image served by the OTA provider
+struct Store([u8; IMAGE_LEN]);
+
+impl Store {
+    fn new() -> Self {
+        let mut image = [0; IMAGE_LEN];
+        for (i, byte) in image.iter_mut().enumerate() {
+            *byte = (i % 251) as u8;
+        }
+
+        Self(image)
+    }
+}
+
+impl ImageStore for Store {
+    fn latest(&self, _vendor_id: u16, _product_id: u16) -> Option<ImageInfo<'_>> {
+        Some(ImageInfo {
+            version: 2,
+            version_str: "2.0",
+            file_designator: "fw-2.bin",
+        })
+    }
+
+    fn read(&self, file_designator: &str, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        if file_designator != "fw-2.bin" {
+            Err(ErrorCode::NotFound)?;
+        }
+
+        let data = self.0.get(offset as usize..).unwrap_or(&[]);
+        let len = data.len().min(buf.len());
+
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+}
+
+fn encode<'b>(msg: &Message, buf: &'b mut [u8]) -> &'b [u8] {
+    let mut wb = WriteBuf::new(buf);
+    msg.encode(&mut wb).unwrap();
+
+    let len = wb.as_slice().len();
+
+    &buf[..len]
+}
+
+fn receive_init(file_designator: &[u8]) -> Message<'_> {
+    Message::ReceiveInit(TransferInit {
+        drive: Drive::Receiver,
+        max_block_size: 512,
+        start_offset: None,
+        max_length: None,
+        file_designator,
+    })
+}
+
+#[test]
+fn test_bdx_download_image() {
+    // A requestor downloads the image of the OTA provider over the exchange of
+    // its ReceiveInit, driving the transfer with BlockQuery's:
+    // - ReceiveInit, answered by a ReceiveAccept
+    // - BlockQuery, answered by the first block of 512 bytes
+    // - BlockQuery, answered by the last block of the remaining 188 bytes
+    // - BlockAckEof, which is only acknowledged
+    init_env_logger();
+
+    let store = Store::new();
+    let provider = OtaProviderCluster::new(&store, dummy_rand);
+
+    let msgs = [
+        receive_init(b"fw-2.bin"),
+        Message::BlockQuery(0),
+        Message::BlockQuery(1),
+        Message::BlockAckEof(1),
+    ];
+
+    let mut bufs = [[0; 64]; 4];
+    let payloads = msgs
+        .iter()
+        .zip(bufs.iter_mut())
+        .map(|(msg, buf)| RawTLV(encode(msg, buf)))
+        .collect::<Vec<_>>();
+
+    let inputs = [
+        ImInput::new_proto(PROTO_ID_BDX, msgs[0].opcode() as _, &payloads[0]),
+        ImInput::new_proto(PROTO_ID_BDX, msgs[1].opcode() as _, &payloads[1]),
+        ImInput::new_proto(PROTO_ID_BDX, msgs[2].opcode() as _, &payloads[2]),
+        ImInput::new_proto_unanswered(PROTO_ID_BDX, msgs[3].opcode() as _, &payloads[3]),
+    ];
+
+    let mut im = ImEngine::new_default();
+    im.matter.set_bdx_files(&provider);
+
+    let mut out = heapless::Vec::<_, 3>::new();
+    im.process(&im.handler(), &inputs.iter().collect::<Vec<_>>(), &mut out)
+        .unwrap();
+
+    assert!(out.iter().all(|out| out.proto_id == PROTO_ID_BDX));
+
+    let replies = out
+        .iter()
+        .map(|out| Message::decode(out.opcode, &out.data).unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        replies,
+        [
+            Message::ReceiveAccept(TransferAccept {
+                drive: Drive::Receiver,
+                max_block_size: 512,
+                length: None,
+            }),
+            Message::Block(0, &store.0[..512]),
+            Message::BlockEof(1, &store.0[512..]),
+        ]
+    );
+}
+
+#[test]
+fn test_bdx_unknown_file() {
+    // The transfer of a file which the provider does not serve is aborted with
+    // a status report
+    init_env_logger();
+
+    let store = Store::new();
+    let provider = OtaProviderCluster::new(&store, dummy_rand);
+
+    let init = receive_init(b"fw-3.bin");
+
+    let mut buf = [0; 64];
+    let payload = RawTLV(encode(&init, &mut buf));
+
+    let input = ImInput::new_proto(PROTO_ID_BDX, init.opcode() as _, &payload);
+
+    let mut im = ImEngine::new_default();
+    im.matter.set_bdx_files(&provider);
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process(&im.handler(), &[&input], &mut out).unwrap();
+
+    assert_eq!(out[0].proto_id, PROTO_ID_SECURE_CHANNEL);
+    assert_eq!(out[0].opcode, OpCode::StatusReport as u8);
+
+    let mut expected = [0; 8];
+    let mut wb = WriteBuf::new(&mut expected);
+    wb.le_u16(GeneralCode::Failure as _).unwrap();
+    wb.le_u32(PROTO_ID_BDX as _).unwrap();
+    wb.le_u16(StatusCode::FileDesignatorUnknown as _).unwrap();
+
+    assert_eq!(out[0].data.as_slice(), &expected);
+}
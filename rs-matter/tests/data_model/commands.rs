@@ -16,17 +16,28 @@
  */
 
 use crate::{
-    cmd_data,
-    common::{commands::*, echo_cluster, im_engine::ImEngine, init_env_logger},
+    attr_data_path, cmd_data,
+    common::{
+        attributes::*,
+        commands::*,
+        echo_cluster,
+        im_engine::{ImEngine, ImInput},
+        init_env_logger,
+    },
     echo_req, echo_resp,
 };
 
 use rs_matter::{
     data_model::{cluster_on_off, objects::EncodeValue},
     interaction_model::{
-        core::IMStatusCode,
-        messages::ib::{CmdData, CmdPath, CmdStatus},
+        core::{IMStatusCode, OpCode, MAX_PATHS_PER_INVOKE},
+        messages::ib::{AttrData, AttrPath, AttrResp, CmdData, CmdPath, CmdStatus, InvResp},
+        messages::{
+            msg::{InvReq, InvResp as InvRespMsg, ReadReq, ReportDataMsg, StatusResp},
+            GenericPath,
+        },
     },
+    tlv::{self, ElementType, FromTLV, TLVArray, TLVElement, TagType},
 };
 
 #[test]
@@ -96,6 +107,35 @@ fn test_invoke_cmds_unsupported_fields() {
     ImEngine::commands(input, expected);
 }
 
+#[test]
+fn test_invoke_cmds_wildcard_cluster_or_command() {
+    // 2 commands
+    // - cluster is wildcard - Cluster cannot be wildcard - UnsupportedCluster
+    // - command is wildcard - Command cannot be wildcard - UnsupportedCommand
+    init_env_logger();
+
+    let wc_cluster = CmdPath::new(Some(0), None, Some(echo_cluster::Commands::EchoReq as u32));
+    let wc_command = CmdPath::new(Some(0), Some(echo_cluster::ID), None);
+    let input = &[
+        cmd_data!(wc_cluster.clone(), 5),
+        cmd_data!(wc_command.clone(), 5),
+    ];
+
+    let expected = &[
+        ExpectedInvResp::Status(CmdStatus::new(
+            wc_cluster,
+            IMStatusCode::UnsupportedCluster,
+            0,
+        )),
+        ExpectedInvResp::Status(CmdStatus::new(
+            wc_command,
+            IMStatusCode::UnsupportedCommand,
+            0,
+        )),
+    ];
+    ImEngine::commands(input, expected);
+}
+
 #[test]
 fn test_invoke_cmd_wc_endpoint_all_have_clusters() {
     // 1 echo Request with wildcard endpoint
@@ -135,3 +175,877 @@ fn test_invoke_cmd_wc_endpoint_only_1_has_cluster() {
     ))];
     ImEngine::commands(input, expected);
 }
+
+#[test]
+fn test_invoke_cmd_suppressed_response() {
+    // 1 on command for on/off cluster with a suppressed response
+    // should turn the light on without generating a response
+    init_env_logger();
+
+    let target = CmdPath::new(
+        Some(1),
+        Some(cluster_on_off::ID),
+        Some(cluster_on_off::CommandsDiscriminants::On as u32),
+    );
+    let input = &[cmd_data!(target, 1)];
+    let req = InvReq {
+        suppress_response: Some(true),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process(
+        &handler,
+        &[&ImInput::new_unanswered(OpCode::InvokeRequest, &req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert!(out.is_empty());
+
+    let on_off = GenericPath::new(
+        Some(1),
+        Some(cluster_on_off::ID),
+        Some(cluster_on_off::AttributesDiscriminants::OnOff as u32),
+    );
+    im.handle_read_reqs(
+        &handler,
+        &[AttrPath::new(&on_off)],
+        &[attr_data_path!(on_off, ElementType::True)],
+    );
+}
+
+#[test]
+fn test_invoke_cmds_groupcast() {
+    // A group invoke of a toggle and an echo request on endpoint 1, followed by a read
+    // of the on/off attribute:
+    // - the light should be toggled on, without the group invoke getting any response
+    // - the echo request responds with data, so it should be dropped
+    init_env_logger();
+
+    const GROUP_ID: u16 = 0x0101;
+
+    let toggle = CmdPath::new(
+        Some(1),
+        Some(cluster_on_off::ID),
+        Some(cluster_on_off::CommandsDiscriminants::Toggle as u32),
+    );
+    let input = &[cmd_data!(toggle, 1), echo_req!(1, 5)];
+    let req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    let on_off = GenericPath::new(
+        Some(1),
+        Some(cluster_on_off::ID),
+        Some(cluster_on_off::AttributesDiscriminants::OnOff as u32),
+    );
+    let read_paths = &[AttrPath::new(&on_off)];
+    let read_req = ReadReq::new(true).set_attr_requests(read_paths);
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+    im.add_group(GROUP_ID, 1, &[0x5a; 16]);
+
+    // Any response to the group invoke would not fit
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process(
+        &handler,
+        &[
+            &ImInput::new_groupcast(OpCode::InvokeRequest, &req, GROUP_ID),
+            &ImInput::new(OpCode::ReadRequest, &read_req),
+        ],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out[0].action, OpCode::ReportData);
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    assert_attr_report(
+        &ReportDataMsg::from_tlv(&root).unwrap(),
+        &[attr_data_path!(on_off, ElementType::True)],
+    );
+}
+
+#[test]
+fn test_invoke_batched_cmds_echo_refs() {
+    // 2 echo Requests in a single batched invoke
+    // - one on endpoint 0 with data 5 and reference 1
+    // - another on endpoint 1 with data 10 and reference 2
+    // each response should carry the reference of its command
+    init_env_logger();
+
+    let input = &[
+        echo_req!(0, 5).set_command_ref(1),
+        echo_req!(1, 10).set_command_ref(2),
+    ];
+    let req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process(
+        &handler,
+        &[&ImInput::new(OpCode::InvokeRequest, &req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out[0].action, OpCode::InvokeResponse);
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let resp = InvRespMsg::from_tlv(&root).unwrap();
+
+    let refs = resp
+        .inv_responses
+        .unwrap()
+        .iter()
+        .map(|inv_response| match inv_response {
+            InvResp::Cmd(cmd) => cmd.command_ref,
+            InvResp::Status(status) => status.command_ref(),
+        })
+        .collect::<heapless::Vec<_, 2>>();
+
+    assert_eq!(refs.as_slice(), &[Some(1), Some(2)]);
+}
+
+#[test]
+fn test_invoke_batched_cmds_too_many() {
+    // A batched invoke with more commands than we support is rejected as a whole
+    init_env_logger();
+
+    let input = &[
+        echo_req!(0, 1).set_command_ref(1),
+        echo_req!(0, 2).set_command_ref(2),
+        echo_req!(0, 3).set_command_ref(3),
+        echo_req!(0, 4).set_command_ref(4),
+        echo_req!(0, 5).set_command_ref(5),
+    ];
+    assert!(input.len() > MAX_PATHS_PER_INVOKE);
+
+    let req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process(
+        &handler,
+        &[&ImInput::new(OpCode::InvokeRequest, &req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out[0].action, OpCode::StatusResponse);
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let status_resp = StatusResp::from_tlv(&root).unwrap();
+    assert_eq!(status_resp.status, IMStatusCode::InvalidAction);
+}
+
+#[test]
+fn test_invoke_batched_cmds_duplicate_ref() {
+    // A batched invoke with two commands sharing a command reference is rejected as a whole
+    init_env_logger();
+
+    let input = &[
+        echo_req!(0, 5).set_command_ref(1),
+        echo_req!(1, 10).set_command_ref(1),
+    ];
+
+    let req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process(
+        &handler,
+        &[&ImInput::new(OpCode::InvokeRequest, &req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out[0].action, OpCode::StatusResponse);
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let status_resp = StatusResp::from_tlv(&root).unwrap();
+    assert_eq!(status_resp.status, IMStatusCode::InvalidAction);
+}
+
+#[cfg(all(feature = "nightly", feature = "std"))]
+#[test]
+fn test_invoke_stalled_handler_times_out() {
+    // A command whose handler never completes is failed with Busy once the command
+    // timeout elapses, as per the timer
+    use core::{cell::Cell, time::Duration};
+
+    use embassy_futures::select::select;
+    use rs_matter::{
+        data_model::{
+            core::DataModel,
+            objects::{
+                AsyncHandler, AsyncMetadata, AttrDataEncoder, AttrDetails, CmdDataEncoder,
+                CmdDetails, Handler, Metadata, Node,
+            },
+        },
+        error::Error,
+        interaction_model::core::PROTO_ID_INTERACTION_MODEL,
+        tlv::{TLVWriter, ToTLV},
+        transport::{
+            exchange::Exchange,
+            network::Address,
+            packet::{Packet, MAX_RX_BUF_SIZE, MAX_RX_STATUS_BUF_SIZE, MAX_TX_BUF_SIZE},
+            session::{CaseDetails, CloneData, NocCatIds, SessionMode},
+        },
+        utils::timer::Timer,
+    };
+
+    use crate::common::im_engine::{ImEngineHandler, IM_ENGINE_PEER_ID, IM_ENGINE_REMOTE_PEER_ID};
+
+    struct StalledHandler<'a>(ImEngineHandler<'a>);
+
+    impl<'a> AsyncHandler for StalledHandler<'a> {
+        async fn read<'b>(
+            &'b self,
+            attr: &'b AttrDetails<'_>,
+            encoder: AttrDataEncoder<'b, '_, '_>,
+        ) -> Result<(), Error> {
+            Handler::read(&self.0, attr, encoder)
+        }
+
+        async fn invoke<'b>(
+            &'b self,
+            _exchange: &'b Exchange<'_>,
+            _cmd: &'b CmdDetails<'_>,
+            _data: &'b TLVElement<'_>,
+            _encoder: CmdDataEncoder<'b, '_, '_>,
+        ) -> Result<(), Error> {
+            core::future::pending().await
+        }
+    }
+
+    impl<'a> AsyncMetadata for StalledHandler<'a> {
+        type MetadataGuard<'g>
+            = Node<'g>
+        where
+            Self: 'g;
+
+        async fn lock(&self) -> Self::MetadataGuard<'_> {
+            Metadata::lock(&self.0)
+        }
+    }
+
+    /// A timer which always elapsed already, recording the duration it was asked to wait
+    #[derive(Default)]
+    struct ElapsedTimer(Cell<Option<Duration>>);
+
+    impl Timer for ElapsedTimer {
+        type Future = core::future::Ready<()>;
+
+        fn after(&self, duration: Duration) -> Self::Future {
+            self.0.set(Some(duration));
+            core::future::ready(())
+        }
+    }
+
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let handler = StalledHandler(im.handler());
+
+    let timeout = Duration::from_secs(10);
+    let timer = ElapsedTimer::default();
+
+    let dm = DataModel::new(&handler, &im.matter.subscription_mgr)
+        .with_cmd_timeout(timeout)
+        .with_timer(&timer);
+
+    let session_id = {
+        let mut session_mgr = im.matter.session_mgr.borrow_mut();
+        let sess_index = session_mgr
+            .clone_session(&CloneData::new(
+                IM_ENGINE_REMOTE_PEER_ID,
+                IM_ENGINE_PEER_ID,
+                1,
+                1,
+                Address::default(),
+                SessionMode::Case(CaseDetails::new(1, &NocCatIds::default())),
+            ))
+            .unwrap();
+
+        session_mgr
+            .mut_by_index(sess_index)
+            .unwrap()
+            .get_session_id()
+    };
+    let mut exchange = im.matter.new_exchange(&session_id).unwrap();
+
+    let input = &[echo_req!(0, 5)];
+    let inv_req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    // The InvokeRequest, as received by the device
+    let mut req_buf = [0; MAX_TX_BUF_SIZE];
+    let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+    let len = {
+        let mut req = Packet::new_tx(&mut req_buf);
+        req.set_proto_id(PROTO_ID_INTERACTION_MODEL);
+        req.set_proto_opcode(OpCode::InvokeRequest as u8);
+
+        inv_req
+            .to_tlv(
+                &mut TLVWriter::new(req.get_writebuf().unwrap()),
+                TagType::Anonymous,
+            )
+            .unwrap();
+
+        req.proto_encode(Address::default(), None, 0, true, None, None)
+            .unwrap();
+
+        let len = req.as_slice().len();
+        rx_buf[..len].copy_from_slice(req.as_slice());
+
+        len
+    };
+
+    let mut rx = Packet::new_rx(&mut rx_buf[..len]);
+    rx.plain_hdr_decode().unwrap();
+    rx.proto_decode(IM_ENGINE_PEER_ID, None).unwrap();
+
+    let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+    let mut sx_buf = [0; MAX_RX_STATUS_BUF_SIZE];
+    let mut tx = Packet::new_tx(&mut tx_buf);
+    let mut rx_status = Packet::new_rx(&mut sx_buf);
+
+    let mut sent_buf = [0; MAX_TX_BUF_SIZE];
+    let mut resp_buf = [0; MAX_RX_BUF_SIZE];
+
+    embassy_futures::block_on(select(
+        dm.handle(&mut exchange, &mut rx, &mut tx, &mut rx_status),
+        async {
+            // Capture the response as it goes out, without ever acknowledging it
+            let mut sent = Packet::new_tx(&mut sent_buf);
+            assert!(im.matter.pull_tx(&mut sent).await.unwrap());
+
+            let len = sent.as_slice().len();
+            resp_buf[..len].copy_from_slice(sent.as_slice());
+
+            let mut resp = Packet::new_rx(&mut resp_buf[..len]);
+            resp.plain_hdr_decode().unwrap();
+            resp.proto_decode(IM_ENGINE_REMOTE_PEER_ID, Some(&[0u8; 16]))
+                .unwrap();
+
+            assert_eq!(
+                resp.get_proto_opcode::<OpCode>().unwrap(),
+                OpCode::InvokeResponse
+            );
+
+            let root = tlv::get_root_node_struct(resp.as_slice()).unwrap();
+            let resp = InvRespMsg::from_tlv(&root).unwrap();
+            assert_inv_response(
+                &resp,
+                &[ExpectedInvResp::Status(CmdStatus::new(
+                    CmdPath::new(
+                        Some(0),
+                        Some(echo_cluster::ID),
+                        Some(echo_cluster::Commands::EchoReq as u32),
+                    ),
+                    IMStatusCode::Busy,
+                    0,
+                ))],
+            );
+        },
+    ));
+
+    assert_eq!(timer.0.get(), Some(timeout));
+}
+
+#[test]
+fn test_invoke_wifi_network_commissioning() {
+    // The root endpoint of a Wi-Fi device configures its networks under the fail-safe:
+    // - AddOrUpdateWiFiNetwork before arming the fail-safe - FailSafeRequired
+    // - ArmFailSafe
+    // - AddOrUpdateWiFiNetwork
+    // - ConnectNetwork, after which the network is reported as connected
+    use core::cell::RefCell;
+
+    use rs_matter::{
+        data_model::{
+            objects::{HandlerCompat, Node},
+            root_endpoint,
+            sdm::{
+                general_commissioning,
+                nw_commissioning::{
+                    self, NetworkCommissioningStatus, NetworkDriver, WifiDriver, WifiScanResult,
+                    MAX_SCAN_RESULTS,
+                },
+            },
+        },
+        tlv::TLVWriter,
+    };
+
+    #[derive(Default)]
+    struct MockWifi {
+        connected: RefCell<heapless::Vec<u8, 32>>,
+    }
+
+    impl WifiDriver for MockWifi {
+        fn scan(
+            &self,
+            _ssid: Option<&[u8]>,
+            _results: &mut heapless::Vec<WifiScanResult, MAX_SCAN_RESULTS>,
+        ) -> Result<(), NetworkCommissioningStatus> {
+            Ok(())
+        }
+
+        fn connect(
+            &self,
+            ssid: &[u8],
+            _credentials: &[u8],
+        ) -> Result<(), NetworkCommissioningStatus> {
+            *self.connected.borrow_mut() = heapless::Vec::from_slice(ssid).unwrap();
+
+            Ok(())
+        }
+    }
+
+    const ENDPOINTS: &[rs_matter::data_model::objects::Endpoint<'static>] =
+        &[root_endpoint::wifi_endpoint(0)];
+
+    init_env_logger();
+
+    let arm = |tag, t: &mut TLVWriter| {
+        let _ = t.start_struct(tag);
+        let _ = t.u16(TagType::Context(0), 60);
+        let _ = t.u64(TagType::Context(1), 1);
+        let _ = t.end_container();
+    };
+    let add = |tag, t: &mut TLVWriter| {
+        let _ = t.start_struct(tag);
+        let _ = t.str8(TagType::Context(0), b"home");
+        let _ = t.str8(TagType::Context(1), b"secret");
+        let _ = t.end_container();
+    };
+    let connect = |tag, t: &mut TLVWriter| {
+        let _ = t.start_struct(tag);
+        let _ = t.str8(TagType::Context(0), b"home");
+        let _ = t.end_container();
+    };
+
+    let nw_cmd = |cmd: nw_commissioning::Commands| {
+        CmdPath::new(Some(0), Some(nw_commissioning::ID), Some(cmd as u32))
+    };
+    let nw_resp = |cmd: nw_commissioning::RespCommands| {
+        CmdPath::new(Some(0), Some(nw_commissioning::ID), Some(cmd as u32))
+    };
+
+    let add_req = [CmdData::new(
+        nw_cmd(nw_commissioning::Commands::AddOrUpdateWifiNetwork),
+        EncodeValue::Closure(&add),
+    )];
+    let arm_req = [CmdData::new(
+        CmdPath::new(
+            Some(0),
+            Some(general_commissioning::ID),
+            Some(general_commissioning::Commands::ArmFailsafe as u32),
+        ),
+        EncodeValue::Closure(&arm),
+    )];
+    let connect_req = [CmdData::new(
+        nw_cmd(nw_commissioning::Commands::ConnectNetwork),
+        EncodeValue::Closure(&connect),
+    )];
+
+    let inv_reqs = [&add_req[..], &arm_req, &add_req, &connect_req].map(|input| InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    });
+
+    let networks = GenericPath::new(
+        Some(0),
+        Some(nw_commissioning::ID),
+        Some(nw_commissioning::AttributesDiscriminants::Networks as u32),
+    );
+    let attr_paths = [AttrPath::new(&networks)];
+    let read_req = ReadReq::new(true).set_attr_requests(&attr_paths);
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let driver = MockWifi::default();
+    let node = Node {
+        id: 0,
+        endpoints: ENDPOINTS,
+    };
+    let root = root_endpoint::wireless_handler(0, &node, &im.matter, NetworkDriver::Wifi(&driver));
+    let handler = (node, &root);
+
+    let inputs = [
+        ImInput::new(OpCode::InvokeRequest, &inv_reqs[0]),
+        ImInput::new(OpCode::InvokeRequest, &inv_reqs[1]),
+        ImInput::new(OpCode::InvokeRequest, &inv_reqs[2]),
+        ImInput::new(OpCode::InvokeRequest, &inv_reqs[3]),
+        ImInput::new(OpCode::ReadRequest, &read_req),
+    ];
+
+    let mut out = heapless::Vec::<_, 5>::new();
+    im.process_with(
+        &HandlerCompat(&handler),
+        &inputs.iter().collect::<heapless::Vec<_, 5>>(),
+        &mut out,
+    )
+    .unwrap();
+
+    let expected: [&[ExpectedInvResp]; 4] = [
+        &[ExpectedInvResp::Status(CmdStatus::new(
+            nw_cmd(nw_commissioning::Commands::AddOrUpdateWifiNetwork),
+            IMStatusCode::FailSafeRequired,
+            0,
+        ))],
+        &[ExpectedInvResp::Cmd(
+            CmdPath::new(
+                Some(0),
+                Some(general_commissioning::ID),
+                Some(general_commissioning::RespCommands::ArmFailsafeResp as u32),
+            ),
+            0,
+        )],
+        &[ExpectedInvResp::Cmd(
+            nw_resp(nw_commissioning::RespCommands::NetworkConfigResp),
+            NetworkCommissioningStatus::Success as _,
+        )],
+        &[ExpectedInvResp::Cmd(
+            nw_resp(nw_commissioning::RespCommands::ConnectNetworkResp),
+            NetworkCommissioningStatus::Success as _,
+        )],
+    ];
+
+    for (out, expected) in out.iter().zip(expected) {
+        assert_eq!(out.action, OpCode::InvokeResponse);
+
+        let root = tlv::get_root_node_struct(&out.data).unwrap();
+        let resp = InvRespMsg::from_tlv(&root).unwrap();
+        assert_inv_response(&resp, expected);
+    }
+
+    assert_eq!(driver.connected.borrow().as_slice(), b"home");
+
+    let root = tlv::get_root_node_struct(&out[4].data).unwrap();
+    let report = ReportDataMsg::from_tlv(&root).unwrap();
+    let Some(AttrResp::Data(data)) = report.attr_reports.unwrap().iter().next() else {
+        panic!("Expected the Networks attribute");
+    };
+
+    let mut networks = data.data.unwrap_tlv().unwrap().enter().unwrap();
+    let network = networks.next().unwrap();
+    assert_eq!(network.find_tag(0).unwrap().slice().unwrap(), b"home");
+    assert!(network.find_tag(1).unwrap().bool().unwrap());
+    assert!(networks.next().is_none());
+}
+
+#[test]
+fn test_invoke_ota_query_image() {
+    // A device serving as an OTA provider offers its newer image to a requestor,
+    // with the URI of the image on our node and the token to apply it with
+    use rs_matter::{
+        data_model::{
+            cluster_ota_provider::{
+                self, DownloadProtocol, ImageInfo, ImageStore, OtaProviderCluster, QueryImageReq,
+                QueryImageResp, QueryImageStatus,
+            },
+            device_types::DEV_TYPE_ROOT_NODE,
+            objects::{EmptyHandler, Endpoint, HandlerCompat, Node},
+        },
+        error::Error,
+        utils::rand::dummy_rand,
+    };
+
+    use crate::common::im_engine::IM_ENGINE_REMOTE_PEER_ID;
+
+    struct Store;
+
+    impl ImageStore for Store {
+        fn latest(&self, _vendor_id: u16, _product_id: u16) -> Option<ImageInfo<'_>> {
+            Some(ImageInfo {
+                version: 2,
+                version_str: "2.0",
+                file_designator: "fw-2.bin",
+            })
+        }
+
+        fn read(
+            &self,
+            _file_designator: &str,
+            _offset: u64,
+            _buf: &mut [u8],
+        ) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    const ENDPOINTS: &[Endpoint<'static>] = &[Endpoint {
+        id: 0,
+        clusters: &[cluster_ota_provider::CLUSTER],
+        device_types: &[DEV_TYPE_ROOT_NODE],
+        tags: &[],
+    }];
+
+    init_env_logger();
+
+    let protocols = [DownloadProtocol::BdxSynchronous];
+    let query = QueryImageReq {
+        vendor_id: 0xfff1,
+        product_id: 0x8000,
+        software_version: 1,
+        protocols_supported: TLVArray::new(&protocols),
+        hardware_version: None,
+        location: None,
+        requestor_can_consent: None,
+        metadata_for_provider: None,
+    };
+
+    let input = [CmdData::new(
+        CmdPath::new(
+            Some(0),
+            Some(cluster_ota_provider::ID),
+            Some(cluster_ota_provider::Commands::QueryImage as u32),
+        ),
+        EncodeValue::Value(&query),
+    )];
+    let inv_req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(&input)),
+    };
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let handler = (
+        Node {
+            id: 0,
+            endpoints: ENDPOINTS,
+        },
+        EmptyHandler.chain(
+            0,
+            cluster_ota_provider::ID,
+            OtaProviderCluster::new(&Store, dummy_rand),
+        ),
+    );
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process_with(
+        &HandlerCompat(&handler),
+        &[&ImInput::new(OpCode::InvokeRequest, &inv_req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out[0].action, OpCode::InvokeResponse);
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let resp = InvRespMsg::from_tlv(&root).unwrap();
+    let Some(InvResp::Cmd(cmd)) = resp.inv_responses.unwrap().iter().next() else {
+        panic!("Expected the QueryImageResponse");
+    };
+
+    assert_eq!(
+        cmd.path,
+        CmdPath::new(
+            Some(0),
+            Some(cluster_ota_provider::ID),
+            Some(cluster_ota_provider::RespCommands::QueryImageResponse as u32),
+        )
+    );
+
+    let resp = QueryImageResp::from_tlv(&cmd.data.unwrap_tlv().unwrap()).unwrap();
+
+    assert_eq!(resp.status, QueryImageStatus::UpdateAvailable);
+    assert_eq!(
+        resp.image_uri.unwrap().as_str(),
+        format!("bdx://{:016X}/fw-2.bin", IM_ENGINE_REMOTE_PEER_ID)
+    );
+    assert_eq!(resp.software_version, Some(2));
+    assert_eq!(resp.update_token.unwrap().len(), 8);
+}
+
+#[test]
+fn test_invoke_change_to_mode() {
+    // 2 ChangeToMode commands, followed by a read of the current mode
+    // - to a supported mode - Success
+    // - to a mode which is not supported - Failure, with the UnsupportedMode cluster status
+    // the current mode should be the one of the first command
+    use core::cell::Cell;
+
+    use rs_matter::{
+        data_model::{
+            cluster_mode_select::{
+                self, ChangeToModeReq, ModeHandler, ModeOption, ModeSelectCluster, StatusCode,
+            },
+            device_types::DEV_TYPE_ROOT_NODE,
+            objects::{EmptyHandler, Endpoint, HandlerCompat, Node},
+        },
+        utils::rand::dummy_rand,
+    };
+
+    #[derive(Default)]
+    struct Actuator(Cell<Option<u8>>);
+
+    impl ModeHandler for Actuator {
+        fn set_mode(&self, mode: u8) {
+            self.0.set(Some(mode));
+        }
+    }
+
+    const ENDPOINTS: &[Endpoint<'static>] = &[Endpoint {
+        id: 0,
+        clusters: &[cluster_mode_select::CLUSTER],
+        device_types: &[DEV_TYPE_ROOT_NODE],
+        tags: &[],
+    }];
+
+    const MODES: &[ModeOption<'static>] = &[
+        ModeOption {
+            label: "Normal",
+            mode: 0,
+            semantic_tags: &[],
+        },
+        ModeOption {
+            label: "Eco",
+            mode: 4,
+            semantic_tags: &[],
+        },
+    ];
+
+    init_env_logger();
+
+    let change_to_mode = CmdPath::new(
+        Some(0),
+        Some(cluster_mode_select::ID),
+        Some(cluster_mode_select::Commands::ChangeToMode as u32),
+    );
+
+    let reqs = [
+        ChangeToModeReq { new_mode: 4 },
+        ChangeToModeReq { new_mode: 1 },
+    ];
+    let inputs = [
+        [CmdData::new(
+            change_to_mode.clone(),
+            EncodeValue::Value(&reqs[0]),
+        )],
+        [CmdData::new(
+            change_to_mode.clone(),
+            EncodeValue::Value(&reqs[1]),
+        )],
+    ];
+    let inv_reqs = [&inputs[0], &inputs[1]].map(|input| InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    });
+
+    let current_mode = GenericPath::new(
+        Some(0),
+        Some(cluster_mode_select::ID),
+        Some(cluster_mode_select::AttributesDiscriminants::CurrentMode as u32),
+    );
+    let attr_paths = [AttrPath::new(&current_mode)];
+    let read_req = ReadReq::new(true).set_attr_requests(&attr_paths);
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let actuator = Actuator::default();
+    let handler = (
+        Node {
+            id: 0,
+            endpoints: ENDPOINTS,
+        },
+        EmptyHandler.chain(
+            0,
+            cluster_mode_select::ID,
+            ModeSelectCluster::new(&actuator, "Fan", None, MODES, dummy_rand).unwrap(),
+        ),
+    );
+
+    let mut out = heapless::Vec::<_, 3>::new();
+    im.process_with(
+        &HandlerCompat(&handler),
+        &[
+            &ImInput::new(OpCode::InvokeRequest, &inv_reqs[0]),
+            &ImInput::new(OpCode::InvokeRequest, &inv_reqs[1]),
+            &ImInput::new(OpCode::ReadRequest, &read_req),
+        ],
+        &mut out,
+    )
+    .unwrap();
+
+    let expected: [&[ExpectedInvResp]; 2] = [
+        &[ExpectedInvResp::Status(CmdStatus::new(
+            change_to_mode.clone(),
+            IMStatusCode::Success,
+            0,
+        ))],
+        &[ExpectedInvResp::Status(CmdStatus::new(
+            change_to_mode,
+            IMStatusCode::Failure,
+            StatusCode::UnsupportedMode as _,
+        ))],
+    ];
+
+    for (out, expected) in out.iter().zip(expected) {
+        assert_eq!(out.action, OpCode::InvokeResponse);
+
+        let root = tlv::get_root_node_struct(&out.data).unwrap();
+        let resp = InvRespMsg::from_tlv(&root).unwrap();
+        assert_inv_response(&resp, expected);
+    }
+
+    assert_eq!(actuator.0.get(), Some(4));
+
+    assert_eq!(out[2].action, OpCode::ReportData);
+
+    let root = tlv::get_root_node_struct(&out[2].data).unwrap();
+    assert_attr_report(
+        &ReportDataMsg::from_tlv(&root).unwrap(),
+        &[attr_data_path!(current_mode, ElementType::U8(4))],
+    );
+}
@@ -0,0 +1,175 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::cell::RefCell;
+
+use rs_matter::{
+    data_model::objects::{
+        AttrDataEncoder, AttrDetails, Endpoint, EndptId, Handler, HandlerCompat, Node,
+        NonBlockingHandler,
+    },
+    error::Error,
+    interaction_model::{
+        core::OpCode,
+        messages::ib::{AttrData, AttrPath, AttrResp},
+        messages::{
+            msg::{ReadReq, ReportDataMsg},
+            GenericPath,
+        },
+    },
+    tlv::{self, ElementType, FromTLV, TLVElement, TagType},
+};
+
+use crate::{
+    attr_data_path,
+    common::{
+        attributes::*,
+        echo_cluster,
+        im_engine::{ImEngine, ImInput},
+        init_env_logger,
+    },
+};
+
+const ENDPOINT_COUNT: usize = 50;
+
+const ENDPOINT: Endpoint<'static> = Endpoint {
+    id: 0,
+    device_types: &[],
+    tags: &[],
+    clusters: &[echo_cluster::CLUSTER],
+};
+
+const fn endpoints() -> [Endpoint<'static>; ENDPOINT_COUNT] {
+    let mut endpoints = [ENDPOINT; ENDPOINT_COUNT];
+
+    let mut index = 0;
+    while index < ENDPOINT_COUNT {
+        endpoints[index].id = index as _;
+        index += 1;
+    }
+
+    endpoints
+}
+
+static ENDPOINTS: [Endpoint<'static>; ENDPOINT_COUNT] = endpoints();
+
+/// Answers every read with the endpoint, keeping track of the attributes it read
+#[derive(Default)]
+struct CountingHandler {
+    reads: RefCell<Vec<(EndptId, u16)>>,
+}
+
+impl Handler for CountingHandler {
+    fn read(&self, attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+        self.reads
+            .borrow_mut()
+            .push((attr.endpoint_id, attr.attr_id));
+
+        if let Some(writer) = encoder.with_dataver(0)? {
+            writer.set(attr.endpoint_id)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl NonBlockingHandler for CountingHandler {}
+
+/// Read the paths from a node with the endpoints, and return the attributes
+/// the handler read, checking that the report has them all
+fn read_counting(endpoints: &[Endpoint], paths: &[GenericPath]) -> Vec<(EndptId, u16)> {
+    let attr_paths = paths.iter().map(AttrPath::new).collect::<Vec<_>>();
+    let read_req = ReadReq::new(true).set_attr_requests(&attr_paths);
+    let input = ImInput::new(OpCode::ReadRequest, &read_req);
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let counting = CountingHandler::default();
+    let node = Node { id: 0, endpoints };
+    let handler = (node, &counting);
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process_with(&HandlerCompat(&handler), &[&input], &mut out)
+        .unwrap();
+
+    let reads = counting.reads.borrow().clone();
+
+    let expected = reads
+        .iter()
+        .map(|(ep, attr)| {
+            attr_data_path!(
+                GenericPath::new(Some(*ep), Some(echo_cluster::ID), Some(*attr as u32)),
+                ElementType::U16(*ep)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let report = ReportDataMsg::from_tlv(&root).unwrap();
+    assert_attr_report(&report, &expected);
+
+    reads
+}
+
+#[test]
+fn test_concrete_reads_on_large_node() {
+    init_env_logger();
+
+    let att1 = echo_cluster::AttributesDiscriminants::Att1 as u32;
+    let paths =
+        [3, 27, 49].map(|ep| GenericPath::new(Some(ep), Some(echo_cluster::ID), Some(att1)));
+
+    // Only the requested attributes were read, none of the other endpoints
+    assert_eq!(
+        read_counting(&ENDPOINTS, &paths),
+        &[(3, att1 as u16), (27, att1 as u16), (49, att1 as u16)]
+    );
+}
+
+#[test]
+fn test_partial_wildcard_reads_on_large_node() {
+    init_env_logger();
+
+    let att1 = echo_cluster::AttributesDiscriminants::Att1 as u32;
+
+    // Concrete endpoints with a wildcard cluster
+    let paths = [27, 49].map(|ep| GenericPath::new(Some(ep), None, Some(att1)));
+
+    assert_eq!(
+        read_counting(&ENDPOINTS, &paths),
+        &[(27, att1 as u16), (49, att1 as u16)]
+    );
+}
+
+#[test]
+fn test_concrete_reads_on_unordered_node() {
+    init_env_logger();
+
+    let att1 = echo_cluster::AttributesDiscriminants::Att1 as u32;
+
+    // The endpoints declared out of order cannot be binary searched, yet are found
+    let mut unordered = endpoints();
+    unordered.reverse();
+
+    let paths = [3, 27, 49].map(|ep| GenericPath::new(Some(ep), None, Some(att1)));
+
+    assert_eq!(
+        read_counting(&unordered, &paths),
+        &[(3, att1 as u16), (27, att1 as u16), (49, att1 as u16)]
+    );
+}
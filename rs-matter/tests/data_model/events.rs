@@ -0,0 +1,95 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use rs_matter::{
+    data_model::events::EventPriority,
+    interaction_model::{
+        core::OpCode,
+        messages::{
+            ib::{EventFilter, EventPath},
+            msg::{ReadReq, ReportDataMsg},
+            GenericPath,
+        },
+    },
+    tlv::{self, FromTLV},
+};
+
+use crate::common::{
+    echo_cluster,
+    im_engine::{ImEngine, ImInput},
+    init_env_logger,
+};
+
+#[test]
+fn test_read_events_filtered_by_event_number() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let (first, second) = {
+        let mut event_mgr = im.matter.event_mgr.borrow_mut();
+
+        (
+            event_mgr
+                .log(1, echo_cluster::ID, 0, EventPriority::Info, false, &1u8)
+                .unwrap(),
+            event_mgr
+                .log(1, echo_cluster::ID, 0, EventPriority::Info, false, &2u8)
+                .unwrap(),
+        )
+    };
+    assert!(second > first);
+
+    let event_paths = [EventPath::new(&GenericPath::new(
+        None,
+        Some(echo_cluster::ID),
+        None,
+    ))];
+    let event_filters = [EventFilter {
+        event_min: Some(second),
+        ..Default::default()
+    }];
+    let read_req = ReadReq::new(true)
+        .set_event_requests(&event_paths)
+        .set_event_filters(&event_filters);
+
+    let handler = im.handler();
+    let mut out = heapless::Vec::<_, 1>::new();
+
+    im.process(
+        &handler,
+        &[&ImInput::new(OpCode::ReadRequest, &read_req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].action, OpCode::ReportData);
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let report = ReportDataMsg::from_tlv(&root).unwrap();
+    assert!(report.attr_reports.is_none());
+
+    let mut events = report.event_reports.unwrap().iter();
+    let event = events.next().unwrap().unwrap_data();
+    assert!(events.next().is_none());
+
+    assert_eq!(event.event_number, second);
+    assert_eq!(event.path.endpoint, Some(1));
+    assert_eq!(event.path.cluster, Some(echo_cluster::ID));
+}
@@ -33,6 +33,9 @@ use rs_matter::{
     tlv::{self, ElementType, FromTLV, TLVElement, TagType},
 };
 
+#[cfg(feature = "tcp")]
+use rs_matter::transport::network::Address;
+
 use crate::{
     attr_data,
     common::{
@@ -58,6 +61,19 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
         attr_data!(0, 29, descriptor::Attributes::ServerList, dont_care.clone()),
         attr_data!(0, 29, descriptor::Attributes::PartsList, dont_care.clone()),
         attr_data!(0, 29, descriptor::Attributes::ClientList, dont_care.clone()),
+        attr_data!(
+            0,
+            29,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            29,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(0, 29, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(0, 40, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(0, 40, GlobalElements::AttributeList, dont_care.clone()),
         attr_data!(
@@ -102,6 +118,37 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             basic_info::AttributesDiscriminants::SerialNo,
             dont_care.clone()
         ),
+        attr_data!(
+            0,
+            40,
+            basic_info::AttributesDiscriminants::CapabilityMinima,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            40,
+            basic_info::AttributesDiscriminants::ProductAppearance,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            40,
+            basic_info::AttributesDiscriminants::MaxPathsPerInvoke,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            40,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            40,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(0, 40, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(0, 48, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(0, 48, GlobalElements::AttributeList, dont_care.clone()),
         attr_data!(
@@ -128,8 +175,37 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             gen_comm::AttributesDiscriminants::BasicCommissioningInfo,
             dont_care.clone()
         ),
+        attr_data!(
+            0,
+            48,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            48,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(0, 48, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(0, 49, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(0, 49, GlobalElements::AttributeList, dont_care.clone()),
+        attr_data!(
+            0,
+            49,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            49,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+    ];
+
+    let part2 = vec![
+        attr_data!(0, 49, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(0, 60, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(0, 60, GlobalElements::AttributeList, dont_care.clone()),
         attr_data!(
@@ -150,6 +226,19 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             adm_comm::AttributesDiscriminants::AdminVendorId,
             dont_care.clone()
         ),
+        attr_data!(
+            0,
+            60,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            60,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(0, 60, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(0, 62, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(0, 62, GlobalElements::AttributeList, dont_care.clone()),
         attr_data!(
@@ -176,6 +265,19 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             noc::AttributesDiscriminants::CommissionedFabrics,
             dont_care.clone()
         ),
+        attr_data!(
+            0,
+            62,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            62,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(0, 62, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(0, 31, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(0, 31, GlobalElements::AttributeList, dont_care.clone()),
         attr_data!(0, 31, acl::AttributesDiscriminants::Acl, dont_care.clone()),
@@ -185,9 +287,6 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             acl::AttributesDiscriminants::Extension,
             dont_care.clone()
         ),
-    ];
-
-    let part2 = vec![
         attr_data!(
             0,
             31,
@@ -206,6 +305,19 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             acl::AttributesDiscriminants::EntriesPerFabric,
             dont_care.clone()
         ),
+        attr_data!(
+            0,
+            31,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            31,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(0, 31, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(0, echo::ID, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(
             0,
@@ -231,7 +343,28 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             echo::AttributesDiscriminants::AttCustom,
             dont_care.clone()
         ),
+        attr_data!(
+            0,
+            echo::ID,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            echo::ID,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            0,
+            echo::ID,
+            GlobalElements::ClusterRevision,
+            dont_care.clone()
+        ),
         attr_data!(1, 29, GlobalElements::FeatureMap, dont_care.clone()),
+    ];
+
+    let part3 = vec![
         attr_data!(1, 29, GlobalElements::AttributeList, dont_care.clone()),
         attr_data!(
             1,
@@ -242,6 +375,19 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
         attr_data!(1, 29, descriptor::Attributes::ServerList, dont_care.clone()),
         attr_data!(1, 29, descriptor::Attributes::PartsList, dont_care.clone()),
         attr_data!(1, 29, descriptor::Attributes::ClientList, dont_care.clone()),
+        attr_data!(
+            1,
+            29,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(
+            1,
+            29,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(1, 29, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(1, 6, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(1, 6, GlobalElements::AttributeList, dont_care.clone()),
         attr_data!(
@@ -250,6 +396,14 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             onoff::AttributesDiscriminants::OnOff,
             dont_care.clone()
         ),
+        attr_data!(
+            1,
+            6,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(1, 6, GlobalElements::AcceptedCommandList, dont_care.clone()),
+        attr_data!(1, 6, GlobalElements::ClusterRevision, dont_care.clone()),
         attr_data!(1, echo::ID, GlobalElements::FeatureMap, dont_care.clone()),
         attr_data!(
             1,
@@ -273,20 +427,33 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
             1,
             echo::ID,
             echo::AttributesDiscriminants::AttCustom,
-            dont_care
+            dont_care.clone()
+        ),
+        attr_data!(
+            1,
+            echo::ID,
+            GlobalElements::GeneratedCommandList,
+            dont_care.clone()
         ),
+        attr_data!(
+            1,
+            echo::ID,
+            GlobalElements::AcceptedCommandList,
+            dont_care.clone()
+        ),
+        attr_data!(1, echo::ID, GlobalElements::ClusterRevision, dont_care),
     ];
 
-    if part == 1 {
-        part1
-    } else {
-        part2
+    match part {
+        1 => part1,
+        2 => part2,
+        _ => part3,
     }
 }
 
 #[test]
 fn test_long_read_success() {
-    // Read the entire attribute database, which requires 2 reads to complete
+    // Read the entire attribute database, which requires 3 reads to complete
     init_env_logger();
 
     let mut out = heapless::Vec::<_, 3>::new();
@@ -305,18 +472,20 @@ fn test_long_read_success() {
         status: IMStatusCode::Success,
     };
     let expected_part2 = wildcard_read_resp(2);
+    let expected_part3 = wildcard_read_resp(3);
 
     im.process(
         &handler,
         &[
             &ImInput::new(OpCode::ReadRequest, &read_req),
             &ImInput::new(OpCode::StatusResponse, &status_report),
+            &ImInput::new(OpCode::StatusResponse, &status_report),
         ],
         &mut out,
     )
     .unwrap();
 
-    assert_eq!(out.len(), 2);
+    assert_eq!(out.len(), 3);
 
     assert_eq!(out[0].action, OpCode::ReportData);
 
@@ -330,15 +499,22 @@ fn test_long_read_success() {
     let root = tlv::get_root_node_struct(&out[1].data).unwrap();
     let report_data = ReportDataMsg::from_tlv(&root).unwrap();
     assert_attr_report_skip_data(&report_data, &expected_part2);
+    assert_eq!(report_data.more_chunks, Some(true));
+
+    assert_eq!(out[2].action, OpCode::ReportData);
+
+    let root = tlv::get_root_node_struct(&out[2].data).unwrap();
+    let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+    assert_attr_report_skip_data(&report_data, &expected_part3);
     assert_eq!(report_data.more_chunks, None);
 }
 
 #[test]
 fn test_long_read_subscription_success() {
-    // Subscribe to the entire attribute database, which requires 2 reads to complete
+    // Subscribe to the entire attribute database, which requires 3 reads to complete
     init_env_logger();
 
-    let mut out = heapless::Vec::<_, 3>::new();
+    let mut out = heapless::Vec::<_, 4>::new();
     let im = ImEngine::new_default();
     let handler = im.handler();
 
@@ -354,6 +530,7 @@ fn test_long_read_subscription_success() {
         status: IMStatusCode::Success,
     };
     let expected_part2 = wildcard_read_resp(2);
+    let expected_part3 = wildcard_read_resp(3);
 
     im.process(
         &handler,
@@ -361,12 +538,13 @@ fn test_long_read_subscription_success() {
             &ImInput::new(OpCode::SubscribeRequest, &subs_req),
             &ImInput::new(OpCode::StatusResponse, &status_report),
             &ImInput::new(OpCode::StatusResponse, &status_report),
+            &ImInput::new(OpCode::StatusResponse, &status_report),
         ],
         &mut out,
     )
     .unwrap();
 
-    assert_eq!(out.len(), 3);
+    assert_eq!(out.len(), 4);
 
     assert_eq!(out[0].action, OpCode::ReportData);
 
@@ -380,11 +558,69 @@ fn test_long_read_subscription_success() {
     let root = tlv::get_root_node_struct(&out[1].data).unwrap();
     let report_data = ReportDataMsg::from_tlv(&root).unwrap();
     assert_attr_report_skip_data(&report_data, &expected_part2);
-    assert_eq!(report_data.more_chunks, None);
+    assert_eq!(report_data.more_chunks, Some(true));
 
-    assert_eq!(out[2].action, OpCode::SubscribeResponse);
+    assert_eq!(out[2].action, OpCode::ReportData);
 
     let root = tlv::get_root_node_struct(&out[2].data).unwrap();
+    let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+    assert_attr_report_skip_data(&report_data, &expected_part3);
+    assert_eq!(report_data.more_chunks, None);
+
+    assert_eq!(out[3].action, OpCode::SubscribeResponse);
+
+    let root = tlv::get_root_node_struct(&out[3].data).unwrap();
     let subs_resp = SubscribeResp::from_tlv(&root).unwrap();
     assert_eq!(subs_resp.subs_id, 1);
 }
+
+/// Read the entire attribute database in two chunks, and return whether the read
+/// needs more chunks still
+#[cfg(feature = "tcp")]
+fn long_read_needs_more_chunks(im: &ImEngine) -> bool {
+    let mut out = heapless::Vec::<_, 2>::new();
+    let handler = im.handler();
+
+    im.add_default_acl();
+
+    let wc_path = GenericPath::new(None, None, None);
+
+    let read_all = [AttrPath::new(&wc_path)];
+    let read_req = ReadReq::new(true).set_attr_requests(&read_all);
+
+    let status_report = StatusResp {
+        status: IMStatusCode::Success,
+    };
+
+    im.process(
+        &handler,
+        &[
+            &ImInput::new(OpCode::ReadRequest, &read_req),
+            &ImInput::new(OpCode::StatusResponse, &status_report),
+        ],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[1].action, OpCode::ReportData);
+
+    let root = tlv::get_root_node_struct(&out[1].data).unwrap();
+    let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+
+    report_data.more_chunks == Some(true)
+}
+
+#[test]
+#[cfg(feature = "tcp")]
+fn test_long_read_chunks_follow_transport() {
+    // The chunks to a UDP peer fit in a datagram, while a TCP peer gets chunks as
+    // large as the buffers, so the same read takes fewer of them
+    init_env_logger();
+
+    let udp = ImEngine::new_default();
+    assert!(long_read_needs_more_chunks(&udp));
+
+    let tcp = ImEngine::new_default().with_peer_addr(Address::Tcp(Address::default().unwrap_udp()));
+    assert!(!long_read_needs_more_chunks(&tcp));
+}
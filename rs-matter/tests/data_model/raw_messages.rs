@@ -0,0 +1,70 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use rs_matter::{
+    data_model::objects::EncodeValue,
+    interaction_model::{
+        core::OpCode,
+        messages::ib::{AttrData, AttrPath, AttrResp},
+        messages::{msg::ReportDataMsg, GenericPath},
+    },
+    tlv::{self, ElementType, FromTLV, TLVElement, TagType},
+};
+
+use crate::{
+    attr_data_path,
+    common::{
+        attributes::*, echo_cluster, im_engine::ImEngine, init_env_logger, test_util::handle_raw,
+    },
+};
+
+/// A ReadRequest for Att1 of the Echo cluster on endpoint 0, as sent by a controller
+const READ_REQUEST: &[u8] = &[
+    0x15, // struct
+    0x36, 0x00, // attr_requests: array
+    0x17, // list
+    0x24, 0x02, 0x00, // endpoint: 0
+    0x26, 0x03, 0xcd, 0xab, 0x00, 0x00, // cluster: 0xabcd
+    0x24, 0x04, 0x00, // attr: 0
+    0x18, // end of list
+    0x18, // end of array
+    0x28, 0x03, // fabric_filtered: false
+    0x24, 0xff, 0x0b, // interaction model revision: 11
+    0x18, // end of struct
+];
+
+#[test]
+fn test_replay_read_request() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let response = handle_raw(&im, &im.handler(), OpCode::ReadRequest, READ_REQUEST);
+
+    let ep0_att1 = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::Att1 as u32),
+    );
+    let expected = &[attr_data_path!(ep0_att1, ElementType::U16(0x1234))];
+
+    let root = tlv::get_root_node_struct(&response).unwrap();
+    let report = ReportDataMsg::from_tlv(&root).unwrap();
+
+    assert_attr_report(&report, expected);
+}
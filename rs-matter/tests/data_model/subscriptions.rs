@@ -0,0 +1,624 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::time::Duration;
+
+use embassy_futures::select::select;
+use rs_matter::{
+    acl::{AclEntry, AuthMode},
+    data_model::{
+        cluster_basic_information as basic_info, cluster_on_off as onoff,
+        core::DataModel,
+        events::EventPriority,
+        objects::{EncodeValue, GlobalElements, HandlerCompat, Privilege},
+        sdm::general_commissioning as gen_comm,
+        subscriptions::{SUBSCRIPTIONS_PER_FABRIC, SUBSCRIPTION_MAX_INTERVAL_PUBLISHER_LIMIT},
+        system_model::access_control,
+    },
+    interaction_model::{
+        core::{IMStatusCode, OpCode},
+        messages::{
+            ib::{AttrData, AttrPath, AttrResp, AttrStatus, CmdData, CmdPath, EventPath},
+            msg::{ReportDataMsg, StatusResp, SubscribeReq, SubscribeResp},
+            GenericPath,
+        },
+    },
+    tlv::{self, FromTLV, TLVWriter, TagType},
+    transport::packet::{Packet, MAX_RX_BUF_SIZE, MAX_RX_STATUS_BUF_SIZE, MAX_TX_BUF_SIZE},
+    utils::clock::MockClock,
+};
+
+use crate::common::{
+    commands::ExpectedInvResp,
+    echo_cluster,
+    im_engine::{ImEngine, ImInput, IM_ENGINE_PEER_ID, IM_ENGINE_REMOTE_PEER_ID},
+    init_env_logger,
+};
+
+fn subscribe(im: &ImEngine, min_int_floor: u16, max_int_ceil: u16) -> SubscribeResp {
+    let path = GenericPath::new(
+        Some(1),
+        Some(onoff::ID),
+        Some(onoff::AttributesDiscriminants::OnOff as u32),
+    );
+
+    subscribe_path(im, &path, min_int_floor, max_int_ceil)
+}
+
+fn subscribe_path(
+    im: &ImEngine,
+    path: &GenericPath,
+    min_int_floor: u16,
+    max_int_ceil: u16,
+) -> SubscribeResp {
+    let attr_paths = [AttrPath::new(path)];
+    let subs_req =
+        SubscribeReq::new(true, min_int_floor, max_int_ceil).set_attr_requests(&attr_paths);
+
+    subscribe_req(im, &subs_req)
+}
+
+fn subscribe_req(im: &ImEngine, subs_req: &SubscribeReq) -> SubscribeResp {
+    let mut out = heapless::Vec::<_, 2>::new();
+    let handler = im.handler();
+
+    let status_report = StatusResp {
+        status: IMStatusCode::Success,
+    };
+
+    im.process(
+        &handler,
+        &[
+            &ImInput::new(OpCode::SubscribeRequest, subs_req),
+            &ImInput::new(OpCode::StatusResponse, &status_report),
+        ],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[0].action, OpCode::ReportData);
+    assert_eq!(out[1].action, OpCode::SubscribeResponse);
+
+    let root = tlv::get_root_node_struct(&out[1].data).unwrap();
+    SubscribeResp::from_tlv(&root).unwrap()
+}
+
+/// Have the device push the next report of the subscription, and return the paths
+/// of the attributes in it, if the report has attribute reports at all
+fn push_report(im: &ImEngine, subs_id: u32) -> Option<heapless::Vec<GenericPath, 8>> {
+    let mut paths = None;
+
+    push_report_with(im, subs_id, |report| {
+        for resp in report
+            .attr_reports
+            .iter()
+            .flat_map(|reports| reports.iter())
+        {
+            let AttrResp::Data(data) = resp else {
+                panic!("Unexpected attribute status in a report");
+            };
+
+            paths
+                .get_or_insert_with(heapless::Vec::new)
+                .push(data.path.to_gp())
+                .unwrap();
+        }
+    });
+
+    paths
+}
+
+/// Same as `push_report`, but return the numbers of the events in the report
+fn push_event_report(im: &ImEngine, subs_id: u32) -> heapless::Vec<u64, 8> {
+    let mut numbers = heapless::Vec::new();
+
+    push_report_with(im, subs_id, |report| {
+        for resp in report
+            .event_reports
+            .iter()
+            .flat_map(|reports| reports.iter())
+        {
+            numbers.push(resp.unwrap_data().event_number).unwrap();
+        }
+    });
+
+    numbers
+}
+
+fn push_report_with<F>(im: &ImEngine, subs_id: u32, f: F)
+where
+    F: FnOnce(&ReportDataMsg),
+{
+    let handler = im.handler();
+    let dm = DataModel::new(&handler, &im.matter.subscription_mgr);
+
+    let subscription = im
+        .matter
+        .subscription_mgr
+        .borrow_mut()
+        .start_report(subs_id)
+        .unwrap();
+
+    let session_id = im
+        .matter
+        .session_mgr
+        .borrow_mut()
+        .get_with_id(subscription.sess_id)
+        .unwrap()
+        .get_session_id();
+
+    let mut exchange = im.matter.new_exchange(&session_id).unwrap();
+
+    let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+    let mut sx_buf = [0; MAX_RX_STATUS_BUF_SIZE];
+    let mut tx = Packet::new_tx(&mut tx_buf);
+    let mut rx_status = Packet::new_rx(&mut sx_buf);
+
+    let mut sent_buf = [0; MAX_TX_BUF_SIZE];
+    let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+
+    embassy_futures::block_on(select(
+        dm.report(&mut exchange, &subscription, &mut tx, &mut rx_status),
+        async {
+            // Capture the report as it goes out, without ever answering it
+            let mut sent = Packet::new_tx(&mut sent_buf);
+            assert!(im.matter.pull_tx(&mut sent).await.unwrap());
+
+            let len = sent.as_slice().len();
+            rx_buf[..len].copy_from_slice(sent.as_slice());
+
+            let mut rx = Packet::new_rx(&mut rx_buf[..len]);
+            rx.plain_hdr_decode().unwrap();
+            rx.proto_decode(IM_ENGINE_REMOTE_PEER_ID, Some(&[0u8; 16]))
+                .unwrap();
+
+            assert_eq!(rx.get_proto_opcode::<OpCode>().unwrap(), OpCode::ReportData);
+
+            let root = tlv::get_root_node_struct(rx.as_slice()).unwrap();
+            let report = ReportDataMsg::from_tlv(&root).unwrap();
+
+            assert_eq!(report.subscription_id, Some(subs_id));
+            assert_eq!(report.suppress_response, Some(false));
+
+            f(&report);
+        },
+    ));
+}
+
+#[test]
+fn test_subscription_negotiated_max_interval() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let subs_resp = subscribe(&im, 1, 60);
+    assert_eq!(subs_resp.max_int, 60);
+
+    let subscriptions = im.matter.subscription_mgr.borrow();
+    let subscription = subscriptions.get(subs_resp.subs_id).unwrap();
+    assert_eq!(subscription.max_int, 60);
+}
+
+#[test]
+fn test_subscription_max_interval_clamped() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let subs_resp = subscribe(&im, 1, u16::MAX);
+    assert_eq!(subs_resp.max_int, SUBSCRIPTION_MAX_INTERVAL_PUBLISHER_LIMIT);
+}
+
+#[test]
+fn test_active_subscriptions() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let subs_resp = subscribe(&im, 1, 60);
+
+    let handler = im.handler();
+    let dm = DataModel::new(&handler, &im.matter.subscription_mgr);
+
+    let mut active = dm.active_subscriptions();
+    let info = active.next().unwrap();
+    assert!(active.next().is_none());
+
+    assert_eq!(info.id, subs_resp.subs_id);
+    assert_eq!(info.fab_idx, 1);
+    assert_eq!(info.peer_node_id, IM_ENGINE_PEER_ID);
+    assert_eq!(info.max_int, 60);
+}
+
+#[test]
+fn test_report_dirty_attributes_only() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    // A wildcard subscription to all attributes of the cluster
+    let subs_resp = subscribe_path(
+        &im,
+        &GenericPath::new(Some(1), Some(onoff::ID), None),
+        1,
+        60,
+    );
+
+    {
+        let mut subscriptions = im.matter.subscription_mgr.borrow_mut();
+
+        assert!(subscriptions.mark_dirty(1, onoff::ID, onoff::AttributesDiscriminants::OnOff as _));
+        assert!(subscriptions.mark_dirty(1, onoff::ID, GlobalElements::FeatureMap as _));
+
+        // Paths outside of the subscription are not tracked
+        assert!(!subscriptions.mark_dirty(
+            0,
+            basic_info::ID,
+            basic_info::AttributesDiscriminants::SerialNo as _
+        ));
+    }
+
+    let paths = push_report(&im, subs_resp.subs_id).unwrap();
+
+    assert_eq!(
+        paths.as_slice(),
+        &[
+            GenericPath::new(
+                Some(1),
+                Some(onoff::ID),
+                Some(GlobalElements::FeatureMap as _)
+            ),
+            GenericPath::new(
+                Some(1),
+                Some(onoff::ID),
+                Some(onoff::AttributesDiscriminants::OnOff as _)
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_written_attribute_reported() {
+    init_env_logger();
+
+    static CLOCK: MockClock = MockClock::new(100_000);
+
+    let im = ImEngine::new_with_clock(Default::default(), &CLOCK);
+    im.add_default_acl();
+
+    let path = GenericPath::new(
+        Some(1),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+    let subs_resp = subscribe_path(&im, &path, 1, 60);
+
+    let attr_data = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, echo_cluster::ATTR_WRITE_MAX_VALUE);
+    };
+    let input = &[AttrData::new(
+        None,
+        AttrPath::new(&path),
+        EncodeValue::Closure(&attr_data),
+    )];
+
+    im.handle_write_reqs(
+        &im.handler(),
+        input,
+        &[AttrStatus::new(&path, IMStatusCode::Success, 0)],
+    );
+
+    // The write marked the attribute dirty, so it is reported once the min interval elapsed
+    CLOCK.advance(Duration::from_secs(2));
+    assert_eq!(
+        im.matter.subscription_mgr.borrow().pending().as_slice(),
+        &[subs_resp.subs_id]
+    );
+
+    assert_eq!(
+        push_report(&im, subs_resp.subs_id).unwrap().as_slice(),
+        &[path]
+    );
+}
+
+#[test]
+fn test_keep_alive_report() {
+    init_env_logger();
+
+    static CLOCK: MockClock = MockClock::new(100_000);
+
+    let im = ImEngine::new_with_clock(Default::default(), &CLOCK);
+    im.add_default_acl();
+
+    let subs_resp = subscribe(&im, 1, 60);
+
+    CLOCK.set(Duration::from_secs(159));
+    assert!(im.matter.subscription_mgr.borrow().pending().is_empty());
+
+    // Nothing changed, yet the subscription has to be kept alive
+    CLOCK.set(Duration::from_secs(161));
+    assert_eq!(
+        im.matter.subscription_mgr.borrow().pending().as_slice(),
+        &[subs_resp.subs_id]
+    );
+
+    assert_eq!(push_report(&im, subs_resp.subs_id), None);
+}
+
+#[test]
+fn test_subscription_rejected_past_fabric_limit() {
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    // Other peers of the fabric already took all of its subscriptions
+    for peer in 0..SUBSCRIPTIONS_PER_FABRIC as u64 {
+        let mut req = SubscribeReq::new(true, 1, 60);
+        req.keep_subs = true;
+
+        im.matter
+            .subscription_mgr
+            .borrow_mut()
+            .add(1, IM_ENGINE_PEER_ID + 1 + peer, 1, &req)
+            .unwrap();
+    }
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    let handler = im.handler();
+
+    let path = GenericPath::new(
+        Some(1),
+        Some(onoff::ID),
+        Some(onoff::AttributesDiscriminants::OnOff as u32),
+    );
+    let attr_paths = [AttrPath::new(&path)];
+    let subs_req = SubscribeReq::new(true, 1, 60).set_attr_requests(&attr_paths);
+
+    im.process(
+        &handler,
+        &[&ImInput::new(OpCode::SubscribeRequest, &subs_req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].action, OpCode::StatusResponse);
+
+    let root = tlv::get_root_node_struct(&out[0].data).unwrap();
+    let status = StatusResp::from_tlv(&root).unwrap();
+    assert_eq!(status.status, IMStatusCode::ResourceExhausted);
+}
+
+#[test]
+fn test_urgent_event_bypasses_min_interval() {
+    init_env_logger();
+
+    static CLOCK: MockClock = MockClock::new(200_000);
+
+    let im = ImEngine::new_with_clock(Default::default(), &CLOCK);
+    im.add_default_acl();
+
+    let event_paths = [EventPath::new(&GenericPath::new(
+        Some(1),
+        Some(echo_cluster::ID),
+        None,
+    ))];
+    let subs_req = SubscribeReq::new(true, 10, 60).set_event_requests(&event_paths);
+    let subs_resp = subscribe_req(&im, &subs_req);
+
+    CLOCK.set(Duration::from_secs(201));
+
+    // A regular event waits for the min interval
+    let regular = im
+        .matter
+        .log_event(1, echo_cluster::ID, 0, EventPriority::Info, false, &1u8)
+        .unwrap();
+    assert!(im.matter.subscription_mgr.borrow().pending().is_empty());
+
+    // An urgent one does not
+    let urgent = im
+        .matter
+        .log_event(1, echo_cluster::ID, 0, EventPriority::Critical, true, &2u8)
+        .unwrap();
+    assert_eq!(
+        im.matter.subscription_mgr.borrow().pending().as_slice(),
+        &[subs_resp.subs_id]
+    );
+
+    assert_eq!(
+        push_event_report(&im, subs_resp.subs_id).as_slice(),
+        &[regular, urgent]
+    );
+
+    // The reported events are not pending anymore
+    assert!(im.matter.subscription_mgr.borrow().pending().is_empty());
+    assert_eq!(
+        im.matter
+            .subscription_mgr
+            .borrow()
+            .get(subs_resp.subs_id)
+            .unwrap()
+            .event_from,
+        urgent + 1
+    );
+}
+
+#[test]
+fn test_cluster_event_notifies_subscription() {
+    init_env_logger();
+
+    static CLOCK: MockClock = MockClock::new(100_000);
+
+    let im = ImEngine::new_with_clock(Default::default(), &CLOCK);
+    im.add_default_acl();
+
+    let event_paths = [EventPath::new(&GenericPath::new(
+        Some(0),
+        Some(access_control::ID),
+        None,
+    ))];
+    let subs_req = SubscribeReq::new(true, 1, 60).set_event_requests(&event_paths);
+    let subs_resp = subscribe_req(&im, &subs_req);
+
+    // Changing the ACL has the Access Control cluster log an event for it
+    let acl_att = GenericPath::new(
+        Some(0),
+        Some(access_control::ID),
+        Some(access_control::AttributesDiscriminants::Acl as u32),
+    );
+    let mut new_acl = AclEntry::new(1, Privilege::VIEW, AuthMode::Case);
+    new_acl.add_subject(IM_ENGINE_PEER_ID + 1).unwrap();
+    im.handle_write_reqs(
+        &im.handler(),
+        &[AttrData::new(
+            None,
+            AttrPath::new(&acl_att),
+            EncodeValue::Value(&new_acl),
+        )],
+        &[AttrStatus::new(&acl_att, IMStatusCode::Success, 0)],
+    );
+
+    CLOCK.advance(Duration::from_secs(2));
+
+    let subscriptions = im.matter.subscription_mgr.borrow();
+    assert!(subscriptions.get(subs_resp.subs_id).unwrap().events_pending);
+    assert_eq!(subscriptions.pending().as_slice(), &[subs_resp.subs_id]);
+}
+
+#[test]
+fn test_breadcrumb_reported_on_change_only() {
+    init_env_logger();
+
+    static CLOCK: MockClock = MockClock::new(100_000);
+
+    let im = ImEngine::new_with_clock(Default::default(), &CLOCK);
+    im.add_default_acl();
+
+    let path = GenericPath::new(
+        Some(0),
+        Some(gen_comm::ID),
+        Some(gen_comm::AttributesDiscriminants::BreadCrumb as u32),
+    );
+    let subs_resp = subscribe_path(&im, &path, 1, 60);
+
+    let arm_failsafe = |breadcrumb: u64| {
+        let arm = move |tag, t: &mut TLVWriter| {
+            let _ = t.start_struct(tag);
+            let _ = t.u16(TagType::Context(0), 60);
+            let _ = t.u64(TagType::Context(1), breadcrumb);
+            let _ = t.end_container();
+        };
+
+        im.handle_commands(
+            &im.handler(),
+            &[CmdData::new(
+                CmdPath::new(
+                    Some(0),
+                    Some(gen_comm::ID),
+                    Some(gen_comm::Commands::ArmFailsafe as u32),
+                ),
+                EncodeValue::Closure(&arm),
+            )],
+            &[ExpectedInvResp::Cmd(
+                CmdPath::new(
+                    Some(0),
+                    Some(gen_comm::ID),
+                    Some(gen_comm::RespCommands::ArmFailsafeResp as u32),
+                ),
+                0,
+            )],
+        );
+    };
+
+    // Arming the fail-safe sets the breadcrumb, which is reported
+    arm_failsafe(1);
+
+    CLOCK.advance(Duration::from_secs(2));
+    assert_eq!(
+        im.matter.subscription_mgr.borrow().pending().as_slice(),
+        &[subs_resp.subs_id]
+    );
+    assert_eq!(
+        push_report(&im, subs_resp.subs_id).unwrap().as_slice(),
+        &[path]
+    );
+    im.matter
+        .subscription_mgr
+        .borrow_mut()
+        .report_sent(subs_resp.subs_id);
+
+    // Re-arming with the reported breadcrumb changes nothing to report
+    arm_failsafe(1);
+
+    CLOCK.advance(Duration::from_secs(2));
+    assert!(im.matter.subscription_mgr.borrow().pending().is_empty());
+}
+
+#[test]
+fn test_first_subscription_kept_alive() {
+    init_env_logger();
+
+    let im = ImEngine::new(Default::default());
+    im.add_default_acl();
+
+    let handler = im.handler();
+
+    let paths = [AttrPath::new(&GenericPath::new(
+        Some(0),
+        Some(basic_info::ID),
+        Some(basic_info::AttributesDiscriminants::SerialNo as u32),
+    ))];
+    let subs_req = SubscribeReq::new(true, 0, 1).set_attr_requests(&paths);
+
+    let status_report = StatusResp {
+        status: IMStatusCode::Success,
+    };
+
+    // With no subscription before this one, the device has nothing to wait for,
+    // so it has to wake up to push the report due after the max interval
+    let mut out = heapless::Vec::<_, 3>::new();
+    im.process_with_reports(
+        &HandlerCompat(&handler),
+        &[
+            &ImInput::new(OpCode::SubscribeRequest, &subs_req),
+            &ImInput::new(OpCode::StatusResponse, &status_report),
+        ],
+        1,
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out.len(), 3);
+    assert_eq!(out[0].action, OpCode::ReportData);
+    assert_eq!(out[1].action, OpCode::SubscribeResponse);
+    assert_eq!(out[2].action, OpCode::ReportData);
+
+    let root = tlv::get_root_node_struct(&out[2].data).unwrap();
+    let report = ReportDataMsg::from_tlv(&root).unwrap();
+
+    let root = tlv::get_root_node_struct(&out[1].data).unwrap();
+    let subs_resp = SubscribeResp::from_tlv(&root).unwrap();
+
+    assert_eq!(report.subscription_id, Some(subs_resp.subs_id));
+    assert!(report.attr_reports.is_none());
+}
@@ -18,19 +18,25 @@
 use rs_matter::{
     data_model::objects::EncodeValue,
     interaction_model::{
-        core::IMStatusCode,
+        core::{IMStatusCode, OpCode},
         messages::ib::{AttrData, AttrPath, AttrStatus},
-        messages::{ib::CmdData, ib::CmdPath, GenericPath},
+        messages::{
+            ib::CmdData,
+            ib::CmdPath,
+            ib::CmdStatus,
+            msg::{StatusResp, TimedReq, WriteReq},
+            GenericPath,
+        },
     },
-    tlv::TLVWriter,
+    tlv::{self, FromTLV, TLVWriter},
 };
 
 use crate::{
     common::{
         commands::*,
         echo_cluster,
-        handlers::{TimedInvResponse, WriteResponse},
-        im_engine::ImEngine,
+        handlers::{assert_write_response, TimedInvResponse, WriteResponse},
+        im_engine::{ImEngine, ImInput},
         init_env_logger,
     },
     echo_req, echo_resp,
@@ -90,6 +96,100 @@ fn test_timed_write_fail_and_success() {
     assert_eq!(val0, handler.echo_cluster(0).att_write.get());
 }
 
+#[test]
+fn test_timed_write_suppressed() {
+    // A timed write within its window which asks for no response gets none,
+    // only the acknowledgement of the request
+    let val0 = 15;
+    init_env_logger();
+    let attr_data0 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val0);
+    };
+
+    let ep0_att = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+    let input = &[AttrData::new(
+        None,
+        AttrPath::new(&ep0_att),
+        EncodeValue::Closure(&attr_data0),
+    )];
+
+    let timed_req = TimedReq { timeout: 400 };
+    let write_req = WriteReq::new(true, input);
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+    im.add_default_acl();
+
+    let mut out = heapless::Vec::<_, 2>::new();
+    im.process(
+        &handler,
+        &[
+            &ImInput::new(OpCode::TimedRequest, &timed_req),
+            &ImInput::new_unanswered(OpCode::WriteRequest, &write_req),
+        ],
+        &mut out,
+    )
+    .unwrap();
+
+    // Only the status response to the timed request
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].action, OpCode::StatusResponse);
+    assert_eq!(val0, handler.echo_cluster(0).att_write.get());
+}
+
+#[test]
+fn test_timed_write_suppressed_after_timeout() {
+    // A timed write after its window expired is rejected with a status response,
+    // even though it asks for no response
+    let val0 = 15;
+    init_env_logger();
+    let attr_data0 = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, val0);
+    };
+
+    let ep0_att = GenericPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::AttributesDiscriminants::AttWrite as u32),
+    );
+    let input = &[AttrData::new(
+        None,
+        AttrPath::new(&ep0_att),
+        EncodeValue::Closure(&attr_data0),
+    )];
+
+    let timed_req = TimedReq { timeout: 100 };
+    let write_req = WriteReq::new(true, input);
+
+    let im = ImEngine::new_default();
+    let handler = im.handler();
+    im.add_default_acl();
+
+    let mut out = heapless::Vec::<_, 2>::new();
+    im.process(
+        &handler,
+        &[
+            &ImInput::new_delayed(OpCode::TimedRequest, &timed_req, Some(500)),
+            &ImInput::new(OpCode::WriteRequest, &write_req),
+        ],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[1].action, OpCode::StatusResponse);
+
+    let root = tlv::get_root_node_struct(&out[1].data).unwrap();
+    let status_resp = StatusResp::from_tlv(&root).unwrap();
+    assert_eq!(status_resp.status, IMStatusCode::Timeout);
+
+    assert_ne!(val0, handler.echo_cluster(0).att_write.get());
+}
+
 #[test]
 fn test_timed_cmd_success() {
     // A timed request that works
@@ -144,3 +244,195 @@ fn test_timed_cmd_timedout_mismatch() {
         true,
     );
 }
+
+fn timed_echo_path() -> CmdPath {
+    CmdPath::new(
+        Some(0),
+        Some(echo_cluster::ID),
+        Some(echo_cluster::Commands::TimedEchoReq as u32),
+    )
+}
+
+#[test]
+fn test_timed_only_cmd_success() {
+    // A command that requires a timed interaction, invoked within the timed window
+    init_env_logger();
+
+    let input = &[CmdData::new(timed_echo_path(), EncodeValue::Value(&5u32))];
+    let expected = &[echo_resp!(0, 10)];
+    ImEngine::timed_commands(
+        input,
+        &TimedInvResponse::TransactionSuccess(expected),
+        2000,
+        0,
+        true,
+    );
+}
+
+#[test]
+fn test_timed_only_cmd_without_timed_request() {
+    // A command that requires a timed interaction, invoked without one
+    init_env_logger();
+
+    let input = &[CmdData::new(timed_echo_path(), EncodeValue::Value(&5u32))];
+    let expected = &[ExpectedInvResp::Status(CmdStatus::new(
+        timed_echo_path(),
+        IMStatusCode::TimedRequestMisMatch,
+        0,
+    ))];
+    ImEngine::commands(input, expected);
+}
+
+#[test]
+fn test_timed_only_cmd_after_timeout() {
+    // A command that requires a timed interaction, invoked after the timed window expired
+    init_env_logger();
+
+    let input = &[CmdData::new(timed_echo_path(), EncodeValue::Value(&5u32))];
+    ImEngine::timed_commands(
+        input,
+        &TimedInvResponse::TransactionError(IMStatusCode::Timeout),
+        100,
+        500,
+        true,
+    );
+}
+
+#[test]
+fn test_timed_only_attr_write() {
+    // An attribute that may only be written as part of a timed interaction:
+    // - an untimed write to it fails with NeedsTimedInteraction
+    // - an untimed wildcard write leaves it out silently
+    // - a timed write succeeds
+    use core::cell::Cell;
+
+    use rs_matter::data_model::objects::{
+        self, Access, AttrDataEncoder, AttrDetails, Attribute, Cluster, Endpoint, Handler,
+        HandlerCompat, Node, NonBlockingHandler, Quality,
+    };
+    use rs_matter::error::Error;
+
+    const CLUSTER_ID: u32 = 0xFFF1_FC21;
+    const ATTR_ID: u16 = 0;
+
+    const ENDPOINTS: &[Endpoint<'static>] = &[Endpoint {
+        id: 0,
+        device_types: &[],
+        tags: &[],
+        clusters: &[Cluster {
+            id: CLUSTER_ID,
+            revision: 1,
+            feature_map: 0,
+            attributes: &[Attribute::new(
+                ATTR_ID,
+                Access::RWVA.union(Access::TIMED_ONLY),
+                Quality::NONE,
+            )],
+            commands: &[],
+            generated_commands: &[],
+            timed_commands: &[],
+            response_commands: &[],
+            manage_commands: &[],
+            admin_commands: &[],
+        }],
+    }];
+
+    struct TimedOnlyHandler(Cell<u16>);
+
+    impl Handler for TimedOnlyHandler {
+        fn read(&self, _attr: &AttrDetails, encoder: AttrDataEncoder) -> Result<(), Error> {
+            if let Some(writer) = encoder.with_dataver(0)? {
+                writer.set(self.0.get())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn write(&self, _attr: &AttrDetails, data: objects::AttrData) -> Result<(), Error> {
+            self.0.set(data.with_dataver(0)?.u16()?);
+
+            Ok(())
+        }
+    }
+
+    impl NonBlockingHandler for TimedOnlyHandler {}
+
+    init_env_logger();
+
+    let im = ImEngine::new_default();
+    im.add_default_acl();
+
+    let timed_only = TimedOnlyHandler(Cell::new(0));
+    let node = Node {
+        id: 0,
+        endpoints: ENDPOINTS,
+    };
+    let handler = (node, &timed_only);
+    let handler = HandlerCompat(&handler);
+
+    let attr_data = |tag, t: &mut TLVWriter| {
+        let _ = t.u16(tag, 42);
+    };
+
+    let path = GenericPath::new(Some(0), Some(CLUSTER_ID), Some(ATTR_ID as u32));
+    let input = &[AttrData::new(
+        None,
+        AttrPath::new(&path),
+        EncodeValue::Closure(&attr_data),
+    )];
+    let write_req = WriteReq::new(false, input);
+
+    let wildcard_path = GenericPath::new(None, Some(CLUSTER_ID), Some(ATTR_ID as u32));
+    let wildcard_input = &[AttrData::new(
+        None,
+        AttrPath::new(&wildcard_path),
+        EncodeValue::Closure(&attr_data),
+    )];
+    let wildcard_write_req = WriteReq::new(false, wildcard_input);
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process_with(
+        &handler,
+        &[&ImInput::new(OpCode::WriteRequest, &write_req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_write_response(
+        &out[0],
+        &[AttrStatus::new(
+            &path,
+            IMStatusCode::NeedsTimedInteraction,
+            0,
+        )],
+    );
+    assert_eq!(timed_only.0.get(), 0);
+
+    let mut out = heapless::Vec::<_, 1>::new();
+    im.process_with(
+        &handler,
+        &[&ImInput::new(OpCode::WriteRequest, &wildcard_write_req)],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_write_response(&out[0], &[]);
+    assert_eq!(timed_only.0.get(), 0);
+
+    let timed_req = TimedReq { timeout: 400 };
+
+    let mut out = heapless::Vec::<_, 2>::new();
+    im.process_with(
+        &handler,
+        &[
+            &ImInput::new(OpCode::TimedRequest, &timed_req),
+            &ImInput::new(OpCode::WriteRequest, &write_req),
+        ],
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(out[0].action, OpCode::StatusResponse);
+    assert_write_response(&out[1], &[AttrStatus::new(&path, IMStatusCode::Success, 0)]);
+    assert_eq!(timed_only.0.get(), 42);
+}
@@ -21,7 +21,12 @@ mod data_model {
     mod acl_and_dataver;
     mod attribute_lists;
     mod attributes;
+    mod bdx;
     mod commands;
+    mod concrete_reads;
+    mod events;
     mod long_reads;
+    mod raw_messages;
+    mod subscriptions;
     mod timed_requests;
 }
@@ -0,0 +1,89 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::borrow::Borrow;
+
+use rs_matter::{
+    data_model::objects::{EmptyHandler, Node},
+    mdns::DummyMdns,
+    secure_channel::{
+        common::{OpCode, PROTO_ID_SECURE_CHANNEL},
+        spake2p::VerifierData,
+    },
+    tlv::{TLVWriter, TagType},
+    transport::{network::Address, packet::Packet, proto_hdr::ExchFlags},
+    CommissioningData, Matter,
+};
+
+use crate::device::{DummyDevAtt, BASIC_INFO};
+
+const NODE: Node<'static> = Node {
+    id: 0,
+    endpoints: &[],
+};
+
+/// The handler of a device without endpoints, which is enough for the handshakes
+pub const HANDLER: (Node<'static>, EmptyHandler) = (NODE, EmptyHandler);
+
+const INITIATOR_NODE_ID: u64 = 0x1234;
+
+pub fn device(port: u16) -> Matter<'static> {
+    Matter::new_default(&BASIC_INFO, &DummyDevAtt, &DummyMdns, port)
+}
+
+pub fn dev_comm(matter: &Matter) -> CommissioningData {
+    CommissioningData {
+        verifier: VerifierData::new_with_pw(123456, *matter.borrow()),
+        discriminator: 250,
+    }
+}
+
+/// Encode the PBKDFParamRequest which starts PASE, returning its length
+pub fn pbkdf_param_request(buf: &mut [u8]) -> usize {
+    let mut packet = Packet::new_tx(buf);
+    packet.plain.ctr = 1;
+    packet.plain.set_src_u64(INITIATOR_NODE_ID);
+    packet.set_proto_id(PROTO_ID_SECURE_CHANNEL);
+    packet.set_proto_opcode(OpCode::PBKDFParamRequest as u8);
+    packet.proto.exch_id = 1;
+    packet.proto.exch_flags |= ExchFlags::INITIATOR;
+
+    let mut tw = TLVWriter::new(packet.get_writebuf().unwrap());
+    tw.start_struct(TagType::Anonymous).unwrap();
+    tw.str8(TagType::Context(1), &[0x55; 32]).unwrap();
+    tw.u16(TagType::Context(2), 1).unwrap();
+    tw.u16(TagType::Context(3), 0).unwrap();
+    tw.bool(TagType::Context(4), false).unwrap();
+    tw.end_container().unwrap();
+
+    packet
+        .proto_encode(Address::default(), None, 0, true, None, None)
+        .unwrap();
+
+    packet.as_slice().len()
+}
+
+/// The opcode of a Secure Channel message sent by the device
+pub fn secure_channel_opcode(buf: &mut [u8]) -> u8 {
+    let mut packet = Packet::new_rx(buf);
+    packet.plain_hdr_decode().unwrap();
+    packet.proto_decode(0, None).unwrap();
+
+    assert_eq!(packet.get_proto_id(), PROTO_ID_SECURE_CHANNEL);
+
+    packet.get_proto_raw_opcode()
+}
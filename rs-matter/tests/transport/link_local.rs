@@ -0,0 +1,104 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::pin::pin;
+
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use embassy_futures::select::{select, Either};
+
+use rs_matter::{
+    transport::{
+        core::PacketBuffers,
+        network::Address,
+        packet::{MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+        pipe::Pipe,
+    },
+    MATTER_PORT,
+};
+
+use super::common::{dev_comm, device, pbkdf_param_request, secure_channel_opcode, HANDLER};
+
+/// Deliver a PBKDFParamRequest from the peer, returning the address the device replies to
+fn reply_addr(peer: SocketAddrV6) -> SocketAddrV6 {
+    let matter = device(MATTER_PORT);
+
+    let mut buffers = PacketBuffers::new();
+
+    let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+    let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+
+    let tx_pipe = Pipe::new(&mut tx_buf);
+    let rx_pipe = Pipe::new(&mut rx_buf);
+
+    let mut request = [0; MAX_TX_BUF_SIZE];
+    let len = pbkdf_param_request(&mut request);
+
+    let run = matter.run_piped(
+        &mut buffers,
+        &tx_pipe,
+        &rx_pipe,
+        dev_comm(&matter),
+        &HANDLER,
+    );
+
+    let exchange = async {
+        rx_pipe
+            .send(Address::Udp(SocketAddr::V6(peer)), &request[..len])
+            .await;
+
+        let mut reply = [0; MAX_TX_BUF_SIZE];
+        let (len, addr) = tx_pipe.recv(&mut reply).await;
+        secure_channel_opcode(&mut reply[..len]);
+
+        addr
+    };
+
+    match embassy_futures::block_on(select(pin!(run), pin!(exchange))) {
+        Either::First(result) => panic!("The transport exited: {:?}", result),
+        Either::Second(Address::Udp(SocketAddr::V6(addr))) => addr,
+        Either::Second(addr) => panic!("Unexpected reply address: {:?}", addr),
+    }
+}
+
+#[test]
+fn test_link_local_scope_round_trips() {
+    let peer = SocketAddrV6::new(
+        Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1c2b, 0x3cff, 0xfe4d, 0x5e6f),
+        5540,
+        0x12345,
+        3,
+    );
+
+    let reply = reply_addr(peer);
+
+    assert_eq!(reply.ip(), peer.ip());
+    assert_eq!(reply.port(), peer.port());
+    assert_eq!(reply.scope_id(), 3);
+    // The flow of the peer is not reused
+    assert_eq!(reply.flowinfo(), 0);
+}
+
+#[test]
+fn test_global_peer_has_no_scope() {
+    let peer = SocketAddrV6::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 5540, 0, 3);
+
+    let reply = reply_addr(peer);
+
+    assert_eq!(reply.ip(), peer.ip());
+    assert_eq!(reply.scope_id(), 0);
+}
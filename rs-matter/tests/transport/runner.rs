@@ -0,0 +1,88 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::pin::pin;
+use core::time::Duration;
+
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use embassy_futures::select::{select, Either};
+use tokio::{net::UdpSocket, time::timeout};
+
+use rs_matter::{
+    secure_channel::common::OpCode,
+    transport::{
+        packet::{MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+        runner::{self, BindAddr, Stack},
+    },
+};
+
+use super::common::{dev_comm, device, pbkdf_param_request, secure_channel_opcode, HANDLER};
+
+/// Not the Matter port, so that the test does not clash with a device running on the host
+const RUNNER_PORT: u16 = 15540;
+
+/// Send a PBKDFParamRequest and return the opcode of the first message,
+/// other than a standalone ack, which the device answers with
+async fn exchange_with(device: SocketAddr) -> u8 {
+    let client = UdpSocket::bind("[::1]:0").await.unwrap();
+
+    let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+    let len = pbkdf_param_request(&mut tx_buf);
+    client.send_to(&tx_buf[..len], device).await.unwrap();
+
+    loop {
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+        let (len, _) = timeout(Duration::from_secs(5), client.recv_from(&mut rx_buf))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let opcode = secure_channel_opcode(&mut rx_buf[..len]);
+        if opcode != OpCode::MRPStandAloneAck as u8 {
+            break opcode;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_pbkdf_param_exchange() {
+    let matter = device(RUNNER_PORT);
+
+    let stack = Stack {
+        matter: &matter,
+        dev_comm: dev_comm(&matter),
+        handler: HANDLER,
+    };
+
+    let bind_addr = BindAddr {
+        ipv6: SocketAddrV6::new(Ipv6Addr::LOCALHOST, RUNNER_PORT, 0, 0),
+        ipv4: None,
+    };
+
+    let device = SocketAddr::V6(bind_addr.ipv6);
+
+    match select(
+        pin!(runner::run(stack, bind_addr)),
+        pin!(exchange_with(device)),
+    )
+    .await
+    {
+        Either::First(result) => panic!("The runner exited: {:?}", result),
+        Either::Second(opcode) => assert_eq!(opcode, OpCode::PBKDFParamResponse as u8),
+    }
+}
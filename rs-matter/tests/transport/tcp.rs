@@ -0,0 +1,93 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use core::pin::pin;
+use core::time::Duration;
+
+use std::net::{Ipv6Addr, SocketAddr};
+
+use embassy_futures::select::{select, Either};
+
+use rs_matter::{
+    secure_channel::common::OpCode,
+    transport::{
+        core::RunBuffers,
+        network::NetworkStack,
+        packet::{MAX_RX_BUF_SIZE, MAX_TX_BUF_SIZE},
+        tcp::TcpConnection,
+    },
+};
+
+use super::common::{dev_comm, device, pbkdf_param_request, secure_channel_opcode, HANDLER};
+
+/// Not the Matter port, so that the test does not clash with a device running on the host
+const TCP_PORT: u16 = 15541;
+
+/// Send a PBKDFParamRequest over TCP and return the opcode of the first message,
+/// other than a standalone ack, which the device answers with
+async fn exchange_with(device: SocketAddr) -> u8 {
+    // The device might not be listening yet
+    let client = loop {
+        if let Ok(client) = TcpConnection::connect(device).await {
+            break client;
+        }
+
+        async_io::Timer::after(Duration::from_millis(50)).await;
+    };
+
+    let mut tx_buf = [0; MAX_TX_BUF_SIZE];
+    let len = pbkdf_param_request(&mut tx_buf);
+    client.send(&tx_buf[..len]).await.unwrap();
+
+    loop {
+        let mut rx_buf = [0; MAX_RX_BUF_SIZE];
+        let (len, _) = client.recv(&mut rx_buf).await.unwrap();
+
+        let opcode = secure_channel_opcode(&mut rx_buf[..len]);
+        if opcode != OpCode::MRPStandAloneAck as u8 {
+            break opcode;
+        }
+    }
+}
+
+#[test]
+fn test_pbkdf_param_exchange_over_tcp() {
+    let matter = device(TCP_PORT);
+    let stack = NetworkStack::new();
+    let mut buffers = Box::new(RunBuffers::new());
+
+    let device = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), TCP_PORT);
+
+    let exchange = async {
+        select(
+            pin!(exchange_with(device)),
+            pin!(async_io::Timer::after(Duration::from_secs(5))),
+        )
+        .await
+    };
+
+    match async_io::block_on(select(
+        pin!(matter.run(&stack, &mut buffers, dev_comm(&matter), &HANDLER)),
+        pin!(exchange),
+    )) {
+        Either::First(result) => panic!("The transport exited: {:?}", result),
+        Either::Second(Either::First(opcode)) => {
+            assert_eq!(opcode, OpCode::PBKDFParamResponse as u8)
+        }
+        Either::Second(Either::Second(_)) => panic!("No reply over TCP"),
+    }
+}